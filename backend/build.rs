@@ -0,0 +1,23 @@
+//! Generates the gRPC services from `proto/`, with a descriptor set for
+//! server reflection. Uses the vendored `protoc`, so no system install is
+//! needed.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let protoc = protoc_bin_vendored::protoc_bin_path()?;
+    let include = protoc_bin_vendored::include_path()?;
+    std::env::set_var("PROTOC", protoc);
+
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .build_client(false)
+        .file_descriptor_set_path(out_dir.join("valentine_descriptor.bin"))
+        .compile_protos(
+            &[
+                "proto/quotes.proto",
+                "proto/messages.proto",
+                "proto/proposals.proto",
+            ],
+            &["proto".into(), include],
+        )?;
+    Ok(())
+}
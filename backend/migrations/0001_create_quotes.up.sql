@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS quotes (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    text       TEXT    NOT NULL UNIQUE,
+    created_at TEXT    NOT NULL
+);
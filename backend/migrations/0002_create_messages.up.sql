@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS messages (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    message    TEXT    NOT NULL,
+    sender     TEXT    NOT NULL,
+    recipient  TEXT,
+    created_at TEXT    NOT NULL
+);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_quotes_category;
+ALTER TABLE quotes DROP COLUMN category;
@@ -0,0 +1,10 @@
+ALTER TABLE quotes ADD COLUMN category TEXT NOT NULL DEFAULT 'romantic';
+
+UPDATE quotes SET category = 'poetic' WHERE text IN (
+    'In all the world, there is no heart for me like yours.',
+    'To love and be loved is to feel the sun from both sides.',
+    'You are my today and all of my tomorrows.',
+    'I fell in love the way you fall asleep: slowly, and then all at once.'
+);
+
+CREATE INDEX IF NOT EXISTS idx_quotes_category ON quotes (category);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS schedules (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    message     TEXT    NOT NULL,
+    sender      TEXT    NOT NULL,
+    recipient   TEXT,
+    reveal_at   TEXT    NOT NULL,
+    revealed_at TEXT,
+    created_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_schedules_pending ON schedules (reveal_at) WHERE revealed_at IS NULL;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS proposals (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    token        TEXT    NOT NULL UNIQUE,
+    question     TEXT    NOT NULL,
+    sender       TEXT    NOT NULL,
+    recipient    TEXT,
+    callback_url TEXT,
+    answer       TEXT,
+    answered_at  TEXT,
+    created_at   TEXT    NOT NULL
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS quote_translations (
+    quote_id INTEGER NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    lang     TEXT    NOT NULL,
+    text     TEXT    NOT NULL,
+    PRIMARY KEY (quote_id, lang)
+);
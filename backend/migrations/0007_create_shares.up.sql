@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS shares (
+    slug       TEXT    PRIMARY KEY,
+    message_id INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    created_at TEXT    NOT NULL
+);
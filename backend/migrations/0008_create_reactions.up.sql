@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS reactions (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id  INTEGER NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    emoji       TEXT    NOT NULL,
+    fingerprint TEXT    NOT NULL,
+    created_at  TEXT    NOT NULL,
+    UNIQUE (message_id, emoji, fingerprint)
+);
@@ -0,0 +1,3 @@
+-- Stored image files are left in place.
+ALTER TABLE messages DROP COLUMN image_url;
+DROP TABLE IF EXISTS uploads;
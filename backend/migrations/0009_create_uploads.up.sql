@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS uploads (
+    id           TEXT    PRIMARY KEY,
+    content_type TEXT    NOT NULL,
+    size         INTEGER NOT NULL,
+    sha256       TEXT    NOT NULL,
+    created_at   TEXT    NOT NULL
+);
+
+ALTER TABLE messages ADD COLUMN image_url TEXT;
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS important_dates (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    title      TEXT    NOT NULL,
+    kind       TEXT    NOT NULL,
+    date       TEXT    NOT NULL,
+    recurring  INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT    NOT NULL
+);
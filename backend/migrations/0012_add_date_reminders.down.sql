@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS date_reminders;
+ALTER TABLE important_dates DROP COLUMN remind_email;
+ALTER TABLE important_dates DROP COLUMN remind_days;
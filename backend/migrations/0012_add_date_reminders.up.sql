@@ -0,0 +1,12 @@
+-- Comma-separated days before each occurrence to send a reminder, e.g. "7,1".
+ALTER TABLE important_dates ADD COLUMN remind_days TEXT NOT NULL DEFAULT '';
+ALTER TABLE important_dates ADD COLUMN remind_email TEXT;
+
+-- One row per reminder sent, so restarts and retries never send it twice.
+CREATE TABLE IF NOT EXISTS date_reminders (
+    date_id    INTEGER NOT NULL REFERENCES important_dates (id) ON DELETE CASCADE,
+    occurrence TEXT    NOT NULL,
+    lead_days  INTEGER NOT NULL,
+    sent_at    TEXT    NOT NULL,
+    PRIMARY KEY (date_id, occurrence, lead_days)
+);
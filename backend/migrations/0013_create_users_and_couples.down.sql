@@ -0,0 +1,8 @@
+-- Couple rows become shared, anonymous data again.
+DROP INDEX IF EXISTS important_dates_couple;
+DROP INDEX IF EXISTS messages_couple;
+ALTER TABLE proposals DROP COLUMN couple_id;
+ALTER TABLE important_dates DROP COLUMN couple_id;
+ALTER TABLE messages DROP COLUMN couple_id;
+DROP TABLE IF EXISTS users;
+DROP TABLE IF EXISTS couples;
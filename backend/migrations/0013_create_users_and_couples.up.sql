@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS couples (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    invite_code TEXT    NOT NULL UNIQUE,
+    created_at  TEXT    NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS users (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    email         TEXT    NOT NULL UNIQUE COLLATE NOCASE,
+    name          TEXT    NOT NULL,
+    -- Argon2id PHC string.
+    password_hash TEXT    NOT NULL,
+    couple_id     INTEGER REFERENCES couples (id) ON DELETE SET NULL,
+    created_at    TEXT    NOT NULL
+);
+
+-- Rows created by a signed-in member belong to their couple; NULL rows are
+-- the shared, anonymous data every other client sees.
+ALTER TABLE messages ADD COLUMN couple_id INTEGER REFERENCES couples (id) ON DELETE CASCADE;
+ALTER TABLE important_dates ADD COLUMN couple_id INTEGER REFERENCES couples (id) ON DELETE CASCADE;
+ALTER TABLE proposals ADD COLUMN couple_id INTEGER REFERENCES couples (id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS messages_couple ON messages (couple_id);
+CREATE INDEX IF NOT EXISTS important_dates_couple ON important_dates (couple_id);
@@ -0,0 +1,8 @@
+-- External OAuth accounts linked to local users, e.g. ("google", "<sub>").
+CREATE TABLE IF NOT EXISTS identities (
+    provider   TEXT    NOT NULL,
+    subject    TEXT    NOT NULL,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TEXT    NOT NULL,
+    PRIMARY KEY (provider, subject)
+);
@@ -0,0 +1,3 @@
+-- Pending and rejected quotes join the served pool.
+DROP INDEX IF EXISTS idx_quotes_status;
+ALTER TABLE quotes DROP COLUMN status;
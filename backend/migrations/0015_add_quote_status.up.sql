@@ -0,0 +1,4 @@
+-- Existing quotes were added by admins and stay in the pool.
+ALTER TABLE quotes ADD COLUMN status TEXT NOT NULL DEFAULT 'approved';
+
+CREATE INDEX IF NOT EXISTS idx_quotes_status ON quotes (status);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS quote_favorites;
+DROP TABLE IF EXISTS quote_stats;
@@ -0,0 +1,14 @@
+-- `served` is flushed from memory in batches, so it can trail the live count
+-- by one flush interval. `favorited` mirrors the rows in quote_favorites.
+CREATE TABLE IF NOT EXISTS quote_stats (
+    quote_id  INTEGER PRIMARY KEY REFERENCES quotes (id) ON DELETE CASCADE,
+    served    INTEGER NOT NULL DEFAULT 0,
+    favorited INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS quote_favorites (
+    quote_id    INTEGER NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    fingerprint TEXT    NOT NULL,
+    created_at  TEXT    NOT NULL,
+    PRIMARY KEY (quote_id, fingerprint)
+);
@@ -0,0 +1,28 @@
+-- `split` is the percentage of visitors assigned to variant `a`.
+CREATE TABLE IF NOT EXISTS experiments (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    name       TEXT    NOT NULL UNIQUE,
+    split      INTEGER NOT NULL CHECK (split BETWEEN 0 AND 100),
+    created_at TEXT    NOT NULL
+);
+
+-- A quote belongs to at most one variant of an experiment.
+CREATE TABLE IF NOT EXISTS experiment_quotes (
+    experiment_id INTEGER NOT NULL REFERENCES experiments (id) ON DELETE CASCADE,
+    quote_id      INTEGER NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    variant       TEXT    NOT NULL CHECK (variant IN ('a', 'b')),
+    PRIMARY KEY (experiment_id, quote_id)
+);
+
+CREATE TABLE IF NOT EXISTS experiment_events (
+    id            INTEGER PRIMARY KEY AUTOINCREMENT,
+    experiment_id INTEGER NOT NULL REFERENCES experiments (id) ON DELETE CASCADE,
+    variant       TEXT    NOT NULL CHECK (variant IN ('a', 'b')),
+    fingerprint   TEXT    NOT NULL,
+    kind          TEXT    NOT NULL CHECK (kind IN ('view', 'click')),
+    quote_id      INTEGER REFERENCES quotes (id) ON DELETE SET NULL,
+    created_at    TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS experiment_events_by_variant
+    ON experiment_events (experiment_id, variant, kind);
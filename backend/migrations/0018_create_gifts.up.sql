@@ -0,0 +1,13 @@
+-- Prices are whole currency units. `tags` is a comma-separated, lowercase
+-- list matched against the interests in `GET /api/gifts`.
+CREATE TABLE IF NOT EXISTS gifts (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    name        TEXT    NOT NULL UNIQUE,
+    description TEXT    NOT NULL,
+    price_min   INTEGER NOT NULL,
+    price_max   INTEGER NOT NULL,
+    url         TEXT,
+    tags        TEXT    NOT NULL,
+    created_at  TEXT    NOT NULL,
+    CHECK (0 <= price_min AND price_min <= price_max)
+);
@@ -0,0 +1,18 @@
+-- `reserved_for` is UTC; `timezone` is the IANA zone it is shown in.
+-- `reminded_at` is set once the reminder has gone out.
+CREATE TABLE IF NOT EXISTS reservations (
+    id             INTEGER PRIMARY KEY AUTOINCREMENT,
+    place          TEXT    NOT NULL,
+    address        TEXT,
+    reserved_for   TEXT    NOT NULL,
+    timezone       TEXT    NOT NULL DEFAULT 'UTC',
+    party_size     INTEGER,
+    confirmation   TEXT,
+    remind_hours   INTEGER NOT NULL,
+    remind_email   TEXT,
+    reminded_at    TEXT,
+    created_at     TEXT    NOT NULL,
+    couple_id      INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS reservations_reserved_for ON reservations (reserved_for);
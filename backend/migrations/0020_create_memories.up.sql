@@ -0,0 +1,13 @@
+-- Photos with a date and caption. The image and its thumbnail are both
+-- uploads, served through `/api/uploads/<id>`.
+CREATE TABLE IF NOT EXISTS memories (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    upload_id    TEXT    NOT NULL REFERENCES uploads (id),
+    thumbnail_id TEXT    NOT NULL REFERENCES uploads (id),
+    caption      TEXT    NOT NULL,
+    taken_on     TEXT    NOT NULL,
+    created_at   TEXT    NOT NULL,
+    couple_id    INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS memories_couple_taken_on ON memories (couple_id, taken_on);
@@ -0,0 +1,13 @@
+-- "Open when..." letters. The body is sealed like message bodies and is
+-- only returned once `opened_at` is set.
+CREATE TABLE IF NOT EXISTS vault_letters (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    condition  TEXT    NOT NULL,
+    body       TEXT    NOT NULL,
+    sender     TEXT    NOT NULL,
+    opened_at  TEXT,
+    created_at TEXT    NOT NULL,
+    couple_id  INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS vault_letters_couple ON vault_letters (couple_id);
@@ -0,0 +1,12 @@
+-- One check-in per account per local day. `day` is the date in the zone
+-- the partner checked in from, so an evening check-in in New York counts
+-- for that evening rather than the next UTC day.
+CREATE TABLE IF NOT EXISTS checkins (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    day        TEXT    NOT NULL,
+    timezone   TEXT    NOT NULL,
+    note       TEXT    NOT NULL,
+    created_at TEXT    NOT NULL,
+    UNIQUE (user_id, day)
+);
@@ -0,0 +1,13 @@
+-- Browser Web Push subscriptions. `p256dh` and `auth` are the base64url
+-- keys from `PushSubscription.toJSON()`; rows are removed once the push
+-- service reports the endpoint gone.
+CREATE TABLE IF NOT EXISTS push_subscriptions (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    endpoint   TEXT    NOT NULL UNIQUE,
+    p256dh     TEXT    NOT NULL,
+    auth       TEXT    NOT NULL,
+    created_at TEXT    NOT NULL,
+    couple_id  INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS push_subscriptions_couple ON push_subscriptions (couple_id);
@@ -0,0 +1,13 @@
+-- Texts handed to the SMS provider, tracked by the provider's id as its
+-- status callbacks arrive. Also counted per number for rate limiting.
+CREATE TABLE IF NOT EXISTS sms_messages (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    provider_id TEXT    NOT NULL UNIQUE,
+    recipient   TEXT    NOT NULL,
+    status      TEXT    NOT NULL,
+    error_code  TEXT,
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sms_messages_recipient ON sms_messages (recipient, created_at);
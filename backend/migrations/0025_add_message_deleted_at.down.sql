@@ -0,0 +1,3 @@
+-- Trashed messages become visible again.
+DROP INDEX IF EXISTS idx_messages_deleted_at;
+ALTER TABLE messages DROP COLUMN deleted_at;
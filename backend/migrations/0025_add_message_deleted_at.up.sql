@@ -0,0 +1,4 @@
+-- Set when a message is moved to the trash; purged 30 days later.
+ALTER TABLE messages ADD COLUMN deleted_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_deleted_at ON messages (deleted_at) WHERE deleted_at IS NOT NULL;
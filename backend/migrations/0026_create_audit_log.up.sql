@@ -0,0 +1,14 @@
+-- Who created, changed or deleted what. `diff` is a JSON object mapping each
+-- changed field to its `from` and `to` values.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    actor      TEXT    NOT NULL,
+    action     TEXT    NOT NULL,
+    entity     TEXT    NOT NULL,
+    entity_id  INTEGER NOT NULL,
+    diff       TEXT    NOT NULL,
+    created_at TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON audit_log (entity, entity_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log (created_at);
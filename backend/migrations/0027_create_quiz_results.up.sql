@@ -0,0 +1,13 @@
+-- Quiz results of signed-in couples, kept so they can be exported.
+-- `categories` is the JSON array of per-category scores.
+CREATE TABLE IF NOT EXISTS quiz_results (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    quiz       TEXT    NOT NULL,
+    score      INTEGER NOT NULL,
+    title      TEXT    NOT NULL,
+    categories TEXT    NOT NULL,
+    created_at TEXT    NOT NULL,
+    couple_id  INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS quiz_results_couple ON quiz_results (couple_id);
@@ -0,0 +1,3 @@
+-- Quotes synced from file and remote sources stay, as if added by hand.
+DROP INDEX IF EXISTS idx_quotes_source;
+ALTER TABLE quotes DROP COLUMN source;
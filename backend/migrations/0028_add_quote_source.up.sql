@@ -0,0 +1,22 @@
+-- `builtin`, `database`, or the name of a file or remote quote source.
+-- Everything so far was added through the API except the bundled defaults.
+ALTER TABLE quotes ADD COLUMN source TEXT NOT NULL DEFAULT 'database';
+
+UPDATE quotes SET source = 'builtin' WHERE text IN (
+    'You are the reason I believe in love.',
+    'Every love story is beautiful, but ours is my favorite.',
+    'In all the world, there is no heart for me like yours.',
+    'I love you more than yesterday, less than tomorrow.',
+    'You had me at hello.',
+    'To love and be loved is to feel the sun from both sides.',
+    'My heart is, and always will be, yours.',
+    'I wish I could turn back the clock. I''d find you sooner and love you longer.',
+    'You are my today and all of my tomorrows.',
+    'I fell in love the way you fall asleep: slowly, and then all at once.',
+    'I love you even when you steal the blankets.',
+    'You''re the only person I''d share my fries with.',
+    'Distance means so little when someone means so much.',
+    'Same moon, same stars, different skies. Counting the days until I''m with you.'
+);
+
+CREATE INDEX IF NOT EXISTS idx_quotes_source ON quotes (source, status, category);
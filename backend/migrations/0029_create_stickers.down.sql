@@ -0,0 +1,6 @@
+-- Sticker images stay in the upload store; sticker reactions are dropped.
+DELETE FROM reactions WHERE sticker_id IS NOT NULL;
+ALTER TABLE reactions DROP COLUMN sticker_id;
+ALTER TABLE messages DROP COLUMN sticker_id;
+DROP TABLE IF EXISTS stickers;
+DROP TABLE IF EXISTS sticker_packs;
@@ -0,0 +1,23 @@
+-- Custom sticker packs uploaded by admins. Each sticker is an image in
+-- `uploads`, named uniquely within its pack.
+CREATE TABLE IF NOT EXISTS sticker_packs (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    name       TEXT    NOT NULL UNIQUE COLLATE NOCASE,
+    created_at TEXT    NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS stickers (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    pack_id    INTEGER NOT NULL REFERENCES sticker_packs (id) ON DELETE CASCADE,
+    name       TEXT    NOT NULL,
+    upload_id  TEXT    NOT NULL REFERENCES uploads (id),
+    created_at TEXT    NOT NULL,
+    UNIQUE (pack_id, name)
+);
+
+-- A message loses its sticker when the sticker is deleted.
+ALTER TABLE messages ADD COLUMN sticker_id INTEGER REFERENCES stickers (id) ON DELETE SET NULL;
+
+-- Sticker reactions store `sticker:<id>` as their emoji, so the existing
+-- one-reaction-per-client constraint covers them too.
+ALTER TABLE reactions ADD COLUMN sticker_id INTEGER REFERENCES stickers (id) ON DELETE CASCADE;
@@ -0,0 +1,2 @@
+-- Tenant database files are left in place.
+DROP TABLE IF EXISTS tenants;
@@ -0,0 +1,11 @@
+-- Tenants hosted by this instance, listed in the main database. Each has
+-- its own database file; the table stays empty in those.
+-- `quote_pool` is `own` (the tenant's database) or `shared` (the main one).
+CREATE TABLE IF NOT EXISTS tenants (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug       TEXT    NOT NULL UNIQUE,
+    name       TEXT    NOT NULL,
+    theme      TEXT,
+    quote_pool TEXT    NOT NULL DEFAULT 'own',
+    created_at TEXT    NOT NULL
+);
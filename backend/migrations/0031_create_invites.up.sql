@@ -0,0 +1,10 @@
+-- Single-use signup invites. `used_at` and `used_by` are set when an
+-- account is created with the invite; it cannot be used after `expires_at`.
+CREATE TABLE IF NOT EXISTS invites (
+    token      TEXT    PRIMARY KEY,
+    note       TEXT,
+    created_at TEXT    NOT NULL,
+    expires_at TEXT    NOT NULL,
+    used_at    TEXT,
+    used_by    INTEGER REFERENCES users (id) ON DELETE SET NULL
+);
@@ -0,0 +1,8 @@
+-- Roles granted to accounts beyond plain membership: `admin` or
+-- `moderator`. Each role grants a fixed set of permissions.
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id    INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role       TEXT    NOT NULL,
+    granted_at TEXT    NOT NULL,
+    PRIMARY KEY (user_id, role)
+);
@@ -0,0 +1,14 @@
+-- Responses to `POST` requests sent with an `Idempotency-Key` header, kept
+-- for a day so retries are answered without running the handler again.
+-- `status` is NULL while the first request is still being handled.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key          TEXT    PRIMARY KEY,
+    path         TEXT    NOT NULL,
+    status       INTEGER,
+    content_type TEXT,
+    location     TEXT,
+    body         BLOB,
+    created_at   TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys (created_at);
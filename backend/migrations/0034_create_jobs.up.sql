@@ -0,0 +1,18 @@
+-- Background work (emails, push notifications, proposal callbacks) queued
+-- for the job workers. `run_at` is when a pending job is next due, or when
+-- a running one's lease runs out and another worker may take it over.
+-- Failed jobs stay until retried or deleted through `/admin/jobs`.
+CREATE TABLE IF NOT EXISTS jobs (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind        TEXT    NOT NULL,
+    payload     TEXT    NOT NULL,
+    status      TEXT    NOT NULL DEFAULT 'pending'
+                CHECK (status IN ('pending', 'running', 'failed')),
+    attempts    INTEGER NOT NULL DEFAULT 0,
+    run_at      TEXT    NOT NULL,
+    last_error  TEXT,
+    created_at  TEXT    NOT NULL,
+    updated_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_status_run_at ON jobs (status, run_at);
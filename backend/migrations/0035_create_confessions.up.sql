@@ -0,0 +1,15 @@
+-- Anonymous confessions for the public board. The author gets a reveal
+-- token once, when posting; only its SHA-256 is kept, so the database alone
+-- cannot tie a confession to anyone. `revealed_as` is the name the author
+-- chose to sign with, set when they claim it.
+CREATE TABLE IF NOT EXISTS confessions (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    content     TEXT    NOT NULL,
+    recipient   TEXT,
+    token_hash  TEXT    NOT NULL,
+    revealed_as TEXT,
+    revealed_at TEXT,
+    created_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_confessions_created_at ON confessions (created_at);
@@ -0,0 +1,14 @@
+-- Finished games of signed-in couples; a couple's high score in a game is
+-- its best `score`. `score` is the percentage of `correct` answers out of
+-- `total`, so games of different lengths compare fairly.
+CREATE TABLE IF NOT EXISTS game_scores (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    game       TEXT    NOT NULL,
+    couple_id  INTEGER NOT NULL REFERENCES couples (id) ON DELETE CASCADE,
+    correct    INTEGER NOT NULL,
+    total      INTEGER NOT NULL,
+    score      INTEGER NOT NULL,
+    created_at TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_game_scores_couple ON game_scores (couple_id, game);
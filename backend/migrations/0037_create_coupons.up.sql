@@ -0,0 +1,17 @@
+-- Love coupons one partner issues to the other, in named booklets. A
+-- coupon can be redeemed once, by the partner it was issued to, until
+-- `expires_at` if it has one.
+CREATE TABLE IF NOT EXISTS coupons (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    booklet     TEXT    NOT NULL,
+    title       TEXT    NOT NULL,
+    description TEXT,
+    couple_id   INTEGER NOT NULL REFERENCES couples (id) ON DELETE CASCADE,
+    issuer_id   INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at  TEXT,
+    redeemed_at TEXT,
+    redeemed_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    created_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_coupons_couple ON coupons (couple_id);
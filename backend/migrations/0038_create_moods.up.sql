@@ -0,0 +1,12 @@
+-- One mood check-in per account per local day, on a 1-5 scale. `day` is
+-- the date in the zone the partner checked in from, as for `checkins`.
+CREATE TABLE IF NOT EXISTS moods (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    day        TEXT    NOT NULL,
+    timezone   TEXT    NOT NULL,
+    score      INTEGER NOT NULL,
+    note       TEXT,
+    created_at TEXT    NOT NULL,
+    UNIQUE (user_id, day)
+);
@@ -0,0 +1,18 @@
+-- Items each partner would like. The other partner can reserve one, for
+-- themselves or on behalf of a friend or relative in `reserved_for`; the
+-- owner is never shown the reservation columns.
+CREATE TABLE IF NOT EXISTS wishlist_items (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    couple_id    INTEGER NOT NULL REFERENCES couples (id) ON DELETE CASCADE,
+    owner_id     INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    title        TEXT    NOT NULL,
+    url          TEXT,
+    note         TEXT,
+    price        INTEGER,
+    reserved_by  INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    reserved_for TEXT,
+    reserved_at  TEXT,
+    created_at   TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_wishlist_items_couple ON wishlist_items (couple_id);
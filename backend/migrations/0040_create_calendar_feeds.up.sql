@@ -0,0 +1,8 @@
+-- Secret links calendar apps subscribe to `/api/calendar.ics` with, since
+-- they cannot sign in. One per couple; issuing a new one replaces it. Only
+-- the SHA-256 of the token is kept.
+CREATE TABLE IF NOT EXISTS calendar_feeds (
+    couple_id  INTEGER PRIMARY KEY REFERENCES couples (id) ON DELETE CASCADE,
+    token_hash TEXT    NOT NULL UNIQUE,
+    created_at TEXT    NOT NULL
+);
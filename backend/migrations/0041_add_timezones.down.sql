@@ -0,0 +1,3 @@
+ALTER TABLE schedules DROP COLUMN couple_id;
+ALTER TABLE schedules DROP COLUMN timezone;
+ALTER TABLE users DROP COLUMN timezone;
@@ -0,0 +1,7 @@
+-- Each account's home zone, used when a request names no zone of its own.
+ALTER TABLE users ADD COLUMN timezone TEXT;
+
+-- The zone a schedule was set in, so "midnight" stays the sender's
+-- midnight, and the couple it was set for, whose zones it is also shown in.
+ALTER TABLE schedules ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC';
+ALTER TABLE schedules ADD COLUMN couple_id INTEGER REFERENCES couples (id) ON DELETE CASCADE;
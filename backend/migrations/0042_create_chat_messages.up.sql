@@ -0,0 +1,12 @@
+-- The couple's private chat. `body` is sealed like other message bodies
+-- when encryption is on; `read_at` is set once the other partner reads it.
+CREATE TABLE IF NOT EXISTS chat_messages (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    couple_id  INTEGER NOT NULL REFERENCES couples (id) ON DELETE CASCADE,
+    sender_id  INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    body       TEXT    NOT NULL,
+    read_at    TEXT,
+    created_at TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_chat_messages_couple ON chat_messages (couple_id, id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS sealed_notes;
+DROP TABLE IF EXISTS public_keys;
@@ -0,0 +1,29 @@
+-- Public keys partners register from their devices, and notes sealed to
+-- one of them on the client. The server stores the ciphertext as given
+-- and never sees a private key or a plaintext. Keys are revoked rather
+-- than deleted so that notes sealed to them keep their envelope.
+CREATE TABLE IF NOT EXISTS public_keys (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id     INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    algorithm   TEXT    NOT NULL,
+    public_key  TEXT    NOT NULL,
+    fingerprint TEXT    NOT NULL,
+    label       TEXT,
+    revoked_at  TEXT,
+    created_at  TEXT    NOT NULL,
+    UNIQUE (user_id, fingerprint)
+);
+
+CREATE TABLE IF NOT EXISTS sealed_notes (
+    id               INTEGER PRIMARY KEY AUTOINCREMENT,
+    couple_id        INTEGER NOT NULL REFERENCES couples (id) ON DELETE CASCADE,
+    sender_id        INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    recipient_key_id INTEGER NOT NULL REFERENCES public_keys (id) ON DELETE CASCADE,
+    sender_key_id    INTEGER REFERENCES public_keys (id) ON DELETE SET NULL,
+    algorithm        TEXT    NOT NULL,
+    nonce            TEXT    NOT NULL,
+    ciphertext       TEXT    NOT NULL,
+    created_at       TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sealed_notes_couple ON sealed_notes (couple_id, id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS surprise_attempts;
+DROP TABLE IF EXISTS surprises;
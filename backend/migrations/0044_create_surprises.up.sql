@@ -0,0 +1,31 @@
+-- Surprises: messages revealed only to someone standing within `radius_m`
+-- metres of a point. The message is sealed like message bodies. Every
+-- unlock attempt is kept with the client's address, to cap each client's
+-- guesses and catch locations that jump further than anyone could travel.
+CREATE TABLE IF NOT EXISTS surprises (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    message     TEXT    NOT NULL,
+    sender      TEXT    NOT NULL,
+    recipient   TEXT,
+    hint        TEXT,
+    latitude    REAL    NOT NULL,
+    longitude   REAL    NOT NULL,
+    radius_m    REAL    NOT NULL,
+    unlocked_at TEXT,
+    created_at  TEXT    NOT NULL,
+    couple_id   INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS surprises_couple ON surprises (couple_id);
+
+CREATE TABLE IF NOT EXISTS surprise_attempts (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    surprise_id INTEGER NOT NULL REFERENCES surprises (id) ON DELETE CASCADE,
+    client      TEXT,
+    latitude    REAL    NOT NULL,
+    longitude   REAL    NOT NULL,
+    outcome     TEXT    NOT NULL,
+    created_at  TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_surprise_attempts ON surprise_attempts (surprise_id, client, id);
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS hunt_attempts;
+DROP TABLE IF EXISTS hunt_clues;
+DROP TABLE IF EXISTS hunts;
@@ -0,0 +1,37 @@
+-- Scavenger hunts: an ordered chain of clues, each answered before the
+-- next is shown. Answers are stored as Argon2 hashes of their normalized
+-- text, and every answer given is kept to limit guessing and show hints.
+CREATE TABLE IF NOT EXISTS hunts (
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    title        TEXT    NOT NULL,
+    sender       TEXT    NOT NULL,
+    hint_after   INTEGER NOT NULL,
+    max_attempts INTEGER NOT NULL,
+    -- The current clue; equal to the number of clues once completed.
+    position     INTEGER NOT NULL DEFAULT 0,
+    completed_at TEXT,
+    created_at   TEXT    NOT NULL,
+    couple_id    INTEGER REFERENCES couples (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS hunts_couple ON hunts (couple_id);
+
+CREATE TABLE IF NOT EXISTS hunt_clues (
+    hunt_id     INTEGER NOT NULL REFERENCES hunts (id) ON DELETE CASCADE,
+    position    INTEGER NOT NULL,
+    clue        TEXT    NOT NULL,
+    answer_hash TEXT    NOT NULL,
+    hint        TEXT,
+    solved_at   TEXT,
+    PRIMARY KEY (hunt_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS hunt_attempts (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    hunt_id    INTEGER NOT NULL REFERENCES hunts (id) ON DELETE CASCADE,
+    position   INTEGER NOT NULL,
+    correct    INTEGER NOT NULL,
+    created_at TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_hunt_attempts ON hunt_attempts (hunt_id, position);
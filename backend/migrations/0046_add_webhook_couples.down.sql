@@ -0,0 +1,3 @@
+-- Couple webhooks hear every event again.
+DROP INDEX IF EXISTS webhooks_couple;
+ALTER TABLE webhooks DROP COLUMN couple_id;
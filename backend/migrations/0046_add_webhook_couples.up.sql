@@ -0,0 +1,5 @@
+-- Webhooks registered by a signed-in member hear only their couple's events;
+-- NULL ones hear the shared, anonymous data.
+ALTER TABLE webhooks ADD COLUMN couple_id INTEGER REFERENCES couples (id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS webhooks_couple ON webhooks (couple_id);
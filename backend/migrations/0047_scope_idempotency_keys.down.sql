@@ -0,0 +1,13 @@
+DROP TABLE IF EXISTS idempotency_keys;
+
+CREATE TABLE idempotency_keys (
+    key          TEXT    PRIMARY KEY,
+    path         TEXT    NOT NULL,
+    status       INTEGER,
+    content_type TEXT,
+    location     TEXT,
+    body         BLOB,
+    created_at   TEXT    NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys (created_at);
@@ -0,0 +1,20 @@
+-- Idempotency keys belong to the caller that sent them (`user:<id>`,
+-- `key:<hash>` or `ip:<address>`) and remember a hash of the request, so a
+-- different request under a used key is refused. Responses are only kept
+-- for a day, so the old keys are dropped rather than migrated.
+DROP TABLE IF EXISTS idempotency_keys;
+
+CREATE TABLE idempotency_keys (
+    caller       TEXT    NOT NULL,
+    key          TEXT    NOT NULL,
+    path         TEXT    NOT NULL,
+    request_hash TEXT    NOT NULL,
+    status       INTEGER,
+    content_type TEXT,
+    location     TEXT,
+    body         BLOB,
+    created_at   TEXT    NOT NULL,
+    PRIMARY KEY (caller, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys (created_at);
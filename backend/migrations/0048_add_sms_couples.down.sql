@@ -0,0 +1,3 @@
+-- Texts are no longer tied to a couple.
+DROP INDEX IF EXISTS sms_messages_couple;
+ALTER TABLE sms_messages DROP COLUMN couple_id;
@@ -0,0 +1,6 @@
+-- Texts sent by a signed-in member belong to their couple, so only that
+-- couple can look up the recipient and delivery status; NULL ones were
+-- sent with the API key alone.
+ALTER TABLE sms_messages ADD COLUMN couple_id INTEGER REFERENCES couples (id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS sms_messages_couple ON sms_messages (couple_id);
@@ -0,0 +1,107 @@
+//! Browsing the audit log.
+
+use chrono::{DateTime, Utc};
+use rocket::form::{FromFormField, ValueField};
+use rocket::http::Status;
+use rocket::Route;
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::roles::{ManageServer, Permission};
+use crate::storage::{AuditAction, AuditEntity, AuditEntry, AuditQuery, Storage};
+
+/// Query string of `GET /admin/audit`. `entity` and `action` are parsed by
+/// the handler, since an optional guard would quietly drop a bad value and
+/// list everything.
+#[derive(FromForm, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditParams<'r> {
+    #[param(value_type = Option<AuditEntity>)]
+    entity: Option<&'r str>,
+    entity_id: Option<i64>,
+    /// Exact actor, e.g. `user:3` or `system:scheduler`.
+    actor: Option<&'r str>,
+    #[param(value_type = Option<AuditAction>)]
+    action: Option<&'r str>,
+    /// RFC 3339; entries at or after this time.
+    since: Option<&'r str>,
+    /// RFC 3339; entries before this time.
+    until: Option<&'r str>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+}
+
+fn parse_filter<'v, T: FromFormField<'v>>(
+    field: &str,
+    value: Option<&'v str>,
+) -> ApiResult<Option<T>> {
+    value
+        .map(|value| {
+            T::from_value(ValueField::from_value(value)).map_err(|_| {
+                error(
+                    Status::BadRequest,
+                    format!("`{}` has no value `{}`", field, value),
+                )
+            })
+        })
+        .transpose()
+}
+
+fn parse_time(field: &str, value: Option<&str>) -> ApiResult<Option<DateTime<Utc>>> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|e| {
+                    error(
+                        Status::BadRequest,
+                        format!("`{}` is not an RFC 3339 time: {}", field, e),
+                    )
+                })
+        })
+        .transpose()
+}
+
+/// Recorded changes, newest first, narrowed by any of the filters.
+#[utoipa::path(
+    tag = "admin",
+    params(AuditParams),
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Page<AuditEntry>),
+        (status = 400, description = "Unknown `entity` or `action`, or bad `since` or `until`", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/audit?<params..>")]
+async fn list(
+    _perm: Permission<ManageServer>,
+    storage: &Storage,
+    params: AuditParams<'_>,
+) -> ApiResult<Negotiated<Page<AuditEntry>>> {
+    let (page, per_page, offset) = paginate(params.page, params.per_page);
+    let query = AuditQuery {
+        actor: params.actor,
+        action: parse_filter("action", params.action)?,
+        entity: parse_filter("entity", params.entity)?,
+        entity_id: params.entity_id,
+        since: parse_time("since", params.since)?,
+        until: parse_time("until", params.until)?,
+        limit: per_page,
+        offset,
+    };
+    let (items, total) = storage.list_audit(query).await.map_err(internal_error)?;
+
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list]
+}
@@ -0,0 +1,54 @@
+//! Taking and checking on database backups.
+
+use rocket::{Route, State};
+
+use crate::backup::{BackupRecord, BackupStatus, Backups};
+use crate::error::{ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageServer, Permission};
+
+/// Backs up the main database and every tenant's now, outside the schedule,
+/// and prunes old backups. Responds once every backup is uploaded.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = [BackupRecord]),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "A backup is already running", body = ErrorResponse),
+        (status = 502, description = "A snapshot or upload failed", body = ErrorResponse),
+        (status = 503, description = "Backups are not configured", body = ErrorResponse),
+    )
+)]
+#[post("/admin/backup/now")]
+async fn now(
+    _perm: Permission<ManageServer>,
+    backups: &State<Backups>,
+) -> ApiResult<Negotiated<Vec<BackupRecord>>> {
+    Ok(Negotiated(backups.job()?.run().await?))
+}
+
+/// The schedule, the next run, the latest success and failure, and the
+/// backups retained.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = BackupStatus),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 503, description = "Backups are not configured", body = ErrorResponse),
+    )
+)]
+#[get("/admin/backup/status")]
+fn status(
+    _perm: Permission<ManageServer>,
+    backups: &State<Backups>,
+) -> ApiResult<Negotiated<BackupStatus>> {
+    Ok(Negotiated(backups.job()?.status()))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![now, status]
+}
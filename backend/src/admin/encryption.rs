@@ -0,0 +1,43 @@
+use rocket::http::Status;
+use rocket::Route;
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageServer, Permission};
+use crate::storage::{RotationReport, Storage};
+
+/// Re-encrypts stored message bodies with the active key. Run after adding a
+/// new key and switching `encryption.active` to it; once it reports zero
+/// rows, the old key can be removed from config.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = RotationReport),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Encryption is not configured", body = ErrorResponse),
+    )
+)]
+#[post("/admin/encryption/rotate")]
+async fn rotate(
+    _perm: Permission<ManageServer>,
+    storage: &Storage,
+) -> ApiResult<Negotiated<RotationReport>> {
+    if !storage.encrypts_messages() {
+        return Err(error(
+            Status::Conflict,
+            "message encryption is not configured",
+        ));
+    }
+
+    storage
+        .rotate_encryption()
+        .await
+        .map(Negotiated)
+        .map_err(internal_error)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![rotate]
+}
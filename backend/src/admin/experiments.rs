@@ -0,0 +1,202 @@
+//! Defines the A/B tests served by [`crate::experiments`]. Experiments
+//! cannot be edited once created, since changing a pool or the split midway
+//! would muddle the results; delete and recreate them instead.
+
+use std::collections::HashSet;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::Deserialize;
+
+use crate::admin::quotes::invalid;
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageContent, Permission};
+use crate::storage::{self, Experiment, NewExperiment, Storage};
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_NAME_LEN: usize = 50;
+const MAX_POOL_LEN: usize = 50;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ExperimentRequest {
+    name: String,
+    /// Percentage of visitors assigned to variant `a`, 0 to 100.
+    #[serde(default = "default_split")]
+    #[schema(default = 50)]
+    split: i64,
+    /// Quote ids served to variant `a`.
+    variant_a: Vec<i64>,
+    /// Quote ids served to variant `b`.
+    variant_b: Vec<i64>,
+}
+
+fn default_split() -> i64 {
+    50
+}
+
+impl Validate for ExperimentRequest {
+    type Valid = NewExperiment;
+
+    fn validate(self) -> Result<NewExperiment, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let name = self.name.trim().to_string();
+        errors.text("name", &name, MAX_NAME_LEN);
+        if !(0..=100).contains(&self.split) {
+            errors.add("split", "`split` must be between 0 and 100");
+        }
+
+        let mut seen = HashSet::new();
+        for (field, pool) in [
+            ("variant_a", &self.variant_a),
+            ("variant_b", &self.variant_b),
+        ] {
+            if pool.is_empty() || pool.len() > MAX_POOL_LEN {
+                errors.add(
+                    field,
+                    format!(
+                        "`{}` must list between 1 and {} quote ids",
+                        field, MAX_POOL_LEN
+                    ),
+                );
+            }
+            if let Some(id) = pool.iter().find(|id| !seen.insert(**id)) {
+                errors.add(field, format!("quote {} is listed more than once", id));
+            }
+        }
+
+        errors.finish(NewExperiment {
+            name,
+            split: self.split,
+            variant_a: self.variant_a,
+            variant_b: self.variant_b,
+        })
+    }
+}
+
+fn duplicate_or_internal(e: sqlx::Error) -> ApiError {
+    if storage::is_unique_violation(&e) {
+        error(
+            Status::Conflict,
+            "an experiment with this name already exists",
+        )
+    } else {
+        internal_error(e)
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<Experiment>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/experiments")]
+async fn list(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+) -> ApiResult<Negotiated<Vec<Experiment>>> {
+    storage
+        .list_experiments()
+        .await
+        .map(Negotiated)
+        .map_err(internal_error)
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Experiment),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/admin/experiments/<id>")]
+async fn get(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<Experiment>> {
+    storage
+        .get_experiment(id)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no experiment with id {}", id)))
+}
+
+/// Pools may list quotes that are not approved yet; only approved ones are
+/// served.
+#[utoipa::path(
+    tag = "admin",
+    request_body = ExperimentRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Experiment),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Duplicate experiment name", body = ErrorResponse),
+        (status = 422, description = "Invalid split or pools, or an unknown quote id", body = ErrorResponse),
+    )
+)]
+#[post("/admin/experiments", data = "<request>")]
+async fn create(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    request: Valid<ExperimentRequest>,
+) -> ApiResult<status::Created<Negotiated<Experiment>>> {
+    let experiment = request.into_inner();
+    for id in experiment.variant_a.iter().chain(&experiment.variant_b) {
+        if storage
+            .get_quote(*id)
+            .await
+            .map_err(internal_error)?
+            .is_none()
+        {
+            return Err(invalid(format!("no quote with id {}", id)));
+        }
+    }
+
+    let experiment = storage
+        .create_experiment(&experiment)
+        .await
+        .map_err(duplicate_or_internal)?;
+    let location = uri!(get(experiment.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(experiment)))
+}
+
+/// Deletes the experiment along with its recorded events.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/admin/experiments/<id>")]
+async fn delete(_perm: Permission<ManageContent>, storage: &Storage, id: i64) -> ApiResult<Status> {
+    match storage
+        .delete_experiment(id)
+        .await
+        .map_err(internal_error)?
+    {
+        true => Ok(Status::NoContent),
+        false => Err(error(
+            Status::NotFound,
+            format!("no experiment with id {}", id),
+        )),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, get, create, delete]
+}
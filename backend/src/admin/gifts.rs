@@ -0,0 +1,142 @@
+//! Editing the gift catalog behind `GET /api/gifts`.
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::gifts::GiftRequest;
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageContent, Permission};
+use crate::storage::{self, Gift, Storage};
+use crate::validation::Valid;
+
+fn duplicate_or_internal(e: sqlx::Error) -> ApiError {
+    if storage::is_unique_violation(&e) {
+        error(Status::Conflict, "a gift with this name already exists")
+    } else {
+        internal_error(e)
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<Gift>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/gifts")]
+async fn list(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+) -> ApiResult<Negotiated<Vec<Gift>>> {
+    storage
+        .list_gifts()
+        .await
+        .map(Negotiated)
+        .map_err(internal_error)
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Gift),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/admin/gifts/<id>")]
+async fn get(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<Gift>> {
+    storage
+        .get_gift(id)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no gift with id {}", id)))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    request_body = GiftRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Gift),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Duplicate gift name", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/admin/gifts", data = "<request>")]
+async fn create(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    request: Valid<GiftRequest>,
+) -> ApiResult<status::Created<Negotiated<Gift>>> {
+    let gift = storage
+        .create_gift(&request.0)
+        .await
+        .map_err(duplicate_or_internal)?;
+
+    let location = uri!(get(gift.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(gift)))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    request_body = GiftRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Gift),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Duplicate gift name", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[put("/admin/gifts/<id>", data = "<request>")]
+async fn update(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    id: i64,
+    request: Valid<GiftRequest>,
+) -> ApiResult<Negotiated<Gift>> {
+    storage
+        .update_gift(id, &request.0)
+        .await
+        .map_err(duplicate_or_internal)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no gift with id {}", id)))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/admin/gifts/<id>")]
+async fn delete(_perm: Permission<ManageContent>, storage: &Storage, id: i64) -> ApiResult<Status> {
+    match storage.delete_gift(id).await.map_err(internal_error)? {
+        true => Ok(Status::NoContent),
+        false => Err(error(Status::NotFound, format!("no gift with id {}", id))),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, get, create, update, delete]
+}
@@ -0,0 +1,123 @@
+//! Creating the signup invites checked by [`crate::invites`].
+
+use chrono::{Duration, Utc};
+use rocket::response::status;
+use rocket::Route;
+use serde::Deserialize;
+
+use crate::error::{internal_error, ApiResult, ErrorResponse};
+use crate::idempotency::NoStore;
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageUsers, Permission};
+use crate::storage::{Invite, Storage};
+use crate::tokens;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const TOKEN_LEN: usize = 32;
+const MAX_EXPIRES_IN_HOURS: i64 = 30 * 24;
+const MAX_NOTE_LEN: usize = 100;
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct InviteRequest {
+    /// How long the invite can be used for, 1 to 720 hours.
+    #[serde(default = "default_expires_in_hours")]
+    #[schema(default = 72)]
+    expires_in_hours: i64,
+    /// Who the invite is for, as a reminder.
+    note: Option<String>,
+}
+
+fn default_expires_in_hours() -> i64 {
+    72
+}
+
+pub(crate) struct NewInvite {
+    expires_in: Duration,
+    note: Option<String>,
+}
+
+impl Validate for InviteRequest {
+    type Valid = NewInvite;
+
+    fn validate(self) -> Result<NewInvite, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if !(1..=MAX_EXPIRES_IN_HOURS).contains(&self.expires_in_hours) {
+            errors.add(
+                "expires_in_hours",
+                format!(
+                    "`expires_in_hours` must be between 1 and {}",
+                    MAX_EXPIRES_IN_HOURS
+                ),
+            );
+        }
+        let note = self
+            .note
+            .map(|note| note.trim().to_string())
+            .filter(|note| !note.is_empty());
+        if let Some(note) = &note {
+            errors.text("note", note, MAX_NOTE_LEN);
+        }
+        errors.finish(NewInvite {
+            expires_in: Duration::hours(self.expires_in_hours),
+            note,
+        })
+    }
+}
+
+/// Creates a single-use invite. Pass its token as `invite` when signing up,
+/// or to the OAuth login routes.
+#[utoipa::path(
+    tag = "admin",
+    request_body = InviteRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Invite),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/admin/invites", data = "<request>")]
+async fn create(
+    _perm: Permission<ManageUsers>,
+    storage: &Storage,
+    request: Valid<InviteRequest>,
+) -> ApiResult<NoStore<status::Created<Negotiated<Invite>>>> {
+    let NewInvite { expires_in, note } = request.into_inner();
+    let token = tokens::random_token(TOKEN_LEN);
+    let invite = storage
+        .create_invite(&token, note.as_deref(), Utc::now() + expires_in)
+        .await
+        .map_err(internal_error)?;
+    let location = format!("/api/invites/{}", invite.token);
+    Ok(NoStore(
+        status::Created::new(location).body(Negotiated(invite)),
+    ))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(expires_in_hours: i64, note: Option<&str>) -> InviteRequest {
+        InviteRequest {
+            expires_in_hours,
+            note: note.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn invites_expire_within_a_month() {
+        let valid = request(72, Some("  ")).validate().unwrap();
+        assert_eq!(valid.expires_in, Duration::hours(72));
+        assert_eq!(valid.note, None);
+
+        assert!(request(0, None).validate().is_err());
+        assert!(request(721, None).validate().is_err());
+        assert!(request(1, Some(&"x".repeat(101))).validate().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! The dead-letter list of background jobs that used up their attempts, and
+//! putting them back in the queue.
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::jobs::Jobs;
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::roles::{ManageServer, Permission};
+use crate::storage::{FailedJob, Storage};
+
+fn no_failed_job(id: i64) -> crate::error::ApiError {
+    error(Status::NotFound, format!("no failed job with id {}", id))
+}
+
+/// Failed jobs, most recently failed first, with the last error of each.
+#[utoipa::path(
+    tag = "admin",
+    params(
+        ("page" = Option<i64>, Query),
+        ("per_page" = Option<i64>, Query),
+    ),
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Page<FailedJob>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/jobs/failed?<page>&<per_page>")]
+async fn failed(
+    _perm: Permission<ManageServer>,
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> ApiResult<Negotiated<Page<FailedJob>>> {
+    let (page, per_page, offset) = paginate(page, per_page);
+    let (items, total) = storage
+        .failed_jobs(per_page, offset)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Queues failed job `id` to run again now, with a fresh set of attempts.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204, description = "Queued again"),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "No failed job with that id", body = ErrorResponse),
+    )
+)]
+#[post("/admin/jobs/<id>/retry")]
+async fn retry(
+    _perm: Permission<ManageServer>,
+    storage: &Storage,
+    jobs: &State<Jobs>,
+    id: i64,
+) -> ApiResult<status::NoContent> {
+    if !storage.retry_failed_job(id).await.map_err(internal_error)? {
+        return Err(no_failed_job(id));
+    }
+    jobs.wake();
+    Ok(status::NoContent)
+}
+
+/// Discards failed job `id`.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "No failed job with that id", body = ErrorResponse),
+    )
+)]
+#[delete("/admin/jobs/<id>")]
+async fn delete(
+    _perm: Permission<ManageServer>,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<status::NoContent> {
+    if !storage
+        .delete_failed_job(id)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(no_failed_job(id));
+    }
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![failed, retry, delete]
+}
@@ -0,0 +1,34 @@
+pub(crate) mod audit;
+pub(crate) mod backup;
+pub(crate) mod encryption;
+pub(crate) mod experiments;
+pub(crate) mod gifts;
+pub(crate) mod invites;
+pub(crate) mod jobs;
+pub(crate) mod moderation;
+pub(crate) mod providers;
+pub(crate) mod quote_sources;
+pub(crate) mod quotes;
+pub(crate) mod roles;
+pub(crate) mod stickers;
+pub(crate) mod tenants;
+
+use rocket::Route;
+
+pub fn routes() -> Vec<Route> {
+    let mut routes = quotes::routes();
+    routes.extend(encryption::routes());
+    routes.extend(moderation::routes());
+    routes.extend(experiments::routes());
+    routes.extend(gifts::routes());
+    routes.extend(providers::routes());
+    routes.extend(audit::routes());
+    routes.extend(backup::routes());
+    routes.extend(quote_sources::routes());
+    routes.extend(stickers::routes());
+    routes.extend(tenants::routes());
+    routes.extend(invites::routes());
+    routes.extend(roles::routes());
+    routes.extend(jobs::routes());
+    routes
+}
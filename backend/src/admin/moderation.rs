@@ -0,0 +1,133 @@
+//! Review queue for quotes submitted through `POST /api/quotes`. They wait
+//! as `pending` until approved into the random pool or rejected.
+
+use rocket::http::Status;
+use rocket::Route;
+
+use crate::audit::{self, Actor};
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::roles::{Moderate, Permission};
+use crate::storage::{AuditAction, AuditEntity, Quote, QuoteStatus, Storage};
+
+/// Pending quotes, oldest first.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Page<Quote>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/moderation?<page>&<per_page>")]
+async fn list(
+    _perm: Permission<Moderate>,
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> ApiResult<Negotiated<Page<Quote>>> {
+    let (page, per_page, offset) = paginate(page, per_page);
+    let status = Some(QuoteStatus::Pending);
+    let total = storage
+        .count_quotes(None, status)
+        .await
+        .map_err(internal_error)?;
+    let items = storage
+        .list_quotes(status, per_page, offset)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+async fn moderate(
+    storage: &Storage,
+    actor: &Actor,
+    id: i64,
+    status: QuoteStatus,
+) -> ApiResult<Negotiated<Quote>> {
+    if let Some(quote) = storage
+        .moderate_quote(id, status)
+        .await
+        .map_err(internal_error)?
+    {
+        let before = Quote {
+            status: QuoteStatus::Pending,
+            ..quote.clone()
+        };
+        audit::record(
+            storage,
+            actor,
+            AuditAction::Update,
+            AuditEntity::Quote,
+            id,
+            audit::changes(&before, &quote),
+        )
+        .await;
+        return Ok(Negotiated(quote));
+    }
+
+    match storage.get_quote(id).await.map_err(internal_error)? {
+        Some(quote) => Err(error(
+            Status::Conflict,
+            format!("quote {} is already {}", id, quote.status.as_str()),
+        )),
+        None => Err(error(Status::NotFound, format!("no quote with id {}", id))),
+    }
+}
+
+/// Adds a pending quote to the random pool.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Quote),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The quote is not pending", body = ErrorResponse),
+    )
+)]
+#[post("/admin/moderation/<id>/approve")]
+async fn approve(
+    _perm: Permission<Moderate>,
+    actor: Actor,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<Quote>> {
+    moderate(storage, &actor, id, QuoteStatus::Approved).await
+}
+
+/// Keeps a pending quote out of the pool. Rejected quotes are kept, so the
+/// same text cannot simply be submitted again.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Quote),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The quote is not pending", body = ErrorResponse),
+    )
+)]
+#[post("/admin/moderation/<id>/reject")]
+async fn reject(
+    _perm: Permission<Moderate>,
+    actor: Actor,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<Quote>> {
+    moderate(storage, &actor, id, QuoteStatus::Rejected).await
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, approve, reject]
+}
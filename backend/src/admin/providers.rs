@@ -0,0 +1,86 @@
+//! The payloads mock providers recorded instead of sending, when
+//! `providers.mode = "mock"`, and the circuit breakers of the real ones.
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::providers::{MockLog, Providers, Recorded};
+use crate::resilience::{ProviderStatus, Resilience};
+use crate::roles::{ManageServer, Permission};
+
+fn mock_log(providers: &Providers) -> ApiResult<&MockLog> {
+    providers
+        .log
+        .as_ref()
+        .ok_or_else(|| error(Status::NotFound, "providers are not in mock mode"))
+}
+
+/// Recorded payloads, oldest first, optionally for one `provider` (`email`,
+/// `sms`, `tts`, `music`, `weather` or `push`). Only the latest 200 are
+/// kept.
+#[utoipa::path(
+    tag = "admin",
+    params(("provider" = Option<String>, Query)),
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<Recorded>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "Not in mock mode", body = ErrorResponse),
+    )
+)]
+#[get("/admin/providers/log?<provider>")]
+fn log(
+    _perm: Permission<ManageServer>,
+    providers: &State<Providers>,
+    provider: Option<&str>,
+) -> ApiResult<Negotiated<Vec<Recorded>>> {
+    Ok(Negotiated(mock_log(providers)?.entries(provider)))
+}
+
+/// Empties the log, e.g. between test runs.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204, description = "Cleared"),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "Not in mock mode", body = ErrorResponse),
+    )
+)]
+#[delete("/admin/providers/log")]
+fn clear(
+    _perm: Permission<ManageServer>,
+    providers: &State<Providers>,
+) -> ApiResult<status::NoContent> {
+    mock_log(providers)?.clear();
+    Ok(status::NoContent)
+}
+
+/// The circuit breaker of each external provider that has been set up:
+/// whether calls go through, fail fast, or are waiting on a trial, with
+/// counts since startup. Empty in mock mode, where nothing calls out.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<ProviderStatus>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/providers/status")]
+fn breakers(
+    _perm: Permission<ManageServer>,
+    resilience: &State<Resilience>,
+) -> Negotiated<Vec<ProviderStatus>> {
+    Negotiated(resilience.status())
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![log, clear, breakers]
+}
@@ -0,0 +1,76 @@
+//! Checking on and refreshing the configured quote sources.
+
+use rocket::http::Status;
+use rocket::{Route, State};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::quote_sources::{QuoteSources, QuoteSourcesStatus, SourceStatus};
+use crate::roles::{ManageServer, Permission};
+use crate::storage::Storage;
+
+/// Every source in priority order, how many quotes each has, and how the
+/// last sync of each file and remote source went.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = QuoteSourcesStatus),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/quote-sources")]
+async fn list(
+    _perm: Permission<ManageServer>,
+    sources: &State<QuoteSources>,
+    storage: &State<Storage>,
+) -> ApiResult<Negotiated<QuoteSourcesStatus>> {
+    Ok(Negotiated(
+        sources.status(storage).await.map_err(internal_error)?,
+    ))
+}
+
+/// Re-reads a file source or re-fetches a remote one now, outside its
+/// refresh interval.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    params(("name" = String, Path, description = "A file or remote source")),
+    responses(
+        (status = 200, body = SourceStatus),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "No file or remote source with this name", body = ErrorResponse),
+        (status = 502, description = "The source could not be read or was invalid; its last synced quotes are kept", body = ErrorResponse),
+    )
+)]
+#[post("/admin/quote-sources/<name>/refresh")]
+async fn refresh(
+    _perm: Permission<ManageServer>,
+    sources: &State<QuoteSources>,
+    storage: &State<Storage>,
+    name: &str,
+) -> ApiResult<Negotiated<SourceStatus>> {
+    match sources.refresh(storage, name).await {
+        None => Err(error(
+            Status::NotFound,
+            format!("no file or remote quote source named `{}`", name),
+        )),
+        Some(Err(e)) => Err(error(Status::BadGateway, e)),
+        Some(Ok(())) => {
+            let status = sources.status(storage).await.map_err(internal_error)?;
+            Ok(Negotiated(
+                status
+                    .sources
+                    .into_iter()
+                    .find(|source| source.name == name)
+                    .expect("synced sources are listed"),
+            ))
+        }
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, refresh]
+}
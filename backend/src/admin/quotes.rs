@@ -0,0 +1,307 @@
+use std::collections::HashSet;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{self, Actor};
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::roles::{ManageContent, Permission};
+use crate::storage::{
+    self, AuditAction, AuditEntity, Category, NewQuote, Quote, QuoteStatus, Storage,
+};
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_QUOTE_LEN: usize = 300;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub(crate) struct QuoteRequest {
+    text: String,
+    #[serde(default = "default_category")]
+    #[schema(default = "romantic")]
+    category: Category,
+}
+
+fn default_category() -> Category {
+    Category::Romantic
+}
+
+impl Validate for QuoteRequest {
+    type Valid = NewQuote;
+
+    fn validate(self) -> Result<NewQuote, FieldErrors> {
+        let text = self.text.trim().to_string();
+        let mut errors = FieldErrors::new();
+        errors.text("text", &text, MAX_QUOTE_LEN);
+        errors.finish(NewQuote {
+            text,
+            category: self.category,
+        })
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ImportResponse {
+    imported: usize,
+    quotes: Vec<Quote>,
+}
+
+pub(crate) fn invalid(e: String) -> ApiError {
+    error(Status::UnprocessableEntity, e)
+}
+
+pub(crate) fn duplicate_or_internal(e: sqlx::Error) -> ApiError {
+    if storage::is_unique_violation(&e) {
+        error(Status::Conflict, "a quote with this text already exists")
+    } else {
+        internal_error(e)
+    }
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Page<Quote>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/quotes?<page>&<per_page>")]
+async fn list(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> ApiResult<Negotiated<Page<Quote>>> {
+    let (page, per_page, offset) = paginate(page, per_page);
+    let total = storage
+        .count_quotes(None, None)
+        .await
+        .map_err(internal_error)?;
+    let items = storage
+        .list_quotes(None, per_page, offset)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Quote),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/admin/quotes/<id>")]
+async fn get(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<Quote>> {
+    storage
+        .get_quote(id)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no quote with id {}", id)))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    request_body = QuoteRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Quote),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Duplicate quote text", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/admin/quotes", data = "<request>")]
+async fn create(
+    _perm: Permission<ManageContent>,
+    actor: Actor,
+    storage: &Storage,
+    request: Valid<QuoteRequest>,
+) -> ApiResult<status::Created<Negotiated<Quote>>> {
+    let quote = storage
+        .create_quote(&request.0, QuoteStatus::Approved)
+        .await
+        .map_err(duplicate_or_internal)?;
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Create,
+        AuditEntity::Quote,
+        quote.id,
+        audit::created(&quote),
+    )
+    .await;
+
+    let location = uri!(get(quote.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(quote)))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    request_body = QuoteRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Quote),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Duplicate quote text", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[put("/admin/quotes/<id>", data = "<request>")]
+async fn update(
+    _perm: Permission<ManageContent>,
+    actor: Actor,
+    storage: &Storage,
+    id: i64,
+    request: Valid<QuoteRequest>,
+) -> ApiResult<Negotiated<Quote>> {
+    let not_found = || error(Status::NotFound, format!("no quote with id {}", id));
+    let before = storage
+        .get_quote(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+    let quote = storage
+        .update_quote(id, &request.0)
+        .await
+        .map_err(duplicate_or_internal)?
+        .ok_or_else(not_found)?;
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Update,
+        AuditEntity::Quote,
+        id,
+        audit::changes(&before, &quote),
+    )
+    .await;
+    Ok(Negotiated(quote))
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/admin/quotes/<id>")]
+async fn delete(
+    _perm: Permission<ManageContent>,
+    actor: Actor,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Status> {
+    let quote = storage
+        .delete_quote(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no quote with id {}", id)))?;
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Delete,
+        AuditEntity::Quote,
+        id,
+        audit::deleted(&quote),
+    )
+    .await;
+    Ok(Status::NoContent)
+}
+
+/// Imports a JSON array of quotes atomically. Any duplicate, whether against
+/// the existing pool or within the batch, rejects the whole import.
+#[utoipa::path(
+    tag = "admin",
+    request_body = Vec<QuoteRequest>,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = ImportResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Duplicates in the batch or the pool", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/admin/quotes/import", data = "<request>")]
+async fn import(
+    _perm: Permission<ManageContent>,
+    actor: Actor,
+    storage: &Storage,
+    request: Valid<Vec<QuoteRequest>>,
+) -> ApiResult<status::Created<Negotiated<ImportResponse>>> {
+    let quotes = request.into_inner();
+
+    let mut seen = HashSet::new();
+    let mut duplicates: Vec<String> = quotes
+        .iter()
+        .filter(|q| !seen.insert(q.text.as_str()))
+        .map(|q| q.text.clone())
+        .collect();
+
+    let texts: Vec<String> = quotes.iter().map(|q| q.text.clone()).collect();
+    duplicates.extend(
+        storage
+            .existing_quote_texts(&texts)
+            .await
+            .map_err(internal_error)?,
+    );
+    if !duplicates.is_empty() {
+        return Err(error(
+            Status::Conflict,
+            format!("duplicate quotes: {}", duplicates.join(" | ")),
+        ));
+    }
+
+    let quotes = storage
+        .import_quotes(&quotes)
+        .await
+        .map_err(duplicate_or_internal)?;
+    for quote in &quotes {
+        audit::record(
+            storage,
+            &actor,
+            AuditAction::Create,
+            AuditEntity::Quote,
+            quote.id,
+            audit::created(quote),
+        )
+        .await;
+    }
+
+    Ok(
+        status::Created::new(uri!(list(_, _)).to_string()).body(Negotiated(ImportResponse {
+            imported: quotes.len(),
+            quotes,
+        })),
+    )
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, get, create, update, delete, import]
+}
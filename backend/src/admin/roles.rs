@@ -0,0 +1,144 @@
+//! Granting and revoking the [`Role`]s that let signed-in users reach the
+//! `/admin` routes; see [`crate::roles`] for what each one allows.
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+use rocket::Route;
+
+use crate::audit::{self, Actor};
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageUsers, Permission};
+use crate::storage::{AuditAction, AuditEntity, Role, RoleGrant, Storage};
+
+fn parse_role(role: &str) -> ApiResult<Role> {
+    Role::parse(role).ok_or_else(|| {
+        error(
+            Status::NotFound,
+            format!("no role named `{}`; use `admin` or `moderator`", role),
+        )
+    })
+}
+
+/// The roles of user `id` after a grant or revoke, auditing the change from
+/// `before` if there was one.
+async fn roles_after(
+    storage: &Storage,
+    actor: &Actor,
+    id: i64,
+    changed: bool,
+    before: Vec<Role>,
+) -> ApiResult<Vec<Role>> {
+    let after = storage.user_roles(id).await.map_err(internal_error)?;
+    if changed {
+        audit::record(
+            storage,
+            actor,
+            AuditAction::Update,
+            AuditEntity::User,
+            id,
+            audit::changes(&json!({ "roles": before }), &json!({ "roles": after })),
+        )
+        .await;
+    }
+    Ok(after)
+}
+
+/// Every role held by any user.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<RoleGrant>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/roles")]
+async fn list(
+    _perm: Permission<ManageUsers>,
+    storage: &Storage,
+) -> ApiResult<Negotiated<Vec<RoleGrant>>> {
+    storage
+        .list_role_grants()
+        .await
+        .map(Negotiated)
+        .map_err(internal_error)
+}
+
+/// Grants `role` to user `id`; granting a held role changes nothing.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    params(("role" = Role, Path)),
+    responses(
+        (status = 200, description = "The user's roles", body = Vec<Role>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "Unknown user or role", body = ErrorResponse),
+    )
+)]
+#[put("/admin/users/<id>/roles/<role>")]
+async fn grant(
+    _perm: Permission<ManageUsers>,
+    actor: Actor,
+    storage: &Storage,
+    id: i64,
+    role: &str,
+) -> ApiResult<Negotiated<Vec<Role>>> {
+    let role = parse_role(role)?;
+    if storage
+        .get_user(id)
+        .await
+        .map_err(internal_error)?
+        .is_none()
+    {
+        return Err(error(Status::NotFound, format!("no user with id {}", id)));
+    }
+    let before = storage.user_roles(id).await.map_err(internal_error)?;
+    let granted = storage.grant_role(id, role).await.map_err(internal_error)?;
+    roles_after(storage, &actor, id, granted, before)
+        .await
+        .map(Negotiated)
+}
+
+/// Takes `role` away from user `id`.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    params(("role" = Role, Path)),
+    responses(
+        (status = 200, description = "The user's remaining roles", body = Vec<Role>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, description = "Unknown role, or not held by the user", body = ErrorResponse),
+    )
+)]
+#[delete("/admin/users/<id>/roles/<role>")]
+async fn revoke(
+    _perm: Permission<ManageUsers>,
+    actor: Actor,
+    storage: &Storage,
+    id: i64,
+    role: &str,
+) -> ApiResult<Negotiated<Vec<Role>>> {
+    let role = parse_role(role)?;
+    let before = storage.user_roles(id).await.map_err(internal_error)?;
+    if !storage
+        .revoke_role(id, role)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(error(
+            Status::NotFound,
+            format!("user {} does not have the `{}` role", id, role.as_str()),
+        ));
+    }
+    roles_after(storage, &actor, id, true, before)
+        .await
+        .map(Negotiated)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, grant, revoke]
+}
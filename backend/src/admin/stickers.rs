@@ -0,0 +1,306 @@
+//! Uploading and removing the sticker packs behind `GET /api/stickers`.
+
+use std::collections::HashSet;
+use std::io::Cursor;
+
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageContent, Permission};
+use crate::stickers::StickerPackResponse;
+use crate::storage::{self, NewSticker, Storage};
+use crate::uploads::{read_image, save_upload, UploadStore};
+use crate::valentine::check_text;
+
+const MAX_PACK_NAME_LEN: usize = 60;
+const MAX_STICKER_NAME_LEN: usize = 32;
+const MAX_PACK_STICKERS: usize = 50;
+const MAX_STICKER_BYTES: usize = 512 * 1024;
+/// Longest side of a sticker image, in pixels.
+const MAX_STICKER_SIDE: u32 = 512;
+
+/// Lowercases a sticker's file name, without its extension, and checks it
+/// is 1 to 32 letters, digits, `-` or `_`.
+fn sticker_name(file_name: Option<&str>) -> Result<String, String> {
+    let name = file_name.unwrap_or("").trim().to_lowercase();
+    if name.is_empty()
+        || name.len() > MAX_STICKER_NAME_LEN
+        || !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return Err(format!(
+            "sticker file name `{}` must be 1 to {} letters, digits, `-` or `_` before the extension",
+            file_name.unwrap_or(""),
+            MAX_STICKER_NAME_LEN
+        ));
+    }
+    Ok(name)
+}
+
+/// Checks the size and dimensions of a sticker image.
+fn check_sticker(name: &str, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() > MAX_STICKER_BYTES {
+        return Err(format!(
+            "sticker `{}` is {} bytes, at most {} are allowed",
+            name,
+            bytes.len(),
+            MAX_STICKER_BYTES
+        ));
+    }
+    let (width, height) = image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())
+        .and_then(|reader| reader.into_dimensions().map_err(|e| e.to_string()))
+        .map_err(|e| format!("sticker `{}` could not be decoded: {}", name, e))?;
+    if width > MAX_STICKER_SIDE || height > MAX_STICKER_SIDE {
+        return Err(format!(
+            "sticker `{}` is {}x{}, at most {}x{} is allowed",
+            name, width, height, MAX_STICKER_SIDE, MAX_STICKER_SIDE
+        ));
+    }
+    Ok(())
+}
+
+/// Validates every file of a request, then stores them, so a bad file
+/// leaves nothing behind in the upload store. `existing` are the names
+/// already in the pack.
+async fn save_stickers(
+    storage: &Storage,
+    store: &UploadStore,
+    files: &[TempFile<'_>],
+    existing: &[String],
+) -> ApiResult<Vec<NewSticker>> {
+    if files.is_empty() {
+        return Err(error(
+            Status::UnprocessableEntity,
+            "`stickers` must contain at least one image",
+        ));
+    }
+    if existing.len() + files.len() > MAX_PACK_STICKERS {
+        return Err(error(
+            Status::UnprocessableEntity,
+            format!("a pack holds at most {} stickers", MAX_PACK_STICKERS),
+        ));
+    }
+
+    let mut names: HashSet<String> = existing.iter().cloned().collect();
+    let mut images = Vec::with_capacity(files.len());
+    for file in files {
+        let name = sticker_name(file.name()).map_err(|e| error(Status::UnprocessableEntity, e))?;
+        if !names.insert(name.clone()) {
+            return Err(error(
+                Status::Conflict,
+                format!("the pack already has a sticker named `{}`", name),
+            ));
+        }
+        let (bytes, content_type) = read_image(file, "stickers").await?;
+        check_sticker(&name, &bytes).map_err(|e| error(Status::UnprocessableEntity, e))?;
+        images.push((name, bytes, content_type));
+    }
+
+    let mut stickers = Vec::with_capacity(images.len());
+    for (name, bytes, content_type) in images {
+        let upload = save_upload(storage, store, bytes, content_type).await?;
+        stickers.push(NewSticker {
+            name,
+            upload_id: upload.id,
+        });
+    }
+    Ok(stickers)
+}
+
+fn duplicate_or_internal(e: sqlx::Error) -> ApiError {
+    if storage::is_unique_violation(&e) {
+        error(
+            Status::Conflict,
+            "a sticker pack or sticker with this name already exists",
+        )
+    } else {
+        internal_error(e)
+    }
+}
+
+#[derive(FromForm, utoipa::ToSchema)]
+struct StickerPackForm<'r> {
+    name: String,
+    /// One PNG, JPEG, GIF or WebP image per sticker, named by its file name.
+    #[schema(value_type = Vec<String>, format = Binary)]
+    stickers: Vec<TempFile<'r>>,
+}
+
+#[derive(FromForm, utoipa::ToSchema)]
+struct StickersForm<'r> {
+    #[schema(value_type = Vec<String>, format = Binary)]
+    stickers: Vec<TempFile<'r>>,
+}
+
+/// Creates a pack from a `multipart/form-data` body with a `name` field and
+/// one `stickers` field per image. Each image is at most 512 KiB and
+/// 512x512 pixels, and its file name without the extension becomes the
+/// sticker's name.
+#[utoipa::path(
+    tag = "admin",
+    request_body(content = StickerPackForm, content_type = "multipart/form-data"),
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = StickerPackResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Duplicate pack or sticker name", body = ErrorResponse),
+        (status = 413, description = "Larger than `limits.file` or `limits.data-form`"),
+        (status = 415, description = "Not a PNG, JPEG, GIF or WebP image", body = ErrorResponse),
+        (status = 422, description = "Invalid name, or a sticker too large or undecodable", body = ErrorResponse),
+    )
+)]
+#[post("/admin/sticker-packs", data = "<form>")]
+async fn create(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    store: &State<UploadStore>,
+    public_url: &PublicUrl,
+    form: Form<StickerPackForm<'_>>,
+) -> ApiResult<status::Created<Negotiated<StickerPackResponse>>> {
+    let name = form.name.trim().to_string();
+    check_text("name", &name, MAX_PACK_NAME_LEN)
+        .map_err(|e| error(Status::UnprocessableEntity, e))?;
+    let stickers = save_stickers(storage, store, &form.stickers, &[]).await?;
+
+    let pack = storage
+        .create_sticker_pack(&name, &stickers)
+        .await
+        .map_err(duplicate_or_internal)?;
+    let location = uri!(crate::stickers::list).to_string();
+    Ok(status::Created::new(location).body(Negotiated(StickerPackResponse::new(public_url, pack))))
+}
+
+/// Adds stickers to a pack, sent like those of a new pack.
+#[utoipa::path(
+    tag = "admin",
+    request_body(content = StickersForm, content_type = "multipart/form-data"),
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = StickerPackResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Duplicate sticker name", body = ErrorResponse),
+        (status = 413, description = "Larger than `limits.file` or `limits.data-form`"),
+        (status = 415, description = "Not a PNG, JPEG, GIF or WebP image", body = ErrorResponse),
+        (status = 422, description = "Invalid name, or a sticker too large or undecodable", body = ErrorResponse),
+    )
+)]
+#[post("/admin/sticker-packs/<id>/stickers", data = "<form>")]
+async fn add(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    store: &State<UploadStore>,
+    public_url: &PublicUrl,
+    id: i64,
+    form: Form<StickersForm<'_>>,
+) -> ApiResult<Negotiated<StickerPackResponse>> {
+    let not_found = || error(Status::NotFound, format!("no sticker pack with id {}", id));
+    let pack = storage
+        .get_sticker_pack(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+    let existing: Vec<String> = pack.stickers.into_iter().map(|s| s.name).collect();
+    let stickers = save_stickers(storage, store, &form.stickers, &existing).await?;
+
+    storage
+        .add_stickers(id, &stickers)
+        .await
+        .map_err(duplicate_or_internal)?
+        .map(|pack| Negotiated(StickerPackResponse::new(public_url, pack)))
+        .ok_or_else(not_found)
+}
+
+/// Deletes a pack and its stickers. Valentines keep their text but lose the
+/// sticker, and reactions with its stickers are removed.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/admin/sticker-packs/<id>")]
+async fn delete_pack(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Status> {
+    match storage
+        .delete_sticker_pack(id)
+        .await
+        .map_err(internal_error)?
+    {
+        true => Ok(Status::NoContent),
+        false => Err(error(
+            Status::NotFound,
+            format!("no sticker pack with id {}", id),
+        )),
+    }
+}
+
+/// Deletes one sticker, like deleting a pack does for all of them.
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/admin/stickers/<id>")]
+async fn delete_sticker(
+    _perm: Permission<ManageContent>,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Status> {
+    match storage.delete_sticker(id).await.map_err(internal_error)? {
+        true => Ok(Status::NoContent),
+        false => Err(error(
+            Status::NotFound,
+            format!("no sticker with id {}", id),
+        )),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, add, delete_pack, delete_sticker]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sticker_names_come_from_file_names() {
+        assert_eq!(sticker_name(Some("Heart-Eyes")).unwrap(), "heart-eyes");
+        assert_eq!(sticker_name(Some("kiss_2")).unwrap(), "kiss_2");
+        assert!(sticker_name(None).is_err());
+        assert!(sticker_name(Some("two words")).is_err());
+        assert!(sticker_name(Some(&"x".repeat(33))).is_err());
+
+        let mut png = Vec::new();
+        image::RgbaImage::new(600, 10)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let e = check_sticker("wide", &png).unwrap_err();
+        assert!(e.contains("600x10"), "{}", e);
+        assert!(check_sticker("junk", b"\x89PNG\r\n\x1a\nnope").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Creating and configuring the tenants of a multi-tenant server. These
+//! routes are only served on the main site.
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+use serde::Deserialize;
+
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::roles::{ManageServer, Permission};
+use crate::storage::{NewTenant, QuotePool, Tenant, TenantUpdate};
+use crate::tenants::{self, CurrentTenant, Tenants, MAX_SLUG_LEN};
+use crate::themes::Themes;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_NAME_LEN: usize = 60;
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct TenantRequest {
+    /// Lowercase letters, digits and inner hyphens: the subdomain or
+    /// `/t/<slug>` path the tenant is served under.
+    slug: String,
+    name: String,
+    /// A theme id from `GET /api/themes` for cards, emails and PDFs that
+    /// name none.
+    theme: Option<String>,
+    /// `own` by default.
+    #[serde(default)]
+    quote_pool: QuotePool,
+}
+
+impl Validate for TenantRequest {
+    type Valid = NewTenant;
+
+    fn validate(self) -> Result<NewTenant, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if !tenants::is_slug(&self.slug) {
+            errors.add(
+                "slug",
+                format!(
+                    "must be 1 to {} lowercase letters, digits or inner hyphens",
+                    MAX_SLUG_LEN
+                ),
+            );
+        }
+        let name = self.name.trim().to_string();
+        errors.text("name", &name, MAX_NAME_LEN);
+        errors.finish(NewTenant {
+            slug: self.slug,
+            name,
+            theme: self.theme,
+            quote_pool: self.quote_pool,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct TenantUpdateRequest {
+    name: String,
+    theme: Option<String>,
+    #[serde(default)]
+    quote_pool: QuotePool,
+}
+
+impl Validate for TenantUpdateRequest {
+    type Valid = TenantUpdate;
+
+    fn validate(self) -> Result<TenantUpdate, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let name = self.name.trim().to_string();
+        errors.text("name", &name, MAX_NAME_LEN);
+        errors.finish(TenantUpdate {
+            name,
+            theme: self.theme,
+            quote_pool: self.quote_pool,
+        })
+    }
+}
+
+/// Fails on a tenant's site, where these routes do not exist.
+fn main_site(tenant: CurrentTenant<'_>) -> ApiResult<()> {
+    match tenant.0 {
+        Some(_) => Err(error(
+            Status::NotFound,
+            "tenants are managed on the main site",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Normalizes a tenant's theme to the id it names.
+fn check_theme(themes: &Themes, theme: Option<String>) -> ApiResult<Option<String>> {
+    theme
+        .map(|theme| {
+            themes
+                .get(&theme)
+                .map(|_| theme.to_ascii_lowercase())
+                .map_err(|e| error(Status::UnprocessableEntity, e))
+        })
+        .transpose()
+}
+
+#[utoipa::path(
+    tag = "admin",
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<Tenant>),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+    )
+)]
+#[get("/admin/tenants")]
+async fn list(
+    _perm: Permission<ManageServer>,
+    current: CurrentTenant<'_>,
+    tenants: &State<Tenants>,
+) -> ApiResult<Negotiated<Vec<Tenant>>> {
+    main_site(current)?;
+    Ok(Negotiated(tenants.list()))
+}
+
+/// Creates a tenant with its own database, which starts with the bundled
+/// quotes and a copy of the main site's gift catalog.
+#[utoipa::path(
+    tag = "admin",
+    request_body = TenantRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Tenant),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 409, description = "Duplicate slug", body = ErrorResponse),
+        (status = 422, description = "Invalid field or unknown theme", body = ErrorResponse),
+        (status = 503, description = "Tenancy is not configured", body = ErrorResponse),
+    )
+)]
+#[post("/admin/tenants", data = "<request>")]
+async fn create(
+    _perm: Permission<ManageServer>,
+    current: CurrentTenant<'_>,
+    tenants: &State<Tenants>,
+    themes: &State<Themes>,
+    request: Valid<TenantRequest>,
+) -> ApiResult<status::Created<Negotiated<Tenant>>> {
+    main_site(current)?;
+    let mut tenant = request.into_inner();
+    tenant.theme = check_theme(themes, tenant.theme)?;
+    let tenant = tenants.create(&tenant).await?;
+    let location = uri!(list).to_string();
+    Ok(status::Created::new(location).body(Negotiated(tenant)))
+}
+
+/// Renames a tenant or changes its theme or quote pool.
+#[utoipa::path(
+    tag = "admin",
+    request_body = TenantUpdateRequest,
+    security(("api_key" = []), ("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Tenant),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Signed in without the permission", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 422, description = "Invalid field or unknown theme", body = ErrorResponse),
+        (status = 503, description = "Tenancy is not configured", body = ErrorResponse),
+    )
+)]
+#[put("/admin/tenants/<slug>", data = "<request>")]
+async fn update(
+    _perm: Permission<ManageServer>,
+    current: CurrentTenant<'_>,
+    tenants: &State<Tenants>,
+    themes: &State<Themes>,
+    slug: &str,
+    request: Valid<TenantUpdateRequest>,
+) -> ApiResult<Negotiated<Tenant>> {
+    main_site(current)?;
+    let mut update = request.into_inner();
+    update.theme = check_theme(themes, update.theme)?;
+    tenants
+        .update(slug, &update)
+        .await?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no tenant named `{}`", slug)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, create, update]
+}
@@ -0,0 +1,440 @@
+//! Spoken valentines: messages read aloud by a text-to-speech provider and
+//! cached on disk as MP3s.
+
+pub(crate) mod tts;
+
+use std::io::{self, Cursor, SeekFrom};
+use std::path::PathBuf;
+
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, status, Responder, Response};
+use rocket::tokio::fs::{self, File};
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::providers::{self, MockProvider};
+use crate::resilience::Resilience;
+use crate::storage::{Message, Storage};
+use crate::tokens;
+use crate::users::CoupleScope;
+
+use tts::{HttpConfig, HttpProvider, TtsProvider};
+
+/// Messages never change once sent, so neither does their audio.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// The `[default.tts]` table in Rocket.toml. Synthesis is enabled by a
+/// provider sub-table; `dir` holds the cached MP3s either way.
+#[derive(Debug, Deserialize)]
+struct TtsConfig {
+    #[serde(default = "default_dir")]
+    dir: PathBuf,
+    http: Option<HttpConfig>,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from("audio")
+}
+
+/// The configured provider, if any, and where its output is kept.
+pub struct Speech {
+    provider: Option<Box<dyn TtsProvider>>,
+    dir: PathBuf,
+}
+
+impl Speech {
+    fn path(&self, id: i64) -> PathBuf {
+        self.dir.join(format!("{}.mp3", id))
+    }
+
+    /// Size of the cached MP3 for message `id`, if there is one.
+    async fn cached(&self, id: i64) -> Result<Option<u64>, String> {
+        match fs::metadata(self.path(id)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    async fn store(&self, id: i64, bytes: &[u8]) -> Result<(), String> {
+        // A unique partial name, so concurrent requests for the same
+        // message never write into each other's file.
+        let partial = self
+            .dir
+            .join(format!("{}.{}.partial", id, tokens::random_token(8)));
+        fs::write(&partial, bytes)
+            .await
+            .map_err(|e| e.to_string())?;
+        fs::rename(&partial, self.path(id))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn cache_error(e: String) -> ApiError {
+    error!("audio cache error: {}", e);
+    error(Status::InternalServerError, "internal audio cache error")
+}
+
+/// What gets read aloud, laid out like the email version of the message.
+fn script(message: &Message) -> String {
+    let text = message.message.trim();
+    // End on a full stop so the sign-off is read as its own sentence.
+    let stop = if text.ends_with(|c: char| ".!?".contains(c)) {
+        ""
+    } else {
+        "."
+    };
+    format!(
+        "{}. {}{} With love, {}.",
+        message.recipient.as_deref().unwrap_or("Hi"),
+        text,
+        stop,
+        message.sender
+    )
+}
+
+/// How much of a file a `Range` header asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// Inclusive, as in `Content-Range`.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Interprets a `Range` header against a file of `len` bytes. Only a single
+/// `bytes=` range is honoured; anything else is ignored and the whole file
+/// served, as RFC 9110 allows.
+fn byte_range(header: Option<&str>, len: u64) -> ByteRange {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    if start.is_empty() {
+        // `bytes=-n`: the last n bytes.
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial(len.saturating_sub(n), len - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Full;
+    };
+    let end = match end {
+        "" => len - 1,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => end.min(len - 1),
+            _ => return ByteRange::Full,
+        },
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(start, end)
+}
+
+/// The `Range` request header, if sent.
+struct RangeHeader(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(RangeHeader(
+            request.headers().get_one("Range").map(str::to_string),
+        ))
+    }
+}
+
+enum Audio {
+    Full(Vec<u8>),
+    Partial {
+        bytes: Vec<u8>,
+        start: u64,
+        total: u64,
+    },
+    Unsatisfiable {
+        total: u64,
+    },
+}
+
+impl<'r> Responder<'r, 'static> for Audio {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("Accept-Ranges", "bytes");
+
+        match self {
+            Audio::Full(bytes) => response
+                .header(ContentType::new("audio", "mpeg"))
+                .raw_header("Cache-Control", CACHE_CONTROL)
+                .sized_body(bytes.len(), Cursor::new(bytes))
+                .ok(),
+            Audio::Partial {
+                bytes,
+                start,
+                total,
+            } => {
+                let end = start + bytes.len() as u64 - 1;
+                response
+                    .status(Status::PartialContent)
+                    .header(ContentType::new("audio", "mpeg"))
+                    .raw_header("Cache-Control", CACHE_CONTROL)
+                    .header(Header::new(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, total),
+                    ))
+                    .sized_body(bytes.len(), Cursor::new(bytes))
+                    .ok()
+            }
+            Audio::Unsatisfiable { total } => response
+                .status(Status::RangeNotSatisfiable)
+                .header(Header::new("Content-Range", format!("bytes */{}", total)))
+                .ok(),
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct AudioInfo {
+    message_id: i64,
+    url: String,
+    size: u64,
+}
+
+#[derive(Responder)]
+enum SynthesizeResponse {
+    /// The audio was generated just now.
+    Created(status::Created<Negotiated<AudioInfo>>),
+    /// The audio was already cached; the provider was not called.
+    #[response(status = 200)]
+    Cached(Negotiated<AudioInfo>),
+}
+
+async fn require_message(storage: &Storage, scope: CoupleScope, id: i64) -> ApiResult<Message> {
+    storage
+        .get_message(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no message with id {}", id)))
+}
+
+/// Reads message `id` aloud through the configured provider and caches the
+/// MP3. Repeat calls return the cached file without calling the provider.
+#[utoipa::path(
+    tag = "messages",
+    security(("api_key" = [])),
+    responses(
+        (status = 201, description = "Audio generated", body = AudioInfo),
+        (status = 200, description = "Already generated", body = AudioInfo),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 502, description = "The TTS provider failed", body = ErrorResponse),
+        (status = 503, description = "No TTS provider is configured", body = ErrorResponse),
+    )
+)]
+#[post("/api/valentine/<id>/audio")]
+async fn synthesize(
+    _key: ApiKey,
+    storage: &Storage,
+    speech: &State<Speech>,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<SynthesizeResponse> {
+    let message = require_message(storage, scope, id).await?;
+    let info = |size| AudioInfo {
+        message_id: id,
+        url: public_url.absolute(&uri!(audio(id)).to_string()),
+        size,
+    };
+
+    if let Some(size) = speech.cached(id).await.map_err(cache_error)? {
+        return Ok(SynthesizeResponse::Cached(Negotiated(info(size))));
+    }
+    let provider = speech.provider.as_ref().ok_or_else(|| {
+        error(
+            Status::ServiceUnavailable,
+            "text-to-speech is not configured",
+        )
+    })?;
+    let bytes = provider.synthesize(&script(&message)).await.map_err(|e| {
+        error!("{} text-to-speech failed: {}", provider.name(), e);
+        error(Status::BadGateway, "the text-to-speech provider failed")
+    })?;
+    speech.store(id, &bytes).await.map_err(cache_error)?;
+
+    let info = info(bytes.len() as u64);
+    Ok(SynthesizeResponse::Created(
+        status::Created::new(info.url.clone()).body(Negotiated(info)),
+    ))
+}
+
+/// Serves the MP3 made by `POST /api/valentine/<id>/audio`, honouring a
+/// single `Range` so players can seek and stream.
+#[utoipa::path(
+    tag = "messages",
+    responses(
+        (status = 200, content_type = "audio/mpeg", body = Vec<u8>),
+        (status = 206, description = "The requested `Range`", content_type = "audio/mpeg", body = Vec<u8>),
+        (status = 404, description = "No such message, or no audio generated yet", body = ErrorResponse),
+        (status = 416, description = "`Range` starts past the end of the file"),
+    )
+)]
+#[get("/api/valentine/<id>/audio")]
+async fn audio(
+    storage: &Storage,
+    speech: &State<Speech>,
+    scope: CoupleScope,
+    range: RangeHeader,
+    id: i64,
+) -> ApiResult<Audio> {
+    require_message(storage, scope, id).await?;
+    let not_generated = || {
+        error(
+            Status::NotFound,
+            format!("no audio for message {}; POST to generate it", id),
+        )
+    };
+    let mut file = match File::open(speech.path(id)).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(not_generated()),
+        Err(e) => return Err(cache_error(e.to_string())),
+    };
+    let total = file
+        .metadata()
+        .await
+        .map_err(|e| cache_error(e.to_string()))?
+        .len();
+
+    match byte_range(range.0.as_deref(), total) {
+        ByteRange::Full => {
+            let mut bytes = Vec::with_capacity(total as usize);
+            file.read_to_end(&mut bytes)
+                .await
+                .map_err(|e| cache_error(e.to_string()))?;
+            Ok(Audio::Full(bytes))
+        }
+        ByteRange::Partial(start, end) => {
+            let mut bytes = vec![0; (end - start + 1) as usize];
+            file.seek(SeekFrom::Start(start))
+                .await
+                .map_err(|e| cache_error(e.to_string()))?;
+            file.read_exact(&mut bytes)
+                .await
+                .map_err(|e| cache_error(e.to_string()))?;
+            Ok(Audio::Partial {
+                bytes,
+                start,
+                total,
+            })
+        }
+        ByteRange::Unsatisfiable => Ok(Audio::Unsatisfiable { total }),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![synthesize, audio]
+}
+
+/// Manages [`Speech`] from the optional `tts` table, with the mock provider
+/// in mock mode. Must be attached after the providers and resilience
+/// stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Text-to-speech", |rocket| async {
+        let config = match rocket.figment().extract_inner::<TtsConfig>("tts") {
+            Ok(config) => config,
+            Err(e) if e.missing() => TtsConfig {
+                dir: default_dir(),
+                http: None,
+            },
+            Err(e) => {
+                error!("invalid tts config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let provider: Option<Box<dyn TtsProvider>> =
+            match (providers::mock_log(&rocket), config.http) {
+                (Some(log), _) => Some(Box::new(MockProvider::new(log))),
+                (None, Some(http)) => {
+                    let Some(client) = rocket.state::<Resilience>().map(|r| r.client("tts")) else {
+                        error!("tts stage attached before the resilience stage");
+                        return Err(rocket);
+                    };
+                    match HttpProvider::new(http, client) {
+                        Ok(provider) => Some(Box::new(provider)),
+                        Err(e) => {
+                            error!("{}", e);
+                            return Err(rocket);
+                        }
+                    }
+                }
+                (None, None) => {
+                    info!("no tts provider configured, audio generation disabled");
+                    None
+                }
+            };
+
+        if let Err(e) = fs::create_dir_all(&config.dir).await {
+            error!("failed to create audio dir {}: {}", config.dir.display(), e);
+            return Err(rocket);
+        }
+        Ok(rocket.manage(Speech {
+            provider,
+            dir: config.dir,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_headers_are_resolved_against_the_file() {
+        assert_eq!(byte_range(None, 100), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=0-9"), 100), ByteRange::Partial(0, 9));
+        assert_eq!(
+            byte_range(Some("bytes=90-"), 100),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=90-500"), 100),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=-10"), 100),
+            ByteRange::Partial(90, 99)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=-500"), 100),
+            ByteRange::Partial(0, 99)
+        );
+        assert_eq!(
+            byte_range(Some("bytes=100-"), 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(byte_range(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        // Malformed, reversed and multi-part ranges fall back to the whole file.
+        assert_eq!(byte_range(Some("bytes=9-0"), 100), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=0-1,5-6"), 100), ByteRange::Full);
+        assert_eq!(byte_range(Some("items=0-9"), 100), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=abc"), 100), ByteRange::Full);
+    }
+}
@@ -0,0 +1,106 @@
+//! Text-to-speech providers. Each turns a message into MP3 bytes; the
+//! `audio` module caches the result, so a provider is called at most once
+//! per message.
+
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::resilience::ResilientClient;
+
+/// Anything that can read a valentine aloud.
+#[rocket::async_trait]
+pub trait TtsProvider: Send + Sync {
+    /// Identifies the provider in logs, e.g. `http (alloy)`.
+    fn name(&self) -> String;
+
+    /// Returns `text` spoken as an MP3.
+    async fn synthesize(&self, text: &str) -> Result<Vec<u8>, String>;
+}
+
+/// The `[default.tts.http]` table in Rocket.toml: any endpoint speaking the
+/// OpenAI `audio/speech` shape (OpenAI itself, or a self-hosted server such
+/// as openedai-speech or Kokoro-FastAPI).
+#[derive(Debug, Deserialize)]
+pub struct HttpConfig {
+    /// e.g. `https://api.openai.com/v1/audio/speech`.
+    pub endpoint: String,
+    /// Sent as a bearer token when set.
+    pub api_key: Option<String>,
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(default = "default_voice")]
+    pub voice: String,
+}
+
+fn default_model() -> String {
+    "tts-1".to_string()
+}
+
+fn default_voice() -> String {
+    "alloy".to_string()
+}
+
+#[derive(Serialize)]
+struct SpeechRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+    voice: &'a str,
+    response_format: &'static str,
+}
+
+pub struct HttpProvider {
+    config: HttpConfig,
+    endpoint: Url,
+    client: ResilientClient,
+}
+
+impl HttpProvider {
+    pub fn new(config: HttpConfig, client: ResilientClient) -> Result<Self, String> {
+        let endpoint = Url::parse(&config.endpoint)
+            .map_err(|e| format!("invalid tts.http.endpoint: {}", e))?;
+        Ok(HttpProvider {
+            config,
+            endpoint,
+            client,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl TtsProvider for HttpProvider {
+    fn name(&self) -> String {
+        format!("http ({})", self.config.voice)
+    }
+
+    async fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        let mut request = self
+            .client
+            .post(self.endpoint.clone())
+            .json(&SpeechRequest {
+                model: &self.config.model,
+                input: text,
+                voice: &self.config.voice,
+                response_format: "mp3",
+            });
+        if let Some(key) = &self.config.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = self.client.send(request).await?;
+        let status = response.status();
+        if status != StatusCode::OK {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!(
+                "{} responded {}: {}",
+                self.endpoint,
+                status,
+                body.chars().take(200).collect::<String>()
+            ));
+        }
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        if bytes.is_empty() {
+            return Err(format!("{} returned no audio", self.endpoint));
+        }
+        Ok(bytes.to_vec())
+    }
+}
@@ -0,0 +1,155 @@
+//! The audit log: who created, changed or deleted which quote, message,
+//! schedule or webhook, who changed whose roles, and what changed. Handlers
+//! take an [`Actor`] and [`record`] each change after it is stored; workers
+//! record theirs as [`Actor::system`].
+
+use std::collections::BTreeSet;
+
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::{self, serde_json::Map, Value};
+use serde::Serialize;
+
+use crate::auth;
+use crate::storage::{AuditAction, AuditEntity, NewAuditEntry, Storage};
+use crate::users;
+
+/// Stands in for message bodies, which are encrypted at rest and so are
+/// kept out of the log.
+const REDACTED: &str = "[redacted]";
+
+/// Who made a change: `user:<id>` for a signed-in user, `key:<fingerprint>`
+/// for an API key, `system:<worker>` for background jobs, or `anonymous`
+/// when debug builds let keyless writes through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor(pub String);
+
+impl Actor {
+    pub fn system(worker: &str) -> Self {
+        Actor(format!("system:{}", worker))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Actor {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let actor = match users::signed_in_id(request).await {
+            Some(id) => format!("user:{}", id),
+            None => match auth::key_fingerprint(request) {
+                Some(fingerprint) => format!("key:{}", fingerprint),
+                None => "anonymous".to_string(),
+            },
+        };
+        Outcome::Success(Actor(actor))
+    }
+}
+
+fn object(value: &impl Serialize) -> Map<String, Value> {
+    match json::to_value(value) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+/// The fields that differ between `before` and `after`, each as
+/// `{"from": .., "to": ..}` with a side left out when the field is absent.
+fn diff(before: Map<String, Value>, after: Map<String, Value>) -> Value {
+    let fields: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    let mut changed = Map::new();
+    for field in fields {
+        let (from, to) = (before.get(field), after.get(field));
+        if from == to {
+            continue;
+        }
+        let mut change = Map::new();
+        if let Some(from) = from {
+            change.insert("from".to_string(), from.clone());
+        }
+        if let Some(to) = to {
+            change.insert("to".to_string(), to.clone());
+        }
+        changed.insert(field.clone(), Value::Object(change));
+    }
+    Value::Object(changed)
+}
+
+/// The diff of a newly created record.
+pub fn created(value: &impl Serialize) -> Value {
+    diff(Map::new(), object(value))
+}
+
+/// The diff of a deleted record.
+pub fn deleted(value: &impl Serialize) -> Value {
+    diff(object(value), Map::new())
+}
+
+/// The diff of an updated record.
+pub fn changes(before: &impl Serialize, after: &impl Serialize) -> Value {
+    diff(object(before), object(after))
+}
+
+/// Replaces the values of encrypted fields in `diff`, keeping the fact
+/// that they changed.
+fn redact(entity: AuditEntity, mut diff: Value) -> Value {
+    if matches!(entity, AuditEntity::Message | AuditEntity::Schedule) {
+        if let Some(Value::Object(change)) = diff.get_mut("message") {
+            for value in change.values_mut() {
+                *value = Value::String(REDACTED.to_string());
+            }
+        }
+    }
+    diff
+}
+
+/// Writes an audit entry for a change that has already been made. A
+/// failure is logged rather than undoing or failing the change.
+pub async fn record(
+    storage: &Storage,
+    actor: &Actor,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i64,
+    diff: Value,
+) {
+    let entry = NewAuditEntry {
+        actor: actor.0.clone(),
+        action,
+        entity,
+        entity_id,
+        diff: redact(entity, diff),
+    };
+    if let Err(e) = storage.record_audit(&entry).await {
+        error!(
+            "failed to audit {:?} of {:?} {} by {}: {}",
+            action, entity, entity_id, actor.0, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn diffs_keep_only_changed_fields() {
+        let before = json!({"id": 3, "text": "Be mine", "status": "pending"});
+        let after = json!({"id": 3, "text": "Be mine", "status": "approved"});
+        assert_eq!(
+            changes(&before, &after),
+            json!({"status": {"from": "pending", "to": "approved"}})
+        );
+        assert_eq!(
+            created(&json!({"id": 1, "to": null})),
+            json!({"id": {"to": 1}, "to": {"to": null}})
+        );
+        assert_eq!(deleted(&json!({"id": 1})), json!({"id": {"from": 1}}));
+
+        let message = created(&json!({"id": 1, "message": "secret"}));
+        assert_eq!(
+            redact(AuditEntity::Message, message),
+            json!({"id": {"to": 1}, "message": {"to": REDACTED}})
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+
+use crate::error::GuardError;
+use crate::users;
+
+const HEADER: &str = "X-Api-Key";
+
+/// Keys accepted by the [`ApiKey`] guard, from `api_keys` in Rocket.toml.
+#[derive(Clone)]
+pub struct ApiKeys {
+    keys: Vec<String>,
+}
+
+impl ApiKeys {
+    fn accepts(&self, candidate: &str) -> bool {
+        self.keys.iter().any(|key| constant_time_eq(key, candidate))
+    }
+
+    /// Checks a presented key, or its absence, giving the reason for
+    /// rejecting it otherwise. Debug builds without any configured keys
+    /// accept everything.
+    pub fn check(&self, candidate: Option<&str>) -> Result<(), &'static str> {
+        if self.keys.is_empty() && cfg!(debug_assertions) {
+            return Ok(());
+        }
+
+        match candidate {
+            Some(candidate) if self.accepts(candidate) => Ok(()),
+            Some(_) => Err("invalid api key"),
+            None => Err("missing X-Api-Key header"),
+        }
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Checks the `X-Api-Key` header alone, giving the status and reason for
+/// rejecting the request otherwise. Debug builds without any configured
+/// keys accept every request.
+pub fn check_api_key(request: &Request<'_>) -> Result<(), (Status, &'static str)> {
+    let Some(keys) = request.rocket().state::<ApiKeys>() else {
+        return Err((Status::InternalServerError, "api keys are not configured"));
+    };
+
+    keys.check(request.headers().get_one(HEADER))
+        .map_err(|reason| (Status::Unauthorized, reason))
+}
+
+/// The `X-Api-Key` header as sent, whether or not it is a configured key.
+pub fn presented_key<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    request.headers().get_one(HEADER)
+}
+
+/// A short, stable name for the API key the request presented, for audit
+/// records that must not hold the key itself.
+pub fn key_fingerprint(request: &Request<'_>) -> Option<String> {
+    request.headers().get_one(HEADER).map(fingerprint)
+}
+
+/// The short name [`key_fingerprint`] gives `key`.
+pub fn fingerprint(key: &str) -> String {
+    let digest = Sha256::digest(key);
+    digest[..4].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Request guard for mutating endpoints: succeeds only when the `X-Api-Key`
+/// header matches a configured key or the request carries a signed-in
+/// session. Debug builds without any configured keys let every request
+/// through. `/admin` routes take a [`Permission`](crate::roles::Permission)
+/// instead, which a session alone does not satisfy.
+pub struct ApiKey;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (status, failure) = match check_api_key(request) {
+            Ok(()) => return Outcome::Success(ApiKey),
+            Err((status, _))
+                if status == Status::Unauthorized && users::is_signed_in(request).await =>
+            {
+                return Outcome::Success(ApiKey)
+            }
+            Err(failure) => failure,
+        };
+        request.local_cache(|| GuardError(Some(failure.to_string())));
+        Outcome::Error((status, failure))
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("API Keys", |rocket| async {
+        let keys = match rocket.figment().extract_inner::<Vec<String>>("api_keys") {
+            Ok(keys) => keys,
+            Err(e) if e.missing() => Vec::new(),
+            Err(e) => {
+                error!("invalid api_keys config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        if keys.is_empty() {
+            if cfg!(debug_assertions) {
+                warn!("no api_keys configured, write endpoints are open in debug build");
+            } else {
+                warn!("no api_keys configured, write endpoints will reject every request");
+            }
+        }
+
+        Ok(rocket.manage(ApiKeys { keys }))
+    })
+}
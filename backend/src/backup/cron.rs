@@ -0,0 +1,187 @@
+//! Cron expressions for the backup schedule: the five standard fields
+//! (minute, hour, day of month, month, day of week), evaluated in UTC.
+//! Each field takes `*`, numbers, ranges (`1-5`), steps (`*/15`, `0-30/10`)
+//! and comma-separated lists of those.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+
+/// How far ahead [`Schedule::next_after`] looks before concluding the
+/// schedule never fires, e.g. `0 0 30 2 *`. Long enough to reach a 29th
+/// of February.
+const HORIZON_DAYS: i64 = 5 * 366;
+
+/// A parsed cron expression, each field a bitset of the values it allows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day-of-month or day-of-week field starts with `*`. As in
+    /// classic cron, a day must match both fields if either does, and
+    /// either field if neither does.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn number(field: &str, value: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` in the {} field is not a number", value, field))
+}
+
+/// The bitset of values `expr` allows between `min` and `max`.
+fn parse_field(field: &str, expr: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in expr.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match number(field, step)? {
+                0 => return Err(format!("step of `{}` in the {} field is 0", part, field)),
+                step => (range, step),
+            },
+            None => (part, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (number(field, start)?, number(field, end)?)
+        } else {
+            let start = number(field, range)?;
+            // `5/15` means every 15 from 5.
+            (start, if step > 1 { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!(
+                "`{}` in the {} field is outside {}-{}",
+                part, field, min, max
+            ));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn allows(bits: u64, value: u32) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "`{}` must have five fields: minute hour day month weekday",
+                expr
+            ));
+        };
+        let mut weekdays = parse_field("weekday", weekday, 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if allows(weekdays, 7) {
+            weekdays |= 1;
+        }
+
+        Ok(Schedule {
+            minutes: parse_field("minute", minute, 0, 59)?,
+            hours: parse_field("hour", hour, 0, 23)?,
+            days: parse_field("day", day, 1, 31)?,
+            months: parse_field("month", month, 1, 12)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+}
+
+fn midnight(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0)
+        .expect("midnight exists")
+        .and_utc()
+}
+
+impl Schedule {
+    fn allows_day(&self, date: NaiveDate) -> bool {
+        let day = allows(self.days, date.day());
+        let weekday = allows(self.weekdays, date.weekday().num_days_from_sunday());
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// The first minute after `time` the schedule fires at, or `None` if it
+    /// never does.
+    pub fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let horizon = time + Duration::days(HORIZON_DAYS);
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        while next < horizon {
+            let date = next.date_naive();
+            if !allows(self.months, date.month()) {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                next = midnight(NaiveDate::from_ymd_opt(year, month, 1)?);
+            } else if !self.allows_day(date) {
+                next = midnight(date.succ_opt()?);
+            } else if !allows(self.hours, next.hour()) {
+                next = next.with_minute(0)? + Duration::hours(1);
+            } else if !allows(self.minutes, next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+    }
+
+    #[test]
+    fn finds_the_next_matching_minute() {
+        let nightly: Schedule = "30 3 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(at("2026-02-14T03:30:00Z")),
+            Some(at("2026-02-15T03:30:00Z"))
+        );
+
+        let quarterly: Schedule = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            quarterly.next_after(at("2026-02-14T23:50:10Z")),
+            Some(at("2026-02-15T00:00:00Z"))
+        );
+
+        // The 1st of the month or any Sunday.
+        let either: Schedule = "0 0 1 * 7".parse().unwrap();
+        assert_eq!(
+            either.next_after(at("2026-02-14T12:00:00Z")),
+            Some(at("2026-02-15T00:00:00Z"))
+        );
+
+        let leap: Schedule = "0 12 29 2 *".parse().unwrap();
+        assert_eq!(
+            leap.next_after(at("2026-02-14T00:00:00Z")),
+            Some(at("2028-02-29T12:00:00Z"))
+        );
+        let never: Schedule = "0 0 30 2 *".parse().unwrap();
+        assert_eq!(never.next_after(at("2026-02-14T00:00:00Z")), None);
+
+        assert!("0 3 * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+    }
+}
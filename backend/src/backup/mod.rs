@@ -0,0 +1,362 @@
+//! Scheduled database backups. On each tick of the cron `schedule` the
+//! backup worker snapshots the main database and every tenant's with
+//! SQLite's `VACUUM INTO`, uploads each copy to an S3-compatible bucket
+//! under `prefix` (tenants' under `prefix` plus `tenants/<slug>/`), and
+//! deletes all but the newest `keep` backups of each there. Admins can also
+//! take them on demand.
+
+pub mod cron;
+
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::tokio::{self, fs};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{error, ApiError};
+use crate::resilience::Resilience;
+use crate::storage::Storage;
+use crate::tenants::Tenants;
+use crate::uploads::s3::{S3Bucket, S3Config};
+use crate::workers::Workers;
+use cron::Schedule;
+
+/// Content type backups are uploaded with.
+const SQLITE_CONTENT_TYPE: &str = "application/vnd.sqlite3";
+
+/// The `[default.backup]` table in Rocket.toml. Backups are disabled
+/// without it.
+#[derive(Debug, Deserialize)]
+struct BackupConfig {
+    /// Five-field cron expression in UTC, e.g. `0 3 * * *` for 03:00 daily.
+    schedule: String,
+    #[serde(default = "default_keep")]
+    keep: usize,
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    s3: S3Config,
+}
+
+fn default_keep() -> usize {
+    7
+}
+
+fn default_prefix() -> String {
+    "backups/".to_string()
+}
+
+/// A backup that was uploaded.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct BackupRecord {
+    /// The tenant whose database this is, absent for the main site's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Object key in the bucket.
+    pub key: String,
+    pub size: u64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct BackupFailure {
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct BackupStatus {
+    pub schedule: String,
+    pub keep: usize,
+    pub running: bool,
+    pub next_run_at: Option<DateTime<Utc>>,
+    /// The latest database backed up.
+    pub last_success: Option<BackupRecord>,
+    /// The latest failure, cleared by the next run that backs up every
+    /// database.
+    pub last_failure: Option<BackupFailure>,
+    /// Keys of the retained backups of every database, main site first and
+    /// each newest first, as of the last run.
+    pub backups: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    /// Another backup is still running.
+    Busy,
+    Failed(String),
+}
+
+impl From<BackupError> for ApiError {
+    fn from(e: BackupError) -> Self {
+        match e {
+            BackupError::Busy => error(Status::Conflict, "a backup is already running"),
+            BackupError::Failed(e) => error(Status::BadGateway, format!("backup failed: {}", e)),
+        }
+    }
+}
+
+/// The configured bucket and schedule, and what has happened so far.
+pub struct BackupJob {
+    bucket: S3Bucket,
+    schedule: Schedule,
+    prefix: String,
+    keep: usize,
+    /// The main database and each tenant's, filled in at liftoff.
+    databases: Mutex<Vec<Storage>>,
+    running: tokio::sync::Mutex<()>,
+    status: Mutex<BackupStatus>,
+}
+
+impl BackupJob {
+    pub fn status(&self) -> BackupStatus {
+        let mut status = self
+            .status
+            .lock()
+            .expect("backup status lock poisoned")
+            .clone();
+        status.running = self.running.try_lock().is_err();
+        status
+    }
+
+    fn update(&self, change: impl FnOnce(&mut BackupStatus)) {
+        change(&mut self.status.lock().expect("backup status lock poisoned"));
+    }
+
+    fn add_database(&self, storage: Storage) {
+        self.databases
+            .lock()
+            .expect("backup databases lock poisoned")
+            .push(storage);
+    }
+
+    /// Backs up every database in turn and applies retention to each,
+    /// unless a backup is already running. One database failing does not
+    /// stop the others; the run then fails with every error.
+    pub async fn run(&self) -> Result<Vec<BackupRecord>, BackupError> {
+        let _running = self.running.try_lock().map_err(|_| BackupError::Busy)?;
+        let databases = self
+            .databases
+            .lock()
+            .expect("backup databases lock poisoned")
+            .clone();
+
+        let mut records = Vec::new();
+        let mut backups = Vec::new();
+        let mut errors = Vec::new();
+        for storage in &databases {
+            match self.upload(storage, Utc::now()).await {
+                Ok((record, kept)) => {
+                    self.update(|status| status.last_success = Some(record.clone()));
+                    records.push(record);
+                    backups.extend(kept);
+                }
+                Err(e) => match storage.tenant() {
+                    Some(slug) => errors.push(format!("tenant {}: {}", slug, e)),
+                    None => errors.push(e),
+                },
+            }
+        }
+
+        self.update(|status| {
+            status.backups = backups;
+            status.last_failure = (!errors.is_empty()).then(|| BackupFailure {
+                error: errors.join("; "),
+                failed_at: Utc::now(),
+            });
+        });
+        if errors.is_empty() {
+            Ok(records)
+        } else {
+            Err(BackupError::Failed(errors.join("; ")))
+        }
+    }
+
+    /// Where `storage`'s backups are kept.
+    fn prefix_for(&self, storage: &Storage) -> String {
+        match storage.tenant() {
+            Some(slug) => format!("{}tenants/{}/", self.prefix, slug),
+            None => self.prefix.clone(),
+        }
+    }
+
+    /// Snapshots a database, uploads it and prunes its old backups,
+    /// returning the new backup and the keys kept. The snapshot is read into
+    /// memory whole, which is fine at the sizes this app reaches.
+    async fn upload(
+        &self,
+        storage: &Storage,
+        started_at: DateTime<Utc>,
+    ) -> Result<(BackupRecord, Vec<String>), String> {
+        let prefix = self.prefix_for(storage);
+        let name = format!("valentine-{}.db", started_at.format("%Y%m%dT%H%M%SZ"));
+        let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+        // A leftover from a crashed run would make VACUUM INTO fail.
+        let _ = fs::remove_file(&path).await;
+        storage
+            .snapshot(&path)
+            .await
+            .map_err(|e| format!("snapshot failed: {}", e))?;
+        let bytes = fs::read(&path).await;
+        let _ = fs::remove_file(&path).await;
+        let bytes = bytes.map_err(|e| format!("reading the snapshot failed: {}", e))?;
+
+        let key = format!("{}{}", prefix, name);
+        let size = bytes.len() as u64;
+        self.bucket.put(&key, bytes, SQLITE_CONTENT_TYPE).await?;
+        let backups = self.prune(&prefix).await?;
+
+        let record = BackupRecord {
+            tenant: storage.tenant().map(str::to_string),
+            key,
+            size,
+            started_at,
+            finished_at: Utc::now(),
+        };
+        info!("backed up a database to {} ({} bytes)", record.key, size);
+        Ok((record, backups))
+    }
+
+    /// Deletes all but the newest `keep` backups directly under `prefix`,
+    /// leaving other objects alone, and returns the kept keys newest first.
+    async fn prune(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let prefix = format!("{}valentine-", prefix);
+        let mut keys: Vec<String> = self
+            .bucket
+            .list(&prefix)
+            .await?
+            .into_iter()
+            .filter(|key| key.ends_with(".db"))
+            .collect();
+        // The timestamps in the names sort chronologically.
+        keys.sort_unstable_by(|a, b| b.cmp(a));
+        let expired = keys.split_off(self.keep.min(keys.len()));
+        for key in &expired {
+            self.bucket.delete(key).await?;
+            info!("deleted expired backup {}", key);
+        }
+        Ok(keys)
+    }
+}
+
+/// Managed state for the backup routes; `job` is `None` when backups are
+/// not configured.
+pub struct Backups {
+    job: Option<Arc<BackupJob>>,
+}
+
+impl Backups {
+    pub fn job(&self) -> Result<&BackupJob, ApiError> {
+        self.job
+            .as_deref()
+            .ok_or_else(|| error(Status::ServiceUnavailable, "backups are not configured"))
+    }
+}
+
+/// Backs up every database at each tick of the schedule until `token` is
+/// cancelled. A tick that finds a backup already running is skipped.
+async fn run_worker(job: Arc<BackupJob>, token: CancellationToken) {
+    while !token.is_cancelled() {
+        let Some(next) = job.schedule.next_after(Utc::now()) else {
+            return;
+        };
+        job.update(|status| status.next_run_at = Some(next));
+        let wait = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = token.cancelled() => return,
+        }
+
+        match job.run().await {
+            Ok(_) => {}
+            Err(BackupError::Busy) => info!("scheduled backup skipped: one is already running"),
+            Err(BackupError::Failed(e)) => error!("scheduled backup failed: {}", e),
+        }
+    }
+}
+
+/// Manages [`Backups`] from the `backup` table and starts the backup
+/// worker once the server has launched. Must be attached after the HTTP
+/// client stage.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Backups", |rocket| async {
+        let config = match rocket.figment().extract_inner::<BackupConfig>("backup") {
+            Ok(config) => config,
+            Err(e) if e.missing() => {
+                info!("no backup table configured, scheduled backups disabled");
+                return Ok(rocket.manage(Backups { job: None }));
+            }
+            Err(e) => {
+                error!("invalid backup config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let schedule: Schedule = match config.schedule.parse() {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                error!("invalid backup.schedule: {}", e);
+                return Err(rocket);
+            }
+        };
+        let Some(next_run_at) = schedule.next_after(Utc::now()) else {
+            error!("backup.schedule `{}` never fires", config.schedule);
+            return Err(rocket);
+        };
+        if config.keep == 0 {
+            error!("backup.keep must be at least 1");
+            return Err(rocket);
+        }
+        let Some(client) = rocket.state::<Resilience>().map(|r| r.client("backup")) else {
+            error!("backup stage attached before the resilience stage");
+            return Err(rocket);
+        };
+        let bucket = match S3Bucket::new(config.s3, client) {
+            Ok(bucket) => bucket,
+            Err(e) => {
+                error!("invalid backup.s3 config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let job = Arc::new(BackupJob {
+            bucket,
+            schedule,
+            status: Mutex::new(BackupStatus {
+                schedule: config.schedule,
+                keep: config.keep,
+                running: false,
+                next_run_at: Some(next_run_at),
+                last_success: None,
+                last_failure: None,
+                backups: vec![],
+            }),
+            prefix: config.prefix,
+            keep: config.keep,
+            databases: Mutex::new(Vec::new()),
+            running: tokio::sync::Mutex::new(()),
+        });
+        let worker = job.clone();
+
+        Ok(rocket
+            .manage(Backups { job: Some(job) })
+            .attach(AdHoc::on_liftoff("Backup Worker", move |rocket| {
+                Box::pin(async move {
+                    match (rocket.state::<Tenants>(), rocket.state::<Workers>()) {
+                        (Some(tenants), Some(workers)) => {
+                            let databases = worker.clone();
+                            tenants
+                                .for_each_database(move |storage| databases.add_database(storage));
+                            workers.spawn("backup worker", |token| run_worker(worker, token));
+                        }
+                        _ => {
+                            error!("backup worker not started: tenants or workers are unavailable")
+                        }
+                    }
+                })
+            })))
+    })
+}
@@ -0,0 +1,195 @@
+//! Per-route caps on request bodies from the `[[default.body_limits]]`
+//! tables in Rocket.toml. A request whose `Content-Length` is over its
+//! route's cap is refused with a JSON `413` before any of the body is read;
+//! bodies sent without a length are cut off at the cap by the JSON and
+//! import readers, and multipart forms by Rocket's `limits.file` and
+//! `limits.data-form`.
+
+use rocket::data::ByteUnit;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+
+/// Internal route that oversize requests are rewritten to, so the original
+/// handler never runs and the body is never read.
+const TOO_LARGE_PATH: &str = "/__body_too_large";
+
+/// The cap for every path starting with `prefix`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BodyLimit {
+    pub prefix: String,
+    pub limit: ByteUnit,
+}
+
+/// The cap configured for the request's route, if any; the longest
+/// matching prefix wins.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RouteLimit(pub Option<ByteUnit>);
+
+impl RouteLimit {
+    /// The cap [`BodyLimits`] found for `request`.
+    pub fn of(request: &Request<'_>) -> RouteLimit {
+        *request.local_cache(RouteLimit::default)
+    }
+
+    fn for_path(limits: &[BodyLimit], path: &str) -> RouteLimit {
+        RouteLimit(
+            limits
+                .iter()
+                .filter(|l| path.starts_with(l.prefix.as_str()))
+                .max_by_key(|l| l.prefix.len())
+                .map(|l| l.limit),
+        )
+    }
+
+    /// The route's cap, falling back to Rocket's limit for the body type.
+    pub fn or(self, fallback: ByteUnit) -> ByteUnit {
+        self.0.unwrap_or(fallback)
+    }
+}
+
+/// Whether a body of `length` bytes, as declared, is over `limit`.
+fn too_large(length: Option<u64>, limit: RouteLimit) -> Option<ByteUnit> {
+    match (length, limit.0) {
+        (Some(length), Some(limit)) if ByteUnit::from(length) > limit => Some(limit),
+        _ => None,
+    }
+}
+
+/// Looks up each request's [`RouteLimit`] and rewrites requests declaring a
+/// larger body to [`TOO_LARGE_PATH`]. Attach it after tenant routing, so
+/// prefixes match the path without `/t/<slug>`.
+struct BodyLimits {
+    limits: Vec<BodyLimit>,
+}
+
+/// The cap a rewritten request was over.
+#[derive(Clone, Copy)]
+struct TooLarge(ByteUnit);
+
+#[rocket::async_trait]
+impl Fairing for BodyLimits {
+    fn info(&self) -> Info {
+        Info {
+            name: "Body Limits",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let limit = RouteLimit::for_path(&self.limits, request.uri().path().as_str());
+        request.local_cache(|| limit);
+        let length = request
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|value| value.trim().parse().ok());
+        if let Some(limit) = too_large(length, limit) {
+            info!(
+                "refused a {} byte body to {} over its {} limit",
+                length.unwrap_or_default(),
+                request.uri().path(),
+                limit
+            );
+            request.local_cache(|| Some(TooLarge(limit)));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(TOO_LARGE_PATH).expect("valid internal path"));
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RouteLimit {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(RouteLimit::of(request))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for TooLarge {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        match request.local_cache(|| None::<TooLarge>) {
+            Some(too_large) => Outcome::Success(*too_large),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+#[get("/__body_too_large")]
+fn body_too_large(too_large: TooLarge) -> ApiError {
+    ApiError::Other(
+        Status::PayloadTooLarge,
+        format!("request body is larger than {}", too_large.0),
+    )
+}
+
+/// Attaches [`BodyLimits`] with the `body_limits` tables; without any, no
+/// route has a cap beyond Rocket's `limits`.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Body Limits", |rocket| async {
+        let limits = match rocket
+            .figment()
+            .extract_inner::<Vec<BodyLimit>>("body_limits")
+        {
+            Ok(limits) => limits,
+            Err(e) if e.missing() => Vec::new(),
+            Err(e) => {
+                error!("invalid body_limits config: {}", e);
+                return Err(rocket);
+            }
+        };
+        if let Some(bad) = limits.iter().find(|l| !l.prefix.starts_with('/')) {
+            error!(
+                "invalid body_limits config: prefix `{}` must start with `/`",
+                bad.prefix
+            );
+            return Err(rocket);
+        }
+
+        Ok(rocket
+            .attach(BodyLimits { limits })
+            .mount("/", routes![body_too_large]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_prefix_sets_the_cap() {
+        let limits = vec![
+            BodyLimit {
+                prefix: "/api".to_string(),
+                limit: ByteUnit::Kibibyte(64),
+            },
+            BodyLimit {
+                prefix: "/api/uploads".to_string(),
+                limit: ByteUnit::Mebibyte(6),
+            },
+        ];
+        let upload = RouteLimit::for_path(&limits, "/api/uploads");
+        assert_eq!(upload.0, Some(ByteUnit::Mebibyte(6)));
+        assert_eq!(
+            RouteLimit::for_path(&limits, "/api/valentine").0,
+            Some(ByteUnit::Kibibyte(64))
+        );
+        assert_eq!(RouteLimit::for_path(&limits, "/health").0, None);
+
+        assert_eq!(too_large(Some(6 << 20), upload), None);
+        assert_eq!(
+            too_large(Some((6 << 20) + 1), upload),
+            Some(ByteUnit::Mebibyte(6))
+        );
+        assert_eq!(too_large(None, upload), None);
+        assert_eq!(too_large(Some(u64::MAX), RouteLimit(None)), None);
+    }
+}
@@ -0,0 +1,295 @@
+//! HTTP caching. [`ETags`] gives every cacheable `GET` response an `ETag`
+//! and `Last-Modified` and answers matching conditional requests with `304`;
+//! [`QueryCache`] keeps the results of expensive queries in memory for the
+//! per-route TTLs in the `cache` table of Rocket.toml.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use moka::future::Cache;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// Routes (by handler name) that read through [`QueryCache`], and so may be
+/// given a TTL under `cache.ttl`.
+pub const CACHEABLE_ROUTES: &[&str] = &["daily", "draft", "list_messages"];
+
+/// URIs whose validators are remembered for `Last-Modified`.
+const MAX_VALIDATORS: u64 = 10_000;
+
+/// Used when `cache.max_entries` is not set.
+const DEFAULT_MAX_ENTRIES: u64 = 1_000;
+
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Whether an `If-None-Match` header lists `etag`. Uses the weak comparison,
+/// so `W/` prefixes on either side are ignored.
+pub fn etag_matches(header: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    header
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Weak, since the envelope's `meta` differs on every response while the
+/// data the tag is computed over does not.
+fn etag(body: &[u8]) -> String {
+    let digest = Sha256::digest(body);
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("W/\"{}\"", hex)
+}
+
+/// The last body seen for a URI and since when it has been served.
+#[derive(Clone)]
+struct Validator {
+    etag: String,
+    last_modified: DateTime<Utc>,
+    /// The previous body was first served within the same second, so
+    /// `If-Modified-Since` cannot tell the two apart.
+    ambiguous: bool,
+}
+
+impl Validator {
+    fn next(previous: Option<Validator>, etag: String, now: DateTime<Utc>) -> Validator {
+        let last_modified = DateTime::from_timestamp(now.timestamp(), 0).unwrap_or(now);
+        match previous {
+            Some(previous) if previous.etag == etag => previous,
+            previous => Validator {
+                etag,
+                last_modified,
+                ambiguous: previous.is_some_and(|p| p.last_modified == last_modified),
+            },
+        }
+    }
+
+    /// Whether a client holding the response from `since` still has this body.
+    fn unmodified_since(&self, since: DateTime<Utc>) -> bool {
+        !self.ambiguous && since >= self.last_modified
+    }
+}
+
+/// Adds `ETag` and `Last-Modified` to successful `GET` and `HEAD` responses
+/// that set neither themselves, and turns them into `304 Not Modified` when
+/// the request's `If-None-Match` (or, failing that, `If-Modified-Since`)
+/// shows the client already has the body. Streams and responses that set
+/// cookies are left alone.
+///
+/// Attach it before [`crate::envelope::Envelope`] so the tag covers the data
+/// rather than the per-response `meta`, and before request logging so the
+/// logged status is the one sent.
+pub struct ETags {
+    validators: Cache<String, Validator>,
+}
+
+impl Default for ETags {
+    fn default() -> Self {
+        ETags {
+            validators: Cache::builder()
+                .max_capacity(MAX_VALIDATORS)
+                .time_to_idle(Duration::from_secs(60 * 60))
+                .build(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for ETags {
+    fn info(&self) -> Info {
+        Info {
+            name: "ETags",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !matches!(request.method(), Method::Get | Method::Head)
+            || response.status() != Status::Ok
+            || response.headers().contains("ETag")
+            || response.headers().contains("Set-Cookie")
+            || response.body().preset_size().is_none()
+        {
+            return;
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("failed to read response body: {}", e);
+                return;
+            }
+        };
+        // Tenant sites share paths, so the site is part of the key; so is
+        // the format, which the same URI may be served in several of.
+        let key = format!(
+            "{}{} {}",
+            crate::tenants::slug(request).unwrap_or(""),
+            request.uri(),
+            response
+                .content_type()
+                .map(|t| t.to_string())
+                .unwrap_or_default()
+        );
+        let validator = Validator::next(self.validators.get(&key).await, etag(&body), Utc::now());
+        self.validators.insert(key, validator.clone()).await;
+
+        let headers = request.headers();
+        let not_modified = match headers.get_one("If-None-Match") {
+            Some(header) => etag_matches(header, &validator.etag),
+            None => headers
+                .get_one("If-Modified-Since")
+                .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+                .is_some_and(|since| validator.unmodified_since(since.with_timezone(&Utc))),
+        };
+
+        response.set_header(Header::new("ETag", validator.etag));
+        response.set_header(Header::new(
+            "Last-Modified",
+            validator.last_modified.format(HTTP_DATE).to_string(),
+        ));
+        if not_modified {
+            response.set_status(Status::NotModified);
+            response.remove_header("Content-Type");
+            response.set_sized_body(0, Cursor::new(Vec::new()));
+        } else {
+            response.set_sized_body(body.len(), Cursor::new(body));
+        }
+    }
+}
+
+fn default_max_entries() -> u64 {
+    DEFAULT_MAX_ENTRIES
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheConfig {
+    /// Per route, not in total.
+    #[serde(default = "default_max_entries")]
+    max_entries: u64,
+    /// Seconds to keep results, by route name; unlisted routes are not cached.
+    #[serde(default)]
+    ttl: HashMap<String, u64>,
+}
+
+/// In-memory TTL cache for query results, one [`moka`] cache per route.
+/// Results may be up to the route's TTL old unless a write
+/// [invalidates](QueryCache::invalidate) them. Clones share the caches.
+#[derive(Clone)]
+pub struct QueryCache {
+    routes: HashMap<&'static str, Cache<String, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl QueryCache {
+    fn new(config: &CacheConfig) -> Self {
+        let routes = CACHEABLE_ROUTES
+            .iter()
+            .filter_map(|route| {
+                let ttl = *config.ttl.get(*route)?;
+                let cache = Cache::builder()
+                    .max_capacity(config.max_entries)
+                    .time_to_live(Duration::from_secs(ttl))
+                    .build();
+                Some((*route, cache))
+            })
+            .collect();
+        QueryCache { routes }
+    }
+
+    /// The cached result for `key` under `route`, or the result of `query`,
+    /// which is stored when it succeeds. Runs `query` directly when the
+    /// route has no TTL.
+    pub async fn get_or_try_insert<T, E>(
+        &self,
+        route: &str,
+        key: String,
+        query: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E>
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let Some(cache) = self.routes.get(route) else {
+            return query.await;
+        };
+        if let Some(hit) = cache.get(&key).await {
+            if let Some(value) = hit.downcast_ref::<T>() {
+                return Ok(value.clone());
+            }
+        }
+
+        let value = query.await?;
+        cache.insert(key, Arc::new(value.clone())).await;
+        Ok(value)
+    }
+
+    /// Drops every cached result for `route`, e.g. after a write changes it.
+    pub fn invalidate(&self, route: &str) {
+        if let Some(cache) = self.routes.get(route) {
+            cache.invalidate_all();
+        }
+    }
+}
+
+/// Manages the [`QueryCache`] from the optional `cache` table; without it
+/// no query results are cached.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Query Cache", |rocket| async {
+        let config = match rocket.figment().extract_inner::<CacheConfig>("cache") {
+            Ok(config) => config,
+            Err(e) if e.missing() => CacheConfig {
+                max_entries: DEFAULT_MAX_ENTRIES,
+                ttl: HashMap::new(),
+            },
+            Err(e) => {
+                error!("invalid cache config: {}", e);
+                return Err(rocket);
+            }
+        };
+        for route in config.ttl.keys() {
+            if !CACHEABLE_ROUTES.contains(&route.as_str()) {
+                warn!(
+                    "cache.ttl.{} ignored: not one of {:?}",
+                    route, CACHEABLE_ROUTES
+                );
+            }
+        }
+
+        Ok(rocket.manage(QueryCache::new(&config)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validators_track_when_the_body_last_changed() {
+        assert!(etag_matches("\"a\", W/\"b\"", "W/\"b\""));
+        assert!(etag_matches("*", "W/\"b\""));
+        assert!(!etag_matches("W/\"a\"", "W/\"b\""));
+
+        let t =
+            |secs: i64, millis: u32| DateTime::from_timestamp(secs, millis * 1_000_000).unwrap();
+        let first = Validator::next(None, etag(b"one"), t(100, 200));
+        assert_eq!(first.last_modified, t(100, 0));
+        let same = Validator::next(Some(first.clone()), etag(b"one"), t(150, 0));
+        assert_eq!(same.last_modified, t(100, 0));
+        assert!(same.unmodified_since(t(100, 0)));
+        assert!(!same.unmodified_since(t(99, 0)));
+
+        // Changed again within the same second: a client holding "one" must
+        // not get a 304 for "two".
+        let two = Validator::next(Some(same), etag(b"two"), t(100, 700));
+        assert_ne!(two.etag, first.etag);
+        assert!(!two.unmodified_since(t(100, 0)));
+        let three = Validator::next(Some(two), etag(b"three"), t(160, 0));
+        assert!(three.unmodified_since(t(160, 0)));
+    }
+}
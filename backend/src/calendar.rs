@@ -0,0 +1,319 @@
+//! An iCalendar (RFC 5545) feed of important dates, reservations and
+//! scheduled valentines to subscribe to from Google or Apple Calendar.
+//! Calendar apps cannot sign in, so couples subscribe with a secret feed
+//! link from `POST /api/calendar/feed`.
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use rocket::http::{ContentType, Status};
+use rocket::{Route, State};
+use serde::Serialize;
+
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{ImportantDate, Reservation, Schedule, Storage};
+use crate::tokens;
+use crate::users::{CoupleScope, Session};
+
+const PRODUCT_ID: &str = "-//Valentine 2026//Calendar//EN";
+const CALENDAR_NAME: &str = "Valentine";
+const FEED_TOKEN_LEN: usize = 32;
+
+/// Longest content line in octets, line break excluded.
+const MAX_LINE_LEN: usize = 75;
+
+/// Local hour at which all-day dates' reminders go off.
+const REMINDER_HOUR: i64 = 9;
+
+/// How long a reservation is blocked out for; bookings have no end time.
+const RESERVATION_HOURS: i64 = 2;
+
+/// Escapes a TEXT value.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A signed DURATION of whole hours, e.g. `-PT15H`.
+fn hours(hours: i64) -> String {
+    match hours {
+        0 => "PT0S".to_string(),
+        h if h < 0 => format!("-PT{}H", -h),
+        h => format!("PT{}H", h),
+    }
+}
+
+fn utc(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn day(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+/// Content lines of a calendar, folded and CRLF-terminated on output.
+#[derive(Default)]
+struct Calendar {
+    lines: Vec<String>,
+}
+
+impl Calendar {
+    fn line(&mut self, name: &str, value: impl AsRef<str>) {
+        self.lines.push(format!("{}:{}", name, value.as_ref()));
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        self.line(name, escape(value));
+    }
+
+    fn alarm(&mut self, trigger: &str, description: &str) {
+        self.line("BEGIN", "VALARM");
+        self.line("ACTION", "DISPLAY");
+        self.line("TRIGGER", trigger);
+        self.text("DESCRIPTION", description);
+        self.line("END", "VALARM");
+    }
+
+    /// An all-day event, every year when `date.recurring`, with an alarm on
+    /// the morning of each reminder day.
+    fn date(&mut self, date: &ImportantDate) {
+        self.line("BEGIN", "VEVENT");
+        self.line("UID", format!("date-{}@valentine", date.id));
+        self.line("DTSTAMP", utc(date.created_at));
+        self.line("DTSTART;VALUE=DATE", day(date.date));
+        self.line("DTEND;VALUE=DATE", day(date.date + Duration::days(1)));
+        self.text("SUMMARY", &date.title);
+        self.line("CATEGORIES", date.kind.as_str());
+        if date.recurring {
+            // A plain yearly rule skips Feb 29 in common years; the app
+            // moves it to Feb 28 instead, so the feed does too.
+            if (date.date.month(), date.date.day()) == (2, 29) {
+                self.line("RRULE", "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1");
+            } else {
+                self.line("RRULE", "FREQ=YEARLY");
+            }
+        }
+        for &days in &date.reminders.days {
+            let description = match days {
+                0 => format!("{} is today", date.title),
+                1 => format!("{} is tomorrow", date.title),
+                d => format!("{} is in {} days", date.title, d),
+            };
+            self.alarm(&hours(REMINDER_HOUR - 24 * days), &description);
+        }
+        self.line("END", "VEVENT");
+    }
+
+    fn reservation(&mut self, reservation: &Reservation) {
+        self.line("BEGIN", "VEVENT");
+        self.line("UID", format!("reservation-{}@valentine", reservation.id));
+        self.line("DTSTAMP", utc(reservation.created_at));
+        self.line("DTSTART", utc(reservation.reserved_for));
+        self.line("DURATION", hours(RESERVATION_HOURS));
+        self.text("SUMMARY", &format!("Reservation at {}", reservation.place));
+        self.text(
+            "LOCATION",
+            reservation.address.as_deref().unwrap_or(&reservation.place),
+        );
+        let mut details = Vec::new();
+        if let Some(size) = reservation.party_size {
+            details.push(format!("Party of {}", size));
+        }
+        if let Some(confirmation) = &reservation.confirmation {
+            details.push(format!("Confirmation {}", confirmation));
+        }
+        if !details.is_empty() {
+            self.text("DESCRIPTION", &details.join("\n"));
+        }
+        self.alarm(
+            &hours(-reservation.remind_hours),
+            &format!("Reservation at {}", reservation.place),
+        );
+        self.line("END", "VEVENT");
+    }
+
+    /// When a scheduled valentine unlocks; the message itself stays out of
+    /// the feed.
+    fn schedule(&mut self, schedule: &Schedule) {
+        self.line("BEGIN", "VEVENT");
+        self.line("UID", format!("schedule-{}@valentine", schedule.id));
+        self.line("DTSTAMP", utc(schedule.created_at));
+        self.line("DTSTART", utc(schedule.reveal_at));
+        let summary = match &schedule.recipient {
+            Some(to) => format!("Valentine from {} to {} unlocks", schedule.sender, to),
+            None => format!("Valentine from {} unlocks", schedule.sender),
+        };
+        self.text("SUMMARY", &summary);
+        self.alarm(&hours(0), &summary);
+        self.line("END", "VEVENT");
+    }
+
+    /// The calendar object, with lines over [`MAX_LINE_LEN`] octets folded
+    /// at character boundaries.
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let header = [
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            format!("PRODID:{}", PRODUCT_ID),
+            "CALSCALE:GREGORIAN".to_string(),
+            "METHOD:PUBLISH".to_string(),
+            format!("X-WR-CALNAME:{}", CALENDAR_NAME),
+        ];
+        let footer = ["END:VCALENDAR".to_string()];
+        for line in header.iter().chain(&self.lines).chain(&footer) {
+            let mut len = 0;
+            for c in line.chars() {
+                // Continuation lines start with a space, which counts.
+                if len + c.len_utf8() > MAX_LINE_LEN {
+                    out.push_str("\r\n ");
+                    len = 1;
+                }
+                out.push(c);
+                len += c.len_utf8();
+            }
+            out.push_str("\r\n");
+        }
+        out
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct CalendarFeed {
+    /// The link to subscribe to; anyone with it can read the calendar.
+    url: String,
+}
+
+/// The calendar of the couple the feed `token` belongs to or, without one,
+/// of the signed-in user's couple. Anonymous requests get the dates,
+/// reservations and scheduled valentines that belong to no couple.
+#[utoipa::path(
+    tag = "dates",
+    params(("token" = Option<String>, Query, description = "Secret from `POST /api/calendar/feed`")),
+    responses(
+        (status = 200, description = "An iCalendar feed", content_type = "text/calendar"),
+        (status = 404, description = "Unknown or replaced feed token", body = ErrorResponse),
+    )
+)]
+#[get("/api/calendar.ics?<token>")]
+async fn calendar(
+    storage: &Storage,
+    scope: CoupleScope,
+    token: Option<&str>,
+) -> ApiResult<(ContentType, String)> {
+    let couple = match token {
+        Some(token) => Some(
+            storage
+                .calendar_feed_couple(&tokens::token_hash(token))
+                .await
+                .map_err(internal_error)?
+                .ok_or_else(|| error(Status::NotFound, "unknown calendar feed token"))?,
+        ),
+        None => scope.0,
+    };
+
+    let mut calendar = Calendar::default();
+    for date in storage.list_dates(couple).await.map_err(internal_error)? {
+        calendar.date(&date);
+    }
+    for reservation in storage
+        .list_reservations(couple)
+        .await
+        .map_err(internal_error)?
+    {
+        calendar.reservation(&reservation);
+    }
+    for schedule in storage
+        .list_schedules(couple)
+        .await
+        .map_err(internal_error)?
+    {
+        calendar.schedule(&schedule);
+    }
+
+    Ok((ContentType::Calendar, calendar.render()))
+}
+
+/// Issues the signed-in user's couple a secret calendar feed link,
+/// replacing the one before, so a leaked link can be revoked.
+#[utoipa::path(
+    tag = "dates",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = CalendarFeed),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[post("/api/calendar/feed")]
+async fn feed(
+    session: Session,
+    storage: &Storage,
+    public_url: &State<PublicUrl>,
+) -> ApiResult<Negotiated<CalendarFeed>> {
+    let couple = session
+        .0
+        .couple_id
+        .ok_or_else(|| error(Status::NotFound, "you are not in a couple yet"))?;
+    let token = tokens::random_token(FEED_TOKEN_LEN);
+    storage
+        .set_calendar_feed(couple, &tokens::token_hash(&token))
+        .await
+        .map_err(internal_error)?;
+
+    let path = uri!(calendar(Some(token.as_str()))).to_string();
+    Ok(Negotiated(CalendarFeed {
+        url: public_url.absolute(&path),
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![calendar, feed]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{DateKind, Reminders};
+
+    #[test]
+    fn feeds_recur_yearly_and_fold_long_lines() {
+        let created_at = "2026-01-05T10:00:00Z".parse().unwrap();
+        let mut calendar = Calendar::default();
+        calendar.date(&ImportantDate {
+            id: 7,
+            title: "Leap day, our day; really".to_string(),
+            kind: DateKind::Anniversary,
+            date: "2024-02-29".parse().unwrap(),
+            recurring: true,
+            reminders: Reminders {
+                days: vec![0, 2],
+                email: None,
+            },
+            created_at,
+            couple_id: None,
+        });
+        calendar.text("DESCRIPTION", &"é".repeat(50));
+        let ics = calendar.render();
+
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(ics.contains("\r\nDTSTART;VALUE=DATE:20240229\r\nDTEND;VALUE=DATE:20240301\r\n"));
+        assert!(ics.contains("\r\nSUMMARY:Leap day\\, our day\\; really\r\n"));
+        assert!(ics.contains("\r\nRRULE:FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=-1\r\n"));
+        assert!(ics.contains("\r\nTRIGGER:PT9H\r\n"));
+        assert!(ics.contains("\r\nTRIGGER:-PT39H\r\n"));
+        assert!(ics.split("\r\n").all(|line| line.len() <= MAX_LINE_LEN));
+        assert!(ics.contains("\r\n é"));
+    }
+}
@@ -0,0 +1,280 @@
+//! Valentines as handwriting: every character of the letter is its own
+//! stroked SVG path, in writing order, so the frontend can animate the pen
+//! across the page with `stroke-dashoffset`. Each character is nudged,
+//! tilted and resized a little, seeded by the message id so a letter comes
+//! out the same way every time.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ab_glyph::{Font, FontVec, OutlineCurve, Point, PxScale, ScaleFont};
+use rocket::fairing::AdHoc;
+use rocket::http::{ContentType, Status};
+use rocket::tokio::{fs, task};
+use rocket::State;
+use serde::Deserialize;
+
+use super::render;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::messages;
+use crate::storage::Storage;
+use crate::users::CoupleScope;
+
+static DEFAULT_FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSerif-Italic.ttf");
+
+const WIDTH: f32 = 720.0;
+const MARGIN: f32 = 48.0;
+const FONT_SIZE: f32 = 34.0;
+const LINE_HEIGHT: f32 = FONT_SIZE * 1.5;
+const INK: &str = "#7a1f3d";
+
+/// The `[default.handwriting]` table in Rocket.toml.
+#[derive(Debug, Default, Deserialize)]
+struct HandwritingConfig {
+    /// A TrueType or OpenType font with outlines, ideally a script one;
+    /// the bundled DejaVu Serif Italic otherwise.
+    font: Option<PathBuf>,
+}
+
+pub struct Handwriting {
+    font: Arc<FontVec>,
+}
+
+/// Where one character sits on the page: tilted and resized about its
+/// origin on the baseline.
+struct Jitter {
+    origin: Point,
+    scale: f32,
+    sin: f32,
+    cos: f32,
+}
+
+impl Jitter {
+    fn apply(&self, glyph: Point, h_factor: f32, v_factor: f32) -> Point {
+        let x = glyph.x * h_factor * self.scale;
+        let y = -glyph.y * v_factor * self.scale;
+        Point {
+            x: self.origin.x + x * self.cos - y * self.sin,
+            y: self.origin.y + x * self.sin + y * self.cos,
+        }
+    }
+}
+
+/// Appends the glyph's outline to `d`, starting a new subpath wherever a
+/// curve does not continue from the last one.
+fn trace(d: &mut String, curves: &[OutlineCurve], place: impl Fn(Point) -> Point) {
+    let mut pen = None;
+    for curve in curves {
+        let (start, points, command) = match *curve {
+            OutlineCurve::Line(p0, p1) => (p0, vec![p1], 'L'),
+            OutlineCurve::Quad(p0, p1, p2) => (p0, vec![p1, p2], 'Q'),
+            OutlineCurve::Cubic(p0, p1, p2, p3) => (p0, vec![p1, p2, p3], 'C'),
+        };
+        if pen != Some(start) {
+            let p = place(start);
+            let _ = write!(d, "M{:.1} {:.1}", p.x, p.y);
+        }
+        d.push(command);
+        for (i, point) in points.iter().enumerate() {
+            let p = place(*point);
+            let separator = if i == 0 { "" } else { " " };
+            let _ = write!(d, "{}{:.1} {:.1}", separator, p.x, p.y);
+        }
+        pen = points.last().copied();
+    }
+}
+
+/// The letter's lines: a greeting when it has a recipient, the wrapped
+/// message, keeping its own line breaks, and the sign-off.
+fn layout<F: Font>(font: &F, recipient: Option<&str>, body: &str, sender: &str) -> Vec<String> {
+    let scale = PxScale::from(FONT_SIZE);
+    let mut lines = Vec::new();
+    if let Some(recipient) = recipient {
+        lines.push(format!("Dear {},", recipient));
+        lines.push(String::new());
+    }
+    for paragraph in body.lines() {
+        let wrapped = render::wrap(font, scale, paragraph, WIDTH - 2.0 * MARGIN);
+        if wrapped.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(wrapped);
+    }
+    lines.push(String::new());
+    lines.push("With love,".to_string());
+    lines.push(sender.to_string());
+    lines
+}
+
+/// The letter as an SVG with one `<path>` per character, in the order they
+/// would be written.
+pub fn render_svg<F: Font>(
+    font: &F,
+    seed: u64,
+    recipient: Option<&str>,
+    body: &str,
+    sender: &str,
+) -> String {
+    let scaled = font.as_scaled(PxScale::from(FONT_SIZE));
+    let (h_factor, v_factor) = (scaled.h_scale_factor(), scaled.v_scale_factor());
+    let lines = layout(font, recipient, body, sender);
+    let height = (2.0 * MARGIN + lines.len() as f32 * LINE_HEIGHT).ceil();
+    let mut scatter = render::Scatter::new(seed);
+
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+        w = WIDTH,
+        h = height
+    );
+    let _ = write!(
+        svg,
+        "<title>{}</title><g fill=\"none\" stroke=\"{}\" stroke-width=\"1.2\" stroke-linecap=\"round\" stroke-linejoin=\"round\">",
+        messages::escape_html(&lines.join("\n")),
+        INK
+    );
+    for (row, line) in lines.iter().enumerate() {
+        let baseline = MARGIN + scaled.ascent() + row as f32 * LINE_HEIGHT;
+        // Hands drift: each line starts a little off the margin and slopes.
+        let mut x = MARGIN + (scatter.next() - 0.5) * 6.0;
+        let slope = (scatter.next() - 0.5) * 0.012;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                x += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+            let advance = scaled.h_advance(id);
+            let tilt = (scatter.next() - 0.5) * 0.12;
+            let jitter = Jitter {
+                origin: Point {
+                    x,
+                    y: baseline + (x - MARGIN) * slope + (scatter.next() - 0.5) * FONT_SIZE * 0.06,
+                },
+                scale: 1.0 + (scatter.next() - 0.5) * 0.08,
+                sin: tilt.sin(),
+                cos: tilt.cos(),
+            };
+            x += advance;
+
+            let Some(outline) = font.outline(id).filter(|_| !c.is_whitespace()) else {
+                continue;
+            };
+            let mut d = String::new();
+            trace(&mut d, &outline.curves, |p| {
+                jitter.apply(p, h_factor, v_factor)
+            });
+            if !d.is_empty() {
+                let _ = write!(svg, "<path pathLength=\"1\" d=\"{}\"/>", d);
+            }
+        }
+    }
+    svg.push_str("</g></svg>");
+    svg
+}
+
+/// Message `id` in handwriting, as SVG paths the frontend can draw stroke
+/// by stroke. The same message always renders identically.
+#[utoipa::path(
+    tag = "messages",
+    responses(
+        (status = 200, content_type = "image/svg+xml", body = String),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/<id>/handwritten.svg")]
+pub async fn handwritten(
+    storage: &Storage,
+    handwriting: &State<Handwriting>,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<(ContentType, String)> {
+    let message = storage
+        .get_message(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no message with id {}", id)))?;
+
+    let font = handwriting.font.clone();
+    let svg = task::spawn_blocking(move || {
+        render_svg(
+            &*font,
+            message.id as u64,
+            message.recipient.as_deref(),
+            &message.message,
+            &message.sender,
+        )
+    })
+    .await
+    .map_err(|e| {
+        error(
+            Status::InternalServerError,
+            format!("handwriting rendering panicked: {}", e),
+        )
+    })?;
+
+    Ok((ContentType::SVG, svg))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Handwriting", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<HandwritingConfig>("handwriting")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => HandwritingConfig::default(),
+            Err(e) => {
+                error!("invalid handwriting config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let bytes = match &config.font {
+            Some(path) => match fs::read(path).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    error!("handwriting font {}: {}", path.display(), e);
+                    return Err(rocket);
+                }
+            },
+            None => DEFAULT_FONT.to_vec(),
+        };
+        match FontVec::try_from_vec(bytes) {
+            Ok(font) => Ok(rocket.manage(Handwriting {
+                font: Arc::new(font),
+            })),
+            Err(e) => {
+                error!("handwriting font is not a usable font: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ab_glyph::FontRef;
+
+    use super::*;
+
+    #[test]
+    fn every_written_character_is_its_own_repeatable_path() {
+        let font = FontRef::try_from_slice(DEFAULT_FONT).unwrap();
+        let svg = render_svg(&font, 7, Some("Sam"), "Be mine & only mine", "Alex");
+        let characters = "Dear Sam,Be mine & only mineWith love,Alex"
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .count();
+        assert_eq!(svg.matches("<path ").count(), characters);
+        assert!(svg.contains("<title>Dear Sam,\n\nBe mine &amp; only mine"));
+        assert_eq!(
+            svg,
+            render_svg(&font, 7, Some("Sam"), "Be mine & only mine", "Alex")
+        );
+        assert_ne!(
+            svg,
+            render_svg(&font, 8, Some("Sam"), "Be mine & only mine", "Alex")
+        );
+    }
+}
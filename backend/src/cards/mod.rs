@@ -0,0 +1,233 @@
+pub mod handwriting;
+pub mod pdf;
+pub mod qr;
+mod render;
+
+use std::io::Cursor;
+
+use ab_glyph::PxScale;
+use image::{ImageFormat, RgbaImage};
+use rocket::http::{ContentType, Status};
+use rocket::tokio::task;
+use rocket::{Route, State};
+
+use crate::determinism::RequestRng;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::messages;
+use crate::metrics::Metrics;
+use crate::quote_sources::QuoteSources;
+use crate::stats::ServeCounter;
+use crate::storage::Category;
+use crate::tenants::{CurrentTenant, QuoteStorage};
+use crate::themes::{Palette, Pattern, Theme, Themes};
+
+pub use render::Color;
+
+pub(crate) const WIDTH: u32 = 800;
+pub(crate) const HEIGHT: u32 = 600;
+const TEXT_MARGIN: f32 = 110.0;
+
+/// Draws the theme's pattern over the background.
+fn decorate(canvas: &mut RgbaImage, pattern: Pattern, palette: &Palette) {
+    let (w, h) = (WIDTH as f32, HEIGHT as f32);
+    match pattern {
+        Pattern::Hearts => {
+            let mut scatter = render::Scatter::new(14);
+            for _ in 0..28 {
+                let (x, y) = (scatter.next() * w, scatter.next() * h);
+                let size = 24.0 + scatter.next() * 56.0;
+                render::heart(canvas, x, y, size, palette.accent, 0.35);
+            }
+        }
+        Pattern::Frame => {
+            render::rect_outline(canvas, 24, 6, palette.accent, 0.9);
+            render::rect_outline(canvas, 40, 2, palette.accent, 0.6);
+            for (x, y) in [
+                (70.0, 70.0),
+                (w - 70.0, 70.0),
+                (70.0, h - 70.0),
+                (w - 70.0, h - 70.0),
+            ] {
+                render::heart(canvas, x, y, 36.0, palette.accent, 0.9);
+            }
+        }
+        Pattern::Stars => {
+            let mut scatter = render::Scatter::new(2);
+            for _ in 0..90 {
+                let (x, y) = (scatter.next() * w, scatter.next() * h);
+                render::dot(
+                    canvas,
+                    x,
+                    y,
+                    0.8 + scatter.next() * 1.6,
+                    [255, 255, 255],
+                    0.8,
+                );
+            }
+            render::heart(canvas, w / 2.0, h / 2.0, 420.0, palette.accent, 0.18);
+        }
+        Pattern::None => {}
+    }
+}
+
+/// Content of a card before it is rasterised.
+pub struct Card<'a> {
+    pub title: &'a str,
+    pub message: &'a str,
+    pub signature: &'a str,
+    pub theme: &'a Theme,
+}
+
+/// Lays out the card and returns it encoded as PNG.
+pub fn render_png(card: &Card) -> Result<Vec<u8>, image::ImageError> {
+    let (quote_font, title_font) = card.theme.font.metrics();
+    let palette = &card.theme.palette;
+    let mut canvas = match &card.theme.background {
+        Some(background) => background.clone(),
+        None => {
+            let mut canvas = RgbaImage::new(WIDTH, HEIGHT);
+            render::vertical_gradient(&mut canvas, palette.top, palette.bottom);
+            canvas
+        }
+    };
+    decorate(&mut canvas, card.theme.pattern, palette);
+
+    let cx = WIDTH as f32 / 2.0;
+    let max_width = WIDTH as f32 - 2.0 * TEXT_MARGIN;
+
+    let title_scale = PxScale::from(52.0);
+    render::centered_text(
+        &mut canvas,
+        title_font,
+        title_scale,
+        cx,
+        140.0,
+        card.title,
+        palette.title,
+    );
+
+    // Shrink the quote until it fits between the title and the signature.
+    let available = HEIGHT as f32 - 330.0;
+    let mut size = 40.0;
+    let (scale, lines) = loop {
+        let scale = PxScale::from(size);
+        let lines = render::wrap(quote_font, scale, card.message, max_width);
+        let height = lines.len() as f32 * render::line_height(quote_font, scale);
+        if height <= available || size <= 16.0 {
+            break (scale, lines);
+        }
+        size -= 2.0;
+    };
+
+    let line_height = render::line_height(quote_font, scale);
+    let block_height = lines.len() as f32 * line_height;
+    let mut baseline = 180.0 + (available - block_height) / 2.0 + line_height * 0.8;
+    for line in &lines {
+        render::centered_text(
+            &mut canvas,
+            quote_font,
+            scale,
+            cx,
+            baseline,
+            line,
+            palette.text,
+        );
+        baseline += line_height;
+    }
+
+    let signature = format!("— {}", card.signature);
+    render::centered_text(
+        &mut canvas,
+        quote_font,
+        PxScale::from(28.0),
+        cx,
+        HEIGHT as f32 - 90.0,
+        &signature,
+        palette.title,
+    );
+
+    let mut png = Cursor::new(Vec::new());
+    canvas.write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+#[utoipa::path(
+    tag = "quotes",
+    params(
+        ("name" = Option<String>, Query, description = "Who the card is addressed to"),
+        ("theme" = Option<String>, Query, description = "A theme id from `GET /api/themes`; the tenant's theme, or `hearts`, by default"),
+        ("category" = Option<Category>, Query),
+        ("seed" = Option<u64>, Query, description = "Picks the same quote every time; also read from `X-Seed`"),
+    ),
+    responses(
+        (status = 200, content_type = "image/png", body = Vec<u8>),
+        (status = 400, body = ErrorResponse),
+        (status = 404, description = "No quotes in the category", body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/card?<name>&<theme>&<category>")]
+#[allow(clippy::too_many_arguments)]
+async fn card(
+    quotes: QuoteStorage,
+    tenant: CurrentTenant<'_>,
+    sources: &State<QuoteSources>,
+    themes: &State<Themes>,
+    metrics: &State<Metrics>,
+    serves: &State<ServeCounter>,
+    mut rng: RequestRng,
+    name: Option<&str>,
+    theme: Option<&str>,
+    category: Option<&str>,
+) -> ApiResult<(ContentType, Vec<u8>)> {
+    let name = name
+        .map(messages::sanitize_name)
+        .transpose()
+        .map_err(|e| error(Status::BadRequest, e))?;
+    let theme = themes
+        .get_or_default(theme.or(tenant.theme()))
+        .map_err(|e| error(Status::BadRequest, e))?;
+    let category = category
+        .map(str::parse::<Category>)
+        .transpose()
+        .map_err(|e| error(Status::BadRequest, e))?;
+
+    let quote = sources
+        .random_quote(&quotes.0, category, &mut rng)
+        .await
+        .map_err(internal_error)?;
+    metrics.quote_served("card", quote.as_ref().map(|q| q.category));
+    serves.served(&quotes.0, quote.as_ref());
+    let quote = quote.map_or_else(|| "I love you!".to_string(), |q| q.text);
+    let title = match &name {
+        Some(name) => format!("{},", name),
+        None => "Happy Valentine's Day".to_string(),
+    };
+
+    let png = task::spawn_blocking(move || {
+        render_png(&Card {
+            title: &title,
+            message: &quote,
+            signature: "Your Valentine",
+            theme: &theme,
+        })
+    })
+    .await
+    .map_err(|e| {
+        error(
+            Status::InternalServerError,
+            format!("card rendering panicked: {}", e),
+        )
+    })?
+    .map_err(|e| {
+        error(
+            Status::InternalServerError,
+            format!("failed to encode card: {}", e),
+        )
+    })?;
+
+    Ok((ContentType::PNG, png))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![card, handwriting::handwritten]
+}
@@ -0,0 +1,495 @@
+//! Printable quarter-fold cards. The PDF is one portrait sheet printed on a
+//! single side: the bottom half holds the back and front covers, and the top
+//! half holds the inside panels upside down, so they read the right way up
+//! once the sheet is folded in half and in half again.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use ab_glyph::{Font, FontRef, PxScale};
+use printpdf::path::{PaintMode, WindingOrder};
+use printpdf::{
+    CurTransMat, IndirectFontRef, Line, LineDashPattern, Mm, PdfDocument, PdfLayerReference, Point,
+    Polygon, Rgb,
+};
+use serde::{Deserialize, Serialize};
+
+use super::render::{self, Color};
+use crate::themes::Theme;
+
+static SERIF_BODY: &[u8] = include_bytes!("../../assets/fonts/DejaVuSerif-Italic.ttf");
+static SERIF_TITLE: &[u8] = include_bytes!("../../assets/fonts/DejaVuSerif-Bold.ttf");
+static SANS_BODY: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+static SANS_TITLE: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans-Bold.ttf");
+
+const PT_PER_MM: f32 = 72.0 / 25.4;
+
+const ROSE: Color = [0xc2, 0x18, 0x4b];
+const BLUSH: Color = [0xf4, 0xb6, 0xc8];
+const INK: Color = [0x3a, 0x2a, 0x30];
+const GUIDE: Color = [0xc8, 0xc8, 0xc8];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, utoipa::ToSchema)]
+#[schema(rename_all = "lowercase")]
+pub enum PaperSize {
+    /// 210 × 297 mm.
+    A4,
+    /// 8.5 × 11 in.
+    Letter,
+}
+
+impl PaperSize {
+    pub const ALL: [PaperSize; 2] = [PaperSize::A4, PaperSize::Letter];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PaperSize::A4 => "a4",
+            PaperSize::Letter => "letter",
+        }
+    }
+
+    /// Width and height in millimetres, portrait.
+    fn dimensions(self) -> (f32, f32) {
+        match self {
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::Letter => (215.9, 279.4),
+        }
+    }
+}
+
+impl fmt::Display for PaperSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for PaperSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PaperSize::ALL
+            .into_iter()
+            .find(|p| p.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = PaperSize::ALL.iter().map(|p| p.as_str()).collect();
+                format!(
+                    "unknown paper size `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CardFont {
+    /// DejaVu Serif, italic for the message and bold for headings.
+    Serif,
+    /// DejaVu Sans, regular for the message and bold for headings.
+    Sans,
+}
+
+impl CardFont {
+    pub const ALL: [CardFont; 2] = [CardFont::Serif, CardFont::Sans];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CardFont::Serif => "serif",
+            CardFont::Sans => "sans",
+        }
+    }
+
+    /// Font files for the message and the headings.
+    fn files(self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            CardFont::Serif => (SERIF_BODY, SERIF_TITLE),
+            CardFont::Sans => (SANS_BODY, SANS_TITLE),
+        }
+    }
+
+    /// Parsed copies of the same files, for measuring and rasterising text.
+    pub(crate) fn metrics(self) -> &'static (FontRef<'static>, FontRef<'static>) {
+        static SERIF: OnceLock<(FontRef, FontRef)> = OnceLock::new();
+        static SANS: OnceLock<(FontRef, FontRef)> = OnceLock::new();
+        let cell = match self {
+            CardFont::Serif => &SERIF,
+            CardFont::Sans => &SANS,
+        };
+        cell.get_or_init(|| {
+            let (body, title) = self.files();
+            (
+                FontRef::try_from_slice(body).expect("embedded body font is valid"),
+                FontRef::try_from_slice(title).expect("embedded title font is valid"),
+            )
+        })
+    }
+}
+
+impl fmt::Display for CardFont {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CardFont {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CardFont::ALL
+            .into_iter()
+            .find(|f| f.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = CardFont::ALL.iter().map(|f| f.as_str()).collect();
+                format!(
+                    "unknown font `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+pub struct PrintCard<'a> {
+    pub recipient: Option<&'a str>,
+    pub sender: &'a str,
+    pub message: &'a str,
+    pub paper: PaperSize,
+    pub font: CardFont,
+    /// Colors the sheet with the theme's palette instead of rose ink on
+    /// white paper.
+    pub theme: Option<&'a Theme>,
+}
+
+/// The colors a sheet is drawn in.
+struct Inks {
+    /// Filled in behind everything; `None` leaves the paper blank.
+    paper: Option<Color>,
+    /// Headings, frames and the cover heart.
+    rose: Color,
+    /// Light decorations.
+    blush: Color,
+    /// The message.
+    ink: Color,
+}
+
+impl Inks {
+    fn new(theme: Option<&Theme>) -> Self {
+        match theme {
+            Some(theme) => Inks {
+                paper: Some(theme.palette.top),
+                rose: theme.palette.title,
+                blush: theme.palette.accent,
+                ink: theme.palette.text,
+            },
+            None => Inks {
+                paper: None,
+                rose: ROSE,
+                blush: BLUSH,
+                ink: INK,
+            },
+        }
+    }
+}
+
+/// A font as embedded in the document, plus its metrics for layout.
+struct Face<'a> {
+    pdf: IndirectFontRef,
+    metrics: &'a FontRef<'static>,
+}
+
+impl Face<'_> {
+    /// The ab_glyph scale whose advances come out in points at `size` pt.
+    fn scale(&self, size: f32) -> PxScale {
+        let units = self.metrics.units_per_em().unwrap_or(2048.0);
+        PxScale::from(size * self.metrics.height_unscaled() / units)
+    }
+
+    fn width(&self, size: f32, text: &str) -> f32 {
+        render::text_width(self.metrics, self.scale(size), text) / PT_PER_MM
+    }
+
+    /// Wraps each paragraph of `text` to `max_width` millimetres.
+    fn wrap(&self, size: f32, text: &str, max_width: f32) -> Vec<String> {
+        text.lines()
+            .flat_map(|paragraph| {
+                let lines = render::wrap(
+                    self.metrics,
+                    self.scale(size),
+                    paragraph,
+                    max_width * PT_PER_MM,
+                );
+                if lines.is_empty() {
+                    vec![String::new()]
+                } else {
+                    lines
+                }
+            })
+            .collect()
+    }
+
+    /// Largest size from `max` down to `min` at which `text` fits on one line.
+    fn fit(&self, text: &str, max: f32, min: f32, width: f32) -> f32 {
+        let mut size = max;
+        while size > min && self.width(size, text) > width {
+            size -= 1.0;
+        }
+        size
+    }
+}
+
+fn rgb(color: Color) -> printpdf::Color {
+    printpdf::Color::Rgb(Rgb::new(
+        color[0] as f32 / 255.0,
+        color[1] as f32 / 255.0,
+        color[2] as f32 / 255.0,
+        None,
+    ))
+}
+
+struct Sheet<'a> {
+    layer: PdfLayerReference,
+    body: Face<'a>,
+    title: Face<'a>,
+    inks: Inks,
+}
+
+impl Sheet<'_> {
+    fn centered(&self, face: &Face, size: f32, cx: f32, baseline: f32, text: &str, color: Color) {
+        self.layer.set_fill_color(rgb(color));
+        let x = cx - face.width(size, text) / 2.0;
+        self.layer
+            .use_text(text, size, Mm(x), Mm(baseline), &face.pdf);
+    }
+
+    fn rect(&self, x0: f32, y0: f32, x1: f32, y1: f32, thickness: f32, color: Color) {
+        self.layer.set_outline_color(rgb(color));
+        self.layer.set_outline_thickness(thickness);
+        self.layer.add_line(Line {
+            points: [(x0, y0), (x1, y0), (x1, y1), (x0, y1)]
+                .into_iter()
+                .map(|(x, y)| (Point::new(Mm(x), Mm(y)), false))
+                .collect(),
+            is_closed: true,
+        });
+    }
+
+    fn fill(&self, width: f32, height: f32, color: Color) {
+        self.layer.set_fill_color(rgb(color));
+        self.layer.add_polygon(Polygon {
+            rings: vec![[(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)]
+                .into_iter()
+                .map(|(x, y)| (Point::new(Mm(x), Mm(y)), false))
+                .collect()],
+            mode: PaintMode::Fill,
+            winding_order: WindingOrder::NonZero,
+        });
+    }
+
+    /// A filled heart `width` millimetres wide, centred on (cx, cy).
+    fn heart(&self, cx: f32, cy: f32, width: f32, color: Color) {
+        let scale = width / 34.0;
+        let points = (0..72)
+            .map(|i| {
+                let t = i as f32 / 72.0 * std::f32::consts::TAU;
+                let x = 16.0 * t.sin().powi(3);
+                let y = 13.0 * t.cos()
+                    - 5.0 * (2.0 * t).cos()
+                    - 2.0 * (3.0 * t).cos()
+                    - (4.0 * t).cos();
+                (Point::new(Mm(cx + x * scale), Mm(cy + y * scale)), false)
+            })
+            .collect();
+        self.layer.set_fill_color(rgb(color));
+        self.layer.add_polygon(Polygon {
+            rings: vec![points],
+            mode: PaintMode::Fill,
+            winding_order: WindingOrder::NonZero,
+        });
+    }
+
+    fn dashed(&self, from: (f32, f32), to: (f32, f32)) {
+        self.layer.set_outline_color(rgb(GUIDE));
+        self.layer.set_outline_thickness(0.5);
+        self.layer.set_line_dash_pattern(LineDashPattern {
+            dash_1: Some(4),
+            gap_1: Some(4),
+            ..LineDashPattern::default()
+        });
+        self.layer.add_line(Line {
+            points: vec![
+                (Point::new(Mm(from.0), Mm(from.1)), false),
+                (Point::new(Mm(to.0), Mm(to.1)), false),
+            ],
+            is_closed: false,
+        });
+        self.layer.set_line_dash_pattern(LineDashPattern::default());
+    }
+
+    /// The front cover, in the panel whose lower left corner is (x, 0).
+    fn front(&self, x: f32, w: f32, h: f32, recipient: Option<&str>) {
+        let inks = &self.inks;
+        self.rect(x + 8.0, 8.0, x + w - 8.0, h - 8.0, 2.0, inks.rose);
+        self.rect(x + 11.0, 11.0, x + w - 11.0, h - 11.0, 0.75, inks.rose);
+        self.heart(x + w / 2.0, h * 0.58, w * 0.42, inks.rose);
+
+        let cx = x + w / 2.0;
+        let title =
+            recipient.map_or_else(|| "Be My Valentine".to_string(), |to| format!("For {}", to));
+        let size = self.title.fit(&title, 26.0, 12.0, w - 30.0);
+        self.centered(&self.title, size, cx, h * 0.27, &title, inks.ink);
+        self.centered(
+            &self.body,
+            12.0,
+            cx,
+            h * 0.2,
+            "Happy Valentine's Day",
+            inks.rose,
+        );
+    }
+
+    fn back(&self, x: f32, w: f32, h: f32) {
+        self.heart(x + w / 2.0, h * 0.16, 8.0, self.inks.blush);
+        let cx = x + w / 2.0;
+        self.centered(
+            &self.body,
+            9.0,
+            cx,
+            h * 0.1,
+            "Made with love",
+            self.inks.ink,
+        );
+    }
+
+    /// The inside left panel: a scatter of small hearts.
+    fn inside_left(&self, x: f32, w: f32, h: f32) {
+        for (fx, fy, size) in [(0.3, 0.65, 14.0), (0.55, 0.5, 22.0), (0.72, 0.33, 10.0)] {
+            self.heart(x + w * fx, h * fy, size, self.inks.blush);
+        }
+    }
+
+    /// The inside right panel: the greeting, the message and the signature.
+    fn inside_right(&self, x: f32, w: f32, h: f32, card: &PrintCard) {
+        let inks = &self.inks;
+        self.rect(x + 8.0, 8.0, x + w - 8.0, h - 8.0, 0.75, inks.blush);
+        let cx = x + w / 2.0;
+        let max_width = w - 28.0;
+
+        let mut top = h - 24.0;
+        if let Some(to) = card.recipient {
+            let greeting = format!("Dear {},", to);
+            let size = self.title.fit(&greeting, 16.0, 10.0, max_width);
+            self.centered(&self.title, size, cx, top, &greeting, inks.rose);
+            top -= 12.0;
+        }
+
+        // Shrink the message until it fits above the signature.
+        let bottom = 30.0;
+        let mut size = 16.0;
+        let (lines, line_height) = loop {
+            let lines = self.body.wrap(size, card.message, max_width);
+            let line_height = size * 1.35 / PT_PER_MM;
+            if lines.len() as f32 * line_height <= top - bottom || size <= 8.0 {
+                break (lines, line_height);
+            }
+            size -= 1.0;
+        };
+        let block = lines.len() as f32 * line_height;
+        let mut baseline = bottom + (top - bottom + block) / 2.0 - line_height * 0.75;
+        for line in &lines {
+            self.centered(&self.body, size, cx, baseline, line, inks.ink);
+            baseline -= line_height;
+        }
+
+        let signature = format!("— {}", card.sender);
+        let size = self.title.fit(&signature, 14.0, 9.0, max_width);
+        self.centered(&self.title, size, cx, 18.0, &signature, inks.rose);
+    }
+}
+
+/// Lays out the card and returns it as a PDF.
+pub fn render_pdf(card: &PrintCard) -> Result<Vec<u8>, String> {
+    let (width, height) = card.paper.dimensions();
+    let title = match card.recipient {
+        Some(to) => format!("A valentine for {}", to),
+        None => "A valentine".to_string(),
+    };
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(width), Mm(height), "Card");
+
+    let (body_file, title_file) = card.font.files();
+    let (body_metrics, title_metrics) = card.font.metrics();
+    let embed = |file: &'static [u8]| {
+        doc.add_external_font(file)
+            .map_err(|e| format!("failed to embed font: {}", e))
+    };
+    let sheet = Sheet {
+        layer: doc.get_page(page).get_layer(layer),
+        body: Face {
+            pdf: embed(body_file)?,
+            metrics: body_metrics,
+        },
+        title: Face {
+            pdf: embed(title_file)?,
+            metrics: title_metrics,
+        },
+        inks: Inks::new(card.theme),
+    };
+
+    if let Some(paper) = sheet.inks.paper {
+        sheet.fill(width, height, paper);
+    }
+    let (w, h) = (width / 2.0, height / 2.0);
+    sheet.dashed((w, 0.0), (w, height));
+    sheet.dashed((0.0, h), (width, h));
+    sheet.back(0.0, w, h);
+    sheet.front(w, w, h, card.recipient);
+
+    // Turn the top half upside down and lay the inside out as if it were
+    // the bottom half: what is drawn on the right lands top left.
+    sheet.layer.save_graphics_state();
+    sheet.layer.set_ctm(CurTransMat::Raw([
+        -1.0,
+        0.0,
+        0.0,
+        -1.0,
+        width * PT_PER_MM,
+        height * PT_PER_MM,
+    ]));
+    sheet.inside_left(0.0, w, h);
+    sheet.inside_right(w, w, h, card);
+    sheet.layer.restore_graphics_state();
+
+    doc.save_to_bytes()
+        .map_err(|e| format!("failed to write PDF: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_every_paper_and_font() {
+        let themes = crate::themes::Themes::bundled();
+        let midnight = themes.get("midnight").unwrap();
+        for (paper, font, theme) in [
+            (PaperSize::A4, CardFont::Serif, None),
+            (PaperSize::Letter, CardFont::Sans, Some(&*midnight)),
+        ] {
+            let pdf = render_pdf(&PrintCard {
+                recipient: Some("Alex"),
+                sender: "Sam",
+                message: "Roses are red,\nviolets are blue. ".repeat(20).trim(),
+                paper,
+                font,
+                theme,
+            })
+            .unwrap();
+            assert!(pdf.starts_with(b"%PDF-"));
+            // Subsetting keeps the embedded fonts to the glyphs in use.
+            assert!(pdf.len() < 150_000, "{} bytes", pdf.len());
+        }
+        assert!("A5".parse::<PaperSize>().is_err());
+        assert_eq!("LETTER".parse::<PaperSize>(), Ok(PaperSize::Letter));
+    }
+}
@@ -0,0 +1,271 @@
+//! QR codes pointing at shared valentines, sized for printing inside a card.
+
+use std::fmt;
+use std::io::Cursor;
+use std::str::FromStr;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+use qrcode::{EcLevel, QrCode};
+use serde::Serialize;
+
+use super::render::{self, Color};
+
+/// Light modules around the code, as the QR spec requires.
+const QUIET_ZONE: usize = 4;
+
+pub const DEFAULT_SIZE: u32 = 512;
+pub const MIN_SIZE: u32 = 128;
+pub const MAX_SIZE: u32 = 2048;
+
+const DARK: Color = [0x1a, 0x1a, 0x1a];
+const HEART_RED: Color = [0xb0, 0x10, 0x3a];
+
+/// A heart one module wide, in a unit square with the origin top left.
+const HEART_PATH: &str = "M.5 .92C.16 .66 0 .46 0 .29 0 .13 .12 0 .28 0 .38 0 .46 .06 .5 .15 \
+                          .54 .06 .62 0 .72 0 .88 0 1 .13 1 .29 1 .46 .84 .66 .5 .92Z";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrModules {
+    /// Plain black squares; scans most reliably.
+    Square,
+    /// Red hearts, with the three corner finder patterns kept square so
+    /// scanners still lock on.
+    Hearts,
+}
+
+impl QrFormat {
+    pub const ALL: [QrFormat; 2] = [QrFormat::Png, QrFormat::Svg];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QrFormat::Png => "png",
+            QrFormat::Svg => "svg",
+        }
+    }
+}
+
+impl fmt::Display for QrFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for QrFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        QrFormat::ALL
+            .into_iter()
+            .find(|v| v.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = QrFormat::ALL.iter().map(|v| v.as_str()).collect();
+                format!(
+                    "unknown format `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+impl QrModules {
+    pub const ALL: [QrModules; 2] = [QrModules::Square, QrModules::Hearts];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QrModules::Square => "square",
+            QrModules::Hearts => "hearts",
+        }
+    }
+}
+
+impl fmt::Display for QrModules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for QrModules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        QrModules::ALL
+            .into_iter()
+            .find(|v| v.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = QrModules::ALL.iter().map(|v| v.as_str()).collect();
+                format!(
+                    "unknown module style `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+/// The dark modules of a QR code, quiet zone included.
+struct Matrix {
+    width: usize,
+    dark: Vec<bool>,
+}
+
+impl Matrix {
+    fn encode(data: &str, modules: QrModules) -> Result<Self, String> {
+        // Hearts cover less of each module than squares do, so give the
+        // scanner more redundancy to work with.
+        let level = match modules {
+            QrModules::Square => EcLevel::M,
+            QrModules::Hearts => EcLevel::Q,
+        };
+        let code = QrCode::with_error_correction_level(data, level)
+            .map_err(|e| format!("cannot encode `{}` as a QR code: {}", data, e))?;
+        let inner = code.width();
+        let width = inner + 2 * QUIET_ZONE;
+        let mut dark = vec![false; width * width];
+        for (i, color) in code.to_colors().into_iter().enumerate() {
+            let (x, y) = (i % inner + QUIET_ZONE, i / inner + QUIET_ZONE);
+            dark[y * width + x] = color == qrcode::Color::Dark;
+        }
+        Ok(Matrix { width, dark })
+    }
+
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.dark[y * self.width + x]
+    }
+
+    /// Whether (x, y) is part of one of the three 7x7 corner finder patterns.
+    fn in_finder(&self, x: usize, y: usize) -> bool {
+        let inner = self.width - 2 * QUIET_ZONE;
+        let near = |v: usize| (QUIET_ZONE..QUIET_ZONE + 7).contains(&v);
+        let far = |v: usize| (QUIET_ZONE + inner - 7..QUIET_ZONE + inner).contains(&v);
+        (near(x) && near(y)) || (far(x) && near(y)) || (near(x) && far(y))
+    }
+
+    fn dark_modules(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.width)
+            .flat_map(move |y| (0..self.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.is_dark(x, y))
+    }
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Renders `data` as a PNG exactly `size` pixels square. QrModules are whole
+/// pixels wide, so any remainder becomes extra margin.
+pub fn render_png(data: &str, size: u32, modules: QrModules) -> Result<Vec<u8>, String> {
+    let matrix = Matrix::encode(data, modules)?;
+    let module = size / matrix.width as u32;
+    if module == 0 {
+        return Err(format!(
+            "a {0}x{0} pixel image is too small for this code",
+            size
+        ));
+    }
+    let offset = (size - module * matrix.width as u32) / 2;
+
+    let mut canvas = RgbaImage::from_pixel(size, size, Rgba([255, 255, 255, 255]));
+    for (x, y) in matrix.dark_modules() {
+        let (left, top) = (offset + x as u32 * module, offset + y as u32 * module);
+        if modules == QrModules::Hearts && !matrix.in_finder(x, y) {
+            let half = module as f32 / 2.0;
+            let (cx, cy) = (left as f32 + half, top as f32 + half);
+            render::heart(&mut canvas, cx, cy, module as f32 * 1.1, HEART_RED, 1.0);
+            continue;
+        }
+        let color = match modules {
+            QrModules::Square => DARK,
+            QrModules::Hearts => HEART_RED,
+        };
+        for py in top..top + module {
+            for px in left..left + module {
+                canvas.put_pixel(px, py, Rgba([color[0], color[1], color[2], 255]));
+            }
+        }
+    }
+
+    let mut png = Cursor::new(Vec::new());
+    canvas
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| format!("failed to encode QR code: {}", e))?;
+    Ok(png.into_inner())
+}
+
+/// Renders `data` as an SVG drawn in module units and scaled to `size`
+/// pixels, so it stays sharp at any print size.
+pub fn render_svg(data: &str, size: u32, modules: QrModules) -> Result<String, String> {
+    let matrix = Matrix::encode(data, modules)?;
+    let mut squares = String::new();
+    let mut hearts = String::new();
+    for (x, y) in matrix.dark_modules() {
+        if modules == QrModules::Hearts && !matrix.in_finder(x, y) {
+            hearts.push_str(&format!(r##"<use href="#m" x="{}" y="{}"/>"##, x, y));
+        } else {
+            squares.push_str(&format!("M{} {}h1v1h-1z", x, y));
+        }
+    }
+
+    let fill = match modules {
+        QrModules::Square => hex(DARK),
+        QrModules::Hearts => hex(HEART_RED),
+    };
+    let defs = match modules {
+        QrModules::Square => String::new(),
+        QrModules::Hearts => format!(r#"<defs><path id="m" d="{}"/></defs>"#, HEART_PATH),
+    };
+    Ok(format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" "#,
+            r#"viewBox="0 0 {w} {w}" shape-rendering="crispEdges">"#,
+            r##"{defs}<rect width="{w}" height="{w}" fill="#fff"/>"##,
+            r#"<path fill="{fill}" d="{squares}"/>"#,
+            r#"<g fill="{fill}" shape-rendering="geometricPrecision">{hearts}</g></svg>"#
+        ),
+        size = size,
+        w = matrix.width,
+        defs = defs,
+        fill = fill,
+        squares = squares,
+        hearts = hearts,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_scannable_codes() {
+        let url = "https://example.com/v/abc234";
+        for modules in [QrModules::Square, QrModules::Hearts] {
+            let png = render_png(url, 300, modules).unwrap();
+            let image = image::load_from_memory(&png).unwrap().to_luma8();
+            assert_eq!(image.dimensions(), (300, 300));
+
+            let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(300, 300, |x, y| {
+                image.get_pixel(x as u32, y as u32).0[0]
+            });
+            let grids = prepared.detect_grids();
+            assert_eq!(grids.len(), 1, "{} code not found", modules);
+            assert_eq!(grids[0].decode().unwrap().1, url, "{}", modules);
+
+            let svg = render_svg(url, 300, modules).unwrap();
+            assert!(svg.starts_with("<svg") && svg.contains(r#"width="300""#));
+            assert_eq!(svg.contains("<use "), modules == QrModules::Hearts);
+        }
+        // The finder patterns stay square, so a hearts code still has some.
+        assert!(render_svg(url, 300, QrModules::Hearts)
+            .unwrap()
+            .contains("h1v1"));
+        assert!(render_png(url, 20, QrModules::Square).is_err());
+    }
+}
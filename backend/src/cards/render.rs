@@ -0,0 +1,202 @@
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use image::{Rgba, RgbaImage};
+
+pub type Color = [u8; 3];
+
+/// Blends `color` over the pixel at (x, y) with the given opacity, ignoring
+/// coordinates outside the canvas.
+pub fn blend(canvas: &mut RgbaImage, x: i64, y: i64, color: Color, alpha: f32) {
+    if x < 0 || y < 0 || x >= canvas.width() as i64 || y >= canvas.height() as i64 {
+        return;
+    }
+
+    let alpha = alpha.clamp(0.0, 1.0);
+    let pixel = canvas.get_pixel_mut(x as u32, y as u32);
+    for (channel, target) in pixel.0.iter_mut().zip(color) {
+        *channel = (*channel as f32 * (1.0 - alpha) + target as f32 * alpha).round() as u8;
+    }
+}
+
+pub fn vertical_gradient(canvas: &mut RgbaImage, top: Color, bottom: Color) {
+    let height = canvas.height().max(2) - 1;
+    for (_, y, pixel) in canvas.enumerate_pixels_mut() {
+        let t = y as f32 / height as f32;
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        *pixel = Rgba([
+            mix(top[0], bottom[0]),
+            mix(top[1], bottom[1]),
+            mix(top[2], bottom[2]),
+            255,
+        ]);
+    }
+}
+
+/// Classic implicit heart curve, evaluated in a unit box where (0, 0) is the
+/// centre and y grows upwards.
+fn inside_heart(x: f32, y: f32) -> bool {
+    let (x, y) = (x * 1.25, y * 1.25 + 0.2);
+    let a = x * x + y * y - 1.0;
+    a * a * a - x * x * y * y * y <= 0.0
+}
+
+/// Fills a heart of the given `size` (width in pixels) centred at (cx, cy),
+/// anti-aliased with 4x4 supersampling.
+pub fn heart(canvas: &mut RgbaImage, cx: f32, cy: f32, size: f32, color: Color, alpha: f32) {
+    let half = size / 2.0;
+    let (x0, x1) = ((cx - half).floor() as i64, (cx + half).ceil() as i64);
+    let (y0, y1) = ((cy - half).floor() as i64, (cy + half).ceil() as i64);
+
+    for py in y0..=y1 {
+        for px in x0..=x1 {
+            let mut hits = 0;
+            for sy in 0..4 {
+                for sx in 0..4 {
+                    let x = (px as f32 + (sx as f32 + 0.5) / 4.0 - cx) / half;
+                    let y = (cy - (py as f32 + (sy as f32 + 0.5) / 4.0)) / half;
+                    if inside_heart(x, y) {
+                        hits += 1;
+                    }
+                }
+            }
+            if hits > 0 {
+                blend(canvas, px, py, color, alpha * hits as f32 / 16.0);
+            }
+        }
+    }
+}
+
+pub fn rect_outline(canvas: &mut RgbaImage, inset: u32, thickness: u32, color: Color, alpha: f32) {
+    let (w, h) = (canvas.width(), canvas.height());
+    for y in inset..h.saturating_sub(inset) {
+        for x in inset..w.saturating_sub(inset) {
+            let edge = (x - inset)
+                .min(y - inset)
+                .min(w - inset - 1 - x)
+                .min(h - inset - 1 - y);
+            if edge < thickness {
+                blend(canvas, x as i64, y as i64, color, alpha);
+            }
+        }
+    }
+}
+
+pub fn dot(canvas: &mut RgbaImage, cx: f32, cy: f32, radius: f32, color: Color, alpha: f32) {
+    let r = radius.ceil() as i64;
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let d = ((dx * dx + dy * dy) as f32).sqrt();
+            if d <= radius {
+                let coverage = (radius - d).min(1.0);
+                blend(
+                    canvas,
+                    cx as i64 + dx,
+                    cy as i64 + dy,
+                    color,
+                    alpha * coverage,
+                );
+            }
+        }
+    }
+}
+
+/// Deterministic pseudo-random sequence in [0, 1) so decorations are stable
+/// between renders of the same theme.
+pub struct Scatter(u64);
+
+impl Scatter {
+    pub fn new(seed: u64) -> Self {
+        Scatter(seed)
+    }
+
+    pub fn next(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+pub fn text_width<F: Font>(font: &F, scale: PxScale, text: &str) -> f32 {
+    let font = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+/// Greedy word wrap against a maximum line width.
+pub fn wrap<F: Font>(font: &F, scale: PxScale, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{} {}", line, word)
+        };
+
+        if !line.is_empty() && text_width(font, scale, &candidate) > max_width {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        } else {
+            line = candidate;
+        }
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Draws a single line horizontally centred on `cx` with its baseline at `y`.
+pub fn centered_text(
+    canvas: &mut RgbaImage,
+    font: &FontRef,
+    scale: PxScale,
+    cx: f32,
+    baseline: f32,
+    text: &str,
+    color: Color,
+) {
+    let scaled = font.as_scaled(scale);
+    let mut x = cx - text_width(font, scale, text) / 2.0;
+    let mut previous = None;
+
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+
+        let glyph = id.with_scale_and_position(scale, point(x, baseline));
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, coverage| {
+                blend(
+                    canvas,
+                    bounds.min.x as i64 + gx as i64,
+                    bounds.min.y as i64 + gy as i64,
+                    color,
+                    coverage,
+                );
+            });
+        }
+
+        x += scaled.h_advance(id);
+        previous = Some(id);
+    }
+}
+
+pub fn line_height(font: &FontRef, scale: PxScale) -> f32 {
+    let scaled = font.as_scaled(scale);
+    scaled.height() + scaled.line_gap()
+}
@@ -0,0 +1,452 @@
+//! A private chat between the two partners of a couple. Messages are kept
+//! in the database and can be paged through at `/api/chat/history`; new
+//! ones, typing indicators and read receipts reach both partners live over
+//! `/ws/chat`, across instances when [`SharedState`] is in Redis.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{self, Json};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Route, Shutdown, State};
+use rocket_ws as ws;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::shared::{Channel, Local, SharedState};
+use crate::storage::{ChatMessage, Storage};
+use crate::users::Session;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_BODY_LEN: usize = 2000;
+
+/// How many events a slow socket may fall behind before it starts skipping
+/// the oldest ones.
+const FEED_CAPACITY: usize = 64;
+
+/// Something that happened in a couple's chat, as sent to their sockets.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    Message {
+        message: ChatMessage,
+    },
+    /// `user_id` started or stopped typing.
+    Typing {
+        user_id: i64,
+        typing: bool,
+    },
+    /// `user_id` read every message up to `up_to` their partner sent.
+    Read {
+        user_id: i64,
+        up_to: i64,
+        read_at: DateTime<Utc>,
+    },
+}
+
+impl ChatEvent {
+    /// Whether `user_id` should get the event: everyone in the chat but the
+    /// typist, whose own indicator would only get in the way.
+    fn for_user(&self, user_id: i64) -> bool {
+        !matches!(self, ChatEvent::Typing { user_id: typist, .. } if *typist == user_id)
+    }
+}
+
+/// An event in `couple_id`'s chat on `tenant`'s site, as sent between
+/// instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CoupleEvent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    couple_id: i64,
+    event: ChatEvent,
+}
+
+/// Fan-out of chat events to connected sockets, across instances when
+/// [`SharedState`] is in Redis.
+#[derive(Clone)]
+pub struct ChatFeed {
+    sender: broadcast::Sender<CoupleEvent>,
+    shared: Arc<dyn SharedState>,
+}
+
+impl ChatFeed {
+    /// A feed that also hands out the events `shared` relays from other
+    /// instances.
+    pub fn new(shared: Arc<dyn SharedState>) -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        let relayed = sender.clone();
+        shared.subscribe(
+            Channel::Chat,
+            Box::new(move |payload| deliver_published(&relayed, payload)),
+        );
+        ChatFeed { sender, shared }
+    }
+
+    /// Sends `event` to `couple`'s sockets on every instance. Having nobody
+    /// listening is not an error.
+    async fn publish(&self, storage: &Storage, couple: i64, event: ChatEvent) {
+        let event = CoupleEvent {
+            tenant: storage.tenant().map(str::to_string),
+            couple_id: couple,
+            event,
+        };
+        let payload = json::to_string(&event).expect("chat events always serialize");
+        if let Err(e) = self.shared.publish(Channel::Chat, &payload).await {
+            // Better this instance's sockets than nobody.
+            error!("failed to publish chat event: {}", e);
+            self.deliver(event);
+        }
+    }
+
+    fn deliver(&self, event: CoupleEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<CoupleEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// Hands an event published by any instance to this one's sockets.
+fn deliver_published(sender: &broadcast::Sender<CoupleEvent>, payload: &str) {
+    match json::from_str::<CoupleEvent>(payload) {
+        Ok(event) => {
+            let _ = sender.send(event);
+        }
+        Err(e) => warn!("ignoring malformed published chat event: {}", e),
+    }
+}
+
+fn couple_of(session: &Session) -> ApiResult<i64> {
+    session
+        .0
+        .couple_id
+        .ok_or_else(|| error(Status::NotFound, "you are not in a couple yet"))
+}
+
+fn check_body(body: &str) -> Result<String, FieldErrors> {
+    let body = body.trim().to_string();
+    let mut errors = FieldErrors::new();
+    errors.text("body", &body, MAX_BODY_LEN);
+    errors.finish(body)
+}
+
+/// Stores `body` from `sender` and sends it to the couple's sockets.
+async fn send(
+    storage: &Storage,
+    feed: &ChatFeed,
+    couple: i64,
+    sender: i64,
+    body: &str,
+) -> ApiResult<ChatMessage> {
+    let message = storage
+        .create_chat_message(couple, sender, body)
+        .await
+        .map_err(internal_error)?;
+    feed.publish(
+        storage,
+        couple,
+        ChatEvent::Message {
+            message: message.clone(),
+        },
+    )
+    .await;
+    Ok(message)
+}
+
+/// Marks what `reader`'s partner sent up to `up_to` as read and, if that
+/// changed anything, tells the couple's sockets.
+async fn mark_read(
+    storage: &Storage,
+    feed: &ChatFeed,
+    couple: i64,
+    reader: i64,
+    up_to: i64,
+) -> ApiResult<ReadReceipt> {
+    let read_at = Utc::now();
+    let read = storage
+        .mark_chat_read(couple, reader, up_to, read_at)
+        .await
+        .map_err(internal_error)?;
+    if read > 0 {
+        let event = ChatEvent::Read {
+            user_id: reader,
+            up_to,
+            read_at,
+        };
+        feed.publish(storage, couple, event).await;
+    }
+    Ok(ReadReceipt { read, read_at })
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ChatRequest {
+    body: String,
+}
+
+impl Validate for ChatRequest {
+    type Valid = String;
+
+    fn validate(self) -> Result<String, FieldErrors> {
+        check_body(&self.body)
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ReadRequest {
+    /// Id of the newest message read.
+    up_to: i64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ReadReceipt {
+    /// How many messages were newly marked read.
+    read: u64,
+    read_at: DateTime<Utc>,
+}
+
+/// What a socket can send: a message, a typing indicator or a read
+/// receipt, shaped like the [`ChatEvent`]s it gets back.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ChatCommand {
+    Message { body: String },
+    Typing { typing: bool },
+    Read { up_to: i64 },
+}
+
+/// The couple's messages, newest first. Pass the id of the newest message
+/// on the first page as `before` to keep later pages from shifting as new
+/// messages arrive.
+#[utoipa::path(
+    tag = "chat",
+    params(
+        ("page" = Option<i64>, Query),
+        ("per_page" = Option<i64>, Query),
+        ("before" = Option<i64>, Query, description = "Only messages with a smaller id"),
+    ),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Page<ChatMessage>),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/chat/history?<page>&<per_page>&<before>")]
+async fn history(
+    session: Session,
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+    before: Option<i64>,
+) -> ApiResult<Negotiated<Page<ChatMessage>>> {
+    let couple = couple_of(&session)?;
+    let (page, per_page, offset) = paginate(page, per_page);
+    let (items, total) = storage
+        .list_chat_messages(couple, before, per_page, offset)
+        .await
+        .map_err(internal_error)?;
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Sends a message to the partner, for clients without a socket open.
+#[utoipa::path(
+    tag = "chat",
+    request_body = ChatRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = ChatMessage),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/chat/messages", data = "<request>")]
+async fn create(
+    session: Session,
+    storage: &Storage,
+    feed: &State<ChatFeed>,
+    request: Valid<ChatRequest>,
+) -> ApiResult<status::Created<Negotiated<ChatMessage>>> {
+    let couple = couple_of(&session)?;
+    let message = send(storage, feed, couple, session.0.id, &request.into_inner()).await?;
+    let location = uri!(history(None::<i64>, None::<i64>, None::<i64>)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(message)))
+}
+
+/// Marks the partner's messages up to `up_to` as read.
+#[utoipa::path(
+    tag = "chat",
+    request_body = ReadRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = ReadReceipt),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[post("/api/chat/read", data = "<request>")]
+async fn read(
+    session: Session,
+    storage: &Storage,
+    feed: &State<ChatFeed>,
+    request: Json<ReadRequest>,
+) -> ApiResult<Negotiated<ReadReceipt>> {
+    let couple = couple_of(&session)?;
+    mark_read(storage, feed, couple, session.0.id, request.up_to)
+        .await
+        .map(Negotiated)
+}
+
+/// Runs one command from a socket, returning an error to send back to it.
+async fn handle(
+    storage: &Storage,
+    feed: &ChatFeed,
+    couple: i64,
+    user: i64,
+    text: &str,
+) -> Result<(), String> {
+    let command = json::from_str::<ChatCommand>(text).map_err(|e| e.to_string())?;
+    let outcome = match command {
+        ChatCommand::Message { body } => {
+            let body = check_body(&body).map_err(|e| e.to_string())?;
+            send(storage, feed, couple, user, &body).await.map(drop)
+        }
+        ChatCommand::Typing { typing } => {
+            let event = ChatEvent::Typing {
+                user_id: user,
+                typing,
+            };
+            feed.publish(storage, couple, event).await;
+            Ok(())
+        }
+        ChatCommand::Read { up_to } => mark_read(storage, feed, couple, user, up_to)
+            .await
+            .map(drop),
+    };
+    outcome.map_err(|_| "the command could not be carried out".to_string())
+}
+
+#[utoipa::path(
+    tag = "chat",
+    security(("session" = [])),
+    responses(
+        (status = 101, description = "WebSocket sending and receiving `ChatEvent`s; \
+            failed commands get `{\"type\": \"error\", \"message\": \"...\"}`"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[get("/ws/chat")]
+fn socket(
+    socket: ws::WebSocket,
+    session: Session,
+    feed: &State<ChatFeed>,
+    storage: &Storage,
+    mut shutdown: Shutdown,
+) -> ApiResult<ws::Channel<'static>> {
+    let couple = couple_of(&session)?;
+    let user = session.0.id;
+    let mut events = feed.subscribe();
+    let feed = feed.inner().clone();
+    let storage = storage.clone();
+
+    Ok(socket.channel(move |mut stream| {
+        Box::pin(async move {
+            let tenant = storage.tenant().map(str::to_string);
+            loop {
+                select! {
+                    event = events.recv() => match event {
+                        Ok(event) if event.tenant != tenant
+                            || event.couple_id != couple
+                            || !event.event.for_user(user) => {}
+                        Ok(event) => {
+                            let json = json::to_string(&event.event)
+                                .expect("chat events always serialize");
+                            stream.send(ws::Message::Text(json)).await?;
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("chat socket lagged, skipped {} events", skipped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    incoming = stream.next() => match incoming {
+                        Some(Ok(ws::Message::Text(text))) => {
+                            if let Err(message) = handle(&storage, &feed, couple, user, &text).await {
+                                let json = json::json!({"type": "error", "message": message});
+                                stream.send(ws::Message::Text(json.to_string())).await?;
+                            }
+                        }
+                        Some(Ok(ws::Message::Close(_))) | None => break,
+                        Some(Err(e)) => return Err(e),
+                        Some(Ok(_)) => {}
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+
+            Ok(())
+        })
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![history, create, read, socket]
+}
+
+/// Manages the [`ChatFeed`]. Attach it after [`crate::shared::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Chat Feed", |rocket| async {
+        let shared = rocket
+            .state::<Arc<dyn SharedState>>()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Local::default()));
+        rocket.manage(ChatFeed::new(shared))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_and_events_round_trip() {
+        let command: ChatCommand = json::from_str(r#"{"type":"typing","typing":true}"#).unwrap();
+        assert!(matches!(command, ChatCommand::Typing { typing: true }));
+        assert!(json::from_str::<ChatCommand>(r#"{"type":"shout"}"#).is_err());
+        assert!(check_body("  ").is_err());
+        assert_eq!(check_body(" hi ").unwrap(), "hi");
+
+        let typing = ChatEvent::Typing {
+            user_id: 1,
+            typing: true,
+        };
+        assert!(!typing.for_user(1));
+        assert!(typing.for_user(2));
+        let event = CoupleEvent {
+            tenant: None,
+            couple_id: 3,
+            event: typing,
+        };
+        let payload = json::to_string(&event).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"couple_id":3,"event":{"type":"typing","user_id":1,"typing":true}}"#
+        );
+        let relayed: CoupleEvent = json::from_str(&payload).unwrap();
+        assert_eq!(relayed.couple_id, 3);
+    }
+}
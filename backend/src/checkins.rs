@@ -0,0 +1,245 @@
+//! Daily check-ins and the couple's streak: consecutive days on which at
+//! least one partner checked in.
+
+use std::collections::BTreeSet;
+
+use chrono::{Duration, NaiveDate};
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::determinism::Now;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{self, CheckIn, NewCheckIn, Storage};
+use crate::timezones::{optional_zone, saved_zone, today_in, zone_for};
+use crate::users::Session;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_NOTE_LEN: usize = 140;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct CheckInRequest {
+    note: String,
+    /// IANA zone the client is in. Days are counted in the user's saved
+    /// zone, so a different one is rejected rather than letting each
+    /// check-in pick its own day.
+    timezone: Option<String>,
+}
+
+impl Validate for CheckInRequest {
+    /// The trimmed note and the zone, if one was named.
+    type Valid = (String, Option<Tz>);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let note = self.note.trim().to_string();
+        errors.text("note", &note, MAX_NOTE_LEN);
+        let zone = errors.check("timezone", optional_zone(self.timezone.as_deref()));
+        errors.finish((note, zone.flatten()))
+    }
+}
+
+/// A run of consecutive days, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Run {
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+impl Run {
+    fn len(&self) -> i64 {
+        (self.end - self.start).num_days() + 1
+    }
+
+    fn contains(&self, day: NaiveDate) -> bool {
+        (self.start..=self.end).contains(&day)
+    }
+}
+
+/// The streak still alive on `today` and the longest ever. A streak stays
+/// alive through `today` until someone checks in, so it only breaks once a
+/// whole day is missed.
+fn streaks(days: &BTreeSet<NaiveDate>, today: NaiveDate) -> (Option<Run>, Option<Run>) {
+    let mut runs: Vec<Run> = Vec::new();
+    for &day in days {
+        match runs.last_mut() {
+            Some(run) if day - run.end == Duration::days(1) => run.end = day,
+            _ => runs.push(Run {
+                start: day,
+                end: day,
+            }),
+        }
+    }
+    let longest = runs.iter().copied().max_by_key(|run| (run.len(), run.end));
+    let current = runs
+        .last()
+        .copied()
+        .filter(|run| run.end >= today - Duration::days(1));
+    (current, longest)
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct Contribution {
+    user_id: i64,
+    name: String,
+    /// Days of the current streak this partner checked in on.
+    current: i64,
+    /// Every check-in they have made.
+    total: i64,
+    checked_in_today: bool,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct Streak {
+    /// Consecutive days, up to today or yesterday, with a check-in.
+    current: i64,
+    longest: i64,
+    checked_in_today: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_check_in: Option<NaiveDate>,
+    partners: Vec<Contribution>,
+}
+
+/// Checks the signed-in partner in for the current day in their saved zone.
+/// Each partner can check in once a day.
+#[utoipa::path(
+    tag = "users",
+    request_body = CheckInRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = CheckIn),
+        (status = 401, body = ErrorResponse),
+        (status = 409, description = "Already checked in today", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/checkin", data = "<request>")]
+async fn check_in(
+    session: Session,
+    storage: &Storage,
+    now: Now,
+    request: Valid<CheckInRequest>,
+) -> ApiResult<status::Created<Negotiated<CheckIn>>> {
+    let (note, zone) = request.into_inner();
+    let saved = saved_zone(&session.0);
+    if zone.is_some_and(|zone| zone != saved) {
+        let mut errors = FieldErrors::new();
+        errors.add(
+            "timezone",
+            format!(
+                "check-ins count in your saved timezone, {}; save a new one first",
+                saved.name()
+            ),
+        );
+        return Err(errors.into());
+    }
+
+    let checkin = NewCheckIn {
+        user_id: session.0.id,
+        day: today_in(now.0, saved),
+        timezone: saved.name().to_string(),
+        note,
+    };
+    match storage.create_checkin(&checkin).await {
+        Ok(checkin) => {
+            Ok(status::Created::new(uri!(streak(None::<&str>)).to_string())
+                .body(Negotiated(checkin)))
+        }
+        Err(e) if storage::is_unique_violation(&e) => Err(error(
+            Status::Conflict,
+            format!("you already checked in on {}", checkin.day),
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// The signed-in user's streak with their partner, counted in `timezone`
+/// (their saved zone by default).
+#[utoipa::path(
+    tag = "users",
+    params(("timezone" = Option<String>, Query, description = "IANA zone for today's date")),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Streak),
+        (status = 400, description = "Unknown timezone", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[get("/api/streak?<timezone>")]
+async fn streak(
+    session: Session,
+    storage: &Storage,
+    now: Now,
+    timezone: Option<&str>,
+) -> ApiResult<Negotiated<Streak>> {
+    let zone = zone_for(timezone, Some(&session.0)).map_err(|e| error(Status::BadRequest, e))?;
+    let today = today_in(now.0, zone);
+    let members = storage
+        .checkin_days(session.0.id)
+        .await
+        .map_err(internal_error)?;
+
+    let days: BTreeSet<NaiveDate> = members.iter().flat_map(|m| m.days.clone()).collect();
+    let (current, longest) = streaks(&days, today);
+    let partners = members
+        .into_iter()
+        .map(|member| Contribution {
+            user_id: member.user_id,
+            current: current.map_or(0, |run| {
+                member.days.iter().filter(|&&d| run.contains(d)).count() as i64
+            }),
+            total: member.days.len() as i64,
+            checked_in_today: member.days.contains(&today),
+            name: member.name,
+        })
+        .collect();
+
+    Ok(Negotiated(Streak {
+        current: current.map_or(0, |run| run.len()),
+        longest: longest.map_or(0, |run| run.len()),
+        checked_in_today: days.contains(&today),
+        last_check_in: days.last().copied(),
+        partners,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![check_in, streak]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streaks_survive_until_a_whole_day_is_missed() {
+        let day = |d: &str| d.parse::<NaiveDate>().unwrap();
+        let days: BTreeSet<NaiveDate> = [
+            "2026-02-01",
+            "2026-02-02",
+            "2026-02-03",
+            "2026-02-04",
+            "2026-02-10",
+            "2026-02-11",
+        ]
+        .into_iter()
+        .map(day)
+        .collect();
+
+        let (current, longest) = streaks(&days, day("2026-02-12"));
+        assert_eq!(current.map(|r| r.len()), Some(2));
+        assert_eq!(longest.map(|r| r.start), Some(day("2026-02-01")));
+        assert_eq!(longest.map(|r| r.len()), Some(4));
+
+        let (current, _) = streaks(&days, day("2026-02-13"));
+        assert_eq!(current, None);
+        assert_eq!(streaks(&BTreeSet::new(), day("2026-02-13")), (None, None));
+
+        assert!(optional_zone(Some("Mars/Olympus")).is_err());
+        assert_eq!(optional_zone(Some(" UTC ")), Ok(Some(Tz::UTC)));
+        assert_eq!(optional_zone(None), Ok(None));
+    }
+}
@@ -0,0 +1,237 @@
+//! An anonymous crush confession board. Anyone can post a confession and
+//! read the board; the author gets a secret token with their confession
+//! and can later use it to sign it with their name.
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::idempotency::NoStore;
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::storage::{Confession, NewConfession, RevealError, Storage};
+use crate::tokens;
+use crate::valentine::MAX_NAME_LEN;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_CONFESSION_LEN: usize = 500;
+
+const REVEAL_TOKEN_LEN: usize = 32;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ConfessionRequest {
+    content: String,
+    /// Who it is for, e.g. "the barista with the red scarf".
+    to: Option<String>,
+}
+
+impl Validate for ConfessionRequest {
+    /// The confession, less its token hash.
+    type Valid = (String, Option<String>);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let content = self.content.trim().to_string();
+        let recipient = self
+            .to
+            .map(|to| to.trim().to_string())
+            .filter(|to| !to.is_empty());
+
+        let mut errors = FieldErrors::new();
+        errors.text("content", &content, MAX_CONFESSION_LEN);
+        if let Some(recipient) = &recipient {
+            errors.text("to", recipient, MAX_NAME_LEN);
+        }
+        errors.finish((content, recipient))
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct PostedConfession {
+    #[serde(flatten)]
+    confession: Confession,
+    /// Reveals the confession through `POST /api/confessions/<id>/reveal`.
+    /// Only returned here; it cannot be recovered if lost.
+    reveal_token: String,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct RevealRequest {
+    /// The `reveal_token` given when the confession was posted.
+    token: String,
+    /// The name to sign the confession with.
+    name: String,
+}
+
+impl Validate for RevealRequest {
+    /// The token and the trimmed name.
+    type Valid = (String, String);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let name = self.name.trim().to_string();
+        let mut errors = FieldErrors::new();
+        if self.token.trim().is_empty() {
+            errors.add("token", "`token` must not be empty");
+        }
+        errors.text("name", &name, MAX_NAME_LEN);
+        errors.finish((self.token, name))
+    }
+}
+
+/// Posts a confession without saying who it is from. The response holds
+/// the only copy of its reveal token.
+#[utoipa::path(
+    tag = "confessions",
+    request_body = ConfessionRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = PostedConfession),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field or refused by the content filter", body = ErrorResponse),
+        (status = 429, body = ErrorResponse),
+    )
+)]
+#[post("/api/confessions", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    request: Valid<ConfessionRequest>,
+) -> ApiResult<NoStore<status::Created<Negotiated<PostedConfession>>>> {
+    let (content, recipient) = request.into_inner();
+    let mut fields = vec![("content", content.as_str())];
+    if let Some(recipient) = &recipient {
+        fields.push(("to", recipient.as_str()));
+    }
+    filter.screen(&fields).await?;
+
+    let reveal_token = tokens::random_token(REVEAL_TOKEN_LEN);
+    let confession = storage
+        .create_confession(&NewConfession {
+            content,
+            recipient,
+            token_hash: tokens::token_hash(&reveal_token),
+        })
+        .await
+        .map_err(internal_error)?;
+
+    Ok(NoStore(
+        status::Created::new(uri!(list(None::<i64>, None::<i64>)).to_string()).body(Negotiated(
+            PostedConfession {
+                confession,
+                reveal_token,
+            },
+        )),
+    ))
+}
+
+/// The board, newest first.
+#[utoipa::path(
+    tag = "confessions",
+    params(
+        ("page" = Option<i64>, Query),
+        ("per_page" = Option<i64>, Query),
+    ),
+    responses((status = 200, body = Page<Confession>))
+)]
+#[get("/api/confessions?<page>&<per_page>")]
+async fn list(
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> ApiResult<Negotiated<Page<Confession>>> {
+    let (page, per_page, offset) = paginate(page, per_page);
+    let (items, total) = storage
+        .list_confessions(per_page, offset)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Signs confession `id` with a name, for the author holding its reveal
+/// token. A confession can be revealed once.
+#[utoipa::path(
+    tag = "confessions",
+    request_body = RevealRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = Confession),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "Wrong reveal token", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Already revealed", body = ErrorResponse),
+        (status = 422, description = "Invalid field or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/confessions/<id>/reveal", data = "<request>")]
+async fn reveal(
+    _key: ApiKey,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    id: i64,
+    request: Valid<RevealRequest>,
+) -> ApiResult<Negotiated<Confession>> {
+    let (token, name) = request.into_inner();
+    filter.screen(&[("name", name.as_str())]).await?;
+
+    match storage
+        .reveal_confession(id, &tokens::token_hash(&token), &name)
+        .await
+        .map_err(internal_error)?
+    {
+        Ok(confession) => Ok(Negotiated(confession)),
+        Err(RevealError::Unknown) => Err(error(
+            Status::NotFound,
+            format!("no confession with id {}", id),
+        )),
+        Err(RevealError::WrongToken) => Err(error(
+            Status::Forbidden,
+            "`token` is not this confession's reveal token",
+        )),
+        Err(RevealError::AlreadyRevealed) => Err(error(
+            Status::Conflict,
+            "this confession has already been revealed",
+        )),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, list, reveal]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_hashed_ignoring_surrounding_space() {
+        let token = "Xq7RkP2mWc9LtB4nZs1YhD6vFj3GaE8u";
+        assert_eq!(
+            tokens::token_hash(token),
+            tokens::token_hash(&format!(" {}\n", token))
+        );
+        assert_eq!(tokens::token_hash(token).len(), 64);
+        assert_ne!(
+            tokens::token_hash(token),
+            tokens::token_hash(&token.to_lowercase())
+        );
+
+        let request = ConfessionRequest {
+            content: "  I like your laugh ".to_string(),
+            to: Some("   ".to_string()),
+        };
+        assert_eq!(
+            request.validate().unwrap(),
+            ("I like your laugh".to_string(), None)
+        );
+    }
+}
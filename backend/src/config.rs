@@ -0,0 +1,373 @@
+//! Config shared by several stages: the deployment [`Profile`], CORS and
+//! the public URL.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::path::Path;
+
+use rocket::config::SecretKey;
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::value::Value;
+use rocket::figment::{Figment, Profile as FigmentProfile};
+use rocket_cors::{AllowedMethods, AllowedOrigins, Cors, CorsOptions, Method};
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+/// Directory of `<profile>.toml` files, each overriding Rocket.toml.
+const PROFILES_DIR: &str = "profiles";
+
+/// A deployment profile, picked with `ROCKET_PROFILE`. Its file in
+/// [`PROFILES_DIR`] overrides Rocket.toml's `[default]`, and `ROCKET_*`
+/// variables override both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Staging,
+    Prod,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 3] = [Profile::Dev, Profile::Staging, Profile::Prod];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Staging => "staging",
+            Profile::Prod => "prod",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Profile> {
+        Profile::ALL
+            .into_iter()
+            .find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The profile used when `ROCKET_PROFILE` is unset.
+    fn for_build() -> Profile {
+        if cfg!(debug_assertions) {
+            Profile::Dev
+        } else {
+            Profile::Prod
+        }
+    }
+
+    /// Keys that must be set, and not empty, for the server to start.
+    fn required_keys(self) -> &'static [&'static str] {
+        match self {
+            Profile::Dev => &[],
+            Profile::Staging => &["api_keys", "secret_key", "public_url"],
+            Profile::Prod => &[
+                "api_keys",
+                "secret_key",
+                "jwt_secret",
+                "public_url",
+                "cors.allowed_origins",
+            ],
+        }
+    }
+}
+
+/// Rocket's figment with the profile's file merged in, the profile
+/// defaulting to dev for debug builds and prod for release builds when
+/// `ROCKET_PROFILE` is unset. Like Rocket's own debug profile, dev makes up
+/// a `secret_key` per launch if none is set.
+///
+/// The file's values go under `[default]` rather than a table of their own,
+/// so `ROCKET_*` variables still win and Rocket does not mistake `dev` and
+/// `prod` tables for its deprecated profiles.
+pub fn figment() -> Figment {
+    let name =
+        std::env::var("ROCKET_PROFILE").unwrap_or_else(|_| Profile::for_build().name().to_string());
+    let mut figment = rocket::Config::figment().select(name.as_str());
+    let Some(profile) = Profile::from_name(&name) else {
+        // Refused by the profile stage.
+        return figment;
+    };
+
+    let file = Path::new(PROFILES_DIR).join(format!("{}.toml", profile.name()));
+    figment = figment.merge(Toml::file(file).profile(FigmentProfile::Default));
+    if profile == Profile::Dev && !is_set(&figment, "secret_key") {
+        let key: [u8; 32] = rand::random();
+        figment = figment.merge(("secret_key", BASE64.encode(key)));
+    }
+    figment
+}
+
+/// Whether `key` has a value that is not empty. Rocket's own defaults leave
+/// an all-zero `secret_key`, which counts as unset.
+fn is_set(figment: &Figment, key: &str) -> bool {
+    if key == "secret_key" {
+        return figment
+            .extract_inner::<SecretKey>(key)
+            .is_ok_and(|key| !key.is_zero());
+    }
+    figment.find_value(key).is_ok_and(|value| !is_blank(&value))
+}
+
+fn is_blank(value: &Value) -> bool {
+    match value {
+        Value::String(_, s) => s.trim().is_empty(),
+        Value::Array(_, items) => items.is_empty(),
+        Value::Dict(_, dict) => dict.is_empty(),
+        _ => false,
+    }
+}
+
+/// Checks `figment` against its profile, returning the profile or every
+/// problem found: the required keys that are missing, a mock provider mode
+/// in prod, and an unparsable `log_filter`.
+fn check_profile(figment: &Figment) -> Result<Profile, Vec<String>> {
+    let name = figment.profile().as_str().as_str();
+    let Some(profile) = Profile::from_name(name) else {
+        return Err(vec![format!(
+            "unknown profile `{}`; ROCKET_PROFILE must be dev, staging or prod",
+            name
+        )]);
+    };
+
+    let mut problems = Vec::new();
+    let missing: Vec<&str> = profile
+        .required_keys()
+        .iter()
+        .copied()
+        .filter(|key| !is_set(figment, key))
+        .collect();
+    if !missing.is_empty() {
+        problems.push(format!(
+            "the {} profile is missing required config: {} (set them in {}/{}.toml or as ROCKET_* variables)",
+            profile.name(),
+            missing.join(", "),
+            PROFILES_DIR,
+            profile.name()
+        ));
+    }
+    if profile == Profile::Prod
+        && figment
+            .find_value("providers.mode")
+            .ok()
+            .as_ref()
+            .and_then(Value::as_str)
+            == Some("mock")
+    {
+        problems.push("providers.mode must not be mock in the prod profile".to_string());
+    }
+    if let Ok(filter) = figment.extract_inner::<String>("log_filter") {
+        if let Err(e) = EnvFilter::try_new(&filter) {
+            problems.push(format!("invalid log_filter `{}`: {}", filter, e));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(profile)
+    } else {
+        Err(problems)
+    }
+}
+
+/// The `log_filter` for `figment`'s profile, if set; `RUST_LOG` overrides it.
+pub fn log_filter(figment: &Figment) -> Option<String> {
+    figment.extract_inner("log_filter").ok()
+}
+
+/// Checks the config against its [`Profile`] before anything else starts, so
+/// a missing key fails the launch with every problem listed rather than the
+/// first request that needs it. Manages the profile.
+pub fn profile() -> AdHoc {
+    AdHoc::try_on_ignite("Profile", |rocket| async {
+        match check_profile(rocket.figment()) {
+            Ok(profile) => {
+                info!("using the {} profile", profile.name());
+                Ok(rocket.manage(profile))
+            }
+            Err(problems) => {
+                for problem in problems {
+                    error!("{}", problem);
+                }
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// The `[default.cors]` table in Rocket.toml.
+#[derive(Debug, Default, Deserialize)]
+pub struct CorsConfig {
+    /// Exact origins such as `"https://valentine.example.com"`; `"*"` allows
+    /// any origin. When unset, debug builds allow every origin and release
+    /// builds allow none.
+    pub allowed_origins: Option<Vec<String>>,
+    #[serde(default = "default_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default)]
+    pub allow_credentials: bool,
+}
+
+fn default_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "DELETE", "OPTIONS"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
+}
+
+impl CorsConfig {
+    pub fn from_figment(figment: &Figment) -> Result<Self, String> {
+        match figment.extract_inner::<CorsConfig>("cors") {
+            Ok(config) => Ok(config),
+            Err(e) if e.missing() => Ok(CorsConfig {
+                allowed_methods: default_methods(),
+                ..CorsConfig::default()
+            }),
+            Err(e) => Err(format!("invalid cors config: {}", e)),
+        }
+    }
+
+    fn allowed_origins(&self) -> AllowedOrigins {
+        match &self.allowed_origins {
+            Some(origins) if origins.iter().any(|o| o == "*") => AllowedOrigins::all(),
+            Some(origins) => AllowedOrigins::some_exact(origins),
+            None if cfg!(debug_assertions) => {
+                warn!("no cors.allowed_origins configured, allowing all origins in debug build");
+                AllowedOrigins::all()
+            }
+            None => {
+                warn!("no cors.allowed_origins configured, cross-origin requests are disabled");
+                AllowedOrigins::some_exact::<&str>(&[])
+            }
+        }
+    }
+
+    fn allowed_methods(&self) -> Result<AllowedMethods, String> {
+        self.allowed_methods
+            .iter()
+            .map(|m| {
+                m.parse::<Method>()
+                    .map_err(|_| format!("invalid cors.allowed_methods entry `{}`", m))
+            })
+            .collect()
+    }
+
+    pub fn to_cors(&self) -> Result<Cors, String> {
+        CorsOptions {
+            allowed_origins: self.allowed_origins(),
+            allowed_methods: self.allowed_methods()?,
+            allow_credentials: self.allow_credentials,
+            ..Default::default()
+        }
+        .to_cors()
+        .map_err(|e| format!("invalid cors config: {}", e))
+    }
+}
+
+/// Attaches the CORS fairing built from the `cors` config table.
+pub fn cors() -> AdHoc {
+    AdHoc::try_on_ignite("CORS Config", |rocket| async {
+        match CorsConfig::from_figment(rocket.figment()).and_then(|c| c.to_cors()) {
+            Ok(cors) => Ok(rocket.attach(cors)),
+            Err(e) => {
+                error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+/// Public origin from the `public_url` key (e.g.
+/// `https://valentine.example.com`), used to build absolute links to share
+/// pages and uploads. Links are relative when unset.
+#[derive(Clone)]
+pub struct PublicUrl(Option<String>);
+
+impl PublicUrl {
+    /// `base` is a scheme and host, optionally with a path, and no trailing
+    /// slash.
+    pub fn new(base: Option<String>) -> Self {
+        PublicUrl(base)
+    }
+
+    pub fn base(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn absolute(&self, path: &str) -> String {
+        match &self.0 {
+            Some(base) => format!("{}{}", base, path),
+            None => path.to_string(),
+        }
+    }
+
+    /// The path of a link built by [`PublicUrl::absolute`], or `None` for a
+    /// link to some other origin.
+    pub fn relative<'a>(&self, url: &'a str) -> Option<&'a str> {
+        let path = match &self.0 {
+            Some(base) => url.strip_prefix(base.as_str()).unwrap_or(url),
+            None => url,
+        };
+        path.starts_with('/').then_some(path)
+    }
+}
+
+/// Manages the [`PublicUrl`] read from `public_url`.
+pub fn public_url() -> AdHoc {
+    AdHoc::try_on_ignite("Public URL", |rocket| async {
+        let public_url = match rocket.figment().extract_inner::<String>("public_url") {
+            Ok(url) => Some(url.trim_end_matches('/').to_string()),
+            Err(e) if e.missing() => None,
+            Err(e) => {
+                error!("invalid public_url: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket.manage(PublicUrl(public_url)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn figment(profile: &str) -> Figment {
+        Figment::from(
+            Toml::string(
+                r#"
+            [default]
+            api_keys = []
+            public_url = "https://valentine.example.com"
+
+            [default.providers]
+            mode = "mock"
+
+            [staging]
+            api_keys = ["k1"]
+            secret_key = "hPRYyVRiMyxpw5sBB1XeCMN1kFsDCqKvBi2QJxBVHQk="
+            "#,
+            )
+            .nested(),
+        )
+        .select(profile)
+    }
+
+    #[test]
+    fn profiles_list_every_missing_key() {
+        assert_eq!(check_profile(&figment("dev")), Ok(Profile::Dev));
+        assert_eq!(check_profile(&figment("staging")), Ok(Profile::Staging));
+
+        let problems = check_profile(&figment("prod")).unwrap_err();
+        assert!(problems[0].contains(
+            "missing required config: api_keys, secret_key, jwt_secret, cors.allowed_origins"
+        ));
+        assert_eq!(
+            problems[1],
+            "providers.mode must not be mock in the prod profile"
+        );
+
+        let unknown = check_profile(&figment("release")).unwrap_err();
+        assert!(unknown[0].starts_with("unknown profile `release`"));
+    }
+}
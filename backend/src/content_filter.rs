@@ -0,0 +1,345 @@
+//! Screens submitted messages and quotes for profanity, links and phone
+//! numbers before they are stored or sent. Enabled by the `content_filter`
+//! table in Rocket.toml; without it every submission passes.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{unprocessable, ApiResult};
+use crate::storage::{NewMessage, NewQuote};
+
+/// Used when `content_filter.words` is not set. Entries match whole words
+/// (or whole phrases) case-insensitively.
+const DEFAULT_WORDS: &[&str] = &[
+    "asshole",
+    "bastard",
+    "bitch",
+    "bullshit",
+    "cunt",
+    "dick",
+    "fuck",
+    "fucking",
+    "motherfucker",
+    "shit",
+    "slut",
+    "whore",
+];
+
+/// Top-level domains that make a bare `name.tld` count as a link.
+const LINK_TLDS: &[&str] = &[
+    "app", "biz", "co", "com", "de", "dev", "gg", "info", "io", "ly", "me", "net", "org", "ru",
+    "tv", "uk", "us", "xyz",
+];
+
+/// Digit counts that read as a phone number rather than, say, a year.
+const PHONE_DIGITS: std::ops::RangeInclusive<usize> = 10..=15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    Word,
+    Url,
+    PhoneNumber,
+}
+
+/// One reason a submission was refused, as listed in the error's `details`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct Violation {
+    pub field: String,
+    pub kind: ViolationKind,
+    /// The offending text as written.
+    pub matched: String,
+}
+
+/// What happens to a submission with violations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Refuse it with `422` and the violations.
+    Reject,
+    /// Accept it and log the violations for review.
+    Flag,
+}
+
+fn default_action() -> Action {
+    Action::Reject
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct FilterConfig {
+    #[serde(default = "default_action")]
+    action: Action,
+    words: Option<Vec<String>>,
+    #[serde(default = "enabled")]
+    urls: bool,
+    #[serde(default = "enabled")]
+    phone_numbers: bool,
+}
+
+/// The in-process checks: a wordlist plus link and phone-number patterns.
+#[derive(Debug)]
+pub struct Rules {
+    /// Normalized entries, see [`normalize`].
+    words: HashSet<String>,
+    urls: bool,
+    phone_numbers: bool,
+}
+
+/// Lowercases `text` and collapses every run of non-alphanumerics into one
+/// space, padded at both ends so whole words can be found with `contains`.
+fn normalize(text: &str) -> String {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    format!(" {} ", words.join(" "))
+}
+
+fn looks_like_link(chunk: &str) -> bool {
+    let chunk = chunk
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    if chunk.contains("://") || chunk.starts_with("www.") {
+        return true;
+    }
+
+    let host = chunk.split('/').next().unwrap_or_default();
+    match host.rsplit_once('.') {
+        Some((name, tld)) => {
+            name.split('.').all(|label| {
+                !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+            }) && LINK_TLDS.contains(&tld)
+        }
+        None => false,
+    }
+}
+
+/// Runs of digits and the separators people type between them.
+fn phone_numbers(text: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut run = String::new();
+    for c in text.chars().chain(std::iter::once('\n')) {
+        if c.is_ascii_digit() || "+(".contains(c) || (!run.is_empty() && " -.)".contains(c)) {
+            run.push(c);
+            continue;
+        }
+        let digits = run.chars().filter(char::is_ascii_digit).count();
+        if PHONE_DIGITS.contains(&digits) {
+            found.push(
+                run.trim_end_matches(|c: char| !c.is_ascii_digit())
+                    .to_string(),
+            );
+        }
+        run.clear();
+    }
+    found
+}
+
+impl Rules {
+    fn new(words: Option<Vec<String>>, urls: bool, phone_numbers: bool) -> Self {
+        let words: Vec<String> = match words {
+            Some(words) => words.iter().map(|w| normalize(w)).collect(),
+            None => DEFAULT_WORDS.iter().map(|w| normalize(w)).collect(),
+        };
+        Rules {
+            words: words.into_iter().filter(|w| !w.trim().is_empty()).collect(),
+            urls,
+            phone_numbers,
+        }
+    }
+
+    fn check(&self, field: &str, text: &str) -> Vec<Violation> {
+        let violation = |kind, matched: String| Violation {
+            field: field.to_string(),
+            kind,
+            matched,
+        };
+
+        let normalized = normalize(text);
+        let mut words: Vec<&String> = self
+            .words
+            .iter()
+            .filter(|w| normalized.contains(w.as_str()))
+            .collect();
+        words.sort();
+        let mut violations: Vec<Violation> = words
+            .into_iter()
+            .map(|w| violation(ViolationKind::Word, w.trim().to_string()))
+            .collect();
+
+        if self.urls {
+            violations.extend(
+                text.split_whitespace()
+                    .filter(|chunk| looks_like_link(chunk))
+                    .map(|chunk| {
+                        let chunk = chunk.trim_end_matches(|c: char| ".,!?;:)".contains(c));
+                        violation(ViolationKind::Url, chunk.to_string())
+                    }),
+            );
+        }
+        if self.phone_numbers {
+            violations.extend(
+                phone_numbers(text)
+                    .into_iter()
+                    .map(|number| violation(ViolationKind::PhoneNumber, number)),
+            );
+        }
+        violations
+    }
+}
+
+/// The backend that does the checking. Another service, such as an external
+/// moderation API, plugs in as a new variant.
+#[derive(Debug)]
+pub enum Checker {
+    Rules(Rules),
+}
+
+impl Checker {
+    async fn check(&self, fields: &[(&str, &str)]) -> Result<Vec<Violation>, String> {
+        match self {
+            Checker::Rules(rules) => Ok(fields
+                .iter()
+                .flat_map(|(field, text)| rules.check(field, text))
+                .collect()),
+        }
+    }
+}
+
+/// Managed state for the `content_filter` config. `checker` is `None` when
+/// filtering is off.
+#[derive(Clone)]
+pub struct ContentFilter {
+    action: Action,
+    checker: Option<Arc<Checker>>,
+}
+
+impl ContentFilter {
+    /// Checks each `(field, text)` pair, failing with `422` and the
+    /// violations in `details` when the action is [`Action::Reject`].
+    pub async fn screen(&self, fields: &[(&str, &str)]) -> ApiResult<()> {
+        let Some(checker) = &self.checker else {
+            return Ok(());
+        };
+        let violations = match checker.check(fields).await {
+            Ok(violations) => violations,
+            Err(e) => {
+                // Fail open: a broken checker should not take submissions down.
+                error!("content filter failed: {}", e);
+                return Ok(());
+            }
+        };
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        match self.action {
+            Action::Flag => {
+                warn!("flagged submission: {:?}", violations);
+                Ok(())
+            }
+            Action::Reject => {
+                let mut fields: Vec<&str> = violations.iter().map(|v| v.field.as_str()).collect();
+                fields.dedup();
+                Err(unprocessable(
+                    format!("content not allowed in `{}`", fields.join("`, `")),
+                    &violations,
+                ))
+            }
+        }
+    }
+
+    pub async fn screen_message(&self, message: &NewMessage) -> ApiResult<()> {
+        let mut fields = vec![("message", message.message.as_str())];
+        fields.push(("from", message.sender.as_str()));
+        if let Some(recipient) = &message.recipient {
+            fields.push(("to", recipient.as_str()));
+        }
+        self.screen(&fields).await
+    }
+
+    pub async fn screen_quote(&self, quote: &NewQuote) -> ApiResult<()> {
+        self.screen(&[("text", quote.text.as_str())]).await
+    }
+}
+
+/// Manages the [`ContentFilter`] from the optional `content_filter` table.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Content Filter", |rocket| async {
+        let filter = match rocket
+            .figment()
+            .extract_inner::<FilterConfig>("content_filter")
+        {
+            Ok(config) => ContentFilter {
+                action: config.action,
+                checker: Some(Arc::new(Checker::Rules(Rules::new(
+                    config.words,
+                    config.urls,
+                    config.phone_numbers,
+                )))),
+            },
+            Err(e) if e.missing() => {
+                info!("no content_filter configured, submissions are not screened");
+                ContentFilter {
+                    action: Action::Reject,
+                    checker: None,
+                }
+            }
+            Err(e) => {
+                error!("invalid content_filter config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket.manage(filter))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(rules: &Rules, text: &str) -> Vec<(ViolationKind, String)> {
+        rules
+            .check("message", text)
+            .into_iter()
+            .map(|v| (v.kind, v.matched))
+            .collect()
+    }
+
+    #[test]
+    fn words_links_and_phone_numbers_are_reported() {
+        let rules = Rules::new(Some(vec!["darn".into(), "Drat It".into()]), true, true);
+
+        assert!(kinds(&rules, "Be mine on 2026-02-14, darling... me too").is_empty());
+        assert_eq!(
+            kinds(&rules, "Darn! Drat, it..."),
+            vec![
+                (ViolationKind::Word, "darn".to_string()),
+                (ViolationKind::Word, "drat it".to_string()),
+            ]
+        );
+        assert_eq!(
+            kinds(&rules, "see https://x.test/a or bit.ly/abc."),
+            vec![
+                (ViolationKind::Url, "https://x.test/a".to_string()),
+                (ViolationKind::Url, "bit.ly/abc".to_string()),
+            ]
+        );
+        assert_eq!(
+            kinds(&rules, "call me: +1 (555) 123-4567!"),
+            vec![(ViolationKind::PhoneNumber, "+1 (555) 123-4567".to_string())]
+        );
+
+        let lenient = Rules::new(Some(Vec::new()), false, false);
+        assert!(kinds(&lenient, "darn www.example.com 5551234567").is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::Route;
+use serde::Serialize;
+
+use crate::determinism::Now;
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct Countdown {
+    pub timezone: String,
+    pub now: String,
+    /// Start of the Valentine's Day being counted down to, in local time.
+    pub target: String,
+    pub is_valentines_day: bool,
+    pub total_seconds: i64,
+    pub days: i64,
+    pub hours: i64,
+    pub minutes: i64,
+    pub seconds: i64,
+}
+
+/// Local midnight starting Feb 14 of `year`. Midnight never falls in a DST
+/// gap for real-world zones on that date, but fall back to UTC if it does.
+fn valentines_start(tz: Tz, year: i32) -> DateTime<Tz> {
+    let midnight = NaiveDate::from_ymd_opt(year, 2, 14)
+        .expect("Feb 14 exists every year")
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time");
+
+    tz.from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_datetime(&midnight))
+}
+
+/// Time remaining until the next Feb 14 in `tz`. On Valentine's Day itself
+/// the countdown is zero and `is_valentines_day` is set.
+pub fn countdown(now: DateTime<Utc>, tz: Tz) -> Countdown {
+    let local = now.with_timezone(&tz);
+    let is_valentines_day = local.month() == 2 && local.day() == 14;
+
+    let this_year = valentines_start(tz, local.year());
+    let target = if is_valentines_day || this_year > local {
+        this_year
+    } else {
+        valentines_start(tz, local.year() + 1)
+    };
+
+    let total_seconds = if is_valentines_day {
+        0
+    } else {
+        (target.with_timezone(&Utc) - now).num_seconds().max(0)
+    };
+
+    Countdown {
+        timezone: tz.name().to_string(),
+        now: local.to_rfc3339(),
+        target: target.to_rfc3339(),
+        is_valentines_day,
+        total_seconds,
+        days: total_seconds / 86_400,
+        hours: total_seconds % 86_400 / 3_600,
+        minutes: total_seconds % 3_600 / 60,
+        seconds: total_seconds % 60,
+    }
+}
+
+/// Parses a `?tz=` IANA timezone name, defaulting to UTC.
+pub fn parse_tz(tz: Option<&str>) -> ApiResult<Tz> {
+    match tz {
+        Some(name) => name.parse::<Tz>().map_err(|_| {
+            error(
+                Status::BadRequest,
+                format!(
+                    "unknown timezone `{}`, expected an IANA name like America/Chicago",
+                    name
+                ),
+            )
+        }),
+        None => Ok(Tz::UTC),
+    }
+}
+
+#[utoipa::path(
+    tag = "countdown",
+    params(("tz" = Option<String>, Query, description = "IANA timezone, UTC by default")),
+    responses(
+        (status = 200, body = Countdown),
+        (status = 400, description = "Unknown timezone", body = ErrorResponse),
+    )
+)]
+#[get("/api/countdown?<tz>")]
+fn get(now: Now, tz: Option<&str>) -> ApiResult<Negotiated<Countdown>> {
+    let tz = parse_tz(tz)?;
+    Ok(Negotiated(countdown(now.0, tz)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![get]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn counts_down_to_local_midnight() {
+        let chicago: Tz = "America/Chicago".parse().unwrap();
+        // 2026-02-13 23:00 in Chicago (UTC-6).
+        let c = countdown(utc("2026-02-14T05:00:00Z"), chicago);
+        assert!(!c.is_valentines_day);
+        assert_eq!(c.total_seconds, 3_600);
+        assert_eq!((c.days, c.hours, c.minutes, c.seconds), (0, 1, 0, 0));
+        assert_eq!(c.target, "2026-02-14T00:00:00-06:00");
+    }
+
+    #[test]
+    fn valentines_day_is_zero() {
+        let c = countdown(utc("2026-02-14T12:00:00Z"), Tz::UTC);
+        assert!(c.is_valentines_day);
+        assert_eq!(c.total_seconds, 0);
+    }
+
+    #[test]
+    fn rolls_over_to_next_year() {
+        let c = countdown(utc("2026-02-15T00:00:00Z"), Tz::UTC);
+        assert_eq!(c.target, "2027-02-14T00:00:00+00:00");
+        assert_eq!(c.days, 364);
+    }
+}
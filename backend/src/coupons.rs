@@ -0,0 +1,310 @@
+//! Love coupons: booklets of favors, e.g. "one breakfast in bed", that one
+//! partner issues and the other redeems, once and before they expire.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::email::Mailer;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::jobs::{Job, Jobs};
+use crate::negotiate::Negotiated;
+use crate::push::{Notification, Push};
+use crate::storage::{Coupon, CouponStatus, NewCoupon, RedeemError, Storage};
+use crate::users::Session;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_BOOKLET_LEN: usize = 80;
+const MAX_TITLE_LEN: usize = 80;
+const MAX_DESCRIPTION_LEN: usize = 500;
+const MAX_COUPONS: usize = 20;
+
+fn check_expiry(expires_at: Option<DateTime<Utc>>) -> Result<(), String> {
+    match expires_at {
+        Some(expires_at) if expires_at <= Utc::now() => {
+            Err("`expires_at` must be in the future".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct CouponRequest {
+    title: String,
+    description: Option<String>,
+    /// Overrides the booklet's expiry for this coupon.
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl Validate for CouponRequest {
+    type Valid = (String, Option<String>, Option<DateTime<Utc>>);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let title = self.title.trim().to_string();
+        errors.text("title", &title, MAX_TITLE_LEN);
+        let description = self
+            .description
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty());
+        if let Some(description) = &description {
+            errors.text("description", description, MAX_DESCRIPTION_LEN);
+        }
+        errors.check("expires_at", check_expiry(self.expires_at));
+        errors.finish((title, description, self.expires_at))
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct BookletRequest {
+    booklet: String,
+    /// When the booklet's coupons stop being redeemable; never by default.
+    expires_at: Option<DateTime<Utc>>,
+    coupons: Vec<CouponRequest>,
+}
+
+impl Validate for BookletRequest {
+    type Valid = Vec<NewCoupon>;
+
+    fn validate(self) -> Result<Vec<NewCoupon>, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let booklet = self.booklet.trim().to_string();
+        errors.text("booklet", &booklet, MAX_BOOKLET_LEN);
+        errors.check("expires_at", check_expiry(self.expires_at));
+        if !(1..=MAX_COUPONS).contains(&self.coupons.len()) {
+            errors.add(
+                "coupons",
+                format!("`coupons` must have 1 to {} coupons", MAX_COUPONS),
+            );
+        }
+        let coupons = errors.nested("coupons", self.coupons).unwrap_or_default();
+
+        errors.finish(
+            coupons
+                .into_iter()
+                .map(|(title, description, expires_at)| NewCoupon {
+                    booklet: booklet.clone(),
+                    title,
+                    description,
+                    expires_at: expires_at.or(self.expires_at),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A coupon as listed, with whether it can still be redeemed.
+#[derive(Serialize, utoipa::ToSchema)]
+struct CouponView {
+    #[serde(flatten)]
+    coupon: Coupon,
+    status: CouponStatus,
+    /// Whether the signed-in user issued it, and so cannot redeem it.
+    issued_by_me: bool,
+}
+
+impl CouponView {
+    fn new(coupon: Coupon, viewer: i64, now: DateTime<Utc>) -> CouponView {
+        CouponView {
+            status: coupon.status(now),
+            issued_by_me: coupon.issuer_id == viewer,
+            coupon,
+        }
+    }
+}
+
+fn couple_of(session: &Session) -> ApiResult<i64> {
+    session
+        .0
+        .couple_id
+        .ok_or_else(|| error(Status::NotFound, "you are not in a couple yet"))
+}
+
+/// Issues a booklet of coupons from the signed-in user to their partner.
+#[utoipa::path(
+    tag = "coupons",
+    request_body = BookletRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Vec<CouponView>),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/coupons", data = "<request>")]
+async fn create(
+    session: Session,
+    storage: &Storage,
+    request: Valid<BookletRequest>,
+) -> ApiResult<status::Created<Negotiated<Vec<CouponView>>>> {
+    let couple = couple_of(&session)?;
+    let coupons = storage
+        .create_coupons(&request.into_inner(), couple, session.0.id)
+        .await
+        .map_err(internal_error)?;
+
+    let now = Utc::now();
+    let views = coupons
+        .into_iter()
+        .map(|coupon| CouponView::new(coupon, session.0.id, now))
+        .collect();
+    Ok(status::Created::new(uri!(list).to_string()).body(Negotiated(views)))
+}
+
+/// Every coupon either partner issued, by booklet.
+#[utoipa::path(
+    tag = "coupons",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<CouponView>),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/coupons")]
+async fn list(session: Session, storage: &Storage) -> ApiResult<Negotiated<Vec<CouponView>>> {
+    let couple = couple_of(&session)?;
+    let coupons = storage.list_coupons(couple).await.map_err(internal_error)?;
+
+    let now = Utc::now();
+    Ok(Negotiated(
+        coupons
+            .into_iter()
+            .map(|coupon| CouponView::new(coupon, session.0.id, now))
+            .collect(),
+    ))
+}
+
+/// Redeems a coupon the signed-in user's partner issued, then lets the
+/// partner know by push and, when SMTP is configured, email.
+#[utoipa::path(
+    tag = "coupons",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = CouponView),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "The coupon is the user's own", body = ErrorResponse),
+        (status = 404, description = "No such coupon", body = ErrorResponse),
+        (status = 409, description = "Already redeemed", body = ErrorResponse),
+        (status = 410, description = "Expired", body = ErrorResponse),
+    )
+)]
+#[post("/api/coupons/<id>/redeem")]
+async fn redeem(
+    session: Session,
+    storage: &Storage,
+    mailer: &State<Mailer>,
+    jobs: &State<Jobs>,
+    push: &State<Push>,
+    id: i64,
+) -> ApiResult<Negotiated<CouponView>> {
+    let couple = couple_of(&session)?;
+    let now = Utc::now();
+    let coupon = storage
+        .redeem_coupon(id, couple, session.0.id, now)
+        .await
+        .map_err(internal_error)?
+        .map_err(|e| match e {
+            RedeemError::Unknown => error(Status::NotFound, "no such coupon"),
+            RedeemError::OwnCoupon => {
+                error(Status::Forbidden, "you cannot redeem a coupon you issued")
+            }
+            RedeemError::Redeemed => error(Status::Conflict, "coupon has already been redeemed"),
+            RedeemError::Expired => error(Status::Gone, "coupon has expired"),
+        })?;
+
+    let title = format!("{} redeemed “{}”", session.0.name, coupon.title);
+    push.notify(
+        storage,
+        Some(couple),
+        Notification {
+            title: title.clone(),
+            body: coupon.booklet.clone(),
+            url: Some(uri!(list).to_string()),
+        },
+    )
+    .await;
+    if mailer.is_enabled() {
+        let issuer = storage
+            .get_user(coupon.issuer_id)
+            .await
+            .map_err(internal_error)?;
+        if let Some(issuer) = issuer {
+            let job = Job::Email {
+                to: issuer.email,
+                subject: title,
+                text: format!(
+                    "{} redeemed “{}” from your booklet “{}”.",
+                    session.0.name, coupon.title, coupon.booklet
+                ),
+            };
+            if let Err(e) = jobs.enqueue(storage, &job).await {
+                error!("failed to queue coupon email: {}", e);
+            }
+        }
+    } else {
+        warn!(
+            "coupon {} redeemed; not emailed, SMTP is not configured",
+            id
+        );
+    }
+
+    Ok(Negotiated(CouponView::new(coupon, session.0.id, now)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, list, redeem]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn coupons_take_the_booklet_expiry_unless_they_set_their_own() {
+        let soon = Utc::now() + Duration::days(7);
+        let later = Utc::now() + Duration::days(30);
+        let booklet = BookletRequest {
+            booklet: " Birthday ".to_string(),
+            expires_at: Some(later),
+            coupons: vec![
+                CouponRequest {
+                    title: "Breakfast in bed".to_string(),
+                    description: Some("  ".to_string()),
+                    expires_at: None,
+                },
+                CouponRequest {
+                    title: "A movie of your choice".to_string(),
+                    description: None,
+                    expires_at: Some(soon),
+                },
+            ],
+        };
+        let coupons = booklet.validate().unwrap();
+        assert_eq!(coupons[0].booklet, "Birthday");
+        assert_eq!(coupons[0].description, None);
+        assert_eq!(coupons[0].expires_at, Some(later));
+        assert_eq!(coupons[1].expires_at, Some(soon));
+
+        let expired = BookletRequest {
+            booklet: "Old".to_string(),
+            expires_at: None,
+            coupons: vec![CouponRequest {
+                title: " ".to_string(),
+                description: None,
+                expires_at: Some(Utc::now() - Duration::days(1)),
+            }],
+        };
+        let errors = expired.validate().unwrap_err().to_string();
+        assert!(errors.contains("`title`"), "{}", errors);
+        assert!(
+            errors.contains("`expires_at` must be in the future"),
+            "{}",
+            errors
+        );
+    }
+}
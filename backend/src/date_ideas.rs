@@ -0,0 +1,481 @@
+//! Date ideas picked to suit the Feb 14 forecast for where the couple is:
+//! outdoor plans for a dry, mild day, indoor ones otherwise. Forecasts come
+//! from a [`WeatherProvider`] (Open-Meteo by default) and are cached per
+//! location for an hour.
+
+use std::time::Duration;
+
+use chrono::{Datelike, Days, NaiveDate, Utc};
+use moka::future::Cache;
+use reqwest::Url;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::dates::next_occurrence;
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::providers::{self, MockProvider};
+use crate::resilience::{Resilience, ResilientClient};
+
+const DEFAULT_FORECAST_URL: &str = "https://api.open-meteo.com/v1/forecast";
+
+/// Days ahead Open-Meteo forecasts, today included.
+const FORECAST_DAYS: u64 = 16;
+
+const FORECAST_TTL: Duration = Duration::from_secs(60 * 60);
+const MAX_CACHED_LOCATIONS: u64 = 10_000;
+
+/// Locations are cached to two decimal places, about a kilometre, which is
+/// finer than any forecast grid.
+const GRID: f64 = 100.0;
+
+/// Chance of rain, in percent, from which a day counts as wet.
+const WET_PERCENT: u8 = 40;
+/// Warmest temperature, in °C, below which a day is too cold to stay out.
+const COLD_CELSIUS: f64 = 8.0;
+
+/// The day's weather at one location.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct Forecast {
+    pub date: NaiveDate,
+    /// WMO weather interpretation code.
+    pub weather_code: u8,
+    pub summary: &'static str,
+    pub temperature_max_c: Option<f64>,
+    pub temperature_min_c: Option<f64>,
+    pub precipitation_probability: Option<u8>,
+}
+
+/// A source of daily forecasts.
+#[rocket::async_trait]
+pub trait WeatherProvider: Send + Sync {
+    /// The forecast for `date` at (`lat`, `lon`), or `None` when `date` is
+    /// too far off to forecast yet.
+    async fn forecast(
+        &self,
+        lat: f64,
+        lon: f64,
+        date: NaiveDate,
+    ) -> Result<Option<Forecast>, String>;
+}
+
+/// Describes a WMO code as used by Open-Meteo.
+fn summary(code: u8) -> &'static str {
+    match code {
+        0 => "clear sky",
+        1 | 2 => "partly cloudy",
+        3 => "overcast",
+        45 | 48 => "fog",
+        51..=57 => "drizzle",
+        61..=67 => "rain",
+        71..=77 => "snow",
+        80..=82 => "rain showers",
+        85 | 86 => "snow showers",
+        95..=99 => "thunderstorm",
+        _ => "unknown",
+    }
+}
+
+/// The `[default.weather]` table in Rocket.toml. Open-Meteo needs no key, so
+/// the table only matters to point at a self-hosted instance.
+#[derive(Debug, Deserialize)]
+struct WeatherConfig {
+    #[serde(default = "default_forecast_url")]
+    forecast_url: String,
+}
+
+fn default_forecast_url() -> String {
+    DEFAULT_FORECAST_URL.to_string()
+}
+
+#[derive(Deserialize)]
+struct OpenMeteoResponse {
+    daily: OpenMeteoDaily,
+}
+
+#[derive(Deserialize)]
+struct OpenMeteoDaily {
+    weather_code: Vec<Option<u8>>,
+    temperature_2m_max: Vec<Option<f64>>,
+    temperature_2m_min: Vec<Option<f64>>,
+    precipitation_probability_max: Vec<Option<u8>>,
+}
+
+pub struct OpenMeteo {
+    url: Url,
+    client: ResilientClient,
+}
+
+impl OpenMeteo {
+    fn new(config: WeatherConfig, client: ResilientClient) -> Result<Self, String> {
+        let url = Url::parse(&config.forecast_url)
+            .map_err(|e| format!("invalid weather.forecast_url: {}", e))?;
+        Ok(OpenMeteo { url, client })
+    }
+}
+
+#[rocket::async_trait]
+impl WeatherProvider for OpenMeteo {
+    async fn forecast(
+        &self,
+        lat: f64,
+        lon: f64,
+        date: NaiveDate,
+    ) -> Result<Option<Forecast>, String> {
+        let horizon = Utc::now().date_naive() + Days::new(FORECAST_DAYS - 1);
+        if date > horizon {
+            return Ok(None);
+        }
+
+        let day = date.to_string();
+        let request = self.client.get(self.url.clone()).query(&[
+            ("latitude", lat.to_string()),
+            ("longitude", lon.to_string()),
+            (
+                "daily",
+                "weather_code,temperature_2m_max,temperature_2m_min,\
+                     precipitation_probability_max"
+                    .to_string(),
+            ),
+            ("timezone", "auto".to_string()),
+            ("start_date", day.clone()),
+            ("end_date", day),
+        ]);
+        let response = self.client.send(request).await?;
+        if !response.status().is_success() {
+            return Err(format!("open-meteo responded {}", response.status()));
+        }
+        let daily = response
+            .json::<OpenMeteoResponse>()
+            .await
+            .map_err(|e| e.to_string())?
+            .daily;
+
+        let Some(weather_code) = daily.weather_code.first().copied().flatten() else {
+            return Ok(None);
+        };
+        Ok(Some(Forecast {
+            date,
+            weather_code,
+            summary: summary(weather_code),
+            temperature_max_c: daily.temperature_2m_max.first().copied().flatten(),
+            temperature_min_c: daily.temperature_2m_min.first().copied().flatten(),
+            precipitation_probability: daily
+                .precipitation_probability_max
+                .first()
+                .copied()
+                .flatten(),
+        }))
+    }
+}
+
+/// The managed provider with an hour-long cache in front of it. Failures
+/// are not cached, so the next request tries again.
+pub struct Forecasts {
+    provider: Box<dyn WeatherProvider>,
+    cache: Cache<(i64, i64, NaiveDate), Option<Forecast>>,
+}
+
+impl Forecasts {
+    pub fn new(provider: Box<dyn WeatherProvider>) -> Self {
+        Forecasts {
+            provider,
+            cache: Cache::builder()
+                .max_capacity(MAX_CACHED_LOCATIONS)
+                .time_to_live(FORECAST_TTL)
+                .build(),
+        }
+    }
+
+    async fn get(&self, lat: f64, lon: f64, date: NaiveDate) -> Result<Option<Forecast>, String> {
+        let (lat, lon) = ((lat * GRID).round(), (lon * GRID).round());
+        let key = (lat as i64, lon as i64, date);
+        if let Some(forecast) = self.cache.get(&key).await {
+            return Ok(forecast);
+        }
+        let forecast = self.provider.forecast(lat / GRID, lon / GRID, date).await?;
+        self.cache.insert(key, forecast.clone()).await;
+        Ok(forecast)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Setting {
+    Outdoor,
+    Indoor,
+    /// No forecast to go on, so ideas of both kinds.
+    Either,
+}
+
+/// Where to spend the day, and why.
+fn setting(forecast: Option<&Forecast>) -> (Setting, String) {
+    let Some(forecast) = forecast else {
+        return (
+            Setting::Either,
+            "no forecast for Feb 14 yet; pick whichever suits the day".to_string(),
+        );
+    };
+    // Drizzle and worse, per the WMO codes.
+    if forecast.weather_code >= 51 {
+        return (Setting::Indoor, format!("{} expected", forecast.summary));
+    }
+    if let Some(chance) = forecast
+        .precipitation_probability
+        .filter(|&p| p >= WET_PERCENT)
+    {
+        return (Setting::Indoor, format!("{}% chance of rain", chance));
+    }
+    if let Some(high) = forecast.temperature_max_c.filter(|&t| t < COLD_CELSIUS) {
+        return (Setting::Indoor, format!("a high of only {:.0}°C", high));
+    }
+    (
+        Setting::Outdoor,
+        match forecast.temperature_max_c {
+            Some(high) => format!("{}, up to {:.0}°C", forecast.summary, high),
+            None => forecast.summary.to_string(),
+        },
+    )
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct Idea {
+    title: &'static str,
+    description: &'static str,
+    setting: Setting,
+}
+
+const OUTDOOR_IDEAS: &[(&str, &str)] = &[
+    (
+        "Picnic in the park",
+        "Pack a blanket, something sweet and a thermos of something warm.",
+    ),
+    (
+        "Sunset walk",
+        "Find the best view in town and get there an hour before sunset.",
+    ),
+    (
+        "Botanical garden",
+        "Wander the glasshouses and outdoor beds, then find a bench for two.",
+    ),
+    (
+        "Bike ride",
+        "Rent a pair of bikes and ride to a café you have never tried.",
+    ),
+    (
+        "Stargazing",
+        "Drive away from the city lights with blankets and a star map app.",
+    ),
+    (
+        "Outdoor market",
+        "Browse a farmers' or flea market and pick a small gift for each other.",
+    ),
+];
+
+const INDOOR_IDEAS: &[(&str, &str)] = &[
+    (
+        "Cook together",
+        "Choose a recipe neither of you has made and shop for it together.",
+    ),
+    (
+        "Museum date",
+        "Pick one gallery, then trade favourites over coffee afterwards.",
+    ),
+    (
+        "Movie night in",
+        "A blanket fort, a double feature and homemade popcorn.",
+    ),
+    (
+        "Pottery or painting class",
+        "Make something together to keep on the shelf.",
+    ),
+    (
+        "Board game café",
+        "Try a cooperative game so you are on the same team.",
+    ),
+    (
+        "Spa evening",
+        "Face masks, candles and a playlist from the music page.",
+    ),
+];
+
+fn ideas(setting: Setting) -> Vec<Idea> {
+    let pick = |list: &'static [(&'static str, &'static str)], setting| {
+        list.iter().map(move |&(title, description)| Idea {
+            title,
+            description,
+            setting,
+        })
+    };
+    match setting {
+        Setting::Outdoor => pick(OUTDOOR_IDEAS, Setting::Outdoor).collect(),
+        Setting::Indoor => pick(INDOOR_IDEAS, Setting::Indoor).collect(),
+        Setting::Either => pick(OUTDOOR_IDEAS, Setting::Outdoor)
+            .take(3)
+            .chain(pick(INDOOR_IDEAS, Setting::Indoor).take(3))
+            .collect(),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct DateIdeas {
+    /// The next Feb 14, today included.
+    date: NaiveDate,
+    /// `None` when Feb 14 is more than 16 days off or the provider failed.
+    forecast: Option<Forecast>,
+    setting: Setting,
+    reason: String,
+    ideas: Vec<Idea>,
+}
+
+fn next_valentines_day(today: NaiveDate) -> NaiveDate {
+    let valentines =
+        NaiveDate::from_ymd_opt(today.year(), 2, 14).expect("Feb 14 exists every year");
+    next_occurrence(valentines, true, today).expect("recurring dates always come around")
+}
+
+/// Date ideas for the next Feb 14 at (`lat`, `lon`), chosen by its forecast.
+/// If the forecast is unavailable the ideas are a mix of both kinds.
+#[utoipa::path(
+    tag = "dates",
+    params(
+        ("lat" = f64, Query, minimum = -90, maximum = 90),
+        ("lon" = f64, Query, minimum = -180, maximum = 180),
+    ),
+    responses(
+        (status = 200, body = DateIdeas),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+#[get("/api/date-ideas?<lat>&<lon>")]
+async fn date_ideas(
+    forecasts: &State<Forecasts>,
+    lat: f64,
+    lon: f64,
+) -> ApiResult<Negotiated<DateIdeas>> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(error(
+            Status::BadRequest,
+            "`lat` must be within -90..90 and `lon` within -180..180",
+        ));
+    }
+
+    let date = next_valentines_day(Utc::now().date_naive());
+    let (forecast, reason) = match forecasts.get(lat, lon, date).await {
+        Ok(forecast) => (forecast, None),
+        Err(e) => {
+            warn!("weather forecast unavailable: {}", e);
+            (
+                None,
+                Some("the forecast is unavailable right now".to_string()),
+            )
+        }
+    };
+    let (setting, default_reason) = setting(forecast.as_ref());
+    let reason = reason.unwrap_or(default_reason);
+    Ok(Negotiated(DateIdeas {
+        date,
+        ideas: ideas(setting),
+        forecast,
+        setting,
+        reason,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![date_ideas]
+}
+
+/// Manages [`Forecasts`] backed by Open-Meteo, configured by the optional
+/// `weather` table, or by the mock provider in mock mode. Must be attached
+/// after the providers and resilience stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Weather", |rocket| async {
+        if let Some(log) = providers::mock_log(&rocket) {
+            return Ok(rocket.manage(Forecasts::new(Box::new(MockProvider::new(log)))));
+        }
+        let config = match rocket.figment().extract_inner::<WeatherConfig>("weather") {
+            Ok(config) => config,
+            Err(e) if e.missing() => WeatherConfig {
+                forecast_url: default_forecast_url(),
+            },
+            Err(e) => {
+                error!("invalid weather config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let Some(client) = rocket.state::<Resilience>().map(|r| r.client("weather")) else {
+            error!("weather stage attached before the resilience stage");
+            return Err(rocket);
+        };
+
+        match OpenMeteo::new(config, client) {
+            Ok(provider) => Ok(rocket.manage(Forecasts::new(Box::new(provider)))),
+            Err(e) => {
+                error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct Fixed {
+        forecast: Option<Forecast>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[rocket::async_trait]
+    impl WeatherProvider for Fixed {
+        async fn forecast(&self, _: f64, _: f64, _: NaiveDate) -> Result<Option<Forecast>, String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(self.forecast.clone())
+        }
+    }
+
+    fn day(weather_code: u8, high: f64, rain: u8) -> Forecast {
+        Forecast {
+            date: NaiveDate::from_ymd_opt(2027, 2, 14).unwrap(),
+            weather_code,
+            summary: summary(weather_code),
+            temperature_max_c: Some(high),
+            temperature_min_c: None,
+            precipitation_probability: Some(rain),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn forecasts_pick_the_setting_and_are_cached() {
+        assert_eq!(setting(Some(&day(1, 16.0, 10))).0, Setting::Outdoor);
+        assert_eq!(setting(Some(&day(63, 16.0, 90))).0, Setting::Indoor);
+        assert_eq!(setting(Some(&day(3, 16.0, 60))).0, Setting::Indoor);
+        assert_eq!(setting(Some(&day(0, 2.0, 0))).0, Setting::Indoor);
+        assert_eq!(setting(None).0, Setting::Either);
+        assert!(ideas(Setting::Either)
+            .iter()
+            .any(|idea| idea.setting == Setting::Indoor));
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let forecasts = Forecasts::new(Box::new(Fixed {
+            forecast: Some(day(0, 14.0, 0)),
+            calls: calls.clone(),
+        }));
+        let date = next_valentines_day(NaiveDate::from_ymd_opt(2026, 10, 14).unwrap());
+        assert_eq!(date, NaiveDate::from_ymd_opt(2027, 2, 14).unwrap());
+        forecasts.get(48.8566, 2.3522, date).await.unwrap();
+        // A few metres away lands in the same cache cell.
+        let cached = forecasts.get(48.8567, 2.3521, date).await.unwrap();
+        assert_eq!(cached, Some(day(0, 14.0, 0)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        forecasts.get(51.5072, -0.1276, date).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
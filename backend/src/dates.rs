@@ -0,0 +1,354 @@
+use chrono::{Datelike, NaiveDate};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::countdown::parse_tz;
+use crate::determinism::Now;
+use crate::email;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{DateKind, ImportantDate, NewImportantDate, Reminders, Storage};
+use crate::timezones::saved_zone;
+use crate::users::{CoupleScope, Session};
+use crate::validation::{FieldErrors, Valid, Validate};
+
+pub const MAX_TITLE_LEN: usize = 100;
+
+/// Most lead times a date may have, and the longest one.
+const MAX_REMINDERS: usize = 5;
+const MAX_LEAD_DAYS: i64 = 365;
+
+/// Window of `GET /api/dates/upcoming` when `days` is not given, and the
+/// largest one accepted.
+const DEFAULT_WINDOW_DAYS: i64 = 30;
+const MAX_WINDOW_DAYS: i64 = 366;
+
+/// `month`/`day` in `year`, with Feb 29 falling back to Feb 28 in common
+/// years so leap-day dates still come around every year.
+fn in_year(date: NaiveDate, year: i32) -> NaiveDate {
+    date.with_year(year)
+        .or_else(|| NaiveDate::from_ymd_opt(year, 2, 28))
+        .expect("Feb 28 exists every year")
+}
+
+/// The first time on or after `today` that `date` happens, if it does again.
+pub fn next_occurrence(date: NaiveDate, recurring: bool, today: NaiveDate) -> Option<NaiveDate> {
+    if date >= today {
+        return Some(date);
+    }
+    if !recurring {
+        return None;
+    }
+
+    let this_year = in_year(date, today.year());
+    Some(if this_year >= today {
+        this_year
+    } else {
+        in_year(date, today.year() + 1)
+    })
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct DateRequest {
+    title: String,
+    kind: DateKind,
+    date: NaiveDate,
+    /// Defaults to true: the date comes back every year.
+    recurring: Option<bool>,
+    #[serde(default)]
+    reminders: Reminders,
+}
+
+impl Validate for Reminders {
+    type Valid = Reminders;
+
+    /// Dedupes and sorts lead times (longest first) and checks the address.
+    fn validate(self) -> Result<Reminders, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let mut days = self.days;
+        days.sort_unstable_by(|a, b| b.cmp(a));
+        days.dedup();
+        if days.len() > MAX_REMINDERS {
+            errors.add(
+                "days",
+                format!(
+                    "`reminders.days` may have at most {} entries",
+                    MAX_REMINDERS
+                ),
+            );
+        }
+        if days.iter().any(|d| !(0..=MAX_LEAD_DAYS).contains(d)) {
+            errors.add(
+                "days",
+                format!(
+                    "`reminders.days` entries must be between 0 and {}",
+                    MAX_LEAD_DAYS
+                ),
+            );
+        }
+
+        let email = self
+            .email
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty());
+        if let Some(address) = &email {
+            if !email::is_valid_address(address) {
+                errors.add(
+                    "email",
+                    format!("`reminders.email` is not a valid address: {}", address),
+                );
+            }
+        }
+
+        errors.finish(Reminders { days, email })
+    }
+}
+
+impl Validate for DateRequest {
+    type Valid = NewImportantDate;
+
+    fn validate(self) -> Result<NewImportantDate, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let title = self.title.trim().to_string();
+        errors.text("title", &title, MAX_TITLE_LEN);
+        let reminders = errors
+            .nested("reminders", self.reminders)
+            .unwrap_or_default();
+
+        errors.finish(NewImportantDate {
+            title,
+            kind: self.kind,
+            date: self.date,
+            recurring: self.recurring.unwrap_or(true),
+            reminders,
+            couple_id: None,
+        })
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct UpcomingDate {
+    #[serde(flatten)]
+    pub date: ImportantDate,
+    pub next_date: NaiveDate,
+    pub days_remaining: i64,
+    /// Which anniversary `next_date` is, e.g. 3 for the third year. Only set
+    /// for recurring dates after their first occurrence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub years: Option<i32>,
+}
+
+impl UpcomingDate {
+    /// `date`'s next occurrence as seen from `today`, if it has one.
+    pub fn next(date: ImportantDate, today: NaiveDate) -> Option<Self> {
+        let next_date = next_occurrence(date.date, date.recurring, today)?;
+        let years = next_date.year() - date.date.year();
+        Some(UpcomingDate {
+            next_date,
+            days_remaining: (next_date - today).num_days(),
+            years: (date.recurring && years > 0).then_some(years),
+            date,
+        })
+    }
+}
+
+#[utoipa::path(
+    tag = "dates",
+    request_body = DateRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = ImportantDate),
+        (status = 401, body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/dates", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    request: Valid<DateRequest>,
+) -> ApiResult<status::Created<Negotiated<ImportantDate>>> {
+    let mut date = request.into_inner();
+    date.couple_id = scope.0;
+    let date = storage.create_date(&date).await.map_err(internal_error)?;
+
+    let location = uri!(get(date.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(date)))
+}
+
+/// Dates happening within the next `days` days (today included), soonest
+/// first. "Today" is taken in `tz`, by default the signed-in user's saved
+/// zone or UTC.
+#[utoipa::path(
+    tag = "dates",
+    params(
+        ("days" = Option<i64>, Query, description = "Window size, 1 to 366 (default 30)"),
+        ("tz" = Option<String>, Query, description = "IANA timezone, the user's saved zone or UTC by default"),
+    ),
+    responses(
+        (status = 200, body = Vec<UpcomingDate>),
+        (status = 400, description = "Window out of range or unknown timezone", body = ErrorResponse),
+    )
+)]
+#[get("/api/dates/upcoming?<days>&<tz>")]
+async fn upcoming(
+    storage: &Storage,
+    scope: CoupleScope,
+    session: Option<Session>,
+    now: Now,
+    days: Option<i64>,
+    tz: Option<&str>,
+) -> ApiResult<Negotiated<Vec<UpcomingDate>>> {
+    let days = days.unwrap_or(DEFAULT_WINDOW_DAYS);
+    if !(1..=MAX_WINDOW_DAYS).contains(&days) {
+        return Err(error(
+            Status::BadRequest,
+            format!("`days` must be between 1 and {}", MAX_WINDOW_DAYS),
+        ));
+    }
+    let tz = match (tz, session) {
+        (None, Some(session)) => saved_zone(&session.0),
+        (tz, _) => parse_tz(tz)?,
+    };
+    let today = now.0.with_timezone(&tz).date_naive();
+    let until = today + chrono::Duration::days(days - 1);
+
+    let dates = storage
+        .dates_between(scope.0, today, until)
+        .await
+        .map_err(internal_error)?;
+    let mut upcoming: Vec<UpcomingDate> = dates
+        .into_iter()
+        .filter_map(|date| UpcomingDate::next(date, today))
+        .filter(|upcoming| upcoming.next_date <= until)
+        .collect();
+    upcoming.sort_by_key(|u| (u.next_date, u.date.id));
+
+    Ok(Negotiated(upcoming))
+}
+
+#[utoipa::path(
+    tag = "dates",
+    responses(
+        (status = 200, body = ImportantDate),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/dates/<id>")]
+async fn get(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<ImportantDate>> {
+    storage
+        .get_date(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no date with id {}", id)))
+}
+
+/// Replaces the date's reminder settings; `{"days": []}` turns them off.
+#[utoipa::path(
+    tag = "dates",
+    request_body = Reminders,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = ImportantDate),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[put("/api/dates/<id>/reminders", data = "<request>")]
+async fn set_reminders(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+    request: Valid<Reminders>,
+) -> ApiResult<Negotiated<ImportantDate>> {
+    storage
+        .set_reminders(id, scope.0, &request.0)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no date with id {}", id)))
+}
+
+#[utoipa::path(
+    tag = "dates",
+    security(("api_key" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/api/dates/<id>")]
+async fn delete(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<status::NoContent> {
+    match storage
+        .delete_date(id, scope.0)
+        .await
+        .map_err(internal_error)?
+    {
+        true => Ok(status::NoContent),
+        false => Err(error(Status::NotFound, format!("no date with id {}", id))),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, upcoming, get, set_reminders, delete]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn recurring_dates_roll_over_to_next_year() {
+        let today = date("2026-10-14");
+        assert_eq!(
+            next_occurrence(date("2019-12-01"), true, today),
+            Some(date("2026-12-01"))
+        );
+        assert_eq!(
+            next_occurrence(date("2019-03-01"), true, today),
+            Some(date("2027-03-01"))
+        );
+        assert_eq!(
+            next_occurrence(date("2019-10-14"), true, today),
+            Some(today)
+        );
+        assert_eq!(next_occurrence(date("2026-03-01"), false, today), None);
+        assert_eq!(
+            next_occurrence(date("2027-05-01"), false, today),
+            Some(date("2027-05-01"))
+        );
+    }
+
+    #[test]
+    fn leap_day_falls_back_to_feb_28() {
+        assert_eq!(
+            next_occurrence(date("2024-02-29"), true, date("2026-01-01")),
+            Some(date("2026-02-28"))
+        );
+        assert_eq!(
+            next_occurrence(date("2024-02-29"), true, date("2027-03-01")),
+            Some(date("2028-02-29"))
+        );
+    }
+}
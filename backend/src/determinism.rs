@@ -0,0 +1,156 @@
+//! Reproducible responses, for load and integration tests. A request that
+//! passes `?seed=` or an `X-Seed` header gets the same random picks every
+//! time (the quote served, a game, letter or poem), and the `freeze_time`
+//! setting pins the clock that "today", countdowns and streaks are worked
+//! out from. Tokens, salts and keys always come from the OS and are never
+//! seeded; stored timestamps always use the real time.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+pub const SEED_HEADER: &str = "X-Seed";
+
+/// Where the server gets the time from.
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always the same instant.
+pub struct FrozenClock(pub DateTime<Utc>);
+
+impl Clock for FrozenClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Managed handle on the server's [`Clock`].
+#[derive(Clone)]
+pub struct AppClock(Arc<dyn Clock>);
+
+impl AppClock {
+    pub fn new(clock: impl Clock) -> Self {
+        AppClock(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.0.now()
+    }
+}
+
+/// Request guard: the current time by the server's clock.
+#[derive(Debug, Clone, Copy)]
+pub struct Now(pub DateTime<Utc>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Now {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let now = match request.rocket().state::<AppClock>() {
+            Some(clock) => clock.now(),
+            None => Utc::now(),
+        };
+        Outcome::Success(Now(now))
+    }
+}
+
+/// Request guard: randomness for what a response picks, seeded from
+/// `?seed=` or [`SEED_HEADER`] when the request has one. Rejects a seed
+/// that is not an unsigned integer with a 400.
+pub struct RequestRng(StdRng);
+
+impl RequestRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        RequestRng(match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        })
+    }
+}
+
+impl RngCore for RequestRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestRng {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let seed = request
+            .query_value::<&str>("seed")
+            .and_then(Result::ok)
+            .or_else(|| request.headers().get_one(SEED_HEADER));
+        match seed.map(str::parse).transpose() {
+            Ok(seed) => Outcome::Success(RequestRng::new(seed)),
+            Err(_) => Outcome::Error((Status::BadRequest, ())),
+        }
+    }
+}
+
+/// Manages the [`AppClock`]: frozen at the `freeze_time` setting (RFC 3339)
+/// when there is one, the system clock otherwise.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Clock", |rocket| async {
+        let clock = match rocket
+            .figment()
+            .extract_inner::<DateTime<Utc>>("freeze_time")
+        {
+            Ok(frozen) => {
+                warn!("the clock is frozen at {}", frozen);
+                AppClock::new(FrozenClock(frozen))
+            }
+            Err(e) if e.missing() => AppClock::new(SystemClock),
+            Err(e) => {
+                error!("invalid freeze_time: {}", e);
+                return Err(rocket);
+            }
+        };
+        Ok(rocket.manage(clock))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn seeded_requests_pick_the_same_every_time() {
+        let picks = |seed| {
+            let mut rng = RequestRng::new(seed);
+            (0..8).map(|_| rng.gen_range(0..1000)).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(Some(14)), picks(Some(14)));
+        assert_ne!(picks(Some(14)), picks(Some(15)));
+    }
+}
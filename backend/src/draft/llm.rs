@@ -0,0 +1,176 @@
+//! Language model providers for drafting. Each turns a prompt into text and
+//! reports the tokens it used, which the `draft` module counts against its
+//! daily budget.
+
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::resilience::ResilientClient;
+
+/// What the model is asked: `system` sets it up, `user` is the request.
+#[derive(Debug, Clone, Serialize)]
+pub struct Prompt {
+    pub system: String,
+    pub user: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub text: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+/// Anything that can write a draft.
+#[rocket::async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Identifies the provider in logs, e.g. `http (gpt-4o-mini)`.
+    fn name(&self) -> String;
+
+    /// Completes `prompt` in at most `max_tokens` tokens.
+    async fn complete(&self, prompt: &Prompt, max_tokens: u32) -> Result<Completion, String>;
+}
+
+/// The `[default.draft.http]` table in Rocket.toml: any endpoint speaking
+/// the OpenAI `chat/completions` shape (OpenAI itself, or a self-hosted
+/// server such as Ollama, llama.cpp or vLLM).
+#[derive(Debug, Deserialize)]
+pub struct HttpConfig {
+    /// e.g. `https://api.openai.com/v1/chat/completions`.
+    pub endpoint: String,
+    /// Sent as a bearer token when set.
+    pub api_key: Option<String>,
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+}
+
+fn default_model() -> String {
+    "gpt-4o-mini".to_string()
+}
+
+fn default_temperature() -> f32 {
+    0.8
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: [ChatMessage<'a>; 2],
+    max_tokens: u32,
+    temperature: f32,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+pub struct HttpProvider {
+    config: HttpConfig,
+    endpoint: Url,
+    client: ResilientClient,
+}
+
+impl HttpProvider {
+    pub fn new(config: HttpConfig, client: ResilientClient) -> Result<Self, String> {
+        let endpoint = Url::parse(&config.endpoint)
+            .map_err(|e| format!("invalid draft.http.endpoint: {}", e))?;
+        Ok(HttpProvider {
+            config,
+            endpoint,
+            client,
+        })
+    }
+}
+
+/// A rough count for providers that do not report usage: about four
+/// characters a token, as for English text.
+pub fn estimate_tokens(text: &str) -> u64 {
+    (text.chars().count() as u64).div_ceil(4)
+}
+
+#[rocket::async_trait]
+impl LlmProvider for HttpProvider {
+    fn name(&self) -> String {
+        format!("http ({})", self.config.model)
+    }
+
+    async fn complete(&self, prompt: &Prompt, max_tokens: u32) -> Result<Completion, String> {
+        let mut request = self.client.post(self.endpoint.clone()).json(&ChatRequest {
+            model: &self.config.model,
+            messages: [
+                ChatMessage {
+                    role: "system",
+                    content: &prompt.system,
+                },
+                ChatMessage {
+                    role: "user",
+                    content: &prompt.user,
+                },
+            ],
+            max_tokens,
+            temperature: self.config.temperature,
+        });
+        if let Some(key) = &self.config.api_key {
+            request = request.bearer_auth(key);
+        }
+
+        let response = self.client.send(request).await?;
+        let status = response.status();
+        if status != StatusCode::OK {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!(
+                "{} responded {}: {}",
+                self.endpoint,
+                status,
+                body.chars().take(200).collect::<String>()
+            ));
+        }
+        let response: ChatResponse = response.json().await.map_err(|e| e.to_string())?;
+        let text = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+            .ok_or_else(|| format!("{} returned no text", self.endpoint))?;
+        let (prompt_tokens, completion_tokens) = match response.usage {
+            Some(usage) => (usage.prompt_tokens, usage.completion_tokens),
+            None => (
+                estimate_tokens(&prompt.system) + estimate_tokens(&prompt.user),
+                estimate_tokens(&text),
+            ),
+        };
+        Ok(Completion {
+            text,
+            prompt_tokens,
+            completion_tokens,
+        })
+    }
+}
@@ -0,0 +1,402 @@
+//! Drafting a valentine with a language model. `POST /api/draft` takes a
+//! few points about the relationship and a tone, fills in that tone's
+//! prompt template and returns what the configured provider writes, for
+//! the sender to edit before sending. Identical requests are answered from
+//! the query cache for `cache.ttl.draft` seconds; each draft is capped at
+//! `max_tokens`, and once the day's `daily_token_budget` is spent drafting
+//! is refused until midnight UTC.
+
+pub mod llm;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{NaiveDate, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::ApiKey;
+use crate::cache::QueryCache;
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::letter::Tone;
+use crate::messages::{self, Vars};
+use crate::negotiate::Negotiated;
+use crate::providers::{self, MockProvider};
+use crate::resilience::Resilience;
+use crate::validation::{FieldErrors, Valid, Validate};
+use llm::{HttpConfig, HttpProvider, LlmProvider, Prompt};
+
+const MAX_POINTS: usize = 10;
+const MAX_POINT_LEN: usize = 200;
+
+const SYSTEM_PROMPT: &str = "You help people write valentine messages. Write one message \
+     of at most 120 words, in the first person, to the recipient, using only the details \
+     you are given. Reply with the message alone: no title, notes or quotation marks.";
+
+/// The built-in prompt for `tone`. `{to}`, `{from}` and `{points}` (one
+/// `- ` line per point) are filled in.
+fn default_template(tone: Tone) -> &'static str {
+    match tone {
+        Tone::Sappy => {
+            "Write a gushing, heartfelt valentine to {to} from {from}, \
+             built on these things about us:\n{points}"
+        }
+        Tone::Playful => {
+            "Write a teasing, playful valentine to {to} from {from}, with a joke or a pun, \
+             built on these things about us:\n{points}"
+        }
+        Tone::Poetic => {
+            "Write a poetic valentine to {to} from {from}, rich in imagery of stars, \
+             seasons and the sea, built on these things about us:\n{points}"
+        }
+        Tone::Classic => {
+            "Write a warm but restrained valentine to {to} from {from}, like a handwritten \
+             note, built on these things about us:\n{points}"
+        }
+    }
+}
+
+/// The `[default.draft]` table in Rocket.toml. Drafting is enabled by the
+/// `http` sub-table, or by mock mode.
+#[derive(Debug, Deserialize)]
+struct DraftConfig {
+    #[serde(default = "default_max_tokens")]
+    max_tokens: u32,
+    #[serde(default = "default_daily_token_budget")]
+    daily_token_budget: u64,
+    /// Replaces the built-in template of each tone named.
+    #[serde(default)]
+    prompts: HashMap<String, String>,
+    http: Option<HttpConfig>,
+}
+
+fn default_max_tokens() -> u32 {
+    300
+}
+
+fn default_daily_token_budget() -> u64 {
+    200_000
+}
+
+/// Tokens used on one UTC day.
+struct Spent {
+    day: NaiveDate,
+    tokens: u64,
+}
+
+/// The configured provider, if any, the templates and the limits.
+pub struct Drafting {
+    provider: Option<Box<dyn LlmProvider>>,
+    templates: HashMap<Tone, String>,
+    max_tokens: u32,
+    daily_token_budget: u64,
+    spent: Mutex<Spent>,
+}
+
+impl Drafting {
+    fn provider(&self) -> ApiResult<&dyn LlmProvider> {
+        self.provider
+            .as_deref()
+            .ok_or_else(|| error(Status::ServiceUnavailable, "drafting is not configured"))
+    }
+
+    /// Tokens used so far on `today`.
+    fn spent_on(&self, today: NaiveDate) -> u64 {
+        let mut spent = self.spent.lock().expect("draft budget lock poisoned");
+        if spent.day != today {
+            *spent = Spent {
+                day: today,
+                tokens: 0,
+            };
+        }
+        spent.tokens
+    }
+
+    fn spend(&self, today: NaiveDate, tokens: u64) {
+        self.spent_on(today);
+        self.spent
+            .lock()
+            .expect("draft budget lock poisoned")
+            .tokens += tokens;
+    }
+
+    fn prompt(&self, request: &DraftRequest) -> Prompt {
+        let points: Vec<String> = request
+            .points
+            .iter()
+            .map(|point| format!("- {}", point))
+            .collect();
+        let points = points.join("\n");
+        let vars = Vars::new()
+            .with("to", request.to.as_deref().unwrap_or("my valentine"))
+            .with("from", request.from.as_deref().unwrap_or("me"))
+            .with("points", &points);
+        Prompt {
+            system: SYSTEM_PROMPT.to_string(),
+            user: messages::render(&self.templates[&request.tone], &vars),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+struct DraftRequest {
+    /// Things about the relationship, e.g. "met at the bookshop on Elm St".
+    points: Vec<String>,
+    #[serde(default = "default_tone")]
+    tone: Tone,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+fn default_tone() -> Tone {
+    Tone::Classic
+}
+
+impl Validate for DraftRequest {
+    /// The request with points trimmed and names sanitized.
+    type Valid = DraftRequest;
+
+    fn validate(self) -> Result<DraftRequest, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|point| point.trim().to_string())
+            .collect();
+        if points.is_empty() || points.len() > MAX_POINTS {
+            errors.add(
+                "points",
+                format!("`points` must have between 1 and {} entries", MAX_POINTS),
+            );
+        }
+        for (i, point) in points.iter().enumerate() {
+            errors.text(&format!("points[{}]", i), point, MAX_POINT_LEN);
+        }
+        let from = self
+            .from
+            .map(|from| errors.check("from", messages::sanitize_name(&from)));
+        let to = self
+            .to
+            .map(|to| errors.check("to", messages::sanitize_name(&to)));
+
+        errors.finish(DraftRequest {
+            points,
+            tone: self.tone,
+            from: from.flatten(),
+            to: to.flatten(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DraftUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Draft {
+    pub message: String,
+    pub tone: Tone,
+    /// The provider that wrote it, e.g. `mock`.
+    pub provider: String,
+    /// What writing it cost; a cached draft repeats the original's.
+    pub usage: DraftUsage,
+}
+
+/// The query cache key for `request`: a digest, so the points are not kept
+/// in the clear as keys.
+fn cache_key(request: &DraftRequest) -> String {
+    let serialized = json::to_string(request).expect("draft requests serialize");
+    Sha256::digest(serialized.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Drafts a valentine from a few points about the relationship. Signed-in
+/// users may call it without a key.
+#[utoipa::path(
+    tag = "messages",
+    request_body = DraftRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = Draft),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "No points, too many, or one too long", body = ErrorResponse),
+        (status = 429, description = "Today's token budget is spent", body = ErrorResponse),
+        (status = 502, description = "The provider failed", body = ErrorResponse),
+        (status = 503, description = "Drafting is not configured", body = ErrorResponse),
+    )
+)]
+#[post("/api/draft", data = "<request>")]
+async fn draft(
+    _key: ApiKey,
+    drafting: &State<Drafting>,
+    cache: &State<QueryCache>,
+    request: Valid<DraftRequest>,
+) -> ApiResult<Negotiated<Draft>> {
+    let provider = drafting.provider()?;
+    let request = request.into_inner();
+
+    let draft = cache.get_or_try_insert("draft", cache_key(&request), async {
+        let today = Utc::now().date_naive();
+        if drafting.spent_on(today) >= drafting.daily_token_budget {
+            return Err(error(
+                Status::TooManyRequests,
+                "today's drafting budget is spent; try again tomorrow",
+            ));
+        }
+
+        let completion = provider
+            .complete(&drafting.prompt(&request), drafting.max_tokens)
+            .await
+            .map_err(|e| {
+                error!("draft via {} failed: {}", provider.name(), e);
+                error(Status::BadGateway, format!("drafting failed: {}", e))
+            })?;
+        drafting.spend(
+            today,
+            completion.prompt_tokens + completion.completion_tokens,
+        );
+        Ok(Draft {
+            message: completion.text,
+            tone: request.tone,
+            provider: provider.name(),
+            usage: DraftUsage {
+                prompt_tokens: completion.prompt_tokens,
+                completion_tokens: completion.completion_tokens,
+            },
+        })
+    });
+    Ok(Negotiated(draft.await?))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![draft]
+}
+
+/// Builds the drafting provider from the optional `draft` config table, or
+/// the mock provider in mock mode, which never calls out even with
+/// `draft.http` set. Must be attached after the providers and resilience
+/// stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Drafting", |rocket| async {
+        let config = match rocket.figment().extract_inner::<DraftConfig>("draft") {
+            Ok(config) => config,
+            Err(e) if e.missing() => DraftConfig {
+                max_tokens: default_max_tokens(),
+                daily_token_budget: default_daily_token_budget(),
+                prompts: HashMap::new(),
+                http: None,
+            },
+            Err(e) => {
+                error!("invalid draft config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let mut templates: HashMap<Tone, String> = Tone::ALL
+            .into_iter()
+            .map(|tone| (tone, default_template(tone).to_string()))
+            .collect();
+        for (tone, template) in config.prompts {
+            match tone.parse::<Tone>() {
+                Ok(tone) => templates.insert(tone, template),
+                Err(e) => {
+                    error!("invalid draft.prompts: {}", e);
+                    return Err(rocket);
+                }
+            };
+        }
+
+        let provider: Option<Box<dyn LlmProvider>> =
+            match (providers::mock_log(&rocket), config.http) {
+                (Some(log), _) => Some(Box::new(MockProvider::new(log))),
+                (None, Some(http)) => {
+                    let Some(client) = rocket.state::<Resilience>().map(|r| r.client("llm")) else {
+                        error!("drafting stage attached before the resilience stage");
+                        return Err(rocket);
+                    };
+                    match HttpProvider::new(http, client) {
+                        Ok(provider) => Some(Box::new(provider)),
+                        Err(e) => {
+                            error!("{}", e);
+                            return Err(rocket);
+                        }
+                    }
+                }
+                (None, None) => {
+                    info!("no drafting provider configured, drafting disabled");
+                    None
+                }
+            };
+
+        Ok(rocket.manage(Drafting {
+            provider,
+            templates,
+            max_tokens: config.max_tokens,
+            daily_token_budget: config.daily_token_budget,
+            spent: Mutex::new(Spent {
+                day: Utc::now().date_naive(),
+                tokens: 0,
+            }),
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts_fill_the_tone_template_and_the_budget_resets_daily() {
+        let drafting = Drafting {
+            provider: None,
+            templates: HashMap::from([(
+                Tone::Playful,
+                "To {to} from {from}:\n{points}".to_string(),
+            )]),
+            max_tokens: 300,
+            daily_token_budget: 1_000,
+            spent: Mutex::new(Spent {
+                day: "2027-02-13".parse().unwrap(),
+                tokens: 0,
+            }),
+        };
+        let request = DraftRequest {
+            points: vec![
+                "  met at the bookshop ".to_string(),
+                "hates cilantro".to_string(),
+            ],
+            tone: Tone::Playful,
+            from: None,
+            to: Some(" Juliet ".to_string()),
+        }
+        .validate()
+        .unwrap();
+        assert_eq!(
+            drafting.prompt(&request).user,
+            "To Juliet from me:\n- met at the bookshop\n- hates cilantro"
+        );
+        assert_eq!(cache_key(&request), cache_key(&request));
+        assert!(DraftRequest {
+            points: vec![],
+            tone: Tone::Classic,
+            from: None,
+            to: None,
+        }
+        .validate()
+        .is_err());
+
+        let valentines: NaiveDate = "2027-02-14".parse().unwrap();
+        drafting.spend(valentines, 700);
+        drafting.spend(valentines, 400);
+        assert_eq!(drafting.spent_on(valentines), 1_100);
+        assert_eq!(drafting.spent_on(valentines.succ_opt().unwrap()), 0);
+    }
+}
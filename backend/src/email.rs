@@ -0,0 +1,430 @@
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::cards::pdf::CardFont;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::messages::escape_html;
+use crate::negotiate::Negotiated;
+use crate::providers::{self, MockLog};
+use crate::storage::NewMessage;
+use crate::tenants::CurrentTenant;
+use crate::themes::{self, Theme, Themes};
+use crate::valentine::ValentineSubmission;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Plain connection upgraded with STARTTLS (port 587).
+    #[default]
+    Starttls,
+    /// Implicit TLS from the first byte (port 465).
+    Tls,
+    /// Unencrypted, for local test servers only.
+    None,
+}
+
+/// The `[default.smtp]` table in Rocket.toml.
+#[derive(Debug, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender mailbox, e.g. `"Valentine <valentine@example.com>"`.
+    pub from: String,
+    #[serde(default)]
+    pub tls: TlsMode,
+}
+
+/// Outgoing mail handle. Disabled when no SMTP server is configured.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Option<Transport>,
+}
+
+#[derive(Clone)]
+enum Transport {
+    Smtp(Box<AsyncSmtpTransport<Tokio1Executor>>, Mailbox),
+    /// Records mail in the log instead of sending it.
+    Mock(MockLog),
+}
+
+#[derive(Serialize)]
+struct MailPayload<'a> {
+    to: String,
+    subject: &'a str,
+    text: &'a str,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct SendReceipt {
+    pub recipient: String,
+    pub smtp_code: String,
+    pub smtp_message: String,
+}
+
+impl Mailer {
+    pub fn disabled() -> Self {
+        Mailer { transport: None }
+    }
+
+    pub fn mock(log: MockLog) -> Self {
+        Mailer {
+            transport: Some(Transport::Mock(log)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.transport.is_some()
+    }
+
+    /// Opens (and closes) a connection to the SMTP server to confirm it is
+    /// reachable and accepts our credentials.
+    pub async fn test_connection(&self) -> Result<(), String> {
+        let transport = match &self.transport {
+            Some(Transport::Smtp(transport, _)) => transport,
+            Some(Transport::Mock(_)) => return Ok(()),
+            None => return Err("email delivery is not configured".to_string()),
+        };
+
+        match transport.test_connection().await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("smtp server rejected the connection".to_string()),
+            Err(e) => Err(format!("smtp server unreachable: {}", e)),
+        }
+    }
+
+    pub fn from_config(config: &SmtpConfig) -> Result<Self, String> {
+        let from: Mailbox = config
+            .from
+            .parse()
+            .map_err(|e| format!("invalid smtp.from address: {}", e))?;
+
+        let builder = match config.tls {
+            TlsMode::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host),
+            TlsMode::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host),
+            TlsMode::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                &config.host,
+            )),
+        };
+        let mut builder = builder.map_err(|e| format!("invalid smtp.host: {}", e))?;
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Mailer {
+            transport: Some(Transport::Smtp(Box::new(builder.build()), from)),
+        })
+    }
+
+    /// Sends `valentine` to `address` as a multipart plain-text/HTML email,
+    /// its HTML part in the colors and font of `theme` if given.
+    pub async fn send_valentine(
+        &self,
+        address: &str,
+        valentine: &NewMessage,
+        theme: Option<&Theme>,
+    ) -> Result<SendReceipt, SendError> {
+        let mut to = parse_address(address)?;
+        if let Some(name) = &valentine.recipient {
+            to.name = Some(name.clone());
+        }
+
+        self.send(
+            to,
+            format!("A valentine from {}", valentine.sender),
+            render_text(valentine),
+            render_html(valentine, &EmailStyle::new(theme)),
+        )
+        .await
+    }
+
+    /// Sends a short notice, e.g. a date reminder. `text` is plain text and
+    /// is escaped for the HTML part.
+    pub async fn send_notice(
+        &self,
+        address: &str,
+        subject: &str,
+        text: &str,
+    ) -> Result<SendReceipt, SendError> {
+        let html = format!(
+            r#"<p style="font-family:Georgia,serif;font-size:18px;color:#c2185b;">{}</p>"#,
+            escape_html(text)
+        );
+        self.send(
+            parse_address(address)?,
+            subject.to_string(),
+            text.to_string(),
+            html,
+        )
+        .await
+    }
+
+    async fn send(
+        &self,
+        to: Mailbox,
+        subject: String,
+        text: String,
+        html: String,
+    ) -> Result<SendReceipt, SendError> {
+        let (transport, from) = match self.transport.as_ref().ok_or(SendError::Disabled)? {
+            Transport::Smtp(transport, from) => (transport, from),
+            Transport::Mock(log) => {
+                log.record(
+                    "email",
+                    &MailPayload {
+                        to: to.to_string(),
+                        subject: &subject,
+                        text: &text,
+                    },
+                );
+                return Ok(SendReceipt {
+                    recipient: to.email.to_string(),
+                    smtp_code: "250".to_string(),
+                    smtp_message: "recorded by the mock provider".to_string(),
+                });
+            }
+        };
+
+        let email = lettre::Message::builder()
+            .from(from.clone())
+            .to(to.clone())
+            .subject(subject)
+            .multipart(MultiPart::alternative_plain_html(text, html))
+            .map_err(|e| SendError::Transport(e.to_string()))?;
+
+        let response = transport
+            .send(email)
+            .await
+            .map_err(|e| SendError::Transport(e.to_string()))?;
+
+        Ok(SendReceipt {
+            recipient: to.email.to_string(),
+            smtp_code: response.code().to_string(),
+            smtp_message: response.message().collect::<Vec<_>>().join(" "),
+        })
+    }
+}
+
+fn parse_address(address: &str) -> Result<Mailbox, SendError> {
+    address
+        .parse()
+        .map_err(|_| SendError::InvalidAddress(address.to_string()))
+}
+
+pub fn is_valid_address(address: &str) -> bool {
+    parse_address(address).is_ok()
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    Disabled,
+    InvalidAddress(String),
+    Transport(String),
+}
+
+impl SendError {
+    fn into_api_error(self) -> crate::error::ApiError {
+        match self {
+            SendError::Disabled => error(
+                Status::ServiceUnavailable,
+                "email delivery is not configured",
+            ),
+            SendError::InvalidAddress(address) => error(
+                Status::UnprocessableEntity,
+                format!("`email` is not a valid address: {}", address),
+            ),
+            SendError::Transport(e) => {
+                error!("smtp delivery failed: {}", e);
+                error(Status::BadGateway, format!("smtp delivery failed: {}", e))
+            }
+        }
+    }
+}
+
+fn render_text(valentine: &NewMessage) -> String {
+    let greeting = valentine.recipient.as_deref().unwrap_or("Hi");
+    format!(
+        "{},\n\n{}\n\nWith love,\n{}\n",
+        greeting, valentine.message, valentine.sender
+    )
+}
+
+/// CSS values for the HTML part of a valentine.
+struct EmailStyle {
+    font: &'static str,
+    page: String,
+    card: String,
+    border: String,
+    title: String,
+    text: String,
+}
+
+impl EmailStyle {
+    fn new(theme: Option<&Theme>) -> Self {
+        let Some(theme) = theme else {
+            return EmailStyle {
+                font: "Georgia,serif",
+                page: "#ffe4ec".to_string(),
+                card: "#fff".to_string(),
+                border: "#ff6b8b".to_string(),
+                title: "#c2185b".to_string(),
+                text: "#333".to_string(),
+            };
+        };
+        let palette = &theme.palette;
+        EmailStyle {
+            font: match theme.font {
+                CardFont::Serif => "Georgia,serif",
+                CardFont::Sans => "Helvetica,Arial,sans-serif",
+            },
+            page: themes::hex(palette.bottom),
+            card: themes::hex(palette.top),
+            border: themes::hex(palette.accent),
+            title: themes::hex(palette.title),
+            text: themes::hex(palette.text),
+        }
+    }
+}
+
+fn render_html(valentine: &NewMessage, style: &EmailStyle) -> String {
+    let greeting = escape_html(valentine.recipient.as_deref().unwrap_or("Hi"));
+    let message = escape_html(&valentine.message).replace('\n', "<br>");
+    let sender = escape_html(&valentine.sender);
+    let EmailStyle {
+        font,
+        page,
+        card,
+        border,
+        title,
+        text,
+    } = style;
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <body style="margin:0;padding:32px;background:{page};font-family:{font};">
+    <div style="max-width:520px;margin:0 auto;padding:32px;background:{card};border-radius:16px;border:2px solid {border};text-align:center;">
+      <div style="font-size:40px;">&#10084;&#65039;</div>
+      <p style="color:{title};font-size:20px;">{greeting},</p>
+      <p style="color:{text};font-size:18px;line-height:1.6;">{message}</p>
+      <p style="color:{title};font-style:italic;">With love,<br>{sender}</p>
+    </div>
+  </body>
+</html>
+"#
+    )
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SendRequest {
+    email: String,
+    /// A theme id from `GET /api/themes` for the HTML part; the tenant's
+    /// theme by default.
+    theme: Option<String>,
+    #[serde(flatten)]
+    valentine: ValentineSubmission,
+}
+
+impl Validate for SendRequest {
+    /// The address, the theme and the message.
+    type Valid = (String, Option<String>, NewMessage);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let email = self.email.trim().to_string();
+        if !is_valid_address(&email) {
+            errors.add(
+                "email",
+                format!("`email` is not a valid address: {}", email),
+            );
+        }
+        match errors.flattened(self.valentine) {
+            Some(valentine) => errors.finish((email, self.theme, valentine)),
+            None => Err(errors),
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "messages",
+    request_body = SendRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = SendReceipt),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field, address or theme, or refused by the content filter", body = ErrorResponse),
+        (status = 502, description = "The SMTP server rejected the message", body = ErrorResponse),
+        (status = 503, description = "SMTP is not configured", body = ErrorResponse),
+    )
+)]
+#[post("/api/valentine/send", data = "<request>")]
+async fn send(
+    _key: ApiKey,
+    mailer: &State<Mailer>,
+    filter: &State<ContentFilter>,
+    themes: &State<Themes>,
+    tenant: CurrentTenant<'_>,
+    request: Valid<SendRequest>,
+) -> ApiResult<Negotiated<SendReceipt>> {
+    let (email, theme, valentine) = request.into_inner();
+    let theme = theme
+        .as_deref()
+        .or(tenant.theme())
+        .map(|theme| themes.get(theme))
+        .transpose()
+        .map_err(|e| error(Status::UnprocessableEntity, e))?;
+    filter.screen_message(&valentine).await?;
+
+    mailer
+        .send_valentine(&email, &valentine, theme.as_deref())
+        .await
+        .map(Negotiated)
+        .map_err(SendError::into_api_error)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![send]
+}
+
+/// Builds the [`Mailer`] from the optional `smtp` config table, or one that
+/// only records mail in mock mode. Must be attached after the providers
+/// stage.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Email", |rocket| async {
+        if let Some(log) = providers::mock_log(&rocket) {
+            return Ok(rocket.manage(Mailer::mock(log)));
+        }
+        let mailer = match rocket.figment().extract_inner::<SmtpConfig>("smtp") {
+            Ok(config) => match Mailer::from_config(&config) {
+                Ok(mailer) => mailer,
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(rocket);
+                }
+            },
+            Err(e) if e.missing() => {
+                info!("no smtp config found, email delivery disabled");
+                Mailer::disabled()
+            }
+            Err(e) => {
+                error!("invalid smtp config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket.manage(mailer))
+    })
+}
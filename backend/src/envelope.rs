@@ -0,0 +1,153 @@
+//! The standard response shape. Successful JSON responses are wrapped as
+//! `{"data": ..., "meta": {...}}` by the [`Envelope`] fairing; errors are
+//! rendered as `{"error": {...}, "meta": {...}}` by [`crate::error::ApiError`].
+//! MessagePack and XML responses have the same shape, the XML one under a
+//! `<response>` root.
+
+use std::io::Cursor;
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::serde::json;
+use rocket::{Request, Response};
+use serde::de::IgnoredAny;
+use serde::Serialize;
+
+use crate::negotiate::{self, Format};
+use crate::telemetry;
+
+/// Paths whose JSON keeps its own format: GraphQL responses follow the
+/// GraphQL spec, the OpenAPI document is read by Swagger UI and exports are
+/// files meant to be imported again as they are.
+pub const RAW_PATHS: &[&str] = &["/graphql", "/api/openapi.json", "/api/export"];
+
+/// Metadata sent with every enveloped response.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct Meta {
+    /// Same as the `X-Request-Id` header; quote it when reporting a problem.
+    request_id: String,
+    timestamp: DateTime<Utc>,
+}
+
+impl Meta {
+    pub fn new(request: &Request<'_>) -> Self {
+        Meta {
+            request_id: telemetry::request_id(request).to_string(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// Set once a response body already has the standard shape.
+struct AlreadyEnveloped(bool);
+
+/// Tells [`Envelope`] to leave this request's response alone.
+pub fn mark_enveloped(request: &Request<'_>) {
+    request.local_cache(|| AlreadyEnveloped(true));
+}
+
+pub fn is_raw_path(path: &str) -> bool {
+    RAW_PATHS.iter().any(|raw| {
+        path.strip_prefix(raw)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// A `format` body wrapped with `meta`, or `None` when a JSON `body` is not
+/// JSON. The body is spliced in as-is so its field order survives; an XML
+/// one is already rooted at `<data>`.
+fn wrap(format: Format, body: &[u8], meta: &Meta) -> Option<Vec<u8>> {
+    let meta = format.encode(meta, "meta").ok()?;
+    let (open, between, close): (&[u8], &[u8], &[u8]) = match format {
+        Format::Json => {
+            json::from_slice::<IgnoredAny>(body).ok()?;
+            (b"{\"data\":", b",\"meta\":", b"}")
+        }
+        // A fixmap of two entries, then the fixstr keys.
+        Format::MessagePack => (b"\x82\xa4data", b"\xa4meta", b""),
+        Format::Xml => (b"<response>", b"", b"</response>"),
+    };
+
+    let mut wrapped = Vec::with_capacity(body.len() + meta.len() + 24);
+    wrapped.extend_from_slice(open);
+    wrapped.extend_from_slice(body);
+    wrapped.extend_from_slice(between);
+    wrapped.extend_from_slice(&meta);
+    wrapped.extend_from_slice(close);
+    Some(wrapped)
+}
+
+/// Wraps every successful JSON response body, and every body a
+/// [`negotiate::Negotiated`] route encoded otherwise, in `{"data", "meta"}`.
+pub struct Envelope;
+
+#[rocket::async_trait]
+impl Fairing for Envelope {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response Envelope",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let format = match negotiate::encoded(request) {
+            Some(format) => format,
+            None if response.content_type() == Some(ContentType::JSON) => Format::Json,
+            None => return,
+        };
+        if request.local_cache(|| AlreadyEnveloped(false)).0
+            || is_raw_path(request.uri().path().as_str())
+        {
+            return;
+        }
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                error!("failed to read response body: {}", e);
+                return;
+            }
+        };
+        let body = wrap(format, &body, &Meta::new(request)).unwrap_or(body);
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_bodies_are_wrapped_and_raw_paths_skipped() {
+        let meta = Meta {
+            request_id: "req-1".to_string(),
+            timestamp: DateTime::from_timestamp(0, 0).unwrap(),
+        };
+        let wrapped = wrap(Format::Json, br#"{"z":1,"a":[]}"#, &meta).unwrap();
+        assert_eq!(
+            String::from_utf8(wrapped).unwrap(),
+            r#"{"data":{"z":1,"a":[]},"meta":{"request_id":"req-1","timestamp":"1970-01-01T00:00:00Z"}}"#
+        );
+        assert_eq!(wrap(Format::Json, b"not json", &meta), None);
+
+        let wrapped = wrap(Format::Xml, b"<data><z>1</z></data>", &meta).unwrap();
+        assert_eq!(
+            String::from_utf8(wrapped).unwrap(),
+            "<response><data><z>1</z></data><meta><request_id>req-1</request_id>\
+             <timestamp>1970-01-01T00:00:00Z</timestamp></meta></response>"
+        );
+        let data = Format::MessagePack.encode(&[1, 2], "data").unwrap();
+        let wrapped = wrap(Format::MessagePack, &data, &meta).unwrap();
+        let decoded: json::Value = rmp_serde::from_slice(&wrapped).unwrap();
+        assert_eq!(decoded["data"], json::json!([1, 2]));
+        assert_eq!(decoded["meta"]["request_id"], "req-1");
+
+        assert!(is_raw_path("/graphql"));
+        assert!(is_raw_path("/graphql/ws"));
+        assert!(is_raw_path("/api/openapi.json"));
+        assert!(!is_raw_path("/graphqlish"));
+        assert!(!is_raw_path("/api/valentine"));
+    }
+}
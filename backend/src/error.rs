@@ -0,0 +1,228 @@
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Value;
+use serde::Serialize;
+
+use crate::envelope::{self, Meta};
+use crate::negotiate::Format;
+
+/// Every error a route returns. Rendered as [`ErrorResponse`] with the
+/// variant's status, whichever API (or catcher) produced it.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    NotFound(String),
+    Conflict(String),
+    UnsupportedMediaType(String),
+    /// Well-formed but refused, e.g. a failed validation. `details` carries
+    /// structured reasons when there is more to say than the message.
+    Unprocessable {
+        message: String,
+        details: Option<Value>,
+    },
+    TooManyRequests(String),
+    /// The cause is logged where it happens; clients only see the message.
+    Internal(String),
+    BadGateway(String),
+    Unavailable(String),
+    /// Statuses without a variant of their own, such as those reaching the
+    /// catcher.
+    Other(Status, String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Unauthorized(_) => Status::Unauthorized,
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::UnsupportedMediaType(_) => Status::UnsupportedMediaType,
+            ApiError::Unprocessable { .. } => Status::UnprocessableEntity,
+            ApiError::TooManyRequests(_) => Status::TooManyRequests,
+            ApiError::Internal(_) => Status::InternalServerError,
+            ApiError::BadGateway(_) => Status::BadGateway,
+            ApiError::Unavailable(_) => Status::ServiceUnavailable,
+            ApiError::Other(status, _) => *status,
+        }
+    }
+
+    /// Stable, machine-readable name for the error's kind.
+    pub fn code(&self) -> String {
+        let code = match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::Unprocessable { .. } => "unprocessable",
+            ApiError::TooManyRequests(_) => "rate_limited",
+            ApiError::Internal(_) => "internal",
+            ApiError::BadGateway(_) => "bad_gateway",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Other(status, _) => {
+                return status
+                    .reason()
+                    .map(|reason| reason.to_lowercase().replace([' ', '-'], "_"))
+                    .unwrap_or_else(|| "error".to_string());
+            }
+        };
+        code.to_string()
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::Unprocessable { message, .. }
+            | ApiError::TooManyRequests(message)
+            | ApiError::Internal(message)
+            | ApiError::BadGateway(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Other(_, message) => message,
+        }
+    }
+
+    pub fn details(&self) -> Option<&Value> {
+        match self {
+            ApiError::Unprocessable { details, .. } => details.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorBody {
+    /// E.g. `not_found` or `unprocessable`.
+    code: String,
+    message: String,
+    /// Structured detail for errors that have it, such as the content
+    /// filter's violations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    details: Option<Value>,
+}
+
+/// The body of every error response.
+#[derive(Serialize, utoipa::ToSchema)]
+pub struct ErrorResponse {
+    error: ErrorBody,
+    meta: Meta,
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let body = ErrorResponse {
+            error: ErrorBody {
+                code: self.code(),
+                message: self.message().to_string(),
+                details: self.details().cloned(),
+            },
+            meta: Meta::new(request),
+        };
+        envelope::mark_enveloped(request);
+
+        Response::build_from(Format::respond(request, &body, "response")?)
+            .status(self.status())
+            .ok()
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// The [`ApiError`] variant for `status`, for call sites that pick the
+/// status themselves.
+pub fn error(status: Status, message: impl Into<String>) -> ApiError {
+    let message = message.into();
+    match status.code {
+        400 => ApiError::BadRequest(message),
+        401 => ApiError::Unauthorized(message),
+        404 => ApiError::NotFound(message),
+        409 => ApiError::Conflict(message),
+        415 => ApiError::UnsupportedMediaType(message),
+        422 => ApiError::Unprocessable {
+            message,
+            details: None,
+        },
+        429 => ApiError::TooManyRequests(message),
+        500 => ApiError::Internal(message),
+        502 => ApiError::BadGateway(message),
+        503 => ApiError::Unavailable(message),
+        _ => ApiError::Other(status, message),
+    }
+}
+
+/// A `422` whose `details` lists why the content was refused.
+pub fn unprocessable(message: impl Into<String>, details: impl Serialize) -> ApiError {
+    ApiError::Unprocessable {
+        message: message.into(),
+        details: rocket::serde::json::to_value(details).ok(),
+    }
+}
+
+/// Logs the underlying database error and hides it from the client.
+pub fn internal_error(e: sqlx::Error) -> ApiError {
+    error!("storage error: {}", e);
+    ApiError::Internal("internal storage error".to_string())
+}
+
+/// Detail left in the request-local cache by a failing request guard, so the
+/// catcher can explain the failure instead of only naming the status.
+#[derive(Default)]
+pub struct GuardError(pub Option<String>);
+
+/// Structured detail to go with a [`GuardError`], such as the fields a
+/// [`Valid`](crate::validation::Valid) body failed on.
+#[derive(Default)]
+pub struct GuardDetails(pub Option<Value>);
+
+/// Also answers for handlers that panicked, so those surface as the usual
+/// `internal` error body rather than an empty 500.
+#[catch(default)]
+fn default_catcher(status: Status, request: &rocket::Request) -> ApiError {
+    let message = request
+        .local_cache(GuardError::default)
+        .0
+        .clone()
+        .unwrap_or_else(|| status.reason_lossy().to_lowercase());
+    match &request.local_cache(GuardDetails::default).0 {
+        Some(details) if status == Status::UnprocessableEntity => ApiError::Unprocessable {
+            message,
+            details: Some(details.clone()),
+        },
+        _ => error(status, message),
+    }
+}
+
+pub fn catchers() -> Vec<rocket::Catcher> {
+    catchers![default_catcher]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_map_to_variants_and_codes() {
+        let e = error(Status::NotFound, "no quote with id 9");
+        assert!(matches!(e, ApiError::NotFound(_)));
+        assert_eq!(
+            (e.status(), e.code().as_str()),
+            (Status::NotFound, "not_found")
+        );
+        assert_eq!(e.message(), "no quote with id 9");
+
+        let e = unprocessable("content not allowed", ["url"]);
+        assert_eq!(e.status(), Status::UnprocessableEntity);
+        assert_eq!(e.details(), Some(&rocket::serde::json::json!(["url"])));
+
+        let e = error(Status::PayloadTooLarge, "too big");
+        assert_eq!(e.status(), Status::PayloadTooLarge);
+        assert_eq!(e.code(), "payload_too_large");
+    }
+}
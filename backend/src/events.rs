@@ -0,0 +1,200 @@
+//! What happened, for the features that react to it. Routes publish a
+//! [`DomainEvent`] once the change is stored and carry on; the notes feed,
+//! webhooks, push, proposal callbacks and live stats each subscribe with
+//! [`subscriber`] and handle it in their own worker, so a slow one never
+//! holds up a request or another subscriber. Each subscriber has its own
+//! unbounded queue: one that falls behind catches up later but skips
+//! nothing. The bus is in memory: other instances learn of an event
+//! through what the subscribers do with it, not from the bus itself.
+
+use std::sync::{Arc, Mutex};
+
+use rocket::fairing::AdHoc;
+use rocket::tokio::select;
+use rocket::tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::storage::{Message, Proposal, Storage};
+use crate::workers::Workers;
+
+#[derive(Debug, Clone)]
+pub enum DomainEvent {
+    MessageCreated {
+        message: Message,
+    },
+    /// `proposal` carries its answer.
+    ProposalAnswered {
+        proposal: Proposal,
+    },
+}
+
+/// An event and the database of the site it happened on.
+#[derive(Clone)]
+pub struct Published {
+    pub storage: Storage,
+    pub event: Arc<DomainEvent>,
+}
+
+/// A feature that reacts to events. Failures are the subscriber's to log;
+/// the route that published the event has already answered.
+#[rocket::async_trait]
+pub trait Subscriber: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, storage: &Storage, event: &DomainEvent);
+}
+
+/// Managed handle routes publish through.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<Published>>>>,
+}
+
+impl EventBus {
+    /// Hands `event` from `storage`'s site to every subscriber. Having
+    /// nobody subscribed is not an error.
+    pub fn publish(&self, storage: &Storage, event: DomainEvent) {
+        let published = Published {
+            storage: storage.clone(),
+            event: Arc::new(event),
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(published.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<Published> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+/// Handles `events` with `subscriber` until `token` is cancelled, then
+/// those already published.
+async fn run_subscriber<S: Subscriber>(
+    subscriber: S,
+    mut events: mpsc::UnboundedReceiver<Published>,
+    token: CancellationToken,
+) {
+    loop {
+        select! {
+            published = events.recv() => match published {
+                Some(published) => subscriber.handle(&published.storage, &published.event).await,
+                None => return,
+            },
+            _ = token.cancelled() => break,
+        }
+    }
+    while let Ok(published) = events.try_recv() {
+        subscriber
+            .handle(&published.storage, &published.event)
+            .await
+    }
+}
+
+/// Manages the [`EventBus`]. Attach it after [`crate::workers::stage`] and
+/// before the stages that subscribe.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Event Bus", |rocket| async {
+        rocket.manage(EventBus::default())
+    })
+}
+
+/// Subscribes `subscriber` to the bus now, so it misses nothing published
+/// from here on, and starts its worker once the server has launched.
+pub fn subscriber<S: Subscriber>(subscriber: S) -> AdHoc {
+    AdHoc::on_ignite(subscriber.name(), |rocket| async {
+        let Some(events) = rocket.state::<EventBus>().map(EventBus::subscribe) else {
+            error!(
+                "{} subscribed before the event bus stage",
+                subscriber.name()
+            );
+            return rocket;
+        };
+        rocket.attach(AdHoc::on_liftoff(subscriber.name(), |rocket| {
+            Box::pin(async move {
+                match rocket.state::<Workers>() {
+                    Some(workers) => workers.spawn(subscriber.name(), |token| {
+                        run_subscriber(subscriber, events, token)
+                    }),
+                    None => error!("{} not started: workers are unavailable", subscriber.name()),
+                }
+            })
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[rocket::async_trait]
+    impl Subscriber for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn handle(&self, _: &Storage, event: &DomainEvent) {
+            let DomainEvent::ProposalAnswered { proposal } = event else {
+                return;
+            };
+            self.0.lock().unwrap().push(proposal.token.clone());
+        }
+    }
+
+    fn answered(token: &str) -> DomainEvent {
+        DomainEvent::ProposalAnswered {
+            proposal: Proposal {
+                token: token.to_string(),
+                question: "Will you be my valentine?".to_string(),
+                sender: "Romeo".to_string(),
+                recipient: None,
+                callback_url: None,
+                answer: None,
+                answered_at: None,
+                created_at: chrono::Utc::now(),
+                couple_id: None,
+            },
+        }
+    }
+
+    #[rocket::async_test]
+    async fn subscribers_handle_what_was_published_before_shutdown() {
+        let storage = Storage::connect("sqlite::memory:").await.unwrap();
+        let bus = EventBus::default();
+        let recorder = Recorder::default();
+        let events = bus.subscribe();
+
+        bus.publish(&storage, answered("a"));
+        bus.publish(&storage, answered("b"));
+        let token = CancellationToken::new();
+        token.cancel();
+        run_subscriber(recorder.clone(), events, token).await;
+
+        assert_eq!(*recorder.0.lock().unwrap(), ["a", "b"]);
+    }
+
+    #[rocket::async_test]
+    async fn slow_subscribers_skip_nothing() {
+        let storage = Storage::connect("sqlite::memory:").await.unwrap();
+        let bus = EventBus::default();
+        let recorder = Recorder::default();
+        let events = bus.subscribe();
+
+        let tokens: Vec<String> = (0..1000).map(|n| n.to_string()).collect();
+        for token in &tokens {
+            bus.publish(&storage, answered(token));
+        }
+        let token = CancellationToken::new();
+        token.cancel();
+        run_subscriber(recorder.clone(), events, token).await;
+
+        assert_eq!(*recorder.0.lock().unwrap(), tokens);
+    }
+}
@@ -0,0 +1,288 @@
+//! A/B tests between two quote pools. Each visitor is assigned a variant
+//! once, per the experiment's split, and keeps it through a cookie; views
+//! are recorded as quotes are served and click-throughs are reported by the
+//! client, so results compare conversion between the pools.
+
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::time::Duration;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::ApiKey;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::metrics::Metrics;
+use crate::negotiate::Negotiated;
+use crate::reactions::ClientFingerprint;
+use crate::stats::ServeCounter;
+use crate::storage::{Experiment, ExperimentEvent, Quote, Storage, Variant};
+
+fn variant_cookie(experiment_id: i64) -> String {
+    format!("valentine_experiment_{}", experiment_id)
+}
+
+/// Where a new visitor lands: a stable hash of the client and experiment
+/// mapped onto 0..100, so the same client gets the same variant even
+/// before it keeps cookies.
+fn assign(fingerprint: &str, experiment_id: i64, split: i64) -> Variant {
+    let digest = Sha256::digest(format!("{}:{}", experiment_id, fingerprint));
+    let bucket = u16::from_be_bytes([digest[0], digest[1]]) % 100;
+    if i64::from(bucket) < split {
+        Variant::A
+    } else {
+        Variant::B
+    }
+}
+
+async fn find_experiment(storage: &Storage, id: i64) -> ApiResult<Experiment> {
+    storage
+        .get_experiment(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no experiment with id {}", id)))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ExperimentQuote {
+    experiment_id: i64,
+    variant: Variant,
+    quote: Quote,
+}
+
+/// A random quote from the caller's variant, assigning one (and setting its
+/// cookie) on the first visit. Each call counts as a view.
+#[utoipa::path(
+    tag = "experiments",
+    responses(
+        (status = 200, body = ExperimentQuote),
+        (status = 404, description = "No such experiment, or no approved quotes in the variant", body = ErrorResponse),
+    )
+)]
+#[get("/api/experiments/<id>/quote")]
+async fn quote(
+    storage: &Storage,
+    metrics: &State<Metrics>,
+    serves: &State<ServeCounter>,
+    client: ClientFingerprint,
+    cookies: &CookieJar<'_>,
+    id: i64,
+) -> ApiResult<Negotiated<ExperimentQuote>> {
+    let experiment = find_experiment(storage, id).await?;
+    let variant = match cookies
+        .get(&variant_cookie(id))
+        .and_then(|c| Variant::parse(c.value()))
+    {
+        Some(variant) => variant,
+        None => {
+            let variant = assign(&client.0, id, experiment.split);
+            cookies.add(
+                Cookie::build((variant_cookie(id), variant.as_str()))
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .max_age(Duration::days(90)),
+            );
+            variant
+        }
+    };
+
+    let quote = storage
+        .random_experiment_quote(id, variant)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| {
+            error(
+                Status::NotFound,
+                format!("no approved quotes in variant {}", variant.as_str()),
+            )
+        })?;
+    metrics.quote_served("experiment", Some(quote.category));
+    serves.served(storage, Some(&quote));
+    storage
+        .record_experiment_event(id, variant, &client.0, ExperimentEvent::View, quote.id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(ExperimentQuote {
+        experiment_id: id,
+        variant,
+        quote,
+    }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct EventRequest {
+    /// The served quote that was clicked.
+    quote_id: i64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct RecordedEvent {
+    experiment_id: i64,
+    variant: Variant,
+    quote_id: i64,
+}
+
+/// Records a click-through on a quote served by `GET /api/experiments/<id>/quote`.
+#[utoipa::path(
+    tag = "experiments",
+    request_body = EventRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = RecordedEvent),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "This client has not been served a quote in the experiment", body = ErrorResponse),
+        (status = 422, description = "The quote is not in the client's variant", body = ErrorResponse),
+    )
+)]
+#[post("/api/experiments/<id>/event", data = "<request>")]
+async fn event(
+    _key: ApiKey,
+    storage: &Storage,
+    client: ClientFingerprint,
+    cookies: &CookieJar<'_>,
+    id: i64,
+    request: Json<EventRequest>,
+) -> ApiResult<status::Created<Negotiated<RecordedEvent>>> {
+    let experiment = find_experiment(storage, id).await?;
+    let not_enrolled = || {
+        error(
+            Status::Conflict,
+            format!(
+                "this client has not been served a quote in experiment {}",
+                id
+            ),
+        )
+    };
+    let variant = cookies
+        .get(&variant_cookie(id))
+        .and_then(|c| Variant::parse(c.value()))
+        .ok_or_else(not_enrolled)?;
+    if !storage
+        .has_experiment_view(id, &client.0)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(not_enrolled());
+    }
+    if !experiment.pool(variant).contains(&request.quote_id) {
+        return Err(error(
+            Status::UnprocessableEntity,
+            format!(
+                "quote {} is not in variant {}",
+                request.quote_id,
+                variant.as_str()
+            ),
+        ));
+    }
+
+    storage
+        .record_experiment_event(
+            id,
+            variant,
+            &client.0,
+            ExperimentEvent::Click,
+            request.quote_id,
+        )
+        .await
+        .map_err(internal_error)?;
+    Ok(
+        status::Created::new(uri!(results(id)).to_string()).body(Negotiated(RecordedEvent {
+            experiment_id: id,
+            variant,
+            quote_id: request.quote_id,
+        })),
+    )
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct VariantResult {
+    variant: Variant,
+    /// Distinct visitors served at least one quote.
+    visitors: i64,
+    views: i64,
+    clicks: i64,
+    /// Distinct visitors who clicked at least once.
+    converted: i64,
+    /// `converted / visitors`, or 0 before the first visitor.
+    conversion_rate: f64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ExperimentResults {
+    experiment: Experiment,
+    variants: Vec<VariantResult>,
+}
+
+#[utoipa::path(
+    tag = "experiments",
+    responses(
+        (status = 200, body = ExperimentResults),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/experiments/<id>/results")]
+async fn results(storage: &Storage, id: i64) -> ApiResult<Negotiated<ExperimentResults>> {
+    let experiment = find_experiment(storage, id).await?;
+
+    let mut variants = Vec::with_capacity(Variant::ALL.len());
+    for variant in Variant::ALL {
+        let counts = storage
+            .variant_counts(id, variant)
+            .await
+            .map_err(internal_error)?;
+        variants.push(VariantResult {
+            variant,
+            visitors: counts.visitors,
+            views: counts.views,
+            clicks: counts.clicks,
+            converted: counts.converted,
+            conversion_rate: conversion_rate(counts.converted, counts.visitors),
+        });
+    }
+
+    Ok(Negotiated(ExperimentResults {
+        experiment,
+        variants,
+    }))
+}
+
+fn conversion_rate(converted: i64, visitors: i64) -> f64 {
+    if visitors == 0 {
+        0.0
+    } else {
+        converted as f64 / visitors as f64
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![quote, event, results]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assignment_is_stable_and_follows_the_split() {
+        let clients: Vec<String> = (0..1000).map(|i| format!("{:032x}", i)).collect();
+
+        for client in &clients {
+            assert_eq!(assign(client, 1, 50), assign(client, 1, 50));
+            assert_eq!(assign(client, 1, 0), Variant::B);
+            assert_eq!(assign(client, 1, 100), Variant::A);
+        }
+
+        let in_a = clients
+            .iter()
+            .filter(|client| assign(client, 7, 30) == Variant::A)
+            .count();
+        assert!((250..350).contains(&in_a), "{} of 1000 in variant a", in_a);
+
+        assert_eq!(conversion_rate(0, 0), 0.0);
+        assert_eq!(conversion_rate(1, 4), 0.25);
+    }
+}
@@ -0,0 +1,374 @@
+//! Exporting everything a couple has kept: their messages, memories,
+//! important dates and quiz results. The ZIP archive holds the records as
+//! `archive.json` plus the bytes of every uploaded image they use under
+//! `media/<upload id>`, and is streamed as it is written so large photo
+//! collections never sit in memory.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipDateTime, ZipEntryBuilder};
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{self, Json};
+use rocket::tokio::io::{self, DuplexStream};
+use rocket::{tokio, Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::storage::{DateKind, QuizScore, Reminders, Storage, Upload};
+use crate::uploads::{self, UploadStore};
+use crate::users::CoupleScope;
+
+/// Bumped whenever [`Archive`] changes in a way older readers cannot handle.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Name of the records file inside the ZIP archive.
+pub const ARCHIVE_FILE: &str = "archive.json";
+
+/// Folder of the ZIP archive holding upload bytes, by upload id.
+pub const MEDIA_DIR: &str = "media";
+
+/// Bytes buffered between the ZIP writer and the response.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ArchivedMessage {
+    pub message: String,
+    pub from: String,
+    pub to: Option<String>,
+    /// Upload id of the attached image.
+    pub image: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ArchivedMemory {
+    pub upload_id: String,
+    pub thumbnail_id: String,
+    pub caption: String,
+    pub taken_on: NaiveDate,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ArchivedDate {
+    pub title: String,
+    pub kind: DateKind,
+    pub date: NaiveDate,
+    pub recurring: bool,
+    pub reminders: Reminders,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An uploaded image the records refer to. In a ZIP archive its bytes are
+/// at `media/<id>`.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ArchivedUpload {
+    pub id: String,
+    pub content_type: String,
+    pub size: i64,
+    /// Hex SHA-256 of the bytes.
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<Upload> for ArchivedUpload {
+    fn from(upload: Upload) -> Self {
+        ArchivedUpload {
+            id: upload.id,
+            content_type: upload.content_type,
+            size: upload.size,
+            sha256: upload.sha256,
+            created_at: upload.created_at,
+        }
+    }
+}
+
+/// Everything exported for one couple.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Archive {
+    /// [`ARCHIVE_VERSION`] of the server that wrote it.
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub messages: Vec<ArchivedMessage>,
+    pub memories: Vec<ArchivedMemory>,
+    pub dates: Vec<ArchivedDate>,
+    pub quiz_results: Vec<QuizScore>,
+    pub uploads: Vec<ArchivedUpload>,
+}
+
+/// Collects the couple's records and the uploads they refer to.
+async fn collect(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    couple: Option<i64>,
+) -> Result<Archive, sqlx::Error> {
+    let messages: Vec<ArchivedMessage> = storage
+        .all_messages(couple)
+        .await?
+        .into_iter()
+        .map(|message| ArchivedMessage {
+            image: message
+                .image_url
+                .as_deref()
+                .and_then(|url| uploads::upload_id(public_url, url))
+                .map(str::to_string),
+            message: message.message,
+            from: message.sender,
+            to: message.recipient,
+            created_at: message.created_at,
+        })
+        .collect();
+    let memories: Vec<ArchivedMemory> = storage
+        .list_memories(couple)
+        .await?
+        .into_iter()
+        .rev()
+        .map(|memory| ArchivedMemory {
+            upload_id: memory.upload_id,
+            thumbnail_id: memory.thumbnail_id,
+            caption: memory.caption,
+            taken_on: memory.taken_on,
+            created_at: memory.created_at,
+        })
+        .collect();
+    let dates = storage
+        .list_dates(couple)
+        .await?
+        .into_iter()
+        .map(|date| ArchivedDate {
+            title: date.title,
+            kind: date.kind,
+            date: date.date,
+            recurring: date.recurring,
+            reminders: date.reminders,
+            created_at: date.created_at,
+        })
+        .collect();
+    let quiz_results = storage.list_quiz_scores(couple).await?;
+
+    let ids: BTreeSet<&str> = messages
+        .iter()
+        .filter_map(|message| message.image.as_deref())
+        .chain(
+            memories
+                .iter()
+                .flat_map(|memory| [memory.upload_id.as_str(), memory.thumbnail_id.as_str()]),
+        )
+        .collect();
+    let mut uploads = Vec::with_capacity(ids.len());
+    for id in ids {
+        match storage.get_upload(id).await? {
+            Some(upload) => uploads.push(ArchivedUpload::from(upload)),
+            None => warn!("export refers to upload {} which has no metadata", id),
+        }
+    }
+
+    Ok(Archive {
+        version: ARCHIVE_VERSION,
+        exported_at: Utc::now(),
+        messages,
+        memories,
+        dates,
+        quiz_results,
+        uploads,
+    })
+}
+
+/// Writes `archive` as a ZIP into `pipe`, reading each upload from `store`
+/// only when its entry is written.
+async fn write_zip(archive: Archive, store: UploadStore, pipe: DuplexStream) -> Result<(), String> {
+    let mut zip = ZipFileWriter::with_tokio(pipe);
+    let records = json::to_string(&archive).map_err(|e| e.to_string())?;
+    let entry = ZipEntryBuilder::new(ARCHIVE_FILE.into(), Compression::Deflate)
+        .last_modification_date(ZipDateTime::from_chrono(&archive.exported_at));
+    zip.write_entry_whole(entry, records.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for upload in &archive.uploads {
+        let Some(bytes) = store.get(&upload.id).await? else {
+            warn!("upload {} has metadata but no stored bytes", upload.id);
+            continue;
+        };
+        // Images are already compressed.
+        let name = format!("{}/{}", MEDIA_DIR, upload.id);
+        let entry = ZipEntryBuilder::new(name.into(), Compression::Stored)
+            .last_modification_date(ZipDateTime::from_chrono(&upload.created_at));
+        zip.write_entry_whole(entry, &bytes)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    zip.close().await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    Zip,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "zip" => Ok(ExportFormat::Zip),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("`format` must be `zip` or `json`, got `{}`", s)),
+        }
+    }
+}
+
+enum Export {
+    Zip(DuplexStream),
+    Json(Json<Archive>),
+}
+
+impl<'r> Responder<'r, 'static> for Export {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let (body, filename) = match self {
+            Export::Zip(reader) => (
+                Response::build()
+                    .header(ContentType::ZIP)
+                    .streamed_body(reader)
+                    .finalize(),
+                "valentine-export.zip",
+            ),
+            Export::Json(archive) => (archive.respond_to(request)?, "valentine-export.json"),
+        };
+        Response::build_from(body)
+            .header(Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", filename),
+            ))
+            .ok()
+    }
+}
+
+/// Downloads everything the signed-in couple (or, with only a key, the
+/// shared space) has kept. `format=json` gives just the records, without
+/// image bytes; the default ZIP includes the images. A ZIP that fails
+/// part-way ends truncated, as its status has already been sent.
+#[utoipa::path(
+    tag = "export",
+    params(("format" = Option<ExportFormat>, Query, description = "`zip` (default) or `json`")),
+    security(("api_key" = [])),
+    responses(
+        (
+            status = 200,
+            description = "The ZIP archive, or with `format=json` the records alone",
+            content((Vec<u8> = "application/zip"), (Archive = "application/json")),
+        ),
+        (status = 400, description = "Unknown `format`", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[get("/api/export?<format>")]
+async fn export(
+    _key: ApiKey,
+    storage: &Storage,
+    store: &State<UploadStore>,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+    format: Option<&str>,
+) -> ApiResult<Export> {
+    let format = format
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(ExportFormat::Zip);
+    let archive = collect(storage, public_url, scope.0)
+        .await
+        .map_err(internal_error)?;
+    if format == ExportFormat::Json {
+        return Ok(Export::Json(Json(archive)));
+    }
+
+    let (writer, reader) = io::duplex(PIPE_CAPACITY);
+    let store = store.inner().clone();
+    tokio::spawn(async move {
+        if let Err(e) = write_zip(archive, store, writer).await {
+            error!("export archive failed: {}", e);
+        }
+    });
+    Ok(Export::Zip(reader))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![export]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use async_zip::base::read1::seek::ZipArchiveReader;
+    use rocket::tokio::io::AsyncReadExt;
+    use tokio_util::compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+
+    #[rocket::async_test]
+    async fn zip_holds_the_records_and_media() {
+        let dir = std::env::temp_dir().join(format!("valentine-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("photo"), b"not really a png").unwrap();
+
+        let now = Utc::now();
+        let archive = Archive {
+            version: ARCHIVE_VERSION,
+            exported_at: now,
+            messages: vec![],
+            memories: vec![],
+            dates: vec![],
+            quiz_results: vec![],
+            uploads: ["photo", "missing"]
+                .map(|id| ArchivedUpload {
+                    id: id.to_string(),
+                    content_type: "image/png".to_string(),
+                    size: 16,
+                    sha256: String::new(),
+                    created_at: now,
+                })
+                .to_vec(),
+        };
+        let (writer, mut reader) = io::duplex(PIPE_CAPACITY);
+        let store = UploadStore::Disk(dir.clone());
+        let written = tokio::spawn(write_zip(archive, store, writer));
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.unwrap();
+        written.await.unwrap().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut zip = ZipArchiveReader::open(Cursor::new(bytes).compat())
+            .await
+            .unwrap();
+        let names: Vec<&str> = zip
+            .cdrs()
+            .iter()
+            .map(|cdr| cdr.insecure_file_name.as_str().unwrap())
+            .collect();
+        assert_eq!(names, [ARCHIVE_FILE, "media/photo"]);
+
+        let mut records = String::new();
+        zip.file(0)
+            .await
+            .unwrap()
+            .compat()
+            .read_to_string(&mut records)
+            .await
+            .unwrap();
+        let read: Archive = json::from_str(&records).unwrap();
+        assert_eq!(read.version, ARCHIVE_VERSION);
+        assert_eq!(read.uploads.len(), 2);
+    }
+}
@@ -0,0 +1,168 @@
+//! Serves the built frontend from the `[default.frontend]` directory, so one
+//! process can host the whole app. Unknown paths that a browser navigates to
+//! get `index.html`, letting the client-side router handle them; API paths
+//! and missing assets still get a `404`.
+
+use std::path::{Path, PathBuf};
+
+use rocket::fairing::AdHoc;
+use rocket::fs::{FileServer, NamedFile};
+use rocket::http::{Header, Method};
+use rocket::route::{Handler, Outcome};
+use rocket::{Data, Request, Route};
+use serde::Deserialize;
+
+/// Below every other route, so the API always wins and the file lookup
+/// only runs for paths nothing else matched.
+const RANK: isize = 20;
+
+const INDEX: &str = "index.html";
+
+/// Cache header for file names carrying a build hash, e.g. Vite's
+/// `assets/index-BdX3k2aQ.js`: a new build gets a new name.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Cache header for `index.html`, which names the current hashed assets and
+/// must be revalidated after each deploy.
+const NO_CACHE: &str = "no-cache";
+
+fn default_max_age() -> u64 {
+    60 * 60
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FrontendConfig {
+    /// The build output, e.g. `../frontend/dist`.
+    dir: PathBuf,
+    /// Seconds that other files, e.g. `favicon.svg`, may be cached.
+    #[serde(default = "default_max_age")]
+    max_age: u64,
+}
+
+/// Whether `name` looks like `<stem>-<hash>.<ext>` with a hash of at least
+/// eight URL-safe characters, as bundlers write them.
+fn is_hashed(name: &str) -> bool {
+    let Some((stem, _ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+    stem.rsplit_once('-').is_some_and(|(_, hash)| {
+        hash.len() >= 8
+            && hash.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+            && hash
+                .bytes()
+                .any(|b| b.is_ascii_digit() || b.is_ascii_uppercase())
+    })
+}
+
+/// Whether a request no file matched gets `index.html`: a browser
+/// navigation, i.e. accepting HTML, to a non-API path without an extension.
+fn wants_index(request: &Request<'_>) -> bool {
+    let path = request.uri().path();
+    let last = path.segments().last().unwrap_or("");
+    request.method() == Method::Get
+        && !path.starts_with("/api/")
+        && !last.contains('.')
+        && request
+            .accept()
+            .is_some_and(|accept| accept.media_types().any(|m| m.is_html()))
+}
+
+/// [`FileServer`] for the build output, with cache headers and the
+/// `index.html` fallback.
+#[derive(Clone)]
+struct Frontend {
+    files: FileServer,
+    index: PathBuf,
+    max_age: String,
+}
+
+impl Frontend {
+    fn cache_control(&self, request: &Request<'_>) -> &str {
+        let path = request.uri().path();
+        match path.segments().last() {
+            None | Some(INDEX) => NO_CACHE,
+            Some(name) if is_hashed(name) => IMMUTABLE,
+            Some(_) => &self.max_age,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for Frontend {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let (cache_control, outcome) = match self.files.handle(request, data).await {
+            Outcome::Forward((data, status)) => {
+                if !wants_index(request) {
+                    return Outcome::Forward((data, status));
+                }
+                (
+                    NO_CACHE,
+                    Outcome::from(request, NamedFile::open(&self.index).await.ok()),
+                )
+            }
+            outcome => (self.cache_control(request), outcome),
+        };
+        match outcome {
+            Outcome::Success(mut response) => {
+                response.set_header(Header::new("Cache-Control", cache_control.to_string()));
+                Outcome::Success(response)
+            }
+            outcome => outcome,
+        }
+    }
+}
+
+impl From<Frontend> for Vec<Route> {
+    fn from(frontend: Frontend) -> Self {
+        let mut route = Route::ranked(RANK, Method::Get, "/<path..>", frontend);
+        route.name = Some("Frontend".into());
+        vec![route]
+    }
+}
+
+/// Mounts the frontend from the optional `frontend` config. A missing build
+/// directory is logged rather than failing the launch, so the API can run
+/// before the frontend is built.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Frontend", |rocket| async {
+        let config = match rocket.figment().extract_inner::<FrontendConfig>("frontend") {
+            Ok(config) => config,
+            Err(e) if e.missing() => return Ok(rocket),
+            Err(e) => {
+                error!("invalid frontend config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let index = config.dir.join(INDEX);
+        if !Path::new(&index).is_file() {
+            warn!(
+                "frontend not served: {} not found; run `npm run build` in frontend/",
+                index.display()
+            );
+            return Ok(rocket);
+        }
+
+        let frontend = Frontend {
+            files: FileServer::from(&config.dir),
+            index,
+            max_age: format!("public, max-age={}", config.max_age),
+        };
+        Ok(rocket.mount("/", frontend))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_bundler_hashes_count_as_hashed() {
+        assert!(is_hashed("index-BdX3k2aQ.js"));
+        assert!(is_hashed("react-CHdo91hT.svg"));
+        assert!(is_hashed("vendor.chunk-4f9a2c1e.css"));
+        assert!(!is_hashed("index.html"));
+        assert!(!is_hashed("vite.svg"));
+        assert!(!is_hashed("long-distance.png"));
+        assert!(!is_hashed("favicon"));
+    }
+}
@@ -0,0 +1,562 @@
+//! Games made from what a couple has saved. The memory quiz asks about
+//! their important dates and memories, with wrong answers taken from their
+//! other dates and memories or made up close to the right one.
+
+use std::collections::HashMap;
+
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::PublicUrl;
+use crate::determinism::RequestRng;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::memories::MONTHS;
+use crate::negotiate::Negotiated;
+use crate::storage::{DateKind, HighScore, NewGameScore, Storage};
+use crate::uploads::upload_url;
+use crate::users::CoupleScope;
+
+/// The `game` high scores are kept under.
+const MEMORY_QUIZ: &str = "memory-quiz";
+
+const DEFAULT_QUESTIONS: usize = 5;
+const MAX_QUESTIONS: usize = 10;
+
+const OPTION_IDS: [&str; 4] = ["a", "b", "c", "d"];
+
+/// Tries at making up a wrong answer before giving up on a question.
+const MAKE_UP_ATTEMPTS: usize = 20;
+
+/// An important date, as far as questions go.
+struct DateFact {
+    title: String,
+    kind: DateKind,
+    date: NaiveDate,
+    recurring: bool,
+}
+
+impl DateFact {
+    /// Recurring dates are asked without the year, except the first date,
+    /// which only happened once.
+    fn with_year(&self) -> bool {
+        !self.recurring || self.kind == DateKind::FirstDate
+    }
+
+    fn question(&self) -> String {
+        match self.kind {
+            DateKind::FirstDate => "When was our first date?".to_string(),
+            _ if self.recurring => format!("When is {}?", self.title),
+            _ => format!("When was {}?", self.title),
+        }
+    }
+}
+
+/// A memory, as far as questions go.
+struct MemoryFact {
+    caption: String,
+    taken_on: NaiveDate,
+    thumbnail_url: String,
+}
+
+/// What a quiz is made from, in a stable order so that a seed always gives
+/// the same quiz.
+#[derive(Default)]
+struct Facts {
+    dates: Vec<DateFact>,
+    memories: Vec<MemoryFact>,
+}
+
+fn format_day(date: NaiveDate, with_year: bool) -> String {
+    let month = MONTHS[date.month0() as usize];
+    if with_year {
+        format!("{} {}, {}", month, date.day(), date.year())
+    } else {
+        format!("{} {}", month, date.day())
+    }
+}
+
+fn format_month(date: NaiveDate) -> String {
+    format!("{} {}", MONTHS[date.month0() as usize], date.year())
+}
+
+/// A date a few weeks either side of `date`.
+fn nearby_day(rng: &mut StdRng, date: NaiveDate) -> Option<NaiveDate> {
+    let days = rng.gen_range(1..=45) * if rng.gen() { 1 } else { -1 };
+    date.checked_add_signed(Duration::days(days))
+}
+
+/// A month up to a year either side of `date`'s.
+fn nearby_month(rng: &mut StdRng, date: NaiveDate) -> Option<NaiveDate> {
+    let months = Months::new(rng.gen_range(1..=12));
+    if rng.gen() {
+        date.checked_add_months(months)
+    } else {
+        date.checked_sub_months(months)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Question {
+    text: String,
+    image_url: Option<String>,
+    options: Vec<String>,
+    /// Index of the right option.
+    answer: usize,
+}
+
+/// `right` and three distinct wrong answers, from `others` where possible
+/// and `make_up` for the rest, in random order. `None` when there are not
+/// enough wrong answers.
+fn question(
+    rng: &mut StdRng,
+    text: String,
+    image_url: Option<String>,
+    right: String,
+    others: Vec<String>,
+    mut make_up: impl FnMut(&mut StdRng) -> Option<String>,
+) -> Option<Question> {
+    let wanted = OPTION_IDS.len() - 1;
+    let mut wrong: Vec<String> = others.into_iter().filter(|o| *o != right).collect();
+    wrong.sort();
+    wrong.dedup();
+    wrong.shuffle(rng);
+    wrong.truncate(wanted);
+    for _ in 0..MAKE_UP_ATTEMPTS {
+        if wrong.len() == wanted {
+            break;
+        }
+        match make_up(rng) {
+            Some(made_up) if made_up != right && !wrong.contains(&made_up) => wrong.push(made_up),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    if wrong.len() < wanted {
+        return None;
+    }
+
+    let answer = rng.gen_range(0..OPTION_IDS.len());
+    let mut options = wrong;
+    options.insert(answer, right);
+    Some(Question {
+        text,
+        image_url,
+        options,
+        answer,
+    })
+}
+
+/// Up to `count` questions from `facts`; the same facts and seed always
+/// give the same questions.
+fn generate(facts: &Facts, seed: u64, count: usize) -> Vec<Question> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut questions = Vec::new();
+
+    for date in &facts.dates {
+        let with_year = date.with_year();
+        let others = facts
+            .dates
+            .iter()
+            .filter(|other| other.with_year() == with_year)
+            .map(|other| format_day(other.date, with_year))
+            .collect();
+        questions.extend(question(
+            &mut rng,
+            date.question(),
+            None,
+            format_day(date.date, with_year),
+            others,
+            |rng| nearby_day(rng, date.date).map(|day| format_day(day, with_year)),
+        ));
+    }
+
+    for memory in &facts.memories {
+        let month = format_month(memory.taken_on);
+        let months = facts
+            .memories
+            .iter()
+            .map(|other| format_month(other.taken_on))
+            .collect();
+        questions.extend(question(
+            &mut rng,
+            format!("When was \u{201c}{}\u{201d} taken?", memory.caption),
+            Some(memory.thumbnail_url.clone()),
+            month.clone(),
+            months,
+            |rng| nearby_month(rng, memory.taken_on).map(format_month),
+        ));
+
+        let captions = facts
+            .memories
+            .iter()
+            .filter(|other| format_month(other.taken_on) != month)
+            .map(|other| other.caption.clone())
+            .collect();
+        questions.extend(question(
+            &mut rng,
+            format!("Which of these happened in {}?", month),
+            None,
+            memory.caption.clone(),
+            captions,
+            |_| None,
+        ));
+    }
+
+    questions.shuffle(&mut rng);
+    questions.truncate(count);
+    questions
+}
+
+/// Names a generated quiz: its length and seed, to make it again when it
+/// is answered, and a digest of its questions, to notice when the facts
+/// under it changed in between.
+fn quiz_id(count: usize, seed: u64, questions: &[Question]) -> String {
+    let mut hasher = Sha256::new();
+    for question in questions {
+        hasher.update(&question.text);
+        for option in &question.options {
+            hasher.update([0]);
+            hasher.update(option);
+        }
+        hasher.update([1]);
+    }
+    let digest: String = hasher.finalize()[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}.{:x}.{}", count, seed, digest)
+}
+
+/// The length and seed in a [`quiz_id`].
+fn parse_quiz_id(id: &str) -> Option<(usize, u64)> {
+    let mut parts = id.split('.');
+    let count = parts.next()?.parse().ok()?;
+    let seed = u64::from_str_radix(parts.next()?, 16).ok()?;
+    parts.next()?;
+    Some((count, seed))
+}
+
+fn question_id(index: usize) -> String {
+    format!("q{}", index + 1)
+}
+
+async fn load_facts(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    couple: Option<i64>,
+) -> ApiResult<Facts> {
+    let dates = storage.list_dates(couple).await.map_err(internal_error)?;
+    let memories = storage
+        .list_memories(couple)
+        .await
+        .map_err(internal_error)?;
+    Ok(Facts {
+        dates: dates
+            .into_iter()
+            .map(|date| DateFact {
+                title: date.title,
+                kind: date.kind,
+                date: date.date,
+                recurring: date.recurring,
+            })
+            .collect(),
+        memories: memories
+            .into_iter()
+            .map(|memory| MemoryFact {
+                thumbnail_url: upload_url(public_url, &memory.thumbnail_id),
+                caption: memory.caption,
+                taken_on: memory.taken_on,
+            })
+            .collect(),
+    })
+}
+
+async fn high_score(storage: &Storage, couple: Option<i64>) -> ApiResult<Option<HighScore>> {
+    match couple {
+        Some(couple) => storage
+            .high_score(MEMORY_QUIZ, couple)
+            .await
+            .map_err(internal_error),
+        None => Ok(None),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct OptionView {
+    id: &'static str,
+    text: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct QuestionView {
+    id: String,
+    text: String,
+    /// A memory's thumbnail, for questions about a photo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_url: Option<String>,
+    options: Vec<OptionView>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct MemoryQuiz {
+    /// Sent back with the answers.
+    id: String,
+    questions: Vec<QuestionView>,
+    /// The couple's best game so far; only for signed-in couples.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    high_score: Option<HighScore>,
+}
+
+/// A multiple-choice quiz about the couple's important dates and memories.
+/// Pass `seed` to get the same quiz again.
+#[utoipa::path(
+    tag = "games",
+    params(
+        ("questions" = Option<usize>, Query, description = "How many questions, 1 to 10 (default 5); fewer when there is not enough to ask about"),
+        ("seed" = Option<u64>, Query, description = "Reproduces an earlier quiz"),
+    ),
+    responses(
+        (status = 200, body = MemoryQuiz),
+        (status = 400, body = ErrorResponse),
+        (status = 404, description = "No dates or memories to ask about yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/games/memory-quiz?<questions>&<seed>")]
+async fn memory_quiz(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+    mut rng: RequestRng,
+    questions: Option<usize>,
+    seed: Option<u64>,
+) -> ApiResult<Negotiated<MemoryQuiz>> {
+    let count = questions.unwrap_or(DEFAULT_QUESTIONS);
+    if !(1..=MAX_QUESTIONS).contains(&count) {
+        return Err(error(
+            Status::BadRequest,
+            format!("`questions` must be between 1 and {}", MAX_QUESTIONS),
+        ));
+    }
+    let seed = seed.unwrap_or_else(|| rng.gen());
+
+    let facts = load_facts(storage, public_url, scope.0).await?;
+    let generated = generate(&facts, seed, count);
+    if generated.is_empty() {
+        return Err(error(
+            Status::NotFound,
+            "add an important date or a memory to play the memory quiz",
+        ));
+    }
+
+    Ok(Negotiated(MemoryQuiz {
+        id: quiz_id(generated.len(), seed, &generated),
+        high_score: high_score(storage, scope.0).await?,
+        questions: generated
+            .into_iter()
+            .enumerate()
+            .map(|(i, question)| QuestionView {
+                id: question_id(i),
+                text: question.text,
+                image_url: question.image_url,
+                options: OPTION_IDS
+                    .iter()
+                    .zip(question.options)
+                    .map(|(&id, text)| OptionView { id, text })
+                    .collect(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct MemoryQuizAnswers {
+    /// The `id` of the quiz from `GET /api/games/memory-quiz`.
+    quiz: String,
+    /// The chosen option id for every question id.
+    answers: HashMap<String, String>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct Marked {
+    question: String,
+    correct: bool,
+    /// The right option id.
+    answer: &'static str,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct MemoryQuizResult {
+    correct: u32,
+    total: u32,
+    /// Percentage answered correctly.
+    score: u32,
+    results: Vec<Marked>,
+    /// Whether this beat the couple's high score; always false when not
+    /// signed in as a couple, since nothing is kept.
+    new_high_score: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    high_score: Option<HighScore>,
+}
+
+/// Scores a memory quiz. For a signed-in couple the result is kept and
+/// counts towards their high score.
+#[utoipa::path(
+    tag = "games",
+    request_body = MemoryQuizAnswers,
+    responses(
+        (status = 200, body = MemoryQuizResult),
+        (status = 409, description = "The dates or memories changed since the quiz was made", body = ErrorResponse),
+        (status = 422, description = "Unknown quiz, or unanswered or unknown questions or options", body = ErrorResponse),
+    )
+)]
+#[post("/api/games/memory-quiz/answers", data = "<request>")]
+async fn memory_quiz_answers(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+    request: Json<MemoryQuizAnswers>,
+) -> ApiResult<Negotiated<MemoryQuizResult>> {
+    let (count, seed) = parse_quiz_id(&request.quiz)
+        .filter(|(count, _)| (1..=MAX_QUESTIONS).contains(count))
+        .ok_or_else(|| {
+            error(
+                Status::UnprocessableEntity,
+                "`quiz` is not a quiz id from GET /api/games/memory-quiz",
+            )
+        })?;
+    let facts = load_facts(storage, public_url, scope.0).await?;
+    let questions = generate(&facts, seed, count);
+    if quiz_id(count, seed, &questions) != request.quiz {
+        return Err(error(
+            Status::Conflict,
+            "your dates or memories changed since this quiz was made; fetch a new one",
+        ));
+    }
+
+    let ids: Vec<String> = (0..questions.len()).map(question_id).collect();
+    if let Some(unknown) = request.answers.keys().find(|id| !ids.contains(id)) {
+        return Err(error(
+            Status::UnprocessableEntity,
+            format!("no question `{}` in this quiz", unknown),
+        ));
+    }
+    let mut results = Vec::with_capacity(questions.len());
+    for (id, question) in ids.into_iter().zip(&questions) {
+        let chosen = request.answers.get(&id).ok_or_else(|| {
+            error(
+                Status::UnprocessableEntity,
+                format!("question `{}` is not answered", id),
+            )
+        })?;
+        let chosen = OPTION_IDS
+            .iter()
+            .position(|option| option == chosen)
+            .ok_or_else(|| {
+                error(
+                    Status::UnprocessableEntity,
+                    format!("`{}` is not an option of `{}`", chosen, id),
+                )
+            })?;
+        results.push(Marked {
+            question: id,
+            correct: chosen == question.answer,
+            answer: OPTION_IDS[question.answer],
+        });
+    }
+
+    let correct = results.iter().filter(|r| r.correct).count() as u32;
+    let total = results.len() as u32;
+    let score = (f64::from(correct) * 100.0 / f64::from(total)).round() as u32;
+    let previous = high_score(storage, scope.0).await?;
+    let mut new_high_score = false;
+    if let Some(couple) = scope.0 {
+        storage
+            .save_game_score(&NewGameScore {
+                game: MEMORY_QUIZ,
+                couple_id: couple,
+                correct,
+                total,
+                score,
+            })
+            .await
+            .map_err(internal_error)?;
+        new_high_score = previous.as_ref().is_none_or(|best| score > best.score);
+    }
+
+    Ok(Negotiated(MemoryQuizResult {
+        correct,
+        total,
+        score,
+        results,
+        new_high_score,
+        high_score: high_score(storage, scope.0).await?,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![memory_quiz, memory_quiz_answers]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quizzes_are_reproducible_with_one_right_answer() {
+        let day = |d: &str| d.parse::<NaiveDate>().unwrap();
+        let facts = Facts {
+            dates: vec![
+                DateFact {
+                    title: "our first date".to_string(),
+                    kind: DateKind::FirstDate,
+                    date: day("2023-06-10"),
+                    recurring: true,
+                },
+                DateFact {
+                    title: "Alex's birthday".to_string(),
+                    kind: DateKind::Birthday,
+                    date: day("1998-03-02"),
+                    recurring: true,
+                },
+            ],
+            memories: vec![MemoryFact {
+                caption: "Picnic at the pier".to_string(),
+                taken_on: day("2024-08-17"),
+                thumbnail_url: "/api/uploads/thumb".to_string(),
+            }],
+        };
+
+        let questions = generate(&facts, 7, MAX_QUESTIONS);
+        // One memory has no other captions to mix in, so it only gets the
+        // question about when it was taken.
+        assert_eq!(questions.len(), 3);
+        assert_eq!(questions, generate(&facts, 7, MAX_QUESTIONS));
+        for question in &questions {
+            let mut options = question.options.clone();
+            options.sort();
+            options.dedup();
+            assert_eq!(options.len(), OPTION_IDS.len(), "{:?}", question);
+        }
+        let first_date = questions
+            .iter()
+            .find(|q| q.text == "When was our first date?")
+            .unwrap();
+        assert_eq!(first_date.options[first_date.answer], "June 10, 2023");
+        let birthday = questions
+            .iter()
+            .find(|q| q.text == "When is Alex's birthday?")
+            .unwrap();
+        assert_eq!(birthday.options[birthday.answer], "March 2");
+
+        let id = quiz_id(questions.len(), 7, &questions);
+        assert_eq!(parse_quiz_id(&id), Some((3, 7)));
+        assert!(generate(&Facts::default(), 7, MAX_QUESTIONS).is_empty());
+    }
+}
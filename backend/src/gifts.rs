@@ -0,0 +1,383 @@
+//! Gift ideas ranked by how well their tags match the caller's interests
+//! and whether they fit the budget. The catalog is seeded from `gifts_file`
+//! (default `gifts.toml`) into an empty database and then edited through
+//! `/admin/gifts`.
+
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Data, Format, Toml};
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{Gift, NewGift, Storage};
+use crate::validation::{FieldErrors, Validate};
+
+const DEFAULT_GIFTS_FILE: &str = "gifts.toml";
+
+const MAX_NAME_LEN: usize = 80;
+const MAX_DESCRIPTION_LEN: usize = 300;
+const MAX_TAGS: usize = 10;
+const MAX_TAG_LEN: usize = 30;
+const MAX_PRICE: i64 = 100_000;
+
+/// Most interests `GET /api/gifts` accepts.
+const MAX_INTERESTS: usize = 10;
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 50;
+
+/// Share of the score that comes from matching interests; the rest is how
+/// well the price range fits the budget.
+const INTEREST_WEIGHT: f64 = 0.8;
+
+/// A gift as written in the catalog file or sent to the admin API.
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+pub(crate) struct GiftRequest {
+    name: String,
+    description: String,
+    price_min: i64,
+    price_max: i64,
+    url: Option<String>,
+    /// Lowercase words or hyphenated phrases, e.g. `board-games`.
+    tags: Vec<String>,
+}
+
+impl Validate for GiftRequest {
+    type Valid = NewGift;
+
+    fn validate(self) -> Result<NewGift, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let name = self.name.trim().to_string();
+        let description = self.description.trim().to_string();
+        errors.text("name", &name, MAX_NAME_LEN);
+        errors.text("description", &description, MAX_DESCRIPTION_LEN);
+
+        if self.price_min < 0 || self.price_min > self.price_max || self.price_max > MAX_PRICE {
+            errors.add(
+                "price_max",
+                format!(
+                    "prices must satisfy 0 <= `price_min` <= `price_max` <= {}",
+                    MAX_PRICE
+                ),
+            );
+        }
+
+        let url = self
+            .url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        if let Some(url) = &url {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => errors.add("url", "`url` must be an absolute http(s) URL"),
+            }
+        }
+
+        let mut tags: Vec<String> = Vec::new();
+        for tag in self.tags {
+            let tag = tag.trim().to_lowercase();
+            let valid = !tag.is_empty()
+                && tag.len() <= MAX_TAG_LEN
+                && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            if !valid {
+                errors.add(
+                    "tags",
+                    format!(
+                        "tag `{}` must be 1 to {} letters, digits or hyphens",
+                        tag, MAX_TAG_LEN
+                    ),
+                );
+            } else if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        if tags.is_empty() || tags.len() > MAX_TAGS {
+            errors.add(
+                "tags",
+                format!("`tags` must list between 1 and {} tags", MAX_TAGS),
+            );
+        }
+
+        errors.finish(NewGift {
+            name,
+            description,
+            price_min: self.price_min,
+            price_max: self.price_max,
+            url,
+            tags,
+        })
+    }
+}
+
+/// How well a gift tag answers an interest: 1 for the same word, 0.5 for
+/// one word of a hyphenated tag (`games` and `board-games`) or when one is
+/// a prefix of the other (`book` and `books`).
+fn tag_match(interest: &str, tag: &str) -> f64 {
+    let prefix = interest.len() >= 3
+        && tag.len() >= 3
+        && (tag.starts_with(interest) || interest.starts_with(tag));
+    if interest == tag {
+        1.0
+    } else if prefix || tag.split('-').any(|word| word == interest) {
+        0.5
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Score {
+    score: f64,
+    matched_tags: Vec<String>,
+}
+
+/// Scores `gift` from 0 to 1, or `None` when it starts above `budget` or,
+/// given any interests, matches none of them. Each interest counts its
+/// best-matching tag; a price range entirely within budget beats one that
+/// only starts within it.
+fn score(gift: &Gift, interests: &[String], budget: Option<i64>) -> Option<Score> {
+    let fit = match budget {
+        None => 1.0,
+        Some(budget) if gift.price_max <= budget => 1.0,
+        Some(budget) if gift.price_min <= budget => 0.5,
+        Some(_) => return None,
+    };
+
+    let mut matched_tags = Vec::new();
+    let relevance = if interests.is_empty() {
+        1.0
+    } else {
+        let mut total = 0.0;
+        for interest in interests {
+            let best = gift
+                .tags
+                .iter()
+                .map(|tag| (tag, tag_match(interest, tag)))
+                .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+            if let Some((tag, value)) = best.filter(|(_, value)| *value > 0.0) {
+                total += value;
+                if !matched_tags.contains(tag) {
+                    matched_tags.push(tag.clone());
+                }
+            }
+        }
+        if total == 0.0 {
+            return None;
+        }
+        total / interests.len() as f64
+    };
+
+    let score = INTEREST_WEIGHT * relevance + (1.0 - INTEREST_WEIGHT) * fit;
+    Some(Score {
+        score: (score * 1000.0).round() / 1000.0,
+        matched_tags,
+    })
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct GiftSuggestion {
+    #[serde(flatten)]
+    gift: Gift,
+    /// 0 to 1; higher is a better match.
+    score: f64,
+    /// The gift's tags that matched an interest.
+    matched_tags: Vec<String>,
+}
+
+/// Gift ideas, best match first; ties go to the cheaper gift.
+#[utoipa::path(
+    tag = "gifts",
+    params(
+        ("budget" = Option<i64>, Query, description = "Most the caller wants to spend, in whole currency units"),
+        ("interests" = Option<String>, Query, description = "Comma-separated interests, e.g. `books,coffee`"),
+        ("limit" = Option<usize>, Query, description = "Most suggestions to return, 1 to 50 (default 10)"),
+    ),
+    responses(
+        (status = 200, body = Vec<GiftSuggestion>),
+        (status = 400, description = "Negative budget or too many interests", body = ErrorResponse),
+    )
+)]
+#[get("/api/gifts?<budget>&<interests>&<limit>")]
+async fn suggest(
+    storage: &Storage,
+    budget: Option<i64>,
+    interests: Option<&str>,
+    limit: Option<usize>,
+) -> ApiResult<Negotiated<Vec<GiftSuggestion>>> {
+    if budget.is_some_and(|budget| budget < 0) {
+        return Err(error(Status::BadRequest, "`budget` must not be negative"));
+    }
+    let mut wanted: Vec<String> = Vec::new();
+    for interest in interests.unwrap_or("").split(',') {
+        let interest = interest
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase();
+        if !interest.is_empty() && !wanted.contains(&interest) {
+            wanted.push(interest);
+        }
+    }
+    if wanted.len() > MAX_INTERESTS {
+        return Err(error(
+            Status::BadRequest,
+            format!("at most {} interests are allowed", MAX_INTERESTS),
+        ));
+    }
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let gifts = storage.list_gifts().await.map_err(internal_error)?;
+    let mut suggestions: Vec<GiftSuggestion> = gifts
+        .into_iter()
+        .filter_map(|gift| {
+            let Score {
+                score,
+                matched_tags,
+            } = score(&gift, &wanted, budget)?;
+            Some(GiftSuggestion {
+                gift,
+                score,
+                matched_tags,
+            })
+        })
+        .collect();
+    suggestions.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then(a.gift.price_min.cmp(&b.gift.price_min))
+            .then(a.gift.id.cmp(&b.gift.id))
+    });
+    suggestions.truncate(limit);
+
+    Ok(Negotiated(suggestions))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![suggest]
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogFile {
+    gifts: Vec<GiftRequest>,
+}
+
+/// Validates the catalog in `toml`, naming it `origin` in errors.
+pub(crate) fn parse_catalog(toml: Data<Toml>, origin: &str) -> Result<Vec<NewGift>, String> {
+    let file: CatalogFile = Figment::from(toml)
+        .extract()
+        .map_err(|e| format!("invalid gift catalog {}: {}", origin, e))?;
+
+    file.gifts
+        .into_iter()
+        .enumerate()
+        .map(|(i, gift)| {
+            gift.validate()
+                .map_err(|e| format!("{}: gift {}: {}", origin, i, e))
+        })
+        .collect()
+}
+
+/// Reads and validates the catalog at `path`; `None` when there is no file.
+fn read_catalog(path: &Path) -> Result<Option<Vec<NewGift>>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    parse_catalog(Toml::file(path), &path.display().to_string()).map(Some)
+}
+
+/// Seeds the gift catalog from `gifts_file` when the table is empty, so edits
+/// made through the admin API survive restarts. Must be attached after
+/// [`crate::storage::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Gift Catalog", |rocket| async {
+        let path = match rocket.figment().extract_inner::<PathBuf>("gifts_file") {
+            Ok(path) => path,
+            Err(e) if e.missing() => PathBuf::from(DEFAULT_GIFTS_FILE),
+            Err(e) => {
+                error!("invalid gifts_file: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let Some(storage) = rocket.state::<Storage>() else {
+            error!("the gift catalog requires the storage stage to be attached first");
+            return Err(rocket);
+        };
+        match storage.count_gifts().await {
+            Ok(0) => {}
+            Ok(_) => return Ok(rocket),
+            Err(e) => {
+                error!("failed to count gifts: {}", e);
+                return Err(rocket);
+            }
+        }
+
+        let gifts = match read_catalog(&path) {
+            Ok(Some(gifts)) => gifts,
+            Ok(None) => {
+                info!("no gift catalog at {}, starting empty", path.display());
+                return Ok(rocket);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return Err(rocket);
+            }
+        };
+        if let Err(e) = storage.import_gifts(&gifts).await {
+            error!("failed to load gift catalog {}: {}", path.display(), e);
+            return Err(rocket);
+        }
+        info!("loaded {} gifts from {}", gifts.len(), path.display());
+        Ok(rocket)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gift(price_min: i64, price_max: i64, tags: &[&str]) -> Gift {
+        Gift {
+            id: 1,
+            name: "Gift".to_string(),
+            description: String::new(),
+            price_min,
+            price_max,
+            url: None,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn gifts_are_scored_by_interests_and_budget() {
+        let interests = vec!["books".to_string(), "coffee".to_string()];
+        let both = gift(20, 40, &["books", "coffee", "mornings"]);
+        let one = gift(20, 40, &["book", "tea"]);
+
+        let both = score(&both, &interests, Some(50)).unwrap();
+        assert_eq!(both.score, 1.0);
+        assert_eq!(both.matched_tags, ["books", "coffee"]);
+
+        // Half of the interests, matched only partially, and fully in budget.
+        let one = score(&one, &interests, Some(50)).unwrap();
+        assert_eq!(one.score, 0.4);
+        assert_eq!(one.matched_tags, ["book"]);
+
+        assert_eq!(score(&gift(20, 40, &["music"]), &interests, None), None);
+        assert_eq!(score(&gift(60, 90, &["books"]), &interests, Some(50)), None);
+        let stretch = score(&gift(40, 90, &["books", "coffee"]), &interests, Some(50)).unwrap();
+        assert_eq!(stretch.score, 0.9);
+
+        assert_eq!(tag_match("games", "board-games"), 0.5);
+        assert_eq!(tag_match("art", "party"), 0.0);
+        assert_eq!(tag_match("bo", "books"), 0.0);
+        assert_eq!(score(&gift(0, 5, &["diy"]), &[], None).unwrap().score, 1.0);
+    }
+}
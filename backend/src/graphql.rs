@@ -0,0 +1,513 @@
+//! GraphQL API at `/graphql`, backed by the same storage and helpers as the
+//! REST routes. Mutations need the same `X-Api-Key` header as REST writes;
+//! subscriptions are served over WebSocket at `/graphql/ws`.
+
+use async_graphql::http::{GraphiQLSource, WebSocket, WebSocketProtocols as Protocols, WsMessage};
+use async_graphql::{ComplexObject, Context, Data, ErrorExtensions, Object, Schema, Subscription};
+use async_graphql_rocket::{GraphQLRequest, GraphQLResponse};
+use rocket::fairing::AdHoc;
+use rocket::futures::{future, SinkExt, Stream, StreamExt};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::content::RawHtml;
+use rocket::response::{self, Responder};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Route, Shutdown, State};
+use rocket_ws as ws;
+
+use crate::audit::Actor;
+use crate::auth::ApiKey;
+use crate::cache::QueryCache;
+use crate::config::PublicUrl;
+use crate::content_filter::ContentFilter;
+use crate::determinism::RequestRng;
+use crate::error::{internal_error, ApiError};
+use crate::events::EventBus;
+use crate::metrics::Metrics;
+use crate::notes::NotesFeed;
+use crate::pagination::{paginate, Page};
+use crate::proposal::{self, ProposalRequest};
+use crate::quote_sources::QuoteSources;
+use crate::reactions::{self, ClientFingerprint};
+use crate::stats::ServeCounter;
+use crate::storage::{
+    Answer, Category, Message, MessageQuery, MessageSort, Proposal, Quote, QuoteStatus,
+    ReactionCount, SortOrder, Storage,
+};
+use crate::tenants::QuoteStorage;
+use crate::users::CoupleScope;
+use crate::valentine::{self, ValentineSubmission};
+use crate::validation::Validate;
+
+/// Deepest selection set a query may use, so cyclic-looking queries cannot
+/// fan out without bound.
+const MAX_DEPTH: usize = 10;
+
+pub type ValentineSchema = Schema<Query, Mutation, SubscriptionRoot>;
+
+type Result<T> = async_graphql::Result<T>;
+
+/// Turns a REST error into a GraphQL error carrying the same message, with
+/// the HTTP status and error code in `extensions.status` and
+/// `extensions.code`.
+fn api_error(e: ApiError) -> async_graphql::Error {
+    async_graphql::Error::new(e.message()).extend_with(|_, ext| {
+        ext.set("status", e.status().code);
+        ext.set("code", e.code());
+        if let Some(details) = e.details().cloned() {
+            if let Ok(details) = async_graphql::Value::from_json(details) {
+                ext.set("details", details);
+            }
+        }
+    })
+}
+
+fn storage_error(e: sqlx::Error) -> async_graphql::Error {
+    api_error(internal_error(e))
+}
+
+/// The signed-in couple, as for REST routes; unscoped when absent.
+fn scope(ctx: &Context<'_>) -> CoupleScope {
+    ctx.data_opt::<CoupleScope>().copied().unwrap_or_default()
+}
+
+/// Present in the request data when the request carried a valid API key.
+struct Authorized;
+
+fn require_key(ctx: &Context<'_>) -> Result<()> {
+    match ctx.data_opt::<Authorized>() {
+        Some(_) => Ok(()),
+        None => Err(
+            async_graphql::Error::new("mutations require a valid X-Api-Key header")
+                .extend_with(|_, ext| ext.set("status", 401)),
+        ),
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    async fn quote(&self, ctx: &Context<'_>, id: i64) -> Result<Option<Quote>> {
+        ctx.data::<Storage>()?
+            .get_quote(id)
+            .await
+            .map(|quote| quote.filter(|q| q.status == QuoteStatus::Approved))
+            .map_err(storage_error)
+    }
+
+    async fn quotes(
+        &self,
+        ctx: &Context<'_>,
+        page: Option<i64>,
+        per_page: Option<i64>,
+    ) -> Result<Page<Quote>> {
+        let storage = ctx.data::<Storage>()?;
+        let (page, per_page, offset) = paginate(page, per_page);
+        let items = storage
+            .list_quotes(Some(QuoteStatus::Approved), per_page, offset)
+            .await
+            .map_err(storage_error)?;
+        let total = storage
+            .count_quotes(None, Some(QuoteStatus::Approved))
+            .await
+            .map_err(storage_error)?;
+
+        Ok(Page {
+            items,
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    /// A random quote, optionally from one category. The same `seed` picks
+    /// the same quote while the pool is unchanged.
+    async fn random_quote(
+        &self,
+        ctx: &Context<'_>,
+        category: Option<Category>,
+        seed: Option<u64>,
+    ) -> Result<Option<Quote>> {
+        let storage = &ctx.data::<QuoteStorage>()?.0;
+        let quote = ctx
+            .data::<QuoteSources>()?
+            .random_quote(storage, category, &mut RequestRng::new(seed))
+            .await
+            .map_err(storage_error)?;
+        ctx.data::<Metrics>()?
+            .quote_served("graphql", quote.as_ref().map(|q| q.category));
+        ctx.data::<ServeCounter>()?.served(storage, quote.as_ref());
+        Ok(quote)
+    }
+
+    async fn message(&self, ctx: &Context<'_>, id: i64) -> Result<Option<Message>> {
+        ctx.data::<Storage>()?
+            .get_message(id, scope(ctx).0)
+            .await
+            .map_err(storage_error)
+    }
+
+    /// Stored messages, newest first by default. `search` matches message
+    /// text case-insensitively.
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        page: Option<i64>,
+        per_page: Option<i64>,
+        #[graphql(default_with = "MessageSort::CreatedAt")] sort: MessageSort,
+        #[graphql(default_with = "SortOrder::Desc")] order: SortOrder,
+        search: Option<String>,
+    ) -> Result<Page<Message>> {
+        let (page, per_page, offset) = paginate(page, per_page);
+        let search = search.as_deref().map(str::trim).filter(|s| !s.is_empty());
+        let (items, total) = ctx
+            .data::<Storage>()?
+            .list_messages(MessageQuery {
+                couple: scope(ctx).0,
+                search,
+                sort,
+                order,
+                limit: per_page,
+                offset,
+            })
+            .await
+            .map_err(storage_error)?;
+
+        Ok(Page {
+            items,
+            page,
+            per_page,
+            total,
+        })
+    }
+
+    async fn proposal(&self, ctx: &Context<'_>, token: String) -> Result<Option<Proposal>> {
+        ctx.data::<Storage>()?
+            .get_proposal(&token)
+            .await
+            .map_err(storage_error)
+    }
+}
+
+#[ComplexObject]
+impl Message {
+    /// Reaction counts per emoji and sticker, most popular first.
+    async fn reactions(&self, ctx: &Context<'_>) -> Result<Vec<ReactionCount>> {
+        ctx.data::<Storage>()?
+            .reaction_counts(self.id)
+            .await
+            .map_err(storage_error)
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Same as `POST /api/valentine`: the message is also published to the
+    /// notes feed, webhooks and subscribed browsers.
+    async fn create_message(
+        &self,
+        ctx: &Context<'_>,
+        input: ValentineSubmission,
+    ) -> Result<Message> {
+        require_key(ctx)?;
+        let input = input.validate().map_err(|e| api_error(e.into()))?;
+        let message = valentine::create_message(
+            ctx.data::<Storage>()?,
+            ctx.data::<ContentFilter>()?,
+            ctx.data::<EventBus>()?,
+            ctx.data::<PublicUrl>()?,
+            ctx.data::<Actor>()?,
+            scope(ctx),
+            input,
+        )
+        .await
+        .map_err(api_error)?;
+        ctx.data::<QueryCache>()?.invalidate("list_messages");
+        Ok(message)
+    }
+
+    async fn create_proposal(&self, ctx: &Context<'_>, input: ProposalRequest) -> Result<Proposal> {
+        require_key(ctx)?;
+        let input = input.validate().map_err(|e| api_error(e.into()))?;
+        proposal::create_proposal(ctx.data::<Storage>()?, scope(ctx), input)
+            .await
+            .map_err(api_error)
+    }
+
+    async fn answer_proposal(
+        &self,
+        ctx: &Context<'_>,
+        token: String,
+        answer: Answer,
+    ) -> Result<Proposal> {
+        require_key(ctx)?;
+        proposal::answer_proposal(
+            ctx.data::<Storage>()?,
+            ctx.data::<EventBus>()?,
+            &token,
+            answer,
+        )
+        .await
+        .map_err(api_error)
+    }
+
+    /// Reacts to a message with exactly one of `emoji` and `stickerId`;
+    /// repeating the same reaction from the same client is a no-op. Returns
+    /// the message's updated reaction counts.
+    async fn react(
+        &self,
+        ctx: &Context<'_>,
+        message_id: i64,
+        emoji: Option<String>,
+        sticker_id: Option<i64>,
+    ) -> Result<Vec<ReactionCount>> {
+        require_key(ctx)?;
+        let storage = ctx.data::<Storage>()?;
+        reactions::add_reaction(
+            storage,
+            ctx.data::<ClientFingerprint>()?,
+            scope(ctx),
+            message_id,
+            emoji.as_deref(),
+            sticker_id,
+        )
+        .await
+        .map_err(api_error)?;
+        storage
+            .reaction_counts(message_id)
+            .await
+            .map_err(storage_error)
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Every valentine submitted from now on, like `/ws/notes`.
+    async fn message_created(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = Message>> {
+        let notes = ctx.data::<NotesFeed>()?.subscribe();
+        let tenant = ctx.data::<Storage>()?.tenant().map(str::to_string);
+        let couple = scope(ctx).0;
+        Ok(rocket::futures::stream::unfold(notes, move |mut notes| {
+            let tenant = tenant.clone();
+            async move {
+                loop {
+                    match notes.recv().await {
+                        Ok(note) if !note.visible_to(tenant.as_deref(), couple) => {}
+                        Ok(note) => return Some((note.message, notes)),
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("graphql subscriber lagged, skipped {} notes", skipped);
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        }))
+    }
+}
+
+#[utoipa::path(
+    tag = "graphql",
+    request_body(content = Object, description = "`{\"query\": \"...\", \"variables\": {...}}`"),
+    responses((status = 200, description = "GraphQL response; errors are reported in `errors`", body = Object)),
+)]
+#[post("/graphql", data = "<request>", format = "application/json")]
+#[allow(clippy::too_many_arguments)]
+async fn execute(
+    schema: &State<ValentineSchema>,
+    key: Option<ApiKey>,
+    client: ClientFingerprint,
+    actor: Actor,
+    scope: CoupleScope,
+    storage: &Storage,
+    public_url: &PublicUrl,
+    quotes: QuoteStorage,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut request = request
+        .data(client)
+        .data(actor)
+        .data(scope)
+        .data(storage.clone())
+        .data(public_url.clone())
+        .data(quotes);
+    if key.is_some() {
+        request = request.data(Authorized);
+    }
+    request.execute(schema.inner()).await
+}
+
+/// GraphiQL explorer for trying queries in the browser.
+#[utoipa::path(tag = "graphql", responses((status = 200, content_type = "text/html", body = String)))]
+#[get("/graphql")]
+fn graphiql() -> RawHtml<String> {
+    RawHtml(
+        GraphiQLSource::build()
+            .endpoint("/graphql")
+            .subscription_endpoint("/graphql/ws")
+            .finish(),
+    )
+}
+
+/// The subscription protocol the client asked for in
+/// `Sec-WebSocket-Protocol`, if it named one we speak.
+struct WsProtocol(Option<Protocols>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WsProtocol {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let protocol = request
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .flat_map(|value| value.split(','))
+            .find_map(|name| name.trim().parse().ok());
+        Outcome::Success(WsProtocol(protocol))
+    }
+}
+
+/// Upgrade response that echoes the negotiated subprotocol, which browsers
+/// require before they accept the socket.
+struct Subscriptions {
+    channel: ws::Channel<'static>,
+    protocol: Option<Protocols>,
+}
+
+impl<'r> Responder<'r, 'static> for Subscriptions {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.channel.respond_to(request)?;
+        if let Some(protocol) = self.protocol {
+            response.set_raw_header("Sec-WebSocket-Protocol", protocol.sec_websocket_protocol());
+        }
+        Ok(response)
+    }
+}
+
+#[utoipa::path(
+    tag = "graphql",
+    responses((status = 101, description = "`graphql-transport-ws` or `graphql-ws` subscriptions")),
+)]
+#[get("/graphql/ws")]
+fn subscriptions(
+    socket: ws::WebSocket,
+    schema: &State<ValentineSchema>,
+    protocol: WsProtocol,
+    scope: CoupleScope,
+    storage: &Storage,
+    mut shutdown: Shutdown,
+) -> Subscriptions {
+    let storage = storage.clone();
+    let schema = schema.inner().clone();
+    let WsProtocol(protocol) = protocol;
+
+    let channel = socket.channel(move |stream| {
+        Box::pin(async move {
+            let (mut sink, source) = stream.split();
+            let incoming = source
+                .take_while(|message| {
+                    future::ready(!matches!(message, Err(_) | Ok(ws::Message::Close(_))))
+                })
+                .filter_map(|message| {
+                    future::ready(match message {
+                        Ok(ws::Message::Text(text)) => Some(text.into_bytes()),
+                        Ok(ws::Message::Binary(bytes)) => Some(bytes),
+                        _ => None,
+                    })
+                });
+            let mut data = Data::default();
+            data.insert(scope);
+            data.insert(storage);
+            let mut outgoing =
+                WebSocket::new(schema, incoming, protocol.unwrap_or(Protocols::GraphQLWS))
+                    .connection_data(data);
+
+            loop {
+                select! {
+                    message = outgoing.next() => match message {
+                        Some(WsMessage::Text(text)) => sink.send(ws::Message::Text(text)).await?,
+                        Some(WsMessage::Close(code, reason)) => {
+                            let frame = ws::frame::CloseFrame {
+                                code: code.into(),
+                                reason: reason.into(),
+                            };
+                            sink.send(ws::Message::Close(Some(frame))).await?;
+                            break;
+                        }
+                        None => break,
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+
+            Ok(())
+        })
+    });
+
+    Subscriptions { channel, protocol }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![execute, graphiql, subscriptions]
+}
+
+/// Builds the schema from the managed feeds and config; each request adds
+/// the storage and public URL of its site. Must be attached after every
+/// stage whose state the resolvers use.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("GraphQL", |rocket| async {
+        macro_rules! state {
+            ($ty:ty) => {
+                match rocket.state::<$ty>() {
+                    Some(state) => state.clone(),
+                    None => {
+                        error!(
+                            "graphql stage attached before {} was managed",
+                            stringify!($ty)
+                        );
+                        return Err(rocket);
+                    }
+                }
+            };
+        }
+
+        let schema = Schema::build(Query, Mutation, SubscriptionRoot)
+            .data(state!(QuoteSources))
+            .data(state!(ContentFilter))
+            .data(state!(QueryCache))
+            .data(state!(NotesFeed))
+            .data(state!(EventBus))
+            .data(state!(Metrics))
+            .data(state!(ServeCounter))
+            .data(state!(reqwest::Client))
+            .limit_depth(MAX_DEPTH)
+            .finish();
+
+        Ok(rocket.manage(schema))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql::EmptyMutation;
+
+    #[test]
+    fn schema_exposes_every_root_field() {
+        let sdl = Schema::build(Query, EmptyMutation, SubscriptionRoot)
+            .finish()
+            .sdl();
+        for field in [
+            "randomQuote(category: Category, seed: Int): Quote",
+            "messages(page: Int, perPage: Int, sort: MessageSort! = CREATED_AT",
+            "reactions: [ReactionCount!]!",
+            "messageCreated: Message!",
+        ] {
+            assert!(sdl.contains(field), "missing `{}` in:\n{}", field, sdl);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use tonic::{Request, Response, Status};
+
+use super::proto::{self, message_service_server::MessageService};
+use super::{authorize, status, storage_error, timestamp};
+use crate::auth::ApiKeys;
+use crate::cache::QueryCache;
+use crate::config::PublicUrl;
+use crate::content_filter::ContentFilter;
+use crate::events::EventBus;
+use crate::pagination::paginate;
+use crate::storage::{Message, MessageQuery, MessageSort, SortOrder, Storage};
+use crate::users::CoupleScope;
+use crate::valentine::{self, ValentineSubmission};
+use crate::validation::Validate;
+
+pub struct Messages {
+    pub storage: Storage,
+    pub keys: ApiKeys,
+    pub filter: ContentFilter,
+    pub events: EventBus,
+    pub public_url: PublicUrl,
+    pub cache: QueryCache,
+}
+
+impl From<Message> for proto::Message {
+    fn from(message: Message) -> Self {
+        proto::Message {
+            id: message.id,
+            message: message.message,
+            from: message.sender,
+            to: message.recipient,
+            image_url: message.image_url,
+            sticker_id: message.sticker_id,
+            created_at: Some(timestamp(message.created_at)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl MessageService for Messages {
+    async fn get_message(
+        &self,
+        request: Request<proto::GetMessageRequest>,
+    ) -> Result<Response<proto::Message>, Status> {
+        let id = request.into_inner().id;
+        self.storage
+            .get_message(id, None)
+            .await
+            .map_err(storage_error)?
+            .map(|message| Response::new(message.into()))
+            .ok_or_else(|| Status::not_found(format!("no message with id {}", id)))
+    }
+
+    async fn list_messages(
+        &self,
+        request: Request<proto::ListMessagesRequest>,
+    ) -> Result<Response<proto::ListMessagesResponse>, Status> {
+        let request = request.into_inner();
+        let (page, per_page, offset) = paginate(request.page, request.per_page);
+        let search = request
+            .search
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty());
+        let (items, total) = self
+            .storage
+            .list_messages(MessageQuery {
+                couple: None,
+                search,
+                sort: MessageSort::CreatedAt,
+                order: SortOrder::Desc,
+                limit: per_page,
+                offset,
+            })
+            .await
+            .map_err(storage_error)?;
+
+        Ok(Response::new(proto::ListMessagesResponse {
+            items: items.into_iter().map(Into::into).collect(),
+            page,
+            per_page,
+            total,
+        }))
+    }
+
+    async fn create_message(
+        &self,
+        request: Request<proto::CreateMessageRequest>,
+    ) -> Result<Response<proto::Message>, Status> {
+        let actor = authorize(&self.keys, &request)?;
+        let request = request.into_inner();
+        let input = ValentineSubmission {
+            message: request.message,
+            from: request.from,
+            to: request.to,
+            image_url: request.image_url,
+            sticker_id: request.sticker_id,
+        }
+        .validate()
+        .map_err(|e| status(e.into()))?;
+        let message = valentine::create_message(
+            &self.storage,
+            &self.filter,
+            &self.events,
+            &self.public_url,
+            &actor,
+            CoupleScope::default(),
+            input,
+        )
+        .await
+        .map_err(status)?;
+        self.cache.invalidate("list_messages");
+        Ok(Response::new(message.into()))
+    }
+}
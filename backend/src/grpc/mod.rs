@@ -0,0 +1,211 @@
+//! gRPC API beside the HTTP one, from the services in `proto/`. It serves
+//! the main site's storage through the same helpers as the REST and GraphQL
+//! routes, so writes screen, audit and notify the same way. Writes need the
+//! same API key, sent as `x-api-key` metadata. Reflection is enabled, so
+//! `grpcurl -plaintext localhost:50051 list` works without the proto files.
+
+mod messages;
+mod proposals;
+mod quotes;
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::tokio::net::TcpListener;
+use serde::Deserialize;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Code, Request, Status};
+
+use crate::audit::Actor;
+use crate::auth::{self, ApiKeys};
+use crate::error::{internal_error, ApiError};
+use crate::workers::Workers;
+
+/// Code generated from `proto/` by the build script.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("valentine.v1");
+
+    /// Every service and message, for the reflection service.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("valentine_descriptor");
+}
+
+/// Metadata key carrying the API key, the gRPC spelling of `X-Api-Key`.
+const API_KEY: &str = "x-api-key";
+
+#[derive(Debug, Clone, Deserialize)]
+struct GrpcConfig {
+    #[serde(default = "default_address")]
+    address: IpAddr,
+    port: u16,
+}
+
+fn default_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+/// The gRPC status for a REST error, keeping its message.
+fn status(e: ApiError) -> Status {
+    let code = match e.status().code {
+        400 | 422 => Code::InvalidArgument,
+        401 => Code::Unauthenticated,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        409 => Code::FailedPrecondition,
+        413 => Code::OutOfRange,
+        429 => Code::ResourceExhausted,
+        503 => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    Status::new(code, e.message())
+}
+
+fn storage_error(e: sqlx::Error) -> Status {
+    status(internal_error(e))
+}
+
+fn timestamp(at: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+/// Checks the request's `x-api-key` like the `ApiKey` guard checks the
+/// header, and names the caller for the audit log.
+#[allow(clippy::result_large_err)]
+fn authorize<T>(keys: &ApiKeys, request: &Request<T>) -> Result<Actor, Status> {
+    let key = request
+        .metadata()
+        .get(API_KEY)
+        .and_then(|value| value.to_str().ok());
+    keys.check(key).map_err(Status::unauthenticated)?;
+    Ok(Actor(match key {
+        Some(key) => format!("key:{}", auth::fingerprint(key)),
+        None => "anonymous".to_string(),
+    }))
+}
+
+/// Binds the address from the optional `grpc` config and serves the
+/// services there once the server has launched, until shutdown. Without the
+/// config there is no gRPC server. Must be attached after every stage whose
+/// state the services use.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("gRPC", |rocket| async {
+        let config = match rocket.figment().extract_inner::<GrpcConfig>("grpc") {
+            Ok(config) => config,
+            Err(e) if e.missing() => return Ok(rocket),
+            Err(e) => {
+                error!("invalid grpc config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        macro_rules! state {
+            ($ty:ty) => {
+                match rocket.state::<$ty>() {
+                    Some(state) => state.clone(),
+                    None => {
+                        error!("grpc stage attached before {} was managed", stringify!($ty));
+                        return Err(rocket);
+                    }
+                }
+            };
+        }
+
+        let keys = state!(ApiKeys);
+        let storage = state!(crate::storage::Storage);
+        let quotes = quotes::Quotes {
+            storage: storage.clone(),
+            sources: state!(crate::quote_sources::QuoteSources),
+            metrics: state!(crate::metrics::Metrics),
+            serves: state!(crate::stats::ServeCounter),
+        };
+        let messages = messages::Messages {
+            storage: storage.clone(),
+            keys: keys.clone(),
+            filter: state!(crate::content_filter::ContentFilter),
+            events: state!(crate::events::EventBus),
+            public_url: state!(crate::config::PublicUrl),
+            cache: state!(crate::cache::QueryCache),
+        };
+        let proposals = proposals::Proposals {
+            storage,
+            keys,
+            events: state!(crate::events::EventBus),
+        };
+        let reflection = match tonic_reflection::server::Builder::configure()
+            .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+            .build_v1()
+        {
+            Ok(reflection) => reflection,
+            Err(e) => {
+                error!("failed to build grpc reflection: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let addr = SocketAddr::new(config.address, config.port);
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("failed to bind grpc server to {}: {}", addr, e);
+                return Err(rocket);
+            }
+        };
+        let router = tonic::transport::Server::builder()
+            .add_service(proto::quote_service_server::QuoteServiceServer::new(quotes))
+            .add_service(proto::message_service_server::MessageServiceServer::new(
+                messages,
+            ))
+            .add_service(proto::proposal_service_server::ProposalServiceServer::new(
+                proposals,
+            ))
+            .add_service(reflection);
+
+        Ok(
+            rocket.attach(AdHoc::on_liftoff("gRPC Server", move |rocket| {
+                Box::pin(async move {
+                    let Some(workers) = rocket.state::<Workers>() else {
+                        error!("grpc server not started: workers are unavailable");
+                        return;
+                    };
+                    info!("serving grpc on {}", addr);
+                    workers.spawn("grpc server", |token| async move {
+                        let incoming = TcpListenerStream::new(listener);
+                        if let Err(e) = router
+                            .serve_with_incoming_shutdown(incoming, token.cancelled_owned())
+                            .await
+                        {
+                            error!("grpc server failed: {}", e);
+                        }
+                    });
+                })
+            })),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status as HttpStatus;
+
+    use super::*;
+    use crate::error::error;
+
+    #[test]
+    fn api_errors_keep_their_message() {
+        let e = status(error(HttpStatus::NotFound, "no such proposal"));
+        assert_eq!(e.code(), Code::NotFound);
+        assert_eq!(e.message(), "no such proposal");
+
+        let e = status(error(HttpStatus::Conflict, "already answered"));
+        assert_eq!(e.code(), Code::FailedPrecondition);
+        assert_eq!(
+            status(error(HttpStatus::BadGateway, "x")).code(),
+            Code::Internal
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use tonic::{Request, Response, Status};
+
+use super::proto::{self, proposal_service_server::ProposalService};
+use super::{authorize, status, storage_error, timestamp};
+use crate::auth::ApiKeys;
+use crate::events::EventBus;
+use crate::proposal::{self, ProposalRequest};
+use crate::storage::{Answer, Proposal, Storage};
+use crate::users::CoupleScope;
+use crate::validation::Validate;
+
+pub struct Proposals {
+    pub storage: Storage,
+    pub keys: ApiKeys,
+    pub events: EventBus,
+}
+
+impl From<Proposal> for proto::Proposal {
+    fn from(proposal: Proposal) -> Self {
+        let answer = match proposal.answer {
+            None => proto::Answer::Unspecified,
+            Some(Answer::Yes) => proto::Answer::Yes,
+            Some(Answer::No) => proto::Answer::No,
+        };
+        proto::Proposal {
+            token: proposal.token,
+            question: proposal.question,
+            from: proposal.sender,
+            to: proposal.recipient,
+            answer: answer.into(),
+            answered_at: proposal.answered_at.map(timestamp),
+            created_at: Some(timestamp(proposal.created_at)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ProposalService for Proposals {
+    async fn get_proposal(
+        &self,
+        request: Request<proto::GetProposalRequest>,
+    ) -> Result<Response<proto::Proposal>, Status> {
+        self.storage
+            .get_proposal(&request.into_inner().token)
+            .await
+            .map_err(storage_error)?
+            .map(|proposal| Response::new(proposal.into()))
+            .ok_or_else(|| Status::not_found("no such proposal"))
+    }
+
+    async fn create_proposal(
+        &self,
+        request: Request<proto::CreateProposalRequest>,
+    ) -> Result<Response<proto::Proposal>, Status> {
+        authorize(&self.keys, &request)?;
+        let request = request.into_inner();
+        let input = ProposalRequest {
+            question: request.question,
+            from: request.from,
+            to: request.to,
+            callback_url: request.callback_url,
+        }
+        .validate()
+        .map_err(|e| status(e.into()))?;
+        let proposal = proposal::create_proposal(&self.storage, CoupleScope::default(), input)
+            .await
+            .map_err(status)?;
+        Ok(Response::new(proposal.into()))
+    }
+
+    async fn answer_proposal(
+        &self,
+        request: Request<proto::AnswerProposalRequest>,
+    ) -> Result<Response<proto::Proposal>, Status> {
+        authorize(&self.keys, &request)?;
+        let request = request.into_inner();
+        let answer = match request.answer() {
+            proto::Answer::Yes => Answer::Yes,
+            proto::Answer::No => Answer::No,
+            proto::Answer::Unspecified => {
+                return Err(Status::invalid_argument("answer must be YES or NO"))
+            }
+        };
+        let proposal =
+            proposal::answer_proposal(&self.storage, &self.events, &request.token, answer)
+                .await
+                .map_err(status)?;
+        Ok(Response::new(proposal.into()))
+    }
+}
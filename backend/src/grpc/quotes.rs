@@ -0,0 +1,102 @@
+use tonic::{Request, Response, Status};
+
+use super::proto::{self, quote_service_server::QuoteService};
+use super::{storage_error, timestamp};
+use crate::determinism::RequestRng;
+use crate::metrics::Metrics;
+use crate::pagination::paginate;
+use crate::quote_sources::QuoteSources;
+use crate::stats::ServeCounter;
+use crate::storage::{Category, Quote, QuoteStatus, Storage};
+
+pub struct Quotes {
+    pub storage: Storage,
+    pub sources: QuoteSources,
+    pub metrics: Metrics,
+    pub serves: ServeCounter,
+}
+
+fn category(category: proto::Category) -> Option<Category> {
+    match category {
+        proto::Category::Unspecified => None,
+        proto::Category::Romantic => Some(Category::Romantic),
+        proto::Category::Funny => Some(Category::Funny),
+        proto::Category::Poetic => Some(Category::Poetic),
+        proto::Category::LongDistance => Some(Category::LongDistance),
+    }
+}
+
+impl From<Quote> for proto::Quote {
+    fn from(quote: Quote) -> Self {
+        let category = match quote.category {
+            Category::Romantic => proto::Category::Romantic,
+            Category::Funny => proto::Category::Funny,
+            Category::Poetic => proto::Category::Poetic,
+            Category::LongDistance => proto::Category::LongDistance,
+        };
+        proto::Quote {
+            id: quote.id,
+            text: quote.text,
+            category: category.into(),
+            source: quote.source,
+            created_at: Some(timestamp(quote.created_at)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl QuoteService for Quotes {
+    async fn get_quote(
+        &self,
+        request: Request<proto::GetQuoteRequest>,
+    ) -> Result<Response<proto::Quote>, Status> {
+        let id = request.into_inner().id;
+        match self.storage.get_quote(id).await.map_err(storage_error)? {
+            Some(quote) if quote.status == QuoteStatus::Approved => Ok(Response::new(quote.into())),
+            _ => Err(Status::not_found(format!("no quote with id {}", id))),
+        }
+    }
+
+    async fn list_quotes(
+        &self,
+        request: Request<proto::ListQuotesRequest>,
+    ) -> Result<Response<proto::ListQuotesResponse>, Status> {
+        let request = request.into_inner();
+        let (page, per_page, offset) = paginate(request.page, request.per_page);
+        let items = self
+            .storage
+            .list_quotes(Some(QuoteStatus::Approved), per_page, offset)
+            .await
+            .map_err(storage_error)?;
+        let total = self
+            .storage
+            .count_quotes(None, Some(QuoteStatus::Approved))
+            .await
+            .map_err(storage_error)?;
+
+        Ok(Response::new(proto::ListQuotesResponse {
+            items: items.into_iter().map(Into::into).collect(),
+            page,
+            per_page,
+            total,
+        }))
+    }
+
+    async fn random_quote(
+        &self,
+        request: Request<proto::RandomQuoteRequest>,
+    ) -> Result<Response<proto::Quote>, Status> {
+        let category = category(request.into_inner().category());
+        let quote = self
+            .sources
+            .random_quote(&self.storage, category, &mut RequestRng::new(None))
+            .await
+            .map_err(storage_error)?;
+        self.metrics
+            .quote_served("grpc", quote.as_ref().map(|q| q.category));
+        self.serves.served(&self.storage, quote.as_ref());
+        quote
+            .map(|quote| Response::new(quote.into()))
+            .ok_or_else(|| Status::not_found("no quotes in the category"))
+    }
+}
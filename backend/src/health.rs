@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::tokio::time::timeout;
+use rocket::{Route, State};
+use serde::Serialize;
+
+use crate::email::Mailer;
+use crate::negotiate::Negotiated;
+use crate::scheduler::Scheduler;
+use crate::shared::SharedState;
+use crate::storage::Storage;
+
+/// Upper bound on each dependency probe, so a hung dependency makes the
+/// check fail instead of hanging the probe itself.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct HealthResponse {
+    status: String,
+    service: String,
+}
+
+fn alive() -> Negotiated<HealthResponse> {
+    Negotiated(HealthResponse {
+        status: "ok".to_string(),
+        service: "valentine-backend".to_string(),
+    })
+}
+
+#[utoipa::path(tag = "health", responses((status = 200, body = HealthResponse)))]
+#[get("/health")]
+fn health() -> Negotiated<HealthResponse> {
+    alive()
+}
+
+/// Liveness: the process is up and serving requests. Never touches
+/// dependencies, so a database outage does not get the pod restarted.
+#[utoipa::path(tag = "health", responses((status = 200, body = HealthResponse)))]
+#[get("/health/live")]
+fn live() -> Negotiated<HealthResponse> {
+    alive()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum CheckStatus {
+    Up,
+    Down,
+    /// Not configured, e.g. SMTP without an `smtp` table.
+    Disabled,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct Check {
+    status: CheckStatus,
+    /// Whether this dependency being down makes the service unready.
+    critical: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn disabled(critical: bool) -> Self {
+        Check {
+            status: CheckStatus::Disabled,
+            critical,
+            latency_ms: None,
+            error: None,
+        }
+    }
+
+    fn from_result(result: Result<(), String>, critical: bool, latency_ms: Option<u64>) -> Self {
+        let (status, error) = match result {
+            Ok(()) => (CheckStatus::Up, None),
+            Err(e) => (CheckStatus::Down, Some(e)),
+        };
+
+        Check {
+            status,
+            critical,
+            latency_ms,
+            error,
+        }
+    }
+}
+
+/// Runs `probe` under [`PROBE_TIMEOUT`] and times it.
+async fn probe<F>(critical: bool, probe: F) -> Check
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let result = match timeout(PROBE_TIMEOUT, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}s", PROBE_TIMEOUT.as_secs())),
+    };
+
+    Check::from_result(result, critical, Some(started.elapsed().as_millis() as u64))
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct Readiness {
+    /// `ok`, `degraded` (a non-critical dependency is down) or `unavailable`.
+    status: &'static str,
+    checks: BTreeMap<&'static str, Check>,
+}
+
+impl Readiness {
+    fn new(checks: BTreeMap<&'static str, Check>) -> Self {
+        let down = |critical: bool| {
+            checks
+                .values()
+                .any(|c| c.status == CheckStatus::Down && c.critical == critical)
+        };
+
+        let status = if down(true) {
+            "unavailable"
+        } else if down(false) {
+            "degraded"
+        } else {
+            "ok"
+        };
+
+        Readiness { status, checks }
+    }
+
+    fn http_status(&self) -> Status {
+        match self.status {
+            "unavailable" => Status::ServiceUnavailable,
+            _ => Status::Ok,
+        }
+    }
+}
+
+/// Readiness: probes the database, the schedule reveal worker, the SMTP
+/// server and Redis. Email is optional, so an SMTP outage only degrades the
+/// service; so does losing Redis, since rate limits then let requests through
+/// and notes reach only this instance's subscribers.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Ready, possibly degraded", body = Readiness),
+        (status = 503, description = "A critical dependency is down", body = Readiness),
+    )
+)]
+#[get("/health/ready")]
+async fn ready(
+    storage: &Storage,
+    scheduler: &State<Scheduler>,
+    mailer: &State<Mailer>,
+    shared: &State<Arc<dyn SharedState>>,
+) -> status::Custom<Negotiated<Readiness>> {
+    let database = probe(true, async {
+        storage.ping().await.map_err(|e| e.to_string())
+    });
+    let smtp = async {
+        if mailer.is_enabled() {
+            probe(false, mailer.test_connection()).await
+        } else {
+            Check::disabled(false)
+        }
+    };
+    let redis = async {
+        if shared.in_process() {
+            Check::disabled(false)
+        } else {
+            probe(false, shared.ping()).await
+        }
+    };
+    let (database, smtp, redis) = rocket::tokio::join!(database, smtp, redis);
+
+    let mut checks = BTreeMap::new();
+    checks.insert("database", database);
+    checks.insert(
+        "scheduler",
+        Check::from_result(scheduler.worker_health(), true, None),
+    );
+    checks.insert("smtp", smtp);
+    checks.insert("shared_state", redis);
+
+    let readiness = Readiness::new(checks);
+    status::Custom(readiness.http_status(), Negotiated(readiness))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![health, live, ready]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(status: CheckStatus, critical: bool) -> Check {
+        Check {
+            status,
+            critical,
+            latency_ms: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn overall_status_depends_on_criticality() {
+        let readiness = Readiness::new(BTreeMap::from([
+            ("database", check(CheckStatus::Up, true)),
+            ("smtp", check(CheckStatus::Disabled, false)),
+        ]));
+        assert_eq!(readiness.status, "ok");
+
+        let readiness = Readiness::new(BTreeMap::from([
+            ("database", check(CheckStatus::Up, true)),
+            ("smtp", check(CheckStatus::Down, false)),
+        ]));
+        assert_eq!(readiness.status, "degraded");
+        assert_eq!(readiness.http_status(), Status::Ok);
+
+        let readiness = Readiness::new(BTreeMap::from([
+            ("database", check(CheckStatus::Down, true)),
+            ("smtp", check(CheckStatus::Up, false)),
+        ]));
+        assert_eq!(readiness.status, "unavailable");
+        assert_eq!(readiness.http_status(), Status::ServiceUnavailable);
+    }
+}
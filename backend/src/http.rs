@@ -0,0 +1,185 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{redirect, Url};
+use rocket::fairing::AdHoc;
+use rocket::tokio::net::lookup_host;
+
+const USER_AGENT: &str = concat!("valentine-backend/", env!("CARGO_PKG_VERSION"));
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outbound client for URLs that API clients supply, i.e. webhooks and
+/// proposal callbacks. It only connects to public addresses and follows no
+/// redirects, so a callback cannot reach the server's own network, even
+/// through a hostname that resolved to a public address when it was
+/// registered.
+#[derive(Clone)]
+pub struct CallbackClient(pub reqwest::Client);
+
+/// Whether `ip` is reachable on the public internet, as opposed to this
+/// host, a private network, link-local addresses such as cloud metadata
+/// endpoints, or no address at all.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_public_v4(mapped),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network" and carrier-grade NAT.
+        || a == 0
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local (fc00::/7) and link-local (fe80::/10).
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Parses a callback URL, which must be absolute http(s) and must not name
+/// `localhost` or a non-public IP address. Hostnames are only resolved by
+/// [`resolve_callback_url`].
+pub fn parse_callback_url(url: &str) -> Result<Url, String> {
+    let parsed = Url::parse(url).map_err(|_| "must be an absolute http(s) URL".to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("must be an absolute http(s) URL".to_string());
+    }
+    let Some(host) = parsed.host_str().map(str::to_ascii_lowercase) else {
+        return Err("must be an absolute http(s) URL".to_string());
+    };
+    let local = match literal_ip(&host) {
+        Some(ip) => !is_public(ip),
+        None => host == "localhost" || host.ends_with(".localhost"),
+    };
+    if local {
+        return Err("must not point at a private or local address".to_string());
+    }
+    Ok(parsed)
+}
+
+/// The address a URL's host names directly, IPv6 ones in brackets.
+fn literal_ip(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Checks that every address the callback URL's host resolves to is
+/// public, for registering it. Deliveries are checked again by
+/// [`CallbackClient`]'s resolver, since DNS can change in between.
+pub async fn resolve_callback_url(url: &str) -> Result<(), String> {
+    let parsed = parse_callback_url(url)?;
+    let domain = parsed.host_str().unwrap_or_default();
+    if literal_ip(domain).is_some() {
+        return Ok(());
+    }
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addrs: Vec<SocketAddr> = lookup_host((domain, port))
+        .await
+        .map_err(|e| format!("host {} could not be resolved: {}", domain, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("host {} could not be resolved", domain));
+    }
+    if addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err("must not point at a private or local address".to_string());
+    }
+    Ok(())
+}
+
+/// Resolves hostnames to their public addresses only.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Manages a shared outbound `reqwest::Client` for external APIs, so
+/// connections are pooled across handlers and workers, and the
+/// [`CallbackClient`] for client-supplied URLs.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("HTTP Client", |rocket| async {
+        let builder = || {
+            reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(REQUEST_TIMEOUT)
+        };
+        let callbacks = builder()
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(redirect::Policy::none())
+            .build();
+        match (builder().build(), callbacks) {
+            (Ok(client), Ok(callbacks)) => {
+                Ok(rocket.manage(client).manage(CallbackClient(callbacks)))
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("failed to build http client: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn callbacks_must_not_point_inside_the_network() {
+        for url in [
+            "http://127.0.0.1:8000/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/",
+            "http://192.168.0.10/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://localhost:3000/",
+            "http://api.localhost/",
+            "ftp://example.com/",
+        ] {
+            assert!(parse_callback_url(url).is_err(), "{} was accepted", url);
+        }
+        assert!(parse_callback_url("https://hooks.example.com/valentine").is_ok());
+        assert!(parse_callback_url("http://93.184.216.34/").is_ok());
+        assert!(is_public("2606:4700::1111".parse().unwrap()));
+
+        // A name that resolves locally, as after DNS rebinding, is refused
+        // at delivery.
+        let name: Name = "localhost".parse().unwrap();
+        assert!(PublicResolver.resolve(name).await.is_err());
+    }
+}
@@ -0,0 +1,512 @@
+//! Scavenger hunts: an ordered chain of clues where each answer unlocks the
+//! next clue. Answers are compared after normalizing case, punctuation and
+//! spacing, and kept only as Argon2 hashes. A clue shows its hint after a
+//! few wrong answers, and takes only so many wrong answers an hour.
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{Hunt, HuntClue, NewHunt, NewHuntClue, Storage};
+use crate::users::{self, CoupleScope};
+use crate::valentine::MAX_NAME_LEN;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_TITLE_LEN: usize = 100;
+const MAX_CLUE_LEN: usize = 500;
+const MAX_ANSWER_LEN: usize = 100;
+const MAX_HINT_LEN: usize = 200;
+const MAX_CLUES: usize = 20;
+
+const DEFAULT_HINT_AFTER: i64 = 3;
+const DEFAULT_MAX_ATTEMPTS: i64 = 10;
+const MAX_ATTEMPTS_LIMIT: i64 = 100;
+
+/// The text answers are compared by: lowercase words, without punctuation
+/// or extra spaces, so "The Eiffel-Tower!" matches "the eiffel tower".
+pub fn normalize_answer(answer: &str) -> String {
+    answer
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ClueRequest {
+    clue: String,
+    answer: String,
+    /// Shown once the clue has been answered wrongly `hint_after` times.
+    hint: Option<String>,
+}
+
+/// A clue before its answer is hashed.
+struct ClueDraft {
+    clue: String,
+    answer: String,
+    hint: Option<String>,
+}
+
+impl Validate for ClueRequest {
+    type Valid = ClueDraft;
+
+    fn validate(self) -> Result<ClueDraft, FieldErrors> {
+        let clue = self.clue.trim().to_string();
+        let answer = normalize_answer(&self.answer);
+        let hint = self
+            .hint
+            .map(|hint| hint.trim().to_string())
+            .filter(|hint| !hint.is_empty());
+
+        let mut errors = FieldErrors::new();
+        errors.text("clue", &clue, MAX_CLUE_LEN);
+        if answer.is_empty() {
+            errors.add("answer", "`answer` must have at least one letter or digit");
+        } else {
+            errors.text("answer", &answer, MAX_ANSWER_LEN);
+        }
+        if let Some(hint) = &hint {
+            errors.text("hint", hint, MAX_HINT_LEN);
+        }
+        errors.finish(ClueDraft { clue, answer, hint })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct HuntRequest {
+    title: String,
+    from: String,
+    /// In the order they are solved; 1-20.
+    clues: Vec<ClueRequest>,
+    /// Wrong answers to a clue before its hint is shown; 3 by default.
+    hint_after: Option<i64>,
+    /// Wrong answers a clue takes within an hour; 10 by default.
+    max_attempts: Option<i64>,
+}
+
+struct HuntDraft {
+    title: String,
+    sender: String,
+    hint_after: i64,
+    max_attempts: i64,
+    clues: Vec<ClueDraft>,
+}
+
+impl Validate for HuntRequest {
+    type Valid = HuntDraft;
+
+    fn validate(self) -> Result<HuntDraft, FieldErrors> {
+        let title = self.title.trim().to_string();
+        let sender = self.from.trim().to_string();
+        let hint_after = self.hint_after.unwrap_or(DEFAULT_HINT_AFTER);
+        let max_attempts = self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS);
+
+        let mut errors = FieldErrors::new();
+        errors.text("title", &title, MAX_TITLE_LEN);
+        errors.text("from", &sender, MAX_NAME_LEN);
+        if self.clues.is_empty() || self.clues.len() > MAX_CLUES {
+            errors.add(
+                "clues",
+                format!("a hunt needs between 1 and {} clues", MAX_CLUES),
+            );
+        }
+        let clues = errors.nested("clues", self.clues).unwrap_or_default();
+        if !(1..=MAX_ATTEMPTS_LIMIT).contains(&max_attempts) {
+            errors.add(
+                "max_attempts",
+                format!(
+                    "`max_attempts` must be between 1 and {}",
+                    MAX_ATTEMPTS_LIMIT
+                ),
+            );
+        }
+        if hint_after < 0 {
+            errors.add("hint_after", "`hint_after` must not be negative");
+        }
+        errors.finish(HuntDraft {
+            title,
+            sender,
+            hint_after,
+            max_attempts,
+            clues,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct AnswerRequest {
+    answer: String,
+}
+
+impl Validate for AnswerRequest {
+    /// The normalized answer.
+    type Valid = String;
+
+    fn validate(self) -> Result<String, FieldErrors> {
+        let answer = normalize_answer(&self.answer);
+        let mut errors = FieldErrors::new();
+        if answer.is_empty() {
+            errors.add("answer", "`answer` must have at least one letter or digit");
+        } else {
+            errors.text("answer", &answer, MAX_ANSWER_LEN);
+        }
+        errors.finish(answer)
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct SolvedClue {
+    /// Counted from 1.
+    number: i64,
+    clue: String,
+    solved_at: DateTime<Utc>,
+}
+
+/// A hunt and the finder's progress through it. Answers are never shown.
+#[derive(Serialize, utoipa::ToSchema)]
+struct HuntResponse {
+    id: i64,
+    title: String,
+    from: String,
+    clue_count: i64,
+    solved: Vec<SolvedClue>,
+    /// Answers given so far, right or wrong.
+    attempts: i64,
+    completed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+impl HuntResponse {
+    fn new(hunt: Hunt, clues: &[HuntClue]) -> Self {
+        HuntResponse {
+            id: hunt.id,
+            title: hunt.title,
+            from: hunt.sender,
+            clue_count: hunt.clue_count,
+            solved: clues
+                .iter()
+                .filter_map(|clue| {
+                    Some(SolvedClue {
+                        number: clue.position + 1,
+                        clue: clue.clue.clone(),
+                        solved_at: clue.solved_at?,
+                    })
+                })
+                .collect(),
+            attempts: hunt.attempts,
+            completed: hunt.completed_at.is_some(),
+            completed_at: hunt.completed_at,
+            created_at: hunt.created_at,
+        }
+    }
+}
+
+/// The clue to solve next.
+#[derive(Serialize, utoipa::ToSchema)]
+struct CurrentClue {
+    /// Counted from 1.
+    number: i64,
+    of: i64,
+    clue: String,
+    /// Once `hint_after` wrong answers were given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+    /// Wrong answers so far.
+    failures: i64,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct AnswerResponse {
+    correct: bool,
+    completed: bool,
+    /// The clue to solve now: the next one after a right answer, the same
+    /// one after a wrong one, and none once the hunt is complete.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clue: Option<CurrentClue>,
+}
+
+async fn find(storage: &Storage, scope: CoupleScope, id: i64) -> ApiResult<Hunt> {
+    storage
+        .get_hunt(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no hunt with id {}", id)))
+}
+
+async fn current_clue(storage: &Storage, hunt: &Hunt) -> ApiResult<Option<CurrentClue>> {
+    if hunt.completed_at.is_some() {
+        return Ok(None);
+    }
+    let clues = storage
+        .list_hunt_clues(hunt.id)
+        .await
+        .map_err(internal_error)?;
+    let Some(clue) = clues.into_iter().nth(hunt.position as usize) else {
+        return Ok(None);
+    };
+    let failures = storage
+        .count_hunt_failures(hunt.id, clue.position, None)
+        .await
+        .map_err(internal_error)?;
+    Ok(Some(CurrentClue {
+        number: clue.position + 1,
+        of: hunt.clue_count,
+        clue: clue.clue,
+        hint: clue.hint.filter(|_| failures >= hunt.hint_after),
+        failures,
+    }))
+}
+
+/// Creates a hunt, hashing each answer. Only the first clue can be read
+/// until it is solved.
+#[utoipa::path(
+    tag = "hunts",
+    request_body = HuntRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = HuntResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/hunts", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    scope: CoupleScope,
+    request: Valid<HuntRequest>,
+) -> ApiResult<status::Created<Negotiated<HuntResponse>>> {
+    let hunt = request.into_inner();
+    let mut fields = vec![
+        ("title", hunt.title.as_str()),
+        ("from", hunt.sender.as_str()),
+    ];
+    for clue in &hunt.clues {
+        fields.push(("clues", clue.clue.as_str()));
+        if let Some(hint) = &clue.hint {
+            fields.push(("clues", hint.as_str()));
+        }
+    }
+    filter.screen(&fields).await?;
+
+    let HuntDraft {
+        title,
+        sender,
+        hint_after,
+        max_attempts,
+        clues,
+    } = hunt;
+    let clues = users::blocking(move || {
+        clues
+            .into_iter()
+            .map(|clue| {
+                Ok(NewHuntClue {
+                    answer_hash: users::hash_password(&clue.answer)?,
+                    clue: clue.clue,
+                    hint: clue.hint,
+                })
+            })
+            .collect::<Result<Vec<_>, argon2::password_hash::Error>>()
+    })
+    .await?
+    .map_err(|e| {
+        error!("failed to hash hunt answers: {}", e);
+        error(Status::InternalServerError, "internal error")
+    })?;
+
+    let hunt = storage
+        .create_hunt(&NewHunt {
+            title,
+            sender,
+            hint_after,
+            max_attempts,
+            clues,
+            couple_id: scope.0,
+        })
+        .await
+        .map_err(internal_error)?;
+    let clues = storage
+        .list_hunt_clues(hunt.id)
+        .await
+        .map_err(internal_error)?;
+    let location = uri!(get(hunt.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(HuntResponse::new(hunt, &clues))))
+}
+
+/// The hunt with the clues solved so far and whether it is complete.
+#[utoipa::path(
+    tag = "hunts",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, body = HuntResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/hunts/<id>")]
+async fn get(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<HuntResponse>> {
+    let hunt = find(storage, scope, id).await?;
+    let clues = storage
+        .list_hunt_clues(hunt.id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Negotiated(HuntResponse::new(hunt, &clues)))
+}
+
+#[utoipa::path(
+    tag = "hunts",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, body = CurrentClue),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The hunt is complete", body = ErrorResponse),
+    )
+)]
+#[get("/api/hunts/<id>/clue")]
+async fn clue(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<CurrentClue>> {
+    let hunt = find(storage, scope, id).await?;
+    current_clue(storage, &hunt)
+        .await?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::Conflict, "this hunt is already complete"))
+}
+
+/// Answers the current clue. A wrong answer is not an error: it returns
+/// `correct: false` with the same clue, and its hint once unlocked.
+#[utoipa::path(
+    tag = "hunts",
+    params(("id" = i64, Path)),
+    request_body = AnswerRequest,
+    responses(
+        (status = 200, body = AnswerResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "The hunt is complete", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+        (status = 429, description = "Too many wrong answers to this clue in the last hour", body = ErrorResponse),
+    )
+)]
+#[post("/api/hunts/<id>/answer", data = "<request>")]
+async fn answer(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+    request: Valid<AnswerRequest>,
+) -> ApiResult<Negotiated<AnswerResponse>> {
+    let hunt = find(storage, scope, id).await?;
+    if hunt.completed_at.is_some() {
+        return Err(error(Status::Conflict, "this hunt is already complete"));
+    }
+    let clue = storage
+        .list_hunt_clues(hunt.id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .nth(hunt.position as usize)
+        .ok_or_else(|| error(Status::Conflict, "this hunt is already complete"))?;
+
+    // Claimed before the slow hash check, so parallel answers share the
+    // limit.
+    let Some(attempt) = storage
+        .claim_hunt_attempt(
+            hunt.id,
+            clue.position,
+            Utc::now() - Duration::hours(1),
+            hunt.max_attempts,
+        )
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err(error(
+            Status::TooManyRequests,
+            "too many wrong answers to this clue; try again in a while",
+        ));
+    };
+
+    let given = request.into_inner();
+    let hash = clue.answer_hash;
+    let correct = users::blocking(move || users::verify_password(&given, &hash)).await?;
+    storage
+        .answer_hunt_clue(attempt, hunt.id, clue.position, correct)
+        .await
+        .map_err(internal_error)?;
+
+    let hunt = find(storage, scope, id).await?;
+    Ok(Negotiated(AnswerResponse {
+        correct,
+        completed: hunt.completed_at.is_some(),
+        clue: current_clue(storage, &hunt).await?,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, get, clue, answer]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_match_regardless_of_case_and_punctuation() {
+        assert_eq!(normalize_answer("  The Eiffel-Tower! "), "the eiffel tower");
+        assert_eq!(normalize_answer("Café  №5"), "café 5");
+        assert_eq!(normalize_answer("?!"), "");
+
+        let hunt = HuntRequest {
+            title: "Our first year".to_string(),
+            from: "Alex".to_string(),
+            clues: vec![
+                ClueRequest {
+                    clue: "Where we met".to_string(),
+                    answer: "The Library".to_string(),
+                    hint: Some(" Books ".to_string()),
+                },
+                ClueRequest {
+                    clue: "Our song".to_string(),
+                    answer: "...".to_string(),
+                    hint: None,
+                },
+            ],
+            hint_after: None,
+            max_attempts: Some(0),
+        };
+        let errors = hunt.validate().err().unwrap().to_string();
+        assert!(errors.contains("`answer` must have"), "{}", errors);
+        assert!(errors.contains("`max_attempts`"), "{}", errors);
+
+        let hunt = HuntRequest {
+            title: "Our first year".to_string(),
+            from: "Alex".to_string(),
+            clues: vec![ClueRequest {
+                clue: "Where we met".to_string(),
+                answer: "The Library".to_string(),
+                hint: Some(" Books ".to_string()),
+            }],
+            hint_after: None,
+            max_attempts: None,
+        }
+        .validate()
+        .ok()
+        .unwrap();
+        assert_eq!(hunt.hint_after, DEFAULT_HINT_AFTER);
+        assert_eq!(hunt.clues[0].answer, "the library");
+        assert_eq!(hunt.clues[0].hint.as_deref(), Some("Books"));
+    }
+}
@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+
+use crate::storage::{Quote, Storage};
+
+/// Language of the quotes stored in the `quotes` table.
+pub const DEFAULT_LANG: &str = "en";
+
+const DEFAULT_LOCALES_DIR: &str = "locales";
+
+/// Where quote translations are loaded from, so databases opened after
+/// launch can load them too.
+pub struct LocalesDir(pub PathBuf);
+
+/// Normalises a language tag to its lowercase primary subtag, e.g. `es-MX`
+/// becomes `es`. Returns `None` for anything that is not a 2-3 letter code.
+pub fn normalize_lang(tag: &str) -> Option<String> {
+    let primary = tag.trim().split(['-', '_']).next()?;
+    let valid =
+        (2..=3).contains(&primary.len()) && primary.chars().all(|c| c.is_ascii_alphabetic());
+    valid.then(|| primary.to_ascii_lowercase())
+}
+
+/// Parses an `Accept-Language` header into language codes ordered by
+/// preference. Entries with `q=0`, wildcards and malformed tags are dropped.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';');
+            let lang = normalize_lang(parts.next()?)?;
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            (q > 0.0).then_some((lang, q))
+        })
+        .collect();
+
+    // Stable sort keeps header order for equal weights.
+    weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut langs: Vec<String> = Vec::new();
+    for (lang, _) in weighted {
+        if !langs.contains(&lang) {
+            langs.push(lang);
+        }
+    }
+    langs
+}
+
+/// Languages the client asked for via `Accept-Language`, most preferred first.
+pub struct AcceptLanguage(pub Vec<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptLanguage {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let langs = request
+            .headers()
+            .get_one("Accept-Language")
+            .map(parse_accept_language)
+            .unwrap_or_default();
+        Outcome::Success(AcceptLanguage(langs))
+    }
+}
+
+/// Returns the quote text in the first preferred language that has a
+/// translation, along with that language's code, falling back to English.
+pub async fn translate(
+    storage: &Storage,
+    quote: &Quote,
+    preferences: &[String],
+) -> Result<(String, String), sqlx::Error> {
+    for lang in preferences {
+        if lang == DEFAULT_LANG {
+            break;
+        }
+        if let Some(text) = storage.get_translation(quote.id, lang).await? {
+            return Ok((lang.clone(), text));
+        }
+    }
+
+    Ok((DEFAULT_LANG.to_string(), quote.text.clone()))
+}
+
+#[derive(Debug, Deserialize)]
+struct LocaleFile {
+    #[serde(default)]
+    quotes: Vec<TranslatedQuote>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TranslatedQuote {
+    /// The English quote text, matched exactly against `quotes.text`.
+    source: String,
+    text: String,
+}
+
+/// Reads one `<lang>.toml` or `<lang>.json` file.
+fn read_locale_file(path: &Path) -> Result<LocaleFile, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => Figment::from(Toml::file(path))
+            .extract()
+            .map_err(|e| e.to_string()),
+        Some("json") => {
+            let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            rocket::serde::json::from_str(&contents).map_err(|e| e.to_string())
+        }
+        _ => Err("expected a .toml or .json file".to_string()),
+    }
+}
+
+/// Loads every locale file in `dir` into the translations table. Files are
+/// named after their language code; quotes whose `source` matches no stored
+/// quote are skipped with a warning.
+pub async fn load_locales(storage: &Storage, dir: &Path) -> Result<(), String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!(
+                "no locales directory at {}, serving English only",
+                dir.display()
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(format!("failed to read {}: {}", dir.display(), e)),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let Some(lang) = normalize_lang(stem).filter(|lang| lang == stem) else {
+            warn!(
+                "skipping {}: file name is not a language code",
+                path.display()
+            );
+            continue;
+        };
+        if lang == DEFAULT_LANG {
+            continue;
+        }
+
+        let file = read_locale_file(&path)
+            .map_err(|e| format!("invalid locale file {}: {}", path.display(), e))?;
+
+        let mut loaded = 0;
+        for quote in &file.quotes {
+            let matched = storage
+                .upsert_translation(&quote.source, &lang, quote.text.trim())
+                .await
+                .map_err(|e| format!("failed to store {} translation: {}", lang, e))?;
+            if matched {
+                loaded += 1;
+            } else {
+                warn!(
+                    "{}: no quote matches source `{}`",
+                    path.display(),
+                    quote.source
+                );
+            }
+        }
+        info!("loaded {} `{}` quote translations", loaded, lang);
+    }
+
+    Ok(())
+}
+
+/// Loads quote translations from the `locales_dir` directory (default
+/// `locales/`) once storage is ready. Must be attached after
+/// [`crate::storage::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Locales", |rocket| async {
+        let dir = match rocket.figment().extract_inner::<PathBuf>("locales_dir") {
+            Ok(dir) => dir,
+            Err(e) if e.missing() => PathBuf::from(DEFAULT_LOCALES_DIR),
+            Err(e) => {
+                error!("invalid locales_dir: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let Some(storage) = rocket.state::<Storage>() else {
+            error!("locales require the storage stage to be attached first");
+            return Err(rocket);
+        };
+
+        match load_locales(storage, &dir).await {
+            Ok(()) => Ok(rocket.manage(LocalesDir(dir))),
+            Err(e) => {
+                error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_language_is_ordered_by_quality() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec!["fr", "en", "de"]
+        );
+        assert_eq!(parse_accept_language("en;q=0.5, es-MX"), vec!["es", "en"]);
+        assert_eq!(parse_accept_language("es;q=0, pt"), vec!["pt"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn language_tags_are_normalized() {
+        assert_eq!(normalize_lang("ES").as_deref(), Some("es"));
+        assert_eq!(normalize_lang("pt_BR").as_deref(), Some("pt"));
+        assert_eq!(normalize_lang("spanish"), None);
+        assert_eq!(normalize_lang("e1"), None);
+    }
+}
@@ -0,0 +1,415 @@
+//! Safe retries of `POST` requests. A client that sends an
+//! `Idempotency-Key` header gets the stored response back when it repeats
+//! the request within [`KEY_TTL_HOURS`], instead of sending a second
+//! valentine or email. Successful responses are kept; failures are not, so
+//! a retry after an error runs the request again. Keys belong to the caller
+//! that sent them, and responses carrying credentials are never kept (see
+//! [`NoStore`]).
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Header, Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::{self, Responder};
+use rocket::tokio;
+use rocket::{Data, Request, Response};
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+use crate::envelope;
+use crate::error::error;
+use crate::storage::{Claim, Storage, StoredResponse};
+use crate::tenants::Tenants;
+use crate::workers::Workers;
+use crate::{auth, tokens, users};
+
+pub const HEADER: &str = "Idempotency-Key";
+
+/// Set on responses that are replays of a stored one.
+const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// How long a key and its response are kept.
+pub const KEY_TTL_HOURS: i64 = 24;
+
+const MAX_KEY_LEN: usize = 255;
+
+/// How much of a body fairings may look at.
+const PEEK_LEN: usize = 512;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Internal route that repeated requests are rewritten to, so the original
+/// handler (and its side effects) does not run again.
+const REPLAY_PATH: &str = "/__idempotent_replay";
+
+/// What the fairing decided for a request, for the replay route and
+/// [`Idempotency::on_response`].
+#[derive(Default)]
+enum Decision {
+    #[default]
+    Untracked,
+    /// The request runs; its response is stored under the caller's key.
+    Claimed {
+        caller: String,
+        key: String,
+        request_hash: String,
+    },
+    Replay(StoredResponse),
+    Rejected(Status, String),
+}
+
+/// Printable ASCII, as in the header drafts and the usual UUIDs.
+fn is_valid_key(key: &str) -> bool {
+    (1..=MAX_KEY_LEN).contains(&key.len()) && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Who sent a request, so one caller's keys never answer another's: the
+/// signed-in user, else the hash of the API key presented, else the client
+/// address.
+async fn caller(request: &Request<'_>) -> String {
+    if let Some(id) = users::signed_in_id(request).await {
+        return format!("user:{}", id);
+    }
+    match (auth::presented_key(request), request.client_ip()) {
+        (Some(key), _) => format!("key:{}", tokens::token_hash(key)),
+        (None, Some(ip)) => format!("ip:{}", ip),
+        (None, None) => "anonymous".to_string(),
+    }
+}
+
+/// Hex SHA-256 of the request's content type, declared length and body.
+/// Fairings only see the first 512 bytes of a body, so longer bodies are
+/// told apart by their length and that prefix.
+async fn request_hash(request: &Request<'_>, data: &mut Data<'_>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(
+        request
+            .content_type()
+            .map(|t| t.to_string())
+            .unwrap_or_default(),
+    );
+    hasher.update([0]);
+    hasher.update(request.headers().get_one("Content-Length").unwrap_or(""));
+    hasher.update([0]);
+    hasher.update(data.peek(PEEK_LEN).await);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// What to do with a `POST` to `path` with `hash` given what the key was
+/// found to be.
+fn decide(caller: &str, key: &str, path: &str, hash: &str, claim: Claim) -> Decision {
+    let (used, used_hash) = match &claim {
+        Claim::Claimed => {
+            return Decision::Claimed {
+                caller: caller.to_string(),
+                key: key.to_string(),
+                request_hash: hash.to_string(),
+            }
+        }
+        Claim::InProgress { path, request_hash } => (path, request_hash),
+        Claim::Done(stored) => (&stored.path, &stored.request_hash),
+    };
+    if used != path {
+        return Decision::Rejected(
+            Status::UnprocessableEntity,
+            format!("this `{}` was already used for POST {}", HEADER, used),
+        );
+    }
+    if used_hash != hash {
+        return Decision::Rejected(
+            Status::UnprocessableEntity,
+            format!("this `{}` was already used with a different body", HEADER),
+        );
+    }
+    match claim {
+        Claim::Done(stored) => Decision::Replay(stored),
+        _ => Decision::Rejected(
+            Status::Conflict,
+            format!("a request with this `{}` is still in progress", HEADER),
+        ),
+    }
+}
+
+/// Wraps the response of an endpoint that hands out credentials, such as
+/// tokens or secrets, with `Cache-Control: no-store`. [`Idempotency`] never
+/// keeps such responses, so a retry issues new credentials instead of
+/// replaying the old ones.
+pub struct NoStore<R>(pub R);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for NoStore<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(request)?;
+        response.set_header(Header::new("Cache-Control", "no-store"));
+        Ok(response)
+    }
+}
+
+/// Whether a response may be kept for replay: a success that sets no
+/// cookie, as sign-in does, and is not marked [`NoStore`].
+fn is_kept(response: &Response<'_>) -> bool {
+    let no_store = response
+        .headers()
+        .get("Cache-Control")
+        .any(|value| value.split(',').any(|d| d.trim() == "no-store"));
+    response.status().class().is_success()
+        && !response.headers().contains("Set-Cookie")
+        && !no_store
+        && response.body().preset_size().is_some()
+}
+
+/// Claims the `Idempotency-Key` of each `POST` and stores the response, or
+/// rewrites repeats to [`REPLAY_PATH`]. Attach it after tenant routing, so
+/// each site keeps its own keys, and after the envelope, so the stored body
+/// is the one that was sent.
+struct Idempotency;
+
+#[rocket::async_trait]
+impl Fairing for Idempotency {
+    fn info(&self) -> Info {
+        Info {
+            name: "Idempotency Keys",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        if request.method() != Method::Post {
+            return;
+        }
+        let Some(key) = request.headers().get_one(HEADER).map(str::to_string) else {
+            return;
+        };
+        let decision = if !is_valid_key(&key) {
+            Decision::Rejected(
+                Status::BadRequest,
+                format!(
+                    "`{}` must be 1 to {} printable ASCII characters",
+                    HEADER, MAX_KEY_LEN
+                ),
+            )
+        } else {
+            let Outcome::Success(storage) = request.guard::<&Storage>().await else {
+                return;
+            };
+            let caller = caller(request).await;
+            let path = request.uri().to_string();
+            let hash = request_hash(request, data).await;
+            let since = Utc::now() - chrono::Duration::hours(KEY_TTL_HOURS);
+            match storage
+                .claim_idempotency_key(&caller, &key, &path, &hash, since)
+                .await
+            {
+                Ok(claim) => decide(&caller, &key, &path, &hash, claim),
+                Err(e) => {
+                    error!("failed to claim idempotency key, running request: {}", e);
+                    return;
+                }
+            }
+        };
+
+        let rewrite = !matches!(decision, Decision::Claimed { .. });
+        request.local_cache(|| decision);
+        if rewrite {
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(REPLAY_PATH).expect("valid internal path"));
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Decision::Claimed {
+            caller,
+            key,
+            request_hash,
+        } = request.local_cache(Decision::default)
+        else {
+            return;
+        };
+        let Outcome::Success(storage) = request.guard::<&Storage>().await else {
+            return;
+        };
+
+        let result = if is_kept(response) {
+            match response.body_mut().to_bytes().await {
+                Ok(body) => {
+                    let stored = StoredResponse {
+                        path: request.uri().to_string(),
+                        request_hash: request_hash.clone(),
+                        status: response.status().code,
+                        content_type: response.content_type().map(|t| t.to_string()),
+                        location: response.headers().get_one("Location").map(str::to_string),
+                        body,
+                    };
+                    response.set_sized_body(stored.body.len(), Cursor::new(stored.body.clone()));
+                    storage.complete_idempotency_key(caller, key, &stored).await
+                }
+                Err(e) => {
+                    error!("failed to read response body: {}", e);
+                    storage.release_idempotency_key(caller, key).await
+                }
+            }
+        } else {
+            storage.release_idempotency_key(caller, key).await
+        };
+        if let Err(e) = result {
+            error!("failed to store the response to idempotency key: {}", e);
+        }
+    }
+}
+
+/// The decision for a rewritten request; forwards on anything else, so the
+/// replay path is a 404 when requested directly.
+struct Rewritten<'r>(&'r Decision);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Rewritten<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        match request.local_cache(Decision::default) {
+            decision @ (Decision::Replay(_) | Decision::Rejected(..)) => {
+                Outcome::Success(Rewritten(decision))
+            }
+            _ => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Rewritten<'r> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let stored = match self.0 {
+            Decision::Replay(stored) => stored,
+            Decision::Rejected(status, message) => {
+                return error(*status, message.clone()).respond_to(request)
+            }
+            _ => return Err(Status::NotFound),
+        };
+        // Already enveloped when it was first sent.
+        envelope::mark_enveloped(request);
+        let mut response = Response::build();
+        response
+            .status(Status::new(stored.status))
+            .header(Header::new(REPLAYED_HEADER, "true"))
+            .sized_body(stored.body.len(), Cursor::new(stored.body.clone()));
+        if let Some(content_type) = stored
+            .content_type
+            .as_deref()
+            .and_then(ContentType::parse_flexible)
+        {
+            response.header(content_type);
+        }
+        if let Some(location) = &stored.location {
+            response.header(Header::new("Location", location.clone()));
+        }
+        response.ok()
+    }
+}
+
+#[get("/__idempotent_replay")]
+fn replay(rewritten: Rewritten<'_>) -> Rewritten<'_> {
+    rewritten
+}
+
+/// Deletes keys older than [`KEY_TTL_HOURS`] every [`PURGE_INTERVAL`] until
+/// `token` is cancelled.
+async fn run_purger(storage: Storage, token: CancellationToken) {
+    while !token.is_cancelled() {
+        let before = Utc::now() - chrono::Duration::hours(KEY_TTL_HOURS);
+        match storage.purge_idempotency_keys(before).await {
+            Ok(0) => {}
+            Ok(purged) => info!("purged {} expired idempotency keys", purged),
+            Err(e) => error!("failed to purge idempotency keys: {}", e),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(PURGE_INTERVAL) => {}
+            _ = token.cancelled() => {}
+        }
+    }
+}
+
+/// Attaches [`Idempotency`] and its replay route, and starts a purge worker
+/// for each database once the server has launched.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Idempotency", |rocket| async {
+        rocket
+            .attach(Idempotency)
+            .mount("/", routes![replay])
+            .attach(AdHoc::on_liftoff("Idempotency Key Purger", |rocket| {
+                Box::pin(async move {
+                    match (rocket.state::<Tenants>(), rocket.state::<Workers>()) {
+                        (Some(tenants), Some(workers)) => {
+                            let workers = workers.clone();
+                            tenants.for_each_database(move |storage| {
+                                workers.spawn("idempotency key purger", |token| {
+                                    run_purger(storage, token)
+                                });
+                            });
+                        }
+                        _ => error!(
+                            "idempotency key purger not started: storage or workers are unavailable"
+                        ),
+                    }
+                })
+            }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn done(path: &str) -> Claim {
+        Claim::Done(StoredResponse {
+            path: path.to_string(),
+            request_hash: "h".to_string(),
+            status: 201,
+            content_type: None,
+            location: None,
+            body: b"{}".to_vec(),
+        })
+    }
+
+    #[test]
+    fn repeats_replay_only_for_the_same_request() {
+        let decide = |hash, claim| decide("user:1", "k", "/api/valentine", hash, claim);
+        assert!(matches!(
+            decide("h", Claim::Claimed),
+            Decision::Claimed { caller, key, .. } if caller == "user:1" && key == "k"
+        ));
+        assert!(matches!(
+            decide("h", done("/api/valentine")),
+            Decision::Replay(_)
+        ));
+        let rejected = |hash, claim| match decide(hash, claim) {
+            Decision::Rejected(status, message) => (status, message),
+            _ => panic!("expected a rejection"),
+        };
+        assert_eq!(
+            rejected("h", done("/api/proposals")).0,
+            Status::UnprocessableEntity
+        );
+        let (status, message) = rejected("other", done("/api/valentine"));
+        assert_eq!(status, Status::UnprocessableEntity);
+        assert!(message.contains("different body"), "{}", message);
+        let in_progress = |path: &str| Claim::InProgress {
+            path: path.to_string(),
+            request_hash: "h".to_string(),
+        };
+        assert_eq!(
+            rejected("h", in_progress("/api/valentine")).0,
+            Status::Conflict
+        );
+
+        assert!(is_valid_key("3f2c9a4e-1b7d-4c55-9e0a-7d1c2b3a4f5e"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("has space"));
+    }
+}
@@ -0,0 +1,710 @@
+//! Restoring an archive from `GET /api/export` into the signed-in couple's
+//! space (or the shared one, with only a key). Records already present are
+//! skipped, so importing the same archive twice adds nothing the second
+//! time, and everything else is added in one transaction.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Cursor;
+
+use async_zip::base::read1::seek::ZipArchiveReader;
+use async_zip::base::read1::ZipOptions;
+use rocket::data::{ByteUnit, Data, Limits};
+use rocket::http::{ContentType, Status};
+use rocket::tokio::io::AsyncReadExt;
+use rocket::{Route, State};
+use serde::Serialize;
+use tokio_util::compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+
+use crate::audit::{self, Actor};
+use crate::auth::ApiKey;
+use crate::body_limits::RouteLimit;
+use crate::cache::QueryCache;
+use crate::config::PublicUrl;
+use crate::dates::MAX_TITLE_LEN;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::export::{
+    Archive, ArchivedDate, ArchivedMemory, ArchivedMessage, ArchivedUpload, ARCHIVE_FILE,
+    ARCHIVE_VERSION, MEDIA_DIR,
+};
+use crate::memories::MAX_CAPTION_LEN;
+use crate::negotiate::Negotiated;
+use crate::storage::{
+    AuditAction, AuditEntity, ImportBatch, Message, NewImportantDate, NewMemory, NewMessage,
+    QuizScore, Restored, Storage, Upload,
+};
+use crate::uploads::{self, UploadStore, ALLOWED_TYPES};
+use crate::users::CoupleScope;
+use crate::valentine::{MAX_MESSAGE_LEN, MAX_NAME_LEN};
+use crate::validation::{self, FieldErrors, Validate};
+
+/// Largest archive accepted, and what it may unpack to, unless a
+/// `body_limits` entry or `limits.import` says otherwise.
+const DEFAULT_LIMIT: ByteUnit = ByteUnit::Mebibyte(64);
+
+/// Longest quiz id or result title.
+const MAX_QUIZ_TEXT_LEN: usize = 100;
+
+fn trimmed(value: String) -> String {
+    value.trim().to_string()
+}
+
+impl Validate for ArchivedMessage {
+    type Valid = ArchivedMessage;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let message = ArchivedMessage {
+            message: trimmed(self.message),
+            from: trimmed(self.from),
+            to: self.to.map(trimmed).filter(|to| !to.is_empty()),
+            ..self
+        };
+        let mut errors = FieldErrors::new();
+        errors.text("message", &message.message, MAX_MESSAGE_LEN);
+        errors.text("from", &message.from, MAX_NAME_LEN);
+        if let Some(to) = &message.to {
+            errors.text("to", to, MAX_NAME_LEN);
+        }
+        errors.finish(message)
+    }
+}
+
+impl Validate for ArchivedMemory {
+    type Valid = ArchivedMemory;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let memory = ArchivedMemory {
+            caption: trimmed(self.caption),
+            ..self
+        };
+        let mut errors = FieldErrors::new();
+        errors.text("caption", &memory.caption, MAX_CAPTION_LEN);
+        errors.finish(memory)
+    }
+}
+
+impl Validate for ArchivedDate {
+    type Valid = ArchivedDate;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let title = trimmed(self.title);
+        let mut errors = FieldErrors::new();
+        errors.text("title", &title, MAX_TITLE_LEN);
+        match errors.nested("reminders", self.reminders) {
+            Some(reminders) => errors.finish(ArchivedDate {
+                title,
+                reminders,
+                ..self
+            }),
+            None => Err(errors),
+        }
+    }
+}
+
+impl Validate for QuizScore {
+    type Valid = QuizScore;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        errors.text("quiz", &self.quiz, MAX_QUIZ_TEXT_LEN);
+        errors.text("title", &self.title, MAX_QUIZ_TEXT_LEN);
+        if self.score > 100 {
+            errors.add("score", "`score` must be at most 100");
+        }
+        errors.finish(self)
+    }
+}
+
+impl Validate for ArchivedUpload {
+    type Valid = ArchivedUpload;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if self.id.is_empty() {
+            errors.add("id", "`id` must not be empty");
+        }
+        if !ALLOWED_TYPES.contains(&self.content_type.as_str()) {
+            errors.add(
+                "content_type",
+                format!(
+                    "`content_type` must be one of: {}",
+                    ALLOWED_TYPES.join(", ")
+                ),
+            );
+        }
+        if self.sha256.len() != 64 || !self.sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            errors.add("sha256", "`sha256` must be 64 hex digits");
+        }
+        errors.finish(self)
+    }
+}
+
+/// Checks the version and every record, and that each image a record uses
+/// is listed in `uploads`.
+impl Validate for Archive {
+    type Valid = Archive;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if !(1..=ARCHIVE_VERSION).contains(&self.version) {
+            errors.add(
+                "version",
+                format!(
+                    "`version` {} is not supported; this server reads versions 1 to {}",
+                    self.version, ARCHIVE_VERSION
+                ),
+            );
+        }
+        let uploads = errors.nested("uploads", self.uploads);
+        let messages = errors.nested("messages", self.messages);
+        let memories = errors.nested("memories", self.memories);
+        let dates = errors.nested("dates", self.dates);
+        let quiz_results = errors.nested("quiz_results", self.quiz_results);
+        let (Some(uploads), Some(messages), Some(memories), Some(dates), Some(quiz_results)) =
+            (uploads, messages, memories, dates, quiz_results)
+        else {
+            return Err(errors);
+        };
+
+        let listed: HashSet<&str> = uploads.iter().map(|u| u.id.as_str()).collect();
+        let mut check = |field: String, id: &str| {
+            if !listed.contains(id) {
+                errors.add(&field, format!("`{}` `{}` is not in `uploads`", field, id));
+            }
+        };
+        for (i, message) in messages.iter().enumerate() {
+            if let Some(image) = &message.image {
+                check(format!("messages[{}].image", i), image);
+            }
+        }
+        for (i, memory) in memories.iter().enumerate() {
+            check(format!("memories[{}].upload_id", i), &memory.upload_id);
+            check(
+                format!("memories[{}].thumbnail_id", i),
+                &memory.thumbnail_id,
+            );
+        }
+
+        errors.finish(Archive {
+            uploads,
+            messages,
+            memories,
+            dates,
+            quiz_results,
+            ..self
+        })
+    }
+}
+
+/// The records and media of a ZIP export, reading no more than `limit`
+/// bytes out of it in total.
+async fn read_zip(
+    bytes: Vec<u8>,
+    limit: ByteUnit,
+) -> ApiResult<(Archive, HashMap<String, Vec<u8>>)> {
+    let unreadable =
+        |e: String| error(Status::BadRequest, format!("unreadable ZIP archive: {}", e));
+    let options = ZipOptions {
+        max_uncompressed_size_per_file: limit.as_u64(),
+        ..ZipOptions::untrusted()
+    };
+    let mut zip = ZipArchiveReader::open_with_options(Cursor::new(bytes).compat(), options)
+        .await
+        .map_err(|e| unreadable(e.to_string()))?;
+    let names: Vec<Option<String>> = zip
+        .cdrs()
+        .iter()
+        .map(|cdr| cdr.insecure_file_name.as_str().map(str::to_string))
+        .collect();
+
+    let mut records = None;
+    let mut media = HashMap::new();
+    let mut total = 0;
+    for (index, name) in names.into_iter().enumerate() {
+        let Some(name) = name else { continue };
+        let id = name
+            .strip_prefix(MEDIA_DIR)
+            .and_then(|rest| rest.strip_prefix('/'));
+        if name != ARCHIVE_FILE && id.is_none() {
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        zip.file(index)
+            .await
+            .map_err(|e| unreadable(e.to_string()))?
+            .compat()
+            .take(limit.as_u64() - total + 1)
+            .read_to_end(&mut contents)
+            .await
+            .map_err(|e| unreadable(e.to_string()))?;
+        total += contents.len() as u64;
+        if total > limit.as_u64() {
+            return Err(error(
+                Status::PayloadTooLarge,
+                format!("archive unpacks to more than {}", limit),
+            ));
+        }
+        match id {
+            Some(id) => {
+                media.insert(id.to_string(), contents);
+            }
+            None => records = Some(contents),
+        }
+    }
+
+    let records = records.ok_or_else(|| {
+        error(
+            Status::UnprocessableEntity,
+            format!("the archive has no `{}`", ARCHIVE_FILE),
+        )
+    })?;
+    let records = String::from_utf8(records).map_err(|_| {
+        error(
+            Status::BadRequest,
+            format!("`{}` is not UTF-8", ARCHIVE_FILE),
+        )
+    })?;
+    Ok((validation::from_json::<Archive>(&records)?, media))
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize, utoipa::ToSchema)]
+struct ImportCounts {
+    messages: usize,
+    memories: usize,
+    dates: usize,
+    quiz_results: usize,
+    uploads: usize,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ImportReport {
+    dry_run: bool,
+    /// Records added, or that would be without `dry_run`.
+    created: ImportCounts,
+    /// Records already here, which were left alone. An image counts as
+    /// here when an upload with the same bytes exists.
+    skipped: ImportCounts,
+}
+
+/// What an import would add.
+struct Plan {
+    messages: Vec<ArchivedMessage>,
+    memories: Vec<ArchivedMemory>,
+    dates: Vec<ArchivedDate>,
+    quiz_results: Vec<QuizScore>,
+    /// Images to store, with their bytes.
+    uploads: Vec<(ArchivedUpload, Vec<u8>)>,
+    /// Archive upload ids already stored here, and their ids here.
+    existing_uploads: HashMap<String, String>,
+    skipped: ImportCounts,
+}
+
+impl Plan {
+    fn report(&self, dry_run: bool) -> ImportReport {
+        ImportReport {
+            dry_run,
+            created: ImportCounts {
+                messages: self.messages.len(),
+                memories: self.memories.len(),
+                dates: self.dates.len(),
+                quiz_results: self.quiz_results.len(),
+                uploads: self.uploads.len(),
+            },
+            skipped: ImportCounts {
+                uploads: self.existing_uploads.len(),
+                ..self.skipped
+            },
+        }
+    }
+}
+
+/// Sorts the archive's records into new ones and ones `couple` already
+/// has, and finds the bytes of every image the new ones need.
+async fn plan(
+    storage: &Storage,
+    couple: Option<i64>,
+    archive: Archive,
+    mut media: HashMap<String, Vec<u8>>,
+) -> ApiResult<Plan> {
+    let mut skipped = ImportCounts::default();
+
+    let mut seen: HashSet<_> = storage
+        .all_messages(couple)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|m| (m.message, m.sender, m.recipient, m.created_at))
+        .collect();
+    let total = archive.messages.len();
+    let messages: Vec<ArchivedMessage> = archive
+        .messages
+        .into_iter()
+        .filter(|m| {
+            let key = (
+                m.message.clone(),
+                m.from.clone(),
+                m.to.clone(),
+                m.created_at,
+            );
+            seen.insert(key)
+        })
+        .collect();
+    skipped.messages = total - messages.len();
+
+    let mut seen: HashSet<_> = storage
+        .list_memories(couple)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|m| (m.caption, m.taken_on, m.created_at))
+        .collect();
+    let total = archive.memories.len();
+    let memories: Vec<ArchivedMemory> = archive
+        .memories
+        .into_iter()
+        .filter(|m| seen.insert((m.caption.clone(), m.taken_on, m.created_at)))
+        .collect();
+    skipped.memories = total - memories.len();
+
+    let mut seen: HashSet<_> = storage
+        .list_dates(couple)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|d| (d.title, d.kind, d.date))
+        .collect();
+    let total = archive.dates.len();
+    let dates: Vec<ArchivedDate> = archive
+        .dates
+        .into_iter()
+        .filter(|d| seen.insert((d.title.clone(), d.kind, d.date)))
+        .collect();
+    skipped.dates = total - dates.len();
+
+    let mut seen: HashSet<_> = storage
+        .list_quiz_scores(couple)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|q| (q.quiz, q.created_at))
+        .collect();
+    let total = archive.quiz_results.len();
+    let quiz_results: Vec<QuizScore> = archive
+        .quiz_results
+        .into_iter()
+        .filter(|q| seen.insert((q.quiz.clone(), q.created_at)))
+        .collect();
+    skipped.quiz_results = total - quiz_results.len();
+
+    let needed: BTreeSet<&str> = messages
+        .iter()
+        .filter_map(|m| m.image.as_deref())
+        .chain(
+            memories
+                .iter()
+                .flat_map(|m| [m.upload_id.as_str(), m.thumbnail_id.as_str()]),
+        )
+        .collect();
+    let mut errors = FieldErrors::new();
+    let mut uploads = Vec::new();
+    let mut existing_uploads = HashMap::new();
+    for (i, upload) in archive.uploads.into_iter().enumerate() {
+        if !needed.contains(upload.id.as_str()) {
+            continue;
+        }
+        let field = format!("uploads[{}]", i);
+        if let Some(existing) = storage
+            .find_upload_by_sha256(&upload.sha256)
+            .await
+            .map_err(internal_error)?
+        {
+            existing_uploads.insert(upload.id, existing.id);
+            continue;
+        }
+        let Some(bytes) = media.remove(&upload.id) else {
+            errors.add(
+                &field,
+                format!(
+                    "`{}/{}` is not in the archive; import the ZIP export to restore images",
+                    MEDIA_DIR, upload.id
+                ),
+            );
+            continue;
+        };
+        if uploads::sha256_hex(&bytes) != upload.sha256 {
+            errors.add(
+                &field,
+                format!("`{}/{}` does not match its `sha256`", MEDIA_DIR, upload.id),
+            );
+        } else if uploads::sniff(&bytes) != Some(upload.content_type.as_str()) {
+            errors.add(
+                &field,
+                format!(
+                    "`{}/{}` is not a {} image",
+                    MEDIA_DIR, upload.id, upload.content_type
+                ),
+            );
+        } else {
+            uploads.push((upload, bytes));
+        }
+    }
+
+    errors
+        .finish(Plan {
+            messages,
+            memories,
+            dates,
+            quiz_results,
+            uploads,
+            existing_uploads,
+            skipped,
+        })
+        .map_err(Into::into)
+}
+
+/// Stores the plan's images, then adds its records in one transaction.
+/// Returns the new messages.
+async fn apply(
+    storage: &Storage,
+    store: &UploadStore,
+    public_url: &PublicUrl,
+    couple: Option<i64>,
+    plan: Plan,
+) -> ApiResult<Vec<Message>> {
+    let mut ids = plan.existing_uploads;
+    let mut batch = ImportBatch::default();
+    for (upload, bytes) in plan.uploads {
+        let size = bytes.len() as i64;
+        let id = uploads::store_bytes(store, bytes, &upload.content_type).await?;
+        ids.insert(upload.id, id.clone());
+        batch.uploads.push(Upload {
+            id,
+            content_type: upload.content_type,
+            size,
+            sha256: upload.sha256,
+            created_at: upload.created_at,
+        });
+    }
+    // Validation and planning guarantee every referenced upload is mapped.
+    let id_here = |id: &str| ids[id].clone();
+
+    batch.messages = plan
+        .messages
+        .into_iter()
+        .map(|m| Restored {
+            record: NewMessage {
+                image_url: m
+                    .image
+                    .as_deref()
+                    .map(|id| uploads::upload_url(public_url, &id_here(id))),
+                message: m.message,
+                sender: m.from,
+                recipient: m.to,
+                couple_id: couple,
+                sticker_id: None,
+            },
+            created_at: m.created_at,
+        })
+        .collect();
+    batch.memories = plan
+        .memories
+        .into_iter()
+        .map(|m| Restored {
+            record: NewMemory {
+                upload_id: id_here(&m.upload_id),
+                thumbnail_id: id_here(&m.thumbnail_id),
+                caption: m.caption,
+                taken_on: m.taken_on,
+                couple_id: couple,
+            },
+            created_at: m.created_at,
+        })
+        .collect();
+    batch.dates = plan
+        .dates
+        .into_iter()
+        .map(|d| Restored {
+            record: NewImportantDate {
+                title: d.title,
+                kind: d.kind,
+                date: d.date,
+                recurring: d.recurring,
+                reminders: d.reminders,
+                couple_id: couple,
+            },
+            created_at: d.created_at,
+        })
+        .collect();
+    batch.quiz_results = plan.quiz_results;
+
+    let message_ids = storage
+        .import_batch(&batch, couple)
+        .await
+        .map_err(internal_error)?;
+    Ok(batch
+        .messages
+        .into_iter()
+        .zip(message_ids)
+        .map(|(m, id)| Message {
+            id,
+            message: m.record.message,
+            sender: m.record.sender,
+            recipient: m.record.recipient,
+            image_url: m.record.image_url,
+            created_at: m.created_at,
+            deleted_at: None,
+            couple_id: couple,
+            sticker_id: None,
+        })
+        .collect())
+}
+
+/// Restores an archive from `GET /api/export`, sent as `application/zip`
+/// or, without images, as the `application/json` records. Records the
+/// couple already has are skipped; with `dry_run=true` nothing is
+/// changed and the report says what would be added.
+#[utoipa::path(
+    tag = "export",
+    params(("dry_run" = Option<bool>, Query)),
+    request_body(
+        description = "A ZIP or JSON export",
+        content((Vec<u8> = "application/zip"), (Archive = "application/json")),
+    ),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 400, description = "Not a readable ZIP or JSON archive", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 413, description = "Larger than the route's body limit or `limits.import`, or unpacks to more", body = ErrorResponse),
+        (status = 415, description = "Neither ZIP nor JSON", body = ErrorResponse),
+        (status = 422, description = "Unsupported version, invalid records or missing images", body = ErrorResponse),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[post("/api/import?<dry_run>", data = "<body>")]
+async fn import(
+    _key: ApiKey,
+    actor: Actor,
+    storage: &Storage,
+    store: &State<UploadStore>,
+    public_url: &PublicUrl,
+    cache: &State<QueryCache>,
+    scope: CoupleScope,
+    limits: &Limits,
+    route_limit: RouteLimit,
+    content_type: Option<&ContentType>,
+    dry_run: Option<bool>,
+    body: Data<'_>,
+) -> ApiResult<Negotiated<ImportReport>> {
+    let limit = route_limit.or(limits.get("import").unwrap_or(DEFAULT_LIMIT));
+    let is_zip = match content_type {
+        Some(ct) if *ct == ContentType::ZIP => true,
+        Some(ct) if ct.is_json() => false,
+        _ => {
+            return Err(error(
+                Status::UnsupportedMediaType,
+                "send the archive as application/zip or application/json",
+            ))
+        }
+    };
+    let bytes = body.open(limit).into_bytes().await.map_err(|e| {
+        error(
+            Status::BadRequest,
+            format!("failed to read the archive: {}", e),
+        )
+    })?;
+    if !bytes.is_complete() {
+        return Err(error(
+            Status::PayloadTooLarge,
+            format!("archive is larger than {}", limit),
+        ));
+    }
+
+    let (archive, media) = if is_zip {
+        read_zip(bytes.into_inner(), limit).await?
+    } else {
+        let records = String::from_utf8(bytes.into_inner())
+            .map_err(|_| error(Status::BadRequest, "archive is not UTF-8"))?;
+        (validation::from_json::<Archive>(&records)?, HashMap::new())
+    };
+
+    let plan = plan(storage, scope.0, archive, media).await?;
+    let dry_run = dry_run.unwrap_or(false);
+    let report = plan.report(dry_run);
+    if dry_run {
+        return Ok(Negotiated(report));
+    }
+
+    let messages = apply(storage, store, public_url, scope.0, plan).await?;
+    if !messages.is_empty() {
+        cache.invalidate("list_messages");
+    }
+    for message in &messages {
+        audit::record(
+            storage,
+            &actor,
+            AuditAction::Create,
+            AuditEntity::Message,
+            message.id,
+            audit::created(message),
+        )
+        .await;
+    }
+    Ok(Negotiated(report))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![import]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::{json, Value};
+
+    fn archive_json() -> Value {
+        json!({
+            "version": ARCHIVE_VERSION,
+            "exported_at": "2026-02-14T12:00:00Z",
+            "messages": [{
+                "message": " Be mine ",
+                "from": "Sam",
+                "to": "",
+                "image": "abc",
+                "created_at": "2026-02-01T09:00:00Z",
+            }],
+            "memories": [],
+            "dates": [],
+            "quiz_results": [],
+            "uploads": [{
+                "id": "abc",
+                "content_type": "image/png",
+                "size": 4,
+                "sha256": "0".repeat(64),
+                "created_at": "2026-02-01T08:00:00Z",
+            }],
+        })
+    }
+
+    #[test]
+    fn archives_are_checked_before_import() {
+        let archive = validation::from_json::<Archive>(&archive_json().to_string()).unwrap();
+        assert_eq!(archive.messages[0].message, "Be mine");
+        assert_eq!(archive.messages[0].to, None);
+
+        let mut newer = archive_json();
+        newer["version"] = json!(ARCHIVE_VERSION + 1);
+        newer["uploads"] = json!([]);
+        let e = validation::from_json::<Archive>(&newer.to_string()).unwrap_err();
+        assert_eq!(e.status(), Status::UnprocessableEntity);
+        let details = e.details().unwrap();
+        assert!(details["version"][0]
+            .as_str()
+            .unwrap()
+            .contains("not supported"));
+        assert_eq!(
+            details["messages[0].image"],
+            json!(["`messages[0].image` `abc` is not in `uploads`"])
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Signup invites. Admins create single-use, expiring invite tokens with
+//! `POST /admin/invites`; with `invite_only = true` in Rocket.toml new
+//! accounts, by password or OAuth, can only be created with one, keeping
+//! the instance private to the people invited.
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::Route;
+use serde::Serialize;
+
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{self, InviteError, NewUser, Storage, User};
+
+/// Whether signing up needs an invite, from `invite_only`.
+pub struct InviteOnly(pub bool);
+
+fn invite_error(e: InviteError) -> &'static str {
+    match e {
+        InviteError::Unknown => "no such invite",
+        InviteError::Used => "this invite has already been used",
+        InviteError::Expired => "this invite has expired",
+    }
+}
+
+/// Creates an account, using up `invite` if one is given. Without one the
+/// account is only created when the instance is not invite-only. Shared by
+/// password and OAuth signups.
+pub async fn create_user(
+    storage: &Storage,
+    invite_only: &InviteOnly,
+    invite: Option<&str>,
+    user: &NewUser,
+) -> ApiResult<User> {
+    let created = match invite {
+        Some(token) => storage.create_invited_user(token.trim(), user).await,
+        None if invite_only.0 => {
+            return Err(error(
+                Status::UnprocessableEntity,
+                "signing up needs an `invite` on this server",
+            ))
+        }
+        None => storage.create_user(user).await.map(Ok),
+    };
+    match created {
+        Ok(Ok(user)) => Ok(user),
+        Ok(Err(e)) => Err(error(Status::UnprocessableEntity, invite_error(e))),
+        Err(e) if storage::is_unique_violation(&e) => {
+            Err(error(Status::Conflict, "that email is already registered"))
+        }
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct InviteStatus {
+    expires_at: DateTime<Utc>,
+}
+
+fn rejected(e: InviteError) -> ApiError {
+    let status = match e {
+        InviteError::Unknown => Status::NotFound,
+        InviteError::Used | InviteError::Expired => Status::Gone,
+    };
+    error(status, invite_error(e))
+}
+
+/// Checks an invite before showing the signup form.
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, description = "The invite can be used until `expires_at`", body = InviteStatus),
+        (status = 404, body = ErrorResponse),
+        (status = 410, description = "Already used or expired", body = ErrorResponse),
+    )
+)]
+#[get("/api/invites/<token>")]
+async fn check(storage: &Storage, token: &str) -> ApiResult<Negotiated<InviteStatus>> {
+    let invite = storage
+        .get_invite(token)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| rejected(InviteError::Unknown))?;
+    invite.check(Utc::now()).map_err(rejected)?;
+    Ok(Negotiated(InviteStatus {
+        expires_at: invite.expires_at,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![check]
+}
+
+/// Manages [`InviteOnly`] from `invite_only`, off by default.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Invites", |rocket| async {
+        let invite_only = match rocket.figment().extract_inner::<bool>("invite_only") {
+            Ok(invite_only) => invite_only,
+            Err(e) if e.missing() => false,
+            Err(e) => {
+                error!("invalid invite_only: {}", e);
+                return Err(rocket);
+            }
+        };
+        if invite_only {
+            info!("signups need an invite");
+        }
+        Ok(rocket.manage(InviteOnly(invite_only)))
+    })
+}
@@ -0,0 +1,308 @@
+//! Background jobs: emails, push notifications and proposal callbacks that
+//! routes and workers hand off instead of sending inline. Jobs are queued in
+//! each site's database, run by a pool of workers, and retried with
+//! exponential backoff; those still failing after `max_attempts` are kept
+//! for `/admin/jobs/failed` until retried or deleted.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+use rocket::serde::json::{self, Value};
+use rocket::tokio::{self, sync::Notify};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::email::{Mailer, SendError};
+use crate::http::{self, CallbackClient};
+use crate::push::{Notification, Push};
+use crate::resilience::queued_backoff;
+use crate::storage::{ClaimedJob, Storage};
+use crate::tenants::Tenants;
+use crate::workers::{Wakers, Workers};
+
+/// How long a worker holds a job before another may take it over.
+const LEASE: Duration = Duration::from_secs(5 * 60);
+
+/// Upper bound on how long a worker sleeps between checks.
+const MAX_IDLE: Duration = Duration::from_secs(60);
+
+/// A unit of background work, stored as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Job {
+    /// A plain-text notice, e.g. a reminder.
+    Email {
+        to: String,
+        subject: String,
+        text: String,
+    },
+    /// One notification to one browser subscription.
+    Push {
+        subscription_id: i64,
+        notification: Notification,
+    },
+    /// `body` POSTed as JSON to a URL a client registered, e.g. a
+    /// proposal's `callback_url`.
+    Callback { url: String, body: Value },
+}
+
+impl Job {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Job::Email { .. } => "email",
+            Job::Push { .. } => "push",
+            Job::Callback { .. } => "callback",
+        }
+    }
+}
+
+/// Why a job did not finish.
+#[derive(Debug)]
+pub enum Failure {
+    /// Worth trying again later, e.g. a timeout or a 5xx.
+    Retry(String),
+    /// Will fail the same way every time; the job is failed at once.
+    GiveUp(String),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+struct JobsConfig {
+    /// Workers per database.
+    workers: usize,
+    /// Runs per job before it is failed.
+    max_attempts: i64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            workers: 2,
+            max_attempts: 6,
+        }
+    }
+}
+
+/// Handle used to queue jobs and wake the workers.
+#[derive(Clone)]
+pub struct Jobs {
+    wake: Wakers,
+}
+
+impl Jobs {
+    /// Queues `job` in `storage`'s database and wakes the workers.
+    pub async fn enqueue(&self, storage: &Storage, job: &Job) -> Result<(), sqlx::Error> {
+        let payload = json::to_string(job).expect("jobs always serialize");
+        storage.enqueue_job(job.kind(), &payload).await?;
+        self.wake.wake_all();
+        Ok(())
+    }
+
+    /// Wakes the workers, e.g. after a failed job was queued again.
+    pub fn wake(&self) {
+        self.wake.wake_all();
+    }
+}
+
+/// What the workers run jobs with.
+#[derive(Clone)]
+struct Runner {
+    storage: Storage,
+    mailer: Mailer,
+    push: Push,
+    client: CallbackClient,
+    max_attempts: i64,
+}
+
+impl Runner {
+    async fn run(&self, job: Job) -> Result<(), Failure> {
+        match job {
+            Job::Email { to, subject, text } => self
+                .mailer
+                .send_notice(&to, &subject, &text)
+                .await
+                .map(|_| ())
+                .map_err(|e| match e {
+                    SendError::Disabled => Failure::GiveUp("smtp is not configured".to_string()),
+                    SendError::InvalidAddress(e) => Failure::GiveUp(e),
+                    SendError::Transport(e) => Failure::Retry(e),
+                }),
+            Job::Push {
+                subscription_id,
+                notification,
+            } => {
+                self.push
+                    .deliver(&self.storage, subscription_id, &notification)
+                    .await
+            }
+            Job::Callback { url, body } => {
+                http::parse_callback_url(&url).map_err(Failure::GiveUp)?;
+                let response = self
+                    .client
+                    .0
+                    .post(&url)
+                    .json(&body)
+                    .send()
+                    .await
+                    .map_err(|e| Failure::Retry(e.to_string()))?;
+                match response.status() {
+                    status if status.is_success() => Ok(()),
+                    status if status.is_client_error() && status.as_u16() != 429 => {
+                        Err(Failure::GiveUp(format!("responded with {}", status)))
+                    }
+                    status => Err(Failure::Retry(format!("responded with {}", status))),
+                }
+            }
+        }
+    }
+
+    /// Runs a claimed job and records how it went.
+    async fn process(&self, claimed: ClaimedJob) {
+        let (id, attempts) = (claimed.id, claimed.attempts);
+        let result = match json::from_str::<Job>(&claimed.payload) {
+            Ok(job) => self.run(job).await,
+            Err(e) => Err(Failure::GiveUp(format!("unreadable payload: {}", e))),
+        };
+        let recorded = match result {
+            Ok(()) => self.storage.finish_job(id).await,
+            Err(Failure::Retry(e)) if attempts < self.max_attempts => {
+                let at = Utc::now()
+                    + chrono::Duration::from_std(queued_backoff(attempts)).expect("backoff fits");
+                warn!(
+                    "{} job {} failed (attempt {}), retrying at {}: {}",
+                    claimed.kind, id, attempts, at, e
+                );
+                self.storage.retry_job_at(id, at, &e).await
+            }
+            Err(Failure::Retry(e) | Failure::GiveUp(e)) => {
+                error!(
+                    "{} job {} failed after {} attempts, giving up: {}",
+                    claimed.kind, id, attempts, e
+                );
+                self.storage.fail_job(id, &e).await
+            }
+        };
+        if let Err(e) = recorded {
+            error!("failed to record {} job {}: {}", claimed.kind, id, e);
+        }
+    }
+
+    /// Runs due jobs one at a time and sleeps until the next is due. Once
+    /// `token` is cancelled it finishes the job in hand and returns; the
+    /// rest stay queued for the next launch.
+    async fn run_worker(self, wake: Arc<Notify>, token: CancellationToken) {
+        while !token.is_cancelled() {
+            let now = Utc::now();
+            let lease_until = now + chrono::Duration::from_std(LEASE).expect("lease fits");
+            match self.storage.claim_job(now, lease_until).await {
+                Ok(Some(job)) => {
+                    self.process(job).await;
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => error!("failed to claim a job: {}", e),
+            }
+
+            let idle = match self.storage.next_job_at().await {
+                Ok(Some(next)) => (next - Utc::now())
+                    .to_std()
+                    .unwrap_or(Duration::ZERO)
+                    .min(MAX_IDLE),
+                Ok(None) => MAX_IDLE,
+                Err(e) => {
+                    error!("failed to look up the next job: {}", e);
+                    MAX_IDLE
+                }
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep(idle) => {}
+                _ = wake.notified() => {}
+                _ = token.cancelled() => {}
+            }
+        }
+    }
+}
+
+/// Manages the [`Jobs`] handle from the optional `jobs` config and starts
+/// its workers for each database once the server has launched. Must be
+/// attached after the workers stage and before anything that queues jobs.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Jobs", |rocket| async {
+        let config = match rocket.figment().extract_inner::<JobsConfig>("jobs") {
+            Ok(config) if config.workers > 0 && config.max_attempts > 0 => config,
+            Ok(_) => {
+                error!("invalid jobs config: `workers` and `max_attempts` must be positive");
+                return Err(rocket);
+            }
+            Err(e) if e.missing() => JobsConfig::default(),
+            Err(e) => {
+                error!("invalid jobs config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let wakers = Wakers::default();
+
+        Ok(rocket
+            .manage(Jobs {
+                wake: wakers.clone(),
+            })
+            .attach(AdHoc::on_liftoff("Job Workers", move |rocket| {
+                Box::pin(async move {
+                    match (
+                        rocket.state::<Tenants>(),
+                        rocket.state::<Mailer>(),
+                        rocket.state::<Push>(),
+                        rocket.state::<CallbackClient>(),
+                        rocket.state::<Workers>(),
+                    ) {
+                        (Some(tenants), Some(mailer), Some(push), Some(client), Some(workers)) => {
+                            let (mailer, push, client) =
+                                (mailer.clone(), push.clone(), client.clone());
+                            let workers = workers.clone();
+                            tenants.for_each_database(move |storage| {
+                                let runner = Runner {
+                                    storage,
+                                    mailer: mailer.clone(),
+                                    push: push.clone(),
+                                    client: client.clone(),
+                                    max_attempts: config.max_attempts,
+                                };
+                                for _ in 0..config.workers {
+                                    let (runner, wake) = (runner.clone(), wakers.register());
+                                    workers.spawn("job worker", |token| {
+                                        runner.run_worker(wake, token)
+                                    });
+                                }
+                            });
+                        }
+                        _ => error!(
+                            "job workers not started: storage, mailer, push, http client or workers are unavailable"
+                        ),
+                    }
+                })
+            })))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_round_trip_with_their_kind() {
+        let job = Job::Push {
+            subscription_id: 3,
+            notification: Notification {
+                title: "New valentine from Sam".to_string(),
+                body: "Be mine".to_string(),
+                url: None,
+            },
+        };
+        let payload = json::to_string(&job).unwrap();
+        assert!(payload.starts_with(r#"{"kind":"push","#));
+        assert_eq!(json::from_str::<Job>(&payload).unwrap(), job);
+    }
+}
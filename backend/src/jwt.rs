@@ -0,0 +1,279 @@
+//! Short-lived JWTs for clients that would rather not use the session
+//! cookie, such as the SPA. `POST /api/token` trades a password login for an
+//! access and a refresh token; `Authorization: Bearer <access_token>` is then
+//! accepted wherever a session is.
+
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::idempotency::NoStore;
+use crate::negotiate::Negotiated;
+use crate::storage::Storage;
+use crate::tenants;
+use crate::tokens;
+use crate::users::{self, LoginRequest};
+
+const ACCESS_TTL: chrono::Duration = chrono::Duration::minutes(15);
+const REFRESH_TTL: chrono::Duration = chrono::Duration::days(30);
+
+/// Seconds of clock skew tolerated when checking `exp`.
+const LEEWAY_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TokenKind {
+    Access,
+    Refresh,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    /// The user id.
+    sub: String,
+    iat: i64,
+    exp: i64,
+    typ: TokenKind,
+    /// The tenant whose site issued the token; absent on the main site.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+}
+
+/// HMAC-SHA256 signing key, from `jwt_secret` in Rocket.toml.
+pub struct JwtKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl JwtKeys {
+    fn new(secret: &[u8]) -> Self {
+        JwtKeys {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    fn issue(
+        &self,
+        tenant: Option<&str>,
+        user_id: i64,
+        kind: TokenKind,
+        now: DateTime<Utc>,
+    ) -> String {
+        let ttl = match kind {
+            TokenKind::Access => ACCESS_TTL,
+            TokenKind::Refresh => REFRESH_TTL,
+        };
+        let claims = Claims {
+            sub: user_id.to_string(),
+            iat: now.timestamp(),
+            exp: (now + ttl).timestamp(),
+            typ: kind,
+            tenant: tenant.map(str::to_string),
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+            .expect("HS256 signing cannot fail")
+    }
+
+    /// The user id in a valid, unexpired token of `kind` issued by `tenant`'s
+    /// site.
+    fn verify(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        kind: TokenKind,
+    ) -> Result<i64, &'static str> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = LEEWAY_SECS;
+        let claims = jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation)
+            .map_err(|e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => "token expired",
+                _ => "invalid token",
+            })?
+            .claims;
+
+        if claims.typ != kind || claims.tenant.as_deref() != tenant {
+            return Err("invalid token");
+        }
+        claims.sub.parse().map_err(|_| "invalid token")
+    }
+
+    fn pair(&self, tenant: Option<&str>, user_id: i64, now: DateTime<Utc>) -> TokenPair {
+        TokenPair {
+            access_token: self.issue(tenant, user_id, TokenKind::Access, now),
+            token_type: "Bearer",
+            expires_in: ACCESS_TTL.num_seconds(),
+            refresh_token: self.issue(tenant, user_id, TokenKind::Refresh, now),
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct TokenPair {
+    access_token: String,
+    token_type: &'static str,
+    /// Seconds until `access_token` expires.
+    expires_in: i64,
+    /// Trade for a new pair at `POST /api/token/refresh`.
+    refresh_token: String,
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// A valid access token in the `Authorization: Bearer` header, issued by the
+/// request's site. Forwards when there is no bearer token, so callers can
+/// fall back to the session cookie.
+pub struct BearerToken {
+    pub user_id: i64,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return Outcome::Forward(Status::Unauthorized);
+        };
+        let Some(keys) = request.rocket().state::<JwtKeys>() else {
+            return Outcome::Error((Status::InternalServerError, "jwt keys are not configured"));
+        };
+
+        match keys.verify(tenants::slug(request), token.trim(), TokenKind::Access) {
+            Ok(user_id) => Outcome::Success(BearerToken { user_id }),
+            Err(e) => Outcome::Error((Status::Unauthorized, e)),
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, body = TokenPair),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[post("/api/token", data = "<request>")]
+async fn token(
+    storage: &Storage,
+    keys: &State<JwtKeys>,
+    request: Json<LoginRequest>,
+) -> ApiResult<NoStore<Negotiated<TokenPair>>> {
+    let user = users::authenticate(storage, request.into_inner()).await?;
+    Ok(NoStore(Negotiated(keys.pair(
+        storage.tenant(),
+        user.id,
+        Utc::now(),
+    ))))
+}
+
+/// Issues a new token pair for a valid refresh token whose user still exists.
+#[utoipa::path(
+    tag = "users",
+    request_body = RefreshRequest,
+    responses(
+        (status = 200, body = TokenPair),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[post("/api/token/refresh", data = "<request>")]
+async fn refresh(
+    storage: &Storage,
+    keys: &State<JwtKeys>,
+    request: Json<RefreshRequest>,
+) -> ApiResult<NoStore<Negotiated<TokenPair>>> {
+    let user_id = keys
+        .verify(
+            storage.tenant(),
+            request.refresh_token.trim(),
+            TokenKind::Refresh,
+        )
+        .map_err(|e| error(Status::Unauthorized, e))?;
+    match storage.get_user(user_id).await.map_err(internal_error)? {
+        Some(user) => Ok(NoStore(Negotiated(keys.pair(
+            storage.tenant(),
+            user.id,
+            Utc::now(),
+        )))),
+        None => Err(error(Status::Unauthorized, "invalid token")),
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![token, refresh]
+}
+
+/// Manages the [`JwtKeys`]. Without `jwt_secret` a random key is made per
+/// launch, so tokens stop working on restart and differ between instances.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("JWT", |rocket| async {
+        let secret = match rocket.figment().extract_inner::<String>("jwt_secret") {
+            Ok(secret) => secret,
+            Err(e) if e.missing() => {
+                warn!("no jwt_secret configured, using a random key for this launch");
+                tokens::random_token(64)
+            }
+            Err(e) => {
+                error!("invalid jwt_secret: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket.manage(JwtKeys::new(secret.as_bytes())))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_checked_for_kind_signature_and_expiry() {
+        let keys = JwtKeys::new(b"test-secret");
+        let now = Utc::now();
+
+        let access = keys.issue(None, 7, TokenKind::Access, now);
+        assert_eq!(keys.verify(None, &access, TokenKind::Access), Ok(7));
+        assert_eq!(
+            keys.verify(None, &access, TokenKind::Refresh),
+            Err("invalid token")
+        );
+
+        let other = JwtKeys::new(b"other-secret");
+        assert_eq!(
+            other.verify(None, &access, TokenKind::Access),
+            Err("invalid token")
+        );
+
+        let stale = keys.issue(None, 7, TokenKind::Access, now - chrono::Duration::hours(1));
+        assert_eq!(
+            keys.verify(None, &stale, TokenKind::Access),
+            Err("token expired")
+        );
+
+        let tenant = keys.issue(Some("jo"), 7, TokenKind::Access, now);
+        assert_eq!(keys.verify(Some("jo"), &tenant, TokenKind::Access), Ok(7));
+        assert_eq!(
+            keys.verify(None, &tenant, TokenKind::Access),
+            Err("invalid token")
+        );
+        assert_eq!(
+            keys.verify(Some("jo"), &access, TokenKind::Access),
+            Err("invalid token")
+        );
+    }
+}
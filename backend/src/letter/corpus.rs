@@ -0,0 +1,178 @@
+//! Sentence fragments the letter generator stitches together. `{to}` and
+//! `{from}` are filled in with the recipient's and sender's names. Fragments
+//! with no tones are neutral and may appear in any letter.
+
+use super::Tone::{self, *};
+
+pub struct Fragment {
+    pub text: &'static str,
+    pub tones: &'static [Tone],
+}
+
+const fn f(text: &'static str, tones: &'static [Tone]) -> Fragment {
+    Fragment { text, tones }
+}
+
+pub const GREETINGS: &[Fragment] = &[
+    f("My dearest {to},", &[Sappy, Classic]),
+    f("My darling {to},", &[Sappy]),
+    f("Sweetest {to},", &[Sappy]),
+    f("Hey {to},", &[Playful]),
+    f("Dear partner in crime {to},", &[Playful]),
+    f("To {to}, my evening star,", &[Poetic]),
+    f("{to}, light of my long nights,", &[Poetic]),
+    f("Dear {to},", &[Classic]),
+    f("Dear {to},", &[]),
+];
+
+pub const OPENINGS: &[Fragment] = &[
+    f(
+        "I have started this letter a dozen times, because no words feel big enough for you.",
+        &[Sappy],
+    ),
+    f(
+        "Every morning I wake up and my very first thought is of you.",
+        &[Sappy],
+    ),
+    f(
+        "I could fill a thousand pages and still not say how much you mean to me.",
+        &[Sappy, Classic],
+    ),
+    f(
+        "I'm writing this instead of doing anything productive, so you know it's serious.",
+        &[Playful],
+    ),
+    f(
+        "Fair warning: this letter contains dangerous levels of affection.",
+        &[Playful],
+    ),
+    f(
+        "I was going to send a meme, but you deserve a whole letter.",
+        &[Playful],
+    ),
+    f(
+        "Some hearts are quiet harbours; mine found its anchor the day it found you.",
+        &[Poetic],
+    ),
+    f(
+        "The seasons keep turning, and still every one of them begins and ends with you.",
+        &[Poetic],
+    ),
+    f(
+        "There is a kind of light that only arrives when you walk into a room.",
+        &[Poetic],
+    ),
+    f(
+        "I hope this letter finds you well, and finds you smiling.",
+        &[Classic],
+    ),
+    f(
+        "It has been on my mind for some time to put my feelings into writing.",
+        &[Classic],
+    ),
+    f(
+        "I wanted to take a moment to tell you something I don't say often enough.",
+        &[],
+    ),
+];
+
+pub const MIDDLES: &[Fragment] = &[
+    f("You make the ordinary days feel like holidays.", &[Sappy]),
+    f(
+        "When you laugh, the whole world seems to soften.",
+        &[Sappy, Poetic],
+    ),
+    f("Holding your hand still gives me butterflies.", &[Sappy]),
+    f(
+        "I love the way you say my name, like it's something precious.",
+        &[Sappy],
+    ),
+    f("You are my favourite place to be.", &[Sappy, Classic]),
+    f(
+        "I'd gladly lose every argument about the thermostat if it means keeping you.",
+        &[Playful],
+    ),
+    f(
+        "You are the only person whose snoring I find charming. Mostly.",
+        &[Playful],
+    ),
+    f(
+        "If you were a vegetable, you'd be a cute-cumber.",
+        &[Playful],
+    ),
+    f(
+        "I still can't believe you willingly hang out with me.",
+        &[Playful],
+    ),
+    f("You're my favourite notification.", &[Playful]),
+    f(
+        "You are the quiet after rain, the warmth after a long winter.",
+        &[Poetic],
+    ),
+    f(
+        "In the map of my life, every road bends toward you.",
+        &[Poetic],
+    ),
+    f(
+        "Your voice is the melody my days are written to.",
+        &[Poetic],
+    ),
+    f(
+        "Even the stars seem a little dimmer when you're away.",
+        &[Poetic],
+    ),
+    f(
+        "Your kindness to others is one of the things I admire most.",
+        &[Classic],
+    ),
+    f(
+        "I am grateful every day for your patience and your good heart.",
+        &[Classic],
+    ),
+    f(
+        "You have made me a better person simply by being yourself.",
+        &[Classic, Sappy],
+    ),
+    f("I treasure every moment we share, {to}.", &[Classic]),
+    f(
+        "I love the little things: your coffee ritual, your terrible puns, the way you hum.",
+        &[],
+    ),
+    f("Being with you feels like coming home.", &[]),
+    f("You make me want to be brave.", &[]),
+    f(
+        "I replay our first date in my head more often than I'd like to admit.",
+        &[],
+    ),
+];
+
+pub const CLOSINGS: &[Fragment] = &[
+    f(
+        "I love you more than yesterday, and less than I will tomorrow.",
+        &[Sappy],
+    ),
+    f(
+        "I can't wait to spend every Valentine's Day with you.",
+        &[Sappy],
+    ),
+    f("Now stop reading and come give me a hug.", &[Playful]),
+    f(
+        "Be my valentine? There may or may not be chocolate involved.",
+        &[Playful],
+    ),
+    f("Until the stars burn out, I am yours.", &[Poetic]),
+    f("May every tomorrow find us side by side.", &[Poetic]),
+    f("With all my affection, now and always.", &[Classic]),
+    f("I look forward to all the days ahead of us.", &[Classic]),
+    f("Happy Valentine's Day, {to}.", &[]),
+];
+
+pub const SIGN_OFFS: &[Fragment] = &[
+    f("Forever yours,", &[Sappy]),
+    f("All my love,", &[Sappy, Classic]),
+    f("Your biggest fan,", &[Playful]),
+    f("XOXO,", &[Playful]),
+    f("Yours beneath every sky,", &[Poetic]),
+    f("Yours sincerely,", &[Classic]),
+    f("Love,", &[]),
+];
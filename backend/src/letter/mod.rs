@@ -0,0 +1,222 @@
+mod corpus;
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rocket::http::Status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::determinism::RequestRng;
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::messages::{self, Vars};
+use crate::negotiate::Negotiated;
+
+use corpus::Fragment;
+
+/// Weight of a fragment tagged with the requested tone, relative to a
+/// neutral fragment's weight of 1. Fragments of other tones are never used.
+const TONE_WEIGHT: u32 = 4;
+
+/// Sentences per body paragraph; the letter always has two.
+const BODY_PARAGRAPHS: usize = 2;
+const SENTENCES_PER_PARAGRAPH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Tone {
+    /// Gushing and heartfelt.
+    Sappy,
+    /// Teasing, with jokes and puns.
+    Playful,
+    /// Imagery of stars, seasons and the sea.
+    Poetic,
+    /// Warm but restrained, like a handwritten note.
+    Classic,
+}
+
+impl Tone {
+    pub const ALL: [Tone; 4] = [Tone::Sappy, Tone::Playful, Tone::Poetic, Tone::Classic];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Tone::Sappy => "sappy",
+            Tone::Playful => "playful",
+            Tone::Poetic => "poetic",
+            Tone::Classic => "classic",
+        }
+    }
+}
+
+impl fmt::Display for Tone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Tone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Tone::ALL
+            .into_iter()
+            .find(|t| t.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = Tone::ALL.iter().map(|t| t.as_str()).collect();
+                format!(
+                    "unknown tone `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+fn weight(fragment: &Fragment, tone: Tone) -> u32 {
+    if fragment.tones.is_empty() {
+        1
+    } else if fragment.tones.contains(&tone) {
+        TONE_WEIGHT
+    } else {
+        0
+    }
+}
+
+/// Draws `count` distinct fragments from `pool`, weighted by tone.
+fn pick<'a>(rng: &mut StdRng, pool: &'a [Fragment], tone: Tone, count: usize) -> Vec<&'a str> {
+    let mut remaining: Vec<&Fragment> = pool.iter().filter(|f| weight(f, tone) > 0).collect();
+    let mut picked = Vec::with_capacity(count);
+
+    while picked.len() < count {
+        let Ok(choice) = remaining.choose_weighted(rng, |f| weight(f, tone)) else {
+            break;
+        };
+        let text = choice.text;
+        remaining.retain(|f| f.text != text);
+        picked.push(text);
+    }
+    picked
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct Letter {
+    pub to: String,
+    pub from: String,
+    pub tone: Tone,
+    /// Pass back as `?seed=` to get the same letter again.
+    pub seed: u64,
+    pub paragraphs: Vec<String>,
+    /// The paragraphs joined with blank lines, ready to display.
+    pub text: String,
+}
+
+/// Composes a letter: greeting, an opening, two body paragraphs, a closing
+/// and a sign-off. The same inputs and seed always give the same letter.
+pub fn compose(to: &str, from: &str, tone: Tone, seed: u64) -> Letter {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let vars = Vars::new().with("to", to).with("from", from);
+    let sentences = |parts: Vec<&str>| messages::render(&parts.join(" "), &vars);
+
+    let mut paragraphs = vec![sentences(pick(&mut rng, corpus::GREETINGS, tone, 1))];
+    paragraphs.push(sentences(pick(&mut rng, corpus::OPENINGS, tone, 2)));
+
+    let body = pick(
+        &mut rng,
+        corpus::MIDDLES,
+        tone,
+        BODY_PARAGRAPHS * SENTENCES_PER_PARAGRAPH,
+    );
+    for chunk in body.chunks(SENTENCES_PER_PARAGRAPH) {
+        paragraphs.push(sentences(chunk.to_vec()));
+    }
+
+    paragraphs.push(sentences(pick(&mut rng, corpus::CLOSINGS, tone, 1)));
+    let sign_off = sentences(pick(&mut rng, corpus::SIGN_OFFS, tone, 1));
+    paragraphs.push(format!("{}\n{}", sign_off, from));
+
+    Letter {
+        to: to.to_string(),
+        from: from.to_string(),
+        tone,
+        seed,
+        text: paragraphs.join("\n\n"),
+        paragraphs,
+    }
+}
+
+#[utoipa::path(
+    tag = "letters",
+    params(
+        ("to" = Option<String>, Query),
+        ("from" = Option<String>, Query),
+        ("tone" = Option<Tone>, Query),
+        ("seed" = Option<u64>, Query, description = "Reproduces an earlier letter"),
+    ),
+    responses(
+        (status = 200, body = Letter),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+#[get("/api/letter?<to>&<from>&<tone>&<seed>")]
+fn letter(
+    mut rng: RequestRng,
+    to: Option<&str>,
+    from: Option<&str>,
+    tone: Option<&str>,
+    seed: Option<u64>,
+) -> ApiResult<Negotiated<Letter>> {
+    let name = |value: Option<&str>, default: &str| {
+        value
+            .map(messages::sanitize_name)
+            .transpose()
+            .map(|name| name.unwrap_or_else(|| default.to_string()))
+            .map_err(|e| error(Status::BadRequest, e))
+    };
+    let to = name(to, "My Love")?;
+    let from = name(from, "Your Valentine")?;
+    let tone = tone
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(Tone::Sappy);
+    let seed = seed.unwrap_or_else(|| rng.gen());
+
+    Ok(Negotiated(compose(&to, &from, tone, seed)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![letter]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_same_letter() {
+        let a = compose("Alex", "Sam", Tone::Poetic, 42);
+        let b = compose("Alex", "Sam", Tone::Poetic, 42);
+        assert_eq!(a.text, b.text);
+        assert_ne!(a.text, compose("Alex", "Sam", Tone::Poetic, 43).text);
+    }
+
+    #[test]
+    fn letters_are_personalized_and_tone_consistent() {
+        for seed in 0..20 {
+            let letter = compose("Alex", "Sam", Tone::Playful, seed);
+            assert_eq!(letter.paragraphs.len(), 6);
+            assert!(letter.paragraphs[0].contains("Alex"));
+            assert!(letter.text.ends_with("\nSam"));
+            assert!(!letter.text.contains('{'));
+
+            let off_tone = corpus::MIDDLES
+                .iter()
+                .filter(|f| weight(f, Tone::Playful) == 0)
+                .any(|f| letter.text.contains(f.text));
+            assert!(!off_tone, "seed {} used an off-tone fragment", seed);
+        }
+    }
+}
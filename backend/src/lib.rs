@@ -0,0 +1,230 @@
+//! The Valentine 2026 API. The `valentine-backend` binary serves
+//! [`rocket`] and runs the [`migrate`] and [`seed`] commands; integration
+//! tests serve [`build_rocket`] with a config of their own.
+
+#[macro_use]
+extern crate rocket;
+
+mod admin;
+mod audio;
+mod audit;
+mod auth;
+mod backup;
+mod body_limits;
+mod cache;
+mod calendar;
+mod cards;
+mod chat;
+mod checkins;
+mod compression;
+mod confessions;
+mod config;
+mod content_filter;
+mod countdown;
+mod coupons;
+mod date_ideas;
+mod dates;
+mod determinism;
+mod draft;
+mod email;
+mod envelope;
+mod error;
+mod events;
+mod experiments;
+mod export;
+mod frontend;
+mod games;
+mod gifts;
+mod graphql;
+mod grpc;
+mod health;
+mod http;
+mod hunt;
+mod i18n;
+mod idempotency;
+mod import;
+mod invites;
+mod jobs;
+mod jwt;
+mod letter;
+mod live_stats;
+mod memories;
+mod messages;
+mod metrics;
+pub mod migrate;
+mod mood;
+mod music;
+mod negotiate;
+mod notes;
+mod oauth;
+mod openapi;
+mod pagination;
+mod poetry;
+mod proposal;
+mod providers;
+mod push;
+mod quiz;
+mod quote_search;
+mod quote_sources;
+mod rate_limit;
+mod reactions;
+mod reminders;
+mod reservations;
+mod resilience;
+mod roles;
+mod scheduler;
+mod sealed_notes;
+pub mod seed;
+mod share;
+mod shared;
+mod sms;
+mod stats;
+mod stickers;
+mod storage;
+mod surprise;
+mod telemetry;
+mod tenants;
+mod themes;
+mod timezones;
+mod tokens;
+mod trash;
+mod uploads;
+mod users;
+mod valentine;
+mod validation;
+mod vault;
+mod webhooks;
+mod wishlist;
+mod workers;
+
+use rocket::figment::Figment;
+use rocket::{Build, Rocket};
+
+pub use config::figment;
+
+/// The server with every stage, catcher and route attached, as
+/// `valentine-backend serve` launches it.
+pub fn rocket() -> Rocket<Build> {
+    build_rocket(config::figment())
+}
+
+/// The same server configured from `figment` alone, without Rocket.toml,
+/// the profile files or `ROCKET_*` variables unless `figment` reads them.
+pub fn build_rocket(figment: Figment) -> Rocket<Build> {
+    telemetry::init(config::log_filter(&figment).as_deref());
+
+    rocket::custom(figment)
+        .attach(config::profile())
+        .attach(cache::ETags::default())
+        .attach(telemetry::RequestTracing)
+        .attach(envelope::Envelope)
+        .attach(metrics::stage())
+        .attach(config::cors())
+        .attach(config::public_url())
+        .attach(determinism::stage())
+        .attach(shared::stage())
+        .attach(rate_limit::stage())
+        .attach(auth::stage())
+        .attach(jwt::stage())
+        .attach(content_filter::stage())
+        .attach(cache::stage())
+        .attach(storage::stage())
+        .attach(storage::warm())
+        .attach(oauth::stage())
+        .attach(invites::stage())
+        .attach(i18n::stage())
+        .attach(gifts::stage())
+        .attach(tenants::stage())
+        .attach(body_limits::stage())
+        .attach(idempotency::stage())
+        .attach(quiz::stage())
+        .attach(themes::stage())
+        .attach(cards::handwriting::stage())
+        .attach(share::templates())
+        .attach(providers::stage())
+        .attach(http::stage())
+        .attach(resilience::stage())
+        .attach(uploads::stage())
+        .attach(backup::stage())
+        .attach(quote_sources::stage())
+        .attach(quote_search::stage())
+        .attach(audio::stage())
+        .attach(music::stage())
+        .attach(date_ideas::stage())
+        .attach(workers::stage())
+        .attach(events::stage())
+        .attach(jobs::stage())
+        .attach(proposal::stage())
+        .attach(stats::stage())
+        .attach(trash::stage())
+        .attach(surprise::stage())
+        .attach(scheduler::stage())
+        .attach(webhooks::stage())
+        .attach(live_stats::stage())
+        .attach(push::stage())
+        .attach(email::stage())
+        .attach(sms::stage())
+        .attach(draft::stage())
+        .attach(reminders::stage())
+        .attach(notes::stage())
+        .attach(chat::stage())
+        .attach(graphql::stage())
+        .attach(grpc::stage())
+        .attach(frontend::stage())
+        .attach(compression::stage())
+        .register("/", error::catchers())
+        .mount("/", health::routes())
+        .mount("/", valentine::routes())
+        .mount("/", trash::routes())
+        .mount("/", scheduler::routes())
+        .mount("/", email::routes())
+        .mount("/", sms::routes())
+        .mount("/", draft::routes())
+        .mount("/", cards::routes())
+        .mount("/", calendar::routes())
+        .mount("/", themes::routes())
+        .mount("/", timezones::routes())
+        .mount("/", notes::routes())
+        .mount("/", proposal::routes())
+        .mount("/", webhooks::routes())
+        .mount("/", wishlist::routes())
+        .mount("/", chat::routes())
+        .mount("/", sealed_notes::routes())
+        .mount("/", push::routes())
+        .mount("/", share::routes())
+        .mount("/", reactions::routes())
+        .mount("/", stickers::routes())
+        .mount("/", audio::routes())
+        .mount("/", stats::routes())
+        .mount("/", quote_search::routes())
+        .mount("/", live_stats::routes())
+        .mount("/", experiments::routes())
+        .mount("/", gifts::routes())
+        .mount("/", quiz::routes())
+        .mount("/", games::routes())
+        .mount("/", uploads::routes())
+        .mount("/", memories::routes())
+        .mount("/", mood::routes())
+        .mount("/", vault::routes())
+        .mount("/", surprise::routes())
+        .mount("/", hunt::routes())
+        .mount("/", countdown::routes())
+        .mount("/", dates::routes())
+        .mount("/", date_ideas::routes())
+        .mount("/", reservations::routes())
+        .mount("/", users::routes())
+        .mount("/", invites::routes())
+        .mount("/", checkins::routes())
+        .mount("/", confessions::routes())
+        .mount("/", coupons::routes())
+        .mount("/", export::routes())
+        .mount("/", import::routes())
+        .mount("/", jwt::routes())
+        .mount("/", letter::routes())
+        .mount("/", poetry::routes())
+        .mount("/", music::routes())
+        .mount("/", admin::routes())
+        .mount("/", metrics::routes())
+        .mount("/", graphql::routes())
+        .mount("/", openapi::routes())
+}
@@ -0,0 +1,275 @@
+//! The app's heartbeat for a public widget: valentines sent today, "yes"
+//! answers and the most favorited quote, streamed over server-sent events.
+//! Each site's counts are kept in memory and bumped by the events routes
+//! publish; every [`RESYNC_INTERVAL`] they are recounted from the database,
+//! which picks up events on other instances, new favorites and the start
+//! of a new UTC day. Nothing about a couple or a message is exposed.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::sync::watch;
+use rocket::tokio::time;
+use rocket::{Route, Shutdown, State};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::events::{self, DomainEvent, Subscriber};
+use crate::storage::{Answer, Popularity, QuoteStat, Storage};
+use crate::tenants::Tenants;
+use crate::workers::Workers;
+
+const RESYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct LiveStatsSnapshot {
+    /// The UTC day `valentines_today` counts.
+    pub day: NaiveDate,
+    pub valentines_today: i64,
+    /// Proposals ever answered "yes".
+    pub proposals_accepted: i64,
+    /// `null` until some quote has been favorited.
+    pub top_quote: Option<QuoteStat>,
+}
+
+impl LiveStatsSnapshot {
+    /// Counts `event` on `today`, returning whether anything changed. A new
+    /// day starts the valentines from zero.
+    fn apply(&mut self, event: &DomainEvent, today: NaiveDate) -> bool {
+        let new_day = self.day != today;
+        if new_day {
+            self.day = today;
+            self.valentines_today = 0;
+        }
+        match event {
+            DomainEvent::MessageCreated { .. } => self.valentines_today += 1,
+            DomainEvent::ProposalAnswered { proposal } if proposal.answer == Some(Answer::Yes) => {
+                self.proposals_accepted += 1
+            }
+            _ => return new_day,
+        }
+        true
+    }
+}
+
+type Snapshots = Arc<watch::Sender<LiveStatsSnapshot>>;
+
+/// Each site's latest snapshot, once its aggregator has counted it, by
+/// tenant slug.
+#[derive(Clone, Default)]
+pub struct LiveStats(Arc<Mutex<HashMap<Option<String>, Snapshots>>>);
+
+impl LiveStats {
+    fn register(&self, tenant: Option<&str>, snapshots: Snapshots) {
+        self.0
+            .lock()
+            .expect("live stats lock poisoned")
+            .insert(tenant.map(str::to_string), snapshots);
+    }
+
+    fn snapshots(&self, tenant: Option<&str>) -> Option<Snapshots> {
+        self.0
+            .lock()
+            .expect("live stats lock poisoned")
+            .get(&tenant.map(str::to_string))
+            .cloned()
+    }
+}
+
+/// Bumps the counts of the event's site. One counted after the event
+/// happened already includes it.
+#[rocket::async_trait]
+impl Subscriber for LiveStats {
+    fn name(&self) -> &'static str {
+        "live stats"
+    }
+
+    async fn handle(&self, storage: &Storage, event: &DomainEvent) {
+        if let Some(snapshots) = self.snapshots(storage.tenant()) {
+            let today = Utc::now().date_naive();
+            snapshots.send_if_modified(|s| s.apply(event, today));
+        }
+    }
+}
+
+async fn count(storage: &Storage) -> Result<LiveStatsSnapshot, sqlx::Error> {
+    let now = Utc::now();
+    let day = now.date_naive();
+    let midnight = day.and_hms_opt(0, 0, 0).expect("midnight exists").and_utc();
+    Ok(LiveStatsSnapshot {
+        day,
+        valentines_today: storage.count_messages_since(midnight).await?,
+        proposals_accepted: storage.count_answers(Answer::Yes).await?,
+        top_quote: storage
+            .top_quotes(Popularity::Favorited, 1)
+            .await?
+            .into_iter()
+            .next(),
+    })
+}
+
+/// Counts `storage`'s figures into `stats`, then recounts them every
+/// [`RESYNC_INTERVAL`] until `token` is cancelled.
+async fn run_aggregator(storage: Storage, stats: LiveStats, token: CancellationToken) {
+    let snapshot = loop {
+        match count(&storage).await {
+            Ok(snapshot) => break snapshot,
+            Err(e) => error!("failed to count live stats, retrying: {}", e),
+        }
+        select! {
+            _ = time::sleep(RESYNC_INTERVAL) => {}
+            _ = token.cancelled() => return,
+        }
+    };
+    let snapshots = Arc::new(watch::Sender::new(snapshot));
+    stats.register(storage.tenant(), snapshots.clone());
+
+    let mut resync = time::interval(RESYNC_INTERVAL);
+    resync.reset();
+    loop {
+        select! {
+            _ = resync.tick() => match count(&storage).await {
+                Ok(counted) => {
+                    snapshots.send_if_modified(|s| {
+                        let changed = *s != counted;
+                        *s = counted;
+                        changed
+                    });
+                }
+                Err(e) => error!("failed to recount live stats: {}", e),
+            },
+            _ = token.cancelled() => return,
+        }
+    }
+}
+
+/// Server-sent events: a `stats` event with the current figures right away
+/// and another each time they change, until the client disconnects or the
+/// server shuts down.
+#[utoipa::path(
+    tag = "stats",
+    responses(
+        (
+            status = 200,
+            description = "`stats` events carrying a `LiveStatsSnapshot`",
+            content_type = "text/event-stream",
+            body = String,
+        ),
+        (status = 503, description = "Not counted yet, just after startup", body = ErrorResponse),
+    )
+)]
+#[get("/api/stats/live")]
+fn live(
+    stats: &State<LiveStats>,
+    storage: &Storage,
+    mut shutdown: Shutdown,
+) -> ApiResult<EventStream![]> {
+    let mut snapshots = stats
+        .snapshots(storage.tenant())
+        .map(|snapshots| snapshots.subscribe())
+        .ok_or_else(|| error(Status::ServiceUnavailable, "live stats are not ready yet"))?;
+
+    Ok(EventStream! {
+        let mut id = 0u64;
+        loop {
+            let snapshot = snapshots.borrow_and_update().clone();
+            id += 1;
+            yield Event::json(&snapshot).event("stats").id(id.to_string());
+
+            select! {
+                changed = snapshots.changed() => if changed.is_err() {
+                    break;
+                },
+                _ = &mut shutdown => break,
+            }
+        }
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![live]
+}
+
+/// Manages [`LiveStats`], subscribes it to events and starts an aggregator
+/// per database at liftoff. Attach it after [`crate::events::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Live Stats", |rocket| async {
+        let stats = LiveStats::default();
+
+        rocket
+            .manage(stats.clone())
+            .attach(events::subscriber(stats.clone()))
+            .attach(AdHoc::on_liftoff("Live Stats Aggregator", move |rocket| {
+                Box::pin(async move {
+                    match (rocket.state::<Tenants>(), rocket.state::<Workers>()) {
+                        (Some(tenants), Some(workers)) => {
+                            let workers = workers.clone();
+                            tenants.for_each_database(move |storage| {
+                                let stats = stats.clone();
+                                workers.spawn("live stats aggregator", |token| {
+                                    run_aggregator(storage, stats, token)
+                                });
+                            });
+                        }
+                        _ => error!("live stats not started: storage or workers are unavailable"),
+                    }
+                })
+            }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Message, Proposal};
+
+    #[test]
+    fn events_bump_the_counts_and_days_restart_them() {
+        let monday: NaiveDate = "2027-02-15".parse().unwrap();
+        let mut stats = LiveStatsSnapshot {
+            day: "2027-02-14".parse().unwrap(),
+            valentines_today: 41,
+            proposals_accepted: 7,
+            top_quote: None,
+        };
+        let created = DomainEvent::MessageCreated {
+            message: Message {
+                id: 1,
+                message: "Be mine".to_string(),
+                sender: "Romeo".to_string(),
+                recipient: None,
+                image_url: None,
+                created_at: Utc::now(),
+                deleted_at: None,
+                couple_id: None,
+                sticker_id: None,
+            },
+        };
+        let answered = |answer| DomainEvent::ProposalAnswered {
+            proposal: Proposal {
+                token: "t".to_string(),
+                question: "Will you be my valentine?".to_string(),
+                sender: "Romeo".to_string(),
+                recipient: None,
+                callback_url: None,
+                answer: Some(answer),
+                answered_at: Some(Utc::now()),
+                created_at: Utc::now(),
+                couple_id: None,
+            },
+        };
+
+        assert!(stats.apply(&created, monday));
+        assert_eq!((stats.day, stats.valentines_today), (monday, 1));
+        assert!(stats.apply(&answered(Answer::Yes), monday));
+        assert!(!stats.apply(&answered(Answer::No), monday));
+        assert_eq!(stats.proposals_accepted, 8);
+    }
+}
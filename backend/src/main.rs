@@ -1,65 +1,122 @@
-#[macro_use]
-extern crate rocket;
+//! The `valentine-backend` command. `serve`, the default, runs the API;
+//! `migrate` manages the database schema and `seed` loads starter data
+//! without starting it. All read their config from `Rocket.toml` and
+//! `ROCKET_*` variables.
 
-use rand::seq::SliceRandom;
-use rocket::serde::json::Json;
-use rocket_cors::{AllowedOrigins, CorsOptions};
-use serde::Serialize;
+use std::process::ExitCode;
 
-#[derive(Serialize)]
-struct HealthResponse {
-    status: String,
-    service: String,
+use clap::{Parser, Subcommand};
+use valentine_backend::migrate::{self, MigrationState};
+use valentine_backend::seed::{self, Seeded};
+
+#[derive(Parser)]
+#[command(version, about = "The Valentine 2026 API server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Serialize)]
-struct ValentineResponse {
-    message: String,
-    from: String,
+#[derive(Subcommand)]
+enum Command {
+    /// Starts the server, applying pending migrations first.
+    Serve,
+    /// Manages the database schema without starting the server.
+    Migrate {
+        #[command(subcommand)]
+        action: Option<MigrateAction>,
+    },
+    /// Adds the bundled starter quotes, gifts and quizzes, skipping any
+    /// already present.
+    Seed,
 }
 
-const LOVE_QUOTES: &[&str] = &[
-    "You are the reason I believe in love.",
-    "Every love story is beautiful, but ours is my favorite.",
-    "In all the world, there is no heart for me like yours.",
-    "I love you more than yesterday, less than tomorrow.",
-    "You had me at hello.",
-    "To love and be loved is to feel the sun from both sides.",
-    "My heart is, and always will be, yours.",
-    "I wish I could turn back the clock. I'd find you sooner and love you longer.",
-    "You are my today and all of my tomorrows.",
-    "I fell in love the way you fall asleep: slowly, and then all at once.",
-];
+#[derive(Subcommand)]
+enum MigrateAction {
+    /// Applies every pending migration (the default).
+    Up,
+    /// Reverts the most recently applied migrations.
+    Down {
+        /// How many migrations to revert.
+        #[arg(long, default_value_t = 1)]
+        steps: usize,
+    },
+    /// Lists every migration and whether it has been applied.
+    Status,
+}
 
-#[get("/health")]
-fn health() -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "ok".to_string(),
-        service: "valentine-backend".to_string(),
-    })
+fn serve() -> Result<(), String> {
+    match rocket::execute(valentine_backend::rocket().launch()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.pretty_print().to_string()),
+    }
 }
 
-#[get("/api/valentine")]
-fn valentine() -> Json<ValentineResponse> {
-    let mut rng = rand::thread_rng();
-    let quote = LOVE_QUOTES.choose(&mut rng).unwrap_or(&"I love you!");
+async fn run_migrate(action: MigrateAction) -> Result<(), String> {
+    let figment = valentine_backend::figment();
+    match action {
+        MigrateAction::Up => {
+            let applied = migrate::up(&figment).await?;
+            if applied.is_empty() {
+                println!("no pending migrations");
+            }
+            for migration in applied {
+                println!("applied {:04} {}", migration.version, migration.description);
+            }
+        }
+        MigrateAction::Down { steps } => {
+            let reverted = migrate::down(&figment, steps).await?;
+            if reverted.is_empty() {
+                println!("no applied migrations");
+            }
+            for version in reverted {
+                println!("reverted {:04}", version);
+            }
+        }
+        MigrateAction::Status => {
+            for migration in migrate::status(&figment).await? {
+                let state = match migration.state {
+                    MigrationState::Applied => "applied",
+                    MigrationState::Pending => "pending",
+                    MigrationState::Modified => "modified",
+                };
+                println!(
+                    "{:04} {:<8} {}",
+                    migration.version, state, migration.description
+                );
+            }
+        }
+    }
+    Ok(())
+}
 
-    Json(ValentineResponse {
-        message: quote.to_string(),
-        from: "Your Valentine".to_string(),
-    })
+async fn run_seed() -> Result<(), String> {
+    let report = seed::run(&valentine_backend::figment()).await?;
+    let line = |kind: &str, seeded: Seeded| {
+        println!(
+            "{}: {} added, {} already present",
+            kind, seeded.added, seeded.present
+        )
+    };
+    line("quotes", report.quotes);
+    println!("translations: {} stored", report.translations);
+    line("gifts", report.gifts);
+    line("quizzes", report.quizzes);
+    Ok(())
 }
 
-#[launch]
-fn rocket() -> _ {
-    let cors = CorsOptions {
-        allowed_origins: AllowedOrigins::all(),
-        ..Default::default()
+fn main() -> ExitCode {
+    let result = match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(),
+        Command::Migrate { action } => {
+            rocket::execute(run_migrate(action.unwrap_or(MigrateAction::Up)))
+        }
+        Command::Seed => rocket::execute(run_seed()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
     }
-    .to_cors()
-    .expect("CORS configuration failed");
-
-    rocket::build()
-        .attach(cors)
-        .mount("/", routes![health, valentine])
 }
@@ -0,0 +1,301 @@
+//! A photo timeline: dated, captioned pictures stored as uploads, each with
+//! a JPEG thumbnail made when it is added.
+
+use std::io::Cursor;
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::tokio::task::spawn_blocking;
+use rocket::{Route, State};
+use serde::Serialize;
+
+use crate::auth::ApiKey;
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{Memory, NewMemory, Storage};
+use crate::uploads::{read_image, save_upload, upload_url, UploadStore};
+use crate::users::CoupleScope;
+use crate::valentine::check_text;
+
+pub const MAX_CAPTION_LEN: usize = 280;
+
+/// Longest side of a thumbnail, in pixels.
+const THUMBNAIL_SIZE: u32 = 400;
+const THUMBNAIL_QUALITY: u8 = 80;
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Decodes `bytes`, turns it upright per its EXIF orientation and shrinks
+/// it to fit [`THUMBNAIL_SIZE`], as a JPEG.
+fn thumbnail(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_decoder()
+        .map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+
+    let small = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    let mut jpeg = Vec::new();
+    small
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, THUMBNAIL_QUALITY))
+        .map_err(|e| e.to_string())?;
+    Ok(jpeg)
+}
+
+#[derive(FromForm, utoipa::ToSchema)]
+struct MemoryForm<'r> {
+    #[schema(value_type = String, format = Binary)]
+    file: TempFile<'r>,
+    caption: String,
+    /// `YYYY-MM-DD`, the day the photo was taken.
+    taken_on: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct MemoryResponse {
+    id: i64,
+    caption: String,
+    taken_on: NaiveDate,
+    image_url: String,
+    thumbnail_url: String,
+    created_at: DateTime<Utc>,
+}
+
+impl MemoryResponse {
+    fn new(public_url: &PublicUrl, memory: Memory) -> Self {
+        MemoryResponse {
+            id: memory.id,
+            image_url: upload_url(public_url, &memory.upload_id),
+            thumbnail_url: upload_url(public_url, &memory.thumbnail_id),
+            caption: memory.caption,
+            taken_on: memory.taken_on,
+            created_at: memory.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct MonthGroup {
+    /// 1 to 12.
+    month: u32,
+    name: &'static str,
+    memories: Vec<MemoryResponse>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct YearGroup {
+    year: i32,
+    months: Vec<MonthGroup>,
+}
+
+/// Groups memories already sorted newest first by year, then month,
+/// keeping that order.
+fn group(memories: Vec<MemoryResponse>) -> Vec<YearGroup> {
+    let mut years: Vec<YearGroup> = Vec::new();
+    for memory in memories {
+        let (year, month) = (memory.taken_on.year(), memory.taken_on.month());
+        if years.last().is_none_or(|y| y.year != year) {
+            years.push(YearGroup {
+                year,
+                months: Vec::new(),
+            });
+        }
+        let months = &mut years.last_mut().expect("pushed above").months;
+        if months.last().is_none_or(|m| m.month != month) {
+            months.push(MonthGroup {
+                month,
+                name: MONTHS[month as usize - 1],
+                memories: Vec::new(),
+            });
+        }
+        months
+            .last_mut()
+            .expect("pushed above")
+            .memories
+            .push(memory);
+    }
+    years
+}
+
+/// Adds a photo to the timeline from a `multipart/form-data` body with
+/// `file`, `caption` and `taken_on` fields. The photo is kept as uploaded
+/// and a JPEG thumbnail is made alongside it.
+#[utoipa::path(
+    tag = "memories",
+    request_body(content = MemoryForm, content_type = "multipart/form-data"),
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = MemoryResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 413, description = "Larger than `limits.file`"),
+        (status = 415, description = "Not a PNG, JPEG, GIF or WebP image", body = ErrorResponse),
+        (status = 422, description = "Invalid field, or an image that cannot be decoded", body = ErrorResponse),
+    )
+)]
+#[post("/api/memories", data = "<form>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    store: &State<UploadStore>,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+    form: Form<MemoryForm<'_>>,
+) -> ApiResult<status::Created<Negotiated<MemoryResponse>>> {
+    let caption = form.caption.trim().to_string();
+    check_text("caption", &caption, MAX_CAPTION_LEN)
+        .map_err(|e| error(Status::UnprocessableEntity, e))?;
+    let taken_on: NaiveDate = form.taken_on.trim().parse().map_err(|_| {
+        error(
+            Status::UnprocessableEntity,
+            "`taken_on` must be a date like 2026-02-14",
+        )
+    })?;
+    let (bytes, content_type) = read_image(&form.file, "file").await?;
+
+    let thumb = {
+        let bytes = bytes.clone();
+        spawn_blocking(move || thumbnail(&bytes))
+            .await
+            .map_err(|e| {
+                error(
+                    Status::InternalServerError,
+                    format!("thumbnail generation panicked: {}", e),
+                )
+            })?
+            .map_err(|e| {
+                error(
+                    Status::UnprocessableEntity,
+                    format!("`file` could not be decoded: {}", e),
+                )
+            })?
+    };
+    let upload = save_upload(storage, store, bytes, content_type).await?;
+    let thumbnail = save_upload(storage, store, thumb, "image/jpeg").await?;
+
+    let memory = storage
+        .create_memory(&NewMemory {
+            upload_id: upload.id,
+            thumbnail_id: thumbnail.id,
+            caption,
+            taken_on,
+            couple_id: scope.0,
+        })
+        .await
+        .map_err(internal_error)?;
+    let memory = MemoryResponse::new(public_url, memory);
+    Ok(status::Created::new(memory.image_url.clone()).body(Negotiated(memory)))
+}
+
+/// Every memory, grouped by the year and month it was taken, newest first.
+#[utoipa::path(
+    tag = "memories",
+    responses((status = 200, body = Vec<YearGroup>))
+)]
+#[get("/api/memories/timeline")]
+async fn timeline(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+) -> ApiResult<Negotiated<Vec<YearGroup>>> {
+    let memories = storage
+        .list_memories(scope.0)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .map(|memory| MemoryResponse::new(public_url, memory))
+        .collect();
+    Ok(Negotiated(group(memories)))
+}
+
+/// One memory at random, for a "remember this?" widget.
+#[utoipa::path(
+    tag = "memories",
+    responses(
+        (status = 200, body = MemoryResponse),
+        (status = 404, description = "No memories yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/memories/random")]
+async fn random(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+) -> ApiResult<Negotiated<MemoryResponse>> {
+    storage
+        .random_memory(scope.0)
+        .await
+        .map_err(internal_error)?
+        .map(|memory| Negotiated(MemoryResponse::new(public_url, memory)))
+        .ok_or_else(|| error(Status::NotFound, "no memories yet"))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, timeline, random]
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn thumbnails_fit_and_timelines_group_by_month() {
+        let mut png = Cursor::new(Vec::new());
+        RgbImage::from_pixel(1200, 600, Rgb([200, 30, 60]))
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let thumb = image::load_from_memory(&thumbnail(png.get_ref()).unwrap()).unwrap();
+        assert_eq!((thumb.width(), thumb.height()), (400, 200));
+        assert!(thumbnail(b"not an image").is_err());
+
+        let memory = |id, taken_on: &str| MemoryResponse {
+            id,
+            caption: String::new(),
+            taken_on: taken_on.parse().unwrap(),
+            image_url: String::new(),
+            thumbnail_url: String::new(),
+            created_at: Utc::now(),
+        };
+        let years = group(vec![
+            memory(4, "2026-02-14"),
+            memory(3, "2026-02-01"),
+            memory(2, "2025-12-24"),
+            memory(1, "2025-06-01"),
+        ]);
+        let shape: Vec<(i32, Vec<(u32, usize)>)> = years
+            .iter()
+            .map(|y| {
+                let months = y.months.iter().map(|m| (m.month, m.memories.len()));
+                (y.year, months.collect())
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            vec![(2026, vec![(2, 2)]), (2025, vec![(12, 1), (6, 1)])]
+        );
+        assert_eq!(years[0].months[0].name, "February");
+    }
+}
@@ -0,0 +1,161 @@
+//! Placeholder templating for quotes and generated messages.
+//!
+//! Templates use `{name}`-style placeholders. Substitution happens in a single
+//! pass over the template, so values are never re-scanned for placeholders and
+//! unknown placeholders are left untouched.
+
+use std::collections::HashMap;
+
+const MAX_NAME_LEN: usize = 50;
+
+/// Variables available to a template, keyed by placeholder name.
+#[derive(Debug, Default)]
+pub struct Vars<'a> {
+    values: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Vars<'a> {
+    pub fn new() -> Self {
+        Vars::default()
+    }
+
+    pub fn with(mut self, key: &'a str, value: &'a str) -> Self {
+        self.values.insert(key, value);
+        self
+    }
+}
+
+/// Replaces every `{key}` in `template` whose key is present in `vars`.
+pub fn render(template: &str, vars: &Vars) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}') {
+            Some(end) if vars.values.contains_key(&after[..end]) => {
+                out.push_str(vars.values[&after[..end]]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+pub fn has_placeholder(template: &str, key: &str) -> bool {
+    template.contains(&format!("{{{}}}", key))
+}
+
+/// Addresses a quote to `name`, either through its `{name}` placeholder or,
+/// for quotes without one, by prefixing the name ("Emma, you are ...").
+pub fn personalize(quote: &str, name: &str) -> String {
+    if has_placeholder(quote, "name") {
+        return render(quote, &Vars::new().with("name", name));
+    }
+
+    format!("{}, {}", name, lowercase_first(quote))
+}
+
+/// Lowercases the first letter unless it starts the pronoun "I".
+fn lowercase_first(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some('I') if matches!(chars.clone().next(), None | Some(' ') | Some('\'')) => {
+            text.to_string()
+        }
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Escapes text for inclusion in HTML element content or attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Validates a recipient name before it is spliced into a template.
+pub fn sanitize_name(raw: &str) -> Result<String, String> {
+    let name = raw.trim();
+    if name.is_empty() {
+        return Err("name must not be empty".to_string());
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("name must be at most {} characters", MAX_NAME_LEN));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphabetic() || matches!(c, ' ' | '-' | '\'' | '.'))
+    {
+        return Err(
+            "name may only contain letters, spaces, hyphens, apostrophes and periods".to_string(),
+        );
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_substitutes_known_placeholders() {
+        let vars = Vars::new().with("name", "Emma");
+        assert_eq!(render("Hi {name}, {name}!", &vars), "Hi Emma, Emma!");
+    }
+
+    #[test]
+    fn render_leaves_unknown_placeholders() {
+        let vars = Vars::new().with("name", "Emma");
+        assert_eq!(render("{other} {name} {", &vars), "{other} Emma {");
+    }
+
+    #[test]
+    fn render_does_not_expand_values() {
+        let vars = Vars::new().with("name", "{name}");
+        assert_eq!(render("{name}", &vars), "{name}");
+    }
+
+    #[test]
+    fn personalize_prefixes_quotes_without_placeholder() {
+        assert_eq!(
+            personalize("You had me at hello.", "Emma"),
+            "Emma, you had me at hello."
+        );
+        assert_eq!(
+            personalize("I love you more than yesterday.", "Emma"),
+            "Emma, I love you more than yesterday."
+        );
+    }
+
+    #[test]
+    fn escape_html_escapes_markup() {
+        assert_eq!(
+            escape_html("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn sanitize_name_rejects_markup() {
+        assert!(sanitize_name("<script>").is_err());
+        assert_eq!(sanitize_name("  Mary-Jane ").unwrap(), "Mary-Jane");
+    }
+}
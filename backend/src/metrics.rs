@@ -0,0 +1,236 @@
+use std::time::Instant;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{ContentType, Status};
+use rocket::{Data, Request, Response, Route, State};
+
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::storage::{Category, PoolStatus, Storage};
+
+/// Label used for requests that matched no route, so 404 scans of random
+/// paths cannot blow up label cardinality.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Prometheus collectors for the API, exposed at `GET /metrics`.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    latency: HistogramVec,
+    quotes_served: IntCounterVec,
+    pool: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "http_request_errors_total",
+                "HTTP responses with a 4xx or 5xx status",
+            ),
+            &["method", "route", "class"],
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time from request arrival to response, in seconds",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["method", "route"],
+        )?;
+        let quotes_served = IntCounterVec::new(
+            Opts::new(
+                "valentine_quotes_served_total",
+                "Quotes returned to clients by endpoint and category",
+            ),
+            &["endpoint", "category"],
+        )?;
+        let pool = IntGaugeVec::new(
+            Opts::new(
+                "valentine_db_pool_connections",
+                "Database pool connections: open, idle, in use, and the configured min and max",
+            ),
+            &["state"],
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(quotes_served.clone()))?;
+        registry.register(Box::new(pool.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            requests,
+            errors,
+            latency,
+            quotes_served,
+            pool,
+        })
+    }
+
+    /// Counts a quote handed out by `endpoint`; `None` is the built-in
+    /// fallback used when the quote table is empty.
+    pub fn quote_served(&self, endpoint: &str, category: Option<Category>) {
+        let category = category.map_or("fallback", Category::as_str);
+        self.quotes_served
+            .with_label_values(&[endpoint, category])
+            .inc();
+    }
+
+    /// Records the main database pool as of this scrape.
+    pub fn observe_pool(&self, status: PoolStatus) {
+        for (state, count) in [
+            ("open", status.open),
+            ("idle", status.idle),
+            ("in_use", status.in_use()),
+            ("min", status.min),
+            ("max", status.max),
+        ] {
+            self.pool.with_label_values(&[state]).set(count.into());
+        }
+    }
+
+    fn observe(&self, method: &str, route: &str, status: Status, seconds: f64) {
+        let code = status.code.to_string();
+        self.requests
+            .with_label_values(&[method, route, &code])
+            .inc();
+        self.latency
+            .with_label_values(&[method, route])
+            .observe(seconds);
+
+        let class = match status.code {
+            400..=499 => "4xx",
+            500..=599 => "5xx",
+            _ => return,
+        };
+        self.errors.with_label_values(&[method, route, class]).inc();
+    }
+
+    fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("prometheus text format is UTF-8"))
+    }
+}
+
+/// Arrival time of the request, set by [`MetricsFairing`].
+struct RequestStart(Instant);
+
+/// Records request counts, errors and latency for every response.
+struct MetricsFairing;
+
+#[rocket::async_trait]
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(metrics) = request.rocket().state::<Metrics>() else {
+            return;
+        };
+
+        let started = request.local_cache(|| RequestStart(Instant::now()));
+        let route = request
+            .route()
+            .map_or(UNMATCHED_ROUTE, |route| route.uri.path());
+        metrics.observe(
+            request.method().as_str(),
+            route,
+            response.status(),
+            started.0.elapsed().as_secs_f64(),
+        );
+    }
+}
+
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", content_type = "text/plain", body = String),
+        (status = 500, body = ErrorResponse),
+    )
+)]
+#[get("/metrics")]
+fn metrics(metrics: &State<Metrics>, storage: &State<Storage>) -> ApiResult<(ContentType, String)> {
+    metrics.observe_pool(storage.pool_status());
+    let body = metrics.render().map_err(|e| {
+        error(
+            Status::InternalServerError,
+            format!("failed to encode metrics: {}", e),
+        )
+    })?;
+
+    let content_type = ContentType::new("text", "plain").with_params([("version", "0.0.4")]);
+    Ok((content_type, body))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![metrics]
+}
+
+/// Manages the [`Metrics`] registry and attaches the fairing that feeds it.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Metrics", |rocket| async {
+        match Metrics::new() {
+            Ok(metrics) => Ok(rocket.manage(metrics).attach(MetricsFairing)),
+            Err(e) => {
+                error!("failed to register metrics: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_counted_by_class() {
+        let metrics = Metrics::new().unwrap();
+        metrics.observe("GET", "/api/valentine", Status::Ok, 0.002);
+        metrics.observe("GET", "/api/valentine", Status::NotFound, 0.001);
+        metrics.quote_served("random", Some(Category::Funny));
+        metrics.observe_pool(PoolStatus {
+            open: 3,
+            idle: 1,
+            min: 1,
+            max: 10,
+        });
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(
+            r#"http_requests_total{method="GET",route="/api/valentine",status="200"} 1"#
+        ));
+        assert!(text.contains(
+            r#"http_request_errors_total{class="4xx",method="GET",route="/api/valentine"} 1"#
+        ));
+        assert!(
+            text.contains(r#"valentine_quotes_served_total{category="funny",endpoint="random"} 1"#)
+        );
+        assert!(text.contains(r#"valentine_db_pool_connections{state="in_use"} 2"#));
+    }
+}
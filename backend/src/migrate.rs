@@ -0,0 +1,46 @@
+//! Applying, reverting and listing schema migrations without starting the
+//! server, for `valentine-backend migrate` as a separate deploy step.
+
+use rocket::figment::Figment;
+
+use crate::storage::{self, Storage};
+pub use crate::storage::{MigrationState, MigrationStatus};
+
+/// Opens the database `figment` names, creating it if missing.
+async fn open(figment: &Figment) -> Result<Storage, String> {
+    let url = storage::database_url(figment);
+    Storage::connect(&url)
+        .await
+        .map_err(|e| format!("failed to open database {}: {}", url, e))
+}
+
+/// Applies every pending migration and returns the ones applied.
+pub async fn up(figment: &Figment) -> Result<Vec<MigrationStatus>, String> {
+    let storage = open(figment).await?;
+    let pending: Vec<MigrationStatus> = status_of(&storage)
+        .await?
+        .into_iter()
+        .filter(|migration| migration.state == MigrationState::Pending)
+        .collect();
+    storage.migrate().await.map_err(|e| e.to_string())?;
+    Ok(pending)
+}
+
+/// Reverts the latest `steps` applied migrations and returns their
+/// versions, newest first.
+pub async fn down(figment: &Figment, steps: usize) -> Result<Vec<i64>, String> {
+    open(figment)
+        .await?
+        .revert_migrations(steps)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Every migration this build knows, oldest first, and whether it has run.
+pub async fn status(figment: &Figment) -> Result<Vec<MigrationStatus>, String> {
+    status_of(&open(figment).await?).await
+}
+
+async fn status_of(storage: &Storage) -> Result<Vec<MigrationStatus>, String> {
+    storage.migration_status().await.map_err(|e| e.to_string())
+}
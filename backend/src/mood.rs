@@ -0,0 +1,369 @@
+//! Daily mood check-ins on a 1-5 scale and the couple's mood insights:
+//! daily and rolling averages, weekly trends and how mood moves with the
+//! check-in streak, as series ready to chart.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Datelike, Duration, NaiveDate};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::determinism::Now;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{self, MoodEntry, MoodScore, NewMood, Storage};
+use crate::timezones::{optional_zone, saved_zone, today_in, zone_for};
+use crate::users::Session;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MIN_SCORE: i64 = 1;
+const MAX_SCORE: i64 = 5;
+const MAX_NOTE_LEN: usize = 140;
+
+const DEFAULT_DAYS: i64 = 30;
+const MAX_DAYS: i64 = 365;
+/// Days in the trailing window of `rolling_average`.
+const ROLLING_DAYS: usize = 7;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct MoodRequest {
+    /// 1 (rough) to 5 (wonderful).
+    score: i64,
+    note: Option<String>,
+    /// IANA zone whose date the mood counts for; the user's saved zone by
+    /// default.
+    timezone: Option<String>,
+}
+
+impl Validate for MoodRequest {
+    /// The score, the trimmed note and the zone, if one was named.
+    type Valid = (i64, Option<String>, Option<chrono_tz::Tz>);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if !(MIN_SCORE..=MAX_SCORE).contains(&self.score) {
+            errors.add(
+                "score",
+                format!("`score` must be {} to {}", MIN_SCORE, MAX_SCORE),
+            );
+        }
+        let note = self
+            .note
+            .map(|note| note.trim().to_string())
+            .filter(|note| !note.is_empty());
+        if let Some(note) = &note {
+            errors.text("note", note, MAX_NOTE_LEN);
+        }
+        let zone = errors.check("timezone", optional_zone(self.timezone.as_deref()));
+        errors.finish((self.score, note, zone.flatten()))
+    }
+}
+
+/// One partner's scores, one per entry of [`MoodInsights::days`].
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct PartnerSeries {
+    user_id: i64,
+    name: String,
+    scores: Vec<Option<i64>>,
+}
+
+/// The couple's average over one Monday-to-Sunday week.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct WeeklyMood {
+    week_start: NaiveDate,
+    average: f64,
+    entries: usize,
+    /// Against the previous week with entries.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<f64>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct MoodInsights {
+    /// The x axis: every day of the window, oldest first. The other series
+    /// have one value per day, `null` where there is nothing to plot.
+    days: Vec<NaiveDate>,
+    partners: Vec<PartnerSeries>,
+    /// Both partners' mean score for the day.
+    average: Vec<Option<f64>>,
+    /// Mean of every score in the trailing seven days.
+    rolling_average: Vec<Option<f64>>,
+    /// The check-in streak as of each day.
+    streak: Vec<i64>,
+    weekly: Vec<WeeklyMood>,
+    /// Pearson correlation, -1 to 1, of the daily average with the streak
+    /// on days with a mood; `null` with too few days or no variation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    streak_correlation: Option<f64>,
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn mean(values: &[i64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<i64>() as f64 / values.len() as f64)
+}
+
+fn pearson(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 3 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let (mx, my) = pairs
+        .iter()
+        .fold((0.0, 0.0), |(x, y), (a, b)| (x + a / n, y + b / n));
+    let (mut cov, mut vx, mut vy) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        cov += (x - mx) * (y - my);
+        vx += (x - mx).powi(2);
+        vy += (y - my).powi(2);
+    }
+    if vx == 0.0 || vy == 0.0 {
+        return None;
+    }
+    Some(cov / (vx * vy).sqrt())
+}
+
+/// Insights over the `window` days up to `today` from `scores`, oldest
+/// first, and the days either partner checked in on.
+fn insights(
+    scores: Vec<MoodScore>,
+    checkins: &BTreeSet<NaiveDate>,
+    today: NaiveDate,
+    window: i64,
+) -> MoodInsights {
+    let start = today - Duration::days(window - 1);
+    let days: Vec<NaiveDate> = (0..window).map(|i| start + Duration::days(i)).collect();
+
+    let mut by_day: BTreeMap<NaiveDate, Vec<i64>> = BTreeMap::new();
+    let mut partners: Vec<PartnerSeries> = Vec::new();
+    for entry in scores
+        .into_iter()
+        .filter(|s| (start..=today).contains(&s.day))
+    {
+        by_day.entry(entry.day).or_default().push(entry.score);
+        let index = match partners.iter().position(|p| p.user_id == entry.user_id) {
+            Some(index) => index,
+            None => {
+                partners.push(PartnerSeries {
+                    user_id: entry.user_id,
+                    name: entry.name,
+                    scores: vec![None; days.len()],
+                });
+                partners.len() - 1
+            }
+        };
+        partners[index].scores[(entry.day - start).num_days() as usize] = Some(entry.score);
+    }
+    partners.sort_by_key(|p| p.user_id);
+
+    let daily: Vec<Option<f64>> = days
+        .iter()
+        .map(|day| by_day.get(day).and_then(|s| mean(s)))
+        .collect();
+    let rolling_average = days
+        .iter()
+        .map(|&day| {
+            let from = day - Duration::days(ROLLING_DAYS as i64 - 1);
+            let recent: Vec<i64> = by_day
+                .range(from..=day)
+                .flat_map(|(_, s)| s.iter().copied())
+                .collect();
+            mean(&recent).map(round)
+        })
+        .collect();
+
+    let mut run = (1..)
+        .take_while(|&i| checkins.contains(&(start - Duration::days(i))))
+        .count() as i64;
+    let streak: Vec<i64> = days
+        .iter()
+        .map(|day| {
+            run = if checkins.contains(day) { run + 1 } else { 0 };
+            run
+        })
+        .collect();
+    let pairs: Vec<(f64, f64)> = daily
+        .iter()
+        .zip(&streak)
+        .filter_map(|(mood, &streak)| mood.map(|m| (m, streak as f64)))
+        .collect();
+
+    let mut weeks: BTreeMap<NaiveDate, Vec<i64>> = BTreeMap::new();
+    for (day, day_scores) in &by_day {
+        let monday = *day - Duration::days(day.weekday().num_days_from_monday() as i64);
+        weeks.entry(monday).or_default().extend(day_scores);
+    }
+    let mut weekly: Vec<WeeklyMood> = Vec::new();
+    for (week_start, week_scores) in weeks {
+        let average = mean(&week_scores).expect("weeks have entries");
+        weekly.push(WeeklyMood {
+            week_start,
+            change: weekly.last().map(|last| round(average - last.average)),
+            average: round(average),
+            entries: week_scores.len(),
+        });
+    }
+
+    MoodInsights {
+        days,
+        partners,
+        average: daily.into_iter().map(|m| m.map(round)).collect(),
+        rolling_average,
+        streak,
+        weekly,
+        streak_correlation: pearson(&pairs).map(round),
+    }
+}
+
+/// Logs the signed-in partner's mood for their current local day, once a
+/// day.
+#[utoipa::path(
+    tag = "users",
+    request_body = MoodRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = MoodEntry),
+        (status = 401, body = ErrorResponse),
+        (status = 409, description = "Already logged a mood today", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/mood", data = "<request>")]
+async fn log_mood(
+    session: Session,
+    storage: &Storage,
+    now: Now,
+    request: Valid<MoodRequest>,
+) -> ApiResult<status::Created<Negotiated<MoodEntry>>> {
+    let (score, note, zone) = request.into_inner();
+    let zone = zone.unwrap_or_else(|| saved_zone(&session.0));
+
+    let mood = NewMood {
+        user_id: session.0.id,
+        day: today_in(now.0, zone),
+        timezone: zone.name().to_string(),
+        score,
+        note,
+    };
+    match storage.create_mood(&mood).await {
+        Ok(mood) => {
+            let location = uri!(mood_insights(None::<i64>, None::<&str>)).to_string();
+            Ok(status::Created::new(location).body(Negotiated(mood)))
+        }
+        Err(e) if storage::is_unique_violation(&e) => Err(error(
+            Status::Conflict,
+            format!("you already logged your mood on {}", mood.day),
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Mood insights for the signed-in user and their partner over the last
+/// `days` days (30 by default, at most 365), counted in `timezone`.
+#[utoipa::path(
+    tag = "users",
+    params(
+        ("days" = Option<i64>, Query, description = "Days to cover, 30 by default"),
+        ("timezone" = Option<String>, Query, description = "IANA zone for today's date"),
+    ),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = MoodInsights),
+        (status = 400, description = "Unknown timezone or `days` out of range", body = ErrorResponse),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[get("/api/mood/insights?<days>&<timezone>")]
+async fn mood_insights(
+    session: Session,
+    storage: &Storage,
+    now: Now,
+    days: Option<i64>,
+    timezone: Option<&str>,
+) -> ApiResult<Negotiated<MoodInsights>> {
+    let zone = zone_for(timezone, Some(&session.0)).map_err(|e| error(Status::BadRequest, e))?;
+    let today = today_in(now.0, zone);
+    let days = days.unwrap_or(DEFAULT_DAYS);
+    if !(1..=MAX_DAYS).contains(&days) {
+        return Err(error(
+            Status::BadRequest,
+            format!("`days` must be 1 to {}", MAX_DAYS),
+        ));
+    }
+
+    let since = today - Duration::days(days - 1);
+    let scores = storage
+        .mood_scores(session.0.id, since)
+        .await
+        .map_err(internal_error)?;
+    let checkins: BTreeSet<NaiveDate> = storage
+        .checkin_days(session.0.id)
+        .await
+        .map_err(internal_error)?
+        .into_iter()
+        .flat_map(|member| member.days)
+        .collect();
+
+    Ok(Negotiated(insights(scores, &checkins, today, days)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![log_mood, mood_insights]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insights_line_up_one_value_per_day() {
+        let day = |d: &str| d.parse::<NaiveDate>().unwrap();
+        let score = |user_id, d, score| MoodScore {
+            user_id,
+            name: format!("partner {}", user_id),
+            day: day(d),
+            score,
+        };
+        let scores = vec![
+            score(1, "2026-02-02", 2),
+            score(2, "2026-02-02", 4),
+            score(1, "2026-02-03", 3),
+            score(2, "2026-02-05", 4),
+            score(1, "2026-02-09", 5),
+        ];
+        let checkins: BTreeSet<NaiveDate> = [
+            "2026-02-01",
+            "2026-02-02",
+            "2026-02-03",
+            "2026-02-05",
+            "2026-02-09",
+        ]
+        .into_iter()
+        .map(day)
+        .collect();
+
+        let insights = insights(scores, &checkins, day("2026-02-09"), 8);
+        assert_eq!(insights.days.first(), Some(&day("2026-02-02")));
+        assert_eq!(insights.days.len(), 8);
+        assert_eq!(insights.partners[1].scores[0], Some(4));
+        assert_eq!(insights.partners[1].scores[1], None);
+        assert_eq!(insights.average[0], Some(3.0));
+        assert_eq!(insights.average[2], None);
+        assert_eq!(insights.rolling_average[3], Some(3.25));
+        assert_eq!(insights.streak, vec![2, 3, 0, 1, 0, 0, 0, 1]);
+
+        assert_eq!(insights.weekly.len(), 2);
+        assert_eq!(insights.weekly[0].week_start, day("2026-02-02"));
+        assert_eq!(insights.weekly[0].average, 3.25);
+        assert_eq!(insights.weekly[1].change, Some(1.75));
+        assert!(insights.streak_correlation.is_some());
+        assert_eq!(pearson(&[(1.0, 2.0), (1.0, 3.0), (1.0, 4.0)]), None);
+    }
+}
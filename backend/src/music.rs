@@ -0,0 +1,530 @@
+//! Track lists for the valentine page, by mood. Tracks come from a Spotify
+//! search (client-credentials auth, cached per mood) when `spotify` is
+//! configured, and from a built-in playlist when it is not or the API is
+//! unavailable.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use moka::future::Cache;
+use reqwest::{StatusCode, Url};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::tokio::sync::Mutex;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::providers::{self, MockProvider};
+use crate::resilience::{Resilience, ResilientClient};
+
+const DEFAULT_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+const DEFAULT_API_URL: &str = "https://api.spotify.com/v1";
+
+/// How long a mood's Spotify results are reused.
+const PLAYLIST_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Tokens are refreshed this long before Spotify says they expire, so one
+/// never runs out mid-request.
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mood {
+    SlowDance,
+    Romantic,
+    Upbeat,
+    Acoustic,
+    Throwback,
+}
+
+impl Mood {
+    pub const ALL: [Mood; 5] = [
+        Mood::SlowDance,
+        Mood::Romantic,
+        Mood::Upbeat,
+        Mood::Acoustic,
+        Mood::Throwback,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mood::SlowDance => "slow-dance",
+            Mood::Romantic => "romantic",
+            Mood::Upbeat => "upbeat",
+            Mood::Acoustic => "acoustic",
+            Mood::Throwback => "throwback",
+        }
+    }
+
+    /// The Spotify search that stands in for the mood.
+    fn query(self) -> &'static str {
+        match self {
+            Mood::SlowDance => "slow dance love songs",
+            Mood::Romantic => "romantic love songs",
+            Mood::Upbeat => "happy love songs",
+            Mood::Acoustic => "acoustic love songs",
+            Mood::Throwback => "classic love songs year:1960-1989",
+        }
+    }
+
+    /// Played when Spotify is not configured or cannot be reached.
+    fn fallback(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Mood::SlowDance => &[
+                ("At Last", "Etta James"),
+                ("Make You Feel My Love", "Adele"),
+                ("Thinking Out Loud", "Ed Sheeran"),
+                ("Can't Help Falling in Love", "Elvis Presley"),
+                ("Unchained Melody", "The Righteous Brothers"),
+                ("Wonderful Tonight", "Eric Clapton"),
+            ],
+            Mood::Romantic => &[
+                ("All of Me", "John Legend"),
+                ("Perfect", "Ed Sheeran"),
+                ("Lover", "Taylor Swift"),
+                ("Adore You", "Harry Styles"),
+                ("Die With A Smile", "Lady Gaga, Bruno Mars"),
+                ("Yellow", "Coldplay"),
+            ],
+            Mood::Upbeat => &[
+                ("Crazy in Love", "Beyoncé"),
+                ("I Wanna Dance with Somebody", "Whitney Houston"),
+                ("Signed, Sealed, Delivered I'm Yours", "Stevie Wonder"),
+                ("Can't Stop the Feeling!", "Justin Timberlake"),
+                ("Walking on Sunshine", "Katrina and the Waves"),
+                ("Marry You", "Bruno Mars"),
+            ],
+            Mood::Acoustic => &[
+                ("I'm Yours", "Jason Mraz"),
+                ("Banana Pancakes", "Jack Johnson"),
+                ("Lucky", "Jason Mraz, Colbie Caillat"),
+                ("Bloom", "The Paper Kites"),
+                ("Such Great Heights", "Iron & Wine"),
+                ("First Day of My Life", "Bright Eyes"),
+            ],
+            Mood::Throwback => &[
+                ("Let's Stay Together", "Al Green"),
+                (
+                    "Ain't No Mountain High Enough",
+                    "Marvin Gaye, Tammi Terrell",
+                ),
+                ("Something", "The Beatles"),
+                ("Your Song", "Elton John"),
+                ("Just the Way You Are", "Billy Joel"),
+                ("Endless Love", "Diana Ross, Lionel Richie"),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Mood {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Mood {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Mood::ALL
+            .into_iter()
+            .find(|v| v.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = Mood::ALL.iter().map(|v| v.as_str()).collect();
+                format!(
+                    "unknown mood `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Track {
+    title: String,
+    artist: String,
+    album: Option<String>,
+    /// Opens the track in Spotify, or a Spotify search for fallback tracks.
+    spotify_url: String,
+    /// For an `<iframe>`; only set for tracks that came from Spotify.
+    embed_url: Option<String>,
+    /// A 30-second MP3 clip, when Spotify offers one.
+    preview_url: Option<String>,
+    duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Spotify,
+    /// The built-in playlist.
+    Fallback,
+    /// The built-in playlist, served by the mock provider.
+    Mock,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct Playlist {
+    mood: Mood,
+    source: Source,
+    tracks: Vec<Track>,
+}
+
+pub(crate) fn fallback_tracks(mood: Mood) -> Vec<Track> {
+    mood.fallback()
+        .iter()
+        .map(|&(title, artist)| {
+            let mut search = Url::parse("https://open.spotify.com/search").expect("valid URL");
+            search
+                .path_segments_mut()
+                .expect("http URLs have paths")
+                .push(&format!("{} {}", title, artist));
+            Track {
+                title: title.to_string(),
+                artist: artist.to_string(),
+                album: None,
+                spotify_url: search.to_string(),
+                embed_url: None,
+                preview_url: None,
+                duration_ms: None,
+            }
+        })
+        .collect()
+}
+
+/// Somewhere to find tracks for a mood.
+#[rocket::async_trait]
+pub trait MusicProvider: Send + Sync {
+    /// Reported with the tracks this provider returns.
+    fn source(&self) -> Source;
+
+    async fn playlist(&self, mood: Mood) -> Result<Vec<Track>, String>;
+}
+
+/// The `[default.spotify]` table in Rocket.toml. Credentials come from an
+/// app registered at developer.spotify.com; no user login is involved.
+#[derive(Debug, Deserialize)]
+struct SpotifyConfig {
+    client_id: String,
+    client_secret: String,
+    /// ISO 3166-1 country whose catalog is searched.
+    #[serde(default = "default_market")]
+    market: String,
+    #[serde(default = "default_token_url")]
+    token_url: String,
+    #[serde(default = "default_api_url")]
+    api_url: String,
+}
+
+fn default_market() -> String {
+    "US".to_string()
+}
+
+fn default_token_url() -> String {
+    DEFAULT_TOKEN_URL.to_string()
+}
+
+fn default_api_url() -> String {
+    DEFAULT_API_URL.to_string()
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+struct AccessToken {
+    value: String,
+    refresh_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    tracks: SearchPage,
+}
+
+#[derive(Deserialize)]
+struct SearchPage {
+    items: Vec<SpotifyTrack>,
+}
+
+#[derive(Deserialize)]
+struct SpotifyTrack {
+    id: String,
+    name: String,
+    artists: Vec<Named>,
+    album: Option<Named>,
+    external_urls: ExternalUrls,
+    preview_url: Option<String>,
+    duration_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ExternalUrls {
+    spotify: String,
+}
+
+impl From<SpotifyTrack> for Track {
+    fn from(track: SpotifyTrack) -> Self {
+        Track {
+            artist: track
+                .artists
+                .into_iter()
+                .map(|a| a.name)
+                .collect::<Vec<_>>()
+                .join(", "),
+            title: track.name,
+            album: track.album.map(|a| a.name),
+            spotify_url: track.external_urls.spotify,
+            embed_url: Some(format!("https://open.spotify.com/embed/track/{}", track.id)),
+            preview_url: track.preview_url,
+            duration_ms: track.duration_ms,
+        }
+    }
+}
+
+/// Spotify Web API client with a shared access token and a per-mood cache
+/// of search results.
+pub struct Spotify {
+    config: SpotifyConfig,
+    client: ResilientClient,
+    token: Mutex<Option<AccessToken>>,
+    playlists: Cache<Mood, Vec<Track>>,
+}
+
+impl Spotify {
+    fn new(config: SpotifyConfig, client: ResilientClient) -> Self {
+        Spotify {
+            config,
+            client,
+            token: Mutex::new(None),
+            playlists: Cache::builder()
+                .max_capacity(Mood::ALL.len() as u64)
+                .time_to_live(PLAYLIST_TTL)
+                .build(),
+        }
+    }
+
+    /// A current access token, fetching a new one when the last is about to
+    /// expire. The lock keeps concurrent requests from all refreshing.
+    async fn access_token(&self) -> Result<String, String> {
+        let mut token = self.token.lock().await;
+        if let Some(current) = token.as_ref().filter(|t| Instant::now() < t.refresh_at) {
+            return Ok(current.value.clone());
+        }
+
+        let request = self
+            .client
+            .post(&self.config.token_url)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[("grant_type", "client_credentials")]);
+        let response = self.client.send(request).await?;
+        if !response.status().is_success() {
+            return Err(format!("token endpoint responded {}", response.status()));
+        }
+        let issued: TokenResponse = response.json().await.map_err(|e| e.to_string())?;
+
+        let lifetime = Duration::from_secs(issued.expires_in).saturating_sub(TOKEN_MARGIN);
+        *token = Some(AccessToken {
+            value: issued.access_token.clone(),
+            refresh_at: Instant::now() + lifetime,
+        });
+        Ok(issued.access_token)
+    }
+
+    async fn forget_token(&self) {
+        *self.token.lock().await = None;
+    }
+
+    async fn search(&self, mood: Mood) -> Result<Vec<Track>, String> {
+        let url = format!("{}/search", self.config.api_url.trim_end_matches('/'));
+        let limit = MAX_LIMIT.to_string();
+        let query = [
+            ("q", mood.query()),
+            ("type", "track"),
+            ("market", &self.config.market),
+            ("limit", &limit),
+        ];
+
+        // A token can be revoked before it expires; on 401 fetch a fresh
+        // one and try once more.
+        for attempt in 0..2 {
+            let token = self.access_token().await?;
+            let request = self.client.get(&url).bearer_auth(token).query(&query);
+            let response = self.client.send(request).await?;
+            match response.status() {
+                StatusCode::UNAUTHORIZED if attempt == 0 => self.forget_token().await,
+                status if status.is_success() => {
+                    let page: SearchResponse = response.json().await.map_err(|e| e.to_string())?;
+                    return Ok(page.tracks.items.into_iter().map(Track::from).collect());
+                }
+                status => return Err(format!("search responded {}", status)),
+            }
+        }
+        Err("search rejected a freshly issued token".to_string())
+    }
+}
+
+#[rocket::async_trait]
+impl MusicProvider for Spotify {
+    fn source(&self) -> Source {
+        Source::Spotify
+    }
+
+    async fn playlist(&self, mood: Mood) -> Result<Vec<Track>, String> {
+        if let Some(tracks) = self.playlists.get(&mood).await {
+            return Ok(tracks);
+        }
+        let tracks = self.search(mood).await?;
+        if tracks.is_empty() {
+            return Err(format!("no tracks found for `{}`", mood.query()));
+        }
+        self.playlists.insert(mood, tracks.clone()).await;
+        Ok(tracks)
+    }
+}
+
+/// Tracks for `mood` (default `romantic`), at most `limit` (1-20, default
+/// 10). `source` says whether they came from Spotify or the built-in
+/// playlist, which is used when Spotify is not configured or is failing,
+/// or `mock` in offline mode.
+#[utoipa::path(
+    tag = "music",
+    params(
+        ("mood" = Option<Mood>, Query),
+        ("limit" = Option<usize>, Query, minimum = 1, maximum = 20),
+    ),
+    responses(
+        (status = 200, body = Playlist),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+#[get("/api/music?<mood>&<limit>")]
+async fn music(
+    provider: &State<Option<Box<dyn MusicProvider>>>,
+    mood: Option<&str>,
+    limit: Option<usize>,
+) -> ApiResult<Negotiated<Playlist>> {
+    let mood = mood
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(Mood::Romantic);
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(error(
+            Status::BadRequest,
+            format!("`limit` must be between 1 and {}", MAX_LIMIT),
+        ));
+    }
+
+    let from_provider = match provider.inner() {
+        Some(provider) => match provider.playlist(mood).await {
+            Ok(tracks) => Some((provider.source(), tracks)),
+            Err(e) => {
+                warn!("spotify unavailable, using the fallback playlist: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let (source, mut tracks) =
+        from_provider.unwrap_or_else(|| (Source::Fallback, fallback_tracks(mood)));
+    tracks.truncate(limit);
+
+    Ok(Negotiated(Playlist {
+        mood,
+        source,
+        tracks,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![music]
+}
+
+/// Manages an `Option<Box<dyn MusicProvider>>`: the mock provider in mock
+/// mode, otherwise Spotify from the optional `spotify` table. Must be
+/// attached after the providers and resilience stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Music", |rocket| async {
+        if let Some(log) = providers::mock_log(&rocket) {
+            let provider: Box<dyn MusicProvider> = Box::new(MockProvider::new(log));
+            return Ok(rocket.manage(Some(provider)));
+        }
+        let provider: Option<Box<dyn MusicProvider>> =
+            match rocket.figment().extract_inner::<SpotifyConfig>("spotify") {
+                Ok(config) => {
+                    let Some(client) = rocket.state::<Resilience>().map(|r| r.client("spotify"))
+                    else {
+                        error!("music stage attached before the resilience stage");
+                        return Err(rocket);
+                    };
+                    Some(Box::new(Spotify::new(config, client)))
+                }
+                Err(e) if e.missing() => {
+                    info!("no spotify config found, serving the built-in playlists");
+                    None
+                }
+                Err(e) => {
+                    error!("invalid spotify config: {}", e);
+                    return Err(rocket);
+                }
+            };
+
+        Ok(rocket.manage(provider))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_results_become_embeddable_tracks() {
+        let page: SearchResponse = rocket::serde::json::from_str(
+            r#"{"tracks": {"items": [{
+                "id": "abc123",
+                "name": "At Last",
+                "artists": [{"name": "Etta James"}, {"name": "Someone Else"}],
+                "album": {"name": "At Last!"},
+                "external_urls": {"spotify": "https://open.spotify.com/track/abc123"},
+                "preview_url": null,
+                "duration_ms": 180000
+            }]}}"#,
+        )
+        .unwrap();
+        let track = Track::from(page.tracks.items.into_iter().next().unwrap());
+        assert_eq!(track.artist, "Etta James, Someone Else");
+        assert_eq!(
+            track.embed_url.as_deref(),
+            Some("https://open.spotify.com/embed/track/abc123")
+        );
+
+        for mood in Mood::ALL {
+            assert_eq!(mood.as_str().parse::<Mood>(), Ok(mood));
+            let tracks = fallback_tracks(mood);
+            assert!(tracks.len() >= 5, "{}", mood);
+            assert!(tracks.iter().all(|t| t.embed_url.is_none()));
+        }
+        assert_eq!(
+            fallback_tracks(Mood::SlowDance)[0].spotify_url,
+            "https://open.spotify.com/search/At%20Last%20Etta%20James"
+        );
+        assert!("disco".parse::<Mood>().is_err());
+    }
+}
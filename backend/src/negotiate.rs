@@ -0,0 +1,162 @@
+//! Response formats. API routes answer in JSON unless `Accept` prefers
+//! MessagePack, which the Unity mini-game client reads, or XML. Routes
+//! return [`Negotiated`] around the same serializable types as before, and
+//! the [`crate::envelope::Envelope`] fairing adds `meta` in whichever format
+//! the data was encoded in.
+
+use std::io::Cursor;
+
+use rocket::http::{Accept, ContentType, MediaType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json;
+use rocket::Request;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Xml,
+}
+
+impl Format {
+    fn of(media_type: &MediaType) -> Option<Format> {
+        let (top, sub) = (media_type.top().as_str(), media_type.sub().as_str());
+        match (
+            top.to_ascii_lowercase().as_str(),
+            sub.to_ascii_lowercase().as_str(),
+        ) {
+            ("*", "*") | ("application", "*" | "json") => Some(Format::Json),
+            ("application", "msgpack" | "x-msgpack" | "vnd.msgpack") => Some(Format::MessagePack),
+            ("application" | "text", "xml") => Some(Format::Xml),
+            _ => None,
+        }
+    }
+
+    /// The supported format `accept` weighs highest, the earliest listed on
+    /// a tie, and JSON when it names none of them. Browsers list `text/html`
+    /// first and XML after it; they get JSON.
+    pub fn for_accept(accept: &Accept) -> Format {
+        if accept
+            .first()
+            .is_some_and(|first| first.media_type().is_html())
+        {
+            return Format::Json;
+        }
+        accept
+            .iter()
+            .filter_map(|media_type| {
+                let weight = media_type.weight_or(1.0);
+                Some((Format::of(media_type.media_type())?, weight)).filter(|_| weight > 0.0)
+            })
+            .fold(
+                None,
+                |best: Option<(Format, f32)>, (format, weight)| match best {
+                    Some((_, highest)) if highest >= weight => best,
+                    _ => Some((format, weight)),
+                },
+            )
+            .map_or(Format::Json, |(format, _)| format)
+    }
+
+    pub fn preferred(request: &Request<'_>) -> Format {
+        request.accept().map_or(Format::Json, Format::for_accept)
+    }
+
+    pub fn content_type(self) -> ContentType {
+        match self {
+            Format::Json => ContentType::JSON,
+            Format::MessagePack => ContentType::MsgPack,
+            Format::Xml => ContentType::new("application", "xml"),
+        }
+    }
+
+    /// `value` in this format. XML has no unnamed top level, so the document
+    /// is rooted at a `root` element.
+    pub fn encode<T: Serialize>(self, value: &T, root: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => json::to_string(value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Xml => quick_xml::se::to_string_with_root(root, value)
+                .map(String::into_bytes)
+                .map_err(|e| e.to_string()),
+        }
+    }
+
+    /// A response with `value` as its whole body, in the format `request`
+    /// prefers.
+    pub fn respond<T: Serialize>(
+        request: &Request<'_>,
+        value: &T,
+        root: &str,
+    ) -> response::Result<'static> {
+        let format = Format::preferred(request);
+        let body = format.encode(value, root).map_err(|e| {
+            error!("failed to encode a {:?} response: {}", format, e);
+            Status::InternalServerError
+        })?;
+        request.local_cache(|| Encoded(Some(format)));
+        Response::build()
+            .header(format.content_type())
+            .raw_header("Vary", "Accept")
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+/// The format [`Format::respond`] encoded this request's response in.
+struct Encoded(Option<Format>);
+
+/// How this request's response body was encoded, if it was negotiated.
+pub fn encoded(request: &Request<'_>) -> Option<Format> {
+    request.local_cache(|| Encoded(None)).0
+}
+
+/// Responder: `T` as the `data` of the standard response, in the format
+/// the request prefers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negotiated<T>(pub T);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Negotiated<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        Format::respond(request, &self.0, "data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_picks_the_highest_weighted_supported_format() {
+        let format = |header: &str| Format::for_accept(&header.parse().unwrap());
+        assert_eq!(format("application/msgpack"), Format::MessagePack);
+        assert_eq!(
+            format("application/x-msgpack, application/json"),
+            Format::MessagePack
+        );
+        assert_eq!(format("application/json, application/xml"), Format::Json);
+        assert_eq!(format("application/json;q=0.5, text/xml"), Format::Xml);
+        assert_eq!(format("image/png, */*;q=0.1"), Format::Json);
+        assert_eq!(format("application/msgpack;q=0"), Format::Json);
+        assert_eq!(
+            format("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"),
+            Format::Json
+        );
+
+        #[derive(Serialize)]
+        struct Quote {
+            text: &'static str,
+        }
+        let quote = Quote { text: "Be mine" };
+        assert_eq!(
+            Format::Xml.encode(&quote, "data").unwrap(),
+            b"<data><text>Be mine</text></data>"
+        );
+        let decoded: json::Value =
+            rmp_serde::from_slice(&Format::MessagePack.encode(&quote, "data").unwrap()).unwrap();
+        assert_eq!(decoded, json::json!({ "text": "Be mine" }));
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::serde::json;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::{self, error::RecvError};
+use rocket::{Route, Shutdown, State};
+use rocket_ws as ws;
+use serde::{Deserialize, Serialize};
+
+use crate::events::{self, DomainEvent, Subscriber};
+use crate::shared::{Channel, Local, SharedState};
+use crate::storage::{Message, Storage};
+use crate::users::CoupleScope;
+
+/// How many notes a slow subscriber may fall behind before it starts
+/// skipping the oldest ones.
+const FEED_CAPACITY: usize = 64;
+
+/// A new valentine and the tenant whose site it was submitted on.
+#[derive(Clone)]
+pub struct Note {
+    pub tenant: Option<String>,
+    pub message: Message,
+}
+
+impl Note {
+    /// Whether a subscriber on `tenant`'s site, scoped to `couple_id`, should
+    /// see the note.
+    pub fn visible_to(&self, tenant: Option<&str>, couple_id: Option<i64>) -> bool {
+        self.tenant.as_deref() == tenant && self.message.couple_id == couple_id
+    }
+}
+
+/// A note as sent between instances. `couple_id` is not part of a
+/// message's JSON, so it travels alongside.
+#[derive(Serialize, Deserialize)]
+struct PublishedNote {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tenant: Option<String>,
+    couple_id: Option<i64>,
+    message: Message,
+}
+
+/// Fan-out of newly submitted valentines to connected WebSocket clients,
+/// across instances when [`SharedState`] is in Redis.
+#[derive(Clone)]
+pub struct NotesFeed {
+    sender: broadcast::Sender<Note>,
+    shared: Arc<dyn SharedState>,
+}
+
+impl NotesFeed {
+    /// A feed that also hands out the notes `shared` relays from other
+    /// instances.
+    pub fn new(shared: Arc<dyn SharedState>) -> Self {
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        let relayed = sender.clone();
+        shared.subscribe(
+            Channel::Notes,
+            Box::new(move |payload| deliver_published(&relayed, payload)),
+        );
+        NotesFeed { sender, shared }
+    }
+
+    /// Publishes a note from `tenant`'s site to every current subscriber, on
+    /// every instance. Having nobody listening is not an error.
+    pub async fn publish(&self, tenant: Option<&str>, message: &Message) {
+        let published = PublishedNote {
+            tenant: tenant.map(str::to_string),
+            couple_id: message.couple_id,
+            message: message.clone(),
+        };
+        let payload = json::to_string(&published).expect("notes always serialize");
+        if let Err(e) = self.shared.publish(Channel::Notes, &payload).await {
+            // Better this instance's subscribers than nobody.
+            error!("failed to publish note {}: {}", message.id, e);
+            self.deliver(Note {
+                tenant: published.tenant,
+                message: message.clone(),
+            });
+        }
+    }
+
+    fn deliver(&self, note: Note) {
+        let _ = self.sender.send(note);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Note> {
+        self.sender.subscribe()
+    }
+}
+
+/// Hands a note published by any instance to this one's subscribers.
+fn deliver_published(sender: &broadcast::Sender<Note>, payload: &str) {
+    match json::from_str::<PublishedNote>(payload) {
+        Ok(note) => {
+            let _ = sender.send(Note {
+                tenant: note.tenant,
+                message: Message {
+                    couple_id: note.couple_id,
+                    ..note.message
+                },
+            });
+        }
+        Err(e) => warn!("ignoring malformed published note: {}", e),
+    }
+}
+
+#[rocket::async_trait]
+impl Subscriber for NotesFeed {
+    fn name(&self) -> &'static str {
+        "notes feed"
+    }
+
+    async fn handle(&self, storage: &Storage, event: &DomainEvent) {
+        if let DomainEvent::MessageCreated { message } = event {
+            self.publish(storage.tenant(), message).await;
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "messages",
+    responses((status = 101, description = "WebSocket pushing each new valentine as a `Message`")),
+)]
+#[get("/ws/notes")]
+fn notes(
+    socket: ws::WebSocket,
+    feed: &State<NotesFeed>,
+    storage: &Storage,
+    scope: CoupleScope,
+    mut shutdown: Shutdown,
+) -> ws::Channel<'static> {
+    let mut notes = feed.subscribe();
+    let tenant = storage.tenant().map(str::to_string);
+
+    socket.channel(move |mut stream| {
+        Box::pin(async move {
+            loop {
+                select! {
+                    note = notes.recv() => match note {
+                        Ok(note) if !note.visible_to(tenant.as_deref(), scope.0) => {}
+                        Ok(note) => {
+                            let json = json::to_string(&note.message)
+                                .expect("messages always serialize");
+                            stream.send(ws::Message::Text(json)).await?;
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("notes subscriber lagged, skipped {} notes", skipped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    incoming = stream.next() => match incoming {
+                        Some(Ok(ws::Message::Close(_))) | None => break,
+                        Some(Err(e)) => return Err(e),
+                        Some(Ok(_)) => {}
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+
+            Ok(())
+        })
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![notes]
+}
+
+/// Manages the [`NotesFeed`] and subscribes it to new messages. Attach it
+/// after [`crate::shared::stage`] and [`crate::events::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Notes Feed", |rocket| async {
+        let shared = rocket
+            .state::<Arc<dyn SharedState>>()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Local::default()));
+        let feed = NotesFeed::new(shared);
+        rocket.manage(feed.clone()).attach(events::subscriber(feed))
+    })
+}
@@ -0,0 +1,354 @@
+//! Sign-in with Google or GitHub. Each provider is enabled by its
+//! `[default.oauth.<name>]` table in Rocket.toml; the callback maps the
+//! external account onto a local user (creating one on first sign-in) and
+//! starts the same session as a password login.
+
+use rocket::fairing::AdHoc;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::response::Redirect;
+use rocket::time::Duration;
+use rocket::{Route, State};
+use rocket_oauth2::{OAuth2, TokenResponse};
+use serde::Deserialize;
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::invites::{self, InviteOnly};
+use crate::resilience::{Resilience, ResilientClient};
+use crate::storage::{self, NewUser, Storage, User};
+use crate::users::{self, normalize_email};
+use crate::valentine::MAX_NAME_LEN;
+
+const DEFAULT_REDIRECT: &str = "/";
+
+/// Carries the `invite` given to a login route through the provider's
+/// redirects to the callback.
+const INVITE_COOKIE: &str = "valentine_invite";
+const INVITE_MINUTES: i64 = 15;
+
+const GOOGLE_USERINFO: &str = "https://openidconnect.googleapis.com/v1/userinfo";
+const GITHUB_USER: &str = "https://api.github.com/user";
+const GITHUB_EMAILS: &str = "https://api.github.com/user/emails";
+
+/// Provider marker types for `rocket_oauth2`.
+pub struct Google;
+pub struct GitHub;
+
+/// Where the browser is sent after signing in, from `oauth.redirect_to`.
+struct LoginRedirect(String);
+
+/// Who the provider says signed in. Only verified addresses are used, since
+/// an email match links the external account to an existing user.
+#[derive(Debug)]
+struct Profile {
+    provider: &'static str,
+    subject: String,
+    email: String,
+    name: String,
+}
+
+fn provider_error(provider: &str, e: impl std::fmt::Display) -> crate::error::ApiError {
+    warn!("{} profile request failed: {}", provider, e);
+    error(
+        Status::BadGateway,
+        format!("could not load your {} profile", provider),
+    )
+}
+
+fn no_verified_email(provider: &str) -> crate::error::ApiError {
+    error(
+        Status::UnprocessableEntity,
+        format!("your {} account has no verified email address", provider),
+    )
+}
+
+async fn google_profile(client: &ResilientClient, token: &str) -> ApiResult<Profile> {
+    #[derive(Deserialize)]
+    struct UserInfo {
+        sub: String,
+        email: Option<String>,
+        #[serde(default)]
+        email_verified: bool,
+        name: Option<String>,
+    }
+
+    let info: UserInfo = client
+        .send(client.get(GOOGLE_USERINFO).bearer_auth(token))
+        .await
+        .and_then(|r| r.error_for_status().map_err(|e| e.to_string()))
+        .map_err(|e| provider_error("google", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("google", e))?;
+
+    let email = info
+        .email
+        .filter(|_| info.email_verified)
+        .ok_or_else(|| no_verified_email("google"))?;
+    Ok(Profile {
+        provider: "google",
+        subject: info.sub,
+        name: info.name.unwrap_or_else(|| email.clone()),
+        email,
+    })
+}
+
+async fn github_profile(client: &ResilientClient, token: &str) -> ApiResult<Profile> {
+    #[derive(Deserialize)]
+    struct UserInfo {
+        id: i64,
+        login: String,
+        name: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Email {
+        email: String,
+        primary: bool,
+        verified: bool,
+    }
+
+    let get = |url| {
+        client.send(
+            client
+                .get(url)
+                .bearer_auth(token)
+                .header("Accept", "application/vnd.github+json"),
+        )
+    };
+    let info: UserInfo = get(GITHUB_USER)
+        .await
+        .and_then(|r| r.error_for_status().map_err(|e| e.to_string()))
+        .map_err(|e| provider_error("github", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("github", e))?;
+    let emails: Vec<Email> = get(GITHUB_EMAILS)
+        .await
+        .and_then(|r| r.error_for_status().map_err(|e| e.to_string()))
+        .map_err(|e| provider_error("github", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("github", e))?;
+
+    let email = emails
+        .into_iter()
+        .filter(|e| e.verified)
+        .max_by_key(|e| e.primary)
+        .map(|e| e.email)
+        .ok_or_else(|| no_verified_email("github"))?;
+    Ok(Profile {
+        provider: "github",
+        subject: info.id.to_string(),
+        name: info.name.unwrap_or(info.login),
+        email,
+    })
+}
+
+/// The local user for `profile`: the one already linked to it, else the one
+/// registered with the same email, else a new account using up `invite`.
+async fn find_or_create_user(
+    storage: &Storage,
+    invite_only: &InviteOnly,
+    invite: Option<&str>,
+    profile: Profile,
+) -> ApiResult<User> {
+    if let Some(user) = storage
+        .user_by_identity(profile.provider, &profile.subject)
+        .await
+        .map_err(internal_error)?
+    {
+        return Ok(user);
+    }
+
+    let email = normalize_email(&profile.email);
+    let user = match storage
+        .user_by_email(&email)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(user) => user,
+        None => {
+            invites::create_user(
+                storage,
+                invite_only,
+                invite,
+                &NewUser {
+                    email,
+                    name: profile.name.trim().chars().take(MAX_NAME_LEN).collect(),
+                    password_hash: String::new(),
+                },
+            )
+            .await?
+        }
+    };
+
+    match storage
+        .link_identity(profile.provider, &profile.subject, user.id)
+        .await
+    {
+        // A concurrent callback for the same account got there first.
+        Err(e) if !storage::is_unique_violation(&e) => Err(internal_error(e)),
+        _ => Ok(user),
+    }
+}
+
+async fn sign_in(
+    storage: &Storage,
+    invite_only: &InviteOnly,
+    cookies: &CookieJar<'_>,
+    redirect: &LoginRedirect,
+    profile: Profile,
+) -> ApiResult<Redirect> {
+    let invite = cookies
+        .get_private(INVITE_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    cookies.remove_private(Cookie::build(INVITE_COOKIE).path("/"));
+    let user = find_or_create_user(storage, invite_only, invite.as_deref(), profile).await?;
+    users::start_session(cookies, storage, &user);
+    Ok(Redirect::to(redirect.0.clone()))
+}
+
+fn authorize<K: 'static>(
+    oauth: OAuth2<K>,
+    cookies: &CookieJar<'_>,
+    invite: Option<&str>,
+    scopes: &[&str],
+) -> ApiResult<Redirect> {
+    if let Some(invite) = invite {
+        cookies.add_private(
+            Cookie::build((INVITE_COOKIE, invite.trim().to_string()))
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(Duration::minutes(INVITE_MINUTES)),
+        );
+    }
+    oauth.get_redirect(cookies, scopes).map_err(|e| {
+        error!("failed to build oauth redirect: {:?}", e);
+        error(Status::InternalServerError, "could not start sign-in")
+    })
+}
+
+/// Sends the browser to Google's consent screen. A first sign-in creates an
+/// account, using up `invite` if given.
+#[utoipa::path(
+    tag = "users",
+    params(("invite" = Option<String>, Query, description = "A token from `POST /admin/invites`; required with `invite_only`")),
+    responses((status = 303, description = "Redirect to Google"))
+)]
+#[get("/auth/google?<invite>")]
+fn google_login(
+    oauth: OAuth2<Google>,
+    cookies: &CookieJar<'_>,
+    invite: Option<&str>,
+) -> ApiResult<Redirect> {
+    authorize(oauth, cookies, invite, &["openid", "email", "profile"])
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 303, description = "Signed in; sets the session cookie and redirects to `oauth.redirect_to`"),
+        (status = 400, description = "Missing or mismatched OAuth state"),
+        (status = 409, description = "The email was registered meanwhile", body = ErrorResponse),
+        (status = 422, description = "No verified email on the account, or a missing, used or expired invite", body = ErrorResponse),
+        (status = 502, description = "The provider could not be reached", body = ErrorResponse),
+    )
+)]
+#[get("/auth/google/callback")]
+async fn google_callback(
+    token: TokenResponse<Google>,
+    storage: &Storage,
+    resilience: &State<Resilience>,
+    redirect: &State<LoginRedirect>,
+    invite_only: &State<InviteOnly>,
+    cookies: &CookieJar<'_>,
+) -> ApiResult<Redirect> {
+    let profile = google_profile(&resilience.client("google"), token.access_token()).await?;
+    sign_in(storage, invite_only, cookies, redirect, profile).await
+}
+
+/// Sends the browser to GitHub's authorization page. A first sign-in
+/// creates an account, using up `invite` if given.
+#[utoipa::path(
+    tag = "users",
+    params(("invite" = Option<String>, Query, description = "A token from `POST /admin/invites`; required with `invite_only`")),
+    responses((status = 303, description = "Redirect to GitHub"))
+)]
+#[get("/auth/github?<invite>")]
+fn github_login(
+    oauth: OAuth2<GitHub>,
+    cookies: &CookieJar<'_>,
+    invite: Option<&str>,
+) -> ApiResult<Redirect> {
+    authorize(oauth, cookies, invite, &["read:user", "user:email"])
+}
+
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 303, description = "Signed in; sets the session cookie and redirects to `oauth.redirect_to`"),
+        (status = 400, description = "Missing or mismatched OAuth state"),
+        (status = 409, description = "The email was registered meanwhile", body = ErrorResponse),
+        (status = 422, description = "No verified email on the account, or a missing, used or expired invite", body = ErrorResponse),
+        (status = 502, description = "The provider could not be reached", body = ErrorResponse),
+    )
+)]
+#[get("/auth/github/callback")]
+async fn github_callback(
+    token: TokenResponse<GitHub>,
+    storage: &Storage,
+    resilience: &State<Resilience>,
+    redirect: &State<LoginRedirect>,
+    invite_only: &State<InviteOnly>,
+    cookies: &CookieJar<'_>,
+) -> ApiResult<Redirect> {
+    let profile = github_profile(&resilience.client("github"), token.access_token()).await?;
+    sign_in(storage, invite_only, cookies, redirect, profile).await
+}
+
+/// Every OAuth route, configured or not; [`stage`] mounts only the
+/// configured providers' routes.
+#[cfg(test)]
+pub fn routes() -> Vec<Route> {
+    routes![google_login, google_callback, github_login, github_callback]
+}
+
+/// Attaches the `rocket_oauth2` fairing and mounts the login and callback
+/// routes of each provider with an `oauth.<name>` table. Unconfigured
+/// providers have no routes, so their URLs return 404.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("OAuth", |rocket| async {
+        let redirect = match rocket
+            .figment()
+            .extract_inner::<String>("oauth.redirect_to")
+        {
+            Ok(redirect) => redirect,
+            Err(e) if e.missing() => DEFAULT_REDIRECT.to_string(),
+            Err(e) => {
+                error!("invalid oauth.redirect_to: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let configured = |name: &str| rocket.figment().contains(&format!("oauth.{}", name));
+        let (google, github) = (configured("google"), configured("github"));
+
+        let mut rocket = rocket.manage(LoginRedirect(redirect));
+        if google {
+            let routes: Vec<Route> = routes![google_login, google_callback];
+            rocket = rocket
+                .attach(OAuth2::<Google>::fairing("google"))
+                .mount("/", routes);
+        }
+        if github {
+            let routes: Vec<Route> = routes![github_login, github_callback];
+            rocket = rocket
+                .attach(OAuth2::<GitHub>::fairing("github"))
+                .mount("/", routes);
+        }
+        if !google && !github {
+            info!("no oauth providers configured");
+        }
+        Ok(rocket)
+    })
+}
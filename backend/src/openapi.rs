@@ -0,0 +1,388 @@
+//! OpenAPI description of the HTTP API, generated from the `utoipa::path`
+//! annotation on each route and the `ToSchema` types it takes and returns.
+
+use rocket::response::content::RawHtml;
+use rocket::serde::json::Json;
+use rocket::Route;
+use utoipa::openapi::schema::{ObjectBuilder, Ref};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{OpenApi as Spec, RefOr};
+use utoipa::{Modify, OpenApi};
+
+use crate::cards::pdf::{CardFont, PaperSize};
+use crate::cards::qr::{QrFormat, QrModules};
+use crate::content_filter::Violation;
+use crate::envelope::{self, Meta};
+use crate::music::Mood;
+use crate::storage::{MessageSort, SortOrder};
+use crate::{
+    admin, audio, calendar, cards, chat, checkins, confessions, countdown, coupons, date_ideas,
+    dates, draft, email, experiments, export, games, gifts, graphql, health, hunt, import, invites,
+    jwt, letter, live_stats, memories, metrics, mood, music, notes, oauth, poetry, proposal, push,
+    quiz, quote_search, reactions, reservations, scheduler, sealed_notes, share, sms, stats,
+    stickers, surprise, themes, timezones, trash, uploads, users, valentine, vault, webhooks,
+    wishlist,
+};
+
+const SWAGGER_PAGE: &str = include_str!("../templates/swagger.html");
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Valentine 2026 API"),
+    modifiers(&SecuritySchemes, &Envelopes),
+    paths(
+        health::health,
+        health::live,
+        health::ready,
+        metrics::metrics,
+        valentine::random,
+        valentine::daily,
+        valentine::stream,
+        valentine::personalized,
+        cards::card,
+        cards::handwriting::handwritten,
+        themes::list,
+        valentine::submit,
+        valentine::submit_quote,
+        valentine::list_messages,
+        valentine::message_by_id,
+        trash::delete,
+        trash::list,
+        trash::restore,
+        email::send,
+        sms::send_sms,
+        sms::get,
+        sms::status_callback,
+        draft::draft,
+        notes::notes,
+        share::share,
+        share::view,
+        share::qr_code,
+        share::pdf_card,
+        reactions::react,
+        reactions::reactions,
+        stickers::list,
+        audio::synthesize,
+        audio::audio,
+        stats::stats,
+        stats::favorite,
+        stats::unfavorite,
+        quote_search::search,
+        live_stats::live,
+        experiments::quote,
+        experiments::event,
+        experiments::results,
+        gifts::suggest,
+        quiz::list,
+        quiz::questions,
+        quiz::answers,
+        games::memory_quiz,
+        games::memory_quiz_answers,
+        uploads::upload,
+        uploads::serve,
+        memories::create,
+        memories::timeline,
+        memories::random,
+        mood::log_mood,
+        mood::mood_insights,
+        vault::create,
+        vault::list,
+        vault::open,
+        surprise::create,
+        surprise::get,
+        surprise::unlock,
+        hunt::create,
+        hunt::get,
+        hunt::clue,
+        hunt::answer,
+        scheduler::create,
+        scheduler::get,
+        proposal::create,
+        proposal::get,
+        proposal::answer,
+        webhooks::create,
+        webhooks::list,
+        webhooks::get,
+        webhooks::delete,
+        wishlist::add,
+        wishlist::list,
+        wishlist::remove,
+        wishlist::reserve,
+        wishlist::release,
+        chat::history,
+        chat::create,
+        chat::read,
+        chat::socket,
+        sealed_notes::register_key,
+        sealed_notes::keys,
+        sealed_notes::revoke_key,
+        sealed_notes::create,
+        sealed_notes::list,
+        sealed_notes::get,
+        sealed_notes::delete,
+        push::public_key,
+        push::subscribe,
+        countdown::get,
+        dates::create,
+        dates::upcoming,
+        dates::get,
+        date_ideas::date_ideas,
+        reservations::create,
+        reservations::next,
+        reservations::get,
+        calendar::calendar,
+        calendar::feed,
+        dates::set_reminders,
+        dates::delete,
+        users::register,
+        users::login,
+        users::logout,
+        users::me,
+        users::create_couple,
+        users::join_couple,
+        users::my_couple,
+        timezones::set_timezone,
+        invites::check,
+        checkins::check_in,
+        checkins::streak,
+        confessions::create,
+        confessions::list,
+        confessions::reveal,
+        coupons::create,
+        coupons::list,
+        coupons::redeem,
+        export::export,
+        import::import,
+        jwt::token,
+        jwt::refresh,
+        oauth::google_login,
+        oauth::google_callback,
+        oauth::github_login,
+        oauth::github_callback,
+        letter::letter,
+        poetry::poem,
+        music::music,
+        admin::quotes::list,
+        admin::quotes::get,
+        admin::quotes::create,
+        admin::quotes::update,
+        admin::quotes::delete,
+        admin::quotes::import,
+        admin::encryption::rotate,
+        admin::moderation::list,
+        admin::moderation::approve,
+        admin::moderation::reject,
+        admin::experiments::list,
+        admin::experiments::get,
+        admin::experiments::create,
+        admin::experiments::delete,
+        admin::gifts::list,
+        admin::gifts::get,
+        admin::gifts::create,
+        admin::gifts::update,
+        admin::gifts::delete,
+        admin::providers::log,
+        admin::providers::clear,
+        admin::providers::breakers,
+        admin::audit::list,
+        admin::backup::now,
+        admin::backup::status,
+        admin::quote_sources::list,
+        admin::quote_sources::refresh,
+        admin::stickers::create,
+        admin::stickers::add,
+        admin::stickers::delete_pack,
+        admin::stickers::delete_sticker,
+        admin::tenants::list,
+        admin::tenants::create,
+        admin::tenants::update,
+        admin::invites::create,
+        admin::roles::list,
+        admin::roles::grant,
+        admin::roles::revoke,
+        admin::jobs::failed,
+        admin::jobs::retry,
+        admin::jobs::delete,
+        graphql::execute,
+        graphql::graphiql,
+        graphql::subscriptions,
+        spec,
+        docs,
+    ),
+    // Only referenced from query parameters, which are not collected
+    // automatically.
+    components(schemas(CardFont, MessageSort, Mood, PaperSize, SortOrder, QrFormat, QrModules, Violation, Meta))
+)]
+struct ApiDoc;
+
+/// Declares the `X-Api-Key` header, session cookie and bearer token that
+/// `security(("api_key" = []))`, `security(("session" = []))` and
+/// `security(("bearer" = []))` refer to.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, spec: &mut Spec) {
+        let components = spec.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+        );
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(users::SESSION_COOKIE))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Documents the `{"data", "meta"}` envelope the [`envelope::Envelope`]
+/// fairing puts around every JSON response except errors (which already
+/// have their own shape) and [`envelope::RAW_PATHS`].
+struct Envelopes;
+
+impl Modify for Envelopes {
+    fn modify(&self, spec: &mut Spec) {
+        let error_ref = Ref::from_schema_name("ErrorResponse").ref_location;
+        for (path, item) in spec.paths.paths.iter_mut() {
+            if envelope::is_raw_path(path) {
+                continue;
+            }
+            let operations = [
+                &mut item.get,
+                &mut item.put,
+                &mut item.post,
+                &mut item.delete,
+                &mut item.patch,
+            ];
+            for operation in operations.into_iter().flatten() {
+                for response in operation.responses.responses.values_mut() {
+                    let RefOr::T(response) = response else {
+                        continue;
+                    };
+                    let Some(content) = response.content.get_mut("application/json") else {
+                        continue;
+                    };
+                    content.schema = content.schema.take().map(|schema| match schema {
+                        RefOr::Ref(r) if r.ref_location == error_ref => RefOr::Ref(r),
+                        data => ObjectBuilder::new()
+                            .property("data", data)
+                            .property("meta", Ref::from_schema_name("Meta"))
+                            .required("data")
+                            .required("meta")
+                            .into(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[utoipa::path(tag = "docs", responses((status = 200, description = "This document")))]
+#[get("/api/openapi.json")]
+fn spec() -> Json<Spec> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI for [`spec`]; its assets load from a CDN.
+#[utoipa::path(tag = "docs", responses((status = 200, content_type = "text/html", body = String)))]
+#[get("/api/docs")]
+fn docs() -> RawHtml<&'static str> {
+    RawHtml(SWAGGER_PAGE)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![spec, docs]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Method;
+
+    #[test]
+    fn every_route_is_documented() {
+        let spec = ApiDoc::openapi();
+        let routes = [
+            health::routes(),
+            metrics::routes(),
+            valentine::routes(),
+            trash::routes(),
+            cards::routes(),
+            email::routes(),
+            sms::routes(),
+            draft::routes(),
+            notes::routes(),
+            share::routes(),
+            reactions::routes(),
+            stickers::routes(),
+            audio::routes(),
+            stats::routes(),
+            quote_search::routes(),
+            live_stats::routes(),
+            experiments::routes(),
+            gifts::routes(),
+            quiz::routes(),
+            games::routes(),
+            uploads::routes(),
+            memories::routes(),
+            mood::routes(),
+            vault::routes(),
+            surprise::routes(),
+            hunt::routes(),
+            scheduler::routes(),
+            proposal::routes(),
+            webhooks::routes(),
+            wishlist::routes(),
+            chat::routes(),
+            sealed_notes::routes(),
+            push::routes(),
+            countdown::routes(),
+            dates::routes(),
+            date_ideas::routes(),
+            reservations::routes(),
+            calendar::routes(),
+            users::routes(),
+            timezones::routes(),
+            invites::routes(),
+            checkins::routes(),
+            confessions::routes(),
+            coupons::routes(),
+            export::routes(),
+            import::routes(),
+            jwt::routes(),
+            oauth::routes(),
+            letter::routes(),
+            poetry::routes(),
+            music::routes(),
+            admin::routes(),
+            graphql::routes(),
+            routes(),
+        ];
+
+        for route in routes.iter().flatten() {
+            let path = route.uri.path().replace('<', "{").replace('>', "}");
+            let item = spec.paths.paths.get(&path);
+            let operation = item.and_then(|item| match route.method {
+                Method::Get => item.get.as_ref(),
+                Method::Post => item.post.as_ref(),
+                Method::Put => item.put.as_ref(),
+                Method::Delete => item.delete.as_ref(),
+                _ => None,
+            });
+            assert!(
+                operation.is_some(),
+                "{} {} has no OpenAPI entry",
+                route.method,
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use serde::Serialize;
+
+use crate::storage::{
+    AuditEntry, ChatMessage, Confession, FailedJob, Message, Quote, QuoteStat, SealedNote,
+};
+
+/// Page of results with the metadata clients need to render pagers.
+#[derive(Serialize, async_graphql::SimpleObject, utoipa::ToSchema)]
+#[graphql(concrete(name = "QuotePage", params(Quote)))]
+#[graphql(concrete(name = "MessagePage", params(Message)))]
+#[graphql(concrete(name = "QuoteStatPage", params(QuoteStat)))]
+#[graphql(concrete(name = "AuditEntryPage", params(AuditEntry)))]
+#[graphql(concrete(name = "FailedJobPage", params(FailedJob)))]
+#[graphql(concrete(name = "ConfessionPage", params(Confession)))]
+#[graphql(concrete(name = "ChatMessagePage", params(ChatMessage)))]
+#[graphql(concrete(name = "SealedNotePage", params(SealedNote)))]
+pub struct Page<T: async_graphql::OutputType> {
+    pub items: Vec<T>,
+    pub page: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+/// Clamps optional `page`/`per_page` query values into a valid
+/// `(page, per_page, offset)` triple.
+pub fn paginate(page: Option<i64>, per_page: Option<i64>) -> (i64, i64, i64) {
+    let page = page.unwrap_or(1).max(1);
+    let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    (page, per_page, (page - 1) * per_page)
+}
@@ -0,0 +1,152 @@
+//! The bundled line corpus, parsed once and indexed by rhyme. See
+//! `assets/poetry/corpus.txt` for the file format.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use super::phonetics;
+use super::Style;
+
+const SOURCE: &str = include_str!("../../assets/poetry/corpus.txt");
+
+#[derive(Debug)]
+pub struct Line {
+    /// The line as written, `{name}` placeholders included.
+    pub template: &'static str,
+    /// Syllables not counting the name.
+    pub syllables: u32,
+    /// How many times the line mentions the name.
+    pub names: u32,
+    pub end_word: String,
+    pub rhyme: String,
+}
+
+impl Line {
+    /// Syllables once `{name}` is replaced with a name of `name_syllables`.
+    pub fn syllables_with(&self, name_syllables: u32) -> u32 {
+        self.syllables + self.names * name_syllables
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Section {
+    pub lines: Vec<Line>,
+    /// Indices into `lines` by rhyme key. A `BTreeMap` so the same seed
+    /// walks the families in the same order.
+    pub rhymes: BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Corpus {
+    haiku: Section,
+    sonnet: Section,
+    limerick: Section,
+}
+
+impl Corpus {
+    pub fn section(&self, style: Style) -> &Section {
+        match style {
+            Style::Haiku => &self.haiku,
+            Style::Sonnet => &self.sonnet,
+            Style::Limerick => &self.limerick,
+        }
+    }
+}
+
+fn parse_line(template: &'static str) -> Result<Line, String> {
+    let end_word: String = template
+        .split_whitespace()
+        .last()
+        .unwrap_or_default()
+        .trim_matches(|c: char| !c.is_alphanumeric() && c != '{' && c != '}')
+        .to_lowercase();
+    if end_word.contains("{name}") {
+        return Err(format!("`{}` ends with `{{name}}`", template));
+    }
+
+    Ok(Line {
+        template,
+        syllables: phonetics::line_syllables(&template.replace("{name}", " ")),
+        names: template.matches("{name}").count() as u32,
+        rhyme: phonetics::rhyme_key(&end_word),
+        end_word,
+    })
+}
+
+fn parse(source: &'static str) -> Result<Corpus, String> {
+    let mut corpus = Corpus::default();
+    let mut section: Option<&mut Section> = None;
+
+    for (number, raw) in source.lines().enumerate() {
+        let text = raw.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = text.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let style: Style = name
+                .parse()
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+            section = Some(match style {
+                Style::Haiku => &mut corpus.haiku,
+                Style::Sonnet => &mut corpus.sonnet,
+                Style::Limerick => &mut corpus.limerick,
+            });
+            continue;
+        }
+
+        let Some(section) = section.as_deref_mut() else {
+            return Err(format!(
+                "line {}: text before the first [style]",
+                number + 1
+            ));
+        };
+        let line = parse_line(text).map_err(|e| format!("line {}: {}", number + 1, e))?;
+        section
+            .rhymes
+            .entry(line.rhyme.clone())
+            .or_default()
+            .push(section.lines.len());
+        section.lines.push(line);
+    }
+    Ok(corpus)
+}
+
+pub fn corpus() -> &'static Corpus {
+    static CORPUS: OnceLock<Corpus> = OnceLock::new();
+    CORPUS.get_or_init(|| parse(SOURCE).expect("bundled poetry corpus is valid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_lines_scan() {
+        let corpus = corpus();
+        for line in &corpus.section(Style::Haiku).lines {
+            assert!(
+                [5, 7].contains(&line.syllables_with(0)) || line.names > 0,
+                "{:?}",
+                line
+            );
+        }
+        for style in [Style::Sonnet, Style::Limerick] {
+            let section = corpus.section(style);
+            for line in section.lines.iter().filter(|l| l.names == 0) {
+                let fits = match style {
+                    Style::Sonnet => (10..=11).contains(&line.syllables),
+                    _ => (5..=6).contains(&line.syllables) || (8..=9).contains(&line.syllables),
+                };
+                assert!(fits, "{:?}", line);
+                assert!(
+                    section.rhymes[&line.rhyme].len() >= 3,
+                    "{:?} has no family",
+                    line
+                );
+            }
+        }
+
+        assert!(parse("[ode]\nHello there").is_err());
+        assert!(parse("[haiku]\nWaiting for {name}").is_err());
+    }
+}
@@ -0,0 +1,425 @@
+mod corpus;
+mod phonetics;
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rocket::http::Status;
+use rocket::Route;
+use serde::Serialize;
+
+use crate::determinism::RequestRng;
+use crate::error::{error, ApiResult, ErrorResponse};
+use crate::messages::{self, Vars};
+use crate::negotiate::Negotiated;
+
+use corpus::Section;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    /// Three unrhymed lines of 5, 7 and 5 syllables.
+    Haiku,
+    /// Fourteen lines of ten or eleven syllables, rhymed ABAB CDCD EFEF GG.
+    Sonnet,
+    /// Five lines rhymed AABBA, the B lines shorter than the A lines.
+    Limerick,
+}
+
+impl Style {
+    pub const ALL: [Style; 3] = [Style::Haiku, Style::Sonnet, Style::Limerick];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Style::Haiku => "haiku",
+            Style::Sonnet => "sonnet",
+            Style::Limerick => "limerick",
+        }
+    }
+
+    fn slots(self) -> Vec<Slot> {
+        match self {
+            Style::Haiku => vec![Slot::free(5), Slot::free(7), Slot::free(5)],
+            Style::Sonnet => "ABABCDCDEFEFGG"
+                .chars()
+                .map(|rhyme| Slot::rhymed(rhyme, 10, 11))
+                .collect(),
+            Style::Limerick => "AABBA"
+                .chars()
+                .map(|rhyme| match rhyme {
+                    'A' => Slot::rhymed(rhyme, 8, 9),
+                    _ => Slot::rhymed(rhyme, 5, 6),
+                })
+                .collect(),
+        }
+    }
+
+    /// Lines per stanza; stanzas are separated by a blank line in the text.
+    fn stanzas(self) -> &'static [usize] {
+        match self {
+            Style::Haiku => &[3],
+            Style::Sonnet => &[4, 4, 4, 2],
+            Style::Limerick => &[5],
+        }
+    }
+}
+
+impl fmt::Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Style::ALL
+            .into_iter()
+            .find(|t| t.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = Style::ALL.iter().map(|t| t.as_str()).collect();
+                format!(
+                    "unknown style `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+/// One line of a form: its syllable range and the rhyme group it shares an
+/// end sound with, if any.
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    rhyme: Option<char>,
+    min: u32,
+    max: u32,
+}
+
+impl Slot {
+    fn free(syllables: u32) -> Self {
+        Slot {
+            rhyme: None,
+            min: syllables,
+            max: syllables,
+        }
+    }
+
+    fn rhymed(rhyme: char, min: u32, max: u32) -> Self {
+        Slot {
+            rhyme: Some(rhyme),
+            min,
+            max,
+        }
+    }
+
+    fn fits(&self, syllables: u32) -> bool {
+        (self.min..=self.max).contains(&syllables)
+    }
+}
+
+/// Fills the slots of a form from one corpus section. Each rhyme group takes
+/// its lines from a single rhyme family no other group uses, with no end word
+/// repeated; a name line, if one is pinned, must sit in its slot.
+struct Search<'a> {
+    section: &'a Section,
+    slots: Vec<Slot>,
+    /// Slot indices per rhyme group, in the order the groups first appear.
+    groups: Vec<Vec<usize>>,
+    name_syllables: u32,
+    pinned: Option<(usize, usize)>,
+}
+
+impl<'a> Search<'a> {
+    fn new(section: &'a Section, style: Style, name_syllables: u32) -> Self {
+        let slots = style.slots();
+        let mut groups: Vec<(Option<char>, Vec<usize>)> = Vec::new();
+        for (i, slot) in slots.iter().enumerate() {
+            match groups
+                .iter_mut()
+                .find(|(rhyme, _)| rhyme.is_some() && *rhyme == slot.rhyme)
+            {
+                Some((_, members)) => members.push(i),
+                None => groups.push((slot.rhyme, vec![i])),
+            }
+        }
+
+        Search {
+            section,
+            slots,
+            groups: groups.into_iter().map(|(_, members)| members).collect(),
+            name_syllables,
+            pinned: None,
+        }
+    }
+
+    fn fits(&self, slot: usize, line: usize) -> bool {
+        let line = &self.section.lines[line];
+        self.slots[slot].fits(line.syllables_with(self.name_syllables))
+    }
+
+    /// Every (slot, line) a name line could be pinned to.
+    fn name_placements(&self) -> Vec<(usize, usize)> {
+        let mut placements = Vec::new();
+        for (line, text) in self.section.lines.iter().enumerate() {
+            if text.names == 0 {
+                continue;
+            }
+            for slot in 0..self.slots.len() {
+                if self.fits(slot, line) {
+                    placements.push((slot, line));
+                }
+            }
+        }
+        placements
+    }
+
+    fn run(&self, rng: &mut StdRng) -> Option<Vec<usize>> {
+        let mut chosen = vec![None; self.slots.len()];
+        let mut families = Vec::new();
+        if self.fill(rng, 0, &mut chosen, &mut families) {
+            chosen.into_iter().collect()
+        } else {
+            None
+        }
+    }
+
+    fn fill<'s>(
+        &'s self,
+        rng: &mut StdRng,
+        group: usize,
+        chosen: &mut Vec<Option<usize>>,
+        families: &mut Vec<&'s str>,
+    ) -> bool {
+        let Some(members) = self.groups.get(group) else {
+            return true;
+        };
+        let pinned = self
+            .pinned
+            .filter(|(slot, _)| members.contains(slot))
+            .map(|(_, line)| line);
+
+        // Unrhymed slots draw from every line; rhymed groups from one family.
+        let mut candidates: Vec<Option<&'s str>> = match self.slots[members[0]].rhyme {
+            None => vec![None],
+            Some(_) => match pinned {
+                Some(line) => vec![Some(self.section.lines[line].rhyme.as_str())],
+                None => self
+                    .section
+                    .rhymes
+                    .keys()
+                    .map(|k| Some(k.as_str()))
+                    .collect(),
+            },
+        };
+        candidates.shuffle(rng);
+
+        for family in candidates {
+            if family.is_some_and(|f| families.contains(&f)) {
+                continue;
+            }
+            let mut pool: Vec<usize> = match family {
+                Some(key) => self.section.rhymes[key].clone(),
+                None => (0..self.section.lines.len()).collect(),
+            };
+            pool.retain(|line| {
+                self.section.lines[*line].names == 0 && !chosen.contains(&Some(*line))
+            });
+            pool.shuffle(rng);
+
+            if !self.pick(members, pinned, &pool, chosen) {
+                for slot in members {
+                    chosen[*slot] = None;
+                }
+                continue;
+            }
+            families.extend(family);
+            if self.fill(rng, group + 1, chosen, families) {
+                return true;
+            }
+            families.retain(|f| Some(*f) != family);
+            for slot in members {
+                chosen[*slot] = None;
+            }
+        }
+        false
+    }
+
+    /// Assigns a line from `pool` to each slot in `members`, keeping end
+    /// words distinct within the group.
+    fn pick(
+        &self,
+        members: &[usize],
+        pinned: Option<usize>,
+        pool: &[usize],
+        chosen: &mut [Option<usize>],
+    ) -> bool {
+        let mut end_words: Vec<&str> = Vec::new();
+        if let Some((slot, line)) = self.pinned.filter(|(_, line)| Some(*line) == pinned) {
+            chosen[slot] = Some(line);
+            end_words.push(&self.section.lines[line].end_word);
+        }
+
+        for slot in members {
+            if chosen[*slot].is_some() {
+                continue;
+            }
+            let line = pool.iter().copied().find(|line| {
+                self.fits(*slot, *line)
+                    && !chosen.contains(&Some(*line))
+                    && !end_words.contains(&self.section.lines[*line].end_word.as_str())
+            });
+            let Some(line) = line else {
+                return false;
+            };
+            chosen[*slot] = Some(line);
+            end_words.push(&self.section.lines[line].end_word);
+        }
+        true
+    }
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct Poem {
+    pub name: String,
+    pub style: Style,
+    /// Pass back as `?seed=` to get the same poem again.
+    pub seed: u64,
+    pub title: String,
+    pub lines: Vec<String>,
+    /// The lines with stanzas separated by blank lines, ready to display.
+    pub text: String,
+}
+
+/// Composes a poem in `style` for `name`, working the name into one line
+/// when the corpus has a line it fits the meter of. The same inputs and seed
+/// always give the same poem.
+pub fn compose(name: &str, style: Style, seed: u64) -> Poem {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let section = corpus::corpus().section(style);
+    let mut search = Search::new(section, style, phonetics::line_syllables(name));
+
+    let mut placements = search.name_placements();
+    placements.shuffle(&mut rng);
+    let mut chosen = None;
+    for placement in placements {
+        search.pinned = Some(placement);
+        chosen = search.run(&mut rng);
+        if chosen.is_some() {
+            break;
+        }
+    }
+    let chosen = chosen
+        .or_else(|| {
+            search.pinned = None;
+            search.run(&mut rng)
+        })
+        .expect("bundled poetry corpus can fill every style");
+
+    let vars = Vars::new().with("name", name);
+    let lines: Vec<String> = chosen
+        .iter()
+        .map(|line| messages::render(section.lines[*line].template, &vars))
+        .collect();
+    let mut stanzas = Vec::new();
+    let mut rest = lines.as_slice();
+    for size in style.stanzas() {
+        let (stanza, tail) = rest.split_at(*size);
+        stanzas.push(stanza.join("\n"));
+        rest = tail;
+    }
+
+    Poem {
+        name: name.to_string(),
+        style,
+        seed,
+        title: format!("A {} for {}", style, name),
+        text: stanzas.join("\n\n"),
+        lines,
+    }
+}
+
+#[utoipa::path(
+    tag = "poems",
+    params(
+        ("name" = Option<String>, Query),
+        ("style" = Option<Style>, Query),
+        ("seed" = Option<u64>, Query, description = "Reproduces an earlier poem"),
+    ),
+    responses(
+        (status = 200, body = Poem),
+        (status = 400, body = ErrorResponse),
+    )
+)]
+#[get("/api/poem?<name>&<style>&<seed>")]
+fn poem(
+    mut rng: RequestRng,
+    name: Option<&str>,
+    style: Option<&str>,
+    seed: Option<u64>,
+) -> ApiResult<Negotiated<Poem>> {
+    let name = name
+        .map(messages::sanitize_name)
+        .transpose()
+        .map_err(|e| error(Status::BadRequest, e))?
+        .unwrap_or_else(|| "My Love".to_string());
+    let style = style
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(Style::Haiku);
+    let seed = seed.unwrap_or_else(|| rng.gen());
+
+    Ok(Negotiated(compose(&name, style, seed)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![poem]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poems_keep_their_form() {
+        for style in Style::ALL {
+            for name in ["Sam", "Alex", "Jennifer", "Elizabeth"] {
+                for seed in 0..25 {
+                    let poem = compose(name, style, seed);
+                    assert_eq!(poem.text, compose(name, style, seed).text);
+
+                    let slots = style.slots();
+                    assert_eq!(poem.lines.len(), slots.len());
+                    let ends: Vec<&str> = poem
+                        .lines
+                        .iter()
+                        .map(|line| line.split_whitespace().last().unwrap())
+                        .collect();
+                    for (i, (line, slot)) in poem.lines.iter().zip(&slots).enumerate() {
+                        assert!(
+                            slot.fits(phonetics::line_syllables(line)),
+                            "{} seed {}: {}",
+                            style,
+                            seed,
+                            line
+                        );
+                        for (j, other) in slots.iter().enumerate().skip(i + 1) {
+                            let rhymes =
+                                phonetics::rhyme_key(ends[i]) == phonetics::rhyme_key(ends[j]);
+                            if slot.rhyme.is_some() {
+                                assert_eq!(rhymes, slot.rhyme == other.rhyme, "{}", poem.text);
+                            }
+                        }
+                    }
+                    assert!(poem.lines.iter().filter(|l| l.contains(name)).count() <= 1);
+                }
+            }
+        }
+        assert!(compose("Alex", Style::Limerick, 7).text.contains("Alex"));
+    }
+}
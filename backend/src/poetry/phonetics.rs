@@ -0,0 +1,264 @@
+//! Spelling-based estimates of how English words sound: syllable counts and
+//! rhyme keys. Both are heuristics tuned for the bundled corpus and common
+//! names; words they get wrong go in the exception tables.
+
+/// Words whose syllables the vowel-group rules miscount.
+const SYLLABLE_EXCEPTIONS: &[(&str, u32)] = &[
+    ("being", 2),
+    ("chloe", 2),
+    ("diamond", 3),
+    ("evening", 2),
+    ("every", 2),
+    ("everything", 3),
+    ("fire", 1),
+    ("flower", 2),
+    ("flowers", 2),
+    ("hour", 1),
+    ("hours", 1),
+    ("lion", 2),
+    ("noel", 2),
+    ("our", 1),
+    ("poem", 2),
+    ("poems", 2),
+    ("poet", 2),
+    ("quiet", 2),
+    ("science", 2),
+    ("chloe's", 2),
+    ("zoe", 2),
+];
+
+/// Words whose rhyme sound their spelling hides, mapped to the key of words
+/// they rhyme with.
+const RHYME_EXCEPTIONS: &[(&str, &str)] = &[
+    ("done", "un"),
+    ("great", "ate"),
+    ("heart", "art"),
+    ("higher", "ire"),
+    ("none", "un"),
+    ("of", "ove"),
+    ("one", "un"),
+    ("sign", "ine"),
+    ("some", "um"),
+    ("through", "oo"),
+    ("two", "oo"),
+    ("won", "un"),
+];
+
+/// Different spellings of the same rhyme sound, folded onto one key.
+const RHYME_SPELLINGS: &[(&str, &str)] = &[
+    ("ait", "ate"),
+    ("ea", "e"),
+    ("eat", "eet"),
+    ("ee", "e"),
+    ("eight", "ate"),
+    ("ete", "eet"),
+    ("ew", "oo"),
+    ("eye", "y"),
+    ("eyes", "ise"),
+    ("ie", "y"),
+    ("ies", "ise"),
+    ("ize", "ise"),
+    ("oal", "ole"),
+    ("ol", "ole"),
+    ("ou", "oo"),
+    ("oul", "ole"),
+    ("ue", "oo"),
+    ("ye", "y"),
+    ("ys", "ise"),
+];
+
+/// Unstressed endings; a word of two or more syllables ending in one rhymes
+/// from the vowel before it (`never` with `forever`, not with `higher`). An
+/// ending only counts when it starts the last vowel group, so the `y` of
+/// "away" is not weak.
+const WEAK_ENDINGS: &[&str] = &["er", "ers", "ing", "y", "le", "en", "ed", "es"];
+
+fn letters(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphabetic() || *c == '\'')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_vowel(chars: &[char], i: usize) -> bool {
+    match chars[i] {
+        'a' | 'e' | 'i' | 'o' | 'u' => true,
+        // `y` starts "you" and "yes" as a consonant, but is a vowel in
+        // "sky" and "rhythm".
+        'y' => i > 0,
+        _ => false,
+    }
+}
+
+/// Start and end (exclusive) of each run of vowels in `chars`.
+fn vowel_groups(chars: &[char]) -> Vec<(usize, usize)> {
+    let mut groups = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if is_vowel(chars, i) {
+            let start = i;
+            while i < chars.len() && is_vowel(chars, i) {
+                i += 1;
+            }
+            groups.push((start, i));
+        } else {
+            i += 1;
+        }
+    }
+    groups
+}
+
+/// Whether the final `e` (or `es`/`ed`) of `chars` adds no syllable, as in
+/// "love", "times" and "sighed" but not "little", "candles", "roses" or
+/// "wanted".
+fn silent_ending(chars: &[char]) -> bool {
+    let n = chars.len();
+    let ends = |s: &str| {
+        chars
+            .iter()
+            .rev()
+            .zip(s.chars().rev())
+            .all(|(a, b)| *a == b)
+            && n >= s.len()
+    };
+    let consonant_at = |i: usize| !is_vowel(chars, i);
+
+    if (ends("le") && n >= 3 && consonant_at(n - 3))
+        || (ends("les") && n >= 4 && consonant_at(n - 4))
+    {
+        return false;
+    }
+    if ends("e") && n >= 2 && consonant_at(n - 2) {
+        return true;
+    }
+    if ends("es") && n >= 3 && consonant_at(n - 3) {
+        return !matches!(chars[n - 3], 's' | 'x' | 'z' | 'c' | 'g' | 'h');
+    }
+    if ends("ed") && n >= 3 && consonant_at(n - 3) {
+        return !matches!(chars[n - 3], 't' | 'd');
+    }
+    false
+}
+
+/// Syllables in one word, at least 1 for anything with a letter in it.
+pub fn syllables(word: &str) -> u32 {
+    let word = letters(word);
+    if word.is_empty() {
+        return 0;
+    }
+    if let Some((_, n)) = SYLLABLE_EXCEPTIONS.iter().find(|(w, _)| *w == word) {
+        return *n;
+    }
+    let word = word.trim_end_matches("'s").replace('\'', "");
+    let chars: Vec<char> = word.chars().collect();
+    let mut count = vowel_groups(&chars).len() as u32;
+    if count > 1 && silent_ending(&chars) {
+        count -= 1;
+    }
+    count.max(1)
+}
+
+/// Syllables in a line of text, ignoring punctuation.
+pub fn line_syllables(text: &str) -> u32 {
+    text.split(|c: char| c.is_whitespace() || c == '-' || c == '—')
+        .map(syllables)
+        .sum()
+}
+
+/// A key shared by words that rhyme: the spelling from the last stressed
+/// vowel on, with equivalent spellings folded together, so "night" and
+/// "light", "eyes" and "rise", or "true" and "you" get the same key.
+pub fn rhyme_key(word: &str) -> String {
+    let word = letters(word).replace('\'', "");
+    if let Some((_, key)) = RHYME_EXCEPTIONS.iter().find(|(w, _)| *w == word) {
+        return key.to_string();
+    }
+    let chars: Vec<char> = word.chars().collect();
+    let groups = vowel_groups(&chars);
+    let Some(&(mut start, _)) = groups.last() else {
+        return word;
+    };
+
+    let silent = groups.len() > 1 && silent_ending(&chars);
+    if silent {
+        // "shine" rhymes from the `i`, not the silent `e`.
+        start = groups[groups.len() - 2].0;
+    } else if groups.len() > 1
+        && syllables(&word) >= 2
+        && WEAK_ENDINGS
+            .iter()
+            .any(|ending| word.ends_with(ending) && start + ending.len() >= chars.len())
+    {
+        start = groups[groups.len() - 2].0;
+    }
+
+    let tail: String = chars[start..].iter().collect();
+    for (spelling, key) in RHYME_SPELLINGS {
+        if tail == *spelling {
+            return key.to_string();
+        }
+    }
+    if let Some(rest) = tail.strip_prefix("igh") {
+        // `igh` sounds like the `i` in "bite" ("night"), or in "sky" ("high").
+        return if rest.is_empty() {
+            "y".to_string()
+        } else {
+            format!("i{}e", rest)
+        };
+    }
+    if let Some(rest) = tail.strip_suffix('e').filter(|_| silent) {
+        return format!("{}e", rest);
+    }
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syllables_and_rhymes_follow_pronunciation() {
+        let counts = [
+            ("love", 1),
+            ("little", 2),
+            ("roses", 2),
+            ("times", 1),
+            ("loved", 1),
+            ("wanted", 2),
+            ("free", 1),
+            ("the", 1),
+            ("eyes", 1),
+            ("you", 1),
+            ("beautiful", 3),
+            ("desire", 2),
+            ("Alex", 2),
+            ("Elizabeth", 4),
+        ];
+        for (word, expected) in counts {
+            assert_eq!(syllables(word), expected, "{}", word);
+        }
+        assert_eq!(
+            line_syllables("Shall I compare thee to a summer's day?"),
+            10
+        );
+
+        let rhymes = [
+            ("night", "light"),
+            ("heart", "apart"),
+            ("eyes", "rise"),
+            ("true", "you"),
+            ("love", "above"),
+            ("shine", "mine"),
+            ("never", "forever"),
+            ("late", "great"),
+            ("sun", "one"),
+            ("away", "today"),
+        ];
+        for (a, b) in rhymes {
+            assert_eq!(rhyme_key(a), rhyme_key(b), "{} / {}", a, b);
+        }
+        for (a, b) in [("never", "higher"), ("love", "live"), ("day", "night")] {
+            assert_ne!(rhyme_key(a), rhyme_key(b), "{} / {}", a, b);
+        }
+    }
+}
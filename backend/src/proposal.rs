@@ -0,0 +1,276 @@
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{self, Json};
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::events::{self, DomainEvent, EventBus, Subscriber};
+use crate::http;
+use crate::jobs::{Job, Jobs};
+use crate::negotiate::Negotiated;
+use crate::push::Notification;
+use crate::storage::{Answer, NewProposal, Proposal, Storage};
+use crate::tokens;
+use crate::users::CoupleScope;
+use crate::valentine::MAX_NAME_LEN;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_QUESTION_LEN: usize = 200;
+const TOKEN_LEN: usize = 16;
+
+#[derive(Deserialize, async_graphql::InputObject, utoipa::ToSchema)]
+#[graphql(name = "ProposalInput")]
+pub struct ProposalRequest {
+    pub question: Option<String>,
+    pub from: String,
+    pub to: Option<String>,
+    /// Receives a JSON POST once the proposal is answered.
+    pub callback_url: Option<String>,
+}
+
+impl Validate for ProposalRequest {
+    type Valid = NewProposal;
+
+    fn validate(self) -> Result<NewProposal, FieldErrors> {
+        let question = self
+            .question
+            .map(|q| q.trim().to_string())
+            .filter(|q| !q.is_empty())
+            .unwrap_or_else(|| "Will you be my valentine?".to_string());
+        let sender = self.from.trim().to_string();
+        let recipient = self
+            .to
+            .map(|to| to.trim().to_string())
+            .filter(|to| !to.is_empty());
+        let callback_url = self
+            .callback_url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+
+        let mut errors = FieldErrors::new();
+        errors.text("question", &question, MAX_QUESTION_LEN);
+        errors.text("from", &sender, MAX_NAME_LEN);
+        if let Some(recipient) = &recipient {
+            errors.text("to", recipient, MAX_NAME_LEN);
+        }
+        if let Some(url) = &callback_url {
+            if let Err(e) = http::parse_callback_url(url) {
+                errors.add("callback_url", format!("`callback_url` {}", e));
+            }
+        }
+
+        errors.finish(NewProposal {
+            token: tokens::random_token(TOKEN_LEN),
+            question,
+            sender,
+            recipient,
+            callback_url,
+            couple_id: None,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct AnswerRequest {
+    answer: Answer,
+}
+
+/// Body POSTed to a proposal's `callback_url` once it is answered.
+#[derive(Serialize)]
+struct AnsweredCallback<'a> {
+    token: &'a str,
+    question: &'a str,
+    answer: Answer,
+    answered_at: Option<DateTime<Utc>>,
+}
+
+/// Stores a validated proposal once its callback's host resolves to
+/// public addresses only. Shared by the REST, GraphQL and gRPC APIs.
+pub async fn create_proposal(
+    storage: &Storage,
+    scope: CoupleScope,
+    mut proposal: NewProposal,
+) -> ApiResult<Proposal> {
+    if let Some(url) = &proposal.callback_url {
+        if let Err(e) = http::resolve_callback_url(url).await {
+            let mut errors = FieldErrors::new();
+            errors.add("callback_url", format!("`callback_url` {}", e));
+            return Err(errors.into());
+        }
+    }
+    proposal.couple_id = scope.0;
+
+    storage
+        .create_proposal(&proposal)
+        .await
+        .map_err(internal_error)
+}
+
+#[utoipa::path(
+    tag = "proposals",
+    request_body = ProposalRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = Proposal),
+        (status = 401, body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/proposal", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    request: Valid<ProposalRequest>,
+) -> ApiResult<status::Created<Negotiated<Proposal>>> {
+    let proposal = create_proposal(storage, scope, request.into_inner()).await?;
+
+    let location = uri!(get(&proposal.token)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(proposal)))
+}
+
+#[utoipa::path(
+    tag = "proposals",
+    responses(
+        (status = 200, body = Proposal),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/proposal/<token>")]
+async fn get(storage: &Storage, token: &str) -> ApiResult<Negotiated<Proposal>> {
+    storage
+        .get_proposal(token)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, "no such proposal"))
+}
+
+/// Records the answer once, then publishes it for webhooks, subscribed
+/// browsers, live stats and the proposal's `callback_url`, which is called
+/// from a job so it is retried if it fails. Shared by the REST, GraphQL and
+/// gRPC APIs.
+pub async fn answer_proposal(
+    storage: &Storage,
+    events: &EventBus,
+    token: &str,
+    answer: Answer,
+) -> ApiResult<Proposal> {
+    let answered = storage
+        .answer_proposal(token, answer)
+        .await
+        .map_err(internal_error)?;
+
+    let Some(proposal) = answered else {
+        return match storage.get_proposal(token).await.map_err(internal_error)? {
+            Some(_) => Err(error(
+                Status::Conflict,
+                "proposal has already been answered",
+            )),
+            None => Err(error(Status::NotFound, "no such proposal")),
+        };
+    };
+
+    events.publish(
+        storage,
+        DomainEvent::ProposalAnswered {
+            proposal: proposal.clone(),
+        },
+    );
+    Ok(proposal)
+}
+
+/// What the couple's browsers show once `proposal` is answered.
+pub fn notification(proposal: &Proposal) -> Notification {
+    Notification {
+        title: format!(
+            "{} said {}",
+            proposal.recipient.as_deref().unwrap_or("Your valentine"),
+            match proposal.answer {
+                Some(Answer::Yes) => "yes!",
+                _ => "no",
+            }
+        ),
+        body: proposal.question.clone(),
+        url: Some(uri!(get(&proposal.token)).to_string()),
+    }
+}
+
+/// Queues the [`AnsweredCallback`] of each answered proposal that asked
+/// for one.
+struct Callbacks(Jobs);
+
+#[rocket::async_trait]
+impl Subscriber for Callbacks {
+    fn name(&self) -> &'static str {
+        "proposal callbacks"
+    }
+
+    async fn handle(&self, storage: &Storage, event: &DomainEvent) {
+        let DomainEvent::ProposalAnswered { proposal } = event else {
+            return;
+        };
+        let (Some(url), Some(answer)) = (&proposal.callback_url, proposal.answer) else {
+            return;
+        };
+        let body = AnsweredCallback {
+            token: &proposal.token,
+            question: &proposal.question,
+            answer,
+            answered_at: proposal.answered_at,
+        };
+        let job = Job::Callback {
+            url: url.clone(),
+            body: json::to_value(&body).expect("callbacks always serialize"),
+        };
+        if let Err(e) = self.0.enqueue(storage, &job).await {
+            error!("failed to queue proposal callback to {}: {}", url, e);
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "proposals",
+    request_body = AnswerRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = Proposal),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, description = "Already answered", body = ErrorResponse),
+    )
+)]
+#[post("/api/proposal/<token>/answer", data = "<request>")]
+async fn answer(
+    _key: ApiKey,
+    storage: &Storage,
+    events: &State<EventBus>,
+    token: &str,
+    request: Json<AnswerRequest>,
+) -> ApiResult<Negotiated<Proposal>> {
+    answer_proposal(storage, events, token, request.answer)
+        .await
+        .map(Negotiated)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, get, answer]
+}
+
+/// Subscribes the proposal callbacks. Attach it after
+/// [`crate::jobs::stage`] and [`crate::events::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Proposal Callbacks", |rocket| async {
+        match rocket.state::<Jobs>().cloned() {
+            Some(jobs) => rocket.attach(events::subscriber(Callbacks(jobs))),
+            None => {
+                error!("proposal callbacks not started: jobs are unavailable");
+                rocket
+            }
+        }
+    })
+}
@@ -0,0 +1,335 @@
+//! Offline mode for the external integrations. With `providers.mode =
+//! "mock"`, email, SMS, text-to-speech, music, weather, Web Push and
+//! drafting go through [`MockProvider`] instead of the network: nothing leaves the
+//! server, and each outgoing payload is recorded in a [`MockLog`] that
+//! `GET /admin/providers/log` shows.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::{self, Value};
+use rocket::{Build, Rocket};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::tts::TtsProvider;
+use crate::date_ideas::{Forecast, WeatherProvider};
+use crate::draft::llm::{estimate_tokens, Completion, LlmProvider, Prompt};
+use crate::music::{fallback_tracks, Mood, MusicProvider, Source, Track};
+use crate::sms::provider::{parse_status, Queued, SmsProvider, StatusUpdate};
+
+/// Oldest entries are dropped once the log holds this many.
+const MAX_ENTRIES: usize = 200;
+
+/// One MPEG-1 layer III frame of silence (128 kbit/s, 44.1 kHz), which
+/// players accept as a valid MP3.
+const SILENT_FRAME_LEN: usize = 417;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Talk to the configured services.
+    #[default]
+    Live,
+    /// Record instead of sending; no credentials needed.
+    Mock,
+}
+
+/// The `[default.providers]` table in Rocket.toml.
+#[derive(Debug, Default, Deserialize)]
+struct ProvidersConfig {
+    #[serde(default)]
+    mode: Mode,
+}
+
+/// A payload a mock provider would have sent.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Recorded {
+    pub id: u64,
+    /// `email`, `sms`, `tts`, `music`, `weather`, `push` or `llm`.
+    pub provider: &'static str,
+    #[schema(value_type = Object)]
+    pub payload: Value,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Entries {
+    next_id: u64,
+    entries: VecDeque<Recorded>,
+}
+
+/// In-memory record of everything the mock providers were asked to send,
+/// newest last.
+#[derive(Clone, Default)]
+pub struct MockLog(Arc<Mutex<Entries>>);
+
+impl MockLog {
+    /// Records `payload` for `provider` and returns its id.
+    pub fn record(&self, provider: &'static str, payload: &impl Serialize) -> u64 {
+        let payload = json::to_value(payload).unwrap_or_else(|e| Value::String(e.to_string()));
+        let mut log = self.0.lock().expect("mock log lock poisoned");
+        log.next_id += 1;
+        let id = log.next_id;
+        if log.entries.len() == MAX_ENTRIES {
+            log.entries.pop_front();
+        }
+        log.entries.push_back(Recorded {
+            id,
+            provider,
+            payload,
+            recorded_at: Utc::now(),
+        });
+        info!("mock {} provider recorded payload {}", provider, id);
+        id
+    }
+
+    /// Recorded payloads, oldest first, optionally for one provider only.
+    pub fn entries(&self, provider: Option<&str>) -> Vec<Recorded> {
+        let log = self.0.lock().expect("mock log lock poisoned");
+        log.entries
+            .iter()
+            .filter(|entry| provider.is_none_or(|p| entry.provider.eq_ignore_ascii_case(p)))
+            .cloned()
+            .collect()
+    }
+
+    /// Empties the log and returns how many entries it held.
+    pub fn clear(&self) -> usize {
+        let mut log = self.0.lock().expect("mock log lock poisoned");
+        let cleared = log.entries.len();
+        log.entries.clear();
+        cleared
+    }
+}
+
+/// Managed state: the log, when running in mock mode.
+pub struct Providers {
+    pub log: Option<MockLog>,
+}
+
+/// The mock log if `rocket` runs in mock mode. For the provider stages,
+/// which must be attached after [`stage`].
+pub fn mock_log(rocket: &Rocket<Build>) -> Option<MockLog> {
+    rocket.state::<Providers>().and_then(|p| p.log.clone())
+}
+
+/// Stands in for every external service, recording what it is given.
+pub struct MockProvider {
+    log: MockLog,
+}
+
+impl MockProvider {
+    pub fn new(log: MockLog) -> Self {
+        MockProvider { log }
+    }
+}
+
+#[derive(Serialize)]
+struct SmsPayload<'a> {
+    to: &'a str,
+    body: &'a str,
+    status_callback: Option<&'a str>,
+}
+
+#[rocket::async_trait]
+impl SmsProvider for MockProvider {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn send(
+        &self,
+        to: &str,
+        body: &str,
+        status_callback: Option<&str>,
+    ) -> Result<Queued, String> {
+        let id = self.log.record(
+            "sms",
+            &SmsPayload {
+                to,
+                body,
+                status_callback,
+            },
+        );
+        Ok(Queued {
+            provider_id: format!("mock-{}", id),
+            status: "queued".to_string(),
+        })
+    }
+
+    /// Unsigned, so delivery can be simulated by posting the callback by
+    /// hand.
+    fn status_update(
+        &self,
+        _url: &str,
+        params: &HashMap<String, String>,
+        _signature: Option<&str>,
+    ) -> Result<StatusUpdate, String> {
+        parse_status(params)
+    }
+}
+
+#[derive(Serialize)]
+struct SpeechPayload<'a> {
+    text: &'a str,
+}
+
+#[rocket::async_trait]
+impl TtsProvider for MockProvider {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn synthesize(&self, text: &str) -> Result<Vec<u8>, String> {
+        self.log.record("tts", &SpeechPayload { text });
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x64];
+        frame.resize(SILENT_FRAME_LEN, 0);
+        Ok(frame)
+    }
+}
+
+#[derive(Serialize)]
+struct SearchPayload {
+    mood: Mood,
+}
+
+#[rocket::async_trait]
+impl MusicProvider for MockProvider {
+    fn source(&self) -> Source {
+        Source::Mock
+    }
+
+    async fn playlist(&self, mood: Mood) -> Result<Vec<Track>, String> {
+        self.log.record("music", &SearchPayload { mood });
+        Ok(fallback_tracks(mood))
+    }
+}
+
+#[derive(Serialize)]
+struct ForecastPayload {
+    lat: f64,
+    lon: f64,
+    date: NaiveDate,
+}
+
+#[rocket::async_trait]
+impl WeatherProvider for MockProvider {
+    /// Always a mild, clear day.
+    async fn forecast(
+        &self,
+        lat: f64,
+        lon: f64,
+        date: NaiveDate,
+    ) -> Result<Option<Forecast>, String> {
+        self.log
+            .record("weather", &ForecastPayload { lat, lon, date });
+        Ok(Some(Forecast {
+            date,
+            weather_code: 0,
+            summary: "clear sky",
+            temperature_max_c: Some(16.0),
+            temperature_min_c: Some(8.0),
+            precipitation_probability: Some(0),
+        }))
+    }
+}
+
+#[derive(Serialize)]
+struct CompletionPayload<'a> {
+    prompt: &'a Prompt,
+    max_tokens: u32,
+}
+
+#[rocket::async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    /// Lists the prompt's points, the same every time.
+    async fn complete(&self, prompt: &Prompt, max_tokens: u32) -> Result<Completion, String> {
+        self.log
+            .record("llm", &CompletionPayload { prompt, max_tokens });
+        let points: Vec<&str> = prompt
+            .user
+            .lines()
+            .filter_map(|line| line.strip_prefix("- "))
+            .collect();
+        let text = format!(
+            "A mock draft, remembering that {}. Happy Valentine's Day!",
+            points.join("; that ")
+        );
+        Ok(Completion {
+            prompt_tokens: estimate_tokens(&prompt.system) + estimate_tokens(&prompt.user),
+            completion_tokens: estimate_tokens(&text),
+            text,
+        })
+    }
+}
+
+/// Manages [`Providers`] from the optional `providers` table. Must be
+/// attached before the stages of the providers it replaces.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Providers", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<ProvidersConfig>("providers")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => ProvidersConfig::default(),
+            Err(e) => {
+                error!("invalid providers config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let log = match config.mode {
+            Mode::Live => None,
+            Mode::Mock => {
+                warn!("providers.mode is mock: outgoing email, sms, push and drafts are only recorded");
+                Some(MockLog::default())
+            }
+        };
+        Ok(rocket.manage(Providers { log }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn mock_providers_record_what_they_would_send() {
+        let log = MockLog::default();
+        let mock = MockProvider::new(log.clone());
+
+        let queued = SmsProvider::send(&mock, "+15551234567", "Be mine", None)
+            .await
+            .unwrap();
+        assert_eq!(queued.provider_id, "mock-1");
+        mock.synthesize("Be mine").await.unwrap();
+
+        let texts = log.entries(Some("sms"));
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].payload["to"], "+15551234567");
+        assert_eq!(log.entries(None).len(), 2);
+
+        let params = HashMap::from([
+            ("MessageSid".to_string(), queued.provider_id),
+            ("MessageStatus".to_string(), "delivered".to_string()),
+        ]);
+        let update = mock.status_update("", &params, None).unwrap();
+        assert_eq!(update.status, "delivered");
+
+        for _ in 0..MAX_ENTRIES {
+            log.record("email", &"hello");
+        }
+        let entries = log.entries(None);
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].id, 3);
+        assert_eq!(log.clear(), MAX_ENTRIES);
+        assert!(log.entries(None).is_empty());
+    }
+}
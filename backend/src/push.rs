@@ -0,0 +1,568 @@
+//! Web Push (RFC 8030) to subscribed browsers: payloads are encrypted to
+//! each subscription's keys (RFC 8291) and requests are signed with the
+//! server's VAPID key (RFC 8292). Subscriptions the push service reports
+//! gone are deleted.
+
+use std::sync::Arc;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use hkdf::Hkdf;
+use p256::ecdh::diffie_hellman;
+use p256::ecdsa::signature::Signer;
+use p256::ecdsa::{Signature, SigningKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use reqwest::StatusCode;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::{self};
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::auth::ApiKey;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::events::{self, DomainEvent, Subscriber};
+use crate::http::{self, CallbackClient};
+use crate::jobs::{Failure, Job, Jobs};
+use crate::negotiate::Negotiated;
+use crate::proposal;
+use crate::providers::{self, MockLog};
+use crate::storage::{NewPushSubscription, PushSubscription, Storage};
+use crate::users::CoupleScope;
+use crate::valentine;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_ENDPOINT_LEN: usize = 2048;
+const AUTH_SECRET_LEN: usize = 16;
+
+/// Record size advertised in the payload header. Everything fits in one
+/// record, so this also caps the plaintext.
+const RECORD_SIZE: u32 = 4096;
+/// Tag, padding delimiter and header of the single record.
+const RECORD_OVERHEAD: usize = 16 + 1 + 86;
+
+/// How long a push service keeps trying to deliver a notification.
+const TTL_SECONDS: u32 = 24 * 60 * 60;
+/// How long a VAPID token is valid; push services allow at most 24 hours.
+const VAPID_LIFETIME: Duration = Duration::hours(12);
+
+#[derive(Deserialize)]
+pub struct PushConfig {
+    /// The VAPID private key: the 32-byte P-256 scalar, base64url encoded.
+    vapid_private_key: String,
+    /// `mailto:` or `https:` contact URL push services can reach you at.
+    subject: String,
+}
+
+/// The server's VAPID identity.
+struct Vapid {
+    key: SigningKey,
+    /// Uncompressed public key, base64url encoded: the `applicationServerKey`
+    /// browsers subscribe with.
+    public_key: String,
+    subject: String,
+}
+
+impl Vapid {
+    fn from_config(config: &PushConfig) -> Result<Self, String> {
+        let bytes =
+            decode(&config.vapid_private_key).ok_or("`vapid_private_key` is not base64url")?;
+        let key = SigningKey::from_slice(&bytes)
+            .map_err(|_| "`vapid_private_key` is not a P-256 private key")?;
+        if !["mailto:", "https:"]
+            .iter()
+            .any(|scheme| config.subject.starts_with(scheme))
+        {
+            return Err("`subject` must be a mailto: or https: URL".to_string());
+        }
+        Ok(Vapid::new(key, config.subject.clone()))
+    }
+
+    /// A throwaway identity for mock mode, where nothing is signed for real.
+    fn generated() -> Self {
+        Vapid::new(
+            SigningKey::random(&mut OsRng),
+            "mailto:mock@localhost".to_string(),
+        )
+    }
+
+    fn new(key: SigningKey, subject: String) -> Self {
+        let public_key = BASE64URL.encode(key.verifying_key().to_encoded_point(false).as_bytes());
+        Vapid {
+            key,
+            public_key,
+            subject,
+        }
+    }
+
+    /// `Authorization` header value for a request to `endpoint`: an ES256
+    /// JWT for the endpoint's origin plus the public key.
+    fn authorization(&self, endpoint: &reqwest::Url) -> String {
+        #[derive(Serialize)]
+        struct Claims<'a> {
+            aud: String,
+            exp: i64,
+            sub: &'a str,
+        }
+
+        let header = BASE64URL.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = json::to_string(&Claims {
+            aud: endpoint.origin().ascii_serialization(),
+            exp: (Utc::now() + VAPID_LIFETIME).timestamp(),
+            sub: &self.subject,
+        })
+        .expect("claims always serialize");
+        let signing_input = format!("{}.{}", header, BASE64URL.encode(claims));
+        let signature: Signature = self.key.sign(signing_input.as_bytes());
+        format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            BASE64URL.encode(signature.to_bytes()),
+            self.public_key
+        )
+    }
+}
+
+/// Base64url with or without padding, as browsers vary.
+fn decode(value: &str) -> Option<Vec<u8>> {
+    BASE64URL.decode(value.trim().trim_end_matches('=')).ok()
+}
+
+/// Encrypts `plaintext` to a subscription's `p256dh` key and `auth` secret
+/// as a single `aes128gcm` record, from the sender key `local` and `salt`.
+fn encrypt_with(
+    plaintext: &[u8],
+    receiver: &PublicKey,
+    auth: &[u8],
+    local: &SecretKey,
+    salt: &[u8; 16],
+) -> Result<Vec<u8>, String> {
+    if plaintext.len() > RECORD_SIZE as usize - RECORD_OVERHEAD {
+        return Err(format!("payload of {} bytes is too large", plaintext.len()));
+    }
+    let receiver_bytes = receiver.to_encoded_point(false);
+    let local_bytes = local.public_key().to_encoded_point(false);
+    let shared = diffie_hellman(local.to_nonzero_scalar(), receiver.as_affine());
+
+    let mut key_info = b"WebPush: info\0".to_vec();
+    key_info.extend_from_slice(receiver_bytes.as_bytes());
+    key_info.extend_from_slice(local_bytes.as_bytes());
+    let mut ikm = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(auth), shared.raw_secret_bytes())
+        .expand(&key_info, &mut ikm)
+        .map_err(|e| e.to_string())?;
+
+    let prk = Hkdf::<Sha256>::new(Some(salt), &ikm);
+    let (mut cek, mut nonce) = ([0u8; 16], [0u8; 12]);
+    prk.expand(b"Content-Encoding: aes128gcm\0", &mut cek)
+        .and_then(|_| prk.expand(b"Content-Encoding: nonce\0", &mut nonce))
+        .map_err(|e| e.to_string())?;
+
+    // 0x02 marks the last (and only) record; no further padding.
+    let mut record = plaintext.to_vec();
+    record.push(2);
+    let ciphertext = Aes128Gcm::new(&cek.into())
+        .encrypt(Nonce::from_slice(&nonce), record.as_slice())
+        .map_err(|_| "encryption failed".to_string())?;
+
+    let mut body = salt.to_vec();
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(local_bytes.len() as u8);
+    body.extend_from_slice(local_bytes.as_bytes());
+    body.extend_from_slice(&ciphertext);
+    Ok(body)
+}
+
+/// [`encrypt_with`] from a fresh sender key and salt, as each message needs.
+fn encrypt(plaintext: &[u8], subscription: &PushSubscription) -> Result<Vec<u8>, String> {
+    let receiver = decode(&subscription.p256dh)
+        .and_then(|bytes| PublicKey::from_sec1_bytes(&bytes).ok())
+        .ok_or("invalid p256dh key")?;
+    let auth = decode(&subscription.auth).ok_or("invalid auth secret")?;
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    encrypt_with(
+        plaintext,
+        &receiver,
+        &auth,
+        &SecretKey::random(&mut OsRng),
+        &salt,
+    )
+}
+
+/// What the service worker shows; `url` is where clicking it leads.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+struct Sender {
+    vapid: Vapid,
+    client: CallbackClient,
+    /// Set in mock mode: notifications are recorded here, not sent.
+    mock: Option<MockLog>,
+}
+
+#[derive(Serialize)]
+struct PushPayload<'a> {
+    endpoint: &'a str,
+    notification: &'a Notification,
+}
+
+enum SendError {
+    /// The push service answered 404 or 410: the subscription expired or
+    /// was revoked and should be dropped.
+    Gone,
+    Failed(String),
+}
+
+impl Sender {
+    async fn send(&self, subscription: &PushSubscription, payload: &[u8]) -> Result<(), SendError> {
+        // Checked again since the subscription was stored before this was.
+        let endpoint =
+            http::parse_callback_url(&subscription.endpoint).map_err(SendError::Failed)?;
+        let body = encrypt(payload, subscription).map_err(SendError::Failed)?;
+        let response = self
+            .client
+            .0
+            .post(endpoint.clone())
+            .header("Authorization", self.vapid.authorization(&endpoint))
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("TTL", TTL_SECONDS)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| SendError::Failed(e.to_string()))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::NOT_FOUND | StatusCode::GONE => Err(SendError::Gone),
+            status => Err(SendError::Failed(format!(
+                "push service answered {}",
+                status
+            ))),
+        }
+    }
+
+    /// Sends `notification` to one subscription, dropping it if the push
+    /// service says it is gone.
+    async fn deliver(
+        &self,
+        storage: &Storage,
+        subscription: &PushSubscription,
+        notification: &Notification,
+    ) -> Result<(), Failure> {
+        if let Some(log) = &self.mock {
+            log.record(
+                "push",
+                &PushPayload {
+                    endpoint: &subscription.endpoint,
+                    notification,
+                },
+            );
+            return Ok(());
+        }
+        let payload = json::to_string(notification).expect("notifications always serialize");
+
+        match self.send(subscription, payload.as_bytes()).await {
+            Ok(()) => Ok(()),
+            Err(SendError::Gone) => {
+                info!("removing expired push subscription {}", subscription.id);
+                storage
+                    .delete_push_subscription(subscription.id)
+                    .await
+                    .map_err(|e| Failure::Retry(e.to_string()))
+            }
+            Err(SendError::Failed(e)) => Err(Failure::Retry(e)),
+        }
+    }
+}
+
+/// Handle used to notify a couple's browsers. A no-op without a `push`
+/// config.
+#[derive(Clone)]
+pub struct Push {
+    sender: Option<Arc<Sender>>,
+    jobs: Jobs,
+}
+
+impl Push {
+    /// Queues a job sending `notification` to each subscription in `couple`,
+    /// so a slow push service never holds up the caller. Failures are
+    /// logged rather than returned.
+    pub async fn notify(&self, storage: &Storage, couple: Option<i64>, notification: Notification) {
+        if self.sender.is_none() {
+            return;
+        }
+        let subscriptions = match storage.push_subscriptions(couple).await {
+            Ok(subscriptions) => subscriptions,
+            Err(e) => return error!("failed to load push subscriptions: {}", e),
+        };
+        for subscription in subscriptions {
+            let job = Job::Push {
+                subscription_id: subscription.id,
+                notification: notification.clone(),
+            };
+            if let Err(e) = self.jobs.enqueue(storage, &job).await {
+                error!(
+                    "failed to queue push to subscription {}: {}",
+                    subscription.id, e
+                );
+            }
+        }
+    }
+
+    /// Runs a [`Job::Push`]. A subscription removed since is skipped.
+    pub async fn deliver(
+        &self,
+        storage: &Storage,
+        subscription_id: i64,
+        notification: &Notification,
+    ) -> Result<(), Failure> {
+        let sender = self
+            .sender
+            .as_deref()
+            .ok_or_else(|| Failure::GiveUp("push notifications are not configured".to_string()))?;
+        match storage.get_push_subscription(subscription_id).await {
+            Ok(Some(subscription)) => sender.deliver(storage, &subscription, notification).await,
+            Ok(None) => Ok(()),
+            Err(e) => Err(Failure::Retry(e.to_string())),
+        }
+    }
+}
+
+/// Tells the couple about new valentines and answered proposals.
+#[rocket::async_trait]
+impl Subscriber for Push {
+    fn name(&self) -> &'static str {
+        "push"
+    }
+
+    async fn handle(&self, storage: &Storage, event: &DomainEvent) {
+        let (couple, notification) = match event {
+            DomainEvent::MessageCreated { message } => {
+                (message.couple_id, valentine::notification(message))
+            }
+            DomainEvent::ProposalAnswered { proposal } => {
+                (proposal.couple_id, proposal::notification(proposal))
+            }
+        };
+        self.notify(storage, couple, notification).await;
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SubscriptionKeys {
+    p256dh: String,
+    auth: String,
+}
+
+/// The JSON of a browser `PushSubscription`.
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SubscribeRequest {
+    endpoint: String,
+    keys: SubscriptionKeys,
+}
+
+impl Validate for SubscribeRequest {
+    type Valid = NewPushSubscription;
+
+    fn validate(self) -> Result<NewPushSubscription, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let endpoint = self.endpoint.trim().to_string();
+        match http::parse_callback_url(&endpoint) {
+            Ok(url) if url.scheme() == "https" && endpoint.len() <= MAX_ENDPOINT_LEN => {}
+            Ok(_) => errors.add("endpoint", "`endpoint` must be an https URL"),
+            Err(e) => errors.add("endpoint", format!("`endpoint` {}", e)),
+        }
+        if decode(&self.keys.p256dh)
+            .and_then(|bytes| PublicKey::from_sec1_bytes(&bytes).ok())
+            .is_none()
+        {
+            errors.add(
+                "keys.p256dh",
+                "`keys.p256dh` must be a base64url P-256 public key",
+            );
+        }
+        if decode(&self.keys.auth).is_none_or(|bytes| bytes.len() != AUTH_SECRET_LEN) {
+            errors.add(
+                "keys.auth",
+                format!(
+                    "`keys.auth` must be {} base64url-encoded bytes",
+                    AUTH_SECRET_LEN
+                ),
+            );
+        }
+        errors.finish(NewPushSubscription {
+            endpoint,
+            p256dh: self.keys.p256dh.trim().to_string(),
+            auth: self.keys.auth.trim().to_string(),
+            couple_id: None,
+        })
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct PublicKeyResponse {
+    /// Pass as `applicationServerKey` to `pushManager.subscribe()`.
+    public_key: String,
+}
+
+fn configured(push: &Push) -> ApiResult<&Sender> {
+    push.sender.as_deref().ok_or_else(|| {
+        error(
+            Status::ServiceUnavailable,
+            "push notifications are not configured",
+        )
+    })
+}
+
+/// The server's VAPID public key, for subscribing in the browser.
+#[utoipa::path(
+    tag = "push",
+    responses(
+        (status = 200, body = PublicKeyResponse),
+        (status = 503, description = "No `push` config", body = ErrorResponse),
+    )
+)]
+#[get("/api/push/public-key")]
+fn public_key(push: &State<Push>) -> ApiResult<Negotiated<PublicKeyResponse>> {
+    Ok(Negotiated(PublicKeyResponse {
+        public_key: configured(push)?.vapid.public_key.clone(),
+    }))
+}
+
+/// Stores a browser subscription. It receives notifications about new
+/// messages, answered proposals and due reminders for the signed-in couple
+/// (or the shared data, for API-key clients). Subscribing the same endpoint
+/// again updates it.
+#[utoipa::path(
+    tag = "push",
+    request_body = SubscribeRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = PushSubscription),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid keys, or an endpoint that is not public https", body = ErrorResponse),
+        (status = 503, description = "No `push` config", body = ErrorResponse),
+    )
+)]
+#[post("/api/push/subscribe", data = "<request>")]
+async fn subscribe(
+    _key: ApiKey,
+    storage: &Storage,
+    push: &State<Push>,
+    scope: CoupleScope,
+    request: Valid<SubscribeRequest>,
+) -> ApiResult<status::Created<Negotiated<PushSubscription>>> {
+    configured(push)?;
+    let mut subscription = request.into_inner();
+    if let Err(e) = http::resolve_callback_url(&subscription.endpoint).await {
+        let mut errors = FieldErrors::new();
+        errors.add("endpoint", format!("`endpoint` {}", e));
+        return Err(errors.into());
+    }
+    subscription.couple_id = scope.0;
+    let subscription = storage
+        .save_push_subscription(&subscription)
+        .await
+        .map_err(internal_error)?;
+
+    let location = uri!(public_key).to_string();
+    Ok(status::Created::new(location).body(Negotiated(subscription)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![public_key, subscribe]
+}
+
+/// Manages the [`Push`] handle, which sends nothing when `push` is not
+/// configured. In mock mode it always accepts subscriptions, with a VAPID
+/// key generated at startup unless one is configured, and only records
+/// notifications. Must be attached after the providers, HTTP client, jobs
+/// and events stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Web Push", |rocket| async {
+        let Some(client) = rocket.state::<CallbackClient>().cloned() else {
+            error!("push stage attached before the HTTP client");
+            return Err(rocket);
+        };
+        let Some(jobs) = rocket.state::<Jobs>().cloned() else {
+            error!("push stage attached before the jobs stage");
+            return Err(rocket);
+        };
+        let mock = providers::mock_log(&rocket);
+        let sender = match rocket.figment().extract_inner::<PushConfig>("push") {
+            Ok(config) => match Vapid::from_config(&config) {
+                Ok(vapid) => Some(Arc::new(Sender {
+                    vapid,
+                    client,
+                    mock,
+                })),
+                Err(e) => {
+                    error!("invalid push config: {}", e);
+                    return Err(rocket);
+                }
+            },
+            Err(e) if e.missing() && mock.is_some() => Some(Arc::new(Sender {
+                vapid: Vapid::generated(),
+                client,
+                mock,
+            })),
+            Err(e) if e.missing() => {
+                info!("no push config found, web push is disabled");
+                None
+            }
+            Err(e) => {
+                error!("invalid push config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let push = Push { sender, jobs };
+        Ok(rocket.manage(push.clone()).attach(events::subscriber(push)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The worked example from RFC 8291, section 5.
+    #[test]
+    fn payloads_match_the_rfc_example() {
+        let key = |b64: &str| decode(b64).unwrap();
+        let receiver = PublicKey::from_sec1_bytes(&key(
+            "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4",
+        ))
+        .unwrap();
+        let local =
+            SecretKey::from_slice(&key("yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw")).unwrap();
+        let salt: [u8; 16] = key("DGv6ra1nlYgDCS1FRnbzlw").try_into().unwrap();
+
+        let body = encrypt_with(
+            b"When I grow up, I want to be a watermelon",
+            &receiver,
+            &key("BTBZMqHH6r4Tts7J_aSIgg"),
+            &local,
+            &salt,
+        )
+        .unwrap();
+        assert_eq!(
+            BASE64URL.encode(body),
+            "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Qulcy4a-fN"
+        );
+        assert!(encrypt_with(&[0; 4000], &receiver, &[0; 16], &local, &salt).is_err());
+    }
+}
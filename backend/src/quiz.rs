@@ -0,0 +1,461 @@
+//! Compatibility quizzes. Each `<id>.json` file in `quizzes_dir` (default
+//! `quizzes/`) is one quiz: questions grouped into categories, options worth
+//! points, and result tiers picked by the overall percentage. Files are read
+//! at startup, so a new quiz only needs a file and a restart.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::{json, Json};
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{QuizScore, Storage};
+use crate::users::CoupleScope;
+
+pub(crate) const DEFAULT_QUIZZES_DIR: &str = "quizzes";
+
+/// Served by `GET /api/quiz` when no `id` is given, if it exists.
+const DEFAULT_QUIZ: &str = "compatibility";
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    id: String,
+    text: String,
+    points: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Question {
+    id: String,
+    category: String,
+    text: String,
+    options: Vec<Choice>,
+}
+
+impl Question {
+    fn max_points(&self) -> u32 {
+        self.options.iter().map(|o| o.points).max().unwrap_or(0)
+    }
+}
+
+/// A result tier, given to overall scores of at least `min` percent.
+#[derive(Debug, Deserialize)]
+struct Outcome {
+    min: u32,
+    title: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Quiz {
+    title: String,
+    description: String,
+    questions: Vec<Question>,
+    results: Vec<Outcome>,
+}
+
+impl Quiz {
+    fn validate(&self) -> Result<(), String> {
+        if self.questions.is_empty() {
+            return Err("a quiz needs at least one question".to_string());
+        }
+        let mut ids = HashSet::new();
+        for question in &self.questions {
+            if !ids.insert(question.id.as_str()) {
+                return Err(format!("duplicate question id `{}`", question.id));
+            }
+            if question.options.len() < 2 {
+                return Err(format!(
+                    "question `{}` needs at least two options",
+                    question.id
+                ));
+            }
+            let mut options = HashSet::new();
+            if let Some(option) = question
+                .options
+                .iter()
+                .find(|o| !options.insert(o.id.as_str()))
+            {
+                return Err(format!(
+                    "question `{}` lists option `{}` twice",
+                    question.id, option.id
+                ));
+            }
+        }
+        if self.questions.iter().all(|q| q.max_points() == 0) {
+            return Err("no option is worth any points".to_string());
+        }
+        if !self.results.iter().any(|r| r.min == 0) {
+            return Err("one result needs `min: 0` so every score has a result".to_string());
+        }
+        Ok(())
+    }
+
+    /// Scores `answers` (option id by question id), which must answer every
+    /// question with one of its options.
+    fn score(&self, answers: &HashMap<String, String>) -> Result<Scores, String> {
+        if let Some(unknown) = answers
+            .keys()
+            .find(|id| !self.questions.iter().any(|q| &q.id == *id))
+        {
+            return Err(format!("no question `{}` in this quiz", unknown));
+        }
+
+        let mut categories: BTreeMap<&str, (u32, u32)> = BTreeMap::new();
+        for question in &self.questions {
+            let answer = answers
+                .get(&question.id)
+                .ok_or_else(|| format!("question `{}` is not answered", question.id))?;
+            let option = question
+                .options
+                .iter()
+                .find(|o| &o.id == answer)
+                .ok_or_else(|| format!("`{}` is not an option of `{}`", answer, question.id))?;
+
+            let entry = categories.entry(&question.category).or_default();
+            entry.0 += option.points;
+            entry.1 += question.max_points();
+        }
+
+        let (points, max_points) = categories
+            .values()
+            .fold((0, 0), |(p, m), (cp, cm)| (p + cp, m + cm));
+        Ok(Scores {
+            overall: percent(points, max_points),
+            categories: categories
+                .into_iter()
+                .map(|(category, (points, max_points))| CategoryScore {
+                    category: category.to_string(),
+                    score: percent(points, max_points),
+                    points,
+                    max_points,
+                })
+                .collect(),
+        })
+    }
+
+    fn outcome(&self, score: u32) -> &Outcome {
+        self.results
+            .iter()
+            .filter(|r| r.min <= score)
+            .max_by_key(|r| r.min)
+            .expect("validated quizzes have a result for 0")
+    }
+}
+
+fn percent(points: u32, max_points: u32) -> u32 {
+    if max_points == 0 {
+        100
+    } else {
+        (f64::from(points) * 100.0 / f64::from(max_points)).round() as u32
+    }
+}
+
+#[derive(Debug)]
+struct Scores {
+    overall: u32,
+    categories: Vec<CategoryScore>,
+}
+
+/// Every loaded quiz by id (its file name). Managed by [`stage`].
+pub struct Quizzes(BTreeMap<String, Quiz>);
+
+impl Quizzes {
+    fn get(&self, id: Option<&str>) -> ApiResult<(&str, &Quiz)> {
+        let found = match id {
+            Some(id) => self.0.get_key_value(id),
+            None => self
+                .0
+                .get_key_value(DEFAULT_QUIZ)
+                .or_else(|| self.0.iter().next()),
+        };
+        found.map(|(id, quiz)| (id.as_str(), quiz)).ok_or_else(|| {
+            error(
+                Status::NotFound,
+                match id {
+                    Some(id) => format!("no quiz `{}`", id),
+                    None => "no quizzes are available".to_string(),
+                },
+            )
+        })
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct QuizSummary {
+    id: String,
+    title: String,
+    description: String,
+    questions: usize,
+}
+
+#[utoipa::path(tag = "quiz", responses((status = 200, body = Vec<QuizSummary>)))]
+#[get("/api/quizzes")]
+fn list(quizzes: &State<Quizzes>) -> Negotiated<Vec<QuizSummary>> {
+    Negotiated(
+        quizzes
+            .0
+            .iter()
+            .map(|(id, quiz)| QuizSummary {
+                id: id.clone(),
+                title: quiz.title.clone(),
+                description: quiz.description.clone(),
+                questions: quiz.questions.len(),
+            })
+            .collect(),
+    )
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct OptionView {
+    id: String,
+    text: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct QuestionView {
+    id: String,
+    category: String,
+    text: String,
+    options: Vec<OptionView>,
+}
+
+/// A quiz's questions, without the points each option is worth.
+#[derive(Serialize, utoipa::ToSchema)]
+struct QuizView {
+    id: String,
+    title: String,
+    description: String,
+    questions: Vec<QuestionView>,
+}
+
+#[utoipa::path(
+    tag = "quiz",
+    params(("id" = Option<String>, Query, description = "Quiz to serve (default `compatibility`); see `GET /api/quizzes`")),
+    responses(
+        (status = 200, body = QuizView),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/quiz?<id>")]
+fn questions(quizzes: &State<Quizzes>, id: Option<&str>) -> ApiResult<Negotiated<QuizView>> {
+    let (id, quiz) = quizzes.get(id)?;
+    Ok(Negotiated(QuizView {
+        id: id.to_string(),
+        title: quiz.title.clone(),
+        description: quiz.description.clone(),
+        questions: quiz
+            .questions
+            .iter()
+            .map(|q| QuestionView {
+                id: q.id.clone(),
+                category: q.category.clone(),
+                text: q.text.clone(),
+                options: q
+                    .options
+                    .iter()
+                    .map(|o| OptionView {
+                        id: o.id.clone(),
+                        text: o.text.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct AnswersRequest {
+    /// Quiz id; defaults like `GET /api/quiz`.
+    quiz: Option<String>,
+    /// The chosen option id for every question id.
+    answers: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct CategoryScore {
+    category: String,
+    /// Percentage of the category's points earned.
+    score: u32,
+    points: u32,
+    max_points: u32,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct QuizResult {
+    quiz: String,
+    /// Overall compatibility, 0 to 100.
+    score: u32,
+    title: String,
+    message: String,
+    categories: Vec<CategoryScore>,
+}
+
+/// Scores a full set of answers. No key is needed; the result is only
+/// kept, for the couple's export, when a member of a couple is signed in.
+#[utoipa::path(
+    tag = "quiz",
+    request_body = AnswersRequest,
+    responses(
+        (status = 200, body = QuizResult),
+        (status = 404, body = ErrorResponse),
+        (status = 422, description = "Unanswered or unknown questions or options", body = ErrorResponse),
+    )
+)]
+#[post("/api/quiz/answers", data = "<request>")]
+async fn answers(
+    quizzes: &State<Quizzes>,
+    storage: &Storage,
+    scope: CoupleScope,
+    request: Json<AnswersRequest>,
+) -> ApiResult<Negotiated<QuizResult>> {
+    let (id, quiz) = quizzes.get(request.quiz.as_deref())?;
+    let scores = quiz
+        .score(&request.answers)
+        .map_err(|e| error(Status::UnprocessableEntity, e))?;
+    let outcome = quiz.outcome(scores.overall);
+
+    if let Some(couple) = scope.0 {
+        let score = QuizScore {
+            quiz: id.to_string(),
+            score: scores.overall,
+            title: outcome.title.clone(),
+            categories: json!(scores.categories),
+            created_at: Utc::now(),
+        };
+        storage
+            .save_quiz_score(&score, couple)
+            .await
+            .map_err(internal_error)?;
+    }
+
+    Ok(Negotiated(QuizResult {
+        quiz: id.to_string(),
+        score: scores.overall,
+        title: outcome.title.clone(),
+        message: outcome.message.clone(),
+        categories: scores.categories,
+    }))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list, questions, answers]
+}
+
+/// Reads and validates every `*.json` file in `dir`.
+fn parse_quiz(contents: &str) -> Result<Quiz, String> {
+    let quiz: Quiz = rocket::serde::json::from_str(contents).map_err(|e| e.to_string())?;
+    quiz.validate()?;
+    Ok(quiz)
+}
+
+/// Checks that `contents` is a quiz file that can be served and scored.
+pub(crate) fn check_quiz(contents: &str) -> Result<(), String> {
+    parse_quiz(contents).map(|_| ())
+}
+
+fn load_quizzes(dir: &Path) -> Result<BTreeMap<String, Quiz>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("no quizzes directory at {}", dir.display());
+            return Ok(BTreeMap::new());
+        }
+        Err(e) => return Err(format!("failed to read {}: {}", dir.display(), e)),
+    };
+
+    let mut quizzes = BTreeMap::new();
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let quiz = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| parse_quiz(&contents))
+            .map_err(|e| format!("invalid quiz {}: {}", path.display(), e))?;
+        quizzes.insert(id, quiz);
+    }
+    Ok(quizzes)
+}
+
+/// Manages [`Quizzes`] loaded from `quizzes_dir`. An invalid file stops the
+/// launch rather than serving a quiz that cannot be scored.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Quizzes", |rocket| async {
+        let dir = match rocket.figment().extract_inner::<PathBuf>("quizzes_dir") {
+            Ok(dir) => dir,
+            Err(e) if e.missing() => PathBuf::from(DEFAULT_QUIZZES_DIR),
+            Err(e) => {
+                error!("invalid quizzes_dir: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        match load_quizzes(&dir) {
+            Ok(quizzes) => {
+                info!("loaded {} quizzes from {}", quizzes.len(), dir.display());
+                Ok(rocket.manage(Quizzes(quizzes)))
+            }
+            Err(e) => {
+                error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_are_scored_per_category() {
+        let quiz: Quiz =
+            rocket::serde::json::from_str(include_str!("../quizzes/compatibility.json")).unwrap();
+        quiz.validate().unwrap();
+
+        // The top option everywhere, except "occasions" (1 of 3) for notes.
+        let mut answers: HashMap<String, String> = quiz
+            .questions
+            .iter()
+            .map(|q| {
+                let best = q.options.iter().max_by_key(|o| o.points).unwrap();
+                (q.id.clone(), best.id.clone())
+            })
+            .collect();
+        answers.insert("love-notes".to_string(), "occasions".to_string());
+
+        let scores = quiz.score(&answers).unwrap();
+        assert_eq!(scores.overall, 92);
+        assert_eq!(quiz.outcome(scores.overall).title, "Soulmates");
+        let romance = scores
+            .categories
+            .iter()
+            .find(|c| c.category == "romance")
+            .unwrap();
+        assert_eq!(
+            (romance.points, romance.max_points, romance.score),
+            (4, 6, 67)
+        );
+
+        answers.remove("argument");
+        assert!(quiz.score(&answers).unwrap_err().contains("`argument`"));
+        answers.insert("argument".to_string(), "shout".to_string());
+        assert!(quiz.score(&answers).is_err());
+        assert_eq!(quiz.outcome(0).title, "Opposites attract");
+    }
+}
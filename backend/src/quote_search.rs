@@ -0,0 +1,387 @@
+//! Finding a quote by what it is about. `GET /api/quotes/search` ranks the
+//! served quotes by cosine similarity to the query, embedded on this server
+//! with the word vectors in the `quote_search.model` file (GloVe's text
+//! format: a word and its components per line). A text's embedding is the
+//! mean of its words' vectors. Each site's index is built once the server
+//! has launched and rebuilt every [`REINDEX_INTERVAL`], which picks up new
+//! and moderated quotes. Without a model, before a site is indexed, or when
+//! the model knows none of the query's words, quotes are ranked by how many
+//! of the query's words they contain instead.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::tokio::{fs, select, time};
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::quote_sources::QuoteSources;
+use crate::storage::{Quote, Storage};
+use crate::tenants::Tenants;
+use crate::workers::Workers;
+
+const REINDEX_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+/// The lowercase words of `text`, split at anything but letters and digits.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// An embedding model: a vector per known word.
+pub struct WordVectors {
+    dimensions: usize,
+    vectors: HashMap<String, Vec<f32>>,
+}
+
+impl WordVectors {
+    /// Reads GloVe's text format. A word2vec-style first line giving the
+    /// word count and dimensions is skipped.
+    pub fn parse(text: &str) -> Result<WordVectors, String> {
+        let mut dimensions = 0;
+        let mut vectors = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let mut fields = line.split_whitespace();
+            let Some(word) = fields.next() else {
+                continue;
+            };
+            let vector = fields
+                .map(str::parse)
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|e| format!("line {}: {}", i + 1, e))?;
+            if i == 0 && vector.len() == 1 && word.parse::<u64>().is_ok() {
+                continue;
+            }
+            if dimensions == 0 {
+                dimensions = vector.len();
+            }
+            if vector.is_empty() || vector.len() != dimensions {
+                return Err(format!(
+                    "line {}: expected {} components, found {}",
+                    i + 1,
+                    dimensions,
+                    vector.len()
+                ));
+            }
+            vectors.insert(word.to_lowercase(), vector);
+        }
+        if vectors.is_empty() {
+            return Err("no word vectors".to_string());
+        }
+        Ok(WordVectors {
+            dimensions,
+            vectors,
+        })
+    }
+
+    /// `text`'s unit-length embedding, or `None` when the model knows none
+    /// of its words.
+    pub fn embed(&self, text: &str) -> Option<Vec<f32>> {
+        let mut sum = vec![0.0; self.dimensions];
+        let mut known = 0;
+        for vector in words(text).filter_map(|word| self.vectors.get(&word)) {
+            sum.iter_mut().zip(vector).for_each(|(s, v)| *s += v);
+            known += 1;
+        }
+        let norm = sum.iter().map(|s| s * s).sum::<f32>().sqrt();
+        if known == 0 || norm == 0.0 {
+            return None;
+        }
+        sum.iter_mut().for_each(|s| *s /= norm);
+        Some(sum)
+    }
+}
+
+fn similarity(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking {
+    /// By the embedding model.
+    Semantic,
+    /// By the words each quote shares with the query.
+    Keyword,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct QuoteMatch {
+    pub quote: Quote,
+    /// Cosine similarity to the query for `semantic` ranking; the share of
+    /// the query's words the quote contains for `keyword`.
+    pub score: f32,
+}
+
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct QuoteSearchResponse {
+    pub ranking: Ranking,
+    /// Best match first.
+    pub results: Vec<QuoteMatch>,
+}
+
+/// The served quotes of one site, embedded. Quotes with no known words are
+/// left out.
+struct Index(Vec<(Quote, Vec<f32>)>);
+
+/// Managed state: the model, when there is one, and each site's index.
+#[derive(Clone)]
+pub struct QuoteSearch {
+    model: Option<Arc<WordVectors>>,
+    indexes: Arc<Mutex<HashMap<Option<String>, Arc<Index>>>>,
+}
+
+impl QuoteSearch {
+    fn index(&self, tenant: Option<&str>) -> Option<Arc<Index>> {
+        let indexes = self.indexes.lock().expect("quote search lock poisoned");
+        indexes.get(&tenant.map(str::to_string)).cloned()
+    }
+
+    /// The best `limit` matches for `query` in `tenant`'s index, or `None`
+    /// when semantic ranking is not possible for it.
+    fn semantic(&self, tenant: Option<&str>, query: &str, limit: usize) -> Option<Vec<QuoteMatch>> {
+        let query = self.model.as_ref()?.embed(query)?;
+        let index = self.index(tenant)?;
+        let mut scored: Vec<_> = index
+            .0
+            .iter()
+            .map(|(quote, embedding)| (quote, similarity(&query, embedding)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Some(
+            scored
+                .into_iter()
+                .take(limit)
+                .map(|(quote, score)| QuoteMatch {
+                    quote: quote.clone(),
+                    score,
+                })
+                .collect(),
+        )
+    }
+}
+
+async fn keyword(
+    storage: &Storage,
+    sources: &QuoteSources,
+    query: &str,
+    limit: i64,
+) -> ApiResult<Vec<QuoteMatch>> {
+    let mut query: Vec<String> = words(query).collect();
+    query.sort();
+    query.dedup();
+    let terms: Vec<&str> = query.iter().map(String::as_str).collect();
+    let quotes = sources
+        .search_quotes(storage, &terms, limit)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(quotes
+        .into_iter()
+        .map(|quote| {
+            let text = quote.text.to_lowercase();
+            let hits = terms.iter().filter(|term| text.contains(*term)).count();
+            QuoteMatch {
+                quote,
+                score: hits as f32 / terms.len() as f32,
+            }
+        })
+        .collect())
+}
+
+/// Ranks the served quotes by how well they match `q`, such as "long
+/// distance" or "first date nerves".
+#[utoipa::path(
+    tag = "quotes",
+    params(
+        ("q" = String, Query, description = "What the quote should be about"),
+        ("limit" = Option<i64>, Query, description = "At most this many results (default 10, at most 50)"),
+    ),
+    responses(
+        (status = 200, body = QuoteSearchResponse),
+        (status = 400, description = "`q` has no words", body = ErrorResponse),
+    )
+)]
+#[get("/api/quotes/search?<q>&<limit>")]
+async fn search(
+    q: &str,
+    limit: Option<i64>,
+    search: &State<QuoteSearch>,
+    sources: &State<QuoteSources>,
+    storage: &Storage,
+) -> ApiResult<Negotiated<QuoteSearchResponse>> {
+    if words(q).next().is_none() {
+        return Err(error(Status::BadRequest, "`q` must contain a word"));
+    }
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let response = match search.semantic(storage.tenant(), q, limit as usize) {
+        Some(results) => QuoteSearchResponse {
+            ranking: Ranking::Semantic,
+            results,
+        },
+        None => QuoteSearchResponse {
+            ranking: Ranking::Keyword,
+            results: keyword(storage, sources, q, limit).await?,
+        },
+    };
+    Ok(Negotiated(response))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![search]
+}
+
+/// Embeds the served quotes of `storage`'s site now and every
+/// [`REINDEX_INTERVAL`] until `token` is cancelled.
+async fn run_indexer(
+    storage: Storage,
+    search: QuoteSearch,
+    model: Arc<WordVectors>,
+    sources: QuoteSources,
+    token: CancellationToken,
+) {
+    let mut reindex = time::interval(REINDEX_INTERVAL);
+    loop {
+        select! {
+            _ = reindex.tick() => match sources.served_quotes(&storage).await {
+                Ok(quotes) => {
+                    let index = Index(
+                        quotes
+                            .into_iter()
+                            .filter_map(|quote| Some((model.embed(&quote.text)?, quote)))
+                            .map(|(embedding, quote)| (quote, embedding))
+                            .collect(),
+                    );
+                    search
+                        .indexes
+                        .lock()
+                        .expect("quote search lock poisoned")
+                        .insert(storage.tenant().map(str::to_string), Arc::new(index));
+                }
+                Err(e) => error!("failed to index quotes for search: {}", e),
+            },
+            _ = token.cancelled() => return,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct QuoteSearchConfig {
+    model: Option<PathBuf>,
+}
+
+/// Manages [`QuoteSearch`] with the model from `quote_search.model`, and
+/// starts an indexer per database at liftoff. A model that cannot be read
+/// is logged and leaves search on keywords. Attach it after the quote
+/// sources stage.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Quote Search", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<QuoteSearchConfig>("quote_search")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => QuoteSearchConfig::default(),
+            Err(e) => {
+                error!("invalid quote_search config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let model = match config.model {
+            Some(path) => match fs::read_to_string(&path).await {
+                Ok(text) => match WordVectors::parse(&text) {
+                    Ok(model) => Some(Arc::new(model)),
+                    Err(e) => {
+                        warn!(
+                            "quote search model {}: {}; using keywords",
+                            path.display(),
+                            e
+                        );
+                        None
+                    }
+                },
+                Err(e) => {
+                    warn!(
+                        "quote search model {}: {}; using keywords",
+                        path.display(),
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let search = QuoteSearch {
+            model: model.clone(),
+            indexes: Arc::default(),
+        };
+
+        let Some(model) = model else {
+            return Ok(rocket.manage(search));
+        };
+        Ok(rocket
+            .manage(search.clone())
+            .attach(AdHoc::on_liftoff("Quote Search Indexer", move |rocket| {
+                Box::pin(async move {
+                    match (
+                        rocket.state::<Tenants>(),
+                        rocket.state::<Workers>(),
+                        rocket.state::<QuoteSources>(),
+                    ) {
+                        (Some(tenants), Some(workers), Some(sources)) => {
+                            let (workers, sources) = (workers.clone(), sources.clone());
+                            tenants.for_each_database(move |storage| {
+                                let (search, model) = (search.clone(), model.clone());
+                                let sources = sources.clone();
+                                workers.spawn("quote search indexer", |token| {
+                                    run_indexer(storage, search, model, sources, token)
+                                });
+                            });
+                        }
+                        _ => error!(
+                            "quote search not indexed: storage, workers or quote sources are unavailable"
+                        ),
+                    }
+                })
+            })))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embeddings_rank_by_meaning() {
+        let model = WordVectors::parse(
+            "3 3\n\
+             miles 0.9 0.1 0.0\n\
+             apart 0.8 0.2 0.1\n\
+             distance 1.0 0.0 0.1\n\
+             laugh 0.0 1.0 0.2\n\
+             joke 0.1 0.9 0.0\n",
+        )
+        .unwrap();
+        let query = model.embed("Long DISTANCE").unwrap();
+        let apart = model.embed("Miles apart, still yours").unwrap();
+        let funny = model.embed("You laugh at my joke").unwrap();
+        assert!(similarity(&query, &apart) > similarity(&query, &funny));
+        assert!((similarity(&query, &query) - 1.0).abs() < 1e-6);
+        assert_eq!(model.embed("nothing known here"), None);
+
+        assert!(WordVectors::parse("a 1 2\nb 3\n").is_err());
+        assert!(WordVectors::parse("").is_err());
+    }
+}
@@ -0,0 +1,643 @@
+//! Where served quotes come from. The `quote_sources` table lists sources
+//! in priority order: `builtin` (the quotes bundled with the binary),
+//! `database` (quotes added through the API), and named `file` and `remote`
+//! sources whose quotes are synced into the database, so translations,
+//! stats and reactions work for them as for any other quote. Remote sources
+//! are re-fetched every `refresh_secs`, which lets a shared Gist feed the
+//! pool without a redeploy; a failed fetch keeps the last synced quotes.
+//!
+//! `merge = "union"` serves from every listed source, `merge = "first"`
+//! from the first one with a quote in the requested category. Without the
+//! table the pool is `builtin` and `database`, merged.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket::tokio::{self, fs};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::admin::quotes::QuoteRequest;
+use crate::cache::QueryCache;
+use crate::resilience::{Resilience, ResilientClient};
+use crate::storage::{Category, NewQuote, Quote, Storage, BUILTIN_SOURCE, DATABASE_SOURCE};
+use crate::validation::Validate;
+use crate::workers::Workers;
+
+/// How often remote sources are re-fetched by default.
+const DEFAULT_REFRESH_SECS: u64 = 3600;
+/// Quotes one file or remote source may list.
+const MAX_SOURCE_QUOTES: usize = 5000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Merge {
+    /// Serve from every source.
+    #[default]
+    Union,
+    /// Serve from the first source, in order, that has a matching quote.
+    First,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum SourceConfig {
+    Builtin,
+    Database,
+    /// A `.toml` file with `[[quotes]]` tables, or a `.json` file.
+    File {
+        name: String,
+        path: PathBuf,
+        /// Re-read the file this often; by default it is read at launch.
+        refresh_secs: Option<u64>,
+    },
+    /// JSON, or TOML if the URL path ends in `.toml`.
+    Remote {
+        name: String,
+        url: String,
+        #[serde(default = "default_refresh_secs")]
+        refresh_secs: u64,
+    },
+}
+
+fn default_refresh_secs() -> u64 {
+    DEFAULT_REFRESH_SECS
+}
+
+/// The `[default.quote_sources]` table in Rocket.toml.
+#[derive(Debug, Deserialize)]
+struct QuoteSourcesConfig {
+    #[serde(default)]
+    merge: Merge,
+    #[serde(default = "default_sources")]
+    sources: Vec<SourceConfig>,
+}
+
+fn default_sources() -> Vec<SourceConfig> {
+    vec![SourceConfig::Builtin, SourceConfig::Database]
+}
+
+impl Default for QuoteSourcesConfig {
+    fn default() -> Self {
+        QuoteSourcesConfig {
+            merge: Merge::default(),
+            sources: default_sources(),
+        }
+    }
+}
+
+/// A quotes document: an array of quotes, or an object with a `quotes`
+/// array as in the TOML format.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum QuotesDocument {
+    List(Vec<QuoteRequest>),
+    Table { quotes: Vec<QuoteRequest> },
+}
+
+/// Parses and validates a quotes document, rejecting it whole if any quote
+/// is invalid.
+fn parse_quotes(body: &str, toml: bool) -> Result<Vec<NewQuote>, String> {
+    let document: QuotesDocument = if toml {
+        Figment::from(Toml::string(body))
+            .extract()
+            .map_err(|e| e.to_string())?
+    } else {
+        rocket::serde::json::from_str(body).map_err(|e| e.to_string())?
+    };
+    let (QuotesDocument::List(quotes) | QuotesDocument::Table { quotes }) = document;
+    if quotes.len() > MAX_SOURCE_QUOTES {
+        return Err(format!(
+            "{} quotes listed, at most {} are allowed",
+            quotes.len(),
+            MAX_SOURCE_QUOTES
+        ));
+    }
+    quotes
+        .into_iter()
+        .enumerate()
+        .map(|(i, quote)| quote.validate().map_err(|e| format!("quote {}: {}", i, e)))
+        .collect()
+}
+
+enum Location {
+    File(PathBuf),
+    Remote { url: reqwest::Url },
+}
+
+/// The last sync of a file or remote source, or a builtin or database
+/// source, which are never synced.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct SourceStatus {
+    pub name: String,
+    /// `builtin`, `database`, `file` or `remote`.
+    pub kind: String,
+    /// The file path or URL.
+    pub location: Option<String>,
+    pub refresh_secs: Option<u64>,
+    /// Quotes currently from this source, of any status.
+    pub quotes: i64,
+    pub last_synced_at: Option<DateTime<Utc>>,
+    /// The latest failure, cleared by the next successful sync.
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct QuoteSourcesStatus {
+    pub merge: Merge,
+    /// In priority order.
+    pub sources: Vec<SourceStatus>,
+}
+
+/// A parsed quotes document and the `ETag` it was served with.
+struct Fetched {
+    quotes: Vec<NewQuote>,
+    etag: Option<String>,
+}
+
+/// A file or remote source whose quotes are synced into the database.
+struct SyncedSource {
+    name: String,
+    location: Location,
+    refresh: Option<Duration>,
+    /// The `ETag` of the last remote response, sent as `If-None-Match`.
+    etag: Mutex<Option<String>>,
+    /// Held while syncing, so a manual refresh waits for the worker.
+    syncing: tokio::sync::Mutex<()>,
+    status: Mutex<SourceStatus>,
+}
+
+impl SyncedSource {
+    fn update(&self, change: impl FnOnce(&mut SourceStatus)) {
+        change(&mut self.status.lock().expect("quote source lock poisoned"));
+    }
+
+    /// The source's quotes, or `None` when a remote source reports them
+    /// unchanged.
+    async fn fetch(&self, client: &ResilientClient) -> Result<Option<Fetched>, String> {
+        match &self.location {
+            Location::File(path) => {
+                let body = fs::read_to_string(path)
+                    .await
+                    .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+                let toml = path.extension().is_some_and(|ext| ext == "toml");
+                let quotes = parse_quotes(&body, toml)?;
+                Ok(Some(Fetched { quotes, etag: None }))
+            }
+            Location::Remote { url } => {
+                let mut request = client.get(url.clone());
+                let etag = self
+                    .etag
+                    .lock()
+                    .expect("quote source lock poisoned")
+                    .clone();
+                if let Some(etag) = etag {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+                }
+                let response = client
+                    .send(request)
+                    .await
+                    .map_err(|e| format!("fetching {} failed: {}", url, e))?;
+                if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+                    return Ok(None);
+                }
+                if !response.status().is_success() {
+                    return Err(format!("{} responded {}", url, response.status()));
+                }
+                let etag = response
+                    .headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(str::to_string);
+                let body = response
+                    .text()
+                    .await
+                    .map_err(|e| format!("reading {} failed: {}", url, e))?;
+                let quotes = parse_quotes(&body, url.path().ends_with(".toml"))?;
+                Ok(Some(Fetched { quotes, etag }))
+            }
+        }
+    }
+}
+
+struct Inner {
+    merge: Merge,
+    /// Every source name, in priority order.
+    order: Vec<String>,
+    synced: Vec<SyncedSource>,
+    client: ResilientClient,
+    cache: QueryCache,
+}
+
+/// Managed state for picking quotes from the configured sources. Clones
+/// share the sources.
+#[derive(Clone)]
+pub struct QuoteSources {
+    inner: Arc<Inner>,
+}
+
+impl QuoteSources {
+    /// The sources to serve a quote in `category` from.
+    async fn served(
+        &self,
+        storage: &Storage,
+        category: Option<Category>,
+    ) -> Result<Vec<&str>, sqlx::Error> {
+        let order = self.inner.order.iter().map(String::as_str);
+        match self.inner.merge {
+            Merge::Union => Ok(order.collect()),
+            Merge::First => {
+                for source in order {
+                    if storage.count_served_quotes(category, &[source]).await? > 0 {
+                        return Ok(vec![source]);
+                    }
+                }
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Picks a uniformly random approved quote from the served sources.
+    pub async fn random_quote(
+        &self,
+        storage: &Storage,
+        category: Option<Category>,
+        rng: &mut (impl Rng + Send),
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        let sources = self.served(storage, category).await?;
+        storage.random_quote(category, &sources, rng).await
+    }
+
+    /// See [`Storage::quote_for_seed`].
+    pub async fn quote_for_seed(
+        &self,
+        storage: &Storage,
+        category: Option<Category>,
+        seed: u64,
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        let sources = self.served(storage, category).await?;
+        storage.quote_for_seed(category, &sources, seed).await
+    }
+
+    /// Every approved quote from the served sources.
+    pub async fn served_quotes(&self, storage: &Storage) -> Result<Vec<Quote>, sqlx::Error> {
+        let sources = self.served(storage, None).await?;
+        storage.served_quotes(&sources).await
+    }
+
+    /// See [`Storage::search_quotes`].
+    pub async fn search_quotes(
+        &self,
+        storage: &Storage,
+        words: &[&str],
+        limit: i64,
+    ) -> Result<Vec<Quote>, sqlx::Error> {
+        let sources = self.served(storage, None).await?;
+        storage.search_quotes(words, &sources, limit).await
+    }
+
+    pub async fn status(&self, storage: &Storage) -> Result<QuoteSourcesStatus, sqlx::Error> {
+        let counts = storage.count_quotes_by_source().await?;
+        let count = |name: &str| {
+            counts
+                .iter()
+                .find(|(source, _)| source == name)
+                .map_or(0, |(_, count)| *count)
+        };
+        let sources = self
+            .inner
+            .order
+            .iter()
+            .map(|name| {
+                let mut status = match self.synced(name) {
+                    Some(source) => source
+                        .status
+                        .lock()
+                        .expect("quote source lock poisoned")
+                        .clone(),
+                    None => SourceStatus {
+                        name: name.clone(),
+                        kind: name.clone(),
+                        location: None,
+                        refresh_secs: None,
+                        quotes: 0,
+                        last_synced_at: None,
+                        last_error: None,
+                        last_error_at: None,
+                    },
+                };
+                status.quotes = count(name);
+                status
+            })
+            .collect();
+        Ok(QuoteSourcesStatus {
+            merge: self.inner.merge,
+            sources,
+        })
+    }
+
+    fn synced(&self, name: &str) -> Option<&SyncedSource> {
+        self.inner.synced.iter().find(|source| source.name == name)
+    }
+
+    /// Syncs the file or remote source `name` now; `None` if there is none.
+    pub async fn refresh(&self, storage: &Storage, name: &str) -> Option<Result<(), String>> {
+        let source = self.synced(name)?;
+        Some(self.sync(storage, source).await)
+    }
+
+    /// Fetches `source` and makes its quotes the synced ones, invalidating
+    /// the quote of the day if anything changed.
+    async fn sync(&self, storage: &Storage, source: &SyncedSource) -> Result<(), String> {
+        let _syncing = source.syncing.lock().await;
+        let result = match source.fetch(&self.inner.client).await {
+            Ok(Some(fetched)) => match storage
+                .sync_quote_source(&source.name, &fetched.quotes)
+                .await
+            {
+                // Only remember the tag of a document that was stored, so a
+                // fixed or unsaved one is fetched again in full.
+                Ok(sync) => {
+                    *source.etag.lock().expect("quote source lock poisoned") = fetched.etag;
+                    Ok(Some(sync))
+                }
+                Err(e) => Err(format!("failed to store quotes: {}", e)),
+            },
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(sync) => {
+                if let Some(sync) = sync.filter(|sync| sync.changed + sync.removed > 0) {
+                    info!(
+                        "quote source {}: {} quotes added or changed, {} removed",
+                        source.name, sync.changed, sync.removed
+                    );
+                    self.inner.cache.invalidate("daily");
+                }
+                source.update(|status| {
+                    status.last_synced_at = Some(Utc::now());
+                    status.last_error = None;
+                    status.last_error_at = None;
+                });
+                Ok(())
+            }
+            Err(e) => {
+                source.update(|status| {
+                    status.last_error = Some(e.clone());
+                    status.last_error_at = Some(Utc::now());
+                });
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Syncs each remote source at launch and every source with a refresh
+/// interval whenever it is due, until `token` is cancelled.
+async fn run_worker(sources: QuoteSources, storage: Storage, token: CancellationToken) {
+    let start = tokio::time::Instant::now();
+    let mut due: Vec<Option<tokio::time::Instant>> = sources
+        .inner
+        .synced
+        .iter()
+        .map(|source| match source.location {
+            Location::Remote { .. } => Some(start),
+            // Files were read when the server launched.
+            Location::File(_) => source.refresh.map(|refresh| start + refresh),
+        })
+        .collect();
+
+    while !token.is_cancelled() {
+        let Some(next) = due.iter().flatten().min().copied() else {
+            return;
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(next) => {}
+            _ = token.cancelled() => return,
+        }
+
+        let now = tokio::time::Instant::now();
+        for (source, due) in sources.inner.synced.iter().zip(&mut due) {
+            if due.is_some_and(|due| due <= now) {
+                if let Err(e) = sources.sync(&storage, source).await {
+                    warn!("quote source {} not synced: {}", source.name, e);
+                }
+                *due = source
+                    .refresh
+                    .map(|refresh| tokio::time::Instant::now() + refresh);
+            }
+        }
+    }
+}
+
+fn check_name(name: &str, names: &[String]) -> Result<(), String> {
+    if name.is_empty() || name.len() > 64 {
+        return Err("source names must be 1 to 64 characters".to_string());
+    }
+    if names.iter().any(|existing| existing == name) {
+        return Err(format!("source `{}` is listed twice", name));
+    }
+    Ok(())
+}
+
+/// The source names in order and the sources to sync.
+fn build_sources(config: QuoteSourcesConfig) -> Result<(Vec<String>, Vec<SyncedSource>), String> {
+    let mut order = Vec::with_capacity(config.sources.len());
+    let mut synced = Vec::new();
+    for source in config.sources {
+        let (name, kind, location, refresh) = match source {
+            SourceConfig::Builtin => {
+                check_name(BUILTIN_SOURCE, &order)?;
+                order.push(BUILTIN_SOURCE.to_string());
+                continue;
+            }
+            SourceConfig::Database => {
+                check_name(DATABASE_SOURCE, &order)?;
+                order.push(DATABASE_SOURCE.to_string());
+                continue;
+            }
+            SourceConfig::File {
+                name,
+                path,
+                refresh_secs,
+            } => (name, "file", Location::File(path), refresh_secs),
+            SourceConfig::Remote {
+                name,
+                url,
+                refresh_secs,
+            } => {
+                let url = reqwest::Url::parse(&url)
+                    .ok()
+                    .filter(|url| matches!(url.scheme(), "http" | "https"))
+                    .ok_or_else(|| format!("source `{}`: `{}` is not an http(s) URL", name, url))?;
+                (name, "remote", Location::Remote { url }, Some(refresh_secs))
+            }
+        };
+        if name == BUILTIN_SOURCE || name == DATABASE_SOURCE {
+            return Err(format!("`{}` is reserved for the {} source", name, name));
+        }
+        check_name(&name, &order)?;
+        if refresh == Some(0) {
+            return Err(format!(
+                "source `{}`: refresh_secs must be at least 1",
+                name
+            ));
+        }
+
+        let location_text = match &location {
+            Location::File(path) => path.display().to_string(),
+            Location::Remote { url } => url.to_string(),
+        };
+        order.push(name.clone());
+        synced.push(SyncedSource {
+            status: Mutex::new(SourceStatus {
+                name: name.clone(),
+                kind: kind.to_string(),
+                location: Some(location_text),
+                refresh_secs: refresh,
+                quotes: 0,
+                last_synced_at: None,
+                last_error: None,
+                last_error_at: None,
+            }),
+            name,
+            location,
+            refresh: refresh.map(Duration::from_secs),
+            etag: Mutex::new(None),
+            syncing: tokio::sync::Mutex::new(()),
+        });
+    }
+    if order.is_empty() {
+        return Err("at least one source must be listed".to_string());
+    }
+    Ok((order, synced))
+}
+
+/// Manages [`QuoteSources`] from the `quote_sources` table, reads file
+/// sources, and starts the worker fetching remote ones once the server has
+/// launched. Must be attached after the storage, query cache and
+/// resilience stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Quote Sources", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<QuoteSourcesConfig>("quote_sources")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => QuoteSourcesConfig::default(),
+            Err(e) => {
+                error!("invalid quote_sources config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let merge = config.merge;
+        let (order, synced) = match build_sources(config) {
+            Ok(sources) => sources,
+            Err(e) => {
+                error!("invalid quote_sources: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let (Some(storage), Some(cache), Some(client)) = (
+            rocket.state::<Storage>().cloned(),
+            rocket.state::<QueryCache>().cloned(),
+            rocket.state::<Resilience>().map(|r| r.client("quotes")),
+        ) else {
+            error!(
+                "quote sources stage attached before storage, the query cache or the resilience stage"
+            );
+            return Err(rocket);
+        };
+
+        let sources = QuoteSources {
+            inner: Arc::new(Inner {
+                merge,
+                order,
+                synced,
+                client,
+                cache,
+            }),
+        };
+        for source in &sources.inner.synced {
+            if matches!(source.location, Location::File(_)) {
+                if let Err(e) = sources.sync(&storage, source).await {
+                    error!("quote source {}: {}", source.name, e);
+                    return Err(rocket);
+                }
+            }
+        }
+
+        let worker = sources.clone();
+        Ok(rocket.manage(sources).attach(AdHoc::on_liftoff(
+            "Quote Sources Worker",
+            move |rocket| {
+                Box::pin(async move {
+                    match (rocket.state::<Storage>(), rocket.state::<Workers>()) {
+                        (Some(storage), Some(workers)) => {
+                            let storage = storage.clone();
+                            workers
+                                .spawn("quote sources", |token| run_worker(worker, storage, token));
+                        }
+                        _ => error!(
+                            "quote sources worker not started: storage or workers are unavailable"
+                        ),
+                    }
+                })
+            },
+        )))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quote_documents_and_source_lists() {
+        let json = r#"[{"text": " Be mine. "}, {"text": "Ha!", "category": "funny"}]"#;
+        let quotes = parse_quotes(json, false).unwrap();
+        assert_eq!(quotes[0].text, "Be mine.");
+        assert_eq!(quotes[1].category, Category::Funny);
+        let toml = "[[quotes]]\ntext = \"Be mine.\"\ncategory = \"poetic\"\n";
+        assert_eq!(
+            parse_quotes(toml, true).unwrap()[0].category,
+            Category::Poetic
+        );
+        assert!(parse_quotes(r#"[{"text": ""}]"#, false).is_err());
+
+        let config: QuoteSourcesConfig = Figment::from(Toml::string(
+            r#"
+            merge = "first"
+            [[sources]]
+            kind = "remote"
+            name = "gist"
+            url = "https://example.com/quotes.json"
+            [[sources]]
+            kind = "builtin"
+            "#,
+        ))
+        .extract()
+        .unwrap();
+        assert_eq!(config.merge, Merge::First);
+        let (order, synced) = build_sources(config).unwrap();
+        assert_eq!(order, ["gist", "builtin"]);
+        assert_eq!(synced[0].refresh, Some(Duration::from_secs(3600)));
+
+        let reserved: QuoteSourcesConfig = Figment::from(Toml::string(
+            "[[sources]]\nkind = \"file\"\nname = \"database\"\npath = \"q.json\"\n",
+        ))
+        .extract()
+        .unwrap();
+        assert!(build_sources(reserved).is_err());
+    }
+}
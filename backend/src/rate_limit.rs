@@ -0,0 +1,417 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request};
+use serde::Deserialize;
+
+use crate::error::ApiError;
+use crate::shared::{Local, SharedState};
+
+/// Internal route that rate-limited requests are rewritten to, so the
+/// original handler (and its side effects) never runs.
+const LIMITED_PATH: &str = "/__rate_limited";
+
+/// Token bucket parameters.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Limit {
+    /// Maximum burst size.
+    pub capacity: f64,
+    /// Tokens restored per second.
+    pub refill_per_second: f64,
+}
+
+impl Limit {
+    fn validate(&self, name: &str) -> Result<(), String> {
+        if !(self.capacity >= 1.0 && self.capacity.is_finite()) {
+            return Err(format!("{}.capacity must be at least 1", name));
+        }
+        if !(self.refill_per_second > 0.0 && self.refill_per_second.is_finite()) {
+            return Err(format!("{}.refill_per_second must be positive", name));
+        }
+        Ok(())
+    }
+}
+
+/// A stricter limit for every path starting with `prefix`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteLimit {
+    pub prefix: String,
+    /// Methods the rule applies to, e.g. `["POST"]`; every method when
+    /// empty.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(flatten)]
+    pub limit: Limit,
+}
+
+impl RouteLimit {
+    fn matches(&self, method: Method, path: &str) -> bool {
+        path.starts_with(self.prefix.as_str())
+            && (self.methods.is_empty()
+                || self
+                    .methods
+                    .iter()
+                    .any(|m| m.eq_ignore_ascii_case(method.as_str())))
+    }
+}
+
+/// The `[default.rate_limit]` table in Rocket.toml.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub default: Limit,
+    /// Path prefixes that are never limited.
+    #[serde(default = "default_exempt")]
+    pub exempt: Vec<String>,
+    #[serde(default)]
+    pub routes: Vec<RouteLimit>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl RateLimitConfig {
+    fn validate(&self) -> Result<(), String> {
+        self.default.validate("rate_limit")?;
+        for (i, route) in self.routes.iter().enumerate() {
+            route
+                .limit
+                .validate(&format!("rate_limit.routes[{}] ({})", i, route.prefix))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether `path` is `prefix` or below it, matching whole segments so
+/// `/health` covers `/health/ready` but not `/healthz`.
+fn under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+fn default_exempt() -> Vec<String> {
+    vec!["/health".to_string(), "/metrics".to_string()]
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: default_enabled(),
+            default: Limit {
+                capacity: 60.0,
+                refill_per_second: 1.0,
+            },
+            exempt: default_exempt(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+/// Per-IP token bucket limiter. Each route rule (and the default) keeps its
+/// own bucket per client address, kept in the [`SharedState`].
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    shared: Arc<dyn SharedState>,
+}
+
+/// Set on requests that were rejected, read back by the 429 route.
+#[derive(Clone, Copy)]
+struct RetryAfter(Duration);
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, shared: Arc<dyn SharedState>) -> Self {
+        RateLimiter { config, shared }
+    }
+
+    /// Index 0 is the default limit, `i + 1` the i-th route rule.
+    fn rule_for(&self, method: Method, path: &str) -> Option<(usize, &Limit)> {
+        if self.config.exempt.iter().any(|p| under(path, p)) {
+            return None;
+        }
+
+        let rule = self
+            .config
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.matches(method, path))
+            .max_by_key(|(_, r)| r.prefix.len())
+            .map(|(i, r)| (i + 1, &r.limit));
+
+        Some(rule.unwrap_or((0, &self.config.default)))
+    }
+
+    /// The name of `ip`'s bucket for the request and its limit, if it is
+    /// limited at all.
+    fn bucket_for(&self, ip: IpAddr, method: Method, path: &str) -> Option<(String, &Limit)> {
+        let (rule, limit) = self.rule_for(method, path)?;
+        Some((format!("rate_limit:{}:{}", rule, ip), limit))
+    }
+
+    /// Takes a token from `ip`'s bucket for the request. Requests are let
+    /// through if the buckets cannot be reached.
+    async fn check(&self, ip: IpAddr, method: Method, path: &str) -> Result<(), Duration> {
+        let Some((bucket, limit)) = self.bucket_for(ip, method, path) else {
+            return Ok(());
+        };
+
+        match self.shared.take_token(&bucket, limit).await {
+            Ok(result) => result,
+            Err(e) => {
+                error!("shared rate limit unavailable, allowing request: {}", e);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate Limiter",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if request.method() == Method::Options {
+            return;
+        }
+        let Some(ip) = request.client_ip() else {
+            return;
+        };
+
+        let path = request.uri().path().as_str().to_string();
+        if let Err(retry_after) = self.check(ip, request.method(), &path).await {
+            info!("rate limited {} on {}", ip, path);
+            request.local_cache(|| Some(RetryAfter(retry_after)));
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(LIMITED_PATH).expect("valid internal path"));
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RetryAfter {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        match request.local_cache(|| None::<RetryAfter>) {
+            Some(retry_after) => Outcome::Success(*retry_after),
+            None => Outcome::Forward(Status::NotFound),
+        }
+    }
+}
+
+#[derive(Responder)]
+#[response(status = 429)]
+struct TooManyRequests {
+    body: ApiError,
+    retry_after: Header<'static>,
+}
+
+#[get("/__rate_limited")]
+fn too_many_requests(retry_after: RetryAfter) -> TooManyRequests {
+    let seconds = retry_after.0.as_secs_f64().ceil().max(1.0) as u64;
+    TooManyRequests {
+        body: ApiError::TooManyRequests(format!(
+            "rate limit exceeded, retry in {} seconds",
+            seconds
+        )),
+        retry_after: Header::new("Retry-After", seconds.to_string()),
+    }
+}
+
+/// Attaches the [`RateLimiter`] configured by the `rate_limit` table, falling
+/// back to the defaults when the table is absent.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Rate Limiting", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<RateLimitConfig>("rate_limit")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => RateLimitConfig::default(),
+            Err(e) => {
+                error!("invalid rate_limit config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        if !config.enabled {
+            info!("rate limiting disabled");
+            return Ok(rocket);
+        }
+        if let Err(e) = config.validate() {
+            error!("invalid rate_limit config: {}", e);
+            return Err(rocket);
+        }
+
+        let shared = rocket
+            .state::<Arc<dyn SharedState>>()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Local::default()));
+        Ok(rocket
+            .attach(RateLimiter::new(config, shared))
+            .mount("/", routes![too_many_requests]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Deref;
+    use std::time::Instant;
+
+    /// A limiter over in-process buckets, checked as of a given time.
+    struct TestLimiter {
+        limiter: RateLimiter,
+        local: Arc<Local>,
+    }
+
+    impl TestLimiter {
+        fn check(
+            &self,
+            ip: IpAddr,
+            method: Method,
+            path: &str,
+            now: Instant,
+        ) -> Result<(), Duration> {
+            match self.bucket_for(ip, method, path) {
+                Some((bucket, limit)) => self.local.take_token_at(&bucket, limit, now),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Deref for TestLimiter {
+        type Target = RateLimiter;
+
+        fn deref(&self) -> &RateLimiter {
+            &self.limiter
+        }
+    }
+
+    fn limiter() -> TestLimiter {
+        let local = Arc::new(Local::default());
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                enabled: true,
+                default: Limit {
+                    capacity: 2.0,
+                    refill_per_second: 1.0,
+                },
+                exempt: default_exempt(),
+                routes: vec![
+                    RouteLimit {
+                        prefix: "/api/valentine/send".to_string(),
+                        methods: Vec::new(),
+                        limit: Limit {
+                            capacity: 1.0,
+                            refill_per_second: 0.1,
+                        },
+                    },
+                    RouteLimit {
+                        prefix: "/api/confessions".to_string(),
+                        methods: vec!["post".to_string()],
+                        limit: Limit {
+                            capacity: 1.0,
+                            refill_per_second: 0.1,
+                        },
+                    },
+                ],
+            },
+            local.clone(),
+        );
+        TestLimiter { limiter, local }
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let limiter = limiter();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let start = Instant::now();
+
+        assert!(limiter
+            .check(ip, Method::Get, "/api/valentine", start)
+            .is_ok());
+        assert!(limiter
+            .check(ip, Method::Get, "/api/valentine", start)
+            .is_ok());
+        let retry = limiter
+            .check(ip, Method::Get, "/api/valentine", start)
+            .unwrap_err();
+        assert_eq!(retry, Duration::from_secs(1));
+
+        let later = start + Duration::from_secs(1);
+        assert!(limiter
+            .check(ip, Method::Get, "/api/valentine", later)
+            .is_ok());
+    }
+
+    #[test]
+    fn route_rules_and_exemptions_use_separate_buckets() {
+        let limiter = limiter();
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let now = Instant::now();
+
+        assert!(limiter
+            .check(ip, Method::Post, "/api/valentine/send", now)
+            .is_ok());
+        let retry = limiter
+            .check(ip, Method::Post, "/api/valentine/send", now)
+            .unwrap_err();
+        assert_eq!(retry, Duration::from_secs(10));
+
+        assert!(limiter
+            .check(ip, Method::Get, "/api/valentine", now)
+            .is_ok());
+        for _ in 0..10 {
+            assert!(limiter.check(ip, Method::Get, "/health", now).is_ok());
+            assert!(limiter.check(ip, Method::Get, "/health/ready", now).is_ok());
+        }
+        for path in ["/healthz", "/metricsfoo"] {
+            assert!(limiter.rule_for(Method::Get, path).is_some(), "{}", path);
+        }
+
+        assert!(limiter
+            .check(ip, Method::Post, "/api/confessions", now)
+            .is_ok());
+        assert!(limiter
+            .check(ip, Method::Post, "/api/confessions", now)
+            .is_err());
+        assert!(limiter
+            .check(ip, Method::Get, "/api/confessions", now)
+            .is_ok());
+    }
+
+    #[test]
+    fn limits_must_refill_and_hold_a_token() {
+        assert!(limiter().config.validate().is_ok());
+        for (capacity, refill_per_second) in [(0.5, 1.0), (2.0, 0.0), (2.0, -1.0), (2.0, f64::NAN)]
+        {
+            let mut config = limiter().config.clone();
+            config.routes[1].limit = Limit {
+                capacity,
+                refill_per_second,
+            };
+            assert!(
+                config.validate().is_err(),
+                "{} {}",
+                capacity,
+                refill_per_second
+            );
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use rocket::http::{Cookie, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::time::Duration;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::ApiKey;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::stickers;
+use crate::storage::{Reaction, ReactionCount, Storage};
+use crate::users::CoupleScope;
+
+/// Emoji accepted by `POST /api/valentine/<id>/react`.
+pub const ALLOWED_EMOJI: &[&str] = &["❤️", "😍", "🥰", "😘", "💘", "🌹", "😂", "🥹"];
+
+const CLIENT_COOKIE: &str = "valentine_client";
+
+/// Length of the hex fingerprint stored per reaction.
+const FINGERPRINT_LEN: usize = 32;
+
+/// Maps user input onto an entry of [`ALLOWED_EMOJI`], ignoring surrounding
+/// whitespace and the emoji variation selector (so `❤` matches `❤️`).
+fn normalize_emoji(input: &str) -> Option<&'static str> {
+    let strip = |s: &str| s.trim().replace('\u{fe0f}', "");
+    let input = strip(input);
+    ALLOWED_EMOJI.iter().copied().find(|e| strip(e) == input)
+}
+
+fn is_fingerprint(value: &str) -> bool {
+    value.len() == FINGERPRINT_LEN && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Identifies a client for duplicate suppression. Reuses the
+/// `valentine_client` cookie when present; otherwise derives an id from the
+/// client's address and user agent and hands it out as that cookie, so
+/// browsers and cookie-less clients both stay stable across requests.
+pub struct ClientFingerprint(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientFingerprint {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        let cookies = request.cookies();
+        if let Some(cookie) = cookies.get(CLIENT_COOKIE) {
+            if is_fingerprint(cookie.value()) {
+                return Outcome::Success(ClientFingerprint(cookie.value().to_string()));
+            }
+        }
+
+        let mut hasher = Sha256::new();
+        if let Some(ip) = request.client_ip() {
+            hasher.update(ip.to_string());
+        }
+        hasher.update([0]);
+        hasher.update(request.headers().get_one("User-Agent").unwrap_or(""));
+        let fingerprint: String = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()[..FINGERPRINT_LEN]
+            .to_string();
+
+        cookies.add(
+            Cookie::build((CLIENT_COOKIE, fingerprint.clone()))
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(Duration::days(365)),
+        );
+        Outcome::Success(ClientFingerprint(fingerprint))
+    }
+}
+
+/// Exactly one of `emoji` and `sticker_id`.
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ReactRequest {
+    emoji: Option<String>,
+    /// A sticker id from `GET /api/stickers`.
+    sticker_id: Option<i64>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ReactionSummary {
+    message_id: i64,
+    total: i64,
+    reactions: Vec<ReactionCount>,
+}
+
+#[derive(Responder)]
+enum ReactResponse {
+    /// The reaction was recorded.
+    #[response(status = 201)]
+    Added(Negotiated<ReactionSummary>),
+    /// This client already left the same reaction; nothing changed.
+    #[response(status = 200)]
+    Unchanged(Negotiated<ReactionSummary>),
+}
+
+async fn summary(storage: &Storage, message_id: i64) -> ApiResult<ReactionSummary> {
+    let reactions = storage
+        .reaction_counts(message_id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(ReactionSummary {
+        message_id,
+        total: reactions.iter().map(|r| r.count).sum(),
+        reactions,
+    })
+}
+
+async fn require_message(storage: &Storage, scope: CoupleScope, id: i64) -> ApiResult<()> {
+    match storage
+        .get_message(id, scope.0)
+        .await
+        .map_err(internal_error)?
+    {
+        Some(_) => Ok(()),
+        None => Err(error(
+            Status::NotFound,
+            format!("no message with id {}", id),
+        )),
+    }
+}
+
+/// Records `client`'s reaction to message `id` with either an emoji or a
+/// sticker, returning false when it was already recorded. Shared by the
+/// REST and GraphQL APIs.
+pub async fn add_reaction(
+    storage: &Storage,
+    client: &ClientFingerprint,
+    scope: CoupleScope,
+    id: i64,
+    emoji: Option<&str>,
+    sticker_id: Option<i64>,
+) -> ApiResult<bool> {
+    let reaction = match (emoji, sticker_id) {
+        (Some(emoji), None) => Reaction::Emoji(normalize_emoji(emoji).ok_or_else(|| {
+            error(
+                Status::UnprocessableEntity,
+                format!("`emoji` must be one of: {}", ALLOWED_EMOJI.join(" ")),
+            )
+        })?),
+        (None, Some(sticker)) => Reaction::Sticker(
+            stickers::require_sticker(storage, "sticker_id", sticker)
+                .await?
+                .id,
+        ),
+        _ => {
+            return Err(error(
+                Status::UnprocessableEntity,
+                "send exactly one of `emoji` and `sticker_id`",
+            ))
+        }
+    };
+    require_message(storage, scope, id).await?;
+
+    storage
+        .add_reaction(id, reaction, &client.0)
+        .await
+        .map_err(internal_error)
+}
+
+#[utoipa::path(
+    tag = "reactions",
+    request_body = ReactRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, description = "Reaction recorded", body = ReactionSummary),
+        (status = 200, description = "Already reacted; nothing changed", body = ReactionSummary),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 422, description = "Emoji not allowed or unknown sticker", body = ErrorResponse),
+    )
+)]
+#[post("/api/valentine/<id>/react", data = "<request>")]
+async fn react(
+    _key: ApiKey,
+    storage: &Storage,
+    client: ClientFingerprint,
+    scope: CoupleScope,
+    id: i64,
+    request: Json<ReactRequest>,
+) -> ApiResult<ReactResponse> {
+    let added = add_reaction(
+        storage,
+        &client,
+        scope,
+        id,
+        request.emoji.as_deref(),
+        request.sticker_id,
+    )
+    .await?;
+
+    let summary = Negotiated(summary(storage, id).await?);
+    Ok(if added {
+        ReactResponse::Added(summary)
+    } else {
+        ReactResponse::Unchanged(summary)
+    })
+}
+
+#[utoipa::path(
+    tag = "reactions",
+    responses(
+        (status = 200, body = ReactionSummary),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/<id>/reactions")]
+async fn reactions(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<ReactionSummary>> {
+    require_message(storage, scope, id).await?;
+    Ok(Negotiated(summary(storage, id).await?))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![react, reactions]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emoji_are_matched_against_the_allowed_set() {
+        assert_eq!(normalize_emoji("❤️"), Some("❤️"));
+        assert_eq!(normalize_emoji(" ❤ "), Some("❤️"));
+        assert_eq!(normalize_emoji("🌹"), Some("🌹"));
+        assert_eq!(normalize_emoji("💩"), None);
+        assert_eq!(normalize_emoji(""), None);
+    }
+}
@@ -0,0 +1,365 @@
+//! Background workers that remind about upcoming important dates and
+//! reservations, by email, push notification and through the
+//! `date.reminder` and `reservation.reminder` webhook events, at each one's
+//! configured lead time.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rocket::fairing::AdHoc;
+use rocket::tokio;
+use tokio_util::sync::CancellationToken;
+
+use crate::dates::UpcomingDate;
+use crate::email::Mailer;
+use crate::jobs::{Job, Jobs};
+use crate::push::{Notification, Push};
+use crate::storage::{ImportantDate, Reservation, Storage, WebhookEvent};
+use crate::tenants::Tenants;
+use crate::webhooks::Webhooks;
+use crate::workers::Workers;
+
+/// How often the worker looks for due reminders. Lead times are in whole
+/// days, so an hour is plenty.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Reservation lead times are in hours, so those are checked more often.
+const RESERVATION_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Lead times that are due `days_remaining` days out and have not been sent
+/// yet. A date added inside one of its lead times is reminded about at once.
+fn due_leads(leads: &[i64], days_remaining: i64, sent: &[i64]) -> Vec<i64> {
+    leads
+        .iter()
+        .copied()
+        .filter(|lead| days_remaining <= *lead && !sent.contains(lead))
+        .collect()
+}
+
+fn subject(upcoming: &UpcomingDate) -> String {
+    match upcoming.days_remaining {
+        0 => format!("Reminder: {} is today", upcoming.date.title),
+        1 => format!("Reminder: {} is tomorrow", upcoming.date.title),
+        days => format!(
+            "Reminder: {} is in {} days ({})",
+            upcoming.date.title, days, upcoming.next_date
+        ),
+    }
+}
+
+/// Whether `reservation`'s reminder should go out at `now`. One booked
+/// inside its lead time is reminded about at once.
+fn reservation_due(reservation: &Reservation, now: DateTime<Utc>) -> bool {
+    now < reservation.reserved_for
+        && reservation.reserved_for - chrono::Duration::hours(reservation.remind_hours) <= now
+}
+
+fn reservation_notice(reservation: &Reservation) -> (String, String) {
+    let tz: Tz = reservation.timezone.parse().unwrap_or(Tz::UTC);
+    let local = reservation.reserved_for.with_timezone(&tz);
+    let subject = format!(
+        "Reminder: {} at {}",
+        reservation.place,
+        local.format("%H:%M")
+    );
+
+    let mut text = format!(
+        "{} at {} on {} ({}).",
+        reservation.place,
+        local.format("%H:%M"),
+        local.format("%A %-d %B"),
+        tz.name()
+    );
+    if let Some(address) = &reservation.address {
+        text.push_str(&format!(" Address: {}.", address));
+    }
+    if let Some(size) = reservation.party_size {
+        text.push_str(&format!(" Table for {}.", size));
+    }
+    if let Some(confirmation) = &reservation.confirmation {
+        text.push_str(&format!(" Confirmation number: {}.", confirmation));
+    }
+    (subject, text)
+}
+
+#[derive(Clone)]
+struct Worker {
+    storage: Storage,
+    mailer: Mailer,
+    jobs: Jobs,
+    webhooks: Webhooks,
+    push: Push,
+}
+
+impl Worker {
+    /// Queues a reminder email; the job retries it if sending fails.
+    async fn email(&self, address: &str, subject: String, text: String) -> Result<(), String> {
+        let job = Job::Email {
+            to: address.to_string(),
+            subject,
+            text,
+        };
+        self.jobs
+            .enqueue(&self.storage, &job)
+            .await
+            .map_err(|e| format!("failed to queue email to {}: {}", address, e))
+    }
+
+    async fn remind(&self, date: ImportantDate, today: chrono::NaiveDate) -> Result<(), String> {
+        let Some(upcoming) = UpcomingDate::next(date, today) else {
+            return Ok(());
+        };
+        let (id, occurrence) = (upcoming.date.id, upcoming.next_date);
+        let sent = self
+            .storage
+            .sent_reminders(id, occurrence)
+            .await
+            .map_err(|e| e.to_string())?;
+        let due = due_leads(
+            &upcoming.date.reminders.days,
+            upcoming.days_remaining,
+            &sent,
+        );
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        // Several lead times can fall due together (e.g. after downtime);
+        // one reminder covers them all.
+        if let Some(address) = &upcoming.date.reminders.email {
+            if self.mailer.is_enabled() {
+                // Not recorded if it cannot be queued, so the next pass
+                // tries again.
+                let text = subject(&upcoming);
+                self.email(address, text.clone(), text).await?;
+            } else {
+                warn!(
+                    "date {} has a reminder email but smtp is not configured",
+                    id
+                );
+            }
+        }
+        self.webhooks
+            .emit(
+                &self.storage,
+                upcoming.date.couple_id,
+                WebhookEvent::DateReminder,
+                &upcoming,
+            )
+            .await;
+        self.push
+            .notify(
+                &self.storage,
+                upcoming.date.couple_id,
+                Notification {
+                    title: subject(&upcoming),
+                    body: format!("{} ({})", upcoming.date.title, upcoming.next_date),
+                    url: Some(format!("/api/dates/{}", id)),
+                },
+            )
+            .await;
+
+        self.storage
+            .record_reminders(id, occurrence, &due)
+            .await
+            .map_err(|e| e.to_string())?;
+        info!(
+            "sent reminder for date {} ({} days out)",
+            id, upcoming.days_remaining
+        );
+        Ok(())
+    }
+
+    async fn remind_reservation(&self, reservation: &Reservation) -> Result<(), String> {
+        if let Some(address) = &reservation.remind_email {
+            if self.mailer.is_enabled() {
+                // Not marked if it cannot be queued, so the next pass tries
+                // again.
+                let (subject, text) = reservation_notice(reservation);
+                self.email(address, subject, text).await?;
+            } else {
+                warn!(
+                    "reservation {} has a reminder email but smtp is not configured",
+                    reservation.id
+                );
+            }
+        }
+        self.webhooks
+            .emit(
+                &self.storage,
+                reservation.couple_id,
+                WebhookEvent::ReservationReminder,
+                reservation,
+            )
+            .await;
+        let (title, body) = reservation_notice(reservation);
+        self.push
+            .notify(
+                &self.storage,
+                reservation.couple_id,
+                Notification {
+                    title,
+                    body,
+                    url: Some(format!("/api/reservations/{}", reservation.id)),
+                },
+            )
+            .await;
+
+        self.storage
+            .mark_reservation_reminded(reservation.id)
+            .await
+            .map_err(|e| e.to_string())?;
+        info!("sent reminder for reservation {}", reservation.id);
+        Ok(())
+    }
+
+    /// Like [`Worker::run`] for reservations, every
+    /// [`RESERVATION_CHECK_INTERVAL`].
+    async fn run_reservations(self, token: CancellationToken) {
+        while !token.is_cancelled() {
+            let now = Utc::now();
+            match self.storage.unreminded_reservations(now).await {
+                Ok(reservations) => {
+                    for reservation in reservations.iter().filter(|r| reservation_due(r, now)) {
+                        if token.is_cancelled() {
+                            return;
+                        }
+                        if let Err(e) = self.remind_reservation(reservation).await {
+                            error!(
+                                "failed to send reminder for reservation {}: {}",
+                                reservation.id, e
+                            );
+                        }
+                    }
+                }
+                Err(e) => error!("failed to load reservation reminders: {}", e),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(RESERVATION_CHECK_INTERVAL) => {}
+                _ = token.cancelled() => {}
+            }
+        }
+    }
+
+    /// Checks every [`CHECK_INTERVAL`] until `token` is cancelled, finishing
+    /// the reminder in hand first; unsent ones go out on the next launch.
+    async fn run(self, token: CancellationToken) {
+        while !token.is_cancelled() {
+            let today = Utc::now().date_naive();
+            match self.storage.dates_with_reminders(today).await {
+                Ok(dates) => {
+                    for date in dates {
+                        if token.is_cancelled() {
+                            return;
+                        }
+                        let id = date.id;
+                        if let Err(e) = self.remind(date, today).await {
+                            error!("failed to send reminder for date {}: {}", id, e);
+                        }
+                    }
+                }
+                Err(e) => error!("failed to load date reminders: {}", e),
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = token.cancelled() => {}
+            }
+        }
+    }
+}
+
+/// Spawns the reminder workers for each database once the server has
+/// launched.
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Reminder Worker", |rocket| {
+        Box::pin(async move {
+            match (
+                rocket.state::<Tenants>(),
+                rocket.state::<Mailer>(),
+                rocket.state::<Jobs>(),
+                rocket.state::<Webhooks>(),
+                rocket.state::<Push>(),
+                rocket.state::<Workers>(),
+            ) {
+                (
+                    Some(tenants),
+                    Some(mailer),
+                    Some(jobs),
+                    Some(webhooks),
+                    Some(push),
+                    Some(workers),
+                ) => {
+                    let (mailer, jobs) = (mailer.clone(), jobs.clone());
+                    let (webhooks, push) = (webhooks.clone(), push.clone());
+                    let workers = workers.clone();
+                    tenants.for_each_database(move |storage| {
+                        let worker = Worker {
+                            storage,
+                            mailer: mailer.clone(),
+                            jobs: jobs.clone(),
+                            webhooks: webhooks.clone(),
+                            push: push.clone(),
+                        };
+                        let reservations = worker.clone();
+                        workers.spawn("reminder worker", |token| worker.run(token));
+                        workers.spawn("reservation reminder worker", |token| {
+                            reservations.run_reservations(token)
+                        });
+                    });
+                }
+                _ => error!(
+                    "reminder workers not started: storage, mailer, jobs, webhooks, push or workers unavailable"
+                ),
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leads_fall_due_once_inside_their_window() {
+        let leads = [7, 1, 0];
+        assert_eq!(due_leads(&leads, 10, &[]), Vec::<i64>::new());
+        assert_eq!(due_leads(&leads, 7, &[]), vec![7]);
+        assert_eq!(due_leads(&leads, 5, &[7]), Vec::<i64>::new());
+        assert_eq!(due_leads(&leads, 1, &[7]), vec![1]);
+        assert_eq!(due_leads(&leads, 0, &[]), vec![7, 1, 0]);
+    }
+
+    #[test]
+    fn reservation_reminders_fall_due_inside_the_lead_time() {
+        let reservation = Reservation {
+            id: 1,
+            place: "Chez Amour".to_string(),
+            address: None,
+            reserved_for: "2027-02-14T18:30:00Z".parse().unwrap(),
+            timezone: "Europe/Paris".to_string(),
+            party_size: Some(2),
+            confirmation: Some("AB12".to_string()),
+            remind_hours: 3,
+            remind_email: None,
+            reminded_at: None,
+            created_at: Utc::now(),
+            couple_id: None,
+        };
+        let at = |t: &str| t.parse::<DateTime<Utc>>().unwrap();
+        assert!(!reservation_due(&reservation, at("2027-02-14T15:29:00Z")));
+        assert!(reservation_due(&reservation, at("2027-02-14T15:30:00Z")));
+        assert!(reservation_due(&reservation, at("2027-02-14T18:00:00Z")));
+        assert!(!reservation_due(&reservation, at("2027-02-14T18:30:00Z")));
+
+        let (subject, text) = reservation_notice(&reservation);
+        assert_eq!(subject, "Reminder: Chez Amour at 19:30");
+        assert_eq!(
+            text,
+            "Chez Amour at 19:30 on Sunday 14 February (Europe/Paris). Table for 2. \
+             Confirmation number: AB12."
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::Deserialize;
+
+use crate::auth::ApiKey;
+use crate::email;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{NewReservation, Reservation, Storage};
+use crate::users::CoupleScope;
+use crate::valentine::check_text;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_PLACE_LEN: usize = 100;
+const MAX_ADDRESS_LEN: usize = 200;
+const MAX_CONFIRMATION_LEN: usize = 50;
+const MAX_PARTY_SIZE: i64 = 50;
+
+/// Hours before the reservation to send its reminder when not given, and
+/// the furthest ahead one may be.
+const DEFAULT_REMIND_HOURS: i64 = 3;
+const MAX_REMIND_HOURS: i64 = 72;
+
+/// How long after its start a reservation stays "next", so the widget
+/// still shows tonight's plan once the table is taken.
+const NEXT_GRACE: Duration = Duration::hours(2);
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ReservationRequest {
+    place: String,
+    address: Option<String>,
+    /// RFC 3339 with an offset, e.g. `2027-02-14T19:30:00-05:00`.
+    time: DateTime<Utc>,
+    /// IANA zone to show the time in, UTC by default.
+    timezone: Option<String>,
+    party_size: Option<i64>,
+    confirmation: Option<String>,
+    /// 1 to 72, default 3.
+    remind_hours: Option<i64>,
+    /// Where to email the reminder, in addition to `reservation.reminder`
+    /// webhooks.
+    email: Option<String>,
+}
+
+fn optional_text(
+    field: &str,
+    value: Option<String>,
+    max_len: usize,
+) -> Result<Option<String>, String> {
+    let value = value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty());
+    if let Some(value) = &value {
+        check_text(field, value, max_len)?;
+    }
+    Ok(value)
+}
+
+impl ReservationRequest {
+    /// Validates the request as of `now`, which the reservation must be after.
+    fn validate_at(self, now: DateTime<Utc>) -> Result<NewReservation, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let place = self.place.trim().to_string();
+        errors.text("place", &place, MAX_PLACE_LEN);
+
+        if self.time <= now {
+            errors.add("time", "`time` must be in the future");
+        }
+        let timezone = match self.timezone.as_deref().map(str::trim) {
+            Some(name) if !name.is_empty() => errors
+                .check(
+                    "timezone",
+                    name.parse::<Tz>()
+                        .map_err(|_| format!("unknown `timezone` `{}`", name)),
+                )
+                .unwrap_or(Tz::UTC)
+                .name()
+                .to_string(),
+            _ => Tz::UTC.name().to_string(),
+        };
+        if let Some(size) = self.party_size {
+            if !(1..=MAX_PARTY_SIZE).contains(&size) {
+                errors.add(
+                    "party_size",
+                    format!("`party_size` must be between 1 and {}", MAX_PARTY_SIZE),
+                );
+            }
+        }
+        let remind_hours = self.remind_hours.unwrap_or(DEFAULT_REMIND_HOURS);
+        if !(1..=MAX_REMIND_HOURS).contains(&remind_hours) {
+            errors.add(
+                "remind_hours",
+                format!("`remind_hours` must be between 1 and {}", MAX_REMIND_HOURS),
+            );
+        }
+        let remind_email = self
+            .email
+            .map(|e| e.trim().to_string())
+            .filter(|e| !e.is_empty());
+        if let Some(address) = &remind_email {
+            if !email::is_valid_address(address) {
+                errors.add(
+                    "email",
+                    format!("`email` is not a valid address: {}", address),
+                );
+            }
+        }
+        let address = errors
+            .check(
+                "address",
+                optional_text("address", self.address, MAX_ADDRESS_LEN),
+            )
+            .flatten();
+        let confirmation = errors
+            .check(
+                "confirmation",
+                optional_text("confirmation", self.confirmation, MAX_CONFIRMATION_LEN),
+            )
+            .flatten();
+
+        errors.finish(NewReservation {
+            place,
+            address,
+            // Whole seconds keep the stored timestamps comparable as text.
+            reserved_for: self.time.trunc_subsecs(0),
+            timezone,
+            party_size: self.party_size,
+            confirmation,
+            remind_hours,
+            remind_email,
+            couple_id: None,
+        })
+    }
+}
+
+impl Validate for ReservationRequest {
+    type Valid = NewReservation;
+
+    fn validate(self) -> Result<NewReservation, FieldErrors> {
+        self.validate_at(Utc::now())
+    }
+}
+
+/// Records a reservation. Its reminder goes out `remind_hours` before, by
+/// email when `email` is set and to `reservation.reminder` webhooks.
+#[utoipa::path(
+    tag = "dates",
+    request_body = ReservationRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = Reservation),
+        (status = 401, body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/reservations", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    request: Valid<ReservationRequest>,
+) -> ApiResult<status::Created<Negotiated<Reservation>>> {
+    let mut reservation = request.into_inner();
+    reservation.couple_id = scope.0;
+    let reservation = storage
+        .create_reservation(&reservation)
+        .await
+        .map_err(internal_error)?;
+
+    let location = uri!(get(reservation.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(reservation)))
+}
+
+/// The soonest upcoming reservation, for a "tonight's plan" widget. One
+/// that started less than two hours ago still counts.
+#[utoipa::path(
+    tag = "dates",
+    responses(
+        (status = 200, body = Reservation),
+        (status = 404, description = "Nothing booked", body = ErrorResponse),
+    )
+)]
+#[get("/api/reservations/next")]
+async fn next(storage: &Storage, scope: CoupleScope) -> ApiResult<Negotiated<Reservation>> {
+    storage
+        .next_reservation(scope.0, Utc::now() - NEXT_GRACE)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, "no upcoming reservation"))
+}
+
+#[utoipa::path(
+    tag = "dates",
+    responses(
+        (status = 200, body = Reservation),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/reservations/<id>")]
+async fn get(storage: &Storage, scope: CoupleScope, id: i64) -> ApiResult<Negotiated<Reservation>> {
+    storage
+        .get_reservation(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no reservation with id {}", id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, next, get]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(time: &str) -> ReservationRequest {
+        ReservationRequest {
+            place: " Chez Amour ".to_string(),
+            address: Some("  ".to_string()),
+            time: time.parse().unwrap(),
+            timezone: Some("Europe/Paris".to_string()),
+            party_size: Some(2),
+            confirmation: Some("AB12".to_string()),
+            remind_hours: None,
+            email: None,
+        }
+    }
+
+    #[test]
+    fn reservations_are_validated() {
+        let now: DateTime<Utc> = "2027-02-14T12:00:00Z".parse().unwrap();
+        let reservation = request("2027-02-14T19:30:00.25+01:00")
+            .validate_at(now)
+            .unwrap();
+        assert_eq!(reservation.place, "Chez Amour");
+        assert_eq!(reservation.address, None);
+        assert_eq!(
+            reservation.reserved_for.to_rfc3339(),
+            "2027-02-14T18:30:00+00:00"
+        );
+        assert_eq!(reservation.remind_hours, DEFAULT_REMIND_HOURS);
+
+        assert!(request("2027-02-14T11:00:00Z").validate_at(now).is_err());
+        let mut bad_zone = request("2027-02-14T19:30:00Z");
+        bad_zone.timezone = Some("Mars/Olympus".to_string());
+        assert!(bad_zone.validate_at(now).is_err());
+        let mut no_lead = request("2027-02-14T19:30:00Z");
+        no_lead.remind_hours = Some(0);
+        assert!(no_lead.validate_at(now).is_err());
+    }
+}
@@ -0,0 +1,518 @@
+//! Timeouts, retries and circuit breakers for calls to external providers.
+//! Each provider sends through a [`ResilientClient`] that bounds every
+//! request by the provider's timeout and retries transient failures with
+//! jittered exponential backoff. After repeated failures its breaker opens
+//! and calls fail at once for a while, so a provider that hangs cannot tie
+//! up the handlers waiting on it; then a single trial call decides whether
+//! it closes again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{Client, IntoUrl, Method, RequestBuilder, Response, StatusCode};
+use rocket::fairing::AdHoc;
+use rocket::tokio::time::sleep;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_TIMEOUT_SECS: f64 = 10.0;
+const DEFAULT_RETRIES: u32 = 2;
+const DEFAULT_BACKOFF_MS: u64 = 200;
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_SECS: u64 = 30;
+
+/// Delay before the first queued retry; each further retry waits twice as
+/// long, up to [`MAX_QUEUED_BACKOFF`].
+const QUEUED_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_QUEUED_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct PolicyConfig {
+    timeout_secs: Option<f64>,
+    retries: Option<u32>,
+    backoff_ms: Option<u64>,
+    failure_threshold: Option<u32>,
+    open_secs: Option<u64>,
+}
+
+/// The `[default.resilience]` table in Rocket.toml: settings for every
+/// provider, overridden for one in `providers.<name>`.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ResilienceConfig {
+    #[serde(flatten)]
+    defaults: PolicyConfig,
+    #[serde(default)]
+    providers: HashMap<String, PolicyConfig>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    /// For the whole request, body included.
+    pub timeout: Duration,
+    /// Further tries after a transient failure.
+    pub retries: u32,
+    /// Before the first retry; doubled for each one after.
+    pub backoff: Duration,
+    /// Consecutive failures that open the breaker.
+    pub failure_threshold: u32,
+    /// How long an open breaker fails calls before letting a trial through.
+    pub open_for: Duration,
+}
+
+impl ResilienceConfig {
+    fn policy(&self, provider: &str) -> Result<Policy, String> {
+        let own = self.providers.get(provider).cloned().unwrap_or_default();
+        let defaults = &self.defaults;
+        let timeout_secs = own
+            .timeout_secs
+            .or(defaults.timeout_secs)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        if !(timeout_secs > 0.0 && timeout_secs.is_finite()) {
+            return Err(format!(
+                "resilience timeout_secs for {} must be positive",
+                provider
+            ));
+        }
+        let failure_threshold = own
+            .failure_threshold
+            .or(defaults.failure_threshold)
+            .unwrap_or(DEFAULT_FAILURE_THRESHOLD);
+        if failure_threshold == 0 {
+            return Err(format!(
+                "resilience failure_threshold for {} must be at least 1",
+                provider
+            ));
+        }
+        Ok(Policy {
+            timeout: Duration::from_secs_f64(timeout_secs),
+            retries: own.retries.or(defaults.retries).unwrap_or(DEFAULT_RETRIES),
+            backoff: Duration::from_millis(
+                own.backoff_ms
+                    .or(defaults.backoff_ms)
+                    .unwrap_or(DEFAULT_BACKOFF_MS),
+            ),
+            failure_threshold,
+            open_for: Duration::from_secs(
+                own.open_secs
+                    .or(defaults.open_secs)
+                    .unwrap_or(DEFAULT_OPEN_SECS),
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls go through.
+    Closed,
+    /// Calls fail without being sent.
+    Open,
+    /// The open period is over; the next call is a trial.
+    HalfOpen,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    consecutive_failures: u32,
+    opened_at: Option<(Instant, DateTime<Utc>)>,
+    trial_started: Option<Instant>,
+    requests: u64,
+    failures: u64,
+    trips: u64,
+    last_error: Option<String>,
+}
+
+/// One provider's circuit breaker, shared by every client for it.
+pub struct Breaker {
+    policy: Policy,
+    counters: Mutex<Counters>,
+}
+
+impl Breaker {
+    fn new(policy: Policy) -> Self {
+        Breaker {
+            policy,
+            counters: Mutex::default(),
+        }
+    }
+
+    fn state(&self, counters: &Counters, now: Instant) -> BreakerState {
+        match counters.opened_at {
+            None => BreakerState::Closed,
+            Some((at, _)) if now.saturating_duration_since(at) < self.policy.open_for => {
+                BreakerState::Open
+            }
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    /// Whether a call may be sent now, or how long until one may. Half open,
+    /// one trial goes at a time; a trial that never reported back, such as
+    /// one whose request was dropped, is given up on after its timeout.
+    fn admit(&self, now: Instant) -> Result<(), Duration> {
+        let mut counters = self.counters.lock().expect("circuit breaker lock poisoned");
+        match self.state(&counters, now) {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => {
+                let (at, _) = counters.opened_at.expect("an open breaker has opened");
+                Err(self
+                    .policy
+                    .open_for
+                    .saturating_sub(now.saturating_duration_since(at)))
+            }
+            BreakerState::HalfOpen => match counters.trial_started {
+                Some(started) if now.saturating_duration_since(started) < self.policy.timeout => {
+                    Err(self.policy.timeout - now.saturating_duration_since(started))
+                }
+                _ => {
+                    counters.trial_started = Some(now);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn record(&self, outcome: Result<(), String>, now: Instant) {
+        let mut counters = self.counters.lock().expect("circuit breaker lock poisoned");
+        counters.requests += 1;
+        counters.trial_started = None;
+        match outcome {
+            Ok(()) => {
+                counters.consecutive_failures = 0;
+                counters.opened_at = None;
+            }
+            Err(e) => {
+                counters.failures += 1;
+                counters.consecutive_failures += 1;
+                counters.last_error = Some(e);
+                let state = self.state(&counters, now);
+                let trips = match state {
+                    BreakerState::Closed => {
+                        counters.consecutive_failures >= self.policy.failure_threshold
+                    }
+                    BreakerState::HalfOpen => true,
+                    BreakerState::Open => false,
+                };
+                if trips {
+                    counters.opened_at = Some((now, Utc::now()));
+                    counters.trips += 1;
+                }
+            }
+        }
+    }
+
+    fn status(&self, provider: &str, now: Instant) -> ProviderStatus {
+        let counters = self.counters.lock().expect("circuit breaker lock poisoned");
+        let state = self.state(&counters, now);
+        ProviderStatus {
+            provider: provider.to_string(),
+            state,
+            consecutive_failures: counters.consecutive_failures,
+            requests: counters.requests,
+            failures: counters.failures,
+            trips: counters.trips,
+            opened_at: counters
+                .opened_at
+                .filter(|_| state != BreakerState::Closed)
+                .map(|(_, at)| at),
+            retry_in_secs: match (state, counters.opened_at) {
+                (BreakerState::Open, Some((at, _))) => Some(
+                    self.policy
+                        .open_for
+                        .saturating_sub(now.saturating_duration_since(at))
+                        .as_secs_f64()
+                        .ceil() as u64,
+                ),
+                _ => None,
+            },
+            last_error: counters.last_error.clone(),
+            timeout_secs: self.policy.timeout.as_secs_f64(),
+            retries: self.policy.retries,
+        }
+    }
+}
+
+/// A provider's breaker as `GET /admin/providers/status` shows it.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct ProviderStatus {
+    pub provider: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    /// Calls sent since startup, each retry counted.
+    pub requests: u64,
+    pub failures: u64,
+    /// Times the breaker opened.
+    pub trips: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<DateTime<Utc>>,
+    /// Seconds until an open breaker lets a trial through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub timeout_secs: f64,
+    pub retries: u32,
+}
+
+/// Statuses worth another try: rate limited, or the provider is briefly
+/// down. A bad gateway or gateway timeout may have reached the provider, so
+/// only requests that are safe to repeat are retried after one.
+fn is_transient(status: StatusCode, idempotent: bool) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => true,
+        StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT => idempotent,
+        _ => false,
+    }
+}
+
+/// The wait before retry `attempt` (from 0): the backoff doubled per
+/// attempt, less a random part of up to half, so clients that failed
+/// together do not retry together.
+fn jittered(backoff: Duration, attempt: u32) -> Duration {
+    let full = backoff.saturating_mul(2u32.saturating_pow(attempt));
+    full.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Delay before retry number `attempt` (1-based) of work queued in the
+/// database, i.e. webhook deliveries and background jobs.
+pub fn queued_backoff(attempt: i64) -> Duration {
+    let exponent = (attempt - 1).clamp(0, 16) as u32;
+    QUEUED_BACKOFF
+        .saturating_mul(2u32.pow(exponent))
+        .min(MAX_QUEUED_BACKOFF)
+}
+
+/// The shared `reqwest::Client`, sending with one provider's policy and
+/// breaker.
+#[derive(Clone)]
+pub struct ResilientClient {
+    provider: &'static str,
+    client: Client,
+    breaker: Arc<Breaker>,
+}
+
+impl ResilientClient {
+    pub fn get<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post<U: IntoUrl>(&self, url: U) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Sends `request`, retrying transient failures while the policy allows
+    /// and the body can be replayed. A request that timed out may have been
+    /// acted on, so only one safe to repeat, such as a `GET`, is retried
+    /// after a timeout; a text or a completion is not sent twice. Other
+    /// responses, errors included, are returned as they are for the caller
+    /// to interpret.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, String> {
+        let policy = self.breaker.policy;
+        let mut request = request.timeout(policy.timeout);
+        let idempotent = request
+            .try_clone()
+            .and_then(|r| r.build().ok())
+            .is_some_and(|r| r.method().is_idempotent());
+        let mut attempt = 0;
+        loop {
+            if let Err(wait) = self.breaker.admit(Instant::now()) {
+                return Err(format!(
+                    "{} is unavailable after repeated failures; retrying in {}s",
+                    self.provider,
+                    wait.as_secs_f64().ceil()
+                ));
+            }
+            let retry = (attempt < policy.retries)
+                .then(|| request.try_clone())
+                .flatten();
+
+            let (result, transient) = match request.send().await {
+                Ok(response) => {
+                    let transient = is_transient(response.status(), idempotent);
+                    (Ok(response), transient)
+                }
+                Err(e) if e.is_timeout() => (
+                    Err(format!(
+                        "no response within {}s",
+                        policy.timeout.as_secs_f64()
+                    )),
+                    idempotent,
+                ),
+                Err(e) => (Err(e.to_string()), e.is_connect()),
+            };
+            let outcome = match &result {
+                Ok(response) if response.status().is_server_error() => {
+                    Err(format!("responded {}", response.status()))
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(e.clone()),
+            };
+            self.breaker.record(outcome, Instant::now());
+
+            match (result, retry) {
+                (result, Some(next)) if transient => {
+                    sleep(jittered(policy.backoff, attempt)).await;
+                    request = next;
+                    attempt += 1;
+                    if let Err(e) = result {
+                        info!("retrying {} after: {}", self.provider, e);
+                    }
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+}
+
+/// Every provider's breaker, by name.
+pub struct Resilience {
+    client: Client,
+    config: ResilienceConfig,
+    breakers: Mutex<BTreeMap<&'static str, Arc<Breaker>>>,
+}
+
+impl Resilience {
+    /// Every provider with the default policy.
+    pub fn new(client: Client) -> Self {
+        Resilience {
+            client,
+            config: ResilienceConfig::default(),
+            breakers: Mutex::default(),
+        }
+    }
+
+    /// The client for `provider`, sharing its breaker with every other
+    /// client for it.
+    pub fn client(&self, provider: &'static str) -> ResilientClient {
+        let breaker = self
+            .breakers
+            .lock()
+            .expect("resilience lock poisoned")
+            .entry(provider)
+            .or_insert_with(|| {
+                let policy = self
+                    .config
+                    .policy(provider)
+                    .expect("resilience policies are validated on ignite");
+                Arc::new(Breaker::new(policy))
+            })
+            .clone();
+        ResilientClient {
+            provider,
+            client: self.client.clone(),
+            breaker,
+        }
+    }
+
+    /// The providers that have a client, alphabetically.
+    pub fn status(&self) -> Vec<ProviderStatus> {
+        let now = Instant::now();
+        self.breakers
+            .lock()
+            .expect("resilience lock poisoned")
+            .iter()
+            .map(|(provider, breaker)| breaker.status(provider, now))
+            .collect()
+    }
+}
+
+/// Manages [`Resilience`] over the shared HTTP client. Must be attached
+/// after the HTTP client stage and before any provider's.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Resilience", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<ResilienceConfig>("resilience")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => ResilienceConfig::default(),
+            Err(e) => {
+                error!("invalid resilience config: {}", e);
+                return Err(rocket);
+            }
+        };
+        let names = config.providers.keys().map(String::as_str);
+        if let Err(e) = std::iter::once("default")
+            .chain(names)
+            .try_for_each(|name| config.policy(name).map(drop))
+        {
+            error!("invalid resilience config: {}", e);
+            return Err(rocket);
+        }
+        let Some(client) = rocket.state::<Client>().cloned() else {
+            error!("resilience stage attached before the HTTP client");
+            return Err(rocket);
+        };
+        Ok(rocket.manage(Resilience {
+            config,
+            ..Resilience::new(client)
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queued_backoff_doubles_up_to_the_cap() {
+        assert_eq!(queued_backoff(1), Duration::from_secs(10));
+        assert_eq!(queued_backoff(2), Duration::from_secs(20));
+        assert_eq!(queued_backoff(4), Duration::from_secs(80));
+        assert_eq!(queued_backoff(20), MAX_QUEUED_BACKOFF);
+    }
+
+    #[test]
+    fn breakers_open_after_repeated_failures_and_close_after_a_trial() {
+        let config = ResilienceConfig {
+            defaults: PolicyConfig {
+                failure_threshold: Some(2),
+                ..PolicyConfig::default()
+            },
+            providers: HashMap::from([(
+                "llm".to_string(),
+                PolicyConfig {
+                    timeout_secs: Some(60.0),
+                    retries: Some(0),
+                    ..PolicyConfig::default()
+                },
+            )]),
+        };
+        let llm = config.policy("llm").unwrap();
+        assert_eq!(llm.timeout, Duration::from_secs(60));
+        assert_eq!((llm.retries, llm.failure_threshold), (0, 2));
+        assert_eq!(config.policy("tts").unwrap().retries, DEFAULT_RETRIES);
+
+        let breaker = Breaker::new(config.policy("weather").unwrap());
+        let start = Instant::now();
+        let failed = || Err("responded 503 Service Unavailable".to_string());
+        breaker.record(failed(), start);
+        assert!(breaker.admit(start).is_ok());
+        breaker.record(failed(), start);
+        assert_eq!(breaker.admit(start), Err(llm.open_for));
+        assert_eq!(breaker.status("weather", start).state, BreakerState::Open);
+
+        let later = start + llm.open_for;
+        assert!(breaker.admit(later).is_ok());
+        assert!(breaker.admit(later).is_err(), "only one trial at a time");
+        breaker.record(failed(), later);
+        assert_eq!(breaker.status("weather", later).trips, 2);
+
+        let much_later = later + llm.open_for;
+        assert!(breaker.admit(much_later).is_ok());
+        breaker.record(Ok(()), much_later);
+        let status = breaker.status("weather", much_later);
+        assert_eq!(status.state, BreakerState::Closed);
+        assert_eq!((status.requests, status.failures), (4, 3));
+
+        let wait = jittered(Duration::from_millis(200), 2);
+        assert!(wait >= Duration::from_millis(400) && wait <= Duration::from_millis(800));
+    }
+}
@@ -0,0 +1,127 @@
+//! Role-based access to the `/admin` routes. Each route takes a
+//! [`Permission`] guard naming what it needs; a configured API key has every
+//! permission, and a signed-in user has those of the [`Role`]s granted to
+//! them through `/admin/users/<id>/roles`.
+
+use std::marker::PhantomData;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::auth;
+use crate::error::GuardError;
+use crate::storage::{Role, Storage};
+use crate::users;
+
+/// Something an `/admin` route needs, granted to a fixed set of roles.
+pub trait Grant: Send + Sync + 'static {
+    const NAME: &'static str;
+    const ROLES: &'static [Role];
+}
+
+/// Reviewing submitted quotes.
+pub struct Moderate;
+
+/// Editing quotes, gifts, sticker packs and experiments.
+pub struct ManageContent;
+
+/// Inviting people and granting roles.
+pub struct ManageUsers;
+
+/// Backups, encryption keys, providers, quote sources, tenants and the
+/// audit log.
+pub struct ManageServer;
+
+impl Grant for Moderate {
+    const NAME: &'static str = "moderate";
+    const ROLES: &'static [Role] = &[Role::Admin, Role::Moderator];
+}
+
+impl Grant for ManageContent {
+    const NAME: &'static str = "manage_content";
+    const ROLES: &'static [Role] = &[Role::Admin, Role::Moderator];
+}
+
+impl Grant for ManageUsers {
+    const NAME: &'static str = "manage_users";
+    const ROLES: &'static [Role] = &[Role::Admin];
+}
+
+impl Grant for ManageServer {
+    const NAME: &'static str = "manage_server";
+    const ROLES: &'static [Role] = &[Role::Admin];
+}
+
+fn allows<P: Grant>(roles: &[Role]) -> bool {
+    roles.iter().any(|role| P::ROLES.contains(role))
+}
+
+/// The roles of the signed-in user, looked up once per request.
+struct UserRoles(Result<Vec<Role>, ()>);
+
+async fn user_roles<'r>(request: &'r Request<'_>, user_id: i64) -> &'r Result<Vec<Role>, ()> {
+    let cached = request
+        .local_cache_async(async {
+            let Outcome::Success(storage) = request.guard::<&Storage>().await else {
+                return UserRoles(Err(()));
+            };
+            UserRoles(storage.user_roles(user_id).await.map_err(|e| {
+                error!("failed to load roles of user {}: {}", user_id, e);
+            }))
+        })
+        .await;
+    &cached.0
+}
+
+fn fail<T>(request: &Request<'_>, status: Status, failure: String) -> Outcome<T, &'static str> {
+    request.local_cache(|| GuardError(Some(failure)));
+    Outcome::Error((status, "permission denied"))
+}
+
+/// Request guard for `/admin` routes needing `P`: succeeds for a configured
+/// API key (or any request in debug builds without keys), and for a
+/// signed-in user with a role granting `P`. Other signed-in users get a
+/// `403`, everyone else a `401`.
+pub struct Permission<P>(PhantomData<P>);
+
+#[rocket::async_trait]
+impl<'r, P: Grant> FromRequest<'r> for Permission<P> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (status, failure) = match auth::check_api_key(request) {
+            Ok(()) => return Outcome::Success(Permission(PhantomData)),
+            Err(failure) => failure,
+        };
+        let Some(user_id) = users::signed_in_id(request).await else {
+            return fail(request, status, failure.to_string());
+        };
+        match user_roles(request, user_id).await {
+            Ok(roles) if allows::<P>(roles) => Outcome::Success(Permission(PhantomData)),
+            Ok(_) => fail(
+                request,
+                Status::Forbidden,
+                format!("this needs the `{}` permission", P::NAME),
+            ),
+            Err(()) => fail(
+                request,
+                Status::InternalServerError,
+                "internal storage error".to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roles_grant_their_permissions() {
+        assert!(allows::<Moderate>(&[Role::Moderator]));
+        assert!(allows::<ManageContent>(&[Role::Moderator]));
+        assert!(!allows::<ManageUsers>(&[Role::Moderator]));
+        assert!(allows::<ManageServer>(&[Role::Moderator, Role::Admin]));
+        assert!(!allows::<Moderate>(&[]));
+    }
+}
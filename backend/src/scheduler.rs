@@ -0,0 +1,328 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::tokio::{self, sync::Notify};
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::audit::{self, Actor};
+use crate::auth::ApiKey;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::shared::{Channel, Local, SharedState};
+use crate::storage::{AuditAction, AuditEntity, NewMessage, Schedule, Storage, User};
+use crate::tenants::Tenants;
+use crate::timezones::{self, optional_zone, saved_zone, LocalOrInstant, LocalTime};
+use crate::users::{CoupleScope, Session};
+use crate::valentine::ValentineSubmission;
+use crate::validation::{FieldErrors, Valid, Validate};
+use crate::workers::{Wakers, Workers};
+
+/// Upper bound on how long the worker sleeps between checks, so reveals
+/// still happen if a wake-up is ever missed.
+const MAX_IDLE: Duration = Duration::from_secs(60);
+
+/// Shared handle used by the routes to nudge the reveal worker whenever a new
+/// schedule might be due sooner than the one it is currently waiting on.
+#[derive(Clone)]
+pub struct Scheduler {
+    wake: Wakers,
+    /// Unix milliseconds of a worker's last loop iteration, 0 until one
+    /// starts.
+    heartbeat: Arc<AtomicI64>,
+    shared: Arc<dyn SharedState>,
+}
+
+impl Scheduler {
+    /// Wakes the reveal worker on every instance, since any of them may be
+    /// the first to reveal the new schedule.
+    async fn nudge(&self) {
+        if let Err(e) = self.shared.publish(Channel::ScheduleWake, "").await {
+            error!("failed to publish schedule wake-up: {}", e);
+            self.wake_worker();
+        }
+    }
+
+    /// Wakes this instance's reveal workers.
+    fn wake_worker(&self) {
+        self.wake.wake_all();
+    }
+
+    /// Checks that the worker has started and looped recently. It wakes at
+    /// least every [`MAX_IDLE`], so a heartbeat older than twice that means
+    /// it has stalled or died.
+    pub fn worker_health(&self) -> Result<(), String> {
+        let last = self.heartbeat.load(Ordering::Relaxed);
+        if last == 0 {
+            return Err("reveal worker is not running".to_string());
+        }
+
+        let age = Utc::now().timestamp_millis() - last;
+        if age > 2 * MAX_IDLE.as_millis() as i64 {
+            return Err(format!("reveal worker last ran {}s ago", age / 1000));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ScheduleRequest {
+    #[serde(flatten)]
+    valentine: ValentineSubmission,
+    /// An instant with an offset, or a wall clock time such as
+    /// `2027-02-14T00:00:00` read in `timezone`.
+    reveal_at: LocalOrInstant,
+    /// IANA zone `reveal_at` is set in; the signed-in user's saved zone or
+    /// UTC by default.
+    timezone: Option<String>,
+}
+
+impl Validate for ScheduleRequest {
+    /// The message, when to reveal it and the zone, if one was named.
+    type Valid = (NewMessage, LocalOrInstant, Option<Tz>);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let zone = errors.check("timezone", optional_zone(self.timezone.as_deref()));
+        match errors.flattened(self.valentine) {
+            Some(message) => errors.finish((message, self.reveal_at, zone.flatten())),
+            None => Err(errors),
+        }
+    }
+}
+
+/// When `reveal_at` in `zone` is, if it is in the future.
+fn reveal_time(reveal_at: LocalOrInstant, zone: Tz) -> Result<DateTime<Utc>, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    if let Some(reveal_at) = errors.check("reveal_at", reveal_at.in_zone(zone)) {
+        if reveal_at <= Utc::now() {
+            errors.add("reveal_at", "`reveal_at` must be in the future");
+        }
+        return errors.finish(reveal_at);
+    }
+    Err(errors)
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct LockedSchedule {
+    id: i64,
+    reveal_at: DateTime<Utc>,
+    timezone: String,
+    /// `reveal_at` in `timezone` and, for the couple it was set for, in
+    /// each partner's saved zone.
+    local: Vec<LocalTime>,
+    seconds_remaining: i64,
+}
+
+impl LockedSchedule {
+    fn new(schedule: &Schedule, members: &[User], now: DateTime<Utc>) -> LockedSchedule {
+        LockedSchedule {
+            id: schedule.id,
+            reveal_at: schedule.reveal_at,
+            timezone: schedule.timezone.clone(),
+            local: timezones::local_times(schedule.reveal_at, &schedule.timezone, members),
+            seconds_remaining: (schedule.reveal_at - now).num_seconds(),
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct RevealedSchedule {
+    #[serde(flatten)]
+    schedule: Schedule,
+    local: Vec<LocalTime>,
+}
+
+#[derive(Responder)]
+enum ScheduleResponse {
+    #[response(status = 200)]
+    Revealed(Negotiated<RevealedSchedule>),
+    #[response(status = 423)]
+    Locked(Negotiated<LockedSchedule>),
+}
+
+#[utoipa::path(
+    tag = "schedules",
+    request_body = ScheduleRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = LockedSchedule),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field, `reveal_at` in the past or skipped by a clock change, or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/schedule", data = "<request>")]
+#[allow(clippy::too_many_arguments)]
+async fn create(
+    _key: ApiKey,
+    actor: Actor,
+    storage: &Storage,
+    scope: CoupleScope,
+    session: Option<Session>,
+    filter: &State<ContentFilter>,
+    scheduler: &State<Scheduler>,
+    request: Valid<ScheduleRequest>,
+) -> ApiResult<status::Created<Negotiated<LockedSchedule>>> {
+    let (message, reveal_at, zone) = request.into_inner();
+    let zone = zone.unwrap_or_else(|| session.map_or(Tz::UTC, |session| saved_zone(&session.0)));
+    let reveal_at = reveal_time(reveal_at, zone)?;
+    filter.screen_message(&message).await?;
+    let now = Utc::now();
+
+    let schedule = storage
+        .create_schedule(&message, reveal_at, zone.name(), scope.0)
+        .await
+        .map_err(internal_error)?;
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Create,
+        AuditEntity::Schedule,
+        schedule.id,
+        audit::created(&schedule),
+    )
+    .await;
+    scheduler.nudge().await;
+
+    let members = timezones::members(storage, schedule.couple_id).await?;
+    let location = uri!(get(schedule.id)).to_string();
+    Ok(status::Created::new(location)
+        .body(Negotiated(LockedSchedule::new(&schedule, &members, now))))
+}
+
+#[utoipa::path(
+    tag = "schedules",
+    responses(
+        (status = 200, description = "Revealed", body = RevealedSchedule),
+        (status = 423, description = "Still locked", body = LockedSchedule),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/schedule/<id>")]
+async fn get(storage: &Storage, scope: CoupleScope, id: i64) -> ApiResult<ScheduleResponse> {
+    let schedule = storage
+        .get_schedule(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no schedule with id {}", id)))?;
+
+    let members = timezones::members(storage, schedule.couple_id).await?;
+    let now = Utc::now();
+    if schedule.reveal_at > now {
+        return Ok(ScheduleResponse::Locked(Negotiated(LockedSchedule::new(
+            &schedule, &members, now,
+        ))));
+    }
+
+    let local = timezones::local_times(schedule.reveal_at, &schedule.timezone, &members);
+    Ok(ScheduleResponse::Revealed(Negotiated(RevealedSchedule {
+        schedule,
+        local,
+    })))
+}
+
+/// Background loop that flips schedules to revealed as their time comes,
+/// sleeping until the next pending reveal in between. Returns once `token`
+/// is cancelled.
+async fn run_worker(
+    storage: Storage,
+    wake: Arc<Notify>,
+    heartbeat: Arc<AtomicI64>,
+    token: CancellationToken,
+) {
+    let actor = Actor::system("scheduler");
+    loop {
+        heartbeat.store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+        match storage.reveal_due_schedules(Utc::now()).await {
+            Ok(revealed) => {
+                for schedule in revealed {
+                    info!("schedule {} revealed", schedule.id);
+                    let before = Schedule {
+                        revealed_at: None,
+                        ..schedule.clone()
+                    };
+                    audit::record(
+                        &storage,
+                        &actor,
+                        AuditAction::Update,
+                        AuditEntity::Schedule,
+                        schedule.id,
+                        audit::changes(&before, &schedule),
+                    )
+                    .await;
+                }
+            }
+            Err(e) => error!("failed to reveal schedules: {}", e),
+        }
+
+        let idle = match storage.next_pending_reveal().await {
+            Ok(Some(next)) => (next - Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+                .min(MAX_IDLE),
+            Ok(None) => MAX_IDLE,
+            Err(e) => {
+                error!("failed to look up next reveal: {}", e);
+                MAX_IDLE
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(idle) => {}
+            _ = wake.notified() => {}
+            _ = token.cancelled() => break,
+        }
+    }
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, get]
+}
+
+/// Manages the [`Scheduler`] handle and spawns a reveal worker for each
+/// database once the server has launched.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Scheduler", |rocket| async {
+        let wakers = Wakers::default();
+        let heartbeat = Arc::new(AtomicI64::new(0));
+        let shared = rocket
+            .state::<Arc<dyn SharedState>>()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Local::default()));
+        let woken = wakers.clone();
+        shared.subscribe(Channel::ScheduleWake, Box::new(move |_| woken.wake_all()));
+
+        rocket
+            .manage(Scheduler {
+                wake: wakers.clone(),
+                heartbeat: heartbeat.clone(),
+                shared,
+            })
+            .attach(AdHoc::on_liftoff("Scheduler Worker", move |rocket| {
+                Box::pin(async move {
+                    match (rocket.state::<Tenants>(), rocket.state::<Workers>()) {
+                        (Some(tenants), Some(workers)) => {
+                            let workers = workers.clone();
+                            tenants.for_each_database(move |storage| {
+                                let (wake, heartbeat) = (wakers.register(), heartbeat.clone());
+                                workers.spawn("scheduler worker", |token| {
+                                    run_worker(storage, wake, heartbeat, token)
+                                });
+                            });
+                        }
+                        _ => error!(
+                            "scheduler worker not started: storage or workers are unavailable"
+                        ),
+                    }
+                })
+            }))
+    })
+}
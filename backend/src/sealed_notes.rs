@@ -0,0 +1,442 @@
+//! End-to-end encrypted notes. Each device registers a public key and seals
+//! notes to a partner's key on the client; the server only keeps the
+//! ciphertext with the envelope needed to open it, checking that nonces
+//! and keys have the sizes their algorithm calls for.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::storage::{
+    self, Envelope, NewPublicKey, NewSealedNote, PublicKey, SealAlgorithm, SealedNote, Storage,
+};
+use crate::users::Session;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_LABEL_LEN: usize = 50;
+
+/// Largest ciphertext, once decoded. `body_limits` in Rocket.toml turns
+/// away bodies that could not fit it before they are read.
+const MAX_CIPHERTEXT_LEN: usize = 64 * 1024;
+
+/// Decodes base64 `value` in `field`, re-encoding it so equal bytes are
+/// always stored the same way.
+fn decode(field: &str, value: &str) -> Result<(Vec<u8>, String), String> {
+    let bytes = BASE64
+        .decode(value.trim())
+        .map_err(|_| format!("`{}` must be base64", field))?;
+    let canonical = BASE64.encode(&bytes);
+    Ok((bytes, canonical))
+}
+
+fn couple_of(session: &Session) -> ApiResult<i64> {
+    session
+        .0
+        .couple_id
+        .ok_or_else(|| error(Status::NotFound, "you are not in a couple yet"))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct PublicKeyRequest {
+    algorithm: SealAlgorithm,
+    /// Base64 of the raw key.
+    public_key: String,
+    /// Which device it is, e.g. `Phone`.
+    label: Option<String>,
+}
+
+impl Validate for PublicKeyRequest {
+    type Valid = NewPublicKey;
+
+    fn validate(self) -> Result<NewPublicKey, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let key = errors.check("public_key", decode("public_key", &self.public_key));
+        let (bytes, public_key) = key.unwrap_or_default();
+        if !public_key.is_empty() {
+            let len = self.algorithm.key_len();
+            if bytes.len() != len {
+                errors.add(
+                    "public_key",
+                    format!("`public_key` must be {} bytes for this algorithm", len),
+                );
+            } else if self.algorithm == SealAlgorithm::P256Aes256Gcm && bytes[0] != 0x04 {
+                errors.add(
+                    "public_key",
+                    "`public_key` must be an uncompressed P-256 point",
+                );
+            }
+        }
+        let label = self
+            .label
+            .map(|label| label.trim().to_string())
+            .filter(|label| !label.is_empty());
+        if let Some(label) = &label {
+            errors.text("label", label, MAX_LABEL_LEN);
+        }
+
+        let fingerprint = Sha256::digest(&bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        errors.finish(NewPublicKey {
+            algorithm: self.algorithm,
+            public_key,
+            fingerprint,
+            label,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct EnvelopeRequest {
+    algorithm: SealAlgorithm,
+    /// Base64.
+    nonce: String,
+    recipient_key_id: i64,
+    sender_key_id: Option<i64>,
+}
+
+impl Validate for EnvelopeRequest {
+    type Valid = Envelope;
+
+    fn validate(self) -> Result<Envelope, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let nonce = errors.check("nonce", decode("nonce", &self.nonce));
+        let len = self.algorithm.nonce_len();
+        if nonce.as_ref().is_some_and(|(bytes, _)| bytes.len() != len) {
+            errors.add(
+                "nonce",
+                format!("`nonce` must be {} bytes for this algorithm", len),
+            );
+        }
+        errors.finish(Envelope {
+            algorithm: self.algorithm,
+            nonce: nonce.map(|(_, nonce)| nonce).unwrap_or_default(),
+            recipient_key_id: self.recipient_key_id,
+            sender_key_id: self.sender_key_id,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SealedNoteRequest {
+    envelope: EnvelopeRequest,
+    /// Base64, tag included.
+    ciphertext: String,
+}
+
+impl Validate for SealedNoteRequest {
+    type Valid = (Envelope, String);
+
+    fn validate(self) -> Result<(Envelope, String), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let algorithm = self.envelope.algorithm;
+        let envelope = errors.nested("envelope", self.envelope);
+
+        let ciphertext = errors.check("ciphertext", decode("ciphertext", &self.ciphertext));
+        if let Some((bytes, _)) = &ciphertext {
+            if bytes.len() <= algorithm.tag_len() {
+                errors.add("ciphertext", "`ciphertext` is too short to hold a message");
+            } else if bytes.len() > MAX_CIPHERTEXT_LEN {
+                errors.add(
+                    "ciphertext",
+                    format!("`ciphertext` must be at most {} bytes", MAX_CIPHERTEXT_LEN),
+                );
+            }
+        }
+
+        let ciphertext = ciphertext.map(|(_, text)| text).unwrap_or_default();
+        match envelope {
+            Some(envelope) => errors.finish((envelope, ciphertext)),
+            None => Err(errors),
+        }
+    }
+}
+
+/// Checks that the envelope's keys may be used by `session` in `couple`:
+/// the recipient key must belong to one of the couple, the sender key to
+/// the sender, and both must be unrevoked and of the envelope's algorithm.
+async fn check_keys(
+    storage: &Storage,
+    session: &Session,
+    couple: i64,
+    envelope: &Envelope,
+) -> ApiResult<()> {
+    let usable = |key: &Option<PublicKey>| {
+        key.as_ref().is_some_and(|key| {
+            key.couple_id == Some(couple)
+                && key.revoked_at.is_none()
+                && key.algorithm == envelope.algorithm
+        })
+    };
+
+    let mut errors = FieldErrors::new();
+    let recipient = storage
+        .get_public_key(envelope.recipient_key_id)
+        .await
+        .map_err(internal_error)?;
+    if !usable(&recipient) {
+        errors.add(
+            "envelope.recipient_key_id",
+            "`recipient_key_id` is not an active key in your couple for this algorithm",
+        );
+    }
+    if let Some(id) = envelope.sender_key_id {
+        let sender = storage.get_public_key(id).await.map_err(internal_error)?;
+        if !usable(&sender) || sender.is_some_and(|key| key.user_id != session.0.id) {
+            errors.add(
+                "envelope.sender_key_id",
+                "`sender_key_id` is not one of your active keys for this algorithm",
+            );
+        }
+    }
+    errors.finish(()).map_err(Into::into)
+}
+
+/// Registers a public key for the signed-in user's device.
+#[utoipa::path(
+    tag = "sealed",
+    request_body = PublicKeyRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = PublicKey),
+        (status = 401, body = ErrorResponse),
+        (status = 409, description = "Key already registered", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/keys", data = "<request>")]
+async fn register_key(
+    session: Session,
+    storage: &Storage,
+    request: Valid<PublicKeyRequest>,
+) -> ApiResult<status::Created<Negotiated<PublicKey>>> {
+    match storage
+        .create_public_key(session.0.id, &request.into_inner())
+        .await
+    {
+        Ok(key) => Ok(status::Created::new(uri!(keys).to_string()).body(Negotiated(key))),
+        Err(e) if storage::is_unique_violation(&e) => Err(error(
+            Status::Conflict,
+            "you have already registered this key",
+        )),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// The active keys of the signed-in user and their partner, to seal notes
+/// to and to check fingerprints against.
+#[utoipa::path(
+    tag = "sealed",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<PublicKey>),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[get("/api/keys")]
+async fn keys(session: Session, storage: &Storage) -> ApiResult<Negotiated<Vec<PublicKey>>> {
+    storage
+        .list_public_keys(session.0.id)
+        .await
+        .map(Negotiated)
+        .map_err(internal_error)
+}
+
+/// Revokes one of the signed-in user's keys, e.g. for a lost device. Notes
+/// already sealed to it are kept.
+#[utoipa::path(
+    tag = "sealed",
+    params(("id" = i64, Path)),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = PublicKey),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "No such active key of yours", body = ErrorResponse),
+    )
+)]
+#[delete("/api/keys/<id>")]
+async fn revoke_key(
+    session: Session,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<PublicKey>> {
+    storage
+        .revoke_public_key(id, session.0.id)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, "no such active key of yours"))
+}
+
+/// Stores a note sealed to a key of the signed-in user's couple.
+#[utoipa::path(
+    tag = "sealed",
+    request_body = SealedNoteRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = SealedNote),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+        (status = 413, description = "Body over the `body_limits` cap", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/sealed-notes", data = "<request>")]
+async fn create(
+    session: Session,
+    storage: &Storage,
+    request: Valid<SealedNoteRequest>,
+) -> ApiResult<status::Created<Negotiated<SealedNote>>> {
+    let couple = couple_of(&session)?;
+    let (envelope, ciphertext) = request.into_inner();
+    check_keys(storage, &session, couple, &envelope).await?;
+
+    let note = storage
+        .create_sealed_note(&NewSealedNote {
+            couple_id: couple,
+            sender_id: session.0.id,
+            envelope,
+            ciphertext,
+        })
+        .await
+        .map_err(internal_error)?;
+    let location = uri!(get(note.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(note)))
+}
+
+/// The notes the signed-in user sent or can open, newest first.
+#[utoipa::path(
+    tag = "sealed",
+    params(("page" = Option<i64>, Query), ("per_page" = Option<i64>, Query)),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Page<SealedNote>),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/sealed-notes?<page>&<per_page>")]
+async fn list(
+    session: Session,
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> ApiResult<Negotiated<Page<SealedNote>>> {
+    let couple = couple_of(&session)?;
+    let (page, per_page, offset) = paginate(page, per_page);
+    let (items, total) = storage
+        .list_sealed_notes(couple, session.0.id, per_page, offset)
+        .await
+        .map_err(internal_error)?;
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Note `id`, if the signed-in user sent it or it was sealed to them.
+async fn find(storage: &Storage, session: &Session, id: i64) -> ApiResult<SealedNote> {
+    storage
+        .get_sealed_note(id)
+        .await
+        .map_err(internal_error)?
+        .filter(|note| {
+            Some(note.couple_id) == session.0.couple_id
+                && (note.sender_id == session.0.id || note.recipient_id == session.0.id)
+        })
+        .ok_or_else(|| error(Status::NotFound, "sealed note not found"))
+}
+
+#[utoipa::path(
+    tag = "sealed",
+    params(("id" = i64, Path)),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = SealedNote),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/sealed-notes/<id>")]
+async fn get(session: Session, storage: &Storage, id: i64) -> ApiResult<Negotiated<SealedNote>> {
+    find(storage, &session, id).await.map(Negotiated)
+}
+
+/// Deletes a note the signed-in user sent or received.
+#[utoipa::path(
+    tag = "sealed",
+    params(("id" = i64, Path)),
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/api/sealed-notes/<id>")]
+async fn delete(session: Session, storage: &Storage, id: i64) -> ApiResult<status::NoContent> {
+    let note = find(storage, &session, id).await?;
+    storage
+        .delete_sealed_note(note.id)
+        .await
+        .map_err(internal_error)?;
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![register_key, keys, revoke_key, create, list, get, delete]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(nonce_len: usize, ciphertext_len: usize) -> SealedNoteRequest {
+        SealedNoteRequest {
+            envelope: EnvelopeRequest {
+                algorithm: SealAlgorithm::X25519XSalsa20Poly1305,
+                nonce: BASE64.encode(vec![7; nonce_len]),
+                recipient_key_id: 1,
+                sender_key_id: None,
+            },
+            ciphertext: BASE64.encode(vec![9; ciphertext_len]),
+        }
+    }
+
+    #[test]
+    fn envelopes_need_sizes_their_algorithm_fixes() {
+        let (envelope, ciphertext) = note(24, 40).validate().unwrap();
+        assert_eq!(envelope.nonce, BASE64.encode([7; 24]));
+        assert_eq!(ciphertext, BASE64.encode([9; 40]));
+
+        let errors = note(12, 40).validate().unwrap_err().to_string();
+        assert!(errors.contains("`nonce` must be 24 bytes"), "{}", errors);
+        assert!(note(24, 16).validate().is_err());
+        assert!(note(24, MAX_CIPHERTEXT_LEN + 1).validate().is_err());
+
+        let key = PublicKeyRequest {
+            algorithm: SealAlgorithm::P256Aes256Gcm,
+            public_key: BASE64.encode([4; 65]),
+            label: Some(" Phone ".to_string()),
+        };
+        let key = key.validate().unwrap();
+        assert_eq!(key.fingerprint.len(), 64);
+        assert_eq!(key.label.as_deref(), Some("Phone"));
+        let compressed = PublicKeyRequest {
+            algorithm: SealAlgorithm::P256Aes256Gcm,
+            public_key: BASE64.encode([2; 65]),
+            label: None,
+        };
+        assert!(compressed.validate().is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! `valentine-backend seed`: loads a starter data set bundled into the
+//! binary, so a fresh deployment has something to show. It adds the
+//! fixture quotes and their translations, the sample gift catalog and the
+//! bundled quizzes, skipping whatever is already there, so it is safe to
+//! run on every deploy.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket::tokio::fs;
+use serde::Deserialize;
+
+use crate::admin::quotes::QuoteRequest;
+use crate::gifts;
+use crate::i18n;
+use crate::quiz;
+use crate::storage::{self, Storage};
+use crate::validation::Validate;
+
+const QUOTES: &str = include_str!("../fixtures/quotes.toml");
+const GIFTS: &str = include_str!("../gifts.toml");
+/// Written to `quizzes_dir` as `<id>.json`.
+const QUIZZES: &[(&str, &str)] = &[
+    (
+        "compatibility",
+        include_str!("../quizzes/compatibility.json"),
+    ),
+    (
+        "love-languages",
+        include_str!("../fixtures/quizzes/love-languages.json"),
+    ),
+];
+
+#[derive(Deserialize)]
+struct SeedQuote {
+    #[serde(flatten)]
+    quote: QuoteRequest,
+    /// Translated text by language code.
+    #[serde(default)]
+    translations: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct QuotesFile {
+    quotes: Vec<SeedQuote>,
+}
+
+/// How many fixtures of one kind were added, and how many were already
+/// there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Seeded {
+    pub added: usize,
+    pub present: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeedReport {
+    pub quotes: Seeded,
+    /// Translations stored, overwriting any earlier text for the same
+    /// quote and language.
+    pub translations: usize,
+    pub gifts: Seeded,
+    pub quizzes: Seeded,
+}
+
+/// A translation of the quote whose English text is `source`.
+struct Translation {
+    source: String,
+    lang: String,
+    text: String,
+}
+
+/// The fixture quotes, validated, and their translations.
+fn fixture_quotes() -> Result<(Vec<storage::NewQuote>, Vec<Translation>), String> {
+    let file: QuotesFile = Figment::from(Toml::string(QUOTES))
+        .extract()
+        .map_err(|e| format!("invalid quote fixtures: {}", e))?;
+
+    let mut quotes = Vec::with_capacity(file.quotes.len());
+    let mut translations = Vec::new();
+    for (i, seed) in file.quotes.into_iter().enumerate() {
+        let quote = seed
+            .quote
+            .validate()
+            .map_err(|e| format!("quote fixture {}: {}", i, e))?;
+        for (lang, text) in seed.translations {
+            if i18n::normalize_lang(&lang).as_deref() != Some(lang.as_str()) {
+                return Err(format!(
+                    "quote fixture {}: `{}` is not a language code",
+                    i, lang
+                ));
+            }
+            translations.push(Translation {
+                source: quote.text.clone(),
+                lang,
+                text: text.trim().to_string(),
+            });
+        }
+        quotes.push(quote);
+    }
+    Ok((quotes, translations))
+}
+
+async fn seed_quotes(storage: &Storage, report: &mut SeedReport) -> Result<(), String> {
+    let (quotes, translations) = fixture_quotes()?;
+    let texts: Vec<String> = quotes.iter().map(|quote| quote.text.clone()).collect();
+    let existing = storage
+        .existing_quote_texts(&texts)
+        .await
+        .map_err(|e| format!("failed to look up quotes: {}", e))?;
+    let new: Vec<storage::NewQuote> = quotes
+        .into_iter()
+        .filter(|quote| !existing.contains(&quote.text))
+        .collect();
+    storage
+        .import_quotes(&new)
+        .await
+        .map_err(|e| format!("failed to add quotes: {}", e))?;
+    report.quotes = Seeded {
+        added: new.len(),
+        present: existing.len(),
+    };
+
+    for translation in &translations {
+        let stored = storage
+            .upsert_translation(&translation.source, &translation.lang, &translation.text)
+            .await
+            .map_err(|e| format!("failed to store {} translation: {}", translation.lang, e))?;
+        if stored {
+            report.translations += 1;
+        }
+    }
+    Ok(())
+}
+
+async fn seed_gifts(storage: &Storage, report: &mut SeedReport) -> Result<(), String> {
+    let gifts = gifts::parse_catalog(Toml::string(GIFTS), "gifts.toml")?;
+    let names: Vec<String> = gifts.iter().map(|gift| gift.name.clone()).collect();
+    let existing = storage
+        .existing_gift_names(&names)
+        .await
+        .map_err(|e| format!("failed to look up gifts: {}", e))?;
+    let new: Vec<storage::NewGift> = gifts
+        .into_iter()
+        .filter(|gift| !existing.contains(&gift.name))
+        .collect();
+    storage
+        .import_gifts(&new)
+        .await
+        .map_err(|e| format!("failed to add gifts: {}", e))?;
+    report.gifts = Seeded {
+        added: new.len(),
+        present: existing.len(),
+    };
+    Ok(())
+}
+
+/// Writes each bundled quiz into `dir` unless a file of that name exists,
+/// even an edited one. The server reads them at its next start.
+async fn seed_quizzes(dir: &Path, report: &mut SeedReport) -> Result<(), String> {
+    fs::create_dir_all(dir)
+        .await
+        .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+    for (id, contents) in QUIZZES {
+        quiz::check_quiz(contents).map_err(|e| format!("invalid quiz fixture {}: {}", id, e))?;
+        let path = dir.join(format!("{}.json", id));
+        match fs::try_exists(&path).await {
+            Ok(true) => report.quizzes.present += 1,
+            Ok(false) => {
+                fs::write(&path, contents)
+                    .await
+                    .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+                report.quizzes.added += 1;
+            }
+            Err(e) => return Err(format!("failed to check {}: {}", path.display(), e)),
+        }
+    }
+    Ok(())
+}
+
+/// Migrates the database `figment` names, gives it the default quotes if
+/// it has none, and adds the fixtures.
+pub async fn run(figment: &Figment) -> Result<SeedReport, String> {
+    let url = storage::database_url(figment);
+    let storage = Storage::connect(&url)
+        .await
+        .map_err(|e| format!("failed to open database {}: {}", url, e))?;
+    storage
+        .migrate()
+        .await
+        .map_err(|e| format!("failed to run migrations: {}", e))?;
+    storage
+        .seed_default_quotes()
+        .await
+        .map_err(|e| format!("failed to seed quotes: {}", e))?;
+
+    let mut report = SeedReport::default();
+    seed_quotes(&storage, &mut report).await?;
+    seed_gifts(&storage, &mut report).await?;
+    let quizzes_dir = figment
+        .extract_inner::<PathBuf>("quizzes_dir")
+        .unwrap_or_else(|_| PathBuf::from(quiz::DEFAULT_QUIZZES_DIR));
+    seed_quizzes(&quizzes_dir, &mut report).await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_are_valid() {
+        let (quotes, translations) = fixture_quotes().unwrap();
+        assert!(quotes.len() >= 10);
+        assert!(translations.iter().any(|t| t.lang == "fr"));
+        gifts::parse_catalog(Toml::string(GIFTS), "gifts.toml").unwrap();
+        for (id, contents) in QUIZZES {
+            quiz::check_quiz(contents).unwrap_or_else(|e| panic!("{}: {}", id, e));
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use rocket::fairing::Fairing;
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket::tokio::task;
+use rocket::{Route, State};
+use rocket_dyn_templates::Template;
+use serde::Serialize;
+
+use crate::audit::{self, Actor};
+use crate::auth::ApiKey;
+use crate::cards::pdf::{self, CardFont, PaperSize, PrintCard};
+use crate::cards::qr::{self, QrFormat, QrModules};
+use crate::config::PublicUrl;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::messages;
+use crate::negotiate::Negotiated;
+use crate::stickers;
+use crate::storage::{self, AuditAction, AuditEntity, Message, Storage};
+use crate::tenants::CurrentTenant;
+use crate::themes::{self, Theme, Themes};
+use crate::tokens;
+use crate::uploads;
+use crate::users::CoupleScope;
+use crate::valentine::ValentineSubmission;
+use crate::validation::Valid;
+
+const SLUG_LEN: usize = 6;
+
+/// Attempts before giving up on finding an unused slug.
+const SLUG_ATTEMPTS: usize = 5;
+
+/// Open Graph descriptions are truncated by most unfurlers well before this.
+const DESCRIPTION_LEN: usize = 200;
+
+/// `templates/share.html.tera`.
+const SHARE_TEMPLATE: &str = "share";
+
+fn link(public_url: &PublicUrl, slug: &str) -> String {
+    public_url.absolute(&uri!(view(slug, _)).to_string())
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ShareResponse {
+    slug: String,
+    url: String,
+    #[serde(flatten)]
+    message: Message,
+}
+
+/// Stores a valentine behind a short slug. Shared messages are private to
+/// whoever has the link, so unlike `POST /api/valentine` they are not
+/// published to the notes feed.
+#[utoipa::path(
+    tag = "shares",
+    request_body = ValentineSubmission,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = ShareResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/valentine/share", data = "<submission>")]
+async fn share(
+    _key: ApiKey,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    public_url: &PublicUrl,
+    actor: Actor,
+    scope: CoupleScope,
+    submission: Valid<ValentineSubmission>,
+) -> ApiResult<status::Created<Negotiated<ShareResponse>>> {
+    let mut new_message = submission.into_inner();
+    filter.screen_message(&new_message).await?;
+    new_message.couple_id = scope.0;
+    if let Some(url) = &new_message.image_url {
+        new_message.image_url = Some(uploads::resolve_image_url(storage, public_url, url).await?);
+    }
+    if let Some(id) = new_message.sticker_id {
+        stickers::require_sticker(storage, "sticker_id", id).await?;
+    }
+
+    for _ in 0..SLUG_ATTEMPTS {
+        let slug = tokens::random_slug(SLUG_LEN);
+        match storage.create_share(&new_message, &slug).await {
+            Ok(message) => {
+                audit::record(
+                    storage,
+                    &actor,
+                    AuditAction::Create,
+                    AuditEntity::Message,
+                    message.id,
+                    audit::created(&message),
+                )
+                .await;
+                let url = link(public_url, &slug);
+                return Ok(
+                    status::Created::new(url.clone()).body(Negotiated(ShareResponse {
+                        slug,
+                        url,
+                        message,
+                    })),
+                );
+            }
+            Err(e) if storage::is_unique_violation(&e) => continue,
+            Err(e) => return Err(internal_error(e)),
+        }
+    }
+
+    Err(error(
+        Status::ServiceUnavailable,
+        "could not allocate a share slug, try again",
+    ))
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// CSS values for the share page, from the theme's palette.
+#[derive(Serialize)]
+struct PageStyle {
+    font: &'static str,
+    page: String,
+    card: String,
+    title: String,
+    text: String,
+    accent: String,
+}
+
+impl PageStyle {
+    fn new(theme: &Theme) -> Self {
+        let palette = &theme.palette;
+        PageStyle {
+            font: match theme.font {
+                CardFont::Serif => "Georgia, serif",
+                CardFont::Sans => "Helvetica, Arial, sans-serif",
+            },
+            page: themes::hex(palette.bottom),
+            card: themes::hex(palette.top),
+            title: themes::hex(palette.title),
+            text: themes::hex(palette.text),
+            accent: themes::hex(palette.accent),
+        }
+    }
+}
+
+/// What the share template is rendered with; Tera escapes every value.
+#[derive(Serialize)]
+struct SharePage<'a> {
+    title: String,
+    description: String,
+    url: &'a str,
+    image_url: Option<&'a str>,
+    /// The message, split so the template can join the lines with `<br>`.
+    lines: Vec<&'a str>,
+    sender: &'a str,
+    style: PageStyle,
+}
+
+impl<'a> SharePage<'a> {
+    fn new(message: &'a Message, url: &'a str, theme: &Theme) -> Self {
+        let title = match &message.recipient {
+            Some(to) => format!("A valentine for {} from {}", to, message.sender),
+            None => format!("A valentine from {}", message.sender),
+        };
+        SharePage {
+            title,
+            description: truncate(&message.message, DESCRIPTION_LEN),
+            url,
+            image_url: message.image_url.as_deref(),
+            lines: message.message.lines().collect(),
+            sender: &message.sender,
+            style: PageStyle::new(theme),
+        }
+    }
+}
+
+/// The valentine as a themed page with Open Graph and Twitter tags, so the
+/// link unfurls in chat apps and opens without the frontend.
+#[utoipa::path(
+    tag = "shares",
+    params(
+        ("theme" = Option<String>, Query, description = "A theme id from `GET /api/themes`; the tenant's theme, or hearts, by default"),
+    ),
+    responses(
+        (status = 200, description = "Page with Open Graph tags", content_type = "text/html", body = String),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/v/<slug>?<theme>")]
+async fn view(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    themes: &State<Themes>,
+    tenant: CurrentTenant<'_>,
+    slug: &str,
+    theme: Option<&str>,
+) -> ApiResult<Template> {
+    let theme = themes
+        .get_or_default(theme.or(tenant.theme()))
+        .map_err(|e| error(Status::BadRequest, e))?;
+    let message = storage
+        .get_shared_message(slug)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, "no such valentine"))?;
+
+    let url = link(public_url, slug);
+    Ok(Template::render(
+        SHARE_TEMPLATE,
+        SharePage::new(&message, &url, &theme),
+    ))
+}
+
+/// A QR code for the valentine's share link, to print inside a physical
+/// card. SVG scales to any print size; PNG is exactly `size` pixels square.
+#[utoipa::path(
+    tag = "shares",
+    params(
+        ("format" = Option<QrFormat>, Query),
+        ("size" = Option<u32>, Query, description = "Width and height in pixels, 128 to 2048 (default 512)"),
+        ("modules" = Option<QrModules>, Query),
+    ),
+    responses(
+        (status = 200, content_type = "image/png", body = Vec<u8>),
+        (status = 200, content_type = "image/svg+xml", body = String),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/<slug>/qr?<format>&<size>&<modules>")]
+async fn qr_code(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    slug: &str,
+    format: Option<&str>,
+    size: Option<u32>,
+    modules: Option<&str>,
+) -> ApiResult<(ContentType, Vec<u8>)> {
+    let format = format
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(QrFormat::Png);
+    let modules = modules
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(QrModules::Square);
+    let size = size.unwrap_or(qr::DEFAULT_SIZE);
+    if !(qr::MIN_SIZE..=qr::MAX_SIZE).contains(&size) {
+        return Err(error(
+            Status::BadRequest,
+            format!(
+                "`size` must be between {} and {}",
+                qr::MIN_SIZE,
+                qr::MAX_SIZE
+            ),
+        ));
+    }
+
+    storage
+        .get_shared_message(slug)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, "no such valentine"))?;
+    let url = link(public_url, slug);
+
+    let rendered = task::spawn_blocking(move || match format {
+        QrFormat::Png => qr::render_png(&url, size, modules).map(|png| (ContentType::PNG, png)),
+        QrFormat::Svg => {
+            qr::render_svg(&url, size, modules).map(|svg| (ContentType::SVG, svg.into_bytes()))
+        }
+    })
+    .await
+    .map_err(|e| {
+        error(
+            Status::InternalServerError,
+            format!("QR rendering panicked: {}", e),
+        )
+    })?;
+    rendered.map_err(|e| error(Status::InternalServerError, e))
+}
+
+/// A foldable card to print on one side of a sheet: fold it in half top to
+/// bottom, then in half again, with the front cover outermost.
+#[utoipa::path(
+    tag = "shares",
+    params(
+        ("paper" = Option<PaperSize>, Query),
+        ("font" = Option<CardFont>, Query, description = "Overrides the theme's font; `serif` by default"),
+        ("theme" = Option<String>, Query, description = "A theme id from `GET /api/themes`; the tenant's theme, or rose on white, by default"),
+    ),
+    responses(
+        (status = 200, content_type = "application/pdf", body = Vec<u8>),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/<slug>/pdf?<paper>&<font>&<theme>")]
+async fn pdf_card(
+    storage: &Storage,
+    themes: &State<Themes>,
+    tenant: CurrentTenant<'_>,
+    slug: &str,
+    paper: Option<&str>,
+    font: Option<&str>,
+    theme: Option<&str>,
+) -> ApiResult<(ContentType, Vec<u8>)> {
+    let paper = paper
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?
+        .unwrap_or(PaperSize::A4);
+    let font = font
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))?;
+    let theme = theme
+        .or(tenant.theme())
+        .map(|theme| themes.get(theme))
+        .transpose()
+        .map_err(|e| error(Status::BadRequest, e))?;
+    let font = font
+        .or(theme.as_ref().map(|theme| theme.font))
+        .unwrap_or(CardFont::Serif);
+
+    let message = storage
+        .get_shared_message(slug)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, "no such valentine"))?;
+
+    let rendered = task::spawn_blocking(move || {
+        pdf::render_pdf(&PrintCard {
+            recipient: message.recipient.as_deref(),
+            sender: &message.sender,
+            message: &message.message,
+            paper,
+            font,
+            theme: theme.as_deref(),
+        })
+    })
+    .await
+    .map_err(|e| {
+        error(
+            Status::InternalServerError,
+            format!("PDF rendering panicked: {}", e),
+        )
+    })?;
+    rendered
+        .map(|pdf| (ContentType::PDF, pdf))
+        .map_err(|e| error(Status::InternalServerError, e))
+}
+
+/// Loads the Tera templates from `template_dir`, escaped like the rest of
+/// the generated HTML; Tera's own escaping also turns `/` into `&#x2F;`.
+pub fn templates() -> impl Fairing {
+    Template::custom(|engines| engines.tera.set_escape_fn(messages::escape_html))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![share, view, qr_code, pdf_card]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use rocket_dyn_templates::tera::{Context, Tera};
+
+    #[test]
+    fn page_escapes_message_into_meta_tags() {
+        let message = Message {
+            id: 1,
+            message: "Roses are <red>\n& so are you".to_string(),
+            sender: "Sam \"The Romantic\"".to_string(),
+            recipient: None,
+            image_url: None,
+            created_at: Utc::now(),
+            deleted_at: None,
+            couple_id: None,
+            sticker_id: None,
+        };
+
+        let mut tera = Tera::default();
+        tera.autoescape_on(vec![".html.tera"]);
+        tera.set_escape_fn(messages::escape_html);
+        tera.add_raw_template(
+            "share.html.tera",
+            include_str!("../templates/share.html.tera"),
+        )
+        .unwrap();
+        let themes = Themes::bundled();
+        let theme = themes.get_or_default(None).unwrap();
+        let context = SharePage::new(&message, "https://example.com/v/abc234", &theme);
+        let page = tera
+            .render(
+                "share.html.tera",
+                &Context::from_serialize(context).unwrap(),
+            )
+            .unwrap();
+        assert!(page.contains(
+            r#"<meta property="og:title" content="A valentine from Sam &quot;The Romantic&quot;">"#
+        ));
+        assert!(page.contains(r#"<meta property="og:url" content="https://example.com/v/abc234">"#));
+        assert!(page.contains("Roses are &lt;red&gt;<br>&amp; so are you"));
+    }
+
+    #[test]
+    fn descriptions_are_truncated_on_char_boundaries() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("héllo wörld", 6), "héllo…");
+    }
+}
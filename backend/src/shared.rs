@@ -0,0 +1,421 @@
+//! State that several instances behind a load balancer have to agree on:
+//! rate-limit buckets, the notes and chat feeds and reveal-worker wake-ups.
+//! Features reach it through the [`SharedState`] trait. With
+//! `shared_state.backend = "redis"` buckets live in Redis and notes, chat
+//! events and wake-ups go out over Redis pub/sub to every instance; the
+//! default keeps all of it in process, which is only right for a single
+//! instance.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use rocket::fairing::AdHoc;
+use rocket::futures::StreamExt;
+use rocket::tokio;
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::rate_limit::Limit;
+use crate::workers::Workers;
+
+/// How long the relay waits before resubscribing after losing Redis.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// In-process buckets are pruned once the table grows past this many
+/// entries.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Token bucket in a Redis hash, refilled from Redis' own clock so every
+/// instance sees the same time. Returns the seconds to wait as a string
+/// (Lua numbers would be truncated to integers), `"0"` when a token was
+/// taken. Idle buckets expire once they would have refilled anyway.
+const TAKE_TOKEN: &str = r#"
+local capacity = tonumber(ARGV[1])
+local rate = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) + tonumber(time[2]) / 1000000
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or capacity
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * rate)
+local wait = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+else
+  wait = (1 - tokens) / rate
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / rate * 1000) + 1000)
+return tostring(wait)
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    Local,
+    Redis,
+}
+
+fn default_backend() -> Backend {
+    Backend::Local
+}
+
+fn default_url() -> String {
+    "redis://127.0.0.1:6379/".to_string()
+}
+
+fn default_prefix() -> String {
+    "valentine".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct SharedStateConfig {
+    #[serde(default = "default_backend")]
+    backend: Backend,
+    #[serde(default = "default_url")]
+    url: String,
+    /// Prepended to every key and channel, so deployments can share a server.
+    #[serde(default = "default_prefix")]
+    prefix: String,
+}
+
+/// Pub/sub channels relayed between instances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// A JSON-encoded note for the notes feed.
+    Notes,
+    /// A schedule was created; reveal workers should recompute their sleep.
+    ScheduleWake,
+    /// A JSON-encoded event for a couple's chat.
+    Chat,
+}
+
+impl Channel {
+    const ALL: [Channel; 3] = [Channel::Notes, Channel::ScheduleWake, Channel::Chat];
+
+    fn name(self) -> &'static str {
+        match self {
+            Channel::Notes => "notes",
+            Channel::ScheduleWake => "schedules:wake",
+            Channel::Chat => "chat",
+        }
+    }
+}
+
+/// Called with each payload published on a channel.
+pub type Handler = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Where shared state lives. Managed by [`stage`] as an
+/// `Arc<dyn SharedState>`.
+#[rocket::async_trait]
+pub trait SharedState: Send + Sync + 'static {
+    /// Takes one token from the bucket `name`, or returns how long until one
+    /// is available. Errors mean the buckets cannot be reached.
+    async fn take_token(&self, name: &str, limit: &Limit) -> Result<Result<(), Duration>, String>;
+
+    /// Sends `payload` to the handlers subscribed to `channel` on every
+    /// instance, this one included.
+    async fn publish(&self, channel: Channel, payload: &str) -> Result<(), String>;
+
+    /// Calls `handler` with every payload published on `channel` from now
+    /// on.
+    fn subscribe(&self, channel: Channel, handler: Handler);
+
+    /// Whether the state is private to this instance, with nothing to
+    /// [`ping`](SharedState::ping).
+    fn in_process(&self) -> bool;
+
+    async fn ping(&self) -> Result<(), String>;
+}
+
+/// The handlers of each channel on this instance.
+#[derive(Default)]
+struct Subscribers(Mutex<Vec<(Channel, Handler)>>);
+
+impl Subscribers {
+    fn add(&self, channel: Channel, handler: Handler) {
+        self.0
+            .lock()
+            .expect("subscribers lock poisoned")
+            .push((channel, handler));
+    }
+
+    fn deliver(&self, channel: Channel, payload: &str) {
+        let subscribers = self.0.lock().expect("subscribers lock poisoned");
+        for (_, handler) in subscribers.iter().filter(|(c, _)| *c == channel) {
+            handler(payload);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &Limit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.capacity,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_second).min(limit.capacity);
+        self.updated = now;
+    }
+
+    /// Takes one token, or returns how long until one is available.
+    fn take(&mut self, limit: &Limit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let missing = 1.0 - self.tokens;
+        Err(Duration::try_from_secs_f64(missing / limit.refill_per_second).unwrap_or(Duration::MAX))
+    }
+}
+
+/// Shared state kept in process, private to this instance.
+#[derive(Default)]
+pub struct Local {
+    /// Each bucket with the limit it was last taken under.
+    buckets: Mutex<HashMap<String, (Limit, Bucket)>>,
+    subscribers: Subscribers,
+}
+
+impl Local {
+    /// [`SharedState::take_token`] as of `now`.
+    pub fn take_token_at(&self, name: &str, limit: &Limit, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("bucket lock poisoned");
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, (limit, bucket)| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.capacity
+            });
+        }
+
+        let (taken_under, bucket) = buckets
+            .entry(name.to_string())
+            .or_insert_with(|| (*limit, Bucket::full(limit, now)));
+        *taken_under = *limit;
+        bucket.take(limit, now)
+    }
+}
+
+#[rocket::async_trait]
+impl SharedState for Local {
+    async fn take_token(&self, name: &str, limit: &Limit) -> Result<Result<(), Duration>, String> {
+        Ok(self.take_token_at(name, limit, Instant::now()))
+    }
+
+    async fn publish(&self, channel: Channel, payload: &str) -> Result<(), String> {
+        self.subscribers.deliver(channel, payload);
+        Ok(())
+    }
+
+    fn subscribe(&self, channel: Channel, handler: Handler) {
+        self.subscribers.add(channel, handler);
+    }
+
+    fn in_process(&self) -> bool {
+        true
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Shared state in Redis: a connection plus the key prefix, and the
+/// handlers the relay hands this instance's messages to.
+struct Redis {
+    client: redis::Client,
+    connection: ConnectionManager,
+    prefix: String,
+    subscribers: Subscribers,
+}
+
+#[rocket::async_trait]
+impl SharedState for Redis {
+    async fn take_token(&self, name: &str, limit: &Limit) -> Result<Result<(), Duration>, String> {
+        let mut connection = self.connection.clone();
+        let wait: String = redis::Script::new(TAKE_TOKEN)
+            .key(self.key(name))
+            .arg(limit.capacity)
+            .arg(limit.refill_per_second)
+            .invoke_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+        let wait: f64 = wait
+            .parse()
+            .map_err(|_| format!("unexpected token bucket reply `{}`", wait))?;
+
+        if wait > 0.0 {
+            Ok(Err(
+                Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX)
+            ))
+        } else {
+            Ok(Ok(()))
+        }
+    }
+
+    async fn publish(&self, channel: Channel, payload: &str) -> Result<(), String> {
+        let mut connection = self.connection.clone();
+        connection
+            .publish::<_, _, ()>(self.key(channel.name()), payload)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    fn subscribe(&self, channel: Channel, handler: Handler) {
+        self.subscribers.add(channel, handler);
+    }
+
+    fn in_process(&self) -> bool {
+        false
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING")
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Redis {
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    /// Hands messages published by any instance to this one's handlers,
+    /// resubscribing whenever the connection drops, until `token` is
+    /// cancelled.
+    async fn relay(self: Arc<Self>, token: CancellationToken) {
+        let channel_for = |name: &str| {
+            Channel::ALL
+                .into_iter()
+                .find(|channel| self.key(channel.name()) == name)
+        };
+
+        while !token.is_cancelled() {
+            let mut pubsub = match self.client.get_async_pubsub().await {
+                Ok(pubsub) => pubsub,
+                Err(e) => {
+                    error!("shared state relay cannot connect to redis: {}", e);
+                    tokio::select! {
+                        _ = tokio::time::sleep(RECONNECT_DELAY) => continue,
+                        _ = token.cancelled() => return,
+                    }
+                }
+            };
+            let channels: Vec<String> = Channel::ALL.iter().map(|c| self.key(c.name())).collect();
+            if let Err(e) = pubsub.subscribe(&channels).await {
+                error!("shared state relay cannot subscribe: {}", e);
+                continue;
+            }
+            info!("shared state relay subscribed to {:?}", channels);
+
+            let mut messages = pubsub.on_message();
+            loop {
+                let message = tokio::select! {
+                    message = messages.next() => message,
+                    _ = token.cancelled() => return,
+                };
+                let Some(message) = message else {
+                    warn!("shared state relay lost its redis connection, resubscribing");
+                    break;
+                };
+                let payload: String = match message.get_payload() {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("ignoring non-text message from redis: {}", e);
+                        continue;
+                    }
+                };
+                if let Some(channel) = channel_for(message.get_channel_name()) {
+                    self.subscribers.deliver(channel, &payload);
+                }
+            }
+            drop(messages);
+            tokio::select! {
+                _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                _ = token.cancelled() => return,
+            }
+        }
+    }
+}
+
+/// Manages the [`SharedState`] from the optional `shared_state` table and,
+/// for Redis, starts the relay worker at liftoff. Attach it before the
+/// stages that read it: rate limiting, the notes and chat feeds and the
+/// scheduler.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Shared State", |rocket| async {
+        let config = match rocket
+            .figment()
+            .extract_inner::<SharedStateConfig>("shared_state")
+        {
+            Ok(config) => config,
+            Err(e) if e.missing() => SharedStateConfig {
+                backend: Backend::Local,
+                url: default_url(),
+                prefix: default_prefix(),
+            },
+            Err(e) => {
+                error!("invalid shared_state config: {}", e);
+                return Err(rocket);
+            }
+        };
+        if config.backend == Backend::Local {
+            let local: Arc<dyn SharedState> = Arc::new(Local::default());
+            return Ok(rocket.manage(local));
+        }
+
+        let client = match redis::Client::open(config.url.as_str()) {
+            Ok(client) => client,
+            Err(e) => {
+                error!("invalid shared_state.url: {}", e);
+                return Err(rocket);
+            }
+        };
+        let connection = match ConnectionManager::new(client.clone()).await {
+            Ok(connection) => connection,
+            Err(e) => {
+                error!("cannot connect to redis at {}: {}", config.url, e);
+                return Err(rocket);
+            }
+        };
+        info!("shared state in redis at {}", config.url);
+        let redis = Arc::new(Redis {
+            client,
+            connection,
+            prefix: config.prefix,
+            subscribers: Subscribers::default(),
+        });
+        let shared: Arc<dyn SharedState> = redis.clone();
+
+        Ok(rocket
+            .manage(shared)
+            .attach(AdHoc::on_liftoff("Shared State Relay", move |rocket| {
+                Box::pin(async move {
+                    match rocket.state::<Workers>() {
+                        Some(workers) => {
+                            workers.spawn("shared state relay", |token| redis.relay(token))
+                        }
+                        None => error!("shared state relay not started: workers unavailable"),
+                    }
+                })
+            })))
+    })
+}
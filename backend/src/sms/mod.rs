@@ -0,0 +1,333 @@
+//! Valentines by text message through an SMS provider, with delivery
+//! status tracked from the provider's callbacks and a cap on texts per
+//! number so the endpoint cannot be used to flood someone's phone.
+
+pub mod provider;
+
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status;
+use rocket::{Route, State};
+use serde::Deserialize;
+
+use crate::auth::ApiKey;
+use crate::config::PublicUrl;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::providers::{self, MockProvider};
+use crate::resilience::Resilience;
+use crate::storage::{NewMessage, SmsMessage, Storage};
+use crate::users::CoupleScope;
+use crate::valentine::ValentineSubmission;
+use crate::validation::{FieldErrors, Valid, Validate};
+use provider::{SmsProvider, TwilioConfig, TwilioProvider};
+
+const MAX_E164_DIGITS: usize = 15;
+const MIN_E164_DIGITS: usize = 7;
+
+/// The `[default.sms]` table in Rocket.toml. Sending is enabled by a
+/// provider sub-table.
+#[derive(Debug, Deserialize)]
+struct SmsConfig {
+    #[serde(default = "default_per_number_per_hour")]
+    per_number_per_hour: i64,
+    twilio: Option<TwilioConfig>,
+}
+
+fn default_per_number_per_hour() -> i64 {
+    3
+}
+
+/// The configured provider, if any, and the per-number limit.
+pub struct Sms {
+    provider: Option<Box<dyn SmsProvider>>,
+    per_number_per_hour: i64,
+}
+
+impl Sms {
+    fn provider(&self) -> ApiResult<&dyn SmsProvider> {
+        self.provider
+            .as_deref()
+            .ok_or_else(|| error(Status::ServiceUnavailable, "sms delivery is not configured"))
+    }
+}
+
+/// `phone` in E.164 form (`+` and up to 15 digits), ignoring the spaces,
+/// dashes, dots and parentheses people type numbers with.
+fn normalize_phone(phone: &str) -> Result<String, String> {
+    let number: String = phone
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '-' | '.' | '(' | ')'))
+        .collect();
+    let digits = number.strip_prefix('+').unwrap_or_default();
+    let valid = (MIN_E164_DIGITS..=MAX_E164_DIGITS).contains(&digits.len())
+        && digits.chars().all(|c| c.is_ascii_digit())
+        && !digits.starts_with('0');
+    if !valid {
+        return Err(format!(
+            "`phone` must be an E.164 number like +15551234567, got {}",
+            phone.trim()
+        ));
+    }
+    Ok(number)
+}
+
+fn render_text(valentine: &NewMessage) -> String {
+    let greeting = valentine.recipient.as_deref().unwrap_or("Hi");
+    format!(
+        "{}, {}\n\nWith love, {}",
+        greeting, valentine.message, valentine.sender
+    )
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SendSmsRequest {
+    /// E.164, e.g. `+15551234567`.
+    phone: String,
+    #[serde(flatten)]
+    valentine: ValentineSubmission,
+}
+
+impl Validate for SendSmsRequest {
+    /// The normalized number and the message.
+    type Valid = (String, NewMessage);
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let phone = errors.check("phone", normalize_phone(&self.phone));
+        match (phone, errors.flattened(self.valentine)) {
+            (Some(phone), Some(valentine)) => errors.finish((phone, valentine)),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// Texts a valentine. The response has the provider's initial status;
+/// follow delivery at `GET /api/sms/<id>`.
+#[utoipa::path(
+    tag = "messages",
+    request_body = SendSmsRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 202, body = SmsMessage),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field or number, or refused by the content filter", body = ErrorResponse),
+        (status = 429, description = "Too many texts to this number in the last hour", body = ErrorResponse),
+        (status = 502, description = "The provider rejected the message", body = ErrorResponse),
+        (status = 503, description = "SMS is not configured", body = ErrorResponse),
+    )
+)]
+#[post("/api/valentine/send-sms", data = "<request>")]
+async fn send_sms(
+    _key: ApiKey,
+    sms: &State<Sms>,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    public_url: &PublicUrl,
+    scope: CoupleScope,
+    request: Valid<SendSmsRequest>,
+) -> ApiResult<status::Accepted<Negotiated<SmsMessage>>> {
+    let provider = sms.provider()?;
+    let (phone, valentine) = request.into_inner();
+    filter.screen_message(&valentine).await?;
+
+    let recent = storage
+        .count_sms_since(&phone, Utc::now() - Duration::hours(1))
+        .await
+        .map_err(internal_error)?;
+    if recent >= sms.per_number_per_hour {
+        return Err(error(
+            Status::TooManyRequests,
+            format!(
+                "{} has been sent {} texts in the last hour; try again later",
+                phone, recent
+            ),
+        ));
+    }
+
+    // The provider can only call back to a public address.
+    let callback = public_url
+        .is_set()
+        .then(|| public_url.absolute(&uri!(status_callback).to_string()));
+    let queued = provider
+        .send(&phone, &render_text(&valentine), callback.as_deref())
+        .await
+        .map_err(|e| {
+            error!("sms via {} failed: {}", provider.name(), e);
+            error(Status::BadGateway, format!("sms delivery failed: {}", e))
+        })?;
+
+    let message = storage
+        .create_sms(&queued.provider_id, &phone, &queued.status, scope.0)
+        .await
+        .map_err(internal_error)?;
+    Ok(status::Accepted(Negotiated(message)))
+}
+
+#[utoipa::path(
+    tag = "messages",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = SmsMessage),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/sms/<id>")]
+async fn get(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<SmsMessage>> {
+    storage
+        .get_sms(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no text with id {}", id)))
+}
+
+/// The provider's request signature, checked by [`SmsProvider::status_update`].
+struct ProviderSignature(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ProviderSignature {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(ProviderSignature(
+            request
+                .headers()
+                .get_one("X-Twilio-Signature")
+                .map(str::to_string),
+        ))
+    }
+}
+
+/// Delivery status callback from the provider, as a signed form POST.
+/// Updates for texts already delivered or failed are ignored.
+#[utoipa::path(
+    tag = "messages",
+    request_body(content = HashMap<String, String>, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 204, description = "Recorded"),
+        (status = 403, description = "Bad or missing signature", body = ErrorResponse),
+        (status = 503, description = "SMS is not configured", body = ErrorResponse),
+    )
+)]
+#[post("/api/sms/status", data = "<params>")]
+async fn status_callback(
+    sms: &State<Sms>,
+    storage: &Storage,
+    public_url: &PublicUrl,
+    signature: ProviderSignature,
+    params: Form<HashMap<String, String>>,
+) -> ApiResult<status::NoContent> {
+    let url = public_url.absolute(&uri!(status_callback).to_string());
+    let update = sms
+        .provider()?
+        .status_update(&url, &params, signature.0.as_deref())
+        .map_err(|e| {
+            warn!("rejected sms status callback: {}", e);
+            error(Status::Forbidden, e)
+        })?;
+
+    let updated = storage
+        .update_sms_status(
+            &update.provider_id,
+            &update.status,
+            update.error_code.as_deref(),
+        )
+        .await
+        .map_err(internal_error)?;
+    if updated.is_none() {
+        info!(
+            "ignored {} status for text {}",
+            update.status, update.provider_id
+        );
+    }
+    Ok(status::NoContent)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![send_sms, get, status_callback]
+}
+
+/// Builds the SMS provider from the optional `sms` config table, or the
+/// mock provider in mock mode. Must be attached after the providers and
+/// resilience stages.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("SMS", |rocket| async {
+        let config = match rocket.figment().extract_inner::<SmsConfig>("sms") {
+            Ok(config) => config,
+            Err(e) if e.missing() => SmsConfig {
+                per_number_per_hour: default_per_number_per_hour(),
+                twilio: None,
+            },
+            Err(e) => {
+                error!("invalid sms config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let provider: Option<Box<dyn SmsProvider>> =
+            match (providers::mock_log(&rocket), config.twilio) {
+                (Some(log), _) => Some(Box::new(MockProvider::new(log))),
+                (None, Some(twilio)) => {
+                    let Some(client) = rocket.state::<Resilience>().map(|r| r.client("sms")) else {
+                        error!("sms stage attached before the resilience stage");
+                        return Err(rocket);
+                    };
+                    match TwilioProvider::new(twilio, client) {
+                        Ok(provider) => Some(Box::new(provider)),
+                        Err(e) => {
+                            error!("{}", e);
+                            return Err(rocket);
+                        }
+                    }
+                }
+                (None, None) => {
+                    info!("no sms provider configured, sms delivery disabled");
+                    None
+                }
+            };
+
+        Ok(rocket.manage(Sms {
+            provider,
+            per_number_per_hour: config.per_number_per_hour,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phone_numbers_are_normalized_to_e164() {
+        assert_eq!(
+            normalize_phone(" +1 (555) 123-4567 ").unwrap(),
+            "+15551234567"
+        );
+        assert_eq!(
+            normalize_phone("+44.20.7946.0958").unwrap(),
+            "+442079460958"
+        );
+        for bad in [
+            "5551234567",
+            "+0555123456",
+            "+1555",
+            "+1234567890123456",
+            "+1555abc4567",
+        ] {
+            assert!(normalize_phone(bad).is_err(), "{}", bad);
+        }
+    }
+}
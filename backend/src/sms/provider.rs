@@ -0,0 +1,228 @@
+//! SMS providers. Each queues a text with an upstream service and later
+//! reports its delivery status to `POST /api/sms/status`.
+
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::Deserialize;
+use sha1::Sha1;
+
+use crate::resilience::ResilientClient;
+
+/// A text handed to a provider, identified by the provider's id for it.
+#[derive(Debug, Clone)]
+pub struct Queued {
+    pub provider_id: String,
+    pub status: String,
+}
+
+/// A delivery status reported by a provider's callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusUpdate {
+    pub provider_id: String,
+    pub status: String,
+    pub error_code: Option<String>,
+}
+
+/// Anything that can send a text message.
+#[rocket::async_trait]
+pub trait SmsProvider: Send + Sync {
+    /// Identifies the provider in logs, e.g. `twilio (+15550001111)`.
+    fn name(&self) -> String;
+
+    /// Queues `body` for `to`, an E.164 number. The provider POSTs status
+    /// changes to `status_callback` when it is set.
+    async fn send(
+        &self,
+        to: &str,
+        body: &str,
+        status_callback: Option<&str>,
+    ) -> Result<Queued, String>;
+
+    /// Checks that a status callback to `url` with form `params` came from
+    /// the provider, then reads the status out of it.
+    fn status_update(
+        &self,
+        url: &str,
+        params: &HashMap<String, String>,
+        signature: Option<&str>,
+    ) -> Result<StatusUpdate, String>;
+}
+
+/// Reads a Twilio-style status callback form, once its signature checks out.
+pub fn parse_status(params: &HashMap<String, String>) -> Result<StatusUpdate, String> {
+    let field = |name: &str| params.get(name).filter(|v| !v.is_empty()).cloned();
+    Ok(StatusUpdate {
+        provider_id: field("MessageSid").ok_or("missing MessageSid")?,
+        status: field("MessageStatus").ok_or("missing MessageStatus")?,
+        error_code: field("ErrorCode"),
+    })
+}
+
+/// The `[default.sms.twilio]` table in Rocket.toml. `api_url` can point at
+/// any service speaking Twilio's Messages API.
+#[derive(Debug, Deserialize)]
+pub struct TwilioConfig {
+    pub account_sid: String,
+    pub auth_token: String,
+    /// Sending number in E.164 form.
+    pub from: String,
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_api_url() -> String {
+    "https://api.twilio.com".to_string()
+}
+
+#[derive(Deserialize)]
+struct MessageResource {
+    sid: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResource {
+    message: String,
+}
+
+pub struct TwilioProvider {
+    config: TwilioConfig,
+    messages_url: Url,
+    client: ResilientClient,
+}
+
+impl TwilioProvider {
+    pub fn new(config: TwilioConfig, client: ResilientClient) -> Result<Self, String> {
+        let messages_url = Url::parse(&format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            config.api_url.trim_end_matches('/'),
+            config.account_sid
+        ))
+        .map_err(|e| format!("invalid sms.twilio.api_url: {}", e))?;
+        Ok(TwilioProvider {
+            config,
+            messages_url,
+            client,
+        })
+    }
+}
+
+/// Twilio's request signature: HMAC-SHA1 of the URL followed by each POST
+/// parameter's name and value, sorted by name, sent base64 encoded.
+fn signature_mac(auth_token: &str, url: &str, params: &HashMap<String, String>) -> Hmac<Sha1> {
+    let mut names: Vec<&String> = params.keys().collect();
+    names.sort();
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(auth_token.as_bytes()).expect("hmac accepts any key length");
+    mac.update(url.as_bytes());
+    for name in names {
+        mac.update(name.as_bytes());
+        mac.update(params[name].as_bytes());
+    }
+    mac
+}
+
+#[rocket::async_trait]
+impl SmsProvider for TwilioProvider {
+    fn name(&self) -> String {
+        format!("twilio ({})", self.config.from)
+    }
+
+    async fn send(
+        &self,
+        to: &str,
+        body: &str,
+        status_callback: Option<&str>,
+    ) -> Result<Queued, String> {
+        let mut form = vec![("To", to), ("From", &self.config.from), ("Body", body)];
+        if let Some(url) = status_callback {
+            form.push(("StatusCallback", url));
+        }
+        let request = self
+            .client
+            .post(self.messages_url.clone())
+            .basic_auth(&self.config.account_sid, Some(&self.config.auth_token))
+            .form(&form);
+        let response = self.client.send(request).await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let detail = match response.json::<ErrorResource>().await {
+                Ok(e) => e.message,
+                Err(_) => String::new(),
+            };
+            return Err(format!("twilio responded {}: {}", status, detail));
+        }
+        let message: MessageResource = response.json().await.map_err(|e| e.to_string())?;
+        Ok(Queued {
+            provider_id: message.sid,
+            status: message.status,
+        })
+    }
+
+    fn status_update(
+        &self,
+        url: &str,
+        params: &HashMap<String, String>,
+        signature: Option<&str>,
+    ) -> Result<StatusUpdate, String> {
+        let given = signature
+            .and_then(|s| BASE64.decode(s).ok())
+            .ok_or("missing or malformed X-Twilio-Signature")?;
+        signature_mac(&self.config.auth_token, url, params)
+            .verify_slice(&given)
+            .map_err(|_| "X-Twilio-Signature does not match")?;
+        parse_status(params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resilience::Resilience;
+
+    #[test]
+    fn status_callbacks_must_be_signed_with_the_auth_token() {
+        let provider = TwilioProvider::new(
+            TwilioConfig {
+                account_sid: "AC123".to_string(),
+                auth_token: "secret".to_string(),
+                from: "+15550001111".to_string(),
+                api_url: default_api_url(),
+            },
+            Resilience::new(reqwest::Client::new()).client("sms"),
+        )
+        .unwrap();
+        let url = "https://valentine.example.com/api/sms/status";
+        let mut params: HashMap<String, String> = [
+            ("MessageSid", "SM42"),
+            ("MessageStatus", "delivered"),
+            ("ErrorCode", ""),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let signed = BASE64.encode(
+            signature_mac("secret", url, &params)
+                .finalize()
+                .into_bytes(),
+        );
+
+        let update = provider.status_update(url, &params, Some(&signed)).unwrap();
+        assert_eq!(
+            update,
+            StatusUpdate {
+                provider_id: "SM42".to_string(),
+                status: "delivered".to_string(),
+                error_code: None,
+            }
+        );
+        assert!(provider.status_update(url, &params, None).is_err());
+        params.insert("MessageStatus".to_string(), "failed".to_string());
+        assert!(provider.status_update(url, &params, Some(&signed)).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Quote popularity: how often each quote is served and favorited. Serves
+//! are counted in memory on the hot path and flushed to the database in
+//! batches every [`FLUSH_INTERVAL`], and once more at shutdown.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::tokio;
+use rocket::Route;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::auth::ApiKey;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::reactions::ClientFingerprint;
+use crate::storage::{Popularity, Quote, QuoteStat, QuoteStatus, Storage};
+use crate::tenants::Tenants;
+use crate::workers::Workers;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Length of the top lists when `top` is not given, and the most allowed.
+const DEFAULT_TOP: i64 = 10;
+const MAX_TOP: i64 = 50;
+
+/// Serve counts by quote id, for each tenant's database.
+type Pending = HashMap<Option<String>, HashMap<i64, i64>>;
+
+/// Serves not yet written to the database, by tenant and quote id. Clones
+/// share the counts.
+#[derive(Clone, Default)]
+pub struct ServeCounter {
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl ServeCounter {
+    /// Counts one serve of `quote` from `storage`; a fallback message
+    /// (`None`) is not a quote.
+    pub fn served(&self, storage: &Storage, quote: Option<&Quote>) {
+        self.count(storage.tenant(), quote);
+    }
+
+    fn count(&self, tenant: Option<&str>, quote: Option<&Quote>) {
+        if let Some(quote) = quote {
+            *self
+                .pending
+                .lock()
+                .expect("serve counter lock poisoned")
+                .entry(tenant.map(str::to_string))
+                .or_default()
+                .entry(quote.id)
+                .or_default() += 1;
+        }
+    }
+
+    fn take(&self, tenant: Option<&str>) -> HashMap<i64, i64> {
+        self.pending
+            .lock()
+            .expect("serve counter lock poisoned")
+            .remove(&tenant.map(str::to_string))
+            .unwrap_or_default()
+    }
+
+    /// Puts back counts that could not be written, adding to any made since.
+    fn restore(&self, tenant: Option<&str>, counts: HashMap<i64, i64>) {
+        let mut pending = self.pending.lock().expect("serve counter lock poisoned");
+        let pending = pending.entry(tenant.map(str::to_string)).or_default();
+        for (quote_id, served) in counts {
+            *pending.entry(quote_id).or_default() += served;
+        }
+    }
+
+    async fn flush(&self, storage: &Storage) {
+        let counts = self.take(storage.tenant());
+        if counts.is_empty() {
+            return;
+        }
+
+        let batch: Vec<(i64, i64)> = counts.iter().map(|(id, n)| (*id, *n)).collect();
+        if let Err(e) = storage.add_quote_serves(&batch).await {
+            error!(
+                "failed to flush quote serves, keeping them for the next try: {}",
+                e
+            );
+            self.restore(storage.tenant(), counts);
+        }
+    }
+}
+
+/// Flushes `counter` every [`FLUSH_INTERVAL`], and a last time once `token`
+/// is cancelled.
+async fn run_flusher(counter: ServeCounter, storage: Storage, token: CancellationToken) {
+    loop {
+        let stop = tokio::select! {
+            _ = tokio::time::sleep(FLUSH_INTERVAL) => false,
+            _ = token.cancelled() => true,
+        };
+        counter.flush(&storage).await;
+        if stop {
+            return;
+        }
+    }
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct StatsResponse {
+    /// Every approved quote, by id.
+    quotes: Page<QuoteStat>,
+    most_served: Vec<QuoteStat>,
+    most_favorited: Vec<QuoteStat>,
+}
+
+/// Per-quote serve and favorite counts plus the top `top` of each. Serve
+/// counts can trail by up to ten seconds.
+#[utoipa::path(
+    tag = "quotes",
+    params(
+        ("page" = Option<i64>, Query),
+        ("per_page" = Option<i64>, Query),
+        ("top" = Option<i64>, Query, description = "Length of the top lists, 1 to 50 (default 10)"),
+    ),
+    responses((status = 200, body = StatsResponse))
+)]
+#[get("/api/quotes/stats?<page>&<per_page>&<top>")]
+async fn stats(
+    storage: &Storage,
+    page: Option<i64>,
+    per_page: Option<i64>,
+    top: Option<i64>,
+) -> ApiResult<Negotiated<StatsResponse>> {
+    let (page, per_page, offset) = paginate(page, per_page);
+    let top = top.unwrap_or(DEFAULT_TOP).clamp(1, MAX_TOP);
+
+    let items = storage
+        .list_quote_stats(per_page, offset)
+        .await
+        .map_err(internal_error)?;
+    let total = storage
+        .count_quotes(None, Some(QuoteStatus::Approved))
+        .await
+        .map_err(internal_error)?;
+    let most_served = storage
+        .top_quotes(Popularity::Served, top)
+        .await
+        .map_err(internal_error)?;
+    let most_favorited = storage
+        .top_quotes(Popularity::Favorited, top)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(StatsResponse {
+        quotes: Page {
+            items,
+            page,
+            per_page,
+            total,
+        },
+        most_served,
+        most_favorited,
+    }))
+}
+
+#[derive(Responder)]
+enum FavoriteResponse {
+    #[response(status = 201)]
+    Added(Negotiated<QuoteStat>),
+    /// Already favorited (or, for `DELETE`, not favorited); nothing changed.
+    #[response(status = 200)]
+    Unchanged(Negotiated<QuoteStat>),
+}
+
+/// Only approved quotes can be favorited, so pending ones stay hidden.
+async fn approved_stat(storage: &Storage, id: i64) -> ApiResult<QuoteStat> {
+    storage
+        .quote_stat(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no quote with id {}", id)))
+}
+
+#[utoipa::path(
+    tag = "quotes",
+    security(("api_key" = [])),
+    responses(
+        (status = 201, description = "Favorite recorded", body = QuoteStat),
+        (status = 200, description = "Already favorited; nothing changed", body = QuoteStat),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[post("/api/quotes/<id>/favorite")]
+async fn favorite(
+    _key: ApiKey,
+    storage: &Storage,
+    client: ClientFingerprint,
+    id: i64,
+) -> ApiResult<FavoriteResponse> {
+    approved_stat(storage, id).await?;
+    let added = storage
+        .favorite_quote(id, &client.0)
+        .await
+        .map_err(internal_error)?;
+
+    let stat = Negotiated(approved_stat(storage, id).await?);
+    Ok(if added {
+        FavoriteResponse::Added(stat)
+    } else {
+        FavoriteResponse::Unchanged(stat)
+    })
+}
+
+#[utoipa::path(
+    tag = "quotes",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = QuoteStat),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/api/quotes/<id>/favorite")]
+async fn unfavorite(
+    _key: ApiKey,
+    storage: &Storage,
+    client: ClientFingerprint,
+    id: i64,
+) -> ApiResult<Negotiated<QuoteStat>> {
+    approved_stat(storage, id).await?;
+    storage
+        .unfavorite_quote(id, &client.0)
+        .await
+        .map_err(internal_error)?;
+    Ok(Negotiated(approved_stat(storage, id).await?))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![stats, favorite, unfavorite]
+}
+
+/// Manages the [`ServeCounter`] and starts a flusher for each database once
+/// the server has launched.
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Quote Stats", |rocket| async {
+        let counter = ServeCounter::default();
+
+        rocket.manage(counter.clone()).attach(AdHoc::on_liftoff(
+            "Quote Stats Flusher",
+            move |rocket| {
+                Box::pin(async move {
+                    match (rocket.state::<Tenants>(), rocket.state::<Workers>()) {
+                        (Some(tenants), Some(workers)) => {
+                            let workers = workers.clone();
+                            tenants.for_each_database(move |storage| {
+                                let counter = counter.clone();
+                                workers.spawn("quote stats flusher", |token| {
+                                    run_flusher(counter, storage, token)
+                                });
+                            });
+                        }
+                        _ => error!(
+                            "quote stats flusher not started: storage or workers are unavailable"
+                        ),
+                    }
+                })
+            },
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_are_batched_and_failed_flushes_kept() {
+        let counter = ServeCounter::default();
+        let quote = |id| Quote {
+            id,
+            text: String::new(),
+            category: crate::storage::Category::Romantic,
+            status: QuoteStatus::Approved,
+            source: crate::storage::DATABASE_SOURCE.to_string(),
+            created_at: chrono::Utc::now(),
+        };
+
+        counter.count(None, Some(&quote(1)));
+        counter.count(None, Some(&quote(1)));
+        counter.count(None, Some(&quote(2)));
+        counter.count(None, None);
+        counter.count(Some("jo"), Some(&quote(1)));
+
+        let counts = counter.take(None);
+        assert_eq!(counts, HashMap::from([(1, 2), (2, 1)]));
+        assert!(counter.take(None).is_empty());
+
+        counter.count(None, Some(&quote(2)));
+        counter.restore(None, counts);
+        assert_eq!(counter.take(None), HashMap::from([(1, 2), (2, 2)]));
+        assert_eq!(counter.take(Some("jo")), HashMap::from([(1, 1)]));
+    }
+}
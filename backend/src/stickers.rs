@@ -0,0 +1,86 @@
+//! Custom sticker packs for the frontend's picker. Admins upload packs
+//! through `/admin/sticker-packs`; stickers can then be attached to
+//! valentines with `sticker_id` and used as reactions.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::Route;
+use serde::Serialize;
+
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiResult};
+use crate::negotiate::Negotiated;
+use crate::storage::{Sticker, StickerPack, Storage};
+use crate::uploads::upload_url;
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct StickerResponse {
+    id: i64,
+    name: String,
+    url: String,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+pub(crate) struct StickerPackResponse {
+    id: i64,
+    name: String,
+    stickers: Vec<StickerResponse>,
+    created_at: DateTime<Utc>,
+}
+
+impl StickerPackResponse {
+    pub(crate) fn new(public_url: &PublicUrl, pack: StickerPack) -> Self {
+        StickerPackResponse {
+            id: pack.id,
+            name: pack.name,
+            stickers: pack
+                .stickers
+                .into_iter()
+                .map(|sticker| StickerResponse {
+                    url: upload_url(public_url, &sticker.upload_id),
+                    id: sticker.id,
+                    name: sticker.name,
+                })
+                .collect(),
+            created_at: pack.created_at,
+        }
+    }
+}
+
+/// Checks that `field`, a sticker id sent by a client, names an existing
+/// sticker.
+pub async fn require_sticker(storage: &Storage, field: &str, id: i64) -> ApiResult<Sticker> {
+    storage
+        .get_sticker(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| {
+            error(
+                Status::UnprocessableEntity,
+                format!("`{}` must be a sticker id from GET /api/stickers", field),
+            )
+        })
+}
+
+/// Every sticker pack, oldest first, with its stickers.
+#[utoipa::path(
+    tag = "stickers",
+    responses((status = 200, body = Vec<StickerPackResponse>))
+)]
+#[get("/api/stickers")]
+async fn list(
+    storage: &Storage,
+    public_url: &PublicUrl,
+) -> ApiResult<Negotiated<Vec<StickerPackResponse>>> {
+    let packs = storage.list_sticker_packs().await.map_err(internal_error)?;
+    Ok(Negotiated(
+        packs
+            .into_iter()
+            .map(|pack| StickerPackResponse::new(public_url, pack))
+            .collect(),
+    ))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list]
+}
@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+use rocket::serde::json::{self, Value};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    FromFormField,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    /// Moved to the trash, or deleted outright for entities without one.
+    Delete,
+    /// Taken back out of the trash.
+    Restore,
+    /// Deleted for good from the trash.
+    Purge,
+}
+
+/// The kinds of record whose changes are audited.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    FromFormField,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum AuditEntity {
+    Quote,
+    Message,
+    Schedule,
+    Webhook,
+    /// An account's roles.
+    User,
+}
+
+/// One recorded change.
+#[derive(Debug, Clone, Serialize, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// `user:<id>`, `key:<fingerprint>`, `system:<worker>` or `anonymous`.
+    pub actor: String,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: i64,
+    /// Each changed field with its `from` and `to` values; created records
+    /// have only `to`, deleted ones only `from`.
+    #[schema(value_type = Object)]
+    #[graphql(skip)]
+    pub diff: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AuditRow {
+    id: i64,
+    actor: String,
+    action: AuditAction,
+    entity: AuditEntity,
+    entity_id: i64,
+    diff: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<AuditRow> for AuditEntry {
+    fn from(row: AuditRow) -> Self {
+        AuditEntry {
+            id: row.id,
+            actor: row.actor,
+            action: row.action,
+            entity: row.entity,
+            entity_id: row.entity_id,
+            diff: json::from_str(&row.diff).unwrap_or(Value::String(row.diff)),
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: i64,
+    pub diff: Value,
+}
+
+/// Filter and window for [`Storage::list_audit`]; `None` fields match
+/// everything.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditQuery<'a> {
+    pub actor: Option<&'a str>,
+    pub action: Option<AuditAction>,
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+const AUDIT_COLUMNS: &str = "id, actor, action, entity, entity_id, diff, created_at";
+
+const AUDIT_FILTER: &str = "(?1 IS NULL OR actor = ?1) AND (?2 IS NULL OR action = ?2) \
+     AND (?3 IS NULL OR entity = ?3) AND (?4 IS NULL OR entity_id = ?4) \
+     AND (?5 IS NULL OR created_at >= ?5) AND (?6 IS NULL OR created_at < ?6)";
+
+impl Storage {
+    pub async fn record_audit(&self, entry: &NewAuditEntry) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO audit_log (actor, action, entity, entity_id, diff, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&entry.actor)
+        .bind(entry.action)
+        .bind(entry.entity)
+        .bind(entry.entity_id)
+        .bind(entry.diff.to_string())
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// One page of matching entries, newest first, plus how many match.
+    pub async fn list_audit(
+        &self,
+        query: AuditQuery<'_>,
+    ) -> Result<(Vec<AuditEntry>, i64), sqlx::Error> {
+        let total = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM audit_log WHERE {}",
+            AUDIT_FILTER
+        ))
+        .bind(query.actor)
+        .bind(query.action)
+        .bind(query.entity)
+        .bind(query.entity_id)
+        .bind(query.since)
+        .bind(query.until)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let rows: Vec<AuditRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM audit_log WHERE {} ORDER BY created_at DESC, id DESC \
+             LIMIT ?7 OFFSET ?8",
+            AUDIT_COLUMNS, AUDIT_FILTER
+        ))
+        .bind(query.actor)
+        .bind(query.action)
+        .bind(query.entity)
+        .bind(query.entity_id)
+        .bind(query.since)
+        .bind(query.until)
+        .bind(query.limit)
+        .bind(query.offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((rows.into_iter().map(AuditEntry::from).collect(), total))
+    }
+}
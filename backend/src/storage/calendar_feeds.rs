@@ -0,0 +1,33 @@
+use chrono::Utc;
+
+use super::Storage;
+
+impl Storage {
+    /// Makes `token_hash` `couple`'s calendar feed token, replacing any
+    /// earlier one.
+    pub async fn set_calendar_feed(
+        &self,
+        couple: i64,
+        token_hash: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO calendar_feeds (couple_id, token_hash, created_at) VALUES (?, ?, ?) \
+             ON CONFLICT (couple_id) DO UPDATE SET token_hash = excluded.token_hash, \
+             created_at = excluded.created_at",
+        )
+        .bind(couple)
+        .bind(token_hash)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// The couple whose calendar feed token hashes to `token_hash`.
+    pub async fn calendar_feed_couple(&self, token_hash: &str) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar("SELECT couple_id FROM calendar_feeds WHERE token_hash = ?")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+    }
+}
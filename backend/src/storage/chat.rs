@@ -0,0 +1,110 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// A message in a couple's chat. `body` is decrypted on read.
+#[derive(
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    sqlx::FromRow,
+    async_graphql::SimpleObject,
+    utoipa::ToSchema,
+)]
+pub struct ChatMessage {
+    pub id: i64,
+    pub sender_id: i64,
+    pub body: String,
+    /// When the other partner read it.
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+const CHAT_COLUMNS: &str = "id, sender_id, body, read_at, created_at";
+
+impl Storage {
+    fn open_chat_message(&self, mut message: ChatMessage) -> Result<ChatMessage, sqlx::Error> {
+        message.body = self.open(message.body)?;
+        Ok(message)
+    }
+
+    pub async fn create_chat_message(
+        &self,
+        couple: i64,
+        sender: i64,
+        body: &str,
+    ) -> Result<ChatMessage, sqlx::Error> {
+        let stored = sqlx::query_as(&format!(
+            "INSERT INTO chat_messages (couple_id, sender_id, body, created_at) \
+             VALUES (?, ?, ?, ?) RETURNING {}",
+            CHAT_COLUMNS
+        ))
+        .bind(couple)
+        .bind(sender)
+        .bind(self.seal(body)?)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.open_chat_message(stored)
+    }
+
+    /// `couple`'s messages older than `before`, when given, newest first,
+    /// and how many of those there are.
+    pub async fn list_chat_messages(
+        &self,
+        couple: i64,
+        before: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<ChatMessage>, i64), sqlx::Error> {
+        let before = before.unwrap_or(i64::MAX);
+        let messages: Vec<ChatMessage> = sqlx::query_as(&format!(
+            "SELECT {} FROM chat_messages WHERE couple_id = ? AND id < ? \
+             ORDER BY id DESC LIMIT ? OFFSET ?",
+            CHAT_COLUMNS
+        ))
+        .bind(couple)
+        .bind(before)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        let total =
+            sqlx::query_scalar("SELECT COUNT(*) FROM chat_messages WHERE couple_id = ? AND id < ?")
+                .bind(couple)
+                .bind(before)
+                .fetch_one(&self.pool)
+                .await?;
+
+        let messages = messages
+            .into_iter()
+            .map(|m| self.open_chat_message(m))
+            .collect::<Result<_, _>>()?;
+        Ok((messages, total))
+    }
+
+    /// Marks every unread message up to `up_to` that `reader`'s partner
+    /// sent as read at `read_at`, returning how many were.
+    pub async fn mark_chat_read(
+        &self,
+        couple: i64,
+        reader: i64,
+        up_to: i64,
+        read_at: DateTime<Utc>,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE chat_messages SET read_at = ? \
+             WHERE couple_id = ? AND sender_id != ? AND id <= ? AND read_at IS NULL",
+        )
+        .bind(read_at)
+        .bind(couple)
+        .bind(reader)
+        .bind(up_to)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A partner's daily check-in. `day` is their local date when they made it.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct CheckIn {
+    pub id: i64,
+    pub user_id: i64,
+    pub day: NaiveDate,
+    pub timezone: String,
+    pub note: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewCheckIn {
+    pub user_id: i64,
+    pub day: NaiveDate,
+    pub timezone: String,
+    pub note: String,
+}
+
+/// The days one account checked in on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckInDays {
+    pub user_id: i64,
+    pub name: String,
+    /// Oldest first.
+    pub days: Vec<NaiveDate>,
+}
+
+const CHECKIN_COLUMNS: &str = "id, user_id, day, timezone, note, created_at";
+
+impl Storage {
+    /// Fails with a unique violation when the account already checked in
+    /// on `day`.
+    pub async fn create_checkin(&self, checkin: &NewCheckIn) -> Result<CheckIn, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO checkins (user_id, day, timezone, note, created_at) \
+             VALUES (?, ?, ?, ?, ?) RETURNING {}",
+            CHECKIN_COLUMNS
+        ))
+        .bind(checkin.user_id)
+        .bind(checkin.day)
+        .bind(&checkin.timezone)
+        .bind(&checkin.note)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Check-in days for `user_id` and, when it is in a couple, its
+    /// partner, including days from before they linked up. Accounts with
+    /// no check-ins are still listed.
+    pub async fn checkin_days(&self, user_id: i64) -> Result<Vec<CheckInDays>, sqlx::Error> {
+        let rows: Vec<(i64, String, Option<NaiveDate>)> = sqlx::query_as(
+            "SELECT u.id, u.name, c.day FROM users u \
+             LEFT JOIN checkins c ON c.user_id = u.id \
+             WHERE u.id = ?1 \
+                OR u.couple_id = (SELECT couple_id FROM users WHERE id = ?1) \
+             ORDER BY u.id, c.day",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut members: Vec<CheckInDays> = Vec::new();
+        for (id, name, day) in rows {
+            if members.last().is_none_or(|m| m.user_id != id) {
+                members.push(CheckInDays {
+                    user_id: id,
+                    name,
+                    days: Vec::new(),
+                });
+            }
+            if let Some(day) = day {
+                members.last_mut().expect("pushed above").days.push(day);
+            }
+        }
+        Ok(members)
+    }
+}
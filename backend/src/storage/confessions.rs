@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A confession on the public board. Who posted it is only known once they
+/// reveal it.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct Confession {
+    pub id: i64,
+    pub content: String,
+    /// Who the confession is for, as the author described them.
+    #[serde(rename = "to")]
+    #[graphql(name = "to")]
+    pub recipient: Option<String>,
+    /// The name the author signed with when revealing it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revealed_as: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revealed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewConfession {
+    pub content: String,
+    pub recipient: Option<String>,
+    /// SHA-256 of the reveal token handed to the author.
+    pub token_hash: String,
+}
+
+/// Why [`Storage::reveal_confession`] did not sign the confession.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevealError {
+    Unknown,
+    WrongToken,
+    AlreadyRevealed,
+}
+
+const CONFESSION_COLUMNS: &str = "id, content, recipient, revealed_as, revealed_at, created_at";
+
+impl Storage {
+    pub async fn create_confession(
+        &self,
+        confession: &NewConfession,
+    ) -> Result<Confession, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO confessions (content, recipient, token_hash, created_at) \
+             VALUES (?, ?, ?, ?) RETURNING {}",
+            CONFESSION_COLUMNS
+        ))
+        .bind(&confession.content)
+        .bind(&confession.recipient)
+        .bind(&confession.token_hash)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Confessions, newest first, and how many there are.
+    pub async fn list_confessions(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Confession>, i64), sqlx::Error> {
+        let confessions = sqlx::query_as(&format!(
+            "SELECT {} FROM confessions ORDER BY created_at DESC, id DESC LIMIT ? OFFSET ?",
+            CONFESSION_COLUMNS
+        ))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM confessions")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((confessions, total))
+    }
+
+    /// Signs confession `id` with `name` if `token_hash` is its token's.
+    /// A confession can only be revealed once.
+    pub async fn reveal_confession(
+        &self,
+        id: i64,
+        token_hash: &str,
+        name: &str,
+    ) -> Result<Result<Confession, RevealError>, sqlx::Error> {
+        let revealed: Option<Confession> = sqlx::query_as(&format!(
+            "UPDATE confessions SET revealed_as = ?, revealed_at = ? \
+             WHERE id = ? AND token_hash = ? AND revealed_as IS NULL RETURNING {}",
+            CONFESSION_COLUMNS
+        ))
+        .bind(name)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(confession) = revealed {
+            return Ok(Ok(confession));
+        }
+
+        let stored: Option<(String, Option<String>)> =
+            sqlx::query_as("SELECT token_hash, revealed_as FROM confessions WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(Err(match stored {
+            None => RevealError::Unknown,
+            Some((hash, _)) if hash != token_hash => RevealError::WrongToken,
+            Some(_) => RevealError::AlreadyRevealed,
+        }))
+    }
+}
@@ -0,0 +1,165 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// Whether a coupon can still be redeemed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CouponStatus {
+    Available,
+    Redeemed,
+    Expired,
+}
+
+/// A favor one partner owes the other.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Coupon {
+    pub id: i64,
+    pub booklet: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub issuer_id: i64,
+    pub issuer_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redeemed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redeemed_by: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Coupon {
+    pub fn status(&self, now: DateTime<Utc>) -> CouponStatus {
+        if self.redeemed_at.is_some() {
+            CouponStatus::Redeemed
+        } else if self.expires_at.is_some_and(|expires| expires <= now) {
+            CouponStatus::Expired
+        } else {
+            CouponStatus::Available
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewCoupon {
+    pub booklet: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Why [`Storage::redeem_coupon`] did not redeem the coupon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedeemError {
+    Unknown,
+    OwnCoupon,
+    Redeemed,
+    Expired,
+}
+
+const COUPON_SELECT: &str = "SELECT c.id, c.booklet, c.title, c.description, c.issuer_id, \
+     u.name AS issuer_name, c.expires_at, c.redeemed_at, c.redeemed_by, c.created_at \
+     FROM coupons c JOIN users u ON u.id = c.issuer_id";
+
+impl Storage {
+    /// Issues `coupons` from `issuer` to the rest of `couple`, all or none.
+    pub async fn create_coupons(
+        &self,
+        coupons: &[NewCoupon],
+        couple: i64,
+        issuer: i64,
+    ) -> Result<Vec<Coupon>, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let mut ids = Vec::with_capacity(coupons.len());
+        for coupon in coupons {
+            let id: i64 = sqlx::query_scalar(
+                "INSERT INTO coupons \
+                 (booklet, title, description, couple_id, issuer_id, expires_at, created_at) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+            )
+            .bind(&coupon.booklet)
+            .bind(&coupon.title)
+            .bind(&coupon.description)
+            .bind(couple)
+            .bind(issuer)
+            .bind(coupon.expires_at)
+            .bind(now)
+            .fetch_one(&mut *tx)
+            .await?;
+            ids.push(id);
+        }
+        tx.commit().await?;
+
+        let mut created = Vec::with_capacity(ids.len());
+        for id in ids {
+            created.extend(self.get_coupon(id, couple).await?);
+        }
+        Ok(created)
+    }
+
+    pub async fn get_coupon(&self, id: i64, couple: i64) -> Result<Option<Coupon>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "{} WHERE c.id = ? AND c.couple_id = ?",
+            COUPON_SELECT
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// All of `couple`'s coupons, by booklet and then in the order issued.
+    pub async fn list_coupons(&self, couple: i64) -> Result<Vec<Coupon>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "{} WHERE c.couple_id = ? ORDER BY c.booklet, c.id",
+            COUPON_SELECT
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Redeems coupon `id` for `user`, who must not have issued it, unless
+    /// it was already redeemed or expired before `now`.
+    pub async fn redeem_coupon(
+        &self,
+        id: i64,
+        couple: i64,
+        user: i64,
+        now: DateTime<Utc>,
+    ) -> Result<Result<Coupon, RedeemError>, sqlx::Error> {
+        let redeemed = sqlx::query(
+            "UPDATE coupons SET redeemed_at = ?, redeemed_by = ? \
+             WHERE id = ? AND couple_id = ? AND issuer_id != ? AND redeemed_at IS NULL \
+             AND (expires_at IS NULL OR expires_at > ?)",
+        )
+        .bind(now)
+        .bind(user)
+        .bind(id)
+        .bind(couple)
+        .bind(user)
+        .bind(now)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        let Some(coupon) = self.get_coupon(id, couple).await? else {
+            return Ok(Err(RedeemError::Unknown));
+        };
+        if redeemed > 0 {
+            return Ok(Ok(coupon));
+        }
+        Ok(Err(if coupon.issuer_id == user {
+            RedeemError::OwnCoupon
+        } else {
+            match coupon.status(now) {
+                CouponStatus::Expired => RedeemError::Expired,
+                _ => RedeemError::Redeemed,
+            }
+        }))
+    }
+}
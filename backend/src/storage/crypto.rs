@@ -0,0 +1,299 @@
+//! AES-256-GCM encryption for message bodies at rest.
+//!
+//! Sealed values are stored as `enc:v1:<key id>:<base64(nonce || ciphertext)>`.
+//! The key id selects the key on read, so old rows stay readable after the
+//! active key changes; [`Storage::rotate_encryption`] re-seals them under the
+//! active key. Values without the prefix are legacy plaintext and are
+//! returned unchanged.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+const PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+/// The `[default.encryption]` table in Rocket.toml.
+#[derive(Debug, Deserialize)]
+pub struct EncryptionConfig {
+    /// Id of the key new values are sealed with.
+    pub active: String,
+    pub keys: Vec<KeyConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyConfig {
+    pub id: String,
+    /// 32 random bytes, base64-encoded (`openssl rand -base64 32`).
+    pub key: String,
+}
+
+#[derive(Debug)]
+pub enum CryptoError {
+    UnknownKey(String),
+    Malformed,
+    /// Wrong key or tampered ciphertext.
+    Decrypt,
+    Encrypt,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::UnknownKey(id) => write!(f, "no encryption key with id `{}`", id),
+            CryptoError::Malformed => f.write_str("malformed encrypted value"),
+            CryptoError::Decrypt => f.write_str("failed to decrypt value"),
+            CryptoError::Encrypt => f.write_str("failed to encrypt value"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+/// The configured keys, indexed by id.
+pub struct Keyring {
+    active: String,
+    ciphers: HashMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self, String> {
+        let mut ciphers = HashMap::new();
+        for key in &config.keys {
+            if key.id.is_empty() || key.id.contains(':') {
+                return Err(format!("invalid encryption key id `{}`", key.id));
+            }
+            let bytes = BASE64
+                .decode(key.key.trim())
+                .map_err(|e| format!("encryption key `{}` is not base64: {}", key.id, e))?;
+            if bytes.len() != 32 {
+                return Err(format!(
+                    "encryption key `{}` must be 32 bytes, got {}",
+                    key.id,
+                    bytes.len()
+                ));
+            }
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes));
+            if ciphers.insert(key.id.clone(), cipher).is_some() {
+                return Err(format!("duplicate encryption key id `{}`", key.id));
+            }
+        }
+
+        if !ciphers.contains_key(&config.active) {
+            return Err(format!(
+                "active encryption key `{}` is not in encryption.keys",
+                config.active
+            ));
+        }
+
+        Ok(Keyring {
+            active: config.active.clone(),
+            ciphers,
+        })
+    }
+
+    pub fn seal(&self, plaintext: &str) -> Result<String, CryptoError> {
+        let cipher = &self.ciphers[&self.active];
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        // The key id is authenticated so a value cannot be relabelled.
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: self.active.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| CryptoError::Encrypt)?;
+
+        let mut blob = nonce.to_vec();
+        blob.extend_from_slice(&ciphertext);
+        Ok(format!("{}{}:{}", PREFIX, self.active, BASE64.encode(blob)))
+    }
+
+    pub fn open(&self, stored: &str) -> Result<String, CryptoError> {
+        let Some(rest) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let (id, encoded) = rest.split_once(':').ok_or(CryptoError::Malformed)?;
+        let cipher = self
+            .ciphers
+            .get(id)
+            .ok_or_else(|| CryptoError::UnknownKey(id.to_string()))?;
+
+        let blob = BASE64.decode(encoded).map_err(|_| CryptoError::Malformed)?;
+        if blob.len() < NONCE_LEN {
+            return Err(CryptoError::Malformed);
+        }
+        let (nonce, ciphertext) = blob.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: id.as_bytes(),
+        };
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| CryptoError::Decrypt)?;
+
+        String::from_utf8(plaintext).map_err(|_| CryptoError::Malformed)
+    }
+
+    /// True when `stored` is already sealed under the active key.
+    fn is_current(&self, stored: &str) -> bool {
+        stored
+            .strip_prefix(PREFIX)
+            .and_then(|rest| rest.split_once(':'))
+            .is_some_and(|(id, _)| id == self.active)
+    }
+}
+
+/// Rows re-sealed by [`Storage::rotate_encryption`], per table.
+#[derive(Debug, Default, Serialize, utoipa::ToSchema)]
+pub struct RotationReport {
+    pub messages: u64,
+    pub schedules: u64,
+    pub webhook_deliveries: u64,
+    pub vault_letters: u64,
+    pub jobs: u64,
+    pub chat_messages: u64,
+    pub surprises: u64,
+}
+
+impl Storage {
+    pub fn encrypts_messages(&self) -> bool {
+        self.keyring.is_some()
+    }
+
+    /// Encrypts a message body for storage; a no-op without a keyring.
+    pub(super) fn seal(&self, plaintext: &str) -> Result<String, sqlx::Error> {
+        match &self.keyring {
+            Some(keyring) => keyring
+                .seal(plaintext)
+                .map_err(|e| sqlx::Error::Encode(Box::new(e))),
+            None => Ok(plaintext.to_string()),
+        }
+    }
+
+    /// Decrypts a stored message body. Fails for sealed values when no
+    /// keyring is configured rather than leaking ciphertext to clients.
+    pub(super) fn open(&self, stored: String) -> Result<String, sqlx::Error> {
+        match &self.keyring {
+            Some(keyring) => keyring
+                .open(&stored)
+                .map_err(|e| sqlx::Error::Decode(Box::new(e))),
+            None if stored.starts_with(PREFIX) => Err(sqlx::Error::Decode(Box::new(
+                CryptoError::UnknownKey("<encryption disabled>".to_string()),
+            ))),
+            None => Ok(stored),
+        }
+    }
+
+    /// Re-seals every message body, webhook and job payload, vault letter,
+    /// chat message and surprise that is plaintext or sealed under an old
+    /// key with the active key, so retired keys can be removed from config.
+    pub async fn rotate_encryption(&self) -> Result<RotationReport, sqlx::Error> {
+        let Some(keyring) = &self.keyring else {
+            return Ok(RotationReport::default());
+        };
+        // Each table is rotated in its own transaction; rerunning after a
+        // failure skips rows that already use the active key.
+
+        let mut report = RotationReport::default();
+        for (table, column) in [
+            ("messages", "message"),
+            ("schedules", "message"),
+            ("webhook_deliveries", "payload"),
+            ("vault_letters", "body"),
+            ("jobs", "payload"),
+            ("chat_messages", "body"),
+            ("surprises", "message"),
+        ] {
+            let mut tx = self.pool.begin().await?;
+            let rows: Vec<(i64, String)> =
+                sqlx::query_as(&format!("SELECT id, {} FROM {}", column, table))
+                    .fetch_all(&mut *tx)
+                    .await?;
+
+            let mut rotated = 0;
+            for (id, stored) in rows {
+                if keyring.is_current(&stored) {
+                    continue;
+                }
+                let plaintext = self.open(stored)?;
+                sqlx::query(&format!("UPDATE {} SET {} = ? WHERE id = ?", table, column))
+                    .bind(self.seal(&plaintext)?)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                rotated += 1;
+            }
+            tx.commit().await?;
+
+            match table {
+                "messages" => report.messages = rotated,
+                "schedules" => report.schedules = rotated,
+                "webhook_deliveries" => report.webhook_deliveries = rotated,
+                "vault_letters" => report.vault_letters = rotated,
+                "chat_messages" => report.chat_messages = rotated,
+                "surprises" => report.surprises = rotated,
+                _ => report.jobs = rotated,
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(active: &str) -> Keyring {
+        Keyring::from_config(&EncryptionConfig {
+            active: active.to_string(),
+            keys: vec![
+                KeyConfig {
+                    id: "old".to_string(),
+                    key: BASE64.encode([1u8; 32]),
+                },
+                KeyConfig {
+                    id: "new".to_string(),
+                    key: BASE64.encode([2u8; 32]),
+                },
+            ],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn sealed_values_round_trip_across_rotation() {
+        let old = keyring("old");
+        let sealed = old.seal("Be mine").unwrap();
+        assert!(sealed.starts_with("enc:v1:old:"));
+        assert!(!sealed.contains("Be mine"));
+
+        let new = keyring("new");
+        assert!(!new.is_current(&sealed));
+        assert_eq!(new.open(&sealed).unwrap(), "Be mine");
+        assert_eq!(new.open("legacy plaintext").unwrap(), "legacy plaintext");
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let keyring = keyring("old");
+        let sealed = keyring.seal("Be mine").unwrap();
+        let relabelled = sealed.replacen("enc:v1:old:", "enc:v1:new:", 1);
+        assert!(matches!(
+            keyring.open(&relabelled),
+            Err(CryptoError::Decrypt)
+        ));
+        assert!(matches!(
+            keyring.open("enc:v1:gone:AAAA"),
+            Err(CryptoError::UnknownKey(_))
+        ));
+    }
+}
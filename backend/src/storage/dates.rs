@@ -0,0 +1,280 @@
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(rename_all = "kebab-case")]
+pub enum DateKind {
+    FirstDate,
+    Anniversary,
+    Birthday,
+    Other,
+}
+
+impl DateKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DateKind::FirstDate => "first-date",
+            DateKind::Anniversary => "anniversary",
+            DateKind::Birthday => "birthday",
+            DateKind::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for DateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// When to be reminded of a date: `days` before each occurrence (0 for the
+/// day itself), by email to `email` when set and to `date.reminder` webhooks.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Reminders {
+    #[serde(default)]
+    pub days: Vec<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// A date worth remembering. Recurring dates come back every year on the
+/// same month and day.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct ImportantDate {
+    pub id: i64,
+    pub title: String,
+    pub kind: DateKind,
+    pub date: NaiveDate,
+    pub recurring: bool,
+    pub reminders: Reminders,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub couple_id: Option<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+struct DateRow {
+    id: i64,
+    title: String,
+    kind: DateKind,
+    date: NaiveDate,
+    recurring: bool,
+    remind_days: String,
+    remind_email: Option<String>,
+    created_at: DateTime<Utc>,
+    couple_id: Option<i64>,
+}
+
+impl From<DateRow> for ImportantDate {
+    fn from(row: DateRow) -> Self {
+        ImportantDate {
+            id: row.id,
+            title: row.title,
+            kind: row.kind,
+            date: row.date,
+            recurring: row.recurring,
+            reminders: Reminders {
+                days: row
+                    .remind_days
+                    .split(',')
+                    .filter_map(|d| d.parse().ok())
+                    .collect(),
+                email: row.remind_email,
+            },
+            created_at: row.created_at,
+            couple_id: row.couple_id,
+        }
+    }
+}
+
+pub(super) fn join_days(days: &[i64]) -> String {
+    days.iter()
+        .map(i64::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Debug, Clone)]
+pub struct NewImportantDate {
+    pub title: String,
+    pub kind: DateKind,
+    pub date: NaiveDate,
+    pub recurring: bool,
+    pub reminders: Reminders,
+    pub couple_id: Option<i64>,
+}
+
+const DATE_COLUMNS: &str =
+    "id, title, kind, date, recurring, remind_days, remind_email, created_at, couple_id";
+
+impl Storage {
+    pub async fn create_date(&self, date: &NewImportantDate) -> Result<ImportantDate, sqlx::Error> {
+        let row: DateRow = sqlx::query_as(&format!(
+            "INSERT INTO important_dates \
+             (title, kind, date, recurring, remind_days, remind_email, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            DATE_COLUMNS
+        ))
+        .bind(&date.title)
+        .bind(date.kind)
+        .bind(date.date)
+        .bind(date.recurring)
+        .bind(join_days(&date.reminders.days))
+        .bind(&date.reminders.email)
+        .bind(Utc::now())
+        .bind(date.couple_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    /// The date with `id` if it belongs to `couple` (or is shared, for
+    /// `None`).
+    pub async fn get_date(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<ImportantDate>, sqlx::Error> {
+        let row: Option<DateRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM important_dates WHERE id = ? AND couple_id IS ?",
+            DATE_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(ImportantDate::from))
+    }
+
+    /// Replaces a date's reminder settings. `None` if it does not exist.
+    pub async fn set_reminders(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+        reminders: &Reminders,
+    ) -> Result<Option<ImportantDate>, sqlx::Error> {
+        let row: Option<DateRow> = sqlx::query_as(&format!(
+            "UPDATE important_dates SET remind_days = ?, remind_email = ? \
+             WHERE id = ? AND couple_id IS ? RETURNING {}",
+            DATE_COLUMNS
+        ))
+        .bind(join_days(&reminders.days))
+        .bind(&reminders.email)
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(ImportantDate::from))
+    }
+
+    /// False if the date did not exist.
+    pub async fn delete_date(&self, id: i64, couple: Option<i64>) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM important_dates WHERE id = ? AND couple_id IS ?")
+            .bind(id)
+            .bind(couple)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// All of `couple`'s dates, in the order they were added.
+    pub async fn list_dates(&self, couple: Option<i64>) -> Result<Vec<ImportantDate>, sqlx::Error> {
+        let rows: Vec<DateRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM important_dates WHERE couple_id IS ? ORDER BY id",
+            DATE_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(ImportantDate::from).collect())
+    }
+
+    /// Every recurring date plus the one-off dates between `from` and `to`
+    /// inclusive. Whether a recurring date falls in the window depends on
+    /// the year, so callers work that out.
+    pub async fn dates_between(
+        &self,
+        couple: Option<i64>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<ImportantDate>, sqlx::Error> {
+        let rows: Vec<DateRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM important_dates \
+             WHERE couple_id IS ? AND (recurring = 1 OR date BETWEEN ? AND ?) ORDER BY id",
+            DATE_COLUMNS
+        ))
+        .bind(couple)
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(ImportantDate::from).collect())
+    }
+
+    /// Dates with at least one reminder configured that may still happen on
+    /// or after `today`.
+    pub async fn dates_with_reminders(
+        &self,
+        today: NaiveDate,
+    ) -> Result<Vec<ImportantDate>, sqlx::Error> {
+        let rows: Vec<DateRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM important_dates \
+             WHERE remind_days != '' AND (recurring = 1 OR date >= ?) ORDER BY id",
+            DATE_COLUMNS
+        ))
+        .bind(today)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(ImportantDate::from).collect())
+    }
+
+    /// Lead times already reminded about for one occurrence of a date.
+    pub async fn sent_reminders(
+        &self,
+        date_id: i64,
+        occurrence: NaiveDate,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT lead_days FROM date_reminders WHERE date_id = ? AND occurrence = ?",
+        )
+        .bind(date_id)
+        .bind(occurrence)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn record_reminders(
+        &self,
+        date_id: i64,
+        occurrence: NaiveDate,
+        lead_days: &[i64],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for lead in lead_days {
+            sqlx::query(
+                "INSERT INTO date_reminders (date_id, occurrence, lead_days, sent_at) \
+                 VALUES (?, ?, ?, ?) ON CONFLICT DO NOTHING",
+            )
+            .bind(date_id)
+            .bind(occurrence)
+            .bind(lead)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+}
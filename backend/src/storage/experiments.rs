@@ -0,0 +1,284 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{Quote, QuoteStatus, Storage};
+
+/// One of the two quote pools of an experiment.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Variant {
+    A,
+    B,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 2] = [Variant::A, Variant::B];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Variant::A => "a",
+            Variant::B => "b",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Variant> {
+        Variant::ALL.into_iter().find(|v| v.as_str() == s)
+    }
+}
+
+/// What happened to a visitor in an experiment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum ExperimentEvent {
+    /// A quote from the visitor's variant was served.
+    View,
+    /// The visitor clicked through on a served quote.
+    Click,
+}
+
+/// Two quote pools and the share of visitors sent to the first.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Experiment {
+    pub id: i64,
+    pub name: String,
+    /// Percentage of visitors assigned to variant `a`.
+    pub split: i64,
+    /// Quote ids in variant `a`.
+    pub variant_a: Vec<i64>,
+    /// Quote ids in variant `b`.
+    pub variant_b: Vec<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Experiment {
+    pub fn pool(&self, variant: Variant) -> &[i64] {
+        match variant {
+            Variant::A => &self.variant_a,
+            Variant::B => &self.variant_b,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewExperiment {
+    pub name: String,
+    pub split: i64,
+    pub variant_a: Vec<i64>,
+    pub variant_b: Vec<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ExperimentRow {
+    id: i64,
+    name: String,
+    split: i64,
+    created_at: DateTime<Utc>,
+}
+
+/// Raw counts for one variant; see [`Storage::variant_counts`].
+#[derive(Debug, Clone, Default, sqlx::FromRow)]
+pub struct VariantCounts {
+    /// Distinct visitors served at least one quote.
+    pub visitors: i64,
+    pub views: i64,
+    pub clicks: i64,
+    /// Distinct visitors who clicked at least once.
+    pub converted: i64,
+}
+
+const EXPERIMENT_COLUMNS: &str = "id, name, split, created_at";
+
+impl Storage {
+    pub async fn create_experiment(
+        &self,
+        experiment: &NewExperiment,
+    ) -> Result<Experiment, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let row: ExperimentRow = sqlx::query_as(&format!(
+            "INSERT INTO experiments (name, split, created_at) VALUES (?, ?, ?) RETURNING {}",
+            EXPERIMENT_COLUMNS
+        ))
+        .bind(&experiment.name)
+        .bind(experiment.split)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for variant in Variant::ALL {
+            let pool = match variant {
+                Variant::A => &experiment.variant_a,
+                Variant::B => &experiment.variant_b,
+            };
+            for quote_id in pool {
+                sqlx::query(
+                    "INSERT INTO experiment_quotes (experiment_id, quote_id, variant) \
+                     VALUES (?, ?, ?)",
+                )
+                .bind(row.id)
+                .bind(quote_id)
+                .bind(variant)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(Experiment {
+            id: row.id,
+            name: row.name,
+            split: row.split,
+            variant_a: experiment.variant_a.clone(),
+            variant_b: experiment.variant_b.clone(),
+            created_at: row.created_at,
+        })
+    }
+
+    async fn with_pools(&self, row: ExperimentRow) -> Result<Experiment, sqlx::Error> {
+        let quotes: Vec<(i64, Variant)> = sqlx::query_as(
+            "SELECT quote_id, variant FROM experiment_quotes WHERE experiment_id = ? \
+             ORDER BY quote_id",
+        )
+        .bind(row.id)
+        .fetch_all(&self.pool)
+        .await?;
+        let pool = |variant| {
+            quotes
+                .iter()
+                .filter(|(_, v)| *v == variant)
+                .map(|(id, _)| *id)
+                .collect()
+        };
+
+        Ok(Experiment {
+            id: row.id,
+            name: row.name,
+            split: row.split,
+            variant_a: pool(Variant::A),
+            variant_b: pool(Variant::B),
+            created_at: row.created_at,
+        })
+    }
+
+    pub async fn list_experiments(&self) -> Result<Vec<Experiment>, sqlx::Error> {
+        let rows: Vec<ExperimentRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM experiments ORDER BY id",
+            EXPERIMENT_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut experiments = Vec::with_capacity(rows.len());
+        for row in rows {
+            experiments.push(self.with_pools(row).await?);
+        }
+        Ok(experiments)
+    }
+
+    pub async fn get_experiment(&self, id: i64) -> Result<Option<Experiment>, sqlx::Error> {
+        let row: Option<ExperimentRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM experiments WHERE id = ?",
+            EXPERIMENT_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match row {
+            Some(row) => Ok(Some(self.with_pools(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes an experiment with its pools and events. False if it did not
+    /// exist.
+    pub async fn delete_experiment(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM experiments WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// A uniformly random approved quote from one variant's pool, or `None`
+    /// when none of its quotes is approved (any more).
+    pub async fn random_experiment_quote(
+        &self,
+        experiment_id: i64,
+        variant: Variant,
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT q.id, q.text, q.category, q.status, q.created_at \
+             FROM experiment_quotes e JOIN quotes q ON q.id = e.quote_id \
+             WHERE e.experiment_id = ? AND e.variant = ? AND q.status = ? \
+             ORDER BY RANDOM() LIMIT 1",
+        )
+        .bind(experiment_id)
+        .bind(variant)
+        .bind(QuoteStatus::Approved)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn record_experiment_event(
+        &self,
+        experiment_id: i64,
+        variant: Variant,
+        fingerprint: &str,
+        kind: ExperimentEvent,
+        quote_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO experiment_events \
+             (experiment_id, variant, fingerprint, kind, quote_id, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(experiment_id)
+        .bind(variant)
+        .bind(fingerprint)
+        .bind(kind)
+        .bind(quote_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// Whether `fingerprint` has been served a quote in the experiment.
+    pub async fn has_experiment_view(
+        &self,
+        experiment_id: i64,
+        fingerprint: &str,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM experiment_events \
+             WHERE experiment_id = ? AND fingerprint = ? AND kind = ?)",
+        )
+        .bind(experiment_id)
+        .bind(fingerprint)
+        .bind(ExperimentEvent::View)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn variant_counts(
+        &self,
+        experiment_id: i64,
+        variant: Variant,
+    ) -> Result<VariantCounts, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT \
+             COUNT(DISTINCT CASE WHEN kind = 'view' THEN fingerprint END) AS visitors, \
+             COALESCE(SUM(kind = 'view'), 0) AS views, \
+             COALESCE(SUM(kind = 'click'), 0) AS clicks, \
+             COUNT(DISTINCT CASE WHEN kind = 'click' THEN fingerprint END) AS converted \
+             FROM experiment_events WHERE experiment_id = ? AND variant = ?",
+        )
+        .bind(experiment_id)
+        .bind(variant)
+        .fetch_one(&self.pool)
+        .await
+    }
+}
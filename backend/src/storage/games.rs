@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A couple's best game, as returned with each memory quiz.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct HighScore {
+    /// Percentage of questions answered correctly.
+    pub score: u32,
+    pub correct: u32,
+    pub total: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewGameScore<'a> {
+    pub game: &'a str,
+    pub couple_id: i64,
+    pub correct: u32,
+    pub total: u32,
+    pub score: u32,
+}
+
+impl Storage {
+    pub async fn save_game_score(&self, score: &NewGameScore<'_>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO game_scores (game, couple_id, correct, total, score, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(score.game)
+        .bind(score.couple_id)
+        .bind(score.correct)
+        .bind(score.total)
+        .bind(score.score)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// `couple`'s best score in `game`; the earliest game wins a tie.
+    pub async fn high_score(
+        &self,
+        game: &str,
+        couple: i64,
+    ) -> Result<Option<HighScore>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT score, correct, total, created_at FROM game_scores \
+             WHERE game = ? AND couple_id = ? \
+             ORDER BY score DESC, correct DESC, created_at, id LIMIT 1",
+        )
+        .bind(game)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
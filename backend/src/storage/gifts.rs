@@ -0,0 +1,173 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// A catalog entry for `GET /api/gifts`. Prices are whole currency units.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Gift {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub price_min: i64,
+    pub price_max: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewGift {
+    pub name: String,
+    pub description: String,
+    pub price_min: i64,
+    pub price_max: i64,
+    pub url: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct GiftRow {
+    id: i64,
+    name: String,
+    description: String,
+    price_min: i64,
+    price_max: i64,
+    url: Option<String>,
+    tags: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<GiftRow> for Gift {
+    fn from(row: GiftRow) -> Self {
+        Gift {
+            id: row.id,
+            name: row.name,
+            description: row.description,
+            price_min: row.price_min,
+            price_max: row.price_max,
+            url: row.url,
+            tags: row
+                .tags
+                .split(',')
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+            created_at: row.created_at,
+        }
+    }
+}
+
+const GIFT_COLUMNS: &str = "id, name, description, price_min, price_max, url, tags, created_at";
+
+impl Storage {
+    pub async fn count_gifts(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM gifts")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Returns which of `names` are already in the catalog.
+    pub async fn existing_gift_names(&self, names: &[String]) -> Result<Vec<String>, sqlx::Error> {
+        let mut existing = Vec::new();
+        for name in names {
+            let found: Option<String> = sqlx::query_scalar("SELECT name FROM gifts WHERE name = ?")
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await?;
+            existing.extend(found);
+        }
+        Ok(existing)
+    }
+
+    /// The whole catalog, oldest first. It is small enough to score in
+    /// memory.
+    pub async fn list_gifts(&self) -> Result<Vec<Gift>, sqlx::Error> {
+        let rows: Vec<GiftRow> =
+            sqlx::query_as(&format!("SELECT {} FROM gifts ORDER BY id", GIFT_COLUMNS))
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows.into_iter().map(Gift::from).collect())
+    }
+
+    pub async fn get_gift(&self, id: i64) -> Result<Option<Gift>, sqlx::Error> {
+        let row: Option<GiftRow> =
+            sqlx::query_as(&format!("SELECT {} FROM gifts WHERE id = ?", GIFT_COLUMNS))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(row.map(Gift::from))
+    }
+
+    pub async fn create_gift(&self, gift: &NewGift) -> Result<Gift, sqlx::Error> {
+        let row: GiftRow = sqlx::query_as(&format!(
+            "INSERT INTO gifts (name, description, price_min, price_max, url, tags, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            GIFT_COLUMNS
+        ))
+        .bind(&gift.name)
+        .bind(&gift.description)
+        .bind(gift.price_min)
+        .bind(gift.price_max)
+        .bind(&gift.url)
+        .bind(gift.tags.join(","))
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    /// Inserts every gift or none of them.
+    pub async fn import_gifts(&self, gifts: &[NewGift]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now();
+        for gift in gifts {
+            sqlx::query(
+                "INSERT INTO gifts \
+                 (name, description, price_min, price_max, url, tags, created_at) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&gift.name)
+            .bind(&gift.description)
+            .bind(gift.price_min)
+            .bind(gift.price_max)
+            .bind(&gift.url)
+            .bind(gift.tags.join(","))
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    pub async fn update_gift(&self, id: i64, gift: &NewGift) -> Result<Option<Gift>, sqlx::Error> {
+        let row: Option<GiftRow> = sqlx::query_as(&format!(
+            "UPDATE gifts SET name = ?, description = ?, price_min = ?, price_max = ?, \
+             url = ?, tags = ? WHERE id = ? RETURNING {}",
+            GIFT_COLUMNS
+        ))
+        .bind(&gift.name)
+        .bind(&gift.description)
+        .bind(gift.price_min)
+        .bind(gift.price_max)
+        .bind(&gift.url)
+        .bind(gift.tags.join(","))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(Gift::from))
+    }
+
+    pub async fn delete_gift(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM gifts WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,210 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+/// A scavenger hunt and how far through it the finder is.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Hunt {
+    pub id: i64,
+    pub title: String,
+    pub sender: String,
+    /// Wrong answers to a clue before its hint is shown.
+    pub hint_after: i64,
+    /// Wrong answers allowed to a clue within an hour.
+    pub max_attempts: i64,
+    /// The current clue, counted from 0; `clue_count` once completed.
+    pub position: i64,
+    pub clue_count: i64,
+    /// Answers given, right or wrong, across every clue.
+    pub attempts: i64,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct HuntClue {
+    pub position: i64,
+    pub clue: String,
+    pub answer_hash: String,
+    pub hint: Option<String>,
+    pub solved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewHuntClue {
+    pub clue: String,
+    pub answer_hash: String,
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewHunt {
+    pub title: String,
+    pub sender: String,
+    pub hint_after: i64,
+    pub max_attempts: i64,
+    pub clues: Vec<NewHuntClue>,
+    pub couple_id: Option<i64>,
+}
+
+const HUNT_COLUMNS: &str = "h.id, h.title, h.sender, h.hint_after, h.max_attempts, h.position, \
+     (SELECT COUNT(*) FROM hunt_clues c WHERE c.hunt_id = h.id) AS clue_count, \
+     (SELECT COUNT(*) FROM hunt_attempts a WHERE a.hunt_id = h.id) AS attempts, \
+     h.completed_at, h.created_at";
+
+impl Storage {
+    pub async fn create_hunt(&self, hunt: &NewHunt) -> Result<Hunt, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO hunts (title, sender, hint_after, max_attempts, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+        )
+        .bind(&hunt.title)
+        .bind(&hunt.sender)
+        .bind(hunt.hint_after)
+        .bind(hunt.max_attempts)
+        .bind(Utc::now())
+        .bind(hunt.couple_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        for (position, clue) in hunt.clues.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO hunt_clues (hunt_id, position, clue, answer_hash, hint) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(position as i64)
+            .bind(&clue.clue)
+            .bind(&clue.answer_hash)
+            .bind(&clue.hint)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.get_hunt(id, hunt.couple_id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    pub async fn get_hunt(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Hunt>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM hunts h WHERE h.id = ? AND h.couple_id IS ?",
+            HUNT_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Every clue of hunt `id`, in order.
+    pub async fn list_hunt_clues(&self, id: i64) -> Result<Vec<HuntClue>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT position, clue, answer_hash, hint, solved_at FROM hunt_clues \
+             WHERE hunt_id = ? ORDER BY position",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Wrong answers to clue `position` of hunt `id`, from `since` on when
+    /// given.
+    pub async fn count_hunt_failures(
+        &self,
+        id: i64,
+        position: i64,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM hunt_attempts \
+             WHERE hunt_id = ? AND position = ? AND NOT correct AND (? IS NULL OR created_at >= ?)",
+        )
+        .bind(id)
+        .bind(position)
+        .bind(since)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Records an attempt at clue `position`, as wrong until
+    /// [`Storage::answer_hunt_clue`] says otherwise, unless it already had
+    /// `max_failures` wrong answers from `since` on. Counting and recording
+    /// are one statement, so parallel answers cannot all slip under the
+    /// limit. Returns the attempt's id, or `None` at the limit.
+    pub async fn claim_hunt_attempt(
+        &self,
+        id: i64,
+        position: i64,
+        since: DateTime<Utc>,
+        max_failures: i64,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar(
+            "INSERT INTO hunt_attempts (hunt_id, position, correct, created_at) \
+             SELECT ?1, ?2, FALSE, ?3 WHERE \
+             (SELECT COUNT(*) FROM hunt_attempts WHERE hunt_id = ?1 AND position = ?2 \
+              AND NOT correct AND created_at >= ?4) < ?5 \
+             RETURNING id",
+        )
+        .bind(id)
+        .bind(position)
+        .bind(Utc::now())
+        .bind(since)
+        .bind(max_failures)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Records the outcome of claimed `attempt` at clue `position`. A
+    /// correct answer moves the hunt on to the next clue, completing it
+    /// after the last, unless another answer already did; returns whether
+    /// this one did.
+    pub async fn answer_hunt_clue(
+        &self,
+        attempt: i64,
+        id: i64,
+        position: i64,
+        correct: bool,
+    ) -> Result<bool, sqlx::Error> {
+        if !correct {
+            return Ok(false);
+        }
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("UPDATE hunt_attempts SET correct = TRUE WHERE id = ?")
+            .bind(attempt)
+            .execute(&mut *tx)
+            .await?;
+
+        let advanced = sqlx::query(
+            "UPDATE hunts SET position = position + 1, \
+                 completed_at = CASE WHEN position + 1 >= \
+                     (SELECT COUNT(*) FROM hunt_clues WHERE hunt_id = hunts.id) \
+                     THEN ? END \
+                 WHERE id = ? AND position = ?",
+        )
+        .bind(now)
+        .bind(id)
+        .bind(position)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if advanced {
+            sqlx::query("UPDATE hunt_clues SET solved_at = ? WHERE hunt_id = ? AND position = ?")
+                .bind(now)
+                .bind(id)
+                .bind(position)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(advanced)
+    }
+}
@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+/// A response kept for replaying to retries of the request.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct StoredResponse {
+    /// The path and query the key was first sent to.
+    pub path: String,
+    /// Identifies the request body the key was first sent with.
+    pub request_hash: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub location: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What [`Storage::claim_idempotency_key`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// The key is new; the request should run and its response be stored.
+    Claimed,
+    /// A request with the key, to `path`, has not finished yet.
+    InProgress {
+        path: String,
+        request_hash: String,
+    },
+    Done(StoredResponse),
+}
+
+#[derive(sqlx::FromRow)]
+struct KeyRow {
+    path: String,
+    request_hash: String,
+    status: Option<u16>,
+    content_type: Option<String>,
+    location: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+impl Storage {
+    /// Claims `caller`'s `key` for a request to `path` whose body hashes to
+    /// `request_hash`, unless the caller already used it since `since`. Older
+    /// uses are forgotten.
+    pub async fn claim_idempotency_key(
+        &self,
+        caller: &str,
+        key: &str,
+        path: &str,
+        request_hash: &str,
+        since: DateTime<Utc>,
+    ) -> Result<Claim, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM idempotency_keys WHERE caller = ? AND key = ? AND created_at < ?")
+            .bind(caller)
+            .bind(key)
+            .bind(since)
+            .execute(&mut *tx)
+            .await?;
+        let inserted = sqlx::query(
+            "INSERT INTO idempotency_keys (caller, key, path, request_hash, created_at) \
+             VALUES (?, ?, ?, ?, ?) ON CONFLICT (caller, key) DO NOTHING",
+        )
+        .bind(caller)
+        .bind(key)
+        .bind(path)
+        .bind(request_hash)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?;
+        let claim = if inserted.rows_affected() > 0 {
+            Claim::Claimed
+        } else {
+            let row: KeyRow = sqlx::query_as(
+                "SELECT path, request_hash, status, content_type, location, body \
+                 FROM idempotency_keys WHERE caller = ? AND key = ?",
+            )
+            .bind(caller)
+            .bind(key)
+            .fetch_one(&mut *tx)
+            .await?;
+            match row.status {
+                None => Claim::InProgress {
+                    path: row.path,
+                    request_hash: row.request_hash,
+                },
+                Some(status) => Claim::Done(StoredResponse {
+                    path: row.path,
+                    request_hash: row.request_hash,
+                    status,
+                    content_type: row.content_type,
+                    location: row.location,
+                    body: row.body.unwrap_or_default(),
+                }),
+            }
+        };
+        tx.commit().await?;
+        Ok(claim)
+    }
+
+    /// Stores the response to the request that claimed `caller`'s `key`.
+    pub async fn complete_idempotency_key(
+        &self,
+        caller: &str,
+        key: &str,
+        response: &StoredResponse,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE idempotency_keys SET status = ?, content_type = ?, location = ?, body = ? \
+             WHERE caller = ? AND key = ? AND status IS NULL",
+        )
+        .bind(response.status)
+        .bind(&response.content_type)
+        .bind(&response.location)
+        .bind(&response.body)
+        .bind(caller)
+        .bind(key)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Forgets `caller`'s claimed `key` whose response is not kept, so a
+    /// retry runs the request again.
+    pub async fn release_idempotency_key(
+        &self,
+        caller: &str,
+        key: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE caller = ? AND key = ? AND status IS NULL")
+            .bind(caller)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes keys first used before `before`, returning how many.
+    pub async fn purge_idempotency_keys(&self, before: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < ?")
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
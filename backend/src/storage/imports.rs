@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+
+use super::dates::join_days;
+use super::{NewImportantDate, NewMemory, NewMessage, QuizScore, Storage, Upload};
+
+/// A record restored from an export, keeping its original creation time.
+#[derive(Debug, Clone)]
+pub struct Restored<T> {
+    pub record: T,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Everything one import adds, with uploads already in the upload store
+/// and every reference pointing at their ids here.
+#[derive(Debug, Default)]
+pub struct ImportBatch {
+    pub uploads: Vec<Upload>,
+    pub messages: Vec<Restored<NewMessage>>,
+    pub memories: Vec<Restored<NewMemory>>,
+    pub dates: Vec<Restored<NewImportantDate>>,
+    pub quiz_results: Vec<QuizScore>,
+}
+
+impl Storage {
+    /// Inserts the whole batch for `couple`, or nothing. Returns the ids of
+    /// the new messages.
+    pub async fn import_batch(
+        &self,
+        batch: &ImportBatch,
+        couple: Option<i64>,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        for upload in &batch.uploads {
+            sqlx::query(
+                "INSERT INTO uploads (id, content_type, size, sha256, created_at) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(&upload.id)
+            .bind(&upload.content_type)
+            .bind(upload.size)
+            .bind(&upload.sha256)
+            .bind(upload.created_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let mut message_ids = Vec::with_capacity(batch.messages.len());
+        for Restored { record, created_at } in &batch.messages {
+            let id = sqlx::query_scalar(
+                "INSERT INTO messages (message, sender, recipient, image_url, created_at, couple_id) \
+                 VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+            )
+            .bind(self.seal(&record.message)?)
+            .bind(&record.sender)
+            .bind(&record.recipient)
+            .bind(&record.image_url)
+            .bind(created_at)
+            .bind(couple)
+            .fetch_one(&mut *tx)
+            .await?;
+            message_ids.push(id);
+        }
+
+        for Restored { record, created_at } in &batch.memories {
+            sqlx::query(
+                "INSERT INTO memories (upload_id, thumbnail_id, caption, taken_on, created_at, couple_id) \
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&record.upload_id)
+            .bind(&record.thumbnail_id)
+            .bind(&record.caption)
+            .bind(record.taken_on)
+            .bind(created_at)
+            .bind(couple)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for Restored { record, created_at } in &batch.dates {
+            sqlx::query(
+                "INSERT INTO important_dates \
+                 (title, kind, date, recurring, remind_days, remind_email, created_at, couple_id) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&record.title)
+            .bind(record.kind)
+            .bind(record.date)
+            .bind(record.recurring)
+            .bind(join_days(&record.reminders.days))
+            .bind(&record.reminders.email)
+            .bind(created_at)
+            .bind(couple)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for score in &batch.quiz_results {
+            sqlx::query(
+                "INSERT INTO quiz_results (quiz, score, title, categories, created_at, couple_id) \
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&score.quiz)
+            .bind(score.score)
+            .bind(&score.title)
+            .bind(score.categories.to_string())
+            .bind(score.created_at)
+            .bind(couple)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(message_ids)
+    }
+}
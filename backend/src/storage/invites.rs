@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::users::USER_COLUMNS;
+use super::{NewUser, Storage, User};
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Invite {
+    pub token: String,
+    /// Who the invite is for, as a reminder for the admin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used_by: Option<i64>,
+}
+
+/// Why an invite cannot be used to sign up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteError {
+    Unknown,
+    Used,
+    Expired,
+}
+
+impl Invite {
+    /// Whether the invite can still be used at `now`.
+    pub fn check(&self, now: DateTime<Utc>) -> Result<(), InviteError> {
+        if self.used_at.is_some() {
+            Err(InviteError::Used)
+        } else if self.expires_at <= now {
+            Err(InviteError::Expired)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+const INVITE_COLUMNS: &str = "token, note, created_at, expires_at, used_at, used_by";
+
+impl Storage {
+    pub async fn create_invite(
+        &self,
+        token: &str,
+        note: Option<&str>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Invite, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO invites (token, note, created_at, expires_at) VALUES (?, ?, ?, ?) \
+             RETURNING {}",
+            INVITE_COLUMNS
+        ))
+        .bind(token)
+        .bind(note)
+        .bind(Utc::now())
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn get_invite(&self, token: &str) -> Result<Option<Invite>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM invites WHERE token = ?",
+            INVITE_COLUMNS
+        ))
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Creates `user` and uses up invite `token` in one transaction, so two
+    /// signups cannot share an invite. Fails with a unique violation when
+    /// the email is taken, leaving the invite unused.
+    pub async fn create_invited_user(
+        &self,
+        token: &str,
+        user: &NewUser,
+    ) -> Result<Result<User, InviteError>, sqlx::Error> {
+        let now = Utc::now();
+        let mut tx = self.pool.begin().await?;
+        let invite: Option<Invite> = sqlx::query_as(&format!(
+            "SELECT {} FROM invites WHERE token = ?",
+            INVITE_COLUMNS
+        ))
+        .bind(token)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(invite) = invite else {
+            return Ok(Err(InviteError::Unknown));
+        };
+        if let Err(e) = invite.check(now) {
+            return Ok(Err(e));
+        }
+
+        let created: User = sqlx::query_as(&format!(
+            "INSERT INTO users (email, name, password_hash, created_at) VALUES (?, ?, ?, ?) \
+             RETURNING {}",
+            USER_COLUMNS
+        ))
+        .bind(&user.email)
+        .bind(&user.name)
+        .bind(&user.password_hash)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let used = sqlx::query(
+            "UPDATE invites SET used_at = ?, used_by = ? WHERE token = ? AND used_at IS NULL",
+        )
+        .bind(now)
+        .bind(created.id)
+        .bind(token)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        if used == 0 {
+            return Ok(Err(InviteError::Used));
+        }
+
+        tx.commit().await?;
+        Ok(Ok(created))
+    }
+}
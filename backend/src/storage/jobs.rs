@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A job taken off the queue by a worker. `attempts` includes this one.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ClaimedJob {
+    pub id: i64,
+    pub kind: String,
+    pub payload: String,
+    pub attempts: i64,
+}
+
+/// A job that used up its attempts, as listed under `/admin/jobs/failed`.
+/// The payload is left out, since it can hold message text.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct FailedJob {
+    pub id: i64,
+    pub kind: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub failed_at: DateTime<Utc>,
+}
+
+impl Storage {
+    /// Queues a job to run as soon as a worker is free. The payload is
+    /// sealed like message bodies when encryption is configured.
+    pub async fn enqueue_job(&self, kind: &str, payload: &str) -> Result<i64, sqlx::Error> {
+        let now = Utc::now();
+        sqlx::query_scalar(
+            "INSERT INTO jobs (kind, payload, run_at, created_at, updated_at) \
+             VALUES (?, ?, ?, ?, ?) RETURNING id",
+        )
+        .bind(kind)
+        .bind(self.seal(payload)?)
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Takes the job due longest, if any, leasing it until `lease_until`. A
+    /// running job whose lease ran out, e.g. because the server stopped
+    /// mid-job, is due again. A job whose payload cannot be decrypted is
+    /// failed rather than left to be claimed again.
+    pub async fn claim_job(
+        &self,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+    ) -> Result<Option<ClaimedJob>, sqlx::Error> {
+        let claimed: Option<ClaimedJob> = sqlx::query_as(
+            "UPDATE jobs SET status = 'running', attempts = attempts + 1, run_at = ?, \
+             updated_at = ? \
+             WHERE id = (SELECT id FROM jobs WHERE status IN ('pending', 'running') \
+                         AND run_at <= ? ORDER BY run_at, id LIMIT 1) \
+             RETURNING id, kind, payload, attempts",
+        )
+        .bind(lease_until)
+        .bind(now)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(mut job) = claimed else {
+            return Ok(None);
+        };
+        match self.open(job.payload) {
+            Ok(payload) => {
+                job.payload = payload;
+                Ok(Some(job))
+            }
+            Err(e) => {
+                self.fail_job(job.id, &e.to_string()).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes a job that ran successfully.
+    pub async fn finish_job(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM jobs WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Puts a job that failed back in the queue until `retry_at`.
+    pub async fn retry_job_at(
+        &self,
+        id: i64,
+        retry_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE jobs SET status = 'pending', run_at = ?, last_error = ?, updated_at = ? \
+             WHERE id = ?",
+        )
+        .bind(retry_at)
+        .bind(error)
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// Gives up on a job; it stays listed until retried or deleted.
+    pub async fn fail_job(&self, id: i64, error: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE jobs SET status = 'failed', last_error = ?, updated_at = ? WHERE id = ?",
+        )
+        .bind(error)
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// When the next queued job is due, or a running one's lease ends.
+    pub async fn next_job_at(&self) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar("SELECT MIN(run_at) FROM jobs WHERE status IN ('pending', 'running')")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Failed jobs, most recently failed first, and how many there are.
+    pub async fn failed_jobs(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<FailedJob>, i64), sqlx::Error> {
+        let jobs = sqlx::query_as(
+            "SELECT id, kind, attempts, last_error, created_at, updated_at AS failed_at FROM jobs \
+             WHERE status = 'failed' ORDER BY updated_at DESC, id DESC LIMIT ? OFFSET ?",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM jobs WHERE status = 'failed'")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((jobs, total))
+    }
+
+    /// Queues a failed job again with a fresh set of attempts. Returns
+    /// whether there was a failed job with that id.
+    pub async fn retry_failed_job(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let now = Utc::now();
+        let result = sqlx::query(
+            "UPDATE jobs SET status = 'pending', attempts = 0, run_at = ?, updated_at = ? \
+             WHERE id = ? AND status = 'failed'",
+        )
+        .bind(now)
+        .bind(now)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns whether there was a failed job with that id.
+    pub async fn delete_failed_job(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM jobs WHERE id = ? AND status = 'failed'")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::Storage;
+
+/// A dated, captioned photo. `upload_id` and `thumbnail_id` are uploads.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Memory {
+    pub id: i64,
+    pub upload_id: String,
+    pub thumbnail_id: String,
+    pub caption: String,
+    pub taken_on: NaiveDate,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewMemory {
+    pub upload_id: String,
+    pub thumbnail_id: String,
+    pub caption: String,
+    pub taken_on: NaiveDate,
+    pub couple_id: Option<i64>,
+}
+
+const MEMORY_COLUMNS: &str = "id, upload_id, thumbnail_id, caption, taken_on, created_at";
+
+impl Storage {
+    pub async fn create_memory(&self, memory: &NewMemory) -> Result<Memory, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO memories (upload_id, thumbnail_id, caption, taken_on, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING {}",
+            MEMORY_COLUMNS
+        ))
+        .bind(&memory.upload_id)
+        .bind(&memory.thumbnail_id)
+        .bind(&memory.caption)
+        .bind(memory.taken_on)
+        .bind(Utc::now())
+        .bind(memory.couple_id)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// All of `couple`'s memories, most recent first.
+    pub async fn list_memories(&self, couple: Option<i64>) -> Result<Vec<Memory>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM memories WHERE couple_id IS ? ORDER BY taken_on DESC, id DESC",
+            MEMORY_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn random_memory(&self, couple: Option<i64>) -> Result<Option<Memory>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM memories WHERE couple_id IS ? ORDER BY RANDOM() LIMIT 1",
+            MEMORY_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
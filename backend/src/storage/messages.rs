@@ -0,0 +1,346 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// A valentine submitted through the API.
+#[derive(
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    sqlx::FromRow,
+    async_graphql::SimpleObject,
+    utoipa::ToSchema,
+)]
+#[graphql(complex)]
+pub struct Message {
+    pub id: i64,
+    pub message: String,
+    #[serde(rename = "from")]
+    #[graphql(name = "from")]
+    pub sender: String,
+    #[serde(rename = "to")]
+    #[graphql(name = "to")]
+    pub recipient: Option<String>,
+    /// An image from `POST /api/uploads` attached to the valentine.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// When the message was moved to the trash; only set for trashed ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[graphql(skip)]
+    pub deleted_at: Option<DateTime<Utc>>,
+    /// The couple the message belongs to; `None` for shared messages.
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub couple_id: Option<i64>,
+    /// A sticker from `GET /api/stickers` attached to the valentine.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sticker_id: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewMessage {
+    pub message: String,
+    pub sender: String,
+    pub recipient: Option<String>,
+    pub image_url: Option<String>,
+    pub couple_id: Option<i64>,
+    pub sticker_id: Option<i64>,
+}
+
+pub(super) const MESSAGE_COLUMNS: &str =
+    "id, message, sender, recipient, image_url, created_at, deleted_at, couple_id, sticker_id";
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, FromFormField, async_graphql::Enum, utoipa::ToSchema,
+)]
+#[schema(rename_all = "snake_case")]
+pub enum MessageSort {
+    #[field(value = "created_at")]
+    CreatedAt,
+    #[field(value = "sender")]
+    Sender,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, FromFormField, async_graphql::Enum, utoipa::ToSchema,
+)]
+#[schema(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[field(value = "asc")]
+    Asc,
+    #[field(value = "desc")]
+    Desc,
+}
+
+/// Filter, ordering and window for [`Storage::list_messages`].
+#[derive(Debug, Clone, Copy)]
+pub struct MessageQuery<'a> {
+    /// Only messages of this couple, or only shared ones when `None`.
+    pub couple: Option<i64>,
+    /// Case-insensitive substring of the message text.
+    pub search: Option<&'a str>,
+    pub sort: MessageSort,
+    pub order: SortOrder,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl MessageQuery<'_> {
+    fn order_by(&self) -> &'static str {
+        match (self.sort, self.order) {
+            (MessageSort::CreatedAt, SortOrder::Asc) => "created_at ASC, id ASC",
+            (MessageSort::CreatedAt, SortOrder::Desc) => "created_at DESC, id DESC",
+            (MessageSort::Sender, SortOrder::Asc) => "sender COLLATE NOCASE ASC, id ASC",
+            (MessageSort::Sender, SortOrder::Desc) => "sender COLLATE NOCASE DESC, id DESC",
+        }
+    }
+}
+
+/// Escapes `LIKE` wildcards so user input only ever matches literally.
+fn like_pattern(search: &str) -> String {
+    let escaped = search
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+impl Storage {
+    /// Decrypts the body of a row read from the `messages` table.
+    pub(super) fn open_message(&self, mut message: Message) -> Result<Message, sqlx::Error> {
+        message.message = self.open(message.message)?;
+        Ok(message)
+    }
+
+    /// How many messages, across every couple, were sent from `since` on
+    /// and not deleted.
+    pub async fn count_messages_since(&self, since: DateTime<Utc>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM messages WHERE created_at >= ? AND deleted_at IS NULL",
+        )
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn create_message(&self, message: &NewMessage) -> Result<Message, sqlx::Error> {
+        let stored = sqlx::query_as(&format!(
+            "INSERT INTO messages \
+             (message, sender, recipient, image_url, created_at, couple_id, sticker_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            MESSAGE_COLUMNS
+        ))
+        .bind(self.seal(&message.message)?)
+        .bind(&message.sender)
+        .bind(&message.recipient)
+        .bind(&message.image_url)
+        .bind(Utc::now())
+        .bind(message.couple_id)
+        .bind(message.sticker_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.open_message(stored)
+    }
+
+    /// One page of messages plus the total number matching the search.
+    pub async fn list_messages(
+        &self,
+        query: MessageQuery<'_>,
+    ) -> Result<(Vec<Message>, i64), sqlx::Error> {
+        // Encrypted bodies cannot be matched in SQL, so searches decrypt and
+        // filter every row in order; unencrypted databases use LIKE.
+        if let (Some(search), true) = (query.search, self.keyring.is_some()) {
+            let needle = search.to_lowercase();
+            let matching: Vec<Message> = sqlx::query_as::<_, Message>(&format!(
+                "SELECT {} FROM messages WHERE couple_id IS ? AND deleted_at IS NULL ORDER BY {}",
+                MESSAGE_COLUMNS,
+                query.order_by()
+            ))
+            .bind(query.couple)
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|message| self.open_message(message))
+            .filter(|message| match message {
+                Ok(message) => message.message.to_lowercase().contains(&needle),
+                Err(_) => true,
+            })
+            .collect::<Result<_, _>>()?;
+
+            let total = matching.len() as i64;
+            let page = matching
+                .into_iter()
+                .skip(query.offset as usize)
+                .take(query.limit as usize)
+                .collect();
+            return Ok((page, total));
+        }
+
+        let pattern = query.search.map(like_pattern);
+        let total = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM messages \
+             WHERE couple_id IS ?2 AND deleted_at IS NULL \
+             AND (?1 IS NULL OR message LIKE ?1 ESCAPE '\\')",
+        )
+        .bind(&pattern)
+        .bind(query.couple)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let messages: Vec<Message> = sqlx::query_as(&format!(
+            "SELECT {} FROM messages \
+             WHERE couple_id IS ?4 AND deleted_at IS NULL \
+             AND (?1 IS NULL OR message LIKE ?1 ESCAPE '\\') \
+             ORDER BY {} LIMIT ?2 OFFSET ?3",
+            MESSAGE_COLUMNS,
+            query.order_by()
+        ))
+        .bind(&pattern)
+        .bind(query.limit)
+        .bind(query.offset)
+        .bind(query.couple)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let messages = messages
+            .into_iter()
+            .map(|message| self.open_message(message))
+            .collect::<Result<_, _>>()?;
+        Ok((messages, total))
+    }
+
+    /// All of `couple`'s messages outside the trash, oldest first.
+    pub async fn all_messages(&self, couple: Option<i64>) -> Result<Vec<Message>, sqlx::Error> {
+        sqlx::query_as::<_, Message>(&format!(
+            "SELECT {} FROM messages WHERE couple_id IS ? AND deleted_at IS NULL \
+             ORDER BY created_at, id",
+            MESSAGE_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|message| self.open_message(message))
+        .collect()
+    }
+
+    /// The message with `id` if it belongs to `couple` (or is shared, for
+    /// `None`) and is not in the trash.
+    pub async fn get_message(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Message>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM messages WHERE id = ? AND couple_id IS ? AND deleted_at IS NULL",
+            MESSAGE_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|message| self.open_message(message))
+        .transpose()
+    }
+
+    /// Moves the message to the trash and returns it, or `None` if there is
+    /// no such message for `couple` outside the trash.
+    pub async fn trash_message(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Message>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE messages SET deleted_at = ? \
+             WHERE id = ? AND couple_id IS ? AND deleted_at IS NULL RETURNING {}",
+            MESSAGE_COLUMNS
+        ))
+        .bind(Utc::now())
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|message| self.open_message(message))
+        .transpose()
+    }
+
+    /// Takes the message back out of the trash, if it is there, and returns
+    /// it with when it had been deleted.
+    pub async fn restore_message(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<(Message, DateTime<Utc>)>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let deleted_at: Option<DateTime<Utc>> = sqlx::query_scalar(
+            "SELECT deleted_at FROM messages \
+             WHERE id = ? AND couple_id IS ? AND deleted_at IS NOT NULL",
+        )
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(deleted_at) = deleted_at else {
+            return Ok(None);
+        };
+
+        let restored: Message = sqlx::query_as(&format!(
+            "UPDATE messages SET deleted_at = NULL WHERE id = ? RETURNING {}",
+            MESSAGE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Some((self.open_message(restored)?, deleted_at)))
+    }
+
+    /// One page of `couple`'s trash, most recently deleted first, plus its
+    /// size.
+    pub async fn list_trashed_messages(
+        &self,
+        couple: Option<i64>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<Message>, i64), sqlx::Error> {
+        let total = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM messages WHERE couple_id IS ? AND deleted_at IS NOT NULL",
+        )
+        .bind(couple)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let messages: Vec<Message> = sqlx::query_as(&format!(
+            "SELECT {} FROM messages WHERE couple_id IS ? AND deleted_at IS NOT NULL \
+             ORDER BY deleted_at DESC, id DESC LIMIT ? OFFSET ?",
+            MESSAGE_COLUMNS
+        ))
+        .bind(couple)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let messages = messages
+            .into_iter()
+            .map(|message| self.open_message(message))
+            .collect::<Result<_, _>>()?;
+        Ok((messages, total))
+    }
+
+    /// Deletes for good every message trashed before `before`, with its
+    /// shares and reactions, and returns their ids.
+    pub async fn purge_trashed_messages(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        sqlx::query_scalar("DELETE FROM messages WHERE deleted_at < ? RETURNING id")
+            .bind(before)
+            .fetch_all(&self.pool)
+            .await
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+
+use super::Storage;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Applied, but its file has changed since.
+    Modified,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+}
+
+impl Storage {
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
+
+    /// Checksums of the applied migrations, by version.
+    async fn applied_migrations(&self) -> Result<HashMap<i64, Vec<u8>>, MigrateError> {
+        let mut conn = self.pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        Ok(conn
+            .list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|applied| (applied.version, applied.checksum.into_owned()))
+            .collect())
+    }
+
+    /// Every known migration, oldest first, and whether it has run.
+    pub async fn migration_status(&self) -> Result<Vec<MigrationStatus>, MigrateError> {
+        let applied = self.applied_migrations().await?;
+        Ok(MIGRATOR
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state: match applied.get(&migration.version) {
+                    None => MigrationState::Pending,
+                    Some(checksum) if *checksum == *migration.checksum => MigrationState::Applied,
+                    Some(_) => MigrationState::Modified,
+                },
+            })
+            .collect())
+    }
+
+    /// Runs the down scripts of the latest `steps` applied migrations,
+    /// newest first, and returns their versions.
+    pub async fn revert_migrations(&self, steps: usize) -> Result<Vec<i64>, MigrateError> {
+        let mut applied: Vec<i64> = self.applied_migrations().await?.into_keys().collect();
+        applied.sort_unstable_by(|a, b| b.cmp(a));
+        let target = applied.get(steps).copied().unwrap_or(0);
+        MIGRATOR.undo(&self.pool, target).await?;
+        applied.truncate(steps);
+        Ok(applied)
+    }
+}
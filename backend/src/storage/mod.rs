@@ -0,0 +1,240 @@
+mod audit;
+mod calendar_feeds;
+mod chat;
+mod checkins;
+mod confessions;
+mod coupons;
+mod crypto;
+mod dates;
+mod experiments;
+mod games;
+mod gifts;
+mod hunts;
+mod idempotency;
+mod imports;
+mod invites;
+mod jobs;
+mod memories;
+mod messages;
+mod migrations;
+mod moods;
+mod pool;
+mod proposals;
+mod public_keys;
+mod push;
+mod quiz;
+mod quotes;
+mod reactions;
+mod reservations;
+mod roles;
+mod schedules;
+mod sealed_notes;
+mod shares;
+mod sms;
+mod stats;
+mod stickers;
+mod surprises;
+mod tenants;
+mod translations;
+mod uploads;
+mod users;
+mod vault;
+mod webhooks;
+mod wishlist;
+
+pub use audit::{AuditAction, AuditEntity, AuditEntry, AuditQuery, NewAuditEntry};
+pub use chat::ChatMessage;
+pub use checkins::{CheckIn, NewCheckIn};
+pub use confessions::{Confession, NewConfession, RevealError};
+pub use coupons::{Coupon, CouponStatus, NewCoupon, RedeemError};
+pub use crypto::{EncryptionConfig, Keyring, RotationReport};
+pub use dates::{DateKind, ImportantDate, NewImportantDate, Reminders};
+pub use experiments::{Experiment, ExperimentEvent, NewExperiment, Variant};
+pub use games::{HighScore, NewGameScore};
+pub use gifts::{Gift, NewGift};
+pub use hunts::{Hunt, HuntClue, NewHunt, NewHuntClue};
+pub use idempotency::{Claim, StoredResponse};
+pub use imports::{ImportBatch, Restored};
+pub use invites::{Invite, InviteError};
+pub use jobs::{ClaimedJob, FailedJob};
+pub use memories::{Memory, NewMemory};
+pub use messages::{Message, MessageQuery, MessageSort, NewMessage, SortOrder};
+pub use migrations::{MigrationState, MigrationStatus};
+pub use moods::{MoodEntry, MoodScore, NewMood};
+pub use pool::{warm, PoolConfig, PoolStatus};
+pub use proposals::{Answer, NewProposal, Proposal};
+pub use public_keys::{NewPublicKey, PublicKey, SealAlgorithm};
+pub use push::{NewPushSubscription, PushSubscription};
+pub use quiz::QuizScore;
+pub use quotes::{Category, NewQuote, Quote, QuoteStatus, BUILTIN_SOURCE, DATABASE_SOURCE};
+pub use reactions::{Reaction, ReactionCount};
+pub use reservations::{NewReservation, Reservation};
+pub use roles::{Role, RoleGrant};
+pub use schedules::Schedule;
+pub use sealed_notes::{Envelope, NewSealedNote, SealedNote};
+pub use sms::SmsMessage;
+pub use stats::{Popularity, QuoteStat};
+pub use stickers::{NewSticker, Sticker, StickerPack};
+pub use surprises::{AttemptOutcome, NewSurprise, Surprise};
+pub use tenants::{NewTenant, QuotePool, Tenant, TenantUpdate};
+pub use uploads::Upload;
+pub use users::{Couple, JoinError, NewUser, User};
+pub use vault::{NewVaultLetter, VaultLetter};
+pub use webhooks::{Delivery, Webhook, WebhookEvent};
+pub use wishlist::{NewWishlistItem, WishlistError, WishlistItem};
+
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+
+const DEFAULT_DATABASE_URL: &str = "sqlite://valentine.db";
+
+/// Handle to the SQLite database, shared with handlers through managed state.
+#[derive(Clone)]
+pub struct Storage {
+    pool: SqlitePool,
+    /// Encrypts message bodies at rest when an `encryption` table is configured.
+    keyring: Option<Arc<Keyring>>,
+    /// The slug of the tenant whose database this is; `None` for the main one.
+    tenant: Option<Arc<str>>,
+    /// Sizes this pool and those of tenant databases opened from it.
+    pool_config: Arc<PoolConfig>,
+}
+
+impl Storage {
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        Storage::connect_with(url, &PoolConfig::default()).await
+    }
+
+    pub async fn connect_with(url: &str, config: &PoolConfig) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = config.options().connect_with(options).await?;
+        Ok(Storage {
+            pool,
+            keyring: None,
+            tenant: None,
+            pool_config: Arc::new(config.clone()),
+        })
+    }
+
+    /// Opens the database of tenant `slug` at `url`, encrypted with the same
+    /// keyring and pooled like this one.
+    pub async fn connect_tenant(&self, url: &str, slug: &str) -> Result<Self, sqlx::Error> {
+        let mut storage = Storage::connect_with(url, &self.pool_config).await?;
+        storage.keyring = self.keyring.clone();
+        storage.tenant = Some(Arc::from(slug));
+        Ok(storage)
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn with_keyring(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(Arc::new(keyring));
+        self
+    }
+
+    /// Round-trips a trivial query, for readiness checks.
+    pub async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Writes a consistent copy of the database to `path`, which must not
+    /// exist yet.
+    pub async fn snapshot(&self, path: &Path) -> Result<(), sqlx::Error> {
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy())
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// True when `e` was caused by a UNIQUE constraint, e.g. a duplicate quote.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|db| db.is_unique_violation())
+}
+
+/// `database_url` from config, or `valentine.db` in the working directory.
+pub fn database_url(figment: &Figment) -> String {
+    figment
+        .extract_inner("database_url")
+        .unwrap_or_else(|_| DEFAULT_DATABASE_URL.to_string())
+}
+
+/// Connects to the database, runs pending migrations and seeds the default
+/// quotes before the server starts accepting requests.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("SQLite Storage", |rocket| async {
+        let url = database_url(rocket.figment());
+
+        let keyring = match rocket
+            .figment()
+            .extract_inner::<EncryptionConfig>("encryption")
+        {
+            Ok(config) => match Keyring::from_config(&config) {
+                Ok(keyring) => Some(keyring),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(rocket);
+                }
+            },
+            Err(e) if e.missing() => {
+                warn!("no encryption config found, messages are stored in plaintext");
+                None
+            }
+            Err(e) => {
+                error!("invalid encryption config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let pool = match rocket
+            .figment()
+            .extract_inner::<PoolConfig>("database_pool")
+        {
+            Ok(pool) => pool,
+            Err(e) if e.missing() => PoolConfig::default(),
+            Err(e) => {
+                error!("invalid database_pool config: {}", e);
+                return Err(rocket);
+            }
+        };
+        if let Err(e) = pool.validate() {
+            error!("{}", e);
+            return Err(rocket);
+        }
+
+        let mut storage = match Storage::connect_with(&url, &pool).await {
+            Ok(storage) => storage,
+            Err(e) => {
+                error!("failed to open database {}: {}", url, e);
+                return Err(rocket);
+            }
+        };
+        if let Some(keyring) = keyring {
+            storage = storage.with_keyring(keyring);
+        }
+
+        if let Err(e) = storage.migrate().await {
+            error!("failed to run migrations: {}", e);
+            return Err(rocket);
+        }
+
+        if let Err(e) = storage.seed_default_quotes().await {
+            error!("failed to seed quotes: {}", e);
+            return Err(rocket);
+        }
+
+        Ok(rocket.manage(storage))
+    })
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A partner's mood on one of their local days.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct MoodEntry {
+    pub id: i64,
+    pub user_id: i64,
+    pub day: NaiveDate,
+    pub timezone: String,
+    pub score: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewMood {
+    pub user_id: i64,
+    pub day: NaiveDate,
+    pub timezone: String,
+    pub score: i64,
+    pub note: Option<String>,
+}
+
+/// A mood score as the insights use it.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct MoodScore {
+    pub user_id: i64,
+    pub name: String,
+    pub day: NaiveDate,
+    pub score: i64,
+}
+
+const MOOD_COLUMNS: &str = "id, user_id, day, timezone, score, note, created_at";
+
+impl Storage {
+    /// Fails with a unique violation when the account already logged a
+    /// mood on `day`.
+    pub async fn create_mood(&self, mood: &NewMood) -> Result<MoodEntry, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO moods (user_id, day, timezone, score, note, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING {}",
+            MOOD_COLUMNS
+        ))
+        .bind(mood.user_id)
+        .bind(mood.day)
+        .bind(&mood.timezone)
+        .bind(mood.score)
+        .bind(&mood.note)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Scores from `since` on for `user_id` and, when it is in a couple,
+    /// its partner, oldest first.
+    pub async fn mood_scores(
+        &self,
+        user_id: i64,
+        since: NaiveDate,
+    ) -> Result<Vec<MoodScore>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT u.id AS user_id, u.name, m.day, m.score FROM moods m \
+             JOIN users u ON u.id = m.user_id \
+             WHERE (u.id = ?1 OR u.couple_id = (SELECT couple_id FROM users WHERE id = ?1)) \
+               AND m.day >= ?2 \
+             ORDER BY m.day, u.id",
+        )
+        .bind(user_id)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
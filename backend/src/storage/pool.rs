@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+use rocket::fairing::AdHoc;
+use rocket::futures::future::try_join_all;
+use serde::Deserialize;
+use sqlx::sqlite::SqlitePoolOptions;
+
+use super::Storage;
+
+/// The `[default.database_pool]` table in Rocket.toml. Tenant databases
+/// get pools of the same size.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Connections opened at startup and kept open while idle.
+    pub min_connections: u32,
+    pub max_connections: u32,
+    /// How long a query waits for a free connection before failing.
+    pub acquire_timeout_secs: u64,
+    /// When a connection above `min_connections` is closed for being idle.
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            min_connections: 1,
+            max_connections: 10,
+            acquire_timeout_secs: 5,
+            idle_timeout_secs: 600,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_connections == 0 {
+            return Err("database_pool.max_connections must be at least 1".to_string());
+        }
+        if self.min_connections > self.max_connections {
+            return Err(format!(
+                "database_pool.min_connections ({}) is more than max_connections ({})",
+                self.min_connections, self.max_connections
+            ));
+        }
+        if self.acquire_timeout_secs == 0 {
+            return Err("database_pool.acquire_timeout_secs must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub(super) fn options(&self) -> SqlitePoolOptions {
+        SqlitePoolOptions::new()
+            .min_connections(self.min_connections)
+            .max_connections(self.max_connections)
+            .acquire_timeout(Duration::from_secs(self.acquire_timeout_secs))
+            .idle_timeout(Duration::from_secs(self.idle_timeout_secs))
+    }
+}
+
+/// How many connections the pool holds and how many are in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStatus {
+    pub open: u32,
+    pub idle: u32,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl PoolStatus {
+    pub fn in_use(&self) -> u32 {
+        self.open.saturating_sub(self.idle)
+    }
+}
+
+impl Storage {
+    pub fn pool_status(&self) -> PoolStatus {
+        let options = self.pool.options();
+        let idle = self.pool.num_idle() as u32;
+        PoolStatus {
+            open: self.pool.size().max(idle),
+            idle,
+            min: options.get_min_connections(),
+            max: options.get_max_connections(),
+        }
+    }
+
+    /// Opens the pool's minimum connections at once and round-trips a query
+    /// on each, so the first requests do not pay for connecting.
+    pub async fn warm_pool(&self) -> Result<PoolStatus, sqlx::Error> {
+        let wanted = self.pool.options().get_min_connections().max(1);
+        let connections = try_join_all((0..wanted).map(|_| self.pool.acquire())).await?;
+        for mut connection in connections {
+            sqlx::query("SELECT 1").execute(&mut *connection).await?;
+        }
+        Ok(self.pool_status())
+    }
+}
+
+/// Warms the main database's pool before launch. Must be attached after
+/// the storage stage. A failure is logged but does not stop the server,
+/// which then connects on demand.
+pub fn warm() -> AdHoc {
+    AdHoc::on_ignite("Warm Database Pool", |rocket| async {
+        let Some(storage) = rocket.state::<Storage>() else {
+            error!("pool warm-up attached before the storage stage");
+            return rocket;
+        };
+        let started = Instant::now();
+        match storage.warm_pool().await {
+            Ok(status) => info!(
+                "database pool warmed: {} connections open in {}ms, up to {}",
+                status.open,
+                started.elapsed().as_millis(),
+                status.max
+            ),
+            Err(e) => warn!("failed to warm the database pool: {}", e),
+        }
+        rocket
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn warming_opens_the_minimum_connections() {
+        let config = PoolConfig {
+            min_connections: 3,
+            max_connections: 4,
+            ..PoolConfig::default()
+        };
+        let storage = Storage::connect_with("sqlite::memory:", &config)
+            .await
+            .unwrap();
+        let status = storage.warm_pool().await.unwrap();
+        assert_eq!((status.open, status.min, status.max), (3, 3, 4));
+
+        // Connections go back to the pool on a background task.
+        for _ in 0..100 {
+            if storage.pool_status().idle == 3 {
+                break;
+            }
+            rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(storage.pool_status().in_use(), 0);
+        let held = storage.pool.acquire().await.unwrap();
+        assert_eq!(storage.pool_status().in_use(), 1);
+        drop(held);
+
+        assert!(PoolConfig {
+            min_connections: 5,
+            ..config
+        }
+        .validate()
+        .is_err());
+    }
+}
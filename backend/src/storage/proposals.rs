@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Answer {
+    Yes,
+    No,
+}
+
+/// A "will you be my valentine?" question addressed by its public token.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct Proposal {
+    pub token: String,
+    pub question: String,
+    #[serde(rename = "from")]
+    #[graphql(name = "from")]
+    pub sender: String,
+    #[serde(rename = "to")]
+    #[graphql(name = "to")]
+    pub recipient: Option<String>,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub callback_url: Option<String>,
+    pub answer: Option<Answer>,
+    pub answered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub couple_id: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewProposal {
+    pub token: String,
+    pub question: String,
+    pub sender: String,
+    pub recipient: Option<String>,
+    pub callback_url: Option<String>,
+    pub couple_id: Option<i64>,
+}
+
+const PROPOSAL_COLUMNS: &str =
+    "token, question, sender, recipient, callback_url, answer, answered_at, created_at, couple_id";
+
+impl Storage {
+    pub async fn create_proposal(&self, proposal: &NewProposal) -> Result<Proposal, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO proposals \
+             (token, question, sender, recipient, callback_url, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            PROPOSAL_COLUMNS
+        ))
+        .bind(&proposal.token)
+        .bind(&proposal.question)
+        .bind(&proposal.sender)
+        .bind(&proposal.recipient)
+        .bind(&proposal.callback_url)
+        .bind(Utc::now())
+        .bind(proposal.couple_id)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn get_proposal(&self, token: &str) -> Result<Option<Proposal>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM proposals WHERE token = ?",
+            PROPOSAL_COLUMNS
+        ))
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Records the answer if the proposal has not been answered yet. Returns
+    /// `None` when there is no unanswered proposal with this token.
+    pub async fn answer_proposal(
+        &self,
+        token: &str,
+        answer: Answer,
+    ) -> Result<Option<Proposal>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE proposals SET answer = ?, answered_at = ? \
+             WHERE token = ? AND answer IS NULL RETURNING {}",
+            PROPOSAL_COLUMNS
+        ))
+        .bind(answer)
+        .bind(Utc::now())
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// How many proposals, across every couple, were answered `answer`.
+    pub async fn count_answers(&self, answer: Answer) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM proposals WHERE answer = ?")
+            .bind(answer)
+            .fetch_one(&self.pool)
+            .await
+    }
+}
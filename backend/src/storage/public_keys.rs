@@ -0,0 +1,155 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// How a sealed note was encrypted, which fixes the sizes of its keys and
+/// nonce. The server checks those sizes and nothing more.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+)]
+pub enum SealAlgorithm {
+    /// libsodium's `crypto_box`: X25519 keys, a 24-byte nonce.
+    #[serde(rename = "x25519-xsalsa20-poly1305")]
+    #[sqlx(rename = "x25519-xsalsa20-poly1305")]
+    X25519XSalsa20Poly1305,
+    /// WebCrypto ECDH on P-256, raw uncompressed keys, with AES-256-GCM and
+    /// a 12-byte nonce.
+    #[serde(rename = "p256-aes-256-gcm")]
+    #[sqlx(rename = "p256-aes-256-gcm")]
+    P256Aes256Gcm,
+}
+
+impl SealAlgorithm {
+    pub fn key_len(self) -> usize {
+        match self {
+            SealAlgorithm::X25519XSalsa20Poly1305 => 32,
+            SealAlgorithm::P256Aes256Gcm => 65,
+        }
+    }
+
+    pub fn nonce_len(self) -> usize {
+        match self {
+            SealAlgorithm::X25519XSalsa20Poly1305 => 24,
+            SealAlgorithm::P256Aes256Gcm => 12,
+        }
+    }
+
+    /// Bytes of authentication tag every ciphertext carries.
+    pub fn tag_len(self) -> usize {
+        16
+    }
+}
+
+/// A device's public key, which partners seal notes to.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct PublicKey {
+    pub id: i64,
+    pub user_id: i64,
+    pub algorithm: SealAlgorithm,
+    /// Base64.
+    pub public_key: String,
+    /// Hex SHA-256 of the raw key, for comparing out of band.
+    pub fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    /// The owner's couple.
+    #[serde(skip)]
+    pub couple_id: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewPublicKey {
+    pub algorithm: SealAlgorithm,
+    pub public_key: String,
+    pub fingerprint: String,
+    pub label: Option<String>,
+}
+
+const KEY_COLUMNS: &str = "k.id, k.user_id, k.algorithm, k.public_key, k.fingerprint, k.label, \
+     k.revoked_at, k.created_at, u.couple_id";
+
+impl Storage {
+    /// Registers `key` for `user`. Fails with a unique violation when they
+    /// already registered it.
+    pub async fn create_public_key(
+        &self,
+        user: i64,
+        key: &NewPublicKey,
+    ) -> Result<PublicKey, sqlx::Error> {
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO public_keys (user_id, algorithm, public_key, fingerprint, label, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING id",
+        )
+        .bind(user)
+        .bind(key.algorithm)
+        .bind(&key.public_key)
+        .bind(&key.fingerprint)
+        .bind(&key.label)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+        self.get_public_key(id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    pub async fn get_public_key(&self, id: i64) -> Result<Option<PublicKey>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM public_keys k JOIN users u ON u.id = k.user_id WHERE k.id = ?",
+            KEY_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// The unrevoked keys of `user` and, when it is in a couple, its
+    /// partner, oldest first.
+    pub async fn list_public_keys(&self, user: i64) -> Result<Vec<PublicKey>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM public_keys k JOIN users u ON u.id = k.user_id \
+             WHERE (u.id = ?1 OR u.couple_id = (SELECT couple_id FROM users WHERE id = ?1)) \
+               AND k.revoked_at IS NULL \
+             ORDER BY k.id",
+            KEY_COLUMNS
+        ))
+        .bind(user)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Revokes `user`'s key `id`, if it has not been already. Notes sealed
+    /// to it stay, but no new ones can be.
+    pub async fn revoke_public_key(
+        &self,
+        id: i64,
+        user: i64,
+    ) -> Result<Option<PublicKey>, sqlx::Error> {
+        let revoked: Option<i64> = sqlx::query_scalar(
+            "UPDATE public_keys SET revoked_at = ? \
+             WHERE id = ? AND user_id = ? AND revoked_at IS NULL RETURNING id",
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .bind(user)
+        .fetch_optional(&self.pool)
+        .await?;
+        match revoked {
+            Some(id) => self.get_public_key(id).await,
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A browser's push endpoint with the keys its payloads are encrypted to.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct PushSubscription {
+    pub id: i64,
+    pub endpoint: String,
+    #[serde(skip)]
+    pub p256dh: String,
+    #[serde(skip)]
+    pub auth: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewPushSubscription {
+    pub endpoint: String,
+    pub p256dh: String,
+    pub auth: String,
+    pub couple_id: Option<i64>,
+}
+
+const SUBSCRIPTION_COLUMNS: &str = "id, endpoint, p256dh, auth, created_at";
+
+impl Storage {
+    /// Stores a subscription, replacing the keys and owner of an existing
+    /// one with the same endpoint since browsers resubscribe in place.
+    pub async fn save_push_subscription(
+        &self,
+        subscription: &NewPushSubscription,
+    ) -> Result<PushSubscription, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO push_subscriptions (endpoint, p256dh, auth, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?) \
+             ON CONFLICT (endpoint) DO UPDATE SET \
+             p256dh = excluded.p256dh, auth = excluded.auth, couple_id = excluded.couple_id \
+             RETURNING {}",
+            SUBSCRIPTION_COLUMNS
+        ))
+        .bind(&subscription.endpoint)
+        .bind(&subscription.p256dh)
+        .bind(&subscription.auth)
+        .bind(Utc::now())
+        .bind(subscription.couple_id)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn push_subscriptions(
+        &self,
+        couple: Option<i64>,
+    ) -> Result<Vec<PushSubscription>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM push_subscriptions WHERE couple_id IS ? ORDER BY id",
+            SUBSCRIPTION_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_push_subscription(
+        &self,
+        id: i64,
+    ) -> Result<Option<PushSubscription>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM push_subscriptions WHERE id = ?",
+            SUBSCRIPTION_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn delete_push_subscription(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM push_subscriptions WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use rocket::serde::json::{self, Value};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// A couple's result in one quiz.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct QuizScore {
+    pub quiz: String,
+    /// Overall compatibility, 0 to 100.
+    pub score: u32,
+    pub title: String,
+    /// Per-category scores, as returned by `POST /api/quiz/answers`.
+    #[schema(value_type = Object)]
+    pub categories: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct QuizScoreRow {
+    quiz: String,
+    score: u32,
+    title: String,
+    categories: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<QuizScoreRow> for QuizScore {
+    fn from(row: QuizScoreRow) -> Self {
+        QuizScore {
+            quiz: row.quiz,
+            score: row.score,
+            title: row.title,
+            categories: json::from_str(&row.categories).unwrap_or(Value::Null),
+            created_at: row.created_at,
+        }
+    }
+}
+
+impl Storage {
+    pub async fn save_quiz_score(&self, score: &QuizScore, couple: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO quiz_results (quiz, score, title, categories, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&score.quiz)
+        .bind(score.score)
+        .bind(&score.title)
+        .bind(score.categories.to_string())
+        .bind(score.created_at)
+        .bind(couple)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// All of `couple`'s quiz results, oldest first.
+    pub async fn list_quiz_scores(
+        &self,
+        couple: Option<i64>,
+    ) -> Result<Vec<QuizScore>, sqlx::Error> {
+        let rows: Vec<QuizScoreRow> = sqlx::query_as(
+            "SELECT quiz, score, title, categories, created_at FROM quiz_results \
+             WHERE couple_id IS ? ORDER BY created_at, id",
+        )
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(QuizScore::from).collect())
+    }
+}
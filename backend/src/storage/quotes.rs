@@ -0,0 +1,536 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(rename_all = "kebab-case")]
+pub enum Category {
+    Romantic,
+    Funny,
+    Poetic,
+    LongDistance,
+}
+
+impl Category {
+    pub const ALL: [Category; 4] = [
+        Category::Romantic,
+        Category::Funny,
+        Category::Poetic,
+        Category::LongDistance,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Romantic => "romantic",
+            Category::Funny => "funny",
+            Category::Poetic => "poetic",
+            Category::LongDistance => "long-distance",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::ALL
+            .into_iter()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let valid: Vec<_> = Category::ALL.iter().map(|c| c.as_str()).collect();
+                format!(
+                    "unknown category `{}`, expected one of: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+/// Where a quote is in moderation. Only approved quotes are served.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    async_graphql::Enum,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum QuoteStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl QuoteStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QuoteStatus::Pending => "pending",
+            QuoteStatus::Approved => "approved",
+            QuoteStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// A quote bundled with the binary and inserted into an empty database.
+struct SeedQuote {
+    text: &'static str,
+    category: Category,
+}
+
+const LOVE_QUOTES: &[SeedQuote] = &[
+    SeedQuote {
+        text: "You are the reason I believe in love.",
+        category: Category::Romantic,
+    },
+    SeedQuote {
+        text: "Every love story is beautiful, but ours is my favorite.",
+        category: Category::Romantic,
+    },
+    SeedQuote {
+        text: "In all the world, there is no heart for me like yours.",
+        category: Category::Poetic,
+    },
+    SeedQuote {
+        text: "I love you more than yesterday, less than tomorrow.",
+        category: Category::Romantic,
+    },
+    SeedQuote {
+        text: "You had me at hello.",
+        category: Category::Romantic,
+    },
+    SeedQuote {
+        text: "To love and be loved is to feel the sun from both sides.",
+        category: Category::Poetic,
+    },
+    SeedQuote {
+        text: "My heart is, and always will be, yours.",
+        category: Category::Romantic,
+    },
+    SeedQuote {
+        text: "I wish I could turn back the clock. I'd find you sooner and love you longer.",
+        category: Category::Romantic,
+    },
+    SeedQuote {
+        text: "You are my today and all of my tomorrows.",
+        category: Category::Poetic,
+    },
+    SeedQuote {
+        text: "I fell in love the way you fall asleep: slowly, and then all at once.",
+        category: Category::Poetic,
+    },
+    SeedQuote {
+        text: "I love you even when you steal the blankets.",
+        category: Category::Funny,
+    },
+    SeedQuote {
+        text: "You're the only person I'd share my fries with.",
+        category: Category::Funny,
+    },
+    SeedQuote {
+        text: "Distance means so little when someone means so much.",
+        category: Category::LongDistance,
+    },
+    SeedQuote {
+        text: "Same moon, same stars, different skies. Counting the days until I'm with you.",
+        category: Category::LongDistance,
+    },
+];
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct Quote {
+    pub id: i64,
+    pub text: String,
+    pub category: Category,
+    pub status: QuoteStatus,
+    /// The quote source it came from: `builtin`, `database`, or the name of
+    /// a configured file or remote source.
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, utoipa::ToSchema)]
+pub struct NewQuote {
+    pub text: String,
+    pub category: Category,
+}
+
+const QUOTE_COLUMNS: &str = "id, text, category, status, source, created_at";
+
+/// The source of the quotes bundled with the binary.
+pub const BUILTIN_SOURCE: &str = "builtin";
+/// The source of quotes added through the API, the importer or `seed`.
+pub const DATABASE_SOURCE: &str = "database";
+
+/// What syncing a quote source changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceSync {
+    /// Quotes added, or whose category changed.
+    pub changed: u64,
+    /// Quotes the source no longer lists.
+    pub removed: u64,
+}
+
+/// `sources` as a JSON array, for `IN (SELECT value FROM json_each(?))`.
+fn source_list(sources: &[&str]) -> String {
+    rocket::serde::json::serde_json::to_string(sources).expect("strings serialize")
+}
+
+impl Storage {
+    pub async fn seed_default_quotes(&self) -> Result<(), sqlx::Error> {
+        if self.count_quotes(None, None).await? > 0 {
+            return Ok(());
+        }
+
+        for seed in LOVE_QUOTES {
+            sqlx::query(
+                "INSERT INTO quotes (text, category, status, source, created_at) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(seed.text)
+            .bind(seed.category)
+            .bind(QuoteStatus::Approved)
+            .bind(BUILTIN_SOURCE)
+            .bind(Utc::now())
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Counts approved quotes in `category` (any when `None`) from any of
+    /// `sources`.
+    pub async fn count_served_quotes(
+        &self,
+        category: Option<Category>,
+        sources: &[&str],
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM quotes WHERE (?1 IS NULL OR category = ?1) AND status = ?2 \
+             AND source IN (SELECT value FROM json_each(?3))",
+        )
+        .bind(category)
+        .bind(QuoteStatus::Approved)
+        .bind(source_list(sources))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Counts quotes of every status by source.
+    pub async fn count_quotes_by_source(&self) -> Result<Vec<(String, i64)>, sqlx::Error> {
+        sqlx::query_as("SELECT source, COUNT(*) FROM quotes GROUP BY source")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Counts quotes in `category` with `status`; `None` matches any.
+    pub async fn count_quotes(
+        &self,
+        category: Option<Category>,
+        status: Option<QuoteStatus>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM quotes \
+             WHERE (?1 IS NULL OR category = ?1) AND (?2 IS NULL OR status = ?2)",
+        )
+        .bind(category)
+        .bind(status)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Quotes with `status` (any when `None`), oldest first.
+    pub async fn list_quotes(
+        &self,
+        status: Option<QuoteStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM quotes WHERE (?1 IS NULL OR status = ?1) \
+             ORDER BY id LIMIT ?2 OFFSET ?3",
+            QUOTE_COLUMNS
+        ))
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_quote(&self, id: i64) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM quotes WHERE id = ?",
+            QUOTE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Picks a uniformly random approved quote from `sources` with `rng`,
+    /// optionally restricted to one category, or `None` when no quote
+    /// matches.
+    pub async fn random_quote(
+        &self,
+        category: Option<Category>,
+        sources: &[&str],
+        rng: &mut (impl Rng + Send),
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        let count = self.count_served_quotes(category, sources).await?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let offset = rng.gen_range(0..count);
+        self.nth_quote(category, sources, offset).await
+    }
+
+    /// The approved quote at position `index` (wrapping) in id order, so a stable
+    /// seed always maps to the same quote while the pool is unchanged.
+    pub async fn quote_for_seed(
+        &self,
+        category: Option<Category>,
+        sources: &[&str],
+        seed: u64,
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        let count = self.count_served_quotes(category, sources).await?;
+        if count == 0 {
+            return Ok(None);
+        }
+
+        self.nth_quote(category, sources, (seed % count as u64) as i64)
+            .await
+    }
+
+    /// Every approved quote from `sources`, in id order.
+    pub async fn served_quotes(&self, sources: &[&str]) -> Result<Vec<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM quotes WHERE status = ?1 \
+             AND source IN (SELECT value FROM json_each(?2)) ORDER BY id",
+            QUOTE_COLUMNS
+        ))
+        .bind(QuoteStatus::Approved)
+        .bind(source_list(sources))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Up to `limit` approved quotes from `sources` containing any of
+    /// `words` (lowercase), those containing the most first.
+    pub async fn search_quotes(
+        &self,
+        words: &[&str],
+        sources: &[&str],
+        limit: i64,
+    ) -> Result<Vec<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM (SELECT *, \
+                 (SELECT COUNT(*) FROM json_each(?1) WHERE instr(lower(text), value) > 0) AS hits \
+                 FROM quotes WHERE status = ?2 AND source IN (SELECT value FROM json_each(?3))) \
+             WHERE hits > 0 ORDER BY hits DESC, id LIMIT ?4",
+            QUOTE_COLUMNS
+        ))
+        .bind(source_list(words))
+        .bind(QuoteStatus::Approved)
+        .bind(source_list(sources))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn nth_quote(
+        &self,
+        category: Option<Category>,
+        sources: &[&str],
+        offset: i64,
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM quotes WHERE (?1 IS NULL OR category = ?1) AND status = ?2 \
+             AND source IN (SELECT value FROM json_each(?3)) \
+             ORDER BY id LIMIT 1 OFFSET ?4",
+            QUOTE_COLUMNS
+        ))
+        .bind(category)
+        .bind(QuoteStatus::Approved)
+        .bind(source_list(sources))
+        .bind(offset)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn create_quote(
+        &self,
+        quote: &NewQuote,
+        status: QuoteStatus,
+    ) -> Result<Quote, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO quotes (text, category, status, created_at) VALUES (?, ?, ?, ?) \
+             RETURNING {}",
+            QUOTE_COLUMNS
+        ))
+        .bind(&quote.text)
+        .bind(quote.category)
+        .bind(status)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Inserts every quote, approved, or none of them.
+    pub async fn import_quotes(&self, quotes: &[NewQuote]) -> Result<Vec<Quote>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut created = Vec::with_capacity(quotes.len());
+        let now = Utc::now();
+
+        for quote in quotes {
+            let row = sqlx::query_as(&format!(
+                "INSERT INTO quotes (text, category, status, created_at) VALUES (?, ?, ?, ?) \
+                 RETURNING {}",
+                QUOTE_COLUMNS
+            ))
+            .bind(&quote.text)
+            .bind(quote.category)
+            .bind(QuoteStatus::Approved)
+            .bind(now)
+            .fetch_one(&mut *tx)
+            .await?;
+            created.push(row);
+        }
+
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    /// Makes `quotes` the approved quotes of `source`, in one transaction:
+    /// adds the new ones, updates changed categories and deletes the ones no
+    /// longer listed. A text another source already has stays with it.
+    pub async fn sync_quote_source(
+        &self,
+        source: &str,
+        quotes: &[NewQuote],
+    ) -> Result<SourceSync, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut sync = SourceSync::default();
+        let now = Utc::now();
+
+        for quote in quotes {
+            sync.changed += sqlx::query(
+                "INSERT INTO quotes (text, category, status, source, created_at) \
+                 VALUES (?, ?, ?, ?, ?) \
+                 ON CONFLICT (text) DO UPDATE SET category = excluded.category \
+                 WHERE quotes.source = excluded.source AND quotes.category != excluded.category",
+            )
+            .bind(&quote.text)
+            .bind(quote.category)
+            .bind(QuoteStatus::Approved)
+            .bind(source)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+
+        let texts: Vec<&str> = quotes.iter().map(|quote| quote.text.as_str()).collect();
+        sync.removed = sqlx::query(
+            "DELETE FROM quotes WHERE source = ? \
+             AND text NOT IN (SELECT value FROM json_each(?))",
+        )
+        .bind(source)
+        .bind(source_list(&texts))
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        tx.commit().await?;
+        Ok(sync)
+    }
+
+    /// Returns which of `texts` already exist in the pool.
+    pub async fn existing_quote_texts(&self, texts: &[String]) -> Result<Vec<String>, sqlx::Error> {
+        let mut existing = Vec::new();
+        for text in texts {
+            let found: Option<String> =
+                sqlx::query_scalar("SELECT text FROM quotes WHERE text = ?")
+                    .bind(text)
+                    .fetch_optional(&self.pool)
+                    .await?;
+            existing.extend(found);
+        }
+        Ok(existing)
+    }
+
+    pub async fn update_quote(
+        &self,
+        id: i64,
+        quote: &NewQuote,
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE quotes SET text = ?, category = ? WHERE id = ? RETURNING {}",
+            QUOTE_COLUMNS
+        ))
+        .bind(&quote.text)
+        .bind(quote.category)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Moves a pending quote to `status`. `None` when there is no pending
+    /// quote with `id`.
+    pub async fn moderate_quote(
+        &self,
+        id: i64,
+        status: QuoteStatus,
+    ) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE quotes SET status = ? WHERE id = ? AND status = ? RETURNING {}",
+            QUOTE_COLUMNS
+        ))
+        .bind(status)
+        .bind(id)
+        .bind(QuoteStatus::Pending)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Deletes the quote and returns it, or `None` if it did not exist.
+    pub async fn delete_quote(&self, id: i64) -> Result<Option<Quote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "DELETE FROM quotes WHERE id = ? RETURNING {}",
+            QUOTE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,81 @@
+use chrono::Utc;
+use serde::Serialize;
+
+use super::Storage;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct ReactionCount {
+    /// Set for emoji reactions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<String>,
+    /// Set for sticker reactions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sticker_id: Option<i64>,
+    pub count: i64,
+}
+
+/// What a client reacted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reaction<'a> {
+    Emoji(&'a str),
+    /// The id of a sticker from `GET /api/stickers`.
+    Sticker(i64),
+}
+
+impl Reaction<'_> {
+    /// The value of the `emoji` column, which the one-reaction-per-client
+    /// constraint is on.
+    fn key(&self) -> String {
+        match self {
+            Reaction::Emoji(emoji) => emoji.to_string(),
+            Reaction::Sticker(id) => format!("sticker:{}", id),
+        }
+    }
+
+    fn sticker_id(&self) -> Option<i64> {
+        match self {
+            Reaction::Emoji(_) => None,
+            Reaction::Sticker(id) => Some(*id),
+        }
+    }
+}
+
+impl Storage {
+    /// Records a reaction, returning false when this fingerprint already
+    /// reacted to the message the same way.
+    pub async fn add_reaction(
+        &self,
+        message_id: i64,
+        reaction: Reaction<'_>,
+        fingerprint: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO reactions (message_id, emoji, sticker_id, fingerprint, created_at) \
+             VALUES (?, ?, ?, ?, ?) ON CONFLICT (message_id, emoji, fingerprint) DO NOTHING",
+        )
+        .bind(message_id)
+        .bind(reaction.key())
+        .bind(reaction.sticker_id())
+        .bind(fingerprint)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Reaction counts for a message, most popular first.
+    pub async fn reaction_counts(
+        &self,
+        message_id: i64,
+    ) -> Result<Vec<ReactionCount>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT CASE WHEN sticker_id IS NULL THEN emoji END AS emoji, sticker_id, \
+             COUNT(*) AS count FROM reactions WHERE message_id = ? \
+             GROUP BY reactions.emoji ORDER BY count DESC, MIN(id)",
+        )
+        .bind(message_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// A dinner (or other) booking, with a reminder `remind_hours` before it by
+/// email to `remind_email` when set and to `reservation.reminder` webhooks.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Reservation {
+    pub id: i64,
+    pub place: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(rename = "time")]
+    pub reserved_for: DateTime<Utc>,
+    /// IANA zone the time is shown in, e.g. in reminder emails.
+    pub timezone: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub party_size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<String>,
+    pub remind_hours: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remind_email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reminded_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub couple_id: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewReservation {
+    pub place: String,
+    pub address: Option<String>,
+    pub reserved_for: DateTime<Utc>,
+    pub timezone: String,
+    pub party_size: Option<i64>,
+    pub confirmation: Option<String>,
+    pub remind_hours: i64,
+    pub remind_email: Option<String>,
+    pub couple_id: Option<i64>,
+}
+
+const RESERVATION_COLUMNS: &str = "id, place, address, reserved_for, timezone, party_size, \
+                                   confirmation, remind_hours, remind_email, reminded_at, \
+                                   created_at, couple_id";
+
+impl Storage {
+    pub async fn create_reservation(
+        &self,
+        reservation: &NewReservation,
+    ) -> Result<Reservation, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO reservations \
+             (place, address, reserved_for, timezone, party_size, confirmation, remind_hours, \
+              remind_email, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            RESERVATION_COLUMNS
+        ))
+        .bind(&reservation.place)
+        .bind(&reservation.address)
+        .bind(reservation.reserved_for)
+        .bind(&reservation.timezone)
+        .bind(reservation.party_size)
+        .bind(&reservation.confirmation)
+        .bind(reservation.remind_hours)
+        .bind(&reservation.remind_email)
+        .bind(Utc::now())
+        .bind(reservation.couple_id)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// The reservation with `id` if it belongs to `couple` (or is shared,
+    /// for `None`).
+    pub async fn get_reservation(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Reservation>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM reservations WHERE id = ? AND couple_id IS ?",
+            RESERVATION_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// All of `couple`'s reservations, soonest first.
+    pub async fn list_reservations(
+        &self,
+        couple: Option<i64>,
+    ) -> Result<Vec<Reservation>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM reservations WHERE couple_id IS ? ORDER BY reserved_for, id",
+            RESERVATION_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// The soonest of `couple`'s reservations at or after `from`.
+    pub async fn next_reservation(
+        &self,
+        couple: Option<i64>,
+        from: DateTime<Utc>,
+    ) -> Result<Option<Reservation>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM reservations WHERE couple_id IS ? AND reserved_for >= ? \
+             ORDER BY reserved_for, id LIMIT 1",
+            RESERVATION_COLUMNS
+        ))
+        .bind(couple)
+        .bind(from)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Reservations still ahead of `now` whose reminder has not gone out.
+    /// Which are due depends on each one's `remind_hours`, so callers work
+    /// that out.
+    pub async fn unreminded_reservations(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Reservation>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM reservations WHERE reminded_at IS NULL AND reserved_for > ? \
+             ORDER BY reserved_for, id",
+            RESERVATION_COLUMNS
+        ))
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn mark_reservation_reminded(&self, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE reservations SET reminded_at = ? WHERE id = ?")
+            .bind(Utc::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,87 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// A role granted to an account; see [`crate::roles`] for what each allows.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Moderator,
+}
+
+impl Role {
+    pub const ALL: [Role; 2] = [Role::Admin, Role::Moderator];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.as_str() == name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct RoleGrant {
+    pub user_id: i64,
+    pub role: Role,
+    pub granted_at: DateTime<Utc>,
+}
+
+impl Storage {
+    pub async fn user_roles(&self, user_id: i64) -> Result<Vec<Role>, sqlx::Error> {
+        sqlx::query_scalar("SELECT role FROM user_roles WHERE user_id = ? ORDER BY role")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    pub async fn list_role_grants(&self) -> Result<Vec<RoleGrant>, sqlx::Error> {
+        sqlx::query_as("SELECT user_id, role, granted_at FROM user_roles ORDER BY user_id, role")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Grants `role` to the user, keeping the original grant if it is
+    /// already held. Returns whether it was newly granted.
+    pub async fn grant_role(&self, user_id: i64, role: Role) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO user_roles (user_id, role, granted_at) VALUES (?, ?, ?) \
+             ON CONFLICT (user_id, role) DO NOTHING",
+        )
+        .bind(user_id)
+        .bind(role)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Returns whether the user held `role`.
+    pub async fn revoke_role(&self, user_id: i64, role: Role) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM user_roles WHERE user_id = ? AND role = ?")
+            .bind(user_id)
+            .bind(role)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,115 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{NewMessage, Storage};
+
+/// A message that stays locked until `reveal_at`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Schedule {
+    pub id: i64,
+    pub message: String,
+    #[serde(rename = "from")]
+    pub sender: String,
+    #[serde(rename = "to")]
+    pub recipient: Option<String>,
+    pub reveal_at: DateTime<Utc>,
+    /// IANA zone the sender set `reveal_at` in.
+    pub timezone: String,
+    pub revealed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub couple_id: Option<i64>,
+}
+
+const SCHEDULE_COLUMNS: &str =
+    "id, message, sender, recipient, reveal_at, timezone, revealed_at, created_at, couple_id";
+
+impl Storage {
+    fn open_schedule(&self, mut schedule: Schedule) -> Result<Schedule, sqlx::Error> {
+        schedule.message = self.open(schedule.message)?;
+        Ok(schedule)
+    }
+
+    pub async fn create_schedule(
+        &self,
+        message: &NewMessage,
+        reveal_at: DateTime<Utc>,
+        timezone: &str,
+        couple: Option<i64>,
+    ) -> Result<Schedule, sqlx::Error> {
+        let stored = sqlx::query_as(&format!(
+            "INSERT INTO schedules \
+             (message, sender, recipient, reveal_at, timezone, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(self.seal(&message.message)?)
+        .bind(&message.sender)
+        .bind(&message.recipient)
+        .bind(reveal_at)
+        .bind(timezone)
+        .bind(Utc::now())
+        .bind(couple)
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.open_schedule(stored)
+    }
+
+    pub async fn get_schedule(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Schedule>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM schedules WHERE id = ? AND couple_id IS ?",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|schedule| self.open_schedule(schedule))
+        .transpose()
+    }
+
+    /// Marks every schedule whose reveal time has passed as revealed and
+    /// returns the newly revealed rows.
+    pub async fn reveal_due_schedules(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Schedule>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE schedules SET revealed_at = ?1 \
+             WHERE revealed_at IS NULL AND reveal_at <= ?1 RETURNING {}",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|schedule| self.open_schedule(schedule))
+        .collect()
+    }
+
+    /// Every schedule set for `couple`, revealed or not, soonest reveal
+    /// first.
+    pub async fn list_schedules(&self, couple: Option<i64>) -> Result<Vec<Schedule>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM schedules WHERE couple_id IS ? ORDER BY reveal_at, id",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|schedule| self.open_schedule(schedule))
+        .collect()
+    }
+
+    pub async fn next_pending_reveal(&self) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar("SELECT MIN(reveal_at) FROM schedules WHERE revealed_at IS NULL")
+            .fetch_one(&self.pool)
+            .await
+    }
+}
@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{SealAlgorithm, Storage};
+
+/// What a recipient needs, besides their private key, to open a note.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct Envelope {
+    pub algorithm: SealAlgorithm,
+    /// Base64.
+    pub nonce: String,
+    pub recipient_key_id: i64,
+    /// The sender's key, for algorithms that authenticate the sender.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_key_id: Option<i64>,
+}
+
+/// A note sealed on a client. `ciphertext` is stored and returned exactly
+/// as it was sent.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema)]
+pub struct SealedNote {
+    pub id: i64,
+    pub sender_id: i64,
+    /// Owner of the recipient key.
+    pub recipient_id: i64,
+    #[sqlx(flatten)]
+    pub envelope: Envelope,
+    /// Base64.
+    pub ciphertext: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub couple_id: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewSealedNote {
+    pub couple_id: i64,
+    pub sender_id: i64,
+    pub envelope: Envelope,
+    pub ciphertext: String,
+}
+
+const NOTE_COLUMNS: &str = "n.id, n.sender_id, k.user_id AS recipient_id, n.algorithm, n.nonce, \
+     n.recipient_key_id, n.sender_key_id, n.ciphertext, n.created_at, n.couple_id";
+
+const NOTE_TABLES: &str = "sealed_notes n JOIN public_keys k ON k.id = n.recipient_key_id";
+
+impl Storage {
+    pub async fn create_sealed_note(
+        &self,
+        note: &NewSealedNote,
+    ) -> Result<SealedNote, sqlx::Error> {
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO sealed_notes \
+             (couple_id, sender_id, recipient_key_id, sender_key_id, algorithm, nonce, ciphertext, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING id",
+        )
+        .bind(note.couple_id)
+        .bind(note.sender_id)
+        .bind(note.envelope.recipient_key_id)
+        .bind(note.envelope.sender_key_id)
+        .bind(note.envelope.algorithm)
+        .bind(&note.envelope.nonce)
+        .bind(&note.ciphertext)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+        self.get_sealed_note(id)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    pub async fn get_sealed_note(&self, id: i64) -> Result<Option<SealedNote>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM {} WHERE n.id = ?",
+            NOTE_COLUMNS, NOTE_TABLES
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// The notes in `couple` that `user` sent or can open, newest first,
+    /// and how many there are.
+    pub async fn list_sealed_notes(
+        &self,
+        couple: i64,
+        user: i64,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(Vec<SealedNote>, i64), sqlx::Error> {
+        let filter = "n.couple_id = ?1 AND (n.sender_id = ?2 OR k.user_id = ?2)";
+        let notes = sqlx::query_as(&format!(
+            "SELECT {} FROM {} WHERE {} ORDER BY n.id DESC LIMIT ?3 OFFSET ?4",
+            NOTE_COLUMNS, NOTE_TABLES, filter
+        ))
+        .bind(couple)
+        .bind(user)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM {} WHERE {}",
+            NOTE_TABLES, filter
+        ))
+        .bind(couple)
+        .bind(user)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok((notes, total))
+    }
+
+    /// Deletes note `id`; whether it existed.
+    pub async fn delete_sealed_note(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM sealed_notes WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::Utc;
+
+use super::messages::MESSAGE_COLUMNS;
+use super::{Message, NewMessage, Storage};
+
+impl Storage {
+    /// Stores the message and links it to `slug` in one transaction, so a
+    /// slug collision leaves no orphaned message behind.
+    pub async fn create_share(
+        &self,
+        message: &NewMessage,
+        slug: &str,
+    ) -> Result<Message, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let now = Utc::now();
+
+        let stored: Message = sqlx::query_as(&format!(
+            "INSERT INTO messages \
+             (message, sender, recipient, image_url, created_at, couple_id, sticker_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            MESSAGE_COLUMNS
+        ))
+        .bind(self.seal(&message.message)?)
+        .bind(&message.sender)
+        .bind(&message.recipient)
+        .bind(&message.image_url)
+        .bind(now)
+        .bind(message.couple_id)
+        .bind(message.sticker_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query("INSERT INTO shares (slug, message_id, created_at) VALUES (?, ?, ?)")
+            .bind(slug)
+            .bind(stored.id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        self.open_message(stored)
+    }
+
+    pub async fn get_shared_message(&self, slug: &str) -> Result<Option<Message>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT m.id, m.message, m.sender, m.recipient, m.image_url, m.created_at, \
+             m.deleted_at, m.couple_id, m.sticker_id FROM shares s JOIN messages m ON m.id = s.message_id \
+             WHERE s.slug = ? AND m.deleted_at IS NULL",
+        )
+        .bind(slug)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|message| self.open_message(message))
+        .transpose()
+    }
+}
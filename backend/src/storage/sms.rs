@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// Statuses after which a text's status no longer changes, so late
+/// callbacks for earlier states are ignored.
+const FINAL_STATUSES: &str = "'delivered', 'undelivered', 'failed'";
+
+/// A text sent through the SMS provider and its latest delivery status.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct SmsMessage {
+    pub id: i64,
+    #[serde(rename = "to")]
+    pub recipient: String,
+    /// As reported by the provider, e.g. `queued`, `sent`, `delivered`.
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const SMS_COLUMNS: &str = "id, recipient, status, error_code, created_at, updated_at";
+
+impl Storage {
+    pub async fn create_sms(
+        &self,
+        provider_id: &str,
+        recipient: &str,
+        status: &str,
+        couple: Option<i64>,
+    ) -> Result<SmsMessage, sqlx::Error> {
+        let now = Utc::now();
+        sqlx::query_as(&format!(
+            "INSERT INTO sms_messages \
+             (provider_id, recipient, status, created_at, updated_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?) RETURNING {}",
+            SMS_COLUMNS
+        ))
+        .bind(provider_id)
+        .bind(recipient)
+        .bind(status)
+        .bind(now)
+        .bind(now)
+        .bind(couple)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn get_sms(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<SmsMessage>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM sms_messages WHERE id = ? AND couple_id IS ?",
+            SMS_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Texts sent to `recipient` since `since`.
+    pub async fn count_sms_since(
+        &self,
+        recipient: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sms_messages WHERE recipient = ? AND created_at > ?",
+        )
+        .bind(recipient)
+        .bind(since)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Records a status callback. `None` when no text has `provider_id` or
+    /// its status is already final.
+    pub async fn update_sms_status(
+        &self,
+        provider_id: &str,
+        status: &str,
+        error_code: Option<&str>,
+    ) -> Result<Option<SmsMessage>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE sms_messages SET status = ?, error_code = ?, updated_at = ? \
+             WHERE provider_id = ? AND status NOT IN ({}) RETURNING {}",
+            FINAL_STATUSES, SMS_COLUMNS
+        ))
+        .bind(status)
+        .bind(error_code)
+        .bind(Utc::now())
+        .bind(provider_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,149 @@
+use chrono::Utc;
+use serde::Serialize;
+
+use super::{Category, Storage};
+
+/// How often an approved quote has been served and favorited.
+#[derive(
+    Debug, Clone, PartialEq, Serialize, sqlx::FromRow, async_graphql::SimpleObject, utoipa::ToSchema,
+)]
+pub struct QuoteStat {
+    pub quote_id: i64,
+    pub text: String,
+    pub category: Category,
+    pub served: i64,
+    pub favorited: i64,
+}
+
+/// What [`Storage::top_quotes`] ranks by.
+#[derive(Debug, Clone, Copy)]
+pub enum Popularity {
+    Served,
+    Favorited,
+}
+
+const STAT_SELECT: &str = "SELECT q.id AS quote_id, q.text, q.category, \
+     COALESCE(s.served, 0) AS served, COALESCE(s.favorited, 0) AS favorited \
+     FROM quotes q LEFT JOIN quote_stats s ON s.quote_id = q.id \
+     WHERE q.status = 'approved'";
+
+impl Storage {
+    /// Adds batched `(quote_id, times served)` counts in one transaction.
+    /// Quotes deleted since they were served are skipped.
+    pub async fn add_quote_serves(&self, counts: &[(i64, i64)]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for (quote_id, served) in counts {
+            sqlx::query(
+                "INSERT INTO quote_stats (quote_id, served) SELECT id, ? FROM quotes WHERE id = ? \
+                 ON CONFLICT (quote_id) DO UPDATE SET served = served + excluded.served",
+            )
+            .bind(served)
+            .bind(quote_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    /// Records that `fingerprint` favorited the quote, returning false when
+    /// it already had.
+    pub async fn favorite_quote(
+        &self,
+        quote_id: i64,
+        fingerprint: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let added = sqlx::query(
+            "INSERT INTO quote_favorites (quote_id, fingerprint, created_at) VALUES (?, ?, ?) \
+             ON CONFLICT (quote_id, fingerprint) DO NOTHING",
+        )
+        .bind(quote_id)
+        .bind(fingerprint)
+        .bind(Utc::now())
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if added {
+            sqlx::query(
+                "INSERT INTO quote_stats (quote_id, favorited) VALUES (?, 1) \
+                 ON CONFLICT (quote_id) DO UPDATE SET favorited = favorited + 1",
+            )
+            .bind(quote_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(added)
+    }
+
+    /// Removes `fingerprint`'s favorite, returning false when there was none.
+    pub async fn unfavorite_quote(
+        &self,
+        quote_id: i64,
+        fingerprint: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let removed =
+            sqlx::query("DELETE FROM quote_favorites WHERE quote_id = ? AND fingerprint = ?")
+                .bind(quote_id)
+                .bind(fingerprint)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                > 0;
+
+        if removed {
+            sqlx::query(
+                "UPDATE quote_stats SET favorited = MAX(favorited - 1, 0) WHERE quote_id = ?",
+            )
+            .bind(quote_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    pub async fn quote_stat(&self, quote_id: i64) -> Result<Option<QuoteStat>, sqlx::Error> {
+        sqlx::query_as(&format!("{} AND q.id = ?", STAT_SELECT))
+            .bind(quote_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Stats for every approved quote, by id.
+    pub async fn list_quote_stats(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<QuoteStat>, sqlx::Error> {
+        sqlx::query_as(&format!("{} ORDER BY q.id LIMIT ? OFFSET ?", STAT_SELECT))
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// The `limit` most served or favorited approved quotes, leaving out
+    /// those at zero. Ties go to the older quote.
+    pub async fn top_quotes(
+        &self,
+        by: Popularity,
+        limit: i64,
+    ) -> Result<Vec<QuoteStat>, sqlx::Error> {
+        let column = match by {
+            Popularity::Served => "served",
+            Popularity::Favorited => "favorited",
+        };
+        sqlx::query_as(&format!(
+            "{select} AND COALESCE(s.{column}, 0) > 0 ORDER BY s.{column} DESC, q.id LIMIT ?",
+            select = STAT_SELECT,
+            column = column
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+}
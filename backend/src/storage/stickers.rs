@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Sticker {
+    pub id: i64,
+    pub pack_id: i64,
+    /// Unique within the pack.
+    pub name: String,
+    pub upload_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewSticker {
+    pub name: String,
+    pub upload_id: String,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct StickerPack {
+    pub id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    #[sqlx(skip)]
+    pub stickers: Vec<Sticker>,
+}
+
+const STICKER_COLUMNS: &str = "id, pack_id, name, upload_id";
+
+impl Storage {
+    /// Creates a pack holding `stickers`, all or nothing. Fails with a unique
+    /// violation when the pack name is taken.
+    pub async fn create_sticker_pack(
+        &self,
+        name: &str,
+        stickers: &[NewSticker],
+    ) -> Result<StickerPack, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut pack: StickerPack = sqlx::query_as(
+            "INSERT INTO sticker_packs (name, created_at) VALUES (?, ?) \
+             RETURNING id, name, created_at",
+        )
+        .bind(name)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for sticker in stickers {
+            pack.stickers.push(
+                sqlx::query_as(&format!(
+                    "INSERT INTO stickers (pack_id, name, upload_id, created_at) \
+                     VALUES (?, ?, ?, ?) RETURNING {}",
+                    STICKER_COLUMNS
+                ))
+                .bind(pack.id)
+                .bind(&sticker.name)
+                .bind(&sticker.upload_id)
+                .bind(Utc::now())
+                .fetch_one(&mut *tx)
+                .await?,
+            );
+        }
+
+        tx.commit().await?;
+        Ok(pack)
+    }
+
+    /// Adds `stickers` to pack `pack_id`, all or nothing, and returns the
+    /// updated pack, or `None` if there is no such pack. Fails with a unique
+    /// violation when a name is already in the pack.
+    pub async fn add_stickers(
+        &self,
+        pack_id: i64,
+        stickers: &[NewSticker],
+    ) -> Result<Option<StickerPack>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let exists: Option<i64> = sqlx::query_scalar("SELECT id FROM sticker_packs WHERE id = ?")
+            .bind(pack_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        for sticker in stickers {
+            sqlx::query(
+                "INSERT INTO stickers (pack_id, name, upload_id, created_at) VALUES (?, ?, ?, ?)",
+            )
+            .bind(pack_id)
+            .bind(&sticker.name)
+            .bind(&sticker.upload_id)
+            .bind(Utc::now())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        self.get_sticker_pack(pack_id).await
+    }
+
+    /// Every pack with its stickers, packs oldest first and stickers in the
+    /// order they were added.
+    pub async fn list_sticker_packs(&self) -> Result<Vec<StickerPack>, sqlx::Error> {
+        let mut packs: Vec<StickerPack> =
+            sqlx::query_as("SELECT id, name, created_at FROM sticker_packs ORDER BY id")
+                .fetch_all(&self.pool)
+                .await?;
+        let stickers: Vec<Sticker> = sqlx::query_as(&format!(
+            "SELECT {} FROM stickers ORDER BY id",
+            STICKER_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        for sticker in stickers {
+            if let Some(pack) = packs.iter_mut().find(|pack| pack.id == sticker.pack_id) {
+                pack.stickers.push(sticker);
+            }
+        }
+        Ok(packs)
+    }
+
+    pub async fn get_sticker_pack(&self, id: i64) -> Result<Option<StickerPack>, sqlx::Error> {
+        let Some(mut pack): Option<StickerPack> =
+            sqlx::query_as("SELECT id, name, created_at FROM sticker_packs WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?
+        else {
+            return Ok(None);
+        };
+        pack.stickers = sqlx::query_as(&format!(
+            "SELECT {} FROM stickers WHERE pack_id = ? ORDER BY id",
+            STICKER_COLUMNS
+        ))
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(Some(pack))
+    }
+
+    pub async fn get_sticker(&self, id: i64) -> Result<Option<Sticker>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM stickers WHERE id = ?",
+            STICKER_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Deletes a pack and its stickers. Messages keep their text but lose
+    /// the sticker, and reactions with it are removed.
+    pub async fn delete_sticker_pack(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM sticker_packs WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deletes one sticker, like [`Storage::delete_sticker_pack`] does for a
+    /// whole pack.
+    pub async fn delete_sticker(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM stickers WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,182 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+/// A location-locked message. `message` is decrypted on read whether or
+/// not the surprise has been unlocked; routes decide whether to show it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Surprise {
+    pub id: i64,
+    pub message: String,
+    pub sender: String,
+    pub recipient: Option<String>,
+    pub hint: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_m: f64,
+    pub unlocked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewSurprise {
+    pub message: String,
+    pub sender: String,
+    pub recipient: Option<String>,
+    pub hint: Option<String>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_m: f64,
+    pub couple_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum AttemptOutcome {
+    Found,
+    Missed,
+    /// Refused because it was implausibly far from the client's last try.
+    Spoofed,
+}
+
+/// Where a client said they were when they tried to unlock a surprise.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct SurpriseAttempt {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub outcome: AttemptOutcome,
+    pub created_at: DateTime<Utc>,
+}
+
+const SURPRISE_COLUMNS: &str = "id, message, sender, recipient, hint, latitude, longitude, \
+     radius_m, unlocked_at, created_at";
+
+impl Storage {
+    fn open_surprise(&self, mut surprise: Surprise) -> Result<Surprise, sqlx::Error> {
+        surprise.message = self.open(surprise.message)?;
+        Ok(surprise)
+    }
+
+    pub async fn create_surprise(&self, surprise: &NewSurprise) -> Result<Surprise, sqlx::Error> {
+        let stored = sqlx::query_as(&format!(
+            "INSERT INTO surprises \
+             (message, sender, recipient, hint, latitude, longitude, radius_m, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+            SURPRISE_COLUMNS
+        ))
+        .bind(self.seal(&surprise.message)?)
+        .bind(&surprise.sender)
+        .bind(&surprise.recipient)
+        .bind(&surprise.hint)
+        .bind(surprise.latitude)
+        .bind(surprise.longitude)
+        .bind(surprise.radius_m)
+        .bind(Utc::now())
+        .bind(surprise.couple_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.open_surprise(stored)
+    }
+
+    pub async fn get_surprise(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Surprise>, sqlx::Error> {
+        let stored: Option<Surprise> = sqlx::query_as(&format!(
+            "SELECT {} FROM surprises WHERE id = ? AND couple_id IS ?",
+            SURPRISE_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        stored.map(|s| self.open_surprise(s)).transpose()
+    }
+
+    /// `client`'s attempts on surprise `id` from `since` on and before
+    /// attempt `before`, newest first.
+    pub async fn recent_surprise_attempts(
+        &self,
+        id: i64,
+        client: &str,
+        since: DateTime<Utc>,
+        before: i64,
+    ) -> Result<Vec<SurpriseAttempt>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT latitude, longitude, outcome, created_at FROM surprise_attempts \
+             WHERE surprise_id = ? AND client = ? AND created_at >= ? AND id < ? \
+             ORDER BY id DESC",
+        )
+        .bind(id)
+        .bind(client)
+        .bind(since)
+        .bind(before)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Records an attempt by `client` on surprise `id`, as missed until
+    /// [`Storage::set_surprise_attempt_outcome`] says otherwise, unless the
+    /// client already failed `max_failed` times from `since` on. Counting
+    /// and recording are one statement, so parallel tries cannot all slip
+    /// under the limit. Returns the attempt's id, or `None` at the limit.
+    pub async fn claim_surprise_attempt(
+        &self,
+        id: i64,
+        client: &str,
+        (latitude, longitude): (f64, f64),
+        since: DateTime<Utc>,
+        max_failed: usize,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar(
+            "INSERT INTO surprise_attempts \
+             (surprise_id, client, latitude, longitude, outcome, created_at) \
+             SELECT ?1, ?2, ?3, ?4, ?5, ?6 WHERE \
+             (SELECT COUNT(*) FROM surprise_attempts WHERE surprise_id = ?1 AND client = ?2 \
+              AND created_at >= ?7 AND outcome != ?8) < ?9 \
+             RETURNING id",
+        )
+        .bind(id)
+        .bind(client)
+        .bind(latitude)
+        .bind(longitude)
+        .bind(AttemptOutcome::Missed)
+        .bind(Utc::now())
+        .bind(since)
+        .bind(AttemptOutcome::Found)
+        .bind(max_failed as i64)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn set_surprise_attempt_outcome(
+        &self,
+        attempt: i64,
+        outcome: AttemptOutcome,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE surprise_attempts SET outcome = ? WHERE id = ?")
+            .bind(outcome)
+            .bind(attempt)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks the surprise unlocked, keeping the first `unlocked_at` if it
+    /// already was, and returns it.
+    pub async fn unlock_surprise(&self, id: i64) -> Result<Option<Surprise>, sqlx::Error> {
+        let stored: Option<Surprise> = sqlx::query_as(&format!(
+            "UPDATE surprises SET unlocked_at = COALESCE(unlocked_at, ?) WHERE id = ? RETURNING {}",
+            SURPRISE_COLUMNS
+        ))
+        .bind(Utc::now())
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        stored.map(|s| self.open_surprise(s)).transpose()
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// Where a tenant's served quotes come from.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum QuotePool {
+    /// The tenant's own database: the bundled quotes and those it adds.
+    #[default]
+    Own,
+    /// The main database, including quotes synced from file and remote
+    /// sources.
+    Shared,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Tenant {
+    pub id: i64,
+    /// The subdomain or path segment the tenant is served under.
+    pub slug: String,
+    pub name: String,
+    /// Theme used when a card, email or PDF request names none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    pub quote_pool: QuotePool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewTenant {
+    pub slug: String,
+    pub name: String,
+    pub theme: Option<String>,
+    pub quote_pool: QuotePool,
+}
+
+/// The settings of a tenant that can change after it is created.
+#[derive(Debug, Clone)]
+pub struct TenantUpdate {
+    pub name: String,
+    pub theme: Option<String>,
+    pub quote_pool: QuotePool,
+}
+
+const TENANT_COLUMNS: &str = "id, slug, name, theme, quote_pool, created_at";
+
+impl Storage {
+    /// Fails with a unique violation when the slug is taken.
+    pub async fn create_tenant(&self, tenant: &NewTenant) -> Result<Tenant, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO tenants (slug, name, theme, quote_pool, created_at) \
+             VALUES (?, ?, ?, ?, ?) RETURNING {}",
+            TENANT_COLUMNS
+        ))
+        .bind(&tenant.slug)
+        .bind(&tenant.name)
+        .bind(&tenant.theme)
+        .bind(tenant.quote_pool)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn list_tenants(&self) -> Result<Vec<Tenant>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM tenants ORDER BY slug",
+            TENANT_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn update_tenant(
+        &self,
+        slug: &str,
+        update: &TenantUpdate,
+    ) -> Result<Option<Tenant>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE tenants SET name = ?, theme = ?, quote_pool = ? WHERE slug = ? RETURNING {}",
+            TENANT_COLUMNS
+        ))
+        .bind(&update.name)
+        .bind(&update.theme)
+        .bind(update.quote_pool)
+        .bind(slug)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,37 @@
+use super::Storage;
+
+impl Storage {
+    /// Stores `text` as the `lang` translation of the quote whose English
+    /// text is `source`. Returns false when no such quote exists.
+    pub async fn upsert_translation(
+        &self,
+        source: &str,
+        lang: &str,
+        text: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO quote_translations (quote_id, lang, text) \
+             SELECT id, ?2, ?3 FROM quotes WHERE text = ?1 \
+             ON CONFLICT (quote_id, lang) DO UPDATE SET text = excluded.text",
+        )
+        .bind(source)
+        .bind(lang)
+        .bind(text)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_translation(
+        &self,
+        quote_id: i64,
+        lang: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT text FROM quote_translations WHERE quote_id = ?1 AND lang = ?2")
+            .bind(quote_id)
+            .bind(lang)
+            .fetch_optional(&self.pool)
+            .await
+    }
+}
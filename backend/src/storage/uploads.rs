@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+/// Metadata of an uploaded image; the bytes live in the upload store.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Upload {
+    pub id: String,
+    pub content_type: String,
+    pub size: i64,
+    /// Hex SHA-256 of the bytes, served as the `ETag`.
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Storage {
+    pub async fn create_upload(
+        &self,
+        id: &str,
+        content_type: &str,
+        size: i64,
+        sha256: &str,
+    ) -> Result<Upload, sqlx::Error> {
+        sqlx::query_as(
+            "INSERT INTO uploads (id, content_type, size, sha256, created_at) \
+             VALUES (?, ?, ?, ?, ?) RETURNING id, content_type, size, sha256, created_at",
+        )
+        .bind(id)
+        .bind(content_type)
+        .bind(size)
+        .bind(sha256)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// An existing upload with exactly these bytes, if any.
+    pub async fn find_upload_by_sha256(&self, sha256: &str) -> Result<Option<Upload>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT id, content_type, size, sha256, created_at FROM uploads \
+             WHERE sha256 = ? ORDER BY created_at LIMIT 1",
+        )
+        .bind(sha256)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn get_upload(&self, id: &str) -> Result<Option<Upload>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT id, content_type, size, sha256, created_at FROM uploads WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
@@ -0,0 +1,257 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::Storage;
+
+/// Most accounts a couple can link.
+const COUPLE_SIZE: i64 = 2;
+
+/// A registered account. The password hash never leaves storage except
+/// through [`Storage::password_hash`].
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub email: String,
+    pub name: String,
+    pub couple_id: Option<i64>,
+    /// IANA zone set by the user, used when a request names none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Accounts created through OAuth have an empty `password_hash` and cannot
+/// sign in with a password.
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub email: String,
+    pub name: String,
+    pub password_hash: String,
+}
+
+/// Two linked accounts that share messages, dates and proposals. The invite
+/// code lets the second partner join.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Couple {
+    pub id: i64,
+    pub invite_code: String,
+    pub members: Vec<User>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct CoupleRow {
+    id: i64,
+    invite_code: String,
+    created_at: DateTime<Utc>,
+}
+
+/// Why [`Storage::join_couple`] did not link the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    UnknownCode,
+    Full,
+    AlreadyLinked,
+}
+
+#[derive(sqlx::FromRow)]
+struct Credentials {
+    #[sqlx(flatten)]
+    user: User,
+    password_hash: String,
+}
+
+pub(super) const USER_COLUMNS: &str = "id, email, name, couple_id, timezone, created_at";
+
+impl Storage {
+    pub async fn create_user(&self, user: &NewUser) -> Result<User, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "INSERT INTO users (email, name, password_hash, created_at) VALUES (?, ?, ?, ?) \
+             RETURNING {}",
+            USER_COLUMNS
+        ))
+        .bind(&user.email)
+        .bind(&user.name)
+        .bind(&user.password_hash)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn get_user(&self, id: i64) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as(&format!("SELECT {} FROM users WHERE id = ?", USER_COLUMNS))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Sets or, with `None`, clears the account's zone.
+    pub async fn set_user_timezone(
+        &self,
+        id: i64,
+        timezone: Option<&str>,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "UPDATE users SET timezone = ? WHERE id = ? RETURNING {}",
+            USER_COLUMNS
+        ))
+        .bind(timezone)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn user_by_email(&self, email: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "SELECT {} FROM users WHERE email = ?",
+            USER_COLUMNS
+        ))
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// The user an external account (`provider`, `subject`) is linked to.
+    pub async fn user_by_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT u.id, u.email, u.name, u.couple_id, u.created_at \
+             FROM identities i JOIN users u ON u.id = i.user_id \
+             WHERE i.provider = ? AND i.subject = ?",
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn link_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        user_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO identities (provider, subject, user_id, created_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(provider)
+        .bind(subject)
+        .bind(user_id)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// The account registered with `email` (case-insensitive) and its
+    /// password hash, for checking a login.
+    pub async fn password_hash(&self, email: &str) -> Result<Option<(User, String)>, sqlx::Error> {
+        let row: Option<Credentials> = sqlx::query_as(&format!(
+            "SELECT {}, password_hash FROM users WHERE email = ?",
+            USER_COLUMNS
+        ))
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| (row.user, row.password_hash)))
+    }
+
+    /// Starts a couple with `user_id` as its first member. `None` if the
+    /// account is already in a couple.
+    pub async fn create_couple(
+        &self,
+        user_id: i64,
+        invite_code: &str,
+    ) -> Result<Option<Couple>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let row: CoupleRow = sqlx::query_as(
+            "INSERT INTO couples (invite_code, created_at) VALUES (?, ?) \
+             RETURNING id, invite_code, created_at",
+        )
+        .bind(invite_code)
+        .bind(Utc::now())
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let linked =
+            sqlx::query("UPDATE users SET couple_id = ? WHERE id = ? AND couple_id IS NULL")
+                .bind(row.id)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        if linked == 0 {
+            return Ok(None);
+        }
+        tx.commit().await?;
+
+        self.get_couple(row.id).await
+    }
+
+    /// Links `user_id` to the couple with `invite_code`.
+    pub async fn join_couple(
+        &self,
+        user_id: i64,
+        invite_code: &str,
+    ) -> Result<Result<Couple, JoinError>, sqlx::Error> {
+        let couple_id: Option<i64> =
+            sqlx::query_scalar("SELECT id FROM couples WHERE invite_code = ?")
+                .bind(invite_code)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(couple_id) = couple_id else {
+            return Ok(Err(JoinError::UnknownCode));
+        };
+
+        let linked = sqlx::query(
+            "UPDATE users SET couple_id = ?1 WHERE id = ?2 AND couple_id IS NULL \
+             AND (SELECT COUNT(*) FROM users WHERE couple_id = ?1) < ?3",
+        )
+        .bind(couple_id)
+        .bind(user_id)
+        .bind(COUPLE_SIZE)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        if linked == 0 {
+            let user = self.get_user(user_id).await?;
+            return Ok(Err(match user.and_then(|u| u.couple_id) {
+                Some(_) => JoinError::AlreadyLinked,
+                None => JoinError::Full,
+            }));
+        }
+
+        let couple = self.get_couple(couple_id).await?;
+        Ok(couple.ok_or(JoinError::UnknownCode))
+    }
+
+    pub async fn get_couple(&self, id: i64) -> Result<Option<Couple>, sqlx::Error> {
+        let row: Option<CoupleRow> =
+            sqlx::query_as("SELECT id, invite_code, created_at FROM couples WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let members = sqlx::query_as(&format!(
+            "SELECT {} FROM users WHERE couple_id = ? ORDER BY id",
+            USER_COLUMNS
+        ))
+        .bind(row.id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(Couple {
+            id: row.id,
+            invite_code: row.invite_code,
+            members,
+            created_at: row.created_at,
+        }))
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+/// An "open when..." letter. `body` is decrypted on read whether or not the
+/// letter has been opened; routes decide whether to show it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct VaultLetter {
+    pub id: i64,
+    pub condition: String,
+    pub body: String,
+    pub sender: String,
+    pub opened_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewVaultLetter {
+    pub condition: String,
+    pub body: String,
+    pub sender: String,
+    pub couple_id: Option<i64>,
+}
+
+const VAULT_COLUMNS: &str = "id, condition, body, sender, opened_at, created_at";
+
+impl Storage {
+    fn open_letter(&self, mut letter: VaultLetter) -> Result<VaultLetter, sqlx::Error> {
+        letter.body = self.open(letter.body)?;
+        Ok(letter)
+    }
+
+    pub async fn create_vault_letter(
+        &self,
+        letter: &NewVaultLetter,
+    ) -> Result<VaultLetter, sqlx::Error> {
+        let stored = sqlx::query_as(&format!(
+            "INSERT INTO vault_letters (condition, body, sender, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?) RETURNING {}",
+            VAULT_COLUMNS
+        ))
+        .bind(&letter.condition)
+        .bind(self.seal(&letter.body)?)
+        .bind(&letter.sender)
+        .bind(Utc::now())
+        .bind(letter.couple_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        self.open_letter(stored)
+    }
+
+    /// All of `couple`'s letters, oldest first.
+    pub async fn list_vault_letters(
+        &self,
+        couple: Option<i64>,
+    ) -> Result<Vec<VaultLetter>, sqlx::Error> {
+        let letters: Vec<VaultLetter> = sqlx::query_as(&format!(
+            "SELECT {} FROM vault_letters WHERE couple_id IS ? ORDER BY id",
+            VAULT_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await?;
+
+        letters.into_iter().map(|l| self.open_letter(l)).collect()
+    }
+
+    /// Marks the letter opened, keeping the first `opened_at` if it already
+    /// was, and returns it. `None` when it is not one of `couple`'s.
+    pub async fn open_vault_letter(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<VaultLetter>, sqlx::Error> {
+        let stored: Option<VaultLetter> = sqlx::query_as(&format!(
+            "UPDATE vault_letters SET opened_at = COALESCE(opened_at, ?) \
+             WHERE id = ? AND couple_id IS ? RETURNING {}",
+            VAULT_COLUMNS
+        ))
+        .bind(Utc::now())
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        stored.map(|l| self.open_letter(l)).transpose()
+    }
+}
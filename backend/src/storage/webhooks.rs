@@ -0,0 +1,264 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Storage;
+
+/// Events a webhook can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub enum WebhookEvent {
+    #[serde(rename = "message.created")]
+    MessageCreated,
+    #[serde(rename = "proposal.answered")]
+    ProposalAnswered,
+    #[serde(rename = "date.reminder")]
+    DateReminder,
+    #[serde(rename = "reservation.reminder")]
+    ReservationReminder,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 4] = [
+        WebhookEvent::MessageCreated,
+        WebhookEvent::ProposalAnswered,
+        WebhookEvent::DateReminder,
+        WebhookEvent::ReservationReminder,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::MessageCreated => "message.created",
+            WebhookEvent::ProposalAnswered => "proposal.answered",
+            WebhookEvent::DateReminder => "date.reminder",
+            WebhookEvent::ReservationReminder => "reservation.reminder",
+        }
+    }
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        WebhookEvent::ALL
+            .into_iter()
+            .find(|e| e.as_str() == s)
+            .ok_or_else(|| format!("unknown webhook event `{}`", s))
+    }
+}
+
+/// A registered callback URL. Its secret is only returned on creation.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct WebhookRow {
+    id: i64,
+    url: String,
+    events: String,
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            url: row.url,
+            // Unknown names can only come from a newer build; skip them.
+            events: row
+                .events
+                .split(',')
+                .filter_map(|e| e.parse().ok())
+                .collect(),
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// A pending attempt to POST one event to one webhook.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Delivery {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i64,
+}
+
+const WEBHOOK_COLUMNS: &str = "id, url, events, created_at";
+
+impl Storage {
+    pub async fn create_webhook(
+        &self,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        couple: Option<i64>,
+    ) -> Result<Webhook, sqlx::Error> {
+        let events: Vec<_> = events.iter().map(|e| e.as_str()).collect();
+        let row: WebhookRow = sqlx::query_as(&format!(
+            "INSERT INTO webhooks (url, secret, events, created_at, couple_id) \
+             VALUES (?, ?, ?, ?, ?) RETURNING {}",
+            WEBHOOK_COLUMNS
+        ))
+        .bind(url)
+        .bind(secret)
+        .bind(events.join(","))
+        .bind(Utc::now())
+        .bind(couple)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    pub async fn list_webhooks(&self, couple: Option<i64>) -> Result<Vec<Webhook>, sqlx::Error> {
+        let rows: Vec<WebhookRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM webhooks WHERE couple_id IS ? ORDER BY id",
+            WEBHOOK_COLUMNS
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    pub async fn get_webhook(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Webhook>, sqlx::Error> {
+        let row: Option<WebhookRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM webhooks WHERE id = ? AND couple_id IS ?",
+            WEBHOOK_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(Webhook::from))
+    }
+
+    /// Removes a webhook and its pending deliveries, returning the webhook.
+    /// `None` if it did not exist in `couple`.
+    pub async fn delete_webhook(
+        &self,
+        id: i64,
+        couple: Option<i64>,
+    ) -> Result<Option<Webhook>, sqlx::Error> {
+        let row: Option<WebhookRow> = sqlx::query_as(&format!(
+            "DELETE FROM webhooks WHERE id = ? AND couple_id IS ? RETURNING {}",
+            WEBHOOK_COLUMNS
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(Webhook::from))
+    }
+
+    /// Queues `payload` for every webhook of `couple` subscribed to `event`,
+    /// due immediately. Returns how many deliveries were queued.
+    pub async fn enqueue_deliveries(
+        &self,
+        couple: Option<i64>,
+        event: WebhookEvent,
+        payload: &str,
+    ) -> Result<u64, sqlx::Error> {
+        let now = Utc::now();
+        // Payloads can carry message bodies, so they are sealed like them.
+        let result = sqlx::query(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload, next_attempt_at, created_at) \
+             SELECT id, ?1, ?2, ?3, ?3 FROM webhooks \
+             WHERE instr(',' || events || ',', ',' || ?1 || ',') > 0 AND couple_id IS ?4",
+        )
+        .bind(event.as_str())
+        .bind(self.seal(payload)?)
+        .bind(now)
+        .bind(couple)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<Delivery>, sqlx::Error> {
+        let deliveries: Vec<Delivery> = sqlx::query_as(
+            "SELECT d.id, w.url, w.secret, d.event, d.payload, d.attempts \
+             FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id \
+             WHERE d.next_attempt_at <= ? ORDER BY d.next_attempt_at LIMIT ?",
+        )
+        .bind(now)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        deliveries
+            .into_iter()
+            .map(|mut delivery| {
+                delivery.payload = self.open(delivery.payload)?;
+                Ok(delivery)
+            })
+            .collect()
+    }
+
+    pub async fn mark_delivered(&self, id: i64, attempts: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE webhook_deliveries \
+             SET attempts = ?, delivered_at = ?, next_attempt_at = NULL, last_error = NULL \
+             WHERE id = ?",
+        )
+        .bind(attempts)
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// Records a failed attempt. `retry_at` of `None` gives up on the delivery.
+    pub async fn mark_failed(
+        &self,
+        id: i64,
+        attempts: i64,
+        retry_at: Option<DateTime<Utc>>,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE webhook_deliveries SET attempts = ?, next_attempt_at = ?, last_error = ? \
+             WHERE id = ?",
+        )
+        .bind(attempts)
+        .bind(retry_at)
+        .bind(error)
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn next_delivery_attempt(&self) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar("SELECT MIN(next_attempt_at) FROM webhook_deliveries")
+            .fetch_one(&self.pool)
+            .await
+    }
+}
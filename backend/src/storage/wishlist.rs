@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+
+use super::Storage;
+
+/// An item on a partner's wishlist, reservation included. Only ever shown
+/// to the owner after the reservation is stripped.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct WishlistItem {
+    pub id: i64,
+    pub owner_id: i64,
+    pub owner_name: String,
+    pub title: String,
+    pub url: Option<String>,
+    pub note: Option<String>,
+    pub price: Option<i64>,
+    pub reserved_by: Option<i64>,
+    pub reserved_for: Option<String>,
+    pub reserved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewWishlistItem {
+    pub title: String,
+    pub url: Option<String>,
+    pub note: Option<String>,
+    pub price: Option<i64>,
+}
+
+/// Why a reservation could not be made or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WishlistError {
+    Unknown,
+    OwnItem,
+    Reserved,
+    NotReserved,
+}
+
+const WISHLIST_SELECT: &str = "SELECT w.id, w.owner_id, u.name AS owner_name, w.title, w.url, \
+     w.note, w.price, w.reserved_by, w.reserved_for, w.reserved_at, w.created_at \
+     FROM wishlist_items w JOIN users u ON u.id = w.owner_id";
+
+impl Storage {
+    pub async fn create_wishlist_item(
+        &self,
+        item: &NewWishlistItem,
+        couple: i64,
+        owner: i64,
+    ) -> Result<WishlistItem, sqlx::Error> {
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO wishlist_items (couple_id, owner_id, title, url, note, price, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) RETURNING id",
+        )
+        .bind(couple)
+        .bind(owner)
+        .bind(&item.title)
+        .bind(&item.url)
+        .bind(&item.note)
+        .bind(item.price)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+        self.get_wishlist_item(id, couple)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    pub async fn get_wishlist_item(
+        &self,
+        id: i64,
+        couple: i64,
+    ) -> Result<Option<WishlistItem>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "{} WHERE w.id = ? AND w.couple_id = ?",
+            WISHLIST_SELECT
+        ))
+        .bind(id)
+        .bind(couple)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Both partners' items, by owner and then oldest first.
+    pub async fn list_wishlist(&self, couple: i64) -> Result<Vec<WishlistItem>, sqlx::Error> {
+        sqlx::query_as(&format!(
+            "{} WHERE w.couple_id = ? ORDER BY w.owner_id, w.id",
+            WISHLIST_SELECT
+        ))
+        .bind(couple)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Returns whether `owner` had an item with that id.
+    pub async fn delete_wishlist_item(&self, id: i64, owner: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM wishlist_items WHERE id = ? AND owner_id = ?")
+            .bind(id)
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Reserves item `id` for `user`, who must not own it, unless it
+    /// already is reserved.
+    pub async fn reserve_wishlist_item(
+        &self,
+        id: i64,
+        couple: i64,
+        user: i64,
+        reserved_for: Option<&str>,
+    ) -> Result<Result<WishlistItem, WishlistError>, sqlx::Error> {
+        let reserved = sqlx::query(
+            "UPDATE wishlist_items SET reserved_by = ?, reserved_for = ?, reserved_at = ? \
+             WHERE id = ? AND couple_id = ? AND owner_id != ? AND reserved_at IS NULL",
+        )
+        .bind(user)
+        .bind(reserved_for)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(couple)
+        .bind(user)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        let Some(item) = self.get_wishlist_item(id, couple).await? else {
+            return Ok(Err(WishlistError::Unknown));
+        };
+        Ok(match (reserved > 0, item.owner_id == user) {
+            (true, _) => Ok(item),
+            (false, true) => Err(WishlistError::OwnItem),
+            (false, false) => Err(WishlistError::Reserved),
+        })
+    }
+
+    /// Releases `user`'s reservation of item `id`.
+    pub async fn release_wishlist_item(
+        &self,
+        id: i64,
+        couple: i64,
+        user: i64,
+    ) -> Result<Result<WishlistItem, WishlistError>, sqlx::Error> {
+        let released = sqlx::query(
+            "UPDATE wishlist_items SET reserved_by = NULL, reserved_for = NULL, \
+             reserved_at = NULL \
+             WHERE id = ? AND couple_id = ? AND owner_id != ? AND reserved_by = ?",
+        )
+        .bind(id)
+        .bind(couple)
+        .bind(user)
+        .bind(user)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        let Some(item) = self.get_wishlist_item(id, couple).await? else {
+            return Ok(Err(WishlistError::Unknown));
+        };
+        Ok(match (released > 0, item.owner_id == user) {
+            (true, _) => Ok(item),
+            (false, true) => Err(WishlistError::OwnItem),
+            (false, false) => Err(WishlistError::NotReserved),
+        })
+    }
+}
@@ -0,0 +1,483 @@
+//! Geofenced surprises: a message pinned to a place that is only revealed
+//! to someone who is standing there, for scavenger hunts and proposals.
+//! Reported locations are easy to fake, so each client (the couple for
+//! their own surprises, else the address) gets a few failed tries at a
+//! time on a surprise, and a location further from that client's last try
+//! than anyone could have travelled is refused.
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::content_filter::ContentFilter;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{AttemptOutcome, NewSurprise, Storage, Surprise};
+use crate::users::CoupleScope;
+use crate::valentine::{MAX_MESSAGE_LEN, MAX_NAME_LEN};
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_HINT_LEN: usize = 200;
+
+const MIN_RADIUS_M: f64 = 10.0;
+const MAX_RADIUS_M: f64 = 5000.0;
+const DEFAULT_RADIUS_M: f64 = 50.0;
+
+/// Mean radius of the Earth, in metres.
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+/// Great-circle distance in metres between two points given in degrees.
+pub fn haversine(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let (dlat, dlon) = ((to.0 - from.0).to_radians(), (to.1 - from.1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+}
+
+/// The `[default.surprise]` table in Rocket.toml.
+#[derive(Debug, Clone, Deserialize)]
+struct SurpriseConfig {
+    /// Failed unlocks each couple, or each address for unscoped surprises,
+    /// is allowed on one surprise within `window_minutes`.
+    #[serde(default = "default_max_attempts")]
+    max_attempts: usize,
+    #[serde(default = "default_window_minutes")]
+    window_minutes: i64,
+    /// The fastest believable travel between two tries, in metres per
+    /// second; about an airliner's by default.
+    #[serde(default = "default_max_speed")]
+    max_speed_mps: f64,
+}
+
+fn default_max_attempts() -> usize {
+    10
+}
+
+fn default_window_minutes() -> i64 {
+    60
+}
+
+fn default_max_speed() -> f64 {
+    250.0
+}
+
+impl Default for SurpriseConfig {
+    fn default() -> Self {
+        SurpriseConfig {
+            max_attempts: default_max_attempts(),
+            window_minutes: default_window_minutes(),
+            max_speed_mps: default_max_speed(),
+        }
+    }
+}
+
+fn check_coordinates(errors: &mut FieldErrors, latitude: f64, longitude: f64) {
+    if !(-90.0..=90.0).contains(&latitude) {
+        errors.add("latitude", "`latitude` must be between -90 and 90");
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        errors.add("longitude", "`longitude` must be between -180 and 180");
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct SurpriseRequest {
+    message: String,
+    from: String,
+    to: Option<String>,
+    /// Shown before the surprise is unlocked, e.g. "where we first met".
+    hint: Option<String>,
+    latitude: f64,
+    longitude: f64,
+    /// How close, in metres, the finder must be: 10-5000, 50 by default.
+    radius_m: Option<f64>,
+}
+
+impl Validate for SurpriseRequest {
+    type Valid = NewSurprise;
+
+    fn validate(self) -> Result<NewSurprise, FieldErrors> {
+        let message = self.message.trim().to_string();
+        let sender = self.from.trim().to_string();
+        let optional = |value: Option<String>| {
+            value
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let (recipient, hint) = (optional(self.to), optional(self.hint));
+        let radius_m = self.radius_m.unwrap_or(DEFAULT_RADIUS_M);
+
+        let mut errors = FieldErrors::new();
+        errors.text("message", &message, MAX_MESSAGE_LEN);
+        errors.text("from", &sender, MAX_NAME_LEN);
+        if let Some(recipient) = &recipient {
+            errors.text("to", recipient, MAX_NAME_LEN);
+        }
+        if let Some(hint) = &hint {
+            errors.text("hint", hint, MAX_HINT_LEN);
+        }
+        check_coordinates(&mut errors, self.latitude, self.longitude);
+        if !(MIN_RADIUS_M..=MAX_RADIUS_M).contains(&radius_m) {
+            errors.add(
+                "radius_m",
+                format!(
+                    "`radius_m` must be between {} and {}",
+                    MIN_RADIUS_M, MAX_RADIUS_M
+                ),
+            );
+        }
+        errors.finish(NewSurprise {
+            message,
+            sender,
+            recipient,
+            hint,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            radius_m,
+            couple_id: None,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct UnlockRequest {
+    latitude: f64,
+    longitude: f64,
+    /// The device's reported accuracy in metres; refused when it is
+    /// coarser than the surprise's radius.
+    accuracy_m: f64,
+}
+
+impl Validate for UnlockRequest {
+    type Valid = UnlockRequest;
+
+    fn validate(self) -> Result<UnlockRequest, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        check_coordinates(&mut errors, self.latitude, self.longitude);
+        if self.accuracy_m < 0.0 {
+            errors.add("accuracy_m", "`accuracy_m` must not be negative");
+        }
+        errors.finish(self)
+    }
+}
+
+/// A surprise as clients see it. Its location is never returned, and
+/// `message` only once it is unlocked.
+#[derive(Serialize, utoipa::ToSchema)]
+struct SurpriseResponse {
+    id: i64,
+    from: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+    radius_m: f64,
+    unlocked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unlocked_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<Surprise> for SurpriseResponse {
+    fn from(surprise: Surprise) -> Self {
+        let unlocked = surprise.unlocked_at.is_some();
+        SurpriseResponse {
+            id: surprise.id,
+            from: surprise.sender,
+            to: surprise.recipient,
+            hint: surprise.hint,
+            radius_m: surprise.radius_m,
+            unlocked,
+            unlocked_at: surprise.unlocked_at,
+            message: unlocked.then_some(surprise.message),
+            created_at: surprise.created_at,
+        }
+    }
+}
+
+/// Pins a message to a place. Neither the message nor the place is
+/// returned, not even here.
+#[utoipa::path(
+    tag = "surprises",
+    request_body = SurpriseRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = SurpriseResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/surprise", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    scope: CoupleScope,
+    request: Valid<SurpriseRequest>,
+) -> ApiResult<status::Created<Negotiated<SurpriseResponse>>> {
+    let mut surprise = request.into_inner();
+    let mut fields = vec![
+        ("message", surprise.message.as_str()),
+        ("from", surprise.sender.as_str()),
+    ];
+    if let Some(recipient) = &surprise.recipient {
+        fields.push(("to", recipient.as_str()));
+    }
+    if let Some(hint) = &surprise.hint {
+        fields.push(("hint", hint.as_str()));
+    }
+    filter.screen(&fields).await?;
+
+    surprise.couple_id = scope.0;
+    let surprise = storage
+        .create_surprise(&surprise)
+        .await
+        .map_err(internal_error)?;
+    let location = uri!(get(surprise.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(surprise.into())))
+}
+
+async fn find(storage: &Storage, scope: CoupleScope, id: i64) -> ApiResult<Surprise> {
+    storage
+        .get_surprise(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no surprise with id {}", id)))
+}
+
+#[utoipa::path(
+    tag = "surprises",
+    params(("id" = i64, Path)),
+    responses(
+        (status = 200, body = SurpriseResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/surprise/<id>")]
+async fn get(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<SurpriseResponse>> {
+    find(storage, scope, id)
+        .await
+        .map(|surprise| Negotiated(surprise.into()))
+}
+
+/// Reveals the surprise when the given coordinates are within its radius.
+/// Once unlocked it stays unlocked, and `GET /api/surprise/<id>` shows the
+/// message too.
+#[utoipa::path(
+    tag = "surprises",
+    params(("id" = i64, Path)),
+    request_body = UnlockRequest,
+    responses(
+        (status = 200, body = SurpriseResponse),
+        (status = 400, description = "Neither signed in to the surprise's couple nor from a known address", body = ErrorResponse),
+        (status = 403, description = "Not close enough, or the location jumped implausibly far", body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 422, description = "Invalid coordinates, or accuracy coarser than the radius", body = ErrorResponse),
+        (status = 429, description = "Too many failed tries; wait and try again", body = ErrorResponse),
+    )
+)]
+#[post("/api/surprise/<id>/unlock", data = "<request>")]
+async fn unlock(
+    storage: &Storage,
+    config: &State<SurpriseConfig>,
+    scope: CoupleScope,
+    ip: Option<IpAddr>,
+    id: i64,
+    request: Valid<UnlockRequest>,
+) -> ApiResult<Negotiated<SurpriseResponse>> {
+    let surprise = find(storage, scope, id).await?;
+    if surprise.unlocked_at.is_some() {
+        return Ok(Negotiated(surprise.into()));
+    }
+    let request = request.into_inner();
+    if request.accuracy_m > surprise.radius_m {
+        return Err(error(
+            Status::UnprocessableEntity,
+            "your location is not accurate enough; try again outdoors",
+        ));
+    }
+
+    // Tries are counted against the couple a scoped surprise belongs to,
+    // since only its partners can see it, and against the address for
+    // unscoped ones.
+    let client = match (scope.0, ip) {
+        (Some(couple), _) => format!("couple:{}", couple),
+        (None, Some(ip)) => format!("ip:{}", ip),
+        (None, None) => {
+            return Err(error(
+                Status::BadRequest,
+                "sign in or connect from a known address to unlock this surprise",
+            ))
+        }
+    };
+    let here = (request.latitude, request.longitude);
+    let now = Utc::now();
+    let since = now - Duration::minutes(config.window_minutes);
+    let Some(attempt) = storage
+        .claim_surprise_attempt(id, &client, here, since, config.max_attempts)
+        .await
+        .map_err(internal_error)?
+    else {
+        return Err(error(
+            Status::TooManyRequests,
+            "too many tries on this surprise; wait a while and try again",
+        ));
+    };
+    let attempts = storage
+        .recent_surprise_attempts(id, &client, since, attempt)
+        .await
+        .map_err(internal_error)?;
+    let failed = attempts
+        .iter()
+        .filter(|a| a.outcome != AttemptOutcome::Found)
+        .count();
+
+    // Spoofed tries are not where the client was, so the next try is
+    // measured from the last believable one.
+    let last = attempts
+        .iter()
+        .find(|a| a.outcome != AttemptOutcome::Spoofed);
+    if let Some(last) = last {
+        let moved = haversine((last.latitude, last.longitude), here);
+        let seconds = (now - last.created_at).num_milliseconds().max(1000) as f64 / 1000.0;
+        if moved / seconds > config.max_speed_mps {
+            storage
+                .set_surprise_attempt_outcome(attempt, AttemptOutcome::Spoofed)
+                .await
+                .map_err(internal_error)?;
+            return Err(error(
+                Status::Forbidden,
+                "your location moved further than anyone could have travelled since the last try",
+            ));
+        }
+    }
+
+    let found = haversine((surprise.latitude, surprise.longitude), here) <= surprise.radius_m;
+    if !found {
+        return Err(error(
+            Status::Forbidden,
+            format!(
+                "not there yet; {} tries left",
+                config.max_attempts.saturating_sub(failed + 1)
+            ),
+        ));
+    }
+    storage
+        .set_surprise_attempt_outcome(attempt, AttemptOutcome::Found)
+        .await
+        .map_err(internal_error)?;
+
+    storage
+        .unlock_surprise(id)
+        .await
+        .map_err(internal_error)?
+        .map(|surprise| Negotiated(surprise.into()))
+        .ok_or_else(|| error(Status::NotFound, format!("no surprise with id {}", id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, get, unlock]
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Surprises", |rocket| async {
+        match rocket.figment().extract_inner::<SurpriseConfig>("surprise") {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) if e.missing() => Ok(rocket.manage(SurpriseConfig::default())),
+            Err(e) => {
+                error!("invalid surprise config: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_and_fences_are_checked_in_metres() {
+        let eiffel = (48.8584, 2.2945);
+        let louvre = (48.8606, 2.3376);
+        let distance = haversine(eiffel, louvre);
+        assert!((distance - 3160.0).abs() < 20.0, "{}", distance);
+        assert_eq!(haversine(eiffel, eiffel), 0.0);
+        let antipode = haversine((0.0, 0.0), (0.0, 180.0));
+        assert!((antipode - std::f64::consts::PI * EARTH_RADIUS_M).abs() < 1.0);
+
+        let request = |latitude, radius_m| SurpriseRequest {
+            message: " Will you marry me? ".to_string(),
+            from: "Alex".to_string(),
+            to: None,
+            hint: Some("  ".to_string()),
+            latitude,
+            longitude: 2.2945,
+            radius_m,
+        };
+        let surprise = request(48.8584, None).validate().unwrap();
+        assert_eq!(surprise.message, "Will you marry me?");
+        assert_eq!(surprise.radius_m, DEFAULT_RADIUS_M);
+        assert_eq!(surprise.hint, None);
+        let errors = request(91.0, Some(1.0)).validate().unwrap_err().to_string();
+        assert!(errors.contains("`latitude`"), "{}", errors);
+        assert!(errors.contains("`radius_m`"), "{}", errors);
+    }
+
+    #[rocket::async_test]
+    async fn parallel_tries_share_the_attempt_limit() {
+        let config = crate::storage::PoolConfig {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let storage = Storage::connect_with("sqlite::memory:", &config)
+            .await
+            .unwrap();
+        storage.migrate().await.unwrap();
+        let surprise = SurpriseRequest {
+            message: "Look up".to_string(),
+            from: "Alex".to_string(),
+            to: None,
+            hint: None,
+            latitude: 48.8584,
+            longitude: 2.2945,
+            radius_m: None,
+        }
+        .validate()
+        .unwrap();
+        let id = storage.create_surprise(&surprise).await.unwrap().id;
+
+        let since = Utc::now() - Duration::minutes(60);
+        let claim = |client| storage.claim_surprise_attempt(id, client, (0.0, 0.0), since, 2);
+        let claims = rocket::futures::future::join_all([
+            claim("ip:192.0.2.1"),
+            claim("ip:192.0.2.1"),
+            claim("ip:192.0.2.1"),
+            claim("couple:1"),
+        ])
+        .await;
+        let claimed: Vec<bool> = claims.into_iter().map(|c| c.unwrap().is_some()).collect();
+        assert_eq!(claimed, [true, true, false, true]);
+
+        // A try that found the surprise does not count against the limit.
+        let attempt = claim("couple:1").await.unwrap().unwrap();
+        storage
+            .set_surprise_attempt_outcome(attempt, AttemptOutcome::Found)
+            .await
+            .unwrap();
+        assert!(claim("couple:1").await.unwrap().is_some());
+        assert!(claim("couple:1").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,139 @@
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use tracing_subscriber::EnvFilter;
+
+use crate::tokens;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Used when neither `RUST_LOG` nor `log_filter` is set. Rocket's own per-request chatter is
+/// replaced by the single line [`RequestTracing`] emits per request.
+const DEFAULT_FILTER: &str = "info,rocket::server=warn,rocket::response=warn";
+
+/// Incoming request ids longer than this (or with odd characters) are
+/// replaced rather than echoed back.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Installs a JSON `tracing` subscriber as the global logger, filtering with
+/// `RUST_LOG`, else `filter` (the profile's `log_filter`), else
+/// [`DEFAULT_FILTER`]. Rocket's
+/// `log` records are bridged into it, so Rocket's built-in logger stays
+/// inactive. A subscriber installed earlier in the process, as when tests
+/// build several servers, is kept.
+pub fn init(filter: Option<&str>) {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(filter.unwrap_or(DEFAULT_FILTER)))
+        .unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    if let Err(e) = tracing_subscriber::fmt()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_env_filter(filter)
+        .try_init()
+    {
+        tracing::debug!("keeping the existing subscriber: {}", e);
+    }
+}
+
+/// Per-request state stashed in the local cache by [`RequestTracing`].
+struct RequestSpan {
+    id: String,
+    /// Captured before other fairings (e.g. the rate limiter) rewrite the URI.
+    path: String,
+    started: Instant,
+}
+
+/// The span cached by [`RequestTracing::on_request`], or a fresh one for
+/// requests that never reached it.
+fn span<'r>(request: &'r Request<'_>) -> &'r RequestSpan {
+    request.local_cache(|| RequestSpan {
+        id: tokens::random_token(16),
+        path: request.uri().to_string(),
+        started: Instant::now(),
+    })
+}
+
+/// The id echoed in the `X-Request-Id` response header.
+pub fn request_id<'r>(request: &'r Request<'_>) -> &'r str {
+    &span(request).id
+}
+
+fn accept_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+/// Assigns every request an id (reusing a well-formed incoming
+/// `X-Request-Id`), echoes it on the response and logs one structured line
+/// with the route, status and latency.
+pub struct RequestTracing;
+
+#[rocket::async_trait]
+impl Fairing for RequestTracing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .filter(|id| accept_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(|| tokens::random_token(16));
+
+        let path = request.uri().to_string();
+        request.local_cache(|| RequestSpan {
+            id,
+            path,
+            started: Instant::now(),
+        });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let span = span(request);
+
+        let route = request.route();
+        let status = response.status().code;
+        let latency_ms = span.started.elapsed().as_secs_f64() * 1000.0;
+
+        tracing::info!(
+            target: "http",
+            request_id = %span.id,
+            method = %request.method(),
+            path = %span.path,
+            route = route.and_then(|r| r.name.as_deref()).unwrap_or(""),
+            route_uri = route.map(|r| r.uri.to_string()).unwrap_or_default(),
+            status,
+            latency_ms,
+            client_ip = request.client_ip().map(|ip| ip.to_string()).unwrap_or_default(),
+            "request completed"
+        );
+
+        response.set_header(Header::new(REQUEST_ID_HEADER, span.id.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_ids_are_validated() {
+        assert!(accept_request_id("3f2c9a-req_01.a:b"));
+        assert!(!accept_request_id(""));
+        assert!(!accept_request_id("has space"));
+        assert!(!accept_request_id("line\nbreak"));
+        assert!(!accept_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+}
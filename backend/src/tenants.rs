@@ -0,0 +1,604 @@
+//! Hosting several sites from one server. With a `[default.tenancy]` table
+//! each tenant gets its own SQLite database in `dir`, so everything a
+//! request reads or writes is scoped to its tenant. Requests are matched to
+//! a tenant by subdomain (`<slug>.<domain>`) or by a `/t/<slug>` path
+//! prefix; anything else is the main site, whose database lists the
+//! tenants. A tenant can override the default theme and serve either its
+//! own quotes or the main site's.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::Data;
+use serde::Deserialize;
+
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiResult, GuardError};
+use crate::i18n::{self, LocalesDir};
+use crate::storage::{self, NewGift, NewTenant, QuotePool, Storage, Tenant, TenantUpdate};
+
+const DEFAULT_TENANTS_DIR: &str = "tenants";
+
+/// Tenant sites live under `/t/<slug>` in `path` mode.
+const PATH_PREFIX: &str = "/t/";
+
+pub const MAX_SLUG_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Routing {
+    Subdomain,
+    Path,
+}
+
+/// The `[default.tenancy]` table in Rocket.toml. Without it there is only
+/// the main site.
+#[derive(Debug, Deserialize)]
+struct TenancyConfig {
+    routing: Routing,
+    /// The main site's host in `subdomain` mode, e.g.
+    /// `valentines.example.com`.
+    domain: Option<String>,
+    #[serde(default = "default_dir")]
+    dir: PathBuf,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from(DEFAULT_TENANTS_DIR)
+}
+
+/// Whether `slug` can name a tenant: 1 to 32 lowercase letters, digits and
+/// inner hyphens, so it works as both a subdomain and a path segment.
+pub fn is_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LEN
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && slug
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+}
+
+/// Which site a request is for, before looking the tenant up.
+#[derive(Debug, PartialEq, Eq)]
+enum Target<'a> {
+    Main,
+    /// The tenant named `slug`; in `path` mode `path` is the request path
+    /// with the prefix removed.
+    Tenant {
+        slug: &'a str,
+        path: Option<String>,
+    },
+}
+
+fn target<'a>(config: &TenancyConfig, host: Option<&'a str>, path: &'a str) -> Target<'a> {
+    match config.routing {
+        Routing::Path => {
+            let Some(rest) = path.strip_prefix(PATH_PREFIX) else {
+                return Target::Main;
+            };
+            let (slug, rest) = rest.split_once('/').unwrap_or((rest, ""));
+            Target::Tenant {
+                slug,
+                path: Some(format!("/{}", rest)),
+            }
+        }
+        Routing::Subdomain => {
+            let (Some(host), Some(domain)) = (host, config.domain.as_deref()) else {
+                return Target::Main;
+            };
+            match host
+                .strip_suffix(domain)
+                .and_then(|label| label.strip_suffix('.'))
+            {
+                Some(slug) if !slug.contains('.') => Target::Tenant { slug, path: None },
+                _ => Target::Main,
+            }
+        }
+    }
+}
+
+/// A tenant with its database and the base of links to its site.
+pub struct TenantSite {
+    pub tenant: Tenant,
+    pub storage: Storage,
+    pub public_url: PublicUrl,
+}
+
+/// Starts something for one database; see [`Tenants::for_each_database`].
+type Starter = Box<dyn Fn(Storage) + Send + Sync>;
+
+struct Inner {
+    config: Option<TenancyConfig>,
+    main: Storage,
+    main_url: PublicUrl,
+    locales_dir: Option<PathBuf>,
+    sites: RwLock<BTreeMap<String, Arc<TenantSite>>>,
+    starters: Mutex<Vec<Starter>>,
+}
+
+/// The tenants and their open databases.
+#[derive(Clone)]
+pub struct Tenants(Arc<Inner>);
+
+impl Tenants {
+    fn site(&self, slug: &str) -> Option<Arc<TenantSite>> {
+        self.0
+            .sites
+            .read()
+            .expect("tenant lock poisoned")
+            .get(slug)
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<Tenant> {
+        self.0
+            .sites
+            .read()
+            .expect("tenant lock poisoned")
+            .values()
+            .map(|site| site.tenant.clone())
+            .collect()
+    }
+
+    fn config(&self) -> ApiResult<&TenancyConfig> {
+        self.0
+            .config
+            .as_ref()
+            .ok_or_else(|| error(Status::ServiceUnavailable, "tenancy is not configured"))
+    }
+
+    /// Links on a tenant's site point at its subdomain or path prefix. In
+    /// `subdomain` mode they stay relative unless `public_url` is set, whose
+    /// scheme and port they keep.
+    fn public_url(&self, config: &TenancyConfig, slug: &str) -> PublicUrl {
+        let main = self.0.main_url.base();
+        PublicUrl::new(match (config.routing, main, config.domain.as_deref()) {
+            (Routing::Path, main, _) => {
+                Some(format!("{}{}{}", main.unwrap_or(""), PATH_PREFIX, slug))
+            }
+            (Routing::Subdomain, Some(main), Some(domain)) => {
+                let (scheme, rest) = main.split_once("://").unwrap_or(("https", main));
+                let authority = rest.split('/').next().unwrap_or(rest);
+                let port = authority
+                    .rsplit_once(':')
+                    .map(|(_, port)| format!(":{}", port))
+                    .unwrap_or_default();
+                Some(format!("{}://{}.{}{}", scheme, slug, domain, port))
+            }
+            (Routing::Subdomain, _, _) => None,
+        })
+    }
+
+    /// Opens and migrates the database of tenant `slug`, creating it if
+    /// needed, and loads the quote translations. A new database gets the
+    /// bundled quotes and a copy of the main site's gift catalog.
+    async fn open_database(&self, config: &TenancyConfig, slug: &str) -> Result<Storage, String> {
+        std::fs::create_dir_all(&config.dir)
+            .map_err(|e| format!("failed to create {}: {}", config.dir.display(), e))?;
+        let url = format!(
+            "sqlite://{}",
+            config.dir.join(format!("{}.db", slug)).display()
+        );
+        let storage = self
+            .0
+            .main
+            .connect_tenant(&url, slug)
+            .await
+            .map_err(|e| format!("failed to open database {}: {}", url, e))?;
+        storage
+            .migrate()
+            .await
+            .map_err(|e| format!("failed to migrate {}: {}", url, e))?;
+        storage
+            .seed_default_quotes()
+            .await
+            .map_err(|e| format!("failed to seed quotes in {}: {}", url, e))?;
+        if let Some(dir) = &self.0.locales_dir {
+            i18n::load_locales(&storage, dir).await?;
+        }
+
+        let copy_gifts = async {
+            if storage.count_gifts().await? > 0 {
+                return Ok(());
+            }
+            let gifts: Vec<NewGift> = self
+                .0
+                .main
+                .list_gifts()
+                .await?
+                .into_iter()
+                .map(|gift| NewGift {
+                    name: gift.name,
+                    description: gift.description,
+                    price_min: gift.price_min,
+                    price_max: gift.price_max,
+                    url: gift.url,
+                    tags: gift.tags,
+                })
+                .collect();
+            storage.import_gifts(&gifts).await
+        };
+        copy_gifts
+            .await
+            .map_err(|e: sqlx::Error| format!("failed to copy gifts into {}: {}", url, e))?;
+        Ok(storage)
+    }
+
+    /// Makes `tenant`'s site available and runs the starters for its
+    /// database.
+    fn register(&self, config: &TenancyConfig, tenant: Tenant, storage: Storage) {
+        // Held throughout so `for_each_database` sees either the site or a
+        // starter list that this call then runs, never both.
+        let starters = self.0.starters.lock().expect("tenant lock poisoned");
+        let site = TenantSite {
+            public_url: self.public_url(config, &tenant.slug),
+            tenant,
+            storage: storage.clone(),
+        };
+        self.0
+            .sites
+            .write()
+            .expect("tenant lock poisoned")
+            .insert(site.tenant.slug.clone(), Arc::new(site));
+        for start in starters.iter() {
+            start(storage.clone());
+        }
+    }
+
+    /// Creates a tenant with a fresh database.
+    pub async fn create(&self, tenant: &NewTenant) -> ApiResult<Tenant> {
+        let config = self.config()?;
+        let storage = self
+            .open_database(config, &tenant.slug)
+            .await
+            .map_err(|e| {
+                error!("{}", e);
+                error(
+                    Status::InternalServerError,
+                    "failed to create the tenant's database",
+                )
+            })?;
+        let tenant = self.0.main.create_tenant(tenant).await.map_err(|e| {
+            if storage::is_unique_violation(&e) {
+                error(Status::Conflict, "a tenant with this slug already exists")
+            } else {
+                internal_error(e)
+            }
+        })?;
+        self.register(config, tenant.clone(), storage);
+        info!("created tenant {}", tenant.slug);
+        Ok(tenant)
+    }
+
+    /// Changes a tenant's settings, or returns `None` if there is no such
+    /// tenant.
+    pub async fn update(&self, slug: &str, update: &TenantUpdate) -> ApiResult<Option<Tenant>> {
+        let config = self.config()?;
+        let Some(site) = self.site(slug) else {
+            return Ok(None);
+        };
+        let Some(tenant) = self
+            .0
+            .main
+            .update_tenant(slug, update)
+            .await
+            .map_err(internal_error)?
+        else {
+            return Ok(None);
+        };
+        self.0.sites.write().expect("tenant lock poisoned").insert(
+            slug.to_string(),
+            Arc::new(TenantSite {
+                public_url: self.public_url(config, slug),
+                tenant: tenant.clone(),
+                storage: site.storage.clone(),
+            }),
+        );
+        Ok(Some(tenant))
+    }
+
+    /// Calls `start` with the main database and each tenant's, now and for
+    /// every tenant created later, so stages can run their background
+    /// workers for every site.
+    pub fn for_each_database(&self, start: impl Fn(Storage) + Send + Sync + 'static) {
+        let mut starters = self.0.starters.lock().expect("tenant lock poisoned");
+        start(self.0.main.clone());
+        for site in self.0.sites.read().expect("tenant lock poisoned").values() {
+            start(site.storage.clone());
+        }
+        starters.push(Box::new(start));
+    }
+}
+
+/// Which site a request was matched to, cached for the guards.
+enum Resolved {
+    Main,
+    Tenant(Arc<TenantSite>),
+    /// A subdomain naming no tenant.
+    Unknown(String),
+}
+
+fn resolved<'r>(request: &'r Request<'_>) -> &'r Resolved {
+    request.local_cache(|| Resolved::Main)
+}
+
+/// The tenant slug of a request's site, or `None` on the main site.
+pub fn slug<'r>(request: &'r Request<'_>) -> Option<&'r str> {
+    match resolved(request) {
+        Resolved::Tenant(site) => Some(&site.tenant.slug),
+        _ => None,
+    }
+}
+
+/// Matches each request to a site. In `path` mode the `/t/<slug>` prefix of
+/// a tenant's requests is removed, so they are routed like the main site's.
+struct TenantRouting(Tenants);
+
+#[rocket::async_trait]
+impl Fairing for TenantRouting {
+    fn info(&self) -> Info {
+        Info {
+            name: "Tenant Routing",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(config) = &self.0 .0.config else {
+            return;
+        };
+        let host = request
+            .host()
+            .map(|host| host.domain().as_str().to_lowercase());
+        let path = request.uri().path().as_str().to_string();
+
+        let (resolved, rewritten) = match target(config, host.as_deref(), &path) {
+            Target::Main => (Resolved::Main, None),
+            Target::Tenant { slug, path } => match self.0.site(slug) {
+                Some(site) => (Resolved::Tenant(site), path),
+                // An unknown path prefix is left to 404 as an unknown route.
+                None if path.is_some() => (Resolved::Main, None),
+                None => (Resolved::Unknown(slug.to_string()), None),
+            },
+        };
+
+        if let Some(path) = rewritten {
+            let uri = match request.uri().query() {
+                Some(query) => format!("{}?{}", path, query),
+                None => path,
+            };
+            match Origin::parse_owned(uri) {
+                Ok(origin) => request.set_uri(origin),
+                Err(e) => warn!(
+                    "failed to strip the tenant prefix from {}: {}",
+                    request.uri(),
+                    e
+                ),
+            }
+        }
+        request.local_cache(|| resolved);
+    }
+}
+
+fn unknown_tenant<T>(request: &Request<'_>, slug: &str) -> Outcome<T, &'static str> {
+    request.local_cache(|| GuardError(Some(format!("no tenant named `{}`", slug))));
+    Outcome::Error((Status::NotFound, "unknown tenant"))
+}
+
+fn main_storage<'r>(request: &'r Request<'_>) -> Option<&'r Storage> {
+    match request.rocket().state::<Tenants>() {
+        Some(tenants) => Some(&tenants.0.main),
+        None => request.rocket().state::<Storage>(),
+    }
+}
+
+/// The database of the request's site.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Storage {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match resolved(request) {
+            Resolved::Tenant(site) => Outcome::Success(&site.storage),
+            Resolved::Unknown(slug) => unknown_tenant(request, slug),
+            Resolved::Main => match main_storage(request) {
+                Some(storage) => Outcome::Success(storage),
+                None => Outcome::Error((Status::InternalServerError, "storage is not configured")),
+            },
+        }
+    }
+}
+
+/// The base of links to the request's site.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r PublicUrl {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match resolved(request) {
+            Resolved::Tenant(site) => Outcome::Success(&site.public_url),
+            Resolved::Unknown(slug) => unknown_tenant(request, slug),
+            Resolved::Main => match request.rocket().state::<PublicUrl>() {
+                Some(public_url) => Outcome::Success(public_url),
+                None => {
+                    Outcome::Error((Status::InternalServerError, "public url is not configured"))
+                }
+            },
+        }
+    }
+}
+
+/// The tenant whose site a request is for; `None` on the main site.
+#[derive(Clone, Copy)]
+pub struct CurrentTenant<'r>(pub Option<&'r Tenant>);
+
+impl CurrentTenant<'_> {
+    /// The theme to use when a request names none.
+    pub fn theme(&self) -> Option<&str> {
+        self.0.and_then(|tenant| tenant.theme.as_deref())
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CurrentTenant<'r> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match resolved(request) {
+            Resolved::Tenant(site) => Outcome::Success(CurrentTenant(Some(&site.tenant))),
+            Resolved::Unknown(slug) => unknown_tenant(request, slug),
+            Resolved::Main => Outcome::Success(CurrentTenant(None)),
+        }
+    }
+}
+
+/// The database random quotes are served from: the site's own, or the main
+/// one for tenants with the `shared` quote pool.
+#[derive(Clone)]
+pub struct QuoteStorage(pub Storage);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for QuoteStorage {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let storage = match resolved(request) {
+            Resolved::Tenant(site) if site.tenant.quote_pool == QuotePool::Shared => {
+                main_storage(request)
+            }
+            Resolved::Tenant(site) => Some(&site.storage),
+            Resolved::Unknown(slug) => return unknown_tenant(request, slug),
+            Resolved::Main => main_storage(request),
+        };
+        match storage {
+            Some(storage) => Outcome::Success(QuoteStorage(storage.clone())),
+            None => Outcome::Error((Status::InternalServerError, "storage is not configured")),
+        }
+    }
+}
+
+/// Manages [`Tenants`] from the `tenancy` table, opening every tenant's
+/// database, and routes requests to them. Must be attached after the
+/// storage, public URL, locales and gift catalog stages; an unreadable
+/// tenant database stops the launch.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Tenants", |rocket| async {
+        let config = match rocket.figment().extract_inner::<TenancyConfig>("tenancy") {
+            Ok(config) => Some(config),
+            Err(e) if e.missing() => None,
+            Err(e) => {
+                error!("invalid tenancy config: {}", e);
+                return Err(rocket);
+            }
+        };
+        if let Some(TenancyConfig {
+            routing: Routing::Subdomain,
+            domain: None,
+            ..
+        }) = &config
+        {
+            error!("tenancy.domain is required with subdomain routing");
+            return Err(rocket);
+        }
+        let (Some(main), Some(main_url)) = (
+            rocket.state::<Storage>().cloned(),
+            rocket.state::<PublicUrl>().cloned(),
+        ) else {
+            error!("tenants stage attached before the storage or public url stage");
+            return Err(rocket);
+        };
+
+        let tenants = Tenants(Arc::new(Inner {
+            config: config.map(|mut config| {
+                config.domain = config.domain.map(|domain| domain.to_lowercase());
+                config
+            }),
+            main,
+            main_url,
+            locales_dir: rocket.state::<LocalesDir>().map(|dir| dir.0.clone()),
+            sites: RwLock::new(BTreeMap::new()),
+            starters: Mutex::new(Vec::new()),
+        }));
+        let Some(config) = &tenants.0.config else {
+            return Ok(rocket.manage(tenants));
+        };
+
+        let list = match tenants.0.main.list_tenants().await {
+            Ok(list) => list,
+            Err(e) => {
+                error!("failed to list tenants: {}", e);
+                return Err(rocket);
+            }
+        };
+        for tenant in list {
+            match tenants.open_database(config, &tenant.slug).await {
+                Ok(storage) => tenants.register(config, tenant, storage),
+                Err(e) => {
+                    error!("{}", e);
+                    return Err(rocket);
+                }
+            }
+        }
+        info!("serving {} tenants", tenants.list().len());
+
+        Ok(rocket
+            .manage(tenants.clone())
+            .attach(TenantRouting(tenants)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_matched_to_tenants() {
+        let path = TenancyConfig {
+            routing: Routing::Path,
+            domain: None,
+            dir: default_dir(),
+        };
+        assert_eq!(target(&path, None, "/api/valentine"), Target::Main);
+        assert_eq!(
+            target(&path, None, "/t/sam-and-alex/api/valentine"),
+            Target::Tenant {
+                slug: "sam-and-alex",
+                path: Some("/api/valentine".to_string())
+            }
+        );
+        assert_eq!(
+            target(&path, None, "/t/jo"),
+            Target::Tenant {
+                slug: "jo",
+                path: Some("/".to_string())
+            }
+        );
+
+        let subdomain = TenancyConfig {
+            routing: Routing::Subdomain,
+            domain: Some("love.test".to_string()),
+            dir: default_dir(),
+        };
+        assert_eq!(target(&subdomain, Some("love.test"), "/"), Target::Main);
+        assert_eq!(target(&subdomain, Some("localhost"), "/"), Target::Main);
+        assert_eq!(target(&subdomain, Some("a.b.love.test"), "/"), Target::Main);
+        assert_eq!(
+            target(&subdomain, Some("jo.love.test"), "/api"),
+            Target::Tenant {
+                slug: "jo",
+                path: None
+            }
+        );
+
+        assert!(is_slug("sam-and-alex"));
+        assert!(!is_slug("-sam"));
+        assert!(!is_slug("Sam"));
+        assert!(!is_slug(""));
+    }
+}
@@ -0,0 +1,357 @@
+//! Looks for generated cards, emails and PDFs. Each `<id>.toml` file in
+//! `themes_dir` (default `themes/`) defines one theme: a name, colors, a
+//! font, the decoration drawn on PNG cards and optionally a background
+//! image for them, relative to the file. The `hearts`, `classic` and
+//! `midnight` themes are bundled into the binary; a file with the same id
+//! replaces one. Files are read at startup.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::imageops::FilterType;
+use image::RgbaImage;
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::Figment;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::cards::pdf::CardFont;
+use crate::cards::{Color, HEIGHT, WIDTH};
+use crate::negotiate::Negotiated;
+
+const DEFAULT_THEMES_DIR: &str = "themes";
+
+/// Used when a generator is not given a theme.
+pub const DEFAULT_THEME: &str = "hearts";
+
+/// Largest background image file accepted.
+const MAX_BACKGROUND_BYTES: u64 = 5 * 1024 * 1024;
+
+const BUNDLED: &[(&str, &str)] = &[
+    ("classic", include_str!("../themes/classic.toml")),
+    ("hearts", include_str!("../themes/hearts.toml")),
+    ("midnight", include_str!("../themes/midnight.toml")),
+];
+
+/// What is drawn over the background of a PNG card.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// Translucent hearts scattered in the accent color.
+    Hearts,
+    /// A double frame with a heart in each corner.
+    Frame,
+    /// A starry sky behind one large faint heart.
+    Stars,
+    #[default]
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// The background, as a vertical gradient from `top` to `bottom` on
+    /// cards and as solid colors in emails and PDFs.
+    pub top: Color,
+    pub bottom: Color,
+    /// Headings and signatures.
+    pub title: Color,
+    /// The message.
+    pub text: Color,
+    /// Decorations.
+    pub accent: Color,
+}
+
+fn parse_color(field: &str, value: &str) -> Result<Color, String> {
+    let invalid = || format!("`colors.{}` must be a color like #ff6b8b", field);
+    let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+pub fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// The colors as `#rrggbb` strings.
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Colors {
+    pub top: String,
+    pub bottom: String,
+    pub title: String,
+    pub text: String,
+    pub accent: String,
+}
+
+impl Colors {
+    fn parse(&self) -> Result<Palette, String> {
+        Ok(Palette {
+            top: parse_color("top", &self.top)?,
+            bottom: parse_color("bottom", &self.bottom)?,
+            title: parse_color("title", &self.title)?,
+            text: parse_color("text", &self.text)?,
+            accent: parse_color("accent", &self.accent)?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ThemeFile {
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    pattern: Pattern,
+    #[serde(default = "default_font")]
+    font: CardFont,
+    /// An image file, relative to the theme file.
+    background: Option<PathBuf>,
+    colors: Colors,
+}
+
+fn default_font() -> CardFont {
+    CardFont::Serif
+}
+
+pub struct Theme {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub pattern: Pattern,
+    pub font: CardFont,
+    pub palette: Palette,
+    /// Card-sized, drawn in place of the gradient on PNG cards.
+    pub background: Option<RgbaImage>,
+}
+
+/// Reads a background image and crops it to fill a card.
+fn load_background(path: &Path) -> Result<RgbaImage, String> {
+    let size = fs::metadata(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?
+        .len();
+    if size > MAX_BACKGROUND_BYTES {
+        return Err(format!(
+            "{} is {} bytes, at most {} are allowed",
+            path.display(),
+            size,
+            MAX_BACKGROUND_BYTES
+        ));
+    }
+    let image = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?
+        .decode()
+        .map_err(|e| {
+            format!(
+                "{} is not a PNG, JPEG, GIF or WebP image: {}",
+                path.display(),
+                e
+            )
+        })?;
+    Ok(image
+        .resize_to_fill(WIDTH, HEIGHT, FilterType::Lanczos3)
+        .to_rgba8())
+}
+
+/// Parses a theme file; `dir` is where its background is looked up, and
+/// `None` for bundled themes, which have none.
+fn parse_theme(id: &str, contents: &str, dir: Option<&Path>) -> Result<Theme, String> {
+    let file: ThemeFile = Figment::from(Toml::string(contents))
+        .extract()
+        .map_err(|e| e.to_string())?;
+    if file.name.trim().is_empty() {
+        return Err("`name` must not be empty".to_string());
+    }
+    let palette = file.colors.parse()?;
+    let background = match (file.background, dir) {
+        (Some(path), Some(dir)) => Some(load_background(&dir.join(path))?),
+        (Some(_), None) => return Err("bundled themes cannot have a background".to_string()),
+        (None, _) => None,
+    };
+    Ok(Theme {
+        id: id.to_string(),
+        name: file.name,
+        description: file.description,
+        pattern: file.pattern,
+        font: file.font,
+        palette,
+        background,
+    })
+}
+
+/// The loaded themes, by id.
+pub struct Themes(BTreeMap<String, Arc<Theme>>);
+
+impl Themes {
+    /// Just the themes bundled into the binary.
+    pub fn bundled() -> Self {
+        Themes(
+            BUNDLED
+                .iter()
+                .map(|(id, contents)| {
+                    let theme = parse_theme(id, contents, None)
+                        .unwrap_or_else(|e| panic!("bundled theme {} is invalid: {}", id, e));
+                    (id.to_string(), Arc::new(theme))
+                })
+                .collect(),
+        )
+    }
+
+    /// The theme `id`, or an error listing the valid ones.
+    pub fn get(&self, id: &str) -> Result<Arc<Theme>, String> {
+        self.0
+            .get(&id.to_ascii_lowercase())
+            .cloned()
+            .ok_or_else(|| {
+                let valid: Vec<&str> = self.0.keys().map(String::as_str).collect();
+                format!(
+                    "unknown theme `{}`, expected one of: {}",
+                    id,
+                    valid.join(", ")
+                )
+            })
+    }
+
+    /// The theme `id`, or the default theme when `None`.
+    pub fn get_or_default(&self, id: Option<&str>) -> Result<Arc<Theme>, String> {
+        self.get(id.unwrap_or(DEFAULT_THEME))
+    }
+}
+
+fn load_themes(dir: &Path) -> Result<Themes, String> {
+    let mut themes = Themes::bundled();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("no themes directory at {}", dir.display());
+            return Ok(themes);
+        }
+        Err(e) => return Err(format!("failed to read {}: {}", dir.display(), e)),
+    };
+
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        if path.extension().and_then(|e| e.to_str()) != Some("toml") {
+            continue;
+        }
+        let Some(id) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(str::to_ascii_lowercase)
+        else {
+            continue;
+        };
+        let theme = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| parse_theme(&id, &contents, Some(dir)))
+            .map_err(|e| format!("invalid theme {}: {}", path.display(), e))?;
+        themes.0.insert(id, Arc::new(theme));
+    }
+    Ok(themes)
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ThemeSummary {
+    id: String,
+    name: String,
+    description: String,
+    pattern: Pattern,
+    font: CardFont,
+    colors: Colors,
+    /// Whether PNG cards are drawn on a background image.
+    has_background: bool,
+}
+
+/// Every theme the card, email and PDF generators accept, by id.
+#[utoipa::path(
+    tag = "themes",
+    responses((status = 200, body = Vec<ThemeSummary>))
+)]
+#[get("/api/themes")]
+fn list(themes: &State<Themes>) -> Negotiated<Vec<ThemeSummary>> {
+    Negotiated(
+        themes
+            .0
+            .values()
+            .map(|theme| ThemeSummary {
+                id: theme.id.clone(),
+                name: theme.name.clone(),
+                description: theme.description.clone(),
+                pattern: theme.pattern,
+                font: theme.font,
+                colors: Colors {
+                    top: hex(theme.palette.top),
+                    bottom: hex(theme.palette.bottom),
+                    title: hex(theme.palette.title),
+                    text: hex(theme.palette.text),
+                    accent: hex(theme.palette.accent),
+                },
+                has_background: theme.background.is_some(),
+            })
+            .collect(),
+    )
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![list]
+}
+
+/// Manages [`Themes`]: the bundled ones and those in `themes_dir`. An
+/// invalid file stops the launch.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Themes", |rocket| async {
+        let dir = match rocket.figment().extract_inner::<PathBuf>("themes_dir") {
+            Ok(dir) => dir,
+            Err(e) if e.missing() => PathBuf::from(DEFAULT_THEMES_DIR),
+            Err(e) => {
+                error!("invalid themes_dir: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        match load_themes(&dir) {
+            Ok(themes) => {
+                info!("loaded {} themes", themes.0.len());
+                Ok(rocket.manage(themes))
+            }
+            Err(e) => {
+                error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_theme_files() {
+        let themes = Themes::bundled();
+        let midnight = themes.get("Midnight").unwrap();
+        assert_eq!(midnight.pattern, Pattern::Stars);
+        assert_eq!(midnight.palette.top, [20, 24, 64]);
+        assert!(themes
+            .get("sepia")
+            .err()
+            .unwrap()
+            .contains("classic, hearts"));
+
+        let file = "name = \"Mint\"\nfont = \"sans\"\n[colors]\ntop = \"#e0fff4\"\n\
+                    bottom = \"#a0e8cf\"\ntitle = \"#0b6e4f\"\ntext = \"#123\"\naccent = \"#fff\"\n";
+        let e = parse_theme("mint", file, None).err().unwrap();
+        assert!(e.contains("colors.text"), "{}", e);
+        let file = file
+            .replace("#123\"", "#112233\"")
+            .replace("#fff\"", "#ffffff\"");
+        let mint = parse_theme("mint", &file, None).unwrap();
+        assert_eq!(mint.font, CardFont::Sans);
+        assert_eq!(mint.pattern, Pattern::None);
+        assert_eq!(hex(mint.palette.title), "#0b6e4f");
+    }
+}
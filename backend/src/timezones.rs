@@ -0,0 +1,219 @@
+//! Per-user timezones. Each account can save a home zone, which requests
+//! that name no zone of their own fall back to. Instants are stored in UTC
+//! next to the zone they were given in, and shown in that zone and in each
+//! partner's, so "midnight" means the same moment to both of them.
+
+use chrono::offset::LocalResult;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rocket::http::Status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{Storage, User};
+use crate::users::Session;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+/// The zone named `name`, if a name is given.
+pub(crate) fn optional_zone(name: Option<&str>) -> Result<Option<Tz>, String> {
+    match name.map(str::trim) {
+        Some(name) if !name.is_empty() => name
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("unknown `timezone` `{}`", name)),
+        _ => Ok(None),
+    }
+}
+
+/// The user's saved zone, UTC when they have not set one.
+pub(crate) fn saved_zone(user: &User) -> Tz {
+    user.timezone
+        .as_deref()
+        .and_then(|zone| zone.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// The zone a request asked for or, without one, the user's saved zone,
+/// UTC for anyone without either.
+pub(crate) fn zone_for(name: Option<&str>, user: Option<&User>) -> Result<Tz, String> {
+    Ok(optional_zone(name)?.unwrap_or_else(|| user.map_or(Tz::UTC, saved_zone)))
+}
+
+pub(crate) fn today_in(now: DateTime<Utc>, zone: Tz) -> NaiveDate {
+    now.with_timezone(&zone).date_naive()
+}
+
+/// A moment given either as an instant, with a UTC offset, or as a wall
+/// clock time such as `2027-02-14T00:00:00` in some zone.
+#[derive(Debug, Clone, Copy, Deserialize, utoipa::ToSchema)]
+#[serde(untagged)]
+pub(crate) enum LocalOrInstant {
+    Instant(DateTime<Utc>),
+    #[schema(value_type = String)]
+    Local(NaiveDateTime),
+}
+
+impl LocalOrInstant {
+    /// The instant, reading a wall clock time in `zone`. A time repeated
+    /// when clocks go back is the first of the two; one skipped when they
+    /// go forward does not exist.
+    pub(crate) fn in_zone(self, zone: Tz) -> Result<DateTime<Utc>, String> {
+        match self {
+            LocalOrInstant::Instant(instant) => Ok(instant),
+            LocalOrInstant::Local(local) => match zone.from_local_datetime(&local) {
+                LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+                    Ok(time.with_timezone(&Utc))
+                }
+                LocalResult::None => Err(format!(
+                    "{} does not exist in {}; the clocks skip it",
+                    local,
+                    zone.name()
+                )),
+            },
+        }
+    }
+}
+
+/// An instant as the clock reads in `timezone`: the zone it was set in
+/// when `user_id` is absent, otherwise that partner's.
+#[derive(Debug, Clone, PartialEq, Serialize, utoipa::ToSchema)]
+pub(crate) struct LocalTime {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub timezone: String,
+    #[schema(value_type = String)]
+    pub time: DateTime<FixedOffset>,
+}
+
+fn render(instant: DateTime<Utc>, zone: Tz) -> DateTime<FixedOffset> {
+    instant.with_timezone(&zone).fixed_offset()
+}
+
+/// `instant` in `zone`, where it was set, then in the saved zone of each
+/// of `members`, skipping those without one.
+pub(crate) fn local_times(instant: DateTime<Utc>, zone: &str, members: &[User]) -> Vec<LocalTime> {
+    let original = zone.parse().unwrap_or(Tz::UTC);
+    let mut times = vec![LocalTime {
+        user_id: None,
+        name: None,
+        timezone: original.name().to_string(),
+        time: render(instant, original),
+    }];
+    for member in members {
+        if member.timezone.is_none() {
+            continue;
+        }
+        let zone = saved_zone(member);
+        times.push(LocalTime {
+            user_id: Some(member.id),
+            name: Some(member.name.clone()),
+            timezone: zone.name().to_string(),
+            time: render(instant, zone),
+        });
+    }
+    times
+}
+
+/// The members of `couple` whose zones an instant is also shown in.
+pub(crate) async fn members(storage: &Storage, couple: Option<i64>) -> ApiResult<Vec<User>> {
+    let Some(couple) = couple else {
+        return Ok(Vec::new());
+    };
+    Ok(storage
+        .get_couple(couple)
+        .await
+        .map_err(internal_error)?
+        .map(|couple| couple.members)
+        .unwrap_or_default())
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct TimezoneRequest {
+    /// IANA zone, e.g. `Europe/Paris`; `null` clears it.
+    timezone: Option<String>,
+}
+
+impl Validate for TimezoneRequest {
+    type Valid = Option<Tz>;
+
+    fn validate(self) -> Result<Option<Tz>, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let zone = errors.check("timezone", optional_zone(self.timezone.as_deref()));
+        errors.finish(zone.flatten())
+    }
+}
+
+/// Saves the signed-in user's home zone, used by check-ins, moods,
+/// upcoming dates and schedules that name no zone.
+#[utoipa::path(
+    tag = "users",
+    request_body = TimezoneRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = User),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Unknown timezone", body = ErrorResponse),
+    )
+)]
+#[put("/api/users/me/timezone", data = "<request>")]
+async fn set_timezone(
+    session: Session,
+    storage: &Storage,
+    request: Valid<TimezoneRequest>,
+) -> ApiResult<Negotiated<User>> {
+    let zone = request.into_inner();
+    storage
+        .set_user_timezone(session.0.id, zone.map(|zone| zone.name()))
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, "account no longer exists"))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![set_timezone]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i64, timezone: Option<&str>) -> User {
+        User {
+            id,
+            email: format!("{}@example.com", id),
+            name: format!("partner {}", id),
+            couple_id: Some(1),
+            timezone: timezone.map(str::to_string),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn wall_clock_times_resolve_in_their_zone() {
+        let midnight = LocalOrInstant::Local("2027-02-14T00:00:00".parse().unwrap());
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        let instant = midnight.in_zone(new_york).unwrap();
+        assert_eq!(instant.to_rfc3339(), "2027-02-14T05:00:00+00:00");
+
+        // Clocks in New York jump from 02:00 to 03:00 on 2027-03-14.
+        let skipped = LocalOrInstant::Local("2027-03-14T02:30:00".parse().unwrap());
+        assert!(skipped.in_zone(new_york).is_err());
+
+        let members = [user(2, Some("Europe/Paris")), user(3, None)];
+        let times = local_times(instant, "America/New_York", &members);
+        assert_eq!(times.len(), 2);
+        assert_eq!(times[0].time.to_rfc3339(), "2027-02-14T00:00:00-05:00");
+        assert_eq!(times[1].user_id, Some(2));
+        assert_eq!(times[1].time.to_rfc3339(), "2027-02-14T06:00:00+01:00");
+
+        let tokyo = user(1, Some("Asia/Tokyo"));
+        assert_eq!(zone_for(None, Some(&tokyo)), Ok(Tz::Asia__Tokyo));
+        assert_eq!(zone_for(Some("UTC"), Some(&tokyo)), Ok(Tz::UTC));
+        assert_eq!(zone_for(None, None), Ok(Tz::UTC));
+    }
+}
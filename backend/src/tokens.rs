@@ -0,0 +1,33 @@
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+/// Random URL-safe alphanumeric token for unguessable public links.
+pub fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// What is stored in place of a secret token: the hex SHA-256 of it, with
+/// surrounding whitespace ignored.
+pub fn token_hash(token: &str) -> String {
+    Sha256::digest(token.trim())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Lowercase letters and digits without look-alikes (`0/o`, `1/l/i`), so
+/// slugs survive being read aloud or retyped.
+const SLUG_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Short human-friendly slug such as `x7k2q9` for share links.
+pub fn random_slug(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len)
+        .map(|_| SLUG_ALPHABET[rng.gen_range(0..SLUG_ALPHABET.len())] as char)
+        .collect()
+}
@@ -0,0 +1,194 @@
+//! The message trash. Deleting a message only hides it; it can be restored
+//! for [`RETENTION_DAYS`], after which the purge worker deletes it for good
+//! along with its shares and reactions.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::json;
+use rocket::tokio;
+use rocket::{Route, State};
+use tokio_util::sync::CancellationToken;
+
+use crate::audit::{self, Actor};
+use crate::auth::ApiKey;
+use crate::cache::QueryCache;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::storage::{AuditAction, AuditEntity, Message, Storage};
+use crate::tenants::Tenants;
+use crate::users::CoupleScope;
+use crate::workers::Workers;
+
+/// How long a deleted message stays restorable.
+const RETENTION_DAYS: i64 = 30;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Moves a message to the trash. It disappears from every listing and
+/// share link, and can be restored for 30 days.
+#[utoipa::path(
+    tag = "messages",
+    security(("api_key" = [])),
+    responses(
+        (status = 204, description = "Moved to the trash"),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[delete("/api/messages/<id>")]
+async fn delete(
+    _key: ApiKey,
+    actor: Actor,
+    storage: &Storage,
+    cache: &State<QueryCache>,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<status::NoContent> {
+    let message = storage
+        .trash_message(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no message with id {}", id)))?;
+    cache.invalidate("list_messages");
+    let before = Message {
+        deleted_at: None,
+        ..message.clone()
+    };
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Delete,
+        AuditEntity::Message,
+        id,
+        audit::changes(&before, &message),
+    )
+    .await;
+    Ok(status::NoContent)
+}
+
+/// Deleted messages, most recently deleted first, each with its
+/// `deleted_at`.
+#[utoipa::path(
+    tag = "messages",
+    params(("page" = Option<i64>, Query), ("per_page" = Option<i64>, Query)),
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = Page<Message>),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[get("/api/messages/trash?<page>&<per_page>")]
+async fn list(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    page: Option<i64>,
+    per_page: Option<i64>,
+) -> ApiResult<Negotiated<Page<Message>>> {
+    let (page, per_page, offset) = paginate(page, per_page);
+    let (items, total) = storage
+        .list_trashed_messages(scope.0, per_page, offset)
+        .await
+        .map_err(internal_error)?;
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Takes a message out of the trash.
+#[utoipa::path(
+    tag = "messages",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = Message),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in the trash", body = ErrorResponse),
+    )
+)]
+#[post("/api/messages/<id>/restore")]
+async fn restore(
+    _key: ApiKey,
+    actor: Actor,
+    storage: &Storage,
+    cache: &State<QueryCache>,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<Message>> {
+    let (message, deleted_at) = storage
+        .restore_message(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| error(Status::NotFound, format!("no message {} in the trash", id)))?;
+    cache.invalidate("list_messages");
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Restore,
+        AuditEntity::Message,
+        id,
+        json!({"deleted_at": {"from": deleted_at}}),
+    )
+    .await;
+    Ok(Negotiated(message))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![delete, list, restore]
+}
+
+/// Purges messages trashed more than [`RETENTION_DAYS`] ago every
+/// [`PURGE_INTERVAL`] until `token` is cancelled.
+async fn run_purger(storage: Storage, token: CancellationToken) {
+    let actor = Actor::system("trash");
+    while !token.is_cancelled() {
+        let before = Utc::now() - chrono::Duration::days(RETENTION_DAYS);
+        match storage.purge_trashed_messages(before).await {
+            Ok(purged) if purged.is_empty() => {}
+            Ok(purged) => {
+                info!("purged {} messages from the trash", purged.len());
+                for id in purged {
+                    audit::record(
+                        &storage,
+                        &actor,
+                        AuditAction::Purge,
+                        AuditEntity::Message,
+                        id,
+                        json!({}),
+                    )
+                    .await;
+                }
+            }
+            Err(e) => error!("failed to purge the trash: {}", e),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(PURGE_INTERVAL) => {}
+            _ = token.cancelled() => {}
+        }
+    }
+}
+
+/// Starts a purge worker for each database once the server has launched.
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Trash Purger", |rocket| {
+        Box::pin(async move {
+            match (rocket.state::<Tenants>(), rocket.state::<Workers>()) {
+                (Some(tenants), Some(workers)) => {
+                    let workers = workers.clone();
+                    tenants.for_each_database(move |storage| {
+                        workers.spawn("trash purger", |token| run_purger(storage, token));
+                    });
+                }
+                _ => error!("trash purger not started: storage or workers are unavailable"),
+            }
+        })
+    })
+}
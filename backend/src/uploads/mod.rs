@@ -0,0 +1,604 @@
+pub(crate) mod s3;
+
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rocket::fairing::AdHoc;
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, status, Responder, Response};
+use rocket::tokio::fs;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::{Route, State};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::ApiKey;
+use crate::cache;
+use crate::config::PublicUrl;
+use crate::error::{error, internal_error, ApiError, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::resilience::Resilience;
+use crate::storage::{Storage, Upload};
+use crate::tokens;
+
+use s3::{S3Bucket, S3Config};
+
+const UPLOAD_ID_LEN: usize = 24;
+
+/// Uploads are read in chunks of this size, so none is held in memory whole.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Enough leading bytes for [`sniff`].
+const SNIFF_LEN: usize = 12;
+
+/// Suffix of files being written to the uploads dir; renamed away once
+/// complete, and cleared at startup if a crash left any behind.
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Upload ids never change content, so clients and CDNs may cache forever.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Image types accepted by `POST /api/uploads`.
+pub const ALLOWED_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Identifies an image by its magic bytes, so the declared content type
+/// cannot smuggle in something else.
+pub(crate) fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= SNIFF_LEN && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// The `[default.uploads]` table in Rocket.toml. Images go to `s3` when it
+/// is set and to `dir` on local disk otherwise.
+#[derive(Debug, Deserialize)]
+struct UploadsConfig {
+    #[serde(default = "default_dir")]
+    dir: PathBuf,
+    s3: Option<S3Config>,
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from("uploads")
+}
+
+/// Where uploaded image bytes are kept; metadata lives in the database.
+#[derive(Clone)]
+pub enum UploadStore {
+    Disk(PathBuf),
+    S3(Box<S3Bucket>),
+}
+
+impl UploadStore {
+    async fn put(&self, id: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String> {
+        match self {
+            UploadStore::Disk(dir) => {
+                // Write then rename so a crash never leaves a truncated image
+                // under a valid id.
+                let partial = dir.join(format!("{}{}", id, PARTIAL_SUFFIX));
+                let written = match fs::write(&partial, bytes).await {
+                    Ok(()) => fs::rename(&partial, dir.join(id)).await,
+                    Err(e) => Err(e),
+                };
+                finish_partial(&partial, written).await
+            }
+            UploadStore::S3(bucket) => bucket.put(id, bytes, content_type).await,
+        }
+    }
+
+    /// Like [`UploadStore::put`] for a file Rocket spooled to its temp dir,
+    /// which is moved (or streamed to S3) rather than read into memory.
+    async fn put_file(
+        &self,
+        id: &str,
+        file: &mut TempFile<'_>,
+        image: &CheckedImage,
+    ) -> Result<(), String> {
+        match self {
+            UploadStore::Disk(dir) => {
+                let partial = dir.join(format!("{}{}", id, PARTIAL_SUFFIX));
+                // A rename when the temp dir is on the same filesystem, a
+                // copy otherwise.
+                let moved = match file.move_copy_to(&partial).await {
+                    Ok(()) => fs::rename(&partial, dir.join(id)).await,
+                    Err(e) => Err(e),
+                };
+                finish_partial(&partial, moved).await
+            }
+            UploadStore::S3(bucket) => match file.path() {
+                Some(path) => {
+                    bucket
+                        .put_file(
+                            id,
+                            path,
+                            image.size as u64,
+                            &image.sha256,
+                            image.content_type,
+                        )
+                        .await
+                }
+                None => {
+                    let mut bytes = Vec::with_capacity(file.len() as usize);
+                    file.open()
+                        .await
+                        .map_err(|e| e.to_string())?
+                        .read_to_end(&mut bytes)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    bucket.put(id, bytes, image.content_type).await
+                }
+            },
+        }
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            UploadStore::Disk(dir) => match fs::read(dir.join(id)).await {
+                Ok(bytes) => Ok(Some(bytes)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.to_string()),
+            },
+            UploadStore::S3(bucket) => bucket.get(id).await,
+        }
+    }
+}
+
+/// Removes `partial` if writing it did not complete.
+async fn finish_partial(partial: &Path, result: io::Result<()>) -> Result<(), String> {
+    if result.is_err() {
+        if let Err(e) = fs::remove_file(partial).await {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("failed to remove {}: {}", partial.display(), e);
+            }
+        }
+    }
+    result.map_err(|e| e.to_string())
+}
+
+/// Deletes the partial files an interrupted write left in `dir`.
+async fn clear_partials(dir: &Path) -> io::Result<usize> {
+    let mut entries = fs::read_dir(dir).await?;
+    let mut cleared = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_SUFFIX)
+        {
+            fs::remove_file(entry.path()).await?;
+            cleared += 1;
+        }
+    }
+    Ok(cleared)
+}
+
+fn store_error(e: String) -> ApiError {
+    error!("upload store error: {}", e);
+    error(Status::InternalServerError, "internal upload store error")
+}
+
+fn is_upload_id(id: &str) -> bool {
+    id.len() == UPLOAD_ID_LEN && id.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+/// The id of the upload `url` points at, if it is an upload URL of this
+/// server.
+pub(crate) fn upload_id<'a>(public_url: &PublicUrl, url: &'a str) -> Option<&'a str> {
+    public_url
+        .relative(url)
+        .and_then(|path| path.strip_prefix("/api/uploads/"))
+        .filter(|id| is_upload_id(id))
+}
+
+/// Checks that `url` points at an existing upload on this server and returns
+/// its canonical absolute form, for attaching images to messages.
+pub async fn resolve_image_url(
+    storage: &Storage,
+    public_url: &PublicUrl,
+    url: &str,
+) -> ApiResult<String> {
+    let invalid = || {
+        error(
+            Status::UnprocessableEntity,
+            "`image_url` must be a URL returned by POST /api/uploads",
+        )
+    };
+    let id = upload_id(public_url, url).ok_or_else(invalid)?;
+
+    match storage.get_upload(id).await.map_err(internal_error)? {
+        Some(upload) => Ok(upload_url(public_url, &upload.id)),
+        None => Err(invalid()),
+    }
+}
+
+pub(crate) fn upload_url(public_url: &PublicUrl, id: &str) -> String {
+    public_url.absolute(&uri!(serve(id)).to_string())
+}
+
+#[derive(FromForm, utoipa::ToSchema)]
+struct UploadForm<'r> {
+    #[schema(value_type = String, format = Binary)]
+    file: TempFile<'r>,
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct UploadResponse {
+    id: String,
+    url: String,
+    content_type: String,
+    size: i64,
+    created_at: DateTime<Utc>,
+}
+
+/// What [`check_image`] found an uploaded image to be.
+pub(crate) struct CheckedImage {
+    pub content_type: &'static str,
+    pub size: i64,
+    /// Hex, as kept in [`Upload::sha256`].
+    pub sha256: String,
+}
+
+/// The declared type of an uploaded file, if it is one of [`ALLOWED_TYPES`].
+/// `field` names the form field in errors.
+fn declared_type(file: &TempFile<'_>, field: &str) -> ApiResult<String> {
+    file.content_type()
+        .map(|ct| ct.media_type().to_string().to_lowercase())
+        .filter(|ct| ALLOWED_TYPES.contains(&ct.as_str()))
+        .ok_or_else(|| {
+            error(
+                Status::UnsupportedMediaType,
+                format!("`{}` must be one of: {}", field, ALLOWED_TYPES.join(", ")),
+            )
+        })
+}
+
+/// The image type of a non-empty upload starting with `head`, if its magic
+/// bytes match the `declared` type.
+fn sniffed_type(head: &[u8], declared: &str, field: &str) -> ApiResult<&'static str> {
+    if head.is_empty() {
+        return Err(error(
+            Status::UnprocessableEntity,
+            format!("`{}` is empty", field),
+        ));
+    }
+    sniff(head)
+        .filter(|sniffed| declared == *sniffed)
+        .ok_or_else(|| {
+            error(
+                Status::UnsupportedMediaType,
+                format!("`{}` content does not match its declared image type", field),
+            )
+        })
+}
+
+/// Checks an uploaded image's declared type and magic bytes against
+/// [`ALLOWED_TYPES`], hashing it in chunks from Rocket's temp file. `field`
+/// names the form field in errors.
+pub(crate) async fn check_image(file: &TempFile<'_>, field: &str) -> ApiResult<CheckedImage> {
+    let declared = declared_type(file, field)?;
+    let mut reader = file.open().await.map_err(|e| store_error(e.to_string()))?;
+    let (mut head, mut hasher, mut size) = (Vec::with_capacity(SNIFF_LEN), Sha256::new(), 0);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader
+            .read(&mut chunk)
+            .await
+            .map_err(|e| store_error(e.to_string()))?;
+        if read == 0 {
+            break;
+        }
+        let wanted = (SNIFF_LEN - head.len()).min(read);
+        head.extend_from_slice(&chunk[..wanted]);
+        hasher.update(&chunk[..read]);
+        size += read;
+    }
+
+    Ok(CheckedImage {
+        content_type: sniffed_type(&head, &declared, field)?,
+        size: size as i64,
+        sha256: hex(&hasher.finalize()),
+    })
+}
+
+/// Reads an uploaded image into memory, for callers that decode it, with
+/// the checks of [`check_image`].
+pub(crate) async fn read_image(
+    file: &TempFile<'_>,
+    field: &str,
+) -> ApiResult<(Vec<u8>, &'static str)> {
+    let declared = declared_type(file, field)?;
+    let mut bytes = Vec::with_capacity(file.len() as usize);
+    file.open()
+        .await
+        .map_err(|e| store_error(e.to_string()))?
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(|e| store_error(e.to_string()))?;
+    let content_type = sniffed_type(&bytes, &declared, field)?;
+    Ok((bytes, content_type))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex SHA-256 of `bytes`, as kept in [`Upload::sha256`].
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Stores `bytes` under a new upload id, which is returned. Recording the
+/// metadata is left to the caller.
+pub(crate) async fn store_bytes(
+    store: &UploadStore,
+    bytes: Vec<u8>,
+    content_type: &str,
+) -> ApiResult<String> {
+    let id = tokens::random_token(UPLOAD_ID_LEN);
+    store
+        .put(&id, bytes, content_type)
+        .await
+        .map_err(store_error)?;
+    Ok(id)
+}
+
+/// Moves a checked upload into the store under a new upload id and records
+/// its metadata.
+pub(crate) async fn save_file(
+    storage: &Storage,
+    store: &UploadStore,
+    file: &mut TempFile<'_>,
+    image: &CheckedImage,
+) -> ApiResult<Upload> {
+    let id = tokens::random_token(UPLOAD_ID_LEN);
+    store
+        .put_file(&id, file, image)
+        .await
+        .map_err(store_error)?;
+    storage
+        .create_upload(&id, image.content_type, image.size, &image.sha256)
+        .await
+        .map_err(internal_error)
+}
+
+/// Stores `bytes` under a new upload id and records its metadata.
+pub(crate) async fn save_upload(
+    storage: &Storage,
+    store: &UploadStore,
+    bytes: Vec<u8>,
+    content_type: &str,
+) -> ApiResult<Upload> {
+    let sha256 = sha256_hex(&bytes);
+    let size = bytes.len() as i64;
+    let id = store_bytes(store, bytes, content_type).await?;
+    storage
+        .create_upload(&id, content_type, size, &sha256)
+        .await
+        .map_err(internal_error)
+}
+
+/// Accepts a `multipart/form-data` body with a single `file` field, which
+/// is streamed to disk rather than held in memory. The size cap is the
+/// route's body limit and Rocket's `limits.file`.
+#[utoipa::path(
+    tag = "uploads",
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = UploadResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 413, description = "Larger than the route's body limit or `limits.file`", body = ErrorResponse),
+        (status = 415, description = "Not a PNG, JPEG, GIF or WebP image", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/uploads", data = "<form>")]
+async fn upload(
+    _key: ApiKey,
+    storage: &Storage,
+    store: &State<UploadStore>,
+    public_url: &PublicUrl,
+    mut form: Form<UploadForm<'_>>,
+) -> ApiResult<status::Created<Negotiated<UploadResponse>>> {
+    let image = check_image(&form.file, "file").await?;
+    let upload = save_file(storage, store, &mut form.file, &image).await?;
+
+    let url = upload_url(public_url, &upload.id);
+    Ok(
+        status::Created::new(url.clone()).body(Negotiated(UploadResponse {
+            id: upload.id,
+            url,
+            content_type: upload.content_type,
+            size: upload.size,
+            created_at: upload.created_at,
+        })),
+    )
+}
+
+/// The `If-None-Match` request header, if sent.
+struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(IfNoneMatch(
+            request
+                .headers()
+                .get_one("If-None-Match")
+                .map(str::to_string),
+        ))
+    }
+}
+
+impl IfNoneMatch {
+    fn matches(&self, etag: &str) -> bool {
+        self.0
+            .as_deref()
+            .is_some_and(|header| cache::etag_matches(header, etag))
+    }
+}
+
+enum Image {
+    Body {
+        bytes: Vec<u8>,
+        content_type: String,
+        etag: String,
+    },
+    NotModified {
+        etag: String,
+    },
+}
+
+impl<'r> Responder<'r, 'static> for Image {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("Cache-Control", CACHE_CONTROL);
+
+        match self {
+            Image::Body {
+                bytes,
+                content_type,
+                etag,
+            } => response
+                .header(ContentType::parse_flexible(&content_type).unwrap_or(ContentType::Binary))
+                .header(Header::new("ETag", etag))
+                .raw_header("X-Content-Type-Options", "nosniff")
+                .sized_body(bytes.len(), Cursor::new(bytes))
+                .ok(),
+            Image::NotModified { etag } => response
+                .status(Status::NotModified)
+                .header(Header::new("ETag", etag))
+                .ok(),
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "uploads",
+    responses(
+        (
+            status = 200,
+            description = "The image, with `ETag` and long-lived `Cache-Control`",
+            content_type = "image/*",
+            body = Vec<u8>,
+        ),
+        (status = 304, description = "Matches `If-None-Match`"),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/uploads/<id>")]
+async fn serve(
+    storage: &Storage,
+    store: &State<UploadStore>,
+    if_none_match: IfNoneMatch,
+    id: &str,
+) -> ApiResult<Image> {
+    let not_found = || error(Status::NotFound, "no such upload");
+    if !is_upload_id(id) {
+        return Err(not_found());
+    }
+    let upload = storage
+        .get_upload(id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(not_found)?;
+
+    let etag = format!("\"{}\"", upload.sha256);
+    if if_none_match.matches(&etag) {
+        return Ok(Image::NotModified { etag });
+    }
+
+    let bytes = store.get(id).await.map_err(store_error)?.ok_or_else(|| {
+        warn!("upload {} has metadata but no stored bytes", id);
+        not_found()
+    })?;
+    Ok(Image::Body {
+        bytes,
+        content_type: upload.content_type,
+        etag,
+    })
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![upload, serve]
+}
+
+/// Manages the [`UploadStore`] from the `uploads` table. Must be attached
+/// after the HTTP client stage, which S3 requests go through.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Uploads", |rocket| async {
+        let config = match rocket.figment().extract_inner::<UploadsConfig>("uploads") {
+            Ok(config) => config,
+            Err(e) if e.missing() => UploadsConfig {
+                dir: default_dir(),
+                s3: None,
+            },
+            Err(e) => {
+                error!("invalid uploads config: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        let store = match config.s3 {
+            Some(s3) => {
+                let Some(client) = rocket.state::<Resilience>().map(|r| r.client("uploads")) else {
+                    error!("uploads stage attached before the resilience stage");
+                    return Err(rocket);
+                };
+                match S3Bucket::new(s3, client) {
+                    Ok(bucket) => UploadStore::S3(Box::new(bucket)),
+                    Err(e) => {
+                        error!("invalid uploads.s3 config: {}", e);
+                        return Err(rocket);
+                    }
+                }
+            }
+            None => {
+                if let Err(e) = fs::create_dir_all(&config.dir).await {
+                    error!(
+                        "failed to create uploads dir {}: {}",
+                        config.dir.display(),
+                        e
+                    );
+                    return Err(rocket);
+                }
+                match clear_partials(&config.dir).await {
+                    Ok(0) => {}
+                    Ok(cleared) => info!("removed {} partially written uploads", cleared),
+                    Err(e) => warn!("failed to clear partially written uploads: {}", e),
+                }
+                UploadStore::Disk(config.dir)
+            }
+        };
+
+        Ok(rocket.manage(store))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_identified_by_magic_bytes() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(&[0xff, 0xd8, 0xff, 0xe0]), Some("image/jpeg"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"), None);
+        assert_eq!(sniff(b""), None);
+    }
+}
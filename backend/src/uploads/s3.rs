@@ -0,0 +1,339 @@
+//! Minimal client for S3-compatible object stores (AWS S3, MinIO, R2),
+//! using path-style URLs and AWS Signature Version 4.
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::{Body, Method, RequestBuilder, StatusCode, Url};
+use rocket::tokio::fs::File;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio_util::io::ReaderStream;
+
+use crate::resilience::ResilientClient;
+
+/// An S3 table in Rocket.toml, `[default.uploads.s3]` or
+/// `[default.backup.s3]`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+#[derive(Clone)]
+pub struct S3Bucket {
+    config: S3Config,
+    base: Url,
+    client: ResilientClient,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of an empty body.
+const EMPTY_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Percent-encodes everything but the unreserved characters, as SigV4
+/// canonical query strings require.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The SigV4 signing key for one day, region and service.
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret).as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+impl S3Bucket {
+    pub fn new(config: S3Config, client: ResilientClient) -> Result<Self, String> {
+        let mut base =
+            Url::parse(&config.endpoint).map_err(|e| format!("invalid endpoint: {}", e))?;
+        if config.bucket.is_empty() || config.bucket.contains('/') {
+            return Err(format!("invalid bucket `{}`", config.bucket));
+        }
+        base.path_segments_mut()
+            .map_err(|_| "endpoint cannot be a base URL".to_string())?
+            .pop_if_empty()
+            .push(&config.bucket);
+
+        Ok(S3Bucket {
+            config,
+            base,
+            client,
+        })
+    }
+
+    /// The object's path-style URL. Slashes in `key` stay path separators,
+    /// as S3 expects.
+    fn object_url(&self, key: &str) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("checked in S3Bucket::new")
+            .extend(key.split('/'));
+        url
+    }
+
+    /// Builds a signed request to `url` with the `query` parameters, for a
+    /// body whose hex SHA-256 is `payload_hash`. Only `host`,
+    /// `x-amz-content-sha256` and `x-amz-date` are signed, which is all S3
+    /// requires.
+    fn request(
+        &self,
+        method: Method,
+        mut url: Url,
+        query: &[(&str, &str)],
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> RequestBuilder {
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name), uri_encode(value)))
+            .collect();
+        query.sort();
+        let canonical_query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        if !canonical_query.is_empty() {
+            url.set_query(Some(&canonical_query));
+        }
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            canonical_query,
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+        let key = signing_key(
+            &self.config.secret_access_key,
+            date,
+            &self.config.region,
+            "s3",
+        );
+        let signature = hex(&hmac(&key, &string_to_sign));
+
+        self.client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.config.access_key_id, scope, signed_headers, signature
+                ),
+            )
+    }
+
+    pub async fn put(&self, key: &str, body: Vec<u8>, content_type: &str) -> Result<(), String> {
+        let request = self
+            .request(
+                Method::PUT,
+                self.object_url(key),
+                &[],
+                &sha256_hex(&body),
+                Utc::now(),
+            )
+            .header("content-type", content_type)
+            .body(body);
+        let response = self.client.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(format!("PUT {} returned {}", key, status)),
+        }
+    }
+
+    /// Streams the `size`-byte file at `path`, whose hex SHA-256 is
+    /// `sha256`, to `key` without reading it into memory.
+    pub async fn put_file(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        sha256: &str,
+        content_type: &str,
+    ) -> Result<(), String> {
+        let file = File::open(path).await.map_err(|e| e.to_string())?;
+        let request = self
+            .request(Method::PUT, self.object_url(key), &[], sha256, Utc::now())
+            .header("content-type", content_type)
+            .header("content-length", size)
+            .body(Body::wrap_stream(ReaderStream::new(file)));
+        let response = self.client.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(format!("PUT {} returned {}", key, status)),
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let request = self.request(
+            Method::GET,
+            self.object_url(key),
+            &[],
+            EMPTY_HASH,
+            Utc::now(),
+        );
+        let response = self.client.send(request).await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => response
+                .bytes()
+                .await
+                .map(|bytes| Some(bytes.to_vec()))
+                .map_err(|e| e.to_string()),
+            status => Err(format!("GET {} returned {}", key, status)),
+        }
+    }
+
+    /// Deletes the object at `key`. Deleting a missing object succeeds.
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        let request = self.request(
+            Method::DELETE,
+            self.object_url(key),
+            &[],
+            EMPTY_HASH,
+            Utc::now(),
+        );
+        let response = self.client.send(request).await?;
+
+        match response.status() {
+            status if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            status => Err(format!("DELETE {} returned {}", key, status)),
+        }
+    }
+
+    /// Keys of every object under `prefix`, in the bucket's (lexicographic)
+    /// order, following ListObjectsV2 continuation tokens.
+    pub async fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token));
+            }
+            let request = self.request(
+                Method::GET,
+                self.base.clone(),
+                &query,
+                EMPTY_HASH,
+                Utc::now(),
+            );
+            let response = self.client.send(request).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(format!("listing {} returned {}", prefix, status));
+            }
+            let xml = response.text().await.map_err(|e| e.to_string())?;
+
+            keys.extend(xml_values(&xml, "Key"));
+            token = match xml_values(&xml, "IsTruncated").first().map(String::as_str) {
+                Some("true") => xml_values(&xml, "NextContinuationToken").pop(),
+                _ => None,
+            };
+            if token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+}
+
+/// The text of every `<tag>` element in `xml`, unescaped. S3 list
+/// responses are flat enough not to need a full parser.
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else {
+            break;
+        };
+        values.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signing_key_matches_aws_example() {
+        // From the AWS "Examples of how to derive a signing key" docs.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+}
@@ -0,0 +1,467 @@
+//! Accounts and couples. A signed-in member of a couple sees and creates
+//! that couple's messages, dates and proposals; everyone else works with the
+//! shared, unscoped data.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::time::Duration;
+use rocket::tokio::task;
+use rocket::{Route, State};
+use serde::Deserialize;
+
+use crate::email;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse, GuardError};
+use crate::invites::{self, InviteOnly};
+use crate::jwt::BearerToken;
+use crate::negotiate::Negotiated;
+use crate::storage::{self, Couple, JoinError, NewUser, Storage, User};
+use crate::tokens;
+use crate::valentine::MAX_NAME_LEN;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+/// Private (encrypted and signed) cookie holding the signed-in user's id.
+pub const SESSION_COOKIE: &str = "valentine_session";
+const SESSION_DAYS: i64 = 30;
+
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 128;
+
+const INVITE_CODE_LEN: usize = 8;
+const INVITE_ATTEMPTS: usize = 5;
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Argon2 is deliberately slow, so it runs off the async workers.
+pub async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> ApiResult<T> {
+    task::spawn_blocking(f).await.map_err(|e| {
+        error!("password hashing task failed: {}", e);
+        error(Status::InternalServerError, "internal error")
+    })
+}
+
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Signs `user` in on the site of `storage`. On a tenant's site the cookie
+/// names the tenant too, as tenants under `/t/` share a host.
+pub fn start_session(cookies: &CookieJar<'_>, storage: &Storage, user: &User) {
+    let value = match storage.tenant() {
+        Some(tenant) => format!("{}:{}", tenant, user.id),
+        None => user.id.to_string(),
+    };
+    cookies.add_private(
+        Cookie::build((SESSION_COOKIE, value))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(Duration::days(SESSION_DAYS)),
+    );
+}
+
+/// The user id in a session cookie, if it was set on `tenant`'s site.
+fn session_user(tenant: Option<&str>, value: &str) -> Option<i64> {
+    let (cookie_tenant, id) = match value.split_once(':') {
+        Some((cookie_tenant, id)) => (Some(cookie_tenant), id),
+        None => (None, value),
+    };
+    (cookie_tenant == tenant).then(|| id.parse().ok()).flatten()
+}
+
+/// The signed-in user, looked up once per request. An `Authorization:
+/// Bearer` token takes precedence over the session cookie; an invalid one
+/// is an error rather than an anonymous request.
+struct CurrentUser(Result<Option<User>, String>);
+
+async fn current_user<'r>(request: &'r Request<'_>) -> &'r Result<Option<User>, String> {
+    let cached = request
+        .local_cache_async(async {
+            let Outcome::Success(storage) = request.guard::<&Storage>().await else {
+                return CurrentUser(Ok(None));
+            };
+            let id = match request.guard::<Result<BearerToken, &str>>().await {
+                Outcome::Success(Ok(token)) => Some(token.user_id),
+                Outcome::Success(Err(e)) => return CurrentUser(Err(e.to_string())),
+                _ => request
+                    .cookies()
+                    .get_private(SESSION_COOKIE)
+                    .and_then(|cookie| session_user(storage.tenant(), cookie.value())),
+            };
+            let Some(id) = id else {
+                return CurrentUser(Ok(None));
+            };
+            match storage.get_user(id).await {
+                Ok(user) => CurrentUser(Ok(user)),
+                Err(e) => {
+                    error!("failed to load session user {}: {}", id, e);
+                    CurrentUser(Ok(None))
+                }
+            }
+        })
+        .await;
+    &cached.0
+}
+
+fn unauthorized<T>(request: &Request<'_>, failure: &str) -> Outcome<T, &'static str> {
+    request.local_cache(|| GuardError(Some(failure.to_string())));
+    Outcome::Error((Status::Unauthorized, "unauthorized"))
+}
+
+/// Request guard for routes that need a signed-in user, by session cookie
+/// or bearer token.
+pub struct Session(pub User);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match current_user(request).await {
+            Ok(Some(user)) => Outcome::Success(Session(user.clone())),
+            Ok(None) => unauthorized(request, "not signed in"),
+            Err(e) => unauthorized(request, e),
+        }
+    }
+}
+
+/// The couple whose data a request works with: the signed-in user's, or
+/// `None` for anonymous and API-key clients, who only see unscoped rows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoupleScope(pub Option<i64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CoupleScope {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match current_user(request).await {
+            Ok(user) => {
+                Outcome::Success(CoupleScope(user.as_ref().and_then(|user| user.couple_id)))
+            }
+            Err(e) => unauthorized(request, e),
+        }
+    }
+}
+
+/// True when the request carries a valid session or bearer token, which the
+/// API key guard accepts in place of a key.
+pub async fn is_signed_in(request: &Request<'_>) -> bool {
+    matches!(current_user(request).await, Ok(Some(_)))
+}
+
+/// The id of the user the request is signed in as, if any.
+pub async fn signed_in_id(request: &Request<'_>) -> Option<i64> {
+    match current_user(request).await {
+        Ok(Some(user)) => Some(user.id),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct RegisterRequest {
+    email: String,
+    name: String,
+    password: String,
+    /// A token from `POST /admin/invites`; required with `invite_only`.
+    invite: Option<String>,
+}
+
+impl Validate for RegisterRequest {
+    type Valid = RegisterRequest;
+
+    /// The request with its email normalized and name trimmed.
+    fn validate(self) -> Result<RegisterRequest, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let email = normalize_email(&self.email);
+        if !email::is_valid_address(&email) {
+            errors.add(
+                "email",
+                format!("`email` is not a valid address: {}", email),
+            );
+        }
+        let name = self.name.trim().to_string();
+        errors.text("name", &name, MAX_NAME_LEN);
+        let length = self.password.chars().count();
+        if !(MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&length) {
+            errors.add(
+                "password",
+                format!(
+                    "`password` must be between {} and {} characters",
+                    MIN_PASSWORD_LEN, MAX_PASSWORD_LEN
+                ),
+            );
+        }
+        errors.finish(RegisterRequest {
+            email,
+            name,
+            password: self.password,
+            invite: self.invite,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct LoginRequest {
+    email: String,
+    password: String,
+}
+
+/// Checks a password login. Shared by the cookie and token endpoints.
+pub async fn authenticate(storage: &Storage, request: LoginRequest) -> ApiResult<User> {
+    let LoginRequest { email, password } = request;
+    let found = storage
+        .password_hash(&normalize_email(&email))
+        .await
+        .map_err(internal_error)?;
+
+    let invalid = || error(Status::Unauthorized, "invalid email or password");
+    let (user, hash) = found.ok_or_else(invalid)?;
+    if !blocking(move || verify_password(&password, &hash)).await? {
+        return Err(invalid());
+    }
+    Ok(user)
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct JoinRequest {
+    invite_code: String,
+}
+
+/// Creates an account and signs it in.
+#[utoipa::path(
+    tag = "users",
+    request_body = RegisterRequest,
+    responses(
+        (status = 201, description = "Signed in; sets the session cookie", body = User),
+        (status = 409, description = "Email already registered", body = ErrorResponse),
+        (status = 422, description = "Invalid field, or a missing, used or expired invite", body = ErrorResponse),
+    )
+)]
+#[post("/api/users/register", data = "<request>")]
+async fn register(
+    storage: &Storage,
+    invite_only: &State<InviteOnly>,
+    cookies: &CookieJar<'_>,
+    request: Valid<RegisterRequest>,
+) -> ApiResult<status::Created<Negotiated<User>>> {
+    let RegisterRequest {
+        email,
+        name,
+        password,
+        invite,
+    } = request.into_inner();
+    let password_hash = blocking(move || hash_password(&password))
+        .await?
+        .map_err(|e| {
+            error!("failed to hash password: {}", e);
+            error(Status::InternalServerError, "internal error")
+        })?;
+
+    let user = invites::create_user(
+        storage,
+        invite_only,
+        invite.as_deref(),
+        &NewUser {
+            email,
+            name,
+            password_hash,
+        },
+    )
+    .await?;
+
+    start_session(cookies, storage, &user);
+    Ok(status::Created::new(uri!(me).to_string()).body(Negotiated(user)))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Signed in; sets the session cookie", body = User),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[post("/api/users/login", data = "<request>")]
+async fn login(
+    storage: &Storage,
+    cookies: &CookieJar<'_>,
+    request: Json<LoginRequest>,
+) -> ApiResult<Negotiated<User>> {
+    let user = authenticate(storage, request.into_inner()).await?;
+    start_session(cookies, storage, &user);
+    Ok(Negotiated(user))
+}
+
+#[utoipa::path(tag = "users", responses((status = 204, description = "Session cookie cleared")))]
+#[post("/api/users/logout")]
+fn logout(cookies: &CookieJar<'_>) -> status::NoContent {
+    cookies.remove_private(Cookie::build(SESSION_COOKIE).path("/"));
+    status::NoContent
+}
+
+#[utoipa::path(
+    tag = "users",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = User),
+        (status = 401, body = ErrorResponse),
+    )
+)]
+#[get("/api/users/me")]
+fn me(session: Session) -> Negotiated<User> {
+    Negotiated(session.0)
+}
+
+/// Starts a couple with the signed-in user as its first member. Share the
+/// returned `invite_code` with your partner so they can join.
+#[utoipa::path(
+    tag = "users",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = Couple),
+        (status = 401, body = ErrorResponse),
+        (status = 409, description = "Already in a couple", body = ErrorResponse),
+    )
+)]
+#[post("/api/couples")]
+async fn create_couple(
+    session: Session,
+    storage: &Storage,
+) -> ApiResult<status::Created<Negotiated<Couple>>> {
+    for _ in 0..INVITE_ATTEMPTS {
+        let code = tokens::random_slug(INVITE_CODE_LEN);
+        match storage.create_couple(session.0.id, &code).await {
+            Ok(Some(couple)) => {
+                return Ok(
+                    status::Created::new(uri!(my_couple).to_string()).body(Negotiated(couple))
+                )
+            }
+            Ok(None) => return Err(error(Status::Conflict, "you are already in a couple")),
+            Err(e) if storage::is_unique_violation(&e) => continue,
+            Err(e) => return Err(internal_error(e)),
+        }
+    }
+
+    error!("no unused invite code after {} attempts", INVITE_ATTEMPTS);
+    Err(error(
+        Status::InternalServerError,
+        "could not allocate an invite code",
+    ))
+}
+
+#[utoipa::path(
+    tag = "users",
+    request_body = JoinRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Couple),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Unknown invite code", body = ErrorResponse),
+        (status = 409, description = "Already in a couple, or the couple is full", body = ErrorResponse),
+    )
+)]
+#[post("/api/couples/join", data = "<request>")]
+async fn join_couple(
+    session: Session,
+    storage: &Storage,
+    request: Json<JoinRequest>,
+) -> ApiResult<Negotiated<Couple>> {
+    let code = request.invite_code.trim().to_lowercase();
+    match storage
+        .join_couple(session.0.id, &code)
+        .await
+        .map_err(internal_error)?
+    {
+        Ok(couple) => Ok(Negotiated(couple)),
+        Err(JoinError::UnknownCode) => Err(error(Status::NotFound, "unknown invite code")),
+        Err(JoinError::Full) => Err(error(Status::Conflict, "that couple is already complete")),
+        Err(JoinError::AlreadyLinked) => {
+            Err(error(Status::Conflict, "you are already in a couple"))
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "users",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Couple),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/couples/me")]
+async fn my_couple(session: Session, storage: &Storage) -> ApiResult<Negotiated<Couple>> {
+    let not_found = || error(Status::NotFound, "you are not in a couple yet");
+    let id = session.0.couple_id.ok_or_else(not_found)?;
+    storage
+        .get_couple(id)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(not_found)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        register,
+        login,
+        logout,
+        me,
+        create_couple,
+        join_couple,
+        my_couple
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(email: &str, name: &str, password: &str) -> RegisterRequest {
+        RegisterRequest {
+            email: email.to_string(),
+            name: name.to_string(),
+            password: password.to_string(),
+            invite: None,
+        }
+    }
+
+    #[test]
+    fn registration_normalizes_and_checks_fields() {
+        let valid = register(" Romeo@Example.com ", " Romeo ", "balcony-1597")
+            .validate()
+            .unwrap();
+        assert_eq!(
+            (valid.email.as_str(), valid.name.as_str()),
+            ("romeo@example.com", "Romeo")
+        );
+        assert!(register("not-an-email", "Romeo", "balcony-1597")
+            .validate()
+            .is_err());
+        assert!(register("romeo@example.com", "  ", "balcony-1597")
+            .validate()
+            .is_err());
+        assert!(register("romeo@example.com", "Romeo", "short")
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,640 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::select;
+use rocket::tokio::time::{self, MissedTickBehavior};
+use rocket::{Route, Shutdown, State};
+use serde::{Deserialize, Serialize};
+
+use crate::admin::quotes::{duplicate_or_internal, QuoteRequest};
+use crate::audit::{self, Actor};
+use crate::auth::ApiKey;
+use crate::cache::QueryCache;
+use crate::config::PublicUrl;
+use crate::content_filter::ContentFilter;
+use crate::determinism::{Now, RequestRng};
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::events::{DomainEvent, EventBus};
+use crate::i18n::{self, AcceptLanguage};
+use crate::messages;
+use crate::metrics::Metrics;
+use crate::negotiate::Negotiated;
+use crate::pagination::{paginate, Page};
+use crate::push::Notification;
+use crate::quote_sources::QuoteSources;
+use crate::stats::ServeCounter;
+use crate::stickers;
+use crate::storage::{
+    AuditAction, AuditEntity, Category, Message, MessageQuery, MessageSort, NewMessage, Quote,
+    QuoteStatus, SortOrder, Storage,
+};
+use crate::tenants::QuoteStorage;
+use crate::uploads;
+use crate::users::CoupleScope;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+pub const MAX_MESSAGE_LEN: usize = 500;
+pub const MAX_NAME_LEN: usize = 50;
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct ValentineResponse {
+    message: String,
+    from: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    category: Option<Category>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lang: Option<String>,
+}
+
+impl ValentineResponse {
+    fn from_quote(quote: Option<Quote>, fallback: &str) -> Self {
+        let (message, category) = match quote {
+            Some(quote) => (quote.text, Some(quote.category)),
+            None => (fallback.to_string(), None),
+        };
+
+        ValentineResponse {
+            message,
+            from: "Your Valentine".to_string(),
+            category,
+            lang: None,
+        }
+    }
+}
+
+#[derive(Deserialize, async_graphql::InputObject, utoipa::ToSchema)]
+#[graphql(name = "MessageInput")]
+pub struct ValentineSubmission {
+    pub message: String,
+    pub from: String,
+    pub to: Option<String>,
+    /// A URL returned by `POST /api/uploads`.
+    pub image_url: Option<String>,
+    /// A sticker id from `GET /api/stickers`.
+    pub sticker_id: Option<i64>,
+}
+
+impl Validate for ValentineSubmission {
+    type Valid = NewMessage;
+
+    /// Trims every field and checks lengths, turning blank optional fields
+    /// into `None`.
+    fn validate(self) -> Result<NewMessage, FieldErrors> {
+        let message = self.message.trim().to_string();
+        let sender = self.from.trim().to_string();
+        let recipient = self
+            .to
+            .map(|to| to.trim().to_string())
+            .filter(|to| !to.is_empty());
+
+        let mut errors = FieldErrors::new();
+        errors.text("message", &message, MAX_MESSAGE_LEN);
+        errors.text("from", &sender, MAX_NAME_LEN);
+        if let Some(recipient) = &recipient {
+            errors.text("to", recipient, MAX_NAME_LEN);
+        }
+        let image_url = self
+            .image_url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+
+        errors.finish(NewMessage {
+            message,
+            sender,
+            recipient,
+            image_url,
+            couple_id: None,
+            sticker_id: self.sticker_id,
+        })
+    }
+}
+
+pub fn check_text(field: &str, value: &str, max_len: usize) -> Result<(), String> {
+    if value.is_empty() {
+        return Err(format!("`{}` must not be empty", field));
+    }
+    if value.chars().count() > max_len {
+        return Err(format!(
+            "`{}` must be at most {} characters",
+            field, max_len
+        ));
+    }
+    if value.chars().any(|c| c.is_control() && c != '\n') {
+        return Err(format!("`{}` contains control characters", field));
+    }
+    Ok(())
+}
+
+fn parse_category(category: Option<&str>) -> ApiResult<Option<Category>> {
+    category
+        .map(str::parse)
+        .transpose()
+        .map_err(|e: String| error(Status::BadRequest, e))
+}
+
+/// Fetches a random quote, treating an empty filtered pool as a 404 while an
+/// unfiltered empty pool falls back to a default message.
+async fn pick_quote(
+    storage: &Storage,
+    sources: &QuoteSources,
+    category: Option<Category>,
+    rng: &mut RequestRng,
+) -> ApiResult<Option<Quote>> {
+    let quote = sources
+        .random_quote(storage, category, rng)
+        .await
+        .map_err(internal_error)?;
+
+    match (quote, category) {
+        (None, Some(category)) => Err(error(
+            Status::NotFound,
+            format!("no quotes in category `{}`", category),
+        )),
+        (quote, _) => Ok(quote),
+    }
+}
+
+/// `?lang=` wins over `Accept-Language`; English is always the last resort.
+#[utoipa::path(
+    tag = "quotes",
+    params(
+        ("category" = Option<Category>, Query, description = "Only pick quotes from this category"),
+        ("lang" = Option<String>, Query, description = "Language code; overrides `Accept-Language`"),
+        ("seed" = Option<u64>, Query, description = "Picks the same quote every time; also read from `X-Seed`"),
+    ),
+    responses(
+        (status = 200, body = ValentineResponse),
+        (status = 400, description = "Unknown category or language code", body = ErrorResponse),
+        (status = 404, description = "No quotes in the category", body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine?<category>&<lang>")]
+#[allow(clippy::too_many_arguments)]
+async fn random(
+    quotes: QuoteStorage,
+    sources: &State<QuoteSources>,
+    metrics: &State<Metrics>,
+    serves: &State<ServeCounter>,
+    mut rng: RequestRng,
+    accept: AcceptLanguage,
+    category: Option<&str>,
+    lang: Option<&str>,
+) -> ApiResult<Negotiated<ValentineResponse>> {
+    let category = parse_category(category)?;
+    let preferences = match lang {
+        Some(lang) => vec![i18n::normalize_lang(lang).ok_or_else(|| {
+            error(
+                Status::BadRequest,
+                format!("invalid language code `{}`", lang),
+            )
+        })?],
+        None => accept.0,
+    };
+
+    let quote = pick_quote(&quotes.0, sources, category, &mut rng).await?;
+    metrics.quote_served("random", quote.as_ref().map(|q| q.category));
+    serves.served(&quotes.0, quote.as_ref());
+
+    let translated = match &quote {
+        Some(quote) => Some(
+            i18n::translate(&quotes.0, quote, &preferences)
+                .await
+                .map_err(internal_error)?,
+        ),
+        None => None,
+    };
+
+    let mut response = ValentineResponse::from_quote(quote, "I love you!");
+    if let Some((lang, text)) = translated {
+        response.message = text;
+        response.lang = Some(lang);
+    }
+    Ok(Negotiated(response))
+}
+
+#[derive(Serialize, utoipa::ToSchema)]
+struct DailyResponse {
+    #[serde(flatten)]
+    valentine: ValentineResponse,
+    date: NaiveDate,
+    next_rotation_at: DateTime<Utc>,
+}
+
+/// SplitMix64 finaliser, so consecutive days land on unrelated quotes rather
+/// than walking the pool in order.
+fn day_seed(date: NaiveDate) -> u64 {
+    let mut z = (date.num_days_from_ce() as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Quote of the day: the same quote for everyone for the whole UTC day.
+#[utoipa::path(
+    tag = "quotes",
+    params(("category" = Option<Category>, Query)),
+    responses(
+        (status = 200, body = DailyResponse),
+        (status = 400, description = "Unknown category", body = ErrorResponse),
+        (status = 404, description = "No quotes in the category", body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/daily?<category>")]
+async fn daily(
+    quotes: QuoteStorage,
+    sources: &State<QuoteSources>,
+    cache: &State<QueryCache>,
+    metrics: &State<Metrics>,
+    serves: &State<ServeCounter>,
+    now: Now,
+    category: Option<&str>,
+) -> ApiResult<Negotiated<DailyResponse>> {
+    let category = parse_category(category)?;
+    let today = now.0.date_naive();
+    let key = format!("{:?}:{}:{:?}", quotes.0.tenant(), today, category);
+    let quote = cache
+        .get_or_try_insert(
+            "daily",
+            key,
+            sources.quote_for_seed(&quotes.0, category, day_seed(today)),
+        )
+        .await
+        .map_err(internal_error)?;
+    if quote.is_none() {
+        if let Some(category) = category {
+            return Err(error(
+                Status::NotFound,
+                format!("no quotes in category `{}`", category),
+            ));
+        }
+    }
+    metrics.quote_served("daily", quote.as_ref().map(|q| q.category));
+    serves.served(&quotes.0, quote.as_ref());
+
+    let tomorrow = today.succ_opt().expect("date within chrono range");
+    Ok(Negotiated(DailyResponse {
+        valentine: ValentineResponse::from_quote(quote, "I love you!"),
+        date: today,
+        next_rotation_at: tomorrow.and_time(NaiveTime::MIN).and_utc(),
+    }))
+}
+
+/// Seconds between quotes on `/api/valentine/stream`, and the accepted range.
+const STREAM_INTERVAL: u64 = 10;
+const STREAM_INTERVAL_RANGE: std::ops::RangeInclusive<u64> = 1..=3600;
+
+/// Server-sent events: a `quote` event right away and then every `interval`
+/// seconds, until the client disconnects or the server shuts down.
+#[utoipa::path(
+    tag = "quotes",
+    params(
+        ("interval" = Option<u64>, Query, description = "Seconds between events, 1 to 3600 (default 10)"),
+        ("category" = Option<Category>, Query),
+        ("seed" = Option<u64>, Query, description = "Streams the same quotes every time; also read from `X-Seed`"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "`quote` events carrying a `ValentineResponse`",
+            content_type = "text/event-stream",
+            body = String,
+        ),
+        (status = 400, description = "Interval out of range or unknown category", body = ErrorResponse),
+        (status = 404, description = "No quotes in the category", body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/stream?<interval>&<category>")]
+#[allow(clippy::too_many_arguments)]
+async fn stream<'r>(
+    quotes: QuoteStorage,
+    sources: &'r State<QuoteSources>,
+    metrics: &'r State<Metrics>,
+    serves: &'r State<ServeCounter>,
+    mut rng: RequestRng,
+    interval: Option<u64>,
+    category: Option<&str>,
+    mut shutdown: Shutdown,
+) -> ApiResult<EventStream![Event + 'r]> {
+    let interval = interval.unwrap_or(STREAM_INTERVAL);
+    if !STREAM_INTERVAL_RANGE.contains(&interval) {
+        return Err(error(
+            Status::BadRequest,
+            format!(
+                "`interval` must be between {} and {} seconds",
+                STREAM_INTERVAL_RANGE.start(),
+                STREAM_INTERVAL_RANGE.end()
+            ),
+        ));
+    }
+    let category = parse_category(category)?;
+    // Fail up front rather than opening a stream that can never emit.
+    pick_quote(&quotes.0, sources, category, &mut rng).await?;
+
+    let mut ticker = time::interval(Duration::from_secs(interval));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    Ok(EventStream! {
+        let mut id = 0u64;
+        let mut last = None;
+        loop {
+            select! {
+                _ = ticker.tick() => {}
+                _ = &mut shutdown => break,
+            }
+
+            // One redraw keeps the ticker from showing the same quote twice
+            // in a row without looping forever on a one-quote category.
+            let mut quote = pick_quote(&quotes.0, sources, category, &mut rng).await;
+            if matches!(&quote, Ok(Some(q)) if Some(q.id) == last) {
+                quote = pick_quote(&quotes.0, sources, category, &mut rng).await;
+            }
+            let quote = match quote {
+                Ok(quote) => quote,
+                Err(_) => {
+                    yield Event::data("failed to fetch a quote").event("error");
+                    continue;
+                }
+            };
+            last = quote.as_ref().map(|q| q.id);
+            metrics.quote_served("stream", quote.as_ref().map(|q| q.category));
+            serves.served(&quotes.0, quote.as_ref());
+
+            id += 1;
+            let response = ValentineResponse::from_quote(quote, "I love you!");
+            yield Event::json(&response).event("quote").id(id.to_string());
+        }
+    })
+}
+
+#[utoipa::path(
+    tag = "quotes",
+    params(
+        ("name" = String, Path, description = "Who the quote is addressed to"),
+        ("category" = Option<Category>, Query),
+        ("seed" = Option<u64>, Query, description = "Picks the same quote every time; also read from `X-Seed`"),
+    ),
+    responses(
+        (status = 200, body = ValentineResponse),
+        (status = 400, description = "Invalid name or unknown category", body = ErrorResponse),
+        (status = 404, description = "No quotes in the category", body = ErrorResponse),
+    )
+)]
+#[get("/api/valentine/<name>?<category>")]
+async fn personalized(
+    quotes: QuoteStorage,
+    sources: &State<QuoteSources>,
+    metrics: &State<Metrics>,
+    serves: &State<ServeCounter>,
+    mut rng: RequestRng,
+    name: &str,
+    category: Option<&str>,
+) -> ApiResult<Negotiated<ValentineResponse>> {
+    let name = messages::sanitize_name(name).map_err(|e| error(Status::BadRequest, e))?;
+    let category = parse_category(category)?;
+    let quote = pick_quote(&quotes.0, sources, category, &mut rng).await?;
+    metrics.quote_served("personalized", quote.as_ref().map(|q| q.category));
+    serves.served(&quotes.0, quote.as_ref());
+
+    let mut response = ValentineResponse::from_quote(quote, "I love you, {name}!");
+    response.message = messages::personalize(&response.message, &name);
+    Ok(Negotiated(response))
+}
+
+/// Screens, stores and audits a validated submission, then publishes it for
+/// the notes feed, webhooks and push notifications. Shared by the
+/// REST, GraphQL and gRPC APIs.
+pub async fn create_message(
+    storage: &Storage,
+    filter: &ContentFilter,
+    events: &EventBus,
+    public_url: &PublicUrl,
+    actor: &Actor,
+    scope: CoupleScope,
+    mut new_message: NewMessage,
+) -> ApiResult<Message> {
+    filter.screen_message(&new_message).await?;
+    new_message.couple_id = scope.0;
+    if let Some(url) = &new_message.image_url {
+        new_message.image_url = Some(uploads::resolve_image_url(storage, public_url, url).await?);
+    }
+    if let Some(id) = new_message.sticker_id {
+        stickers::require_sticker(storage, "sticker_id", id).await?;
+    }
+
+    let message = storage
+        .create_message(&new_message)
+        .await
+        .map_err(internal_error)?;
+    audit::record(
+        storage,
+        actor,
+        AuditAction::Create,
+        AuditEntity::Message,
+        message.id,
+        audit::created(&message),
+    )
+    .await;
+    events.publish(
+        storage,
+        DomainEvent::MessageCreated {
+            message: message.clone(),
+        },
+    );
+    Ok(message)
+}
+
+/// What the couple's browsers show for a new valentine.
+pub fn notification(message: &Message) -> Notification {
+    Notification {
+        title: format!("New valentine from {}", message.sender),
+        body: message.message.clone(),
+        url: Some(uri!(message_by_id(message.id)).to_string()),
+    }
+}
+
+#[utoipa::path(
+    tag = "messages",
+    request_body = ValentineSubmission,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = Message),
+        (status = 401, body = ErrorResponse),
+        (status = 422, description = "Invalid field, `image_url` or `sticker_id`, or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/valentine", data = "<submission>")]
+#[allow(clippy::too_many_arguments)]
+async fn submit(
+    _key: ApiKey,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    cache: &State<QueryCache>,
+    events: &State<EventBus>,
+    public_url: &PublicUrl,
+    actor: Actor,
+    scope: CoupleScope,
+    submission: Valid<ValentineSubmission>,
+) -> ApiResult<status::Created<Negotiated<Message>>> {
+    let message = create_message(
+        storage,
+        filter,
+        events,
+        public_url,
+        &actor,
+        scope,
+        submission.into_inner(),
+    )
+    .await?;
+    cache.invalidate("list_messages");
+
+    let location = uri!(message_by_id(message.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(message)))
+}
+
+/// Suggests a quote for the pool. It stays `pending` until approved through
+/// `/admin/moderation`.
+#[utoipa::path(
+    tag = "quotes",
+    request_body = QuoteRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 202, description = "Queued for moderation", body = Quote),
+        (status = 401, body = ErrorResponse),
+        (status = 409, description = "Duplicate quote text", body = ErrorResponse),
+        (status = 422, description = "Invalid field or refused by the content filter", body = ErrorResponse),
+    )
+)]
+#[post("/api/quotes", data = "<request>")]
+async fn submit_quote(
+    _key: ApiKey,
+    actor: Actor,
+    storage: &Storage,
+    filter: &State<ContentFilter>,
+    request: Valid<QuoteRequest>,
+) -> ApiResult<status::Accepted<Negotiated<Quote>>> {
+    let quote = request.into_inner();
+    filter.screen_quote(&quote).await?;
+    let quote = storage
+        .create_quote(&quote, QuoteStatus::Pending)
+        .await
+        .map_err(duplicate_or_internal)?;
+    audit::record(
+        storage,
+        &actor,
+        AuditAction::Create,
+        AuditEntity::Quote,
+        quote.id,
+        audit::created(&quote),
+    )
+    .await;
+    Ok(status::Accepted(Negotiated(quote)))
+}
+
+/// Query string of `GET /api/messages`.
+#[derive(FromForm, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListParams<'r> {
+    page: Option<i64>,
+    per_page: Option<i64>,
+    #[field(default = MessageSort::CreatedAt)]
+    #[param(required = false)]
+    sort: MessageSort,
+    #[field(default = SortOrder::Desc)]
+    #[param(required = false)]
+    order: SortOrder,
+    search: Option<&'r str>,
+}
+
+/// Stored messages, newest first unless `sort`/`order` say otherwise.
+/// `search` matches message text case-insensitively.
+#[utoipa::path(
+    tag = "messages",
+    params(ListParams),
+    responses(
+        (status = 200, body = Page<Message>),
+        (status = 422, description = "Invalid `sort` or `order`", body = ErrorResponse),
+    )
+)]
+#[get("/api/messages?<params..>")]
+async fn list_messages(
+    storage: &Storage,
+    cache: &State<QueryCache>,
+    scope: CoupleScope,
+    params: ListParams<'_>,
+) -> ApiResult<Negotiated<Page<Message>>> {
+    let (page, per_page, offset) = paginate(params.page, params.per_page);
+    let search = params.search.map(str::trim).filter(|s| !s.is_empty());
+
+    let query = MessageQuery {
+        couple: scope.0,
+        search,
+        sort: params.sort,
+        order: params.order,
+        limit: per_page,
+        offset,
+    };
+    let (items, total) = cache
+        .get_or_try_insert(
+            "list_messages",
+            format!("{:?}:{:?}", storage.tenant(), query),
+            storage.list_messages(query),
+        )
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(Page {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+#[utoipa::path(
+    tag = "messages",
+    responses(
+        (status = 200, body = Message),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[get("/api/messages/<id>")]
+async fn message_by_id(
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<Message>> {
+    storage
+        .get_message(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .map(Negotiated)
+        .ok_or_else(|| error(Status::NotFound, format!("no message with id {}", id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        random,
+        daily,
+        stream,
+        personalized,
+        submit,
+        submit_quote,
+        list_messages,
+        message_by_id
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_seed_is_stable_and_varies_by_day() {
+        let day = NaiveDate::from_ymd_opt(2026, 2, 14).unwrap();
+        assert_eq!(day_seed(day), day_seed(day));
+        assert_ne!(day_seed(day), day_seed(day.succ_opt().unwrap()));
+    }
+}
@@ -0,0 +1,280 @@
+//! Validated JSON bodies. A route taking [`Valid<T>`] gets `T`'s checked
+//! form, built by its [`Validate`] impl; bodies that fail are answered
+//! with `422` and a `details` object mapping each bad field to what is
+//! wrong with it. Bodies that are not JSON at all are a `400`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use rocket::data::{self, Data, FromData, Limits};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::serde::json::serde_json::{self, error::Category};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::body_limits::RouteLimit;
+use crate::error::{unprocessable, ApiError, GuardDetails, GuardError};
+use crate::valentine::check_text;
+
+/// Problems found in a request, by field. Fields of nested objects are
+/// dotted (`reminders.email`) and list items indexed (`[2].text`), the way
+/// the body's JSON paths are written.
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct FieldErrors(BTreeMap<String, Vec<String>>);
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        FieldErrors::default()
+    }
+
+    pub fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.0
+            .entry(field.to_string())
+            .or_default()
+            .push(message.into());
+    }
+
+    /// The value in `result`, or `None` with its error recorded against
+    /// `field`.
+    pub fn check<T>(&mut self, field: &str, result: Result<T, String>) -> Option<T> {
+        result.map_err(|e| self.add(field, e)).ok()
+    }
+
+    /// Records why `value` is not acceptable text for `field`, if it is not.
+    pub fn text(&mut self, field: &str, value: &str, max_len: usize) {
+        self.check(field, check_text(field, value, max_len));
+    }
+
+    /// Validates the object in field `prefix`, recording its problems under
+    /// that name.
+    pub fn nested<T: Validate>(&mut self, prefix: &str, value: T) -> Option<T::Valid> {
+        value.validate().map_err(|e| self.nest(prefix, e)).ok()
+    }
+
+    /// Validates fields flattened into this object from `value`.
+    pub fn flattened<T: Validate>(&mut self, value: T) -> Option<T::Valid> {
+        value.validate().map_err(|e| self.merge(e)).ok()
+    }
+
+    fn nest(&mut self, prefix: &str, other: FieldErrors) {
+        for (field, messages) in other.0 {
+            let field = if field.starts_with('[') {
+                format!("{}{}", prefix, field)
+            } else {
+                format!("{}.{}", prefix, field)
+            };
+            self.0.entry(field).or_default().extend(messages);
+        }
+    }
+
+    fn merge(&mut self, other: FieldErrors) {
+        for (field, messages) in other.0 {
+            self.0.entry(field).or_default().extend(messages);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// `valid` if nothing was recorded.
+    pub fn finish<T>(self, valid: T) -> Result<T, FieldErrors> {
+        if self.is_empty() {
+            Ok(valid)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Every problem, in field order.
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<&str> = self.0.values().flatten().map(String::as_str).collect();
+        f.write_str(&messages.join("; "))
+    }
+}
+
+impl From<FieldErrors> for ApiError {
+    fn from(errors: FieldErrors) -> Self {
+        unprocessable(errors.to_string(), errors)
+    }
+}
+
+/// A request body that can check itself and become what handlers work with.
+pub trait Validate: Sized {
+    type Valid;
+
+    /// Checks every field, reporting all problems rather than the first.
+    fn validate(self) -> Result<Self::Valid, FieldErrors>;
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    type Valid = Vec<T::Valid>;
+
+    fn validate(self) -> Result<Self::Valid, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let mut valid = Vec::with_capacity(self.len());
+        for (i, item) in self.into_iter().enumerate() {
+            valid.extend(errors.nested(&format!("[{}]", i), item));
+        }
+        errors.finish(valid)
+    }
+}
+
+/// A JSON body of `T` that passed [`Validate`], as the validated value.
+pub struct Valid<T: Validate>(pub T::Valid);
+
+impl<T: Validate> Valid<T> {
+    pub fn into_inner(self) -> T::Valid {
+        self.0
+    }
+}
+
+/// What went wrong reading the body as a `T`: a syntax error, or the field
+/// at fault and why.
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Result<FieldErrors, String>> {
+    let mut deserializer = serde_json::Deserializer::from_str(body);
+    let e = match serde_path_to_error::deserialize(&mut deserializer) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    let (path, inner) = (e.path().to_string(), e.into_inner());
+    if !matches!(inner.classify(), Category::Data) {
+        return Err(Err(format!("request body is not valid JSON: {}", inner)));
+    }
+
+    // serde_json ends every message with the position, which the field
+    // name makes redundant.
+    let message = inner.to_string();
+    let message = message
+        .rfind(" at line ")
+        .map_or(message.as_str(), |at| &message[..at]);
+    let mut errors = FieldErrors::new();
+    match message.strip_prefix("missing field `") {
+        Some(rest) => {
+            let name = rest.trim_end_matches('`');
+            let field = if path == "." {
+                name.to_string()
+            } else {
+                format!("{}.{}", path, name)
+            };
+            errors.add(&field, format!("`{}` is required", field));
+        }
+        None => {
+            let field = if path == "." {
+                "body".to_string()
+            } else {
+                path
+            };
+            errors.add(&field, format!("`{}`: {}", field, message));
+        }
+    }
+    Err(Ok(errors))
+}
+
+/// Reads `body` as a `T` and validates it, failing the way a [`Valid<T>`]
+/// body would: `400` for malformed JSON and `422` with every bad field.
+pub fn from_json<T: DeserializeOwned + Validate>(body: &str) -> Result<T::Valid, ApiError> {
+    match parse::<T>(body) {
+        Ok(value) => value.validate().map_err(ApiError::from),
+        Err(Ok(errors)) => Err(errors.into()),
+        Err(Err(message)) => Err(ApiError::BadRequest(message)),
+    }
+}
+
+fn reject<'r, T>(request: &'r Request<'_>, error: ApiError) -> data::Outcome<'r, T, ()> {
+    let status = error.status();
+    request.local_cache(|| GuardError(Some(error.message().to_string())));
+    request.local_cache(|| GuardDetails(error.details().cloned()));
+    data::Outcome::Error((status, ()))
+}
+
+#[rocket::async_trait]
+impl<'r, T> FromData<'r> for Valid<T>
+where
+    T: DeserializeOwned + Validate + Send,
+    T::Valid: Send,
+{
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit =
+            RouteLimit::of(request).or(request.limits().get("json").unwrap_or(Limits::JSON));
+        let body = match data.open(limit).into_string().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => {
+                let message = format!("request body is larger than {}", limit);
+                return reject(request, ApiError::Other(Status::PayloadTooLarge, message));
+            }
+            Err(e) => {
+                let message = format!("failed to read request body: {}", e);
+                return reject(request, ApiError::BadRequest(message));
+            }
+        };
+
+        match from_json::<T>(&body) {
+            Ok(valid) => data::Outcome::Success(Valid(valid)),
+            Err(e) => reject(request, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Note {
+        text: String,
+        from: String,
+    }
+
+    impl Validate for Note {
+        type Valid = (String, String);
+
+        fn validate(self) -> Result<Self::Valid, FieldErrors> {
+            let mut errors = FieldErrors::new();
+            errors.text("text", self.text.trim(), 10);
+            errors.text("from", self.from.trim(), 10);
+            errors.finish((self.text, self.from))
+        }
+    }
+
+    #[test]
+    fn every_bad_field_is_reported() {
+        let notes: Vec<Note> =
+            parse(r#"[{"text": "hi", "from": "Sam"}, {"text": " ", "from": "a long name"}]"#)
+                .unwrap();
+        let errors = notes.validate().unwrap_err();
+        let details = rocket::serde::json::to_value(&errors).unwrap();
+        assert_eq!(
+            details,
+            rocket::serde::json::json!({
+                "[1].from": ["`from` must be at most 10 characters"],
+                "[1].text": ["`text` must not be empty"],
+            })
+        );
+        assert_eq!(
+            errors.to_string(),
+            "`from` must be at most 10 characters; `text` must not be empty"
+        );
+
+        let missing = parse::<Vec<Note>>(r#"[{"text": "hi"}]"#)
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(missing.0.keys().collect::<Vec<_>>(), vec!["[0].from"]);
+        let wrong_type = parse::<Note>(r#"{"text": 5, "from": "Sam"}"#)
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(
+            wrong_type.to_string(),
+            "`text`: invalid type: integer `5`, expected a string"
+        );
+        assert!(parse::<Note>(r#"{"text": "#).unwrap_err().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! "Open when..." letters: notes written for a moment ("open when you miss
+//! me") that stay sealed until someone decides that moment has come.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::ApiKey;
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{NewVaultLetter, Storage, VaultLetter};
+use crate::users::CoupleScope;
+use crate::valentine::MAX_NAME_LEN;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_CONDITION_LEN: usize = 100;
+const MAX_BODY_LEN: usize = 2000;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct LetterRequest {
+    /// When to open it, e.g. "open when you're sad".
+    condition: String,
+    body: String,
+    from: String,
+}
+
+impl Validate for LetterRequest {
+    type Valid = NewVaultLetter;
+
+    fn validate(self) -> Result<NewVaultLetter, FieldErrors> {
+        let condition = self.condition.trim().to_string();
+        let body = self.body.trim().to_string();
+        let sender = self.from.trim().to_string();
+        let mut errors = FieldErrors::new();
+        errors.text("condition", &condition, MAX_CONDITION_LEN);
+        errors.text("body", &body, MAX_BODY_LEN);
+        errors.text("from", &sender, MAX_NAME_LEN);
+        errors.finish(NewVaultLetter {
+            condition,
+            body,
+            sender,
+            couple_id: None,
+        })
+    }
+}
+
+/// A letter as clients see it. `body` is only present once it is opened.
+#[derive(Serialize, utoipa::ToSchema)]
+struct LetterResponse {
+    id: i64,
+    condition: String,
+    from: String,
+    opened: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    opened_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<VaultLetter> for LetterResponse {
+    fn from(letter: VaultLetter) -> Self {
+        let opened = letter.opened_at.is_some();
+        LetterResponse {
+            id: letter.id,
+            condition: letter.condition,
+            from: letter.sender,
+            opened,
+            opened_at: letter.opened_at,
+            body: opened.then_some(letter.body),
+            created_at: letter.created_at,
+        }
+    }
+}
+
+/// Seals a letter in the vault. Its body is not returned until it is
+/// opened, not even here.
+#[utoipa::path(
+    tag = "letters",
+    request_body = LetterRequest,
+    security(("api_key" = [])),
+    responses(
+        (status = 201, body = LetterResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/vault", data = "<request>")]
+async fn create(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    request: Valid<LetterRequest>,
+) -> ApiResult<status::Created<Negotiated<LetterResponse>>> {
+    let mut letter = request.into_inner();
+    letter.couple_id = scope.0;
+    let letter = storage
+        .create_vault_letter(&letter)
+        .await
+        .map_err(internal_error)?;
+
+    let location = uri!(open(letter.id)).to_string();
+    Ok(status::Created::new(location).body(Negotiated(letter.into())))
+}
+
+/// Every letter in the vault, oldest first. Sealed ones show only their
+/// condition and sender.
+#[utoipa::path(
+    tag = "letters",
+    responses((status = 200, body = Vec<LetterResponse>))
+)]
+#[get("/api/vault")]
+async fn list(storage: &Storage, scope: CoupleScope) -> ApiResult<Negotiated<Vec<LetterResponse>>> {
+    let letters = storage
+        .list_vault_letters(scope.0)
+        .await
+        .map_err(internal_error)?;
+    Ok(Negotiated(letters.into_iter().map(Into::into).collect()))
+}
+
+/// Opens a letter and returns it with its body. Opening it again returns
+/// it unchanged, with the time it was first opened.
+#[utoipa::path(
+    tag = "letters",
+    security(("api_key" = [])),
+    responses(
+        (status = 200, body = LetterResponse),
+        (status = 401, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+#[post("/api/vault/<id>/open")]
+async fn open(
+    _key: ApiKey,
+    storage: &Storage,
+    scope: CoupleScope,
+    id: i64,
+) -> ApiResult<Negotiated<LetterResponse>> {
+    storage
+        .open_vault_letter(id, scope.0)
+        .await
+        .map_err(internal_error)?
+        .map(|letter| Negotiated(letter.into()))
+        .ok_or_else(|| error(Status::NotFound, format!("no letter with id {}", id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![create, list, open]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_letters_hide_their_body() {
+        let mut letter = VaultLetter {
+            id: 1,
+            condition: "open when you're sad".to_string(),
+            body: "You are my favourite person.".to_string(),
+            sender: "Sam".to_string(),
+            opened_at: None,
+            created_at: Utc::now(),
+        };
+        let sealed = rocket::serde::json::to_string(&LetterResponse::from(letter.clone())).unwrap();
+        assert!(!sealed.contains("favourite"));
+        assert!(sealed.contains(r#""opened":false"#));
+
+        letter.opened_at = Some(Utc::now());
+        let opened = LetterResponse::from(letter);
+        assert_eq!(opened.body.as_deref(), Some("You are my favourite person."));
+    }
+}
@@ -0,0 +1,350 @@
+//! Each partner's wishlist. The other partner can quietly reserve an item,
+//! for themselves or on behalf of a friend or relative, so it is not
+//! bought twice. Reservations are shaped out of every response the owner
+//! sees, including the errors, which never say whether an item is taken.
+
+use chrono::{DateTime, Utc};
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::Route;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{error, internal_error, ApiResult, ErrorResponse};
+use crate::negotiate::Negotiated;
+use crate::storage::{NewWishlistItem, Storage, WishlistError, WishlistItem};
+use crate::users::Session;
+use crate::valentine::MAX_NAME_LEN;
+use crate::validation::{FieldErrors, Valid, Validate};
+
+const MAX_TITLE_LEN: usize = 120;
+const MAX_NOTE_LEN: usize = 500;
+const MAX_PRICE: i64 = 100_000;
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct WishlistRequest {
+    title: String,
+    url: Option<String>,
+    /// Size, colour or anything else a buyer should know.
+    note: Option<String>,
+    /// Rough price in whole currency units.
+    price: Option<i64>,
+}
+
+impl Validate for WishlistRequest {
+    type Valid = NewWishlistItem;
+
+    fn validate(self) -> Result<NewWishlistItem, FieldErrors> {
+        let trimmed = |value: Option<String>| {
+            value
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let title = self.title.trim().to_string();
+        let url = trimmed(self.url);
+        let note = trimmed(self.note);
+
+        let mut errors = FieldErrors::new();
+        errors.text("title", &title, MAX_TITLE_LEN);
+        if let Some(url) = &url {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                _ => errors.add("url", "`url` must be an absolute http(s) URL"),
+            }
+        }
+        if let Some(note) = &note {
+            errors.text("note", note, MAX_NOTE_LEN);
+        }
+        if self
+            .price
+            .is_some_and(|price| !(0..=MAX_PRICE).contains(&price))
+        {
+            errors.add("price", format!("`price` must be 0 to {}", MAX_PRICE));
+        }
+
+        errors.finish(NewWishlistItem {
+            title,
+            url,
+            note,
+            price: self.price,
+        })
+    }
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+struct ReserveRequest {
+    /// Who is buying it, e.g. `Mom`, when not the partner themselves.
+    #[serde(rename = "for")]
+    reserved_for: Option<String>,
+}
+
+impl Validate for ReserveRequest {
+    type Valid = Option<String>;
+
+    fn validate(self) -> Result<Option<String>, FieldErrors> {
+        let reserved_for = self
+            .reserved_for
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty());
+        let mut errors = FieldErrors::new();
+        if let Some(name) = &reserved_for {
+            errors.text("for", name, MAX_NAME_LEN);
+        }
+        errors.finish(reserved_for)
+    }
+}
+
+/// Who reserved an item, shown only to the partner who does not own it.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct WishlistReservation {
+    by: i64,
+    #[serde(rename = "for", skip_serializing_if = "Option::is_none")]
+    reserved_for: Option<String>,
+    at: DateTime<Utc>,
+}
+
+/// An item as one partner sees it.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+struct WishlistItemView {
+    id: i64,
+    owner_id: i64,
+    owner_name: String,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<i64>,
+    /// Whether the viewer owns the item.
+    mine: bool,
+    /// Always left out of the owner's view; `null` when nobody has it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reserved: Option<Option<WishlistReservation>>,
+    created_at: DateTime<Utc>,
+}
+
+impl WishlistItemView {
+    fn new(item: WishlistItem, viewer: i64) -> WishlistItemView {
+        let mine = item.owner_id == viewer;
+        let reservation = match (item.reserved_by, item.reserved_at) {
+            (Some(by), Some(at)) => Some(WishlistReservation {
+                by,
+                reserved_for: item.reserved_for,
+                at,
+            }),
+            _ => None,
+        };
+        WishlistItemView {
+            id: item.id,
+            owner_id: item.owner_id,
+            owner_name: item.owner_name,
+            title: item.title,
+            url: item.url,
+            note: item.note,
+            price: item.price,
+            mine,
+            reserved: (!mine).then_some(reservation),
+            created_at: item.created_at,
+        }
+    }
+}
+
+fn couple_of(session: &Session) -> ApiResult<i64> {
+    session
+        .0
+        .couple_id
+        .ok_or_else(|| error(Status::NotFound, "you are not in a couple yet"))
+}
+
+fn wishlist_error(e: WishlistError) -> crate::error::ApiError {
+    match e {
+        WishlistError::Unknown => error(Status::NotFound, "no such wishlist item"),
+        WishlistError::OwnItem => error(
+            Status::Forbidden,
+            "items on your own wishlist cannot be reserved",
+        ),
+        WishlistError::Reserved => error(Status::Conflict, "item is already reserved"),
+        WishlistError::NotReserved => error(Status::Conflict, "you have not reserved this item"),
+    }
+}
+
+/// Adds an item to the signed-in user's wishlist.
+#[utoipa::path(
+    tag = "wishlist",
+    request_body = WishlistRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 201, body = WishlistItemView),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/wishlist", data = "<request>")]
+async fn add(
+    session: Session,
+    storage: &Storage,
+    request: Valid<WishlistRequest>,
+) -> ApiResult<status::Created<Negotiated<WishlistItemView>>> {
+    let couple = couple_of(&session)?;
+    let item = storage
+        .create_wishlist_item(&request.into_inner(), couple, session.0.id)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(status::Created::new(uri!(list).to_string())
+        .body(Negotiated(WishlistItemView::new(item, session.0.id))))
+}
+
+/// Both partners' wishlists, with reservations on the partner's items only.
+#[utoipa::path(
+    tag = "wishlist",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = Vec<WishlistItemView>),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "Not in a couple yet", body = ErrorResponse),
+    )
+)]
+#[get("/api/wishlist")]
+async fn list(session: Session, storage: &Storage) -> ApiResult<Negotiated<Vec<WishlistItemView>>> {
+    let couple = couple_of(&session)?;
+    let items = storage
+        .list_wishlist(couple)
+        .await
+        .map_err(internal_error)?;
+
+    Ok(Negotiated(
+        items
+            .into_iter()
+            .map(|item| WishlistItemView::new(item, session.0.id))
+            .collect(),
+    ))
+}
+
+/// Removes an item from the signed-in user's own wishlist.
+#[utoipa::path(
+    tag = "wishlist",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 204),
+        (status = 401, body = ErrorResponse),
+        (status = 404, description = "No such item of yours", body = ErrorResponse),
+    )
+)]
+#[delete("/api/wishlist/<id>")]
+async fn remove(session: Session, storage: &Storage, id: i64) -> ApiResult<status::NoContent> {
+    if !storage
+        .delete_wishlist_item(id, session.0.id)
+        .await
+        .map_err(internal_error)?
+    {
+        return Err(error(Status::NotFound, "no such wishlist item"));
+    }
+    Ok(status::NoContent)
+}
+
+/// Reserves one of the partner's items, optionally on behalf of someone
+/// else.
+#[utoipa::path(
+    tag = "wishlist",
+    request_body = ReserveRequest,
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = WishlistItemView),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "The item is the user's own", body = ErrorResponse),
+        (status = 404, description = "No such item", body = ErrorResponse),
+        (status = 409, description = "Already reserved", body = ErrorResponse),
+        (status = 422, body = ErrorResponse),
+    )
+)]
+#[post("/api/wishlist/<id>/reserve", data = "<request>")]
+async fn reserve(
+    session: Session,
+    storage: &Storage,
+    id: i64,
+    request: Valid<ReserveRequest>,
+) -> ApiResult<Negotiated<WishlistItemView>> {
+    let couple = couple_of(&session)?;
+    let reserved_for = request.into_inner();
+    let item = storage
+        .reserve_wishlist_item(id, couple, session.0.id, reserved_for.as_deref())
+        .await
+        .map_err(internal_error)?
+        .map_err(wishlist_error)?;
+
+    Ok(Negotiated(WishlistItemView::new(item, session.0.id)))
+}
+
+/// Releases the signed-in user's reservation of one of the partner's items.
+#[utoipa::path(
+    tag = "wishlist",
+    security(("session" = []), ("bearer" = [])),
+    responses(
+        (status = 200, body = WishlistItemView),
+        (status = 401, body = ErrorResponse),
+        (status = 403, description = "The item is the user's own", body = ErrorResponse),
+        (status = 404, description = "No such item", body = ErrorResponse),
+        (status = 409, description = "Not reserved by the user", body = ErrorResponse),
+    )
+)]
+#[delete("/api/wishlist/<id>/reserve")]
+async fn release(
+    session: Session,
+    storage: &Storage,
+    id: i64,
+) -> ApiResult<Negotiated<WishlistItemView>> {
+    let couple = couple_of(&session)?;
+    let item = storage
+        .release_wishlist_item(id, couple, session.0.id)
+        .await
+        .map_err(internal_error)?
+        .map_err(wishlist_error)?;
+
+    Ok(Negotiated(WishlistItemView::new(item, session.0.id)))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![add, list, remove, reserve, release]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json;
+
+    #[test]
+    fn owners_never_see_reservations() {
+        let item = WishlistItem {
+            id: 1,
+            owner_id: 1,
+            owner_name: "Alex".to_string(),
+            title: "Record player".to_string(),
+            url: None,
+            note: None,
+            price: Some(120),
+            reserved_by: Some(2),
+            reserved_for: Some("Mom".to_string()),
+            reserved_at: Some(Utc::now()),
+            created_at: Utc::now(),
+        };
+
+        let owner = json::to_value(WishlistItemView::new(item.clone(), 1)).unwrap();
+        assert_eq!(owner["mine"], true);
+        assert!(owner.get("reserved").is_none(), "{}", owner);
+
+        let partner = json::to_value(WishlistItemView::new(item.clone(), 2)).unwrap();
+        assert_eq!(partner["reserved"]["for"], "Mom");
+
+        let free = WishlistItem {
+            reserved_by: None,
+            reserved_for: None,
+            reserved_at: None,
+            ..item
+        };
+        let partner = json::to_value(WishlistItemView::new(free, 2)).unwrap();
+        assert!(partner["reserved"].is_null());
+        assert!(partner.get("reserved").is_some());
+    }
+}
@@ -0,0 +1,142 @@
+//! Background workers and how they stop. Workers are spawned through
+//! [`Workers`] and handed a cancellation token; on shutdown (SIGTERM or
+//! Ctrl-C) the token is cancelled and each worker finishes the job in hand
+//! before returning. Their queues live in the database, so anything not
+//! started yet is picked up on the next launch.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::AdHoc;
+use rocket::tokio::{self, sync::Notify};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Used when `worker_drain_secs` is not set.
+const DEFAULT_DRAIN: Duration = Duration::from_secs(10);
+
+/// Managed handle for spawning workers that shutdown waits on.
+#[derive(Clone)]
+pub struct Workers {
+    token: CancellationToken,
+    tracker: TaskTracker,
+    drain: Duration,
+}
+
+impl Workers {
+    fn new(drain: Duration) -> Self {
+        Workers {
+            token: CancellationToken::new(),
+            tracker: TaskTracker::new(),
+            drain,
+        }
+    }
+
+    /// Spawns `worker` with a token that is cancelled at shutdown. The
+    /// worker should return promptly once it is, without abandoning a job
+    /// halfway.
+    pub fn spawn<F, Fut>(&self, name: &'static str, worker: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let run = worker(self.token.child_token());
+        self.tracker.spawn(async move {
+            run.await;
+            info!("{} stopped", name);
+        });
+    }
+
+    /// Cancels every worker and waits up to `timeout` for them to return.
+    /// `false` means some were still busy and get dropped with the runtime.
+    async fn drain(&self, timeout: Duration) -> bool {
+        self.token.cancel();
+        self.tracker.close();
+        tokio::time::timeout(timeout, self.tracker.wait())
+            .await
+            .is_ok()
+    }
+}
+
+/// Wake-ups for a worker that runs once per database, e.g. for each tenant.
+/// Clones share the registered workers.
+#[derive(Clone, Default)]
+pub struct Wakers(Arc<Mutex<Vec<Arc<Notify>>>>);
+
+impl Wakers {
+    /// A wake-up for one more worker.
+    pub fn register(&self) -> Arc<Notify> {
+        let wake = Arc::new(Notify::new());
+        self.0
+            .lock()
+            .expect("wakers lock poisoned")
+            .push(wake.clone());
+        wake
+    }
+
+    /// Wakes every registered worker; one not waiting yet wakes as soon as
+    /// it does.
+    pub fn wake_all(&self) {
+        for wake in self.0.lock().expect("wakers lock poisoned").iter() {
+            wake.notify_one();
+        }
+    }
+}
+
+/// Manages [`Workers`] and drains them when Rocket shuts down. Attach it
+/// before the stages that spawn workers.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Workers", |rocket| async {
+        let drain = match rocket.figment().extract_inner::<u64>("worker_drain_secs") {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(e) if e.missing() => DEFAULT_DRAIN,
+            Err(e) => {
+                error!("invalid worker_drain_secs: {}", e);
+                return Err(rocket);
+            }
+        };
+
+        Ok(rocket
+            .manage(Workers::new(drain))
+            .attach(AdHoc::on_shutdown("Worker Drain", |rocket| {
+                Box::pin(async move {
+                    let Some(workers) = rocket.state::<Workers>() else {
+                        return;
+                    };
+                    info!("stopping {} background workers", workers.tracker.len());
+                    if workers.drain(workers.drain).await {
+                        info!("background workers drained");
+                    } else {
+                        warn!(
+                            "{} background workers still busy after {:?}, abandoning them",
+                            workers.tracker.len(),
+                            workers.drain
+                        );
+                    }
+                })
+            })))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn drain_waits_for_workers_to_finish_their_job() {
+        let workers = Workers::new(DEFAULT_DRAIN);
+        workers.spawn("polite", |token| async move {
+            token.cancelled().await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        });
+        assert!(workers.drain(Duration::from_secs(1)).await);
+        assert!(workers.tracker.is_empty());
+
+        let workers = Workers::new(DEFAULT_DRAIN);
+        workers.spawn("stubborn", |_| async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+        assert!(!workers.drain(Duration::from_millis(20)).await);
+    }
+}
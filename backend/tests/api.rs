@@ -0,0 +1,96 @@
+mod common;
+
+use rocket::http::{Accept, ContentType, Header, Status};
+use rocket::serde::json::{json, Value};
+
+use common::fixtures::{MessageBuilder, QuoteBuilder, UserBuilder};
+use common::{client, data, with_json, with_key};
+
+#[rocket::async_test]
+async fn added_quotes_are_stored_as_approved() {
+    let client = client().await;
+    let quote = QuoteBuilder::new("You make my heart skip a beat.")
+        .category("funny")
+        .create(&client)
+        .await;
+
+    let uri = format!("/admin/quotes/{}", quote["id"]);
+    let response = with_key(client.get(uri.as_str())).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let stored = data(&response.into_string().await.unwrap());
+    assert_eq!(stored["text"], "You make my heart skip a beat.");
+    assert_eq!(stored["category"], "funny");
+    assert_eq!(stored["status"], "approved");
+}
+
+#[rocket::async_test]
+async fn responses_follow_the_accept_header() {
+    let client = client().await;
+    let quote = QuoteBuilder::new("Be mine").create(&client).await;
+    let uri = format!("/admin/quotes/{}", quote["id"]);
+
+    let response = with_key(client.get(uri.as_str()))
+        .header(Header::new("Accept", "application/msgpack"))
+        .dispatch()
+        .await;
+    assert_eq!(response.content_type(), Some(ContentType::MsgPack));
+    let body: Value = rmp_serde::from_slice(&response.into_bytes().await.unwrap()).unwrap();
+    assert_eq!(body["data"]["text"], "Be mine");
+    assert!(body["meta"]["request_id"].is_string());
+
+    let response = with_key(client.get(uri.as_str()))
+        .header(Accept::XML)
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.unwrap();
+    assert!(body.starts_with("<response><data>"), "{}", body);
+    assert!(body.contains("<text>Be mine</text>"), "{}", body);
+}
+
+#[rocket::async_test]
+async fn valentines_are_listed_newest_first() {
+    let client = client().await;
+    MessageBuilder::new("Be mine").create(&client).await;
+    let latest = MessageBuilder::new("Still yours")
+        .from("Juliet")
+        .to("Romeo")
+        .create(&client)
+        .await;
+
+    let response = client.get("/api/messages").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let page = data(&response.into_string().await.unwrap());
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["items"][0]["id"], latest["id"]);
+    assert_eq!(page["items"][0]["to"], "Romeo");
+}
+
+#[rocket::async_test]
+async fn partners_join_with_the_invite_code() {
+    let client = client().await;
+    let alice = UserBuilder::new("Alice").create(&client).await;
+    let bob = UserBuilder::new("Bob")
+        .email("bob@example.org")
+        .create(&client)
+        .await;
+
+    let response = alice.post(&client, "/api/couples").dispatch().await;
+    assert_eq!(response.status(), Status::Created);
+    let code = data(&response.into_string().await.unwrap())["invite_code"].clone();
+    let join = bob.post(&client, "/api/couples/join");
+    let response = with_json(join, &json!({ "invite_code": code }))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+
+    let response = alice.get(&client, "/api/couples/me").dispatch().await;
+    let couple = data(&response.into_string().await.unwrap());
+    let members: Vec<_> = couple["members"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|member| member["id"].as_i64().unwrap())
+        .collect();
+    assert_eq!(members, [alice.id, bob.id]);
+}
@@ -0,0 +1,130 @@
+//! Builders for what tests need in the database, each made through the API
+//! as a client would. Every field has a default, so a test only names the
+//! ones it cares about.
+
+use rocket::http::{Cookie, Status};
+use rocket::local::asynchronous::{Client, LocalRequest};
+use rocket::serde::json::{json, Value};
+
+use super::{data, with_json, with_key};
+
+/// An approved quote, added with the API key.
+pub struct QuoteBuilder {
+    text: String,
+    category: &'static str,
+}
+
+impl QuoteBuilder {
+    pub fn new(text: &str) -> Self {
+        QuoteBuilder {
+            text: text.to_string(),
+            category: "romantic",
+        }
+    }
+
+    pub fn category(mut self, category: &'static str) -> Self {
+        self.category = category;
+        self
+    }
+
+    pub async fn create(self, client: &Client) -> Value {
+        let body = json!({ "text": self.text, "category": self.category });
+        let response = with_json(with_key(client.post("/admin/quotes")), &body)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created, "creating a quote");
+        data(&response.into_string().await.unwrap())
+    }
+}
+
+/// A registered user, signed in.
+pub struct UserBuilder {
+    name: String,
+    email: String,
+    password: String,
+}
+
+impl UserBuilder {
+    pub fn new(name: &str) -> Self {
+        UserBuilder {
+            name: name.to_string(),
+            email: format!("{}@example.com", name.to_lowercase()),
+            password: "correct horse battery staple".to_string(),
+        }
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = email.to_string();
+        self
+    }
+
+    pub async fn create(self, client: &Client) -> TestUser {
+        let body = json!({ "email": self.email, "name": self.name, "password": self.password });
+        let response = with_json(client.post("/api/users/register"), &body)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created, "registering a user");
+        let cookies = response
+            .cookies()
+            .iter()
+            .map(|cookie| cookie.clone().into_owned())
+            .collect();
+        let user = data(&response.into_string().await.unwrap());
+        TestUser {
+            id: user["id"].as_i64().expect("a user id"),
+            cookies,
+        }
+    }
+}
+
+/// A signed-in user. Requests made through it carry its session cookie.
+pub struct TestUser {
+    pub id: i64,
+    cookies: Vec<Cookie<'static>>,
+}
+
+impl TestUser {
+    pub fn get<'c>(&self, client: &'c Client, uri: &'c str) -> LocalRequest<'c> {
+        client.get(uri).cookies(self.cookies.clone())
+    }
+
+    pub fn post<'c>(&self, client: &'c Client, uri: &'c str) -> LocalRequest<'c> {
+        client.post(uri).cookies(self.cookies.clone())
+    }
+}
+
+/// A valentine submitted with the API key, so it belongs to no couple.
+pub struct MessageBuilder {
+    message: String,
+    from: String,
+    to: Option<String>,
+}
+
+impl MessageBuilder {
+    pub fn new(message: &str) -> Self {
+        MessageBuilder {
+            message: message.to_string(),
+            from: "Romeo".to_string(),
+            to: None,
+        }
+    }
+
+    pub fn from(mut self, from: &str) -> Self {
+        self.from = from.to_string();
+        self
+    }
+
+    pub fn to(mut self, to: &str) -> Self {
+        self.to = Some(to.to_string());
+        self
+    }
+
+    pub async fn create(self, client: &Client) -> Value {
+        let body = json!({ "message": self.message, "from": self.from, "to": self.to });
+        let response = with_json(with_key(client.post("/api/valentine")), &body)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created, "sending a valentine");
+        data(&response.into_string().await.unwrap())
+    }
+}
@@ -0,0 +1,45 @@
+//! A whole server per test, on an in-memory database of its own, driven
+//! through Rocket's local client.
+
+pub mod fixtures;
+
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header};
+use rocket::local::asynchronous::{Client, LocalRequest};
+use rocket::serde::json::{self, Value};
+
+/// The one key in the test server's `api_keys`.
+pub const API_KEY: &str = "test-key";
+
+/// Just enough config for the dev profile: no gRPC listener, frontend or
+/// optional providers, and a fresh database each time.
+pub fn figment() -> Figment {
+    Figment::from(rocket::Config::debug_default())
+        .select("dev")
+        .merge(("database_url", "sqlite::memory:"))
+        .merge(("api_keys", [API_KEY]))
+        .merge(("secret_key", "hPRYyVRiMyxpw5sBB1XeCMN1kFsDCqKvBi2QJxBVHQk="))
+        .merge(("log_filter", "off"))
+}
+
+pub async fn client() -> Client {
+    Client::untracked(valentine_backend::build_rocket(figment()))
+        .await
+        .expect("the test server launches")
+}
+
+/// `request` with the test API key.
+pub fn with_key(request: LocalRequest<'_>) -> LocalRequest<'_> {
+    request.header(Header::new("X-API-Key", API_KEY))
+}
+
+/// `request` carrying `body` as JSON.
+pub fn with_json<'c>(request: LocalRequest<'c>, body: &Value) -> LocalRequest<'c> {
+    request.header(ContentType::JSON).body(body.to_string())
+}
+
+/// The `data` of an enveloped JSON response.
+pub fn data(body: &str) -> Value {
+    let mut body: Value = json::from_str(body).expect("a JSON body");
+    body["data"].take()
+}